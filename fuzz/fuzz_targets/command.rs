@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sovelma_fuzz::line;
+
+// Words as the control channel passes them, which need not be what
+// `line::split` produces; NUL separates them here.
+fuzz_target!(|text: &str| {
+    let words: Vec<&str> = text.split('\0').collect();
+    let Some((cmd, args)) = words.split_first() else {
+        return;
+    };
+    if let Some(parsed) = line::parse(cmd, args) {
+        assert!(!parsed.args.iter().any(|arg| arg == line::JSON_FLAG));
+        assert_eq!(parsed.json, args.contains(&line::JSON_FLAG));
+    }
+});
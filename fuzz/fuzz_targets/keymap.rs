@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sovelma_fuzz::keymap::KeyDecoder;
+
+fuzz_target!(|scancodes: &[u8]| {
+    let mut decoder = KeyDecoder::new();
+    for &scancode in scancodes {
+        if let Some(event) = decoder.add_byte(scancode) {
+            let _ = decoder.process(event);
+        }
+    }
+});
@@ -0,0 +1,37 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sovelma_fuzz::line;
+
+fuzz_target!(|input: &str| {
+    let Ok(words) = line::words(input) else {
+        assert!(line::split(input).is_none());
+        return;
+    };
+    let Some((cmd, args)) = line::split(input) else {
+        assert!(words.is_empty());
+        return;
+    };
+    assert_eq!(cmd, words[0]);
+    for stage in line::stages(&args) {
+        assert!(!stage.iter().any(|arg| arg == line::PIPE));
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    assert_eq!(line::parse(&cmd, &args).is_some(), !cmd.is_empty());
+
+    // Each word comes back the same from within double quotes
+    let quoted: Vec<String> = words
+        .iter()
+        .map(|word| {
+            let escaped: String = word
+                .chars()
+                .flat_map(|c| match c {
+                    '"' | '\\' => vec!['\\', c],
+                    c => vec![c],
+                })
+                .collect();
+            format!("\"{}\"", escaped)
+        })
+        .collect();
+    assert_eq!(line::words(&quoted.join(" ")), Ok(words));
+});
@@ -0,0 +1,22 @@
+//! Fuzz targets for the kernel's console input parsing.
+//!
+//! Input reaches the shell from the keyboard, serial, telnet and the
+//! control channel, so none of it may panic the kernel. The modules that
+//! turn it into commands and keys are compiled in from `src/kernel`:
+//!
+//! - `command`: `line::parse` on arbitrary words, as `Command::parse`
+//!   receives them (without the registry lookup)
+//! - `shell_line`: `line::words`, `line::split` and `line::stages` on
+//!   arbitrary lines, and quoting the words to get them back
+//! - `keymap`: `KeyDecoder` on arbitrary scancode sequences
+//!
+//! ```text
+//! cargo +nightly fuzz run keymap
+//! ```
+
+extern crate alloc;
+
+#[path = "../../src/kernel/src/terminal/keymap.rs"]
+pub mod keymap;
+#[path = "../../src/kernel/src/terminal/line.rs"]
+pub mod line;
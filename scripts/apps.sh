@@ -0,0 +1,22 @@
+#!/bin/sh
+# Build the example apps and a kernel with them installed in /apps.
+#
+# The apps are built for wasm32-unknown-unknown and collected in
+# target/apps as <name>.wasm; the kernel is then built with SOVELMA_APPS
+# pointing there. Extra arguments go to the kernel build.
+#
+# Usage: scripts/apps.sh [--release]
+set -e
+
+cd "$(dirname "$0")/.."
+APPS="cat counter echo ping pong"
+OUT="$PWD/target/apps"
+WASM_DIR="target/wasm32-unknown-unknown/release"
+
+mkdir -p "$OUT"
+for app in $APPS; do
+    cargo build -p "$app-app" --target wasm32-unknown-unknown --release
+    cp "$WASM_DIR/${app}_app.wasm" "$OUT/$app.wasm"
+done
+(cd src/kernel && SOVELMA_APPS="$OUT" cargo build "$@")
+echo "Embedded $(ls "$OUT" | wc -l) apps from $OUT"
@@ -0,0 +1,22 @@
+#!/bin/sh
+# Build the kernel with its own symbol map embedded.
+#
+# The map is taken from a previous build, so the kernel is built three
+# times: the second build embeds a map of the right size, and the third
+# embeds one whose addresses match its own layout.
+#
+# Usage: scripts/ksyms.sh [--release]
+set -e
+
+cd "$(dirname "$0")/.."
+PROFILE=debug
+[ "$1" = "--release" ] && PROFILE=release
+KERNEL="target/x86_64-unknown-none/$PROFILE/sovelma-kernel"
+SYMS="$PWD/target/kernel.sym"
+
+(cd src/kernel && cargo build "$@")
+for _ in 1 2; do
+    nm -n --defined-only --demangle "$KERNEL" > "$SYMS"
+    (cd src/kernel && SOVELMA_KSYMS="$SYMS" cargo build "$@")
+done
+echo "Embedded $(wc -l < "$SYMS") symbols from $SYMS"
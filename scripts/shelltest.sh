@@ -0,0 +1,20 @@
+#!/bin/sh
+# Run the shell tests under QEMU and check the results on the host.
+#
+# Boots the kernel with `shelltest`, keeps its serial output, and hands the
+# log and QEMU's exit status to src/testharness, which compares the output
+# with the golden files and names the test or kernel module that failed.
+# Apps built by scripts/apps.sh are embedded, so their tests run too.
+#
+# Usage: scripts/shelltest.sh [harness args, e.g. --update]
+
+cd "$(dirname "$0")/.." || exit 1
+LOG="$PWD/target/shelltest.log"
+mkdir -p target
+[ -d target/apps ] && export SOVELMA_APPS="$PWD/target/apps"
+
+(cd src/kernel && SOVELMA_CMDLINE="shelltest" cargo run -- \
+    -device isa-debug-exit,iobase=0xf4,iosize=0x04 -display none) > "$LOG"
+STATUS=$?
+
+(cd src/testharness && cargo run -q -- "$LOG" --status "$STATUS" "$@")
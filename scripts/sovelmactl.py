@@ -0,0 +1,219 @@
+#!/usr/bin/env python3
+"""Control a running SovelmaOS kernel over its control port.
+
+The kernel serves the protocol on the serial port named by `ctl=<n>` on its
+command line (see src/kernel/src/ctl.rs). Connect to it through a serial
+device or a pty, or through QEMU's TCP serial backend:
+
+    cd src/kernel && SOVELMA_CMDLINE="serial=2 ctl=2" \\
+        cargo run -- -serial tcp::4444,server,nowait
+    scripts/sovelmactl.py --tcp localhost:4444 put app.wasm app.wasm
+    scripts/sovelmactl.py --tcp localhost:4444 run wasm run app.wasm
+    scripts/sovelmactl.py --tcp localhost:4444 settime
+    scripts/sovelmactl.py --tcp localhost:4444 log --follow
+
+Uses only the Python standard library.
+"""
+
+import argparse
+import os
+import socket
+import sys
+import termios
+import time
+import tty
+
+# Must match MAX_CHUNK in ctl.rs
+MAX_CHUNK = 512
+
+BAUD_RATES = {
+    9600: termios.B9600,
+    19200: termios.B19200,
+    38400: termios.B38400,
+    57600: termios.B57600,
+    115200: termios.B115200,
+}
+
+
+class CtlError(Exception):
+    """The kernel refused a request or the link failed."""
+
+
+def checksum(text):
+    value = 0
+    for byte in text.encode():
+        value ^= byte
+    return value
+
+
+def frame(text):
+    return f"{text}*{checksum(text):02x}\n".encode()
+
+
+def unframe(line):
+    text, sep, digits = line.rpartition("*")
+    if not sep or len(digits) != 2 or int(digits, 16) != checksum(text):
+        raise CtlError(f"bad checksum in reply: {line!r}")
+    return text
+
+
+class Link:
+    """A byte stream to the kernel's control port."""
+
+    def __init__(self, args):
+        self.buffer = b""
+        if args.tcp:
+            host, _, port = args.tcp.rpartition(":")
+            self.sock = socket.create_connection((host or "localhost", int(port)))
+            self.fd = None
+        else:
+            self.sock = None
+            self.fd = os.open(args.device, os.O_RDWR | os.O_NOCTTY)
+            tty.setraw(self.fd)
+            attrs = termios.tcgetattr(self.fd)
+            attrs[4] = attrs[5] = BAUD_RATES[args.baud]
+            termios.tcsetattr(self.fd, termios.TCSANOW, attrs)
+        self.next_id = 1
+
+    def send(self, data):
+        if self.sock:
+            self.sock.sendall(data)
+        else:
+            os.write(self.fd, data)
+
+    def read_line(self):
+        while b"\n" not in self.buffer:
+            chunk = self.sock.recv(4096) if self.sock else os.read(self.fd, 4096)
+            if not chunk:
+                raise CtlError("connection closed")
+            self.buffer += chunk
+        line, _, self.buffer = self.buffer.partition(b"\n")
+        return line.decode("ascii", "replace").rstrip("\r")
+
+    def request(self, op, *args):
+        """Send a request; returns (data chunks, ok result)."""
+        request_id = str(self.next_id)
+        self.next_id += 1
+        self.send(frame(" ".join([request_id, op, *map(str, args)])))
+        data = []
+        while True:
+            line = self.read_line()
+            try:
+                text = unframe(line)
+            except (CtlError, ValueError):
+                # Noise from before the kernel started, or a damaged line
+                continue
+            reply_id, _, rest = text.partition(" ")
+            kind, _, value = rest.partition(" ")
+            if reply_id == "-" and kind == "err":
+                raise CtlError(value)
+            if reply_id != request_id:
+                continue
+            if kind == "data":
+                data.append(bytes.fromhex(value))
+            elif kind == "ok":
+                return data, value
+            elif kind == "err":
+                raise CtlError(value)
+
+
+def cmd_hello(link, _args):
+    _, result = link.request("hello")
+    print(result)
+
+
+def cmd_put(link, args):
+    with open(args.local, "rb") as f:
+        content = f.read()
+    offset = 0
+    # An empty file still takes one request to create it
+    while True:
+        chunk = content[offset : offset + MAX_CHUNK]
+        link.request("put", args.remote, offset, chunk.hex())
+        offset += len(chunk)
+        if offset >= len(content):
+            break
+    print(f"{args.local} -> {args.remote} ({len(content)} bytes)")
+
+
+def cmd_get(link, args):
+    content = b""
+    while True:
+        data, size = link.request("get", args.remote, len(content), MAX_CHUNK)
+        chunk = b"".join(data)
+        content += chunk
+        if not chunk or len(content) >= int(size):
+            break
+    if args.local:
+        with open(args.local, "wb") as f:
+            f.write(content)
+        print(f"{args.remote} -> {args.local} ({len(content)} bytes)")
+    else:
+        sys.stdout.buffer.write(content)
+
+
+def cmd_run(link, args):
+    data, _ = link.request("run", " ".join(args.command))
+    sys.stdout.write(b"".join(data).decode("utf-8", "replace"))
+
+
+def cmd_settime(link, _args):
+    now = time.time_ns()
+    _, mono_ns = link.request("time", now)
+    print(f"kernel clock set at {int(mono_ns) / 1e9:.6f}s after boot")
+
+
+def cmd_log(link, args):
+    since = args.since
+    while True:
+        data, next_since = link.request("log", since)
+        for record in data:
+            print(record.decode("utf-8", "replace"))
+        since = int(next_since)
+        if not args.follow:
+            break
+        time.sleep(args.interval)
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    target = parser.add_mutually_exclusive_group(required=True)
+    target.add_argument("--tcp", metavar="HOST:PORT", help="QEMU TCP serial backend")
+    target.add_argument("--device", metavar="PATH", help="serial device or pty")
+    parser.add_argument("--baud", type=int, default=115200, choices=sorted(BAUD_RATES))
+    commands = parser.add_subparsers(dest="command", required=True)
+
+    commands.add_parser("hello", help="show the kernel version and granted rights")
+    put = commands.add_parser("put", help="copy a file into the RAM filesystem")
+    put.add_argument("local")
+    put.add_argument("remote")
+    get = commands.add_parser("get", help="copy a file out of the RAM filesystem")
+    get.add_argument("remote")
+    get.add_argument("local", nargs="?", help="default: standard output")
+    run = commands.add_parser("run", help="run a shell command")
+    run.add_argument("command", nargs=argparse.REMAINDER)
+    log = commands.add_parser("log", help="fetch kernel log records")
+    log.add_argument("--since", type=int, default=0, help="first record number")
+    log.add_argument("--follow", action="store_true", help="keep fetching new records")
+    log.add_argument("--interval", type=float, default=1.0, help="seconds between fetches")
+    commands.add_parser("settime", help="set the kernel's wall clock to this host's")
+
+    args = parser.parse_args()
+    handlers = {
+        "hello": cmd_hello,
+        "put": cmd_put,
+        "get": cmd_get,
+        "run": cmd_run,
+        "log": cmd_log,
+        "settime": cmd_settime,
+    }
+    try:
+        handlers[args.command](Link(args), args)
+    except CtlError as e:
+        sys.exit(f"sovelmactl: {e}")
+    except KeyboardInterrupt:
+        pass
+
+
+if __name__ == "__main__":
+    main()
@@ -1,4 +1,7 @@
+use crate::codec::{CodecError, Decode, Encode, Reader, Writer};
+use crate::rate::RateLimit;
 use bitflags::bitflags;
+use core::fmt;
 use core::sync::atomic::{AtomicU32, Ordering};
 
 /// A unique identifier for a capability, including a generation for revocation.
@@ -53,6 +56,54 @@ bitflags! {
         const EXECUTE   = 1 << 2;
         const GRANT     = 1 << 3; // Ability to share this cap
         const CALL      = 1 << 4; // Ability to invoke (for HostFunctions/IPC)
+        const CONNECT   = 1 << 5; // Open outbound connections (Network)
+        const LISTEN    = 1 << 6; // Accept connections on the cap's ports (Network)
+        const RAW       = 1 << 7; // Send raw Ethernet frames (Network)
+    }
+}
+
+/// Longest capability label, in bytes.
+pub const LABEL_MAX: usize = 16;
+
+/// A capability's label: 1 to `LABEL_MAX` ASCII letters, digits, `-` and
+/// `_`, e.g. `web` or `uplink-2`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label {
+    bytes: [u8; LABEL_MAX],
+    len: u8,
+}
+
+impl Label {
+    /// `name` as a label, if it is a valid one.
+    pub fn new(name: &str) -> Option<Self> {
+        let valid = |b: &u8| b.is_ascii_alphanumeric() || *b == b'-' || *b == b'_';
+        if name.is_empty() || name.len() > LABEL_MAX || !name.bytes().all(|b| valid(&b)) {
+            return None;
+        }
+        let mut bytes = [0u8; LABEL_MAX];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        Some(Self {
+            bytes,
+            len: name.len() as u8,
+        })
+    }
+
+    /// The label's text.
+    pub fn as_str(&self) -> &str {
+        // Only ASCII is accepted by `new`
+        core::str::from_utf8(&self.bytes[..usize::from(self.len)]).unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
@@ -67,6 +118,11 @@ pub struct Capability {
     pub object: CapabilityType,
     /// Generation counter for revocation (0 = initial).
     pub generation: u64,
+    /// Name given when the capability was granted, to tell it apart from
+    /// others of the same kind.
+    pub label: Option<Label>,
+    /// How often the capability, and those derived from it, may be used.
+    pub rate_limit: Option<RateLimit>,
 }
 
 impl Capability {
@@ -77,6 +133,38 @@ impl Capability {
             rights,
             object,
             generation: 0,
+            label: None,
+            rate_limit: None,
+        }
+    }
+
+    /// The same capability, labelled `label`.
+    pub fn with_label(self, label: Label) -> Self {
+        Self {
+            label: Some(label),
+            ..self
+        }
+    }
+
+    /// The same capability, limited to `limit`.
+    pub fn with_rate_limit(self, limit: RateLimit) -> Self {
+        Self {
+            rate_limit: Some(limit),
+            ..self
+        }
+    }
+
+    /// Whether this is a Network capability that may listen on `port`.
+    pub fn permits_listen(&self, port: u16) -> bool {
+        match self.object {
+            CapabilityType::Network {
+                first_port,
+                last_port,
+            } => {
+                self.rights.contains(CapabilityRights::LISTEN)
+                    && (first_port..=last_port).contains(&port)
+            }
+            _ => false,
         }
     }
 }
@@ -103,8 +191,17 @@ pub enum CapabilityType {
         /// The IRQ number.
         irq: u8,
     },
-    /// Net socket
-    Network(u32),
+    /// Access to the network stack. CONNECT allows outbound connections,
+    /// LISTEN listening on ports `first_port..=last_port`, RAW sending raw
+    /// frames.
+    Network {
+        /// Lowest port the holder may listen on.
+        first_port: u16,
+        /// Highest port the holder may listen on.
+        last_port: u16,
+    },
+    /// TCP socket (handle)
+    Socket(u64),
     /// Filesystem Directory (handle)
     Directory(u64),
     /// Open File (handle)
@@ -113,4 +210,242 @@ pub enum CapabilityType {
     Mutex(u64),
     /// Kernel semaphore (handle)
     Semaphore(u64),
+    /// WASM process (pid)
+    Process(u64),
+    /// The system configuration store
+    Config,
+}
+
+impl CapabilityType {
+    /// Capability kind names, as used in module manifests.
+    pub const KIND_NAMES: [&'static str; 12] = [
+        "memory",
+        "serial",
+        "timer",
+        "interrupt",
+        "network",
+        "directory",
+        "file",
+        "mutex",
+        "semaphore",
+        "process",
+        "config",
+        "socket",
+    ];
+
+    /// Kind name of this capability (one of `KIND_NAMES`).
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            CapabilityType::Memory { .. } => "memory",
+            CapabilityType::Serial { .. } => "serial",
+            CapabilityType::Timer => "timer",
+            CapabilityType::Interrupt { .. } => "interrupt",
+            CapabilityType::Network { .. } => "network",
+            CapabilityType::Directory(_) => "directory",
+            CapabilityType::File(_) => "file",
+            CapabilityType::Mutex(_) => "mutex",
+            CapabilityType::Semaphore(_) => "semaphore",
+            CapabilityType::Process(_) => "process",
+            CapabilityType::Config => "config",
+            CapabilityType::Socket(_) => "socket",
+        }
+    }
+}
+
+/// Versions of the capability list format: 1 lists `CapabilityRecord`s,
+/// 2 `CapabilityDescriptor`s.
+pub const CAPABILITY_FORMATS: core::ops::RangeInclusive<u32> = 1..=2;
+
+/// A held capability as version 1 of the capability list describes it
+/// (`sp_get_capabilities`), encoded with `codec` as its handle, kind name
+/// and rights bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapabilityRecord<'a> {
+    /// The process's handle for the capability.
+    pub handle: i64,
+    /// Kind of resource (one of `CapabilityType::KIND_NAMES`).
+    pub kind: &'a str,
+    /// Rights the capability grants.
+    pub rights: CapabilityRights,
+}
+
+impl CapabilityRecord<'static> {
+    /// The record for `cap`, held as `handle`.
+    pub fn new(handle: i64, cap: &Capability) -> Self {
+        Self {
+            handle,
+            kind: cap.object.kind_name(),
+            rights: cap.rights,
+        }
+    }
+}
+
+impl Encode for CapabilityRecord<'_> {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+        writer.encode(&self.handle)?;
+        writer.encode(self.kind)?;
+        writer.encode(&self.rights.bits())
+    }
+}
+
+impl<'a> Decode<'a> for CapabilityRecord<'a> {
+    fn decode(reader: &mut Reader<'a>) -> Result<Self, CodecError> {
+        Ok(Self {
+            handle: reader.decode()?,
+            kind: reader.decode()?,
+            rights: CapabilityRights::from_bits_retain(reader.decode()?),
+        })
+    }
+}
+
+/// What a capability refers to, in a `CapabilityDescriptor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapabilityDetail<'a> {
+    /// Nothing more than the kind, or not known.
+    None,
+    /// Path of a directory or file, from the root; not known for a
+    /// process's own namespace.
+    Path(&'a str),
+    /// The ports a Network capability may listen on (both 0 for none).
+    Ports {
+        /// Lowest port.
+        first: u16,
+        /// Highest port.
+        last: u16,
+    },
+    /// I/O port of a Serial capability.
+    SerialPort(u16),
+}
+
+impl Encode for CapabilityDetail<'_> {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+        match *self {
+            CapabilityDetail::None => writer.encode(&0u8),
+            CapabilityDetail::Path(path) => {
+                writer.encode(&1u8)?;
+                writer.encode(path)
+            }
+            CapabilityDetail::Ports { first, last } => {
+                writer.encode(&2u8)?;
+                writer.encode(&first)?;
+                writer.encode(&last)
+            }
+            CapabilityDetail::SerialPort(port) => {
+                writer.encode(&3u8)?;
+                writer.encode(&port)
+            }
+        }
+    }
+}
+
+impl<'a> Decode<'a> for CapabilityDetail<'a> {
+    fn decode(reader: &mut Reader<'a>) -> Result<Self, CodecError> {
+        match reader.decode::<u8>()? {
+            0 => Ok(CapabilityDetail::None),
+            1 => Ok(CapabilityDetail::Path(reader.decode()?)),
+            2 => Ok(CapabilityDetail::Ports {
+                first: reader.decode()?,
+                last: reader.decode()?,
+            }),
+            3 => Ok(CapabilityDetail::SerialPort(reader.decode()?)),
+            _ => Err(CodecError::Invalid),
+        }
+    }
+}
+
+/// A held capability as version 2 of the capability list describes it:
+/// a `CapabilityRecord` followed by its label (an `Option`) and detail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapabilityDescriptor<'a> {
+    /// Handle, kind and rights.
+    pub record: CapabilityRecord<'a>,
+    /// Label given at grant time.
+    pub label: Option<&'a str>,
+    /// What the capability refers to.
+    pub detail: CapabilityDetail<'a>,
+}
+
+impl<'a> CapabilityDescriptor<'a> {
+    /// The descriptor for `cap`, held as `handle`; `path` is where a
+    /// Directory or File capability's node is, if known.
+    pub fn new(handle: i64, cap: &'a Capability, path: Option<&'a str>) -> Self {
+        let detail = match cap.object {
+            CapabilityType::Network {
+                first_port,
+                last_port,
+            } => CapabilityDetail::Ports {
+                first: first_port,
+                last: last_port,
+            },
+            CapabilityType::Serial { port } => CapabilityDetail::SerialPort(port),
+            CapabilityType::Directory(_) | CapabilityType::File(_) => {
+                path.map_or(CapabilityDetail::None, CapabilityDetail::Path)
+            }
+            _ => CapabilityDetail::None,
+        };
+        Self {
+            record: CapabilityRecord::new(handle, cap),
+            label: cap.label.as_ref().map(Label::as_str),
+            detail,
+        }
+    }
+}
+
+impl Encode for CapabilityDescriptor<'_> {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+        writer.encode(&self.record)?;
+        writer.encode(&self.label)?;
+        writer.encode(&self.detail)
+    }
+}
+
+impl<'a> Decode<'a> for CapabilityDescriptor<'a> {
+    fn decode(reader: &mut Reader<'a>) -> Result<Self, CodecError> {
+        Ok(Self {
+            record: reader.decode()?,
+            label: reader.decode()?,
+            detail: reader.decode()?,
+        })
+    }
+}
+
+/// The capabilities a process holds, to encode in one of the
+/// `CAPABILITY_FORMATS`: a sequence of records or descriptors.
+#[derive(Debug, Clone, Copy)]
+pub struct CapabilityList<'a> {
+    version: u32,
+    descriptors: &'a [CapabilityDescriptor<'a>],
+}
+
+impl<'a> CapabilityList<'a> {
+    /// `descriptors` in format `version`, if it is one of
+    /// `CAPABILITY_FORMATS`.
+    pub fn new(version: u32, descriptors: &'a [CapabilityDescriptor<'a>]) -> Option<Self> {
+        CAPABILITY_FORMATS.contains(&version).then_some(Self {
+            version,
+            descriptors,
+        })
+    }
+
+    /// Number of capabilities listed.
+    pub fn len(&self) -> usize {
+        self.descriptors.len()
+    }
+
+    /// Whether no capabilities are listed.
+    pub fn is_empty(&self) -> bool {
+        self.descriptors.is_empty()
+    }
+}
+
+impl Encode for CapabilityList<'_> {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+        writer.varint(self.descriptors.len() as u64)?;
+        self.descriptors
+            .iter()
+            .try_for_each(|descriptor| match self.version {
+                1 => writer.encode(&descriptor.record),
+                _ => writer.encode(descriptor),
+            })
+    }
 }
@@ -0,0 +1,492 @@
+//! A compact binary encoding for records passed between the kernel and
+//! WASM processes.
+//!
+//! The format follows postcard: unsigned integers are LEB128 varints,
+//! signed ones are zigzag-encoded first, a `bool` is one byte, byte
+//! strings and strings are their length as a varint followed by the
+//! bytes, an `Option` is a 0 or 1 byte followed by the value, and a
+//! sequence is its length followed by its items. Nothing is aligned or
+//! padded, so small values take one byte whatever their type.
+//!
+//! ```ignore
+//! let len = codec::to_slice(&record, &mut buffer)?;
+//! let record: CapabilityRecord = codec::from_slice(&buffer[..len])?;
+//! ```
+//!
+//! Neither side allocates: `Writer` fills a caller's buffer (or only
+//! counts, for `encoded_len`) and `Reader` borrows strings from its input.
+
+use crate::error::ErrorKind;
+use core::fmt;
+use core::marker::PhantomData;
+
+/// Why a value could not be encoded or decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecError {
+    /// The output buffer is too small.
+    BufferFull,
+    /// The input ends in the middle of a value.
+    Truncated,
+    /// A varint does not fit the integer type read.
+    Overflow,
+    /// A string is not valid UTF-8.
+    InvalidUtf8,
+    /// A value is out of range for its type (e.g. a `bool` of 2).
+    Invalid,
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CodecError::BufferFull => "buffer too small",
+            CodecError::Truncated => "input truncated",
+            CodecError::Overflow => "integer out of range",
+            CodecError::InvalidUtf8 => "invalid UTF-8",
+            CodecError::Invalid => "invalid value",
+        })
+    }
+}
+
+impl ErrorKind for CodecError {}
+
+/// A value that can be written with a `Writer`.
+pub trait Encode {
+    /// Write `self` to `writer`.
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError>;
+}
+
+/// A value that can be read with a `Reader`, possibly borrowing from its
+/// input.
+pub trait Decode<'a>: Sized {
+    /// Read a value from `reader`.
+    fn decode(reader: &mut Reader<'a>) -> Result<Self, CodecError>;
+}
+
+/// Writes encoded values into a buffer.
+pub struct Writer<'a> {
+    /// Where the bytes go; `None` only counts them.
+    buf: Option<&'a mut [u8]>,
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// A writer filling `buf` from the start.
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf: Some(buf),
+            len: 0,
+        }
+    }
+
+    /// A writer that only counts the bytes it is given.
+    pub fn sizer() -> Writer<'static> {
+        Writer { buf: None, len: 0 }
+    }
+
+    /// Bytes written so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether nothing has been written yet.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append `bytes` as they are, without a length.
+    pub fn raw(&mut self, bytes: &[u8]) -> Result<(), CodecError> {
+        let end = self.len + bytes.len();
+        if let Some(buf) = &mut self.buf {
+            buf.get_mut(self.len..end)
+                .ok_or(CodecError::BufferFull)?
+                .copy_from_slice(bytes);
+        }
+        self.len = end;
+        Ok(())
+    }
+
+    /// Write an unsigned integer as a varint.
+    pub fn varint(&mut self, mut value: u64) -> Result<(), CodecError> {
+        let mut bytes = [0u8; 10];
+        let mut n = 0;
+        loop {
+            let low = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes[n] = low;
+                n += 1;
+                break;
+            }
+            bytes[n] = low | 0x80;
+            n += 1;
+        }
+        self.raw(&bytes[..n])
+    }
+
+    /// Write a signed integer, zigzag-encoded, as a varint.
+    pub fn signed(&mut self, value: i64) -> Result<(), CodecError> {
+        self.varint(((value << 1) ^ (value >> 63)) as u64)
+    }
+
+    /// Write a byte string: its length, then the bytes.
+    pub fn bytes(&mut self, bytes: &[u8]) -> Result<(), CodecError> {
+        self.varint(bytes.len() as u64)?;
+        self.raw(bytes)
+    }
+
+    /// Write `value`.
+    pub fn encode<T: Encode + ?Sized>(&mut self, value: &T) -> Result<(), CodecError> {
+        value.encode(self)
+    }
+}
+
+/// Reads encoded values from a buffer.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// A reader starting at the beginning of `buf`.
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// Bytes not read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// Take the next `len` bytes as they are.
+    pub fn raw(&mut self, len: usize) -> Result<&'a [u8], CodecError> {
+        let end = self.pos.checked_add(len).ok_or(CodecError::Truncated)?;
+        let bytes = self.buf.get(self.pos..end).ok_or(CodecError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Read a varint.
+    pub fn varint(&mut self) -> Result<u64, CodecError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.raw(1)?[0];
+            let bits = u64::from(byte & 0x7f);
+            if shift == 63 && bits > 1 {
+                return Err(CodecError::Overflow);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(CodecError::Overflow)
+    }
+
+    /// Read a zigzag-encoded signed varint.
+    pub fn signed(&mut self) -> Result<i64, CodecError> {
+        let value = self.varint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    /// Read a byte string written by `Writer::bytes`.
+    pub fn bytes(&mut self) -> Result<&'a [u8], CodecError> {
+        let len = usize::try_from(self.varint()?).map_err(|_| CodecError::Truncated)?;
+        self.raw(len)
+    }
+
+    /// Read a `T`.
+    pub fn decode<T: Decode<'a>>(&mut self) -> Result<T, CodecError> {
+        T::decode(self)
+    }
+
+    /// Read the length of a sequence and iterate over its items.
+    ///
+    /// The iterator stops at the first item that fails to decode, after
+    /// yielding the error.
+    pub fn seq<T: Decode<'a>>(&mut self) -> Result<Items<'_, 'a, T>, CodecError> {
+        let left = self.varint()?;
+        Ok(Items {
+            reader: self,
+            left,
+            item: PhantomData,
+        })
+    }
+}
+
+/// The items of a sequence, from `Reader::seq`.
+pub struct Items<'r, 'a, T> {
+    reader: &'r mut Reader<'a>,
+    left: u64,
+    item: PhantomData<T>,
+}
+
+impl<'a, T: Decode<'a>> Iterator for Items<'_, 'a, T> {
+    type Item = Result<T, CodecError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.left == 0 {
+            return None;
+        }
+        let item = T::decode(self.reader);
+        self.left = if item.is_ok() { self.left - 1 } else { 0 };
+        Some(item)
+    }
+}
+
+/// A sequence to encode: its length, then each item. Decode it with
+/// `Reader::seq`.
+#[derive(Debug, Clone, Copy)]
+pub struct Seq<'s, T>(pub &'s [T]);
+
+impl<T: Encode> Encode for Seq<'_, T> {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+        writer.varint(self.0.len() as u64)?;
+        self.0.iter().try_for_each(|item| writer.encode(item))
+    }
+}
+
+/// Bytes `value` takes when encoded.
+pub fn encoded_len<T: Encode + ?Sized>(value: &T) -> usize {
+    let mut sizer = Writer::sizer();
+    // Counting never fails
+    let _ = value.encode(&mut sizer);
+    sizer.len()
+}
+
+/// Encode `value` at the start of `buf`, returning the bytes written.
+pub fn to_slice<T: Encode + ?Sized>(value: &T, buf: &mut [u8]) -> Result<usize, CodecError> {
+    let mut writer = Writer::new(buf);
+    value.encode(&mut writer)?;
+    Ok(writer.len())
+}
+
+/// Decode a `T` from the start of `buf`.
+pub fn from_slice<'a, T: Decode<'a>>(buf: &'a [u8]) -> Result<T, CodecError> {
+    Reader::new(buf).decode()
+}
+
+macro_rules! unsigned {
+    ($($ty:ty),*) => {$(
+        impl Encode for $ty {
+            fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+                writer.varint(u64::from(*self))
+            }
+        }
+
+        impl Decode<'_> for $ty {
+            fn decode(reader: &mut Reader<'_>) -> Result<Self, CodecError> {
+                <$ty>::try_from(reader.varint()?).map_err(|_| CodecError::Overflow)
+            }
+        }
+    )*};
+}
+
+macro_rules! signed {
+    ($($ty:ty),*) => {$(
+        impl Encode for $ty {
+            fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+                writer.signed(i64::from(*self))
+            }
+        }
+
+        impl Decode<'_> for $ty {
+            fn decode(reader: &mut Reader<'_>) -> Result<Self, CodecError> {
+                <$ty>::try_from(reader.signed()?).map_err(|_| CodecError::Overflow)
+            }
+        }
+    )*};
+}
+
+unsigned!(u8, u16, u32, u64);
+signed!(i8, i16, i32, i64);
+
+impl Encode for bool {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+        writer.raw(&[u8::from(*self)])
+    }
+}
+
+impl Decode<'_> for bool {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, CodecError> {
+        match reader.raw(1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(CodecError::Invalid),
+        }
+    }
+}
+
+impl Encode for [u8] {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+        writer.bytes(self)
+    }
+}
+
+impl<'a> Decode<'a> for &'a [u8] {
+    fn decode(reader: &mut Reader<'a>) -> Result<Self, CodecError> {
+        reader.bytes()
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+        writer.bytes(self.as_bytes())
+    }
+}
+
+impl<'a> Decode<'a> for &'a str {
+    fn decode(reader: &mut Reader<'a>) -> Result<Self, CodecError> {
+        core::str::from_utf8(reader.bytes()?).map_err(|_| CodecError::InvalidUtf8)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+        match self {
+            None => writer.encode(&false),
+            Some(value) => {
+                writer.encode(&true)?;
+                writer.encode(value)
+            }
+        }
+    }
+}
+
+impl<'a, T: Decode<'a>> Decode<'a> for Option<T> {
+    fn decode(reader: &mut Reader<'a>) -> Result<Self, CodecError> {
+        match reader.decode::<bool>()? {
+            false => Ok(None),
+            true => reader.decode().map(Some),
+        }
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+        (**self).encode(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capability::{CapabilityRecord, CapabilityRights};
+
+    fn round_trip<T>(value: T, expected: &[u8])
+    where
+        T: Encode + for<'a> Decode<'a> + PartialEq + fmt::Debug,
+    {
+        let mut buf = [0u8; 16];
+        let len = to_slice(&value, &mut buf).unwrap();
+        assert_eq!(&buf[..len], expected);
+        assert_eq!(encoded_len(&value), len);
+        assert_eq!(from_slice::<T>(&buf[..len]), Ok(value));
+    }
+
+    #[test]
+    fn integers_are_varints() {
+        round_trip(0u32, &[0]);
+        round_trip(127u8, &[0x7f]);
+        round_trip(300u16, &[0xac, 0x02]);
+        round_trip(
+            u64::MAX,
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+        );
+        round_trip(-1i32, &[1]);
+        round_trip(1i64, &[2]);
+        round_trip(
+            i64::MIN,
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+        );
+        round_trip(Some(true), &[1, 1]);
+        round_trip(None::<u8>, &[0]);
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        assert_eq!(from_slice::<u8>(&[0x80, 0x02]), Err(CodecError::Overflow));
+        assert_eq!(from_slice::<u64>(&[0xff; 10]), Err(CodecError::Overflow));
+        assert_eq!(from_slice::<u32>(&[0x80]), Err(CodecError::Truncated));
+        assert_eq!(from_slice::<bool>(&[2]), Err(CodecError::Invalid));
+        assert_eq!(from_slice::<&str>(&[1, 0xff]), Err(CodecError::InvalidUtf8));
+        assert_eq!(from_slice::<&[u8]>(&[3, 1, 2]), Err(CodecError::Truncated));
+    }
+
+    #[test]
+    fn strings_and_sequences_borrow_from_the_input() {
+        let mut buf = [0u8; 16];
+        let len = to_slice(&Seq(&["ab", "c"]), &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[2, 2, b'a', b'b', 1, b'c']);
+
+        let mut reader = Reader::new(&buf[..len]);
+        let items: std::vec::Vec<_> = reader.seq::<&str>().unwrap().collect();
+        assert_eq!(items, [Ok("ab"), Ok("c")]);
+        assert!(reader.remaining().is_empty());
+
+        // A failed item ends the sequence
+        let mut reader = Reader::new(&[3, 1, b'a', 5]);
+        let items: std::vec::Vec<_> = reader.seq::<&str>().unwrap().collect();
+        assert_eq!(items, [Ok("a"), Err(CodecError::Truncated)]);
+    }
+
+    #[test]
+    fn a_full_buffer_is_an_error() {
+        let mut buf = [0u8; 2];
+        assert_eq!(to_slice("abc", &mut buf), Err(CodecError::BufferFull));
+        assert_eq!(encoded_len("abc"), 4);
+    }
+
+    #[test]
+    fn capability_records_round_trip() {
+        let record = CapabilityRecord {
+            handle: 3,
+            kind: "timer",
+            rights: CapabilityRights::READ | CapabilityRights::WRITE,
+        };
+        let mut buf = [0u8; 16];
+        let len = to_slice(&record, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[6, 5, b't', b'i', b'm', b'e', b'r', 3]);
+        assert_eq!(from_slice(&buf[..len]), Ok(record));
+    }
+
+    #[test]
+    fn capability_lists_follow_their_version() {
+        use crate::capability::{
+            Capability, CapabilityDescriptor, CapabilityDetail, CapabilityList, CapabilityType,
+            Label,
+        };
+
+        let cap = Capability::new(
+            CapabilityType::Network {
+                first_port: 80,
+                last_port: 81,
+            },
+            CapabilityRights::LISTEN,
+        )
+        .with_label(Label::new("web").unwrap());
+        let descriptors = [CapabilityDescriptor::new(1, &cap, None)];
+        assert!(CapabilityList::new(3, &descriptors).is_none());
+
+        let mut buf = [0u8; 32];
+        let v1 = CapabilityList::new(1, &descriptors).unwrap();
+        let len = to_slice(&v1, &mut buf).unwrap();
+        let mut reader = Reader::new(&buf[..len]);
+        let records: std::vec::Vec<_> = reader.seq::<CapabilityRecord>().unwrap().collect();
+        assert_eq!(records, [Ok(descriptors[0].record)]);
+
+        let v2 = CapabilityList::new(2, &descriptors).unwrap();
+        let len = to_slice(&v2, &mut buf).unwrap();
+        let mut reader = Reader::new(&buf[..len]);
+        let Some(Ok(descriptor)) = reader.seq::<CapabilityDescriptor>().unwrap().next() else {
+            panic!("no descriptor");
+        };
+        assert_eq!(descriptor.label, Some("web"));
+        assert_eq!(
+            descriptor.detail,
+            CapabilityDetail::Ports {
+                first: 80,
+                last: 81
+            }
+        );
+    }
+}
@@ -1,6 +1,27 @@
 //! System-wide error types for SovelmaOS.
+//!
+//! Subsystems report failures as small enums (`NetError`, the kernel's
+//! `FsError`, ...). Where the enum alone would not say what failed, an
+//! `Error` carries it together with a chain of context frames, each
+//! saying what was being done:
+//!
+//! ```ignore
+//! let bytes = read(path).context("loading", path)?;
+//! // "not found, while loading /apps/hello.wasm"
+//! ```
+//!
+//! The chain lives inline, without allocating, so an `Error` stays small
+//! enough to return by value: at most `MAX_FRAMES` frames are kept,
+//! innermost first, and their details share `MAX_DETAIL` bytes.
 
-use core::fmt;
+use core::fmt::{self, Write};
+
+/// Most context frames an `Error` keeps; further ones are only counted.
+pub const MAX_FRAMES: usize = 3;
+
+/// Bytes of detail an `Error` keeps across its frames; a detail that
+/// does not fit is cut short.
+pub const MAX_DETAIL: usize = 40;
 
 /// Network subsystem error types.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -23,6 +44,8 @@ pub enum NetError {
     DhcpFailed,
     /// Generic I/O error
     IoError,
+    /// No memory (or socket table slot) left for another socket
+    OutOfMemory,
 }
 
 impl fmt::Display for NetError {
@@ -37,6 +60,291 @@ impl fmt::Display for NetError {
             NetError::DnsError => write!(f, "DNS error"),
             NetError::DhcpFailed => write!(f, "DHCP failed to acquire lease"),
             NetError::IoError => write!(f, "I/O error"),
+            NetError::OutOfMemory => write!(f, "out of memory for sockets"),
+        }
+    }
+}
+
+impl ErrorKind for NetError {}
+
+/// An error enum that can be wrapped in an `Error`.
+pub trait ErrorKind: fmt::Debug + fmt::Display {}
+
+/// One step of an error's context: what was being done, and to what.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// What was being done, e.g. "loading".
+    pub what: &'static str,
+    /// What it was being done to, e.g. a path; may be empty.
+    pub detail: &'a str,
+    /// Whether the detail was cut short.
+    pub truncated: bool,
+}
+
+impl fmt::Display for Frame<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "while {}", self.what)?;
+        if !self.detail.is_empty() || self.truncated {
+            write!(f, " {}", self.detail)?;
+        }
+        if self.truncated {
+            f.write_str("...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes into a fixed buffer, keeping what fits up to a char boundary.
+struct Cursor<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    truncated: bool,
+}
+
+impl Write for Cursor<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut take = s.len().min(self.buf.len() - self.len);
+        while !s.is_char_boundary(take) {
+            take -= 1;
+        }
+        self.buf[self.len..self.len + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take;
+        if take < s.len() {
+            self.truncated = true;
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+/// An error of kind `E` with the context it happened in.
+///
+/// Displays as `kind, while ..., while ...`; the alternate form (`{:#}`)
+/// puts each frame on a line of its own.
+#[derive(Debug, Clone)]
+pub struct Error<E> {
+    kind: E,
+    /// What each frame was doing, innermost first.
+    whats: [&'static str; MAX_FRAMES],
+    /// End of each frame's detail in `text`; a frame's detail starts
+    /// where the previous one's ends.
+    ends: [u8; MAX_FRAMES],
+    /// Bit `i`: frame `i`'s detail was cut short.
+    truncated: u8,
+    depth: u8,
+    omitted: u8,
+    text: [u8; MAX_DETAIL],
+}
+
+impl<E> Error<E> {
+    /// An error with no context yet.
+    pub fn new(kind: E) -> Self {
+        Error {
+            kind,
+            whats: [""; MAX_FRAMES],
+            ends: [0; MAX_FRAMES],
+            truncated: 0,
+            depth: 0,
+            omitted: 0,
+            text: [0; MAX_DETAIL],
+        }
+    }
+
+    /// What went wrong.
+    pub fn kind(&self) -> &E {
+        &self.kind
+    }
+
+    /// What went wrong, without the context.
+    pub fn into_kind(self) -> E {
+        self.kind
+    }
+
+    /// Add a frame for doing `what` to `detail` around the context so far.
+    /// The detail may be empty.
+    pub fn context(mut self, what: &'static str, detail: impl fmt::Display) -> Self {
+        let depth = usize::from(self.depth);
+        if depth == MAX_FRAMES {
+            self.omitted = self.omitted.saturating_add(1);
+            return self;
+        }
+        let start = depth
+            .checked_sub(1)
+            .map_or(0, |last| usize::from(self.ends[last]));
+        let mut cursor = Cursor {
+            buf: &mut self.text[start..],
+            len: 0,
+            truncated: false,
+        };
+        // A full buffer ends the write early; what fitted is kept
+        let _ = write!(cursor, "{}", detail);
+        self.whats[depth] = what;
+        self.ends[depth] = (start + cursor.len) as u8;
+        if cursor.truncated {
+            self.truncated |= 1 << depth;
+        }
+        self.depth += 1;
+        self
+    }
+
+    /// The frames, innermost first.
+    pub fn frames(&self) -> impl Iterator<Item = Frame<'_>> {
+        (0..usize::from(self.depth)).map(move |i| {
+            let start = i
+                .checked_sub(1)
+                .map_or(0, |last| usize::from(self.ends[last]));
+            let detail = &self.text[start..usize::from(self.ends[i])];
+            Frame {
+                what: self.whats[i],
+                detail: core::str::from_utf8(detail).unwrap_or(""),
+                truncated: self.truncated & (1 << i) != 0,
+            }
+        })
+    }
+
+    /// Turn the kind into another, keeping the context; e.g. to report a
+    /// filesystem error as the cause of a failed module load.
+    pub fn map_kind<F>(self, f: impl FnOnce(E) -> F) -> Error<F> {
+        Error {
+            kind: f(self.kind),
+            whats: self.whats,
+            ends: self.ends,
+            truncated: self.truncated,
+            depth: self.depth,
+            omitted: self.omitted,
+            text: self.text,
         }
     }
 }
+
+impl<E: ErrorKind> From<E> for Error<E> {
+    fn from(kind: E) -> Self {
+        Error::new(kind)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if f.alternate() { "\n  " } else { ", " };
+        write!(f, "{}", self.kind)?;
+        for frame in self.frames() {
+            write!(f, "{}{}", separator, frame)?;
+        }
+        if self.omitted > 0 {
+            write!(f, "{}({} more)", separator, self.omitted)?;
+        }
+        Ok(())
+    }
+}
+
+/// Adding context to the error of a `Result`.
+pub trait Context<T, E> {
+    /// Wrap the error, if any, in a frame for doing `what` to `detail`.
+    fn context(self, what: &'static str, detail: impl fmt::Display) -> Result<T, Error<E>>;
+}
+
+impl<T, E: ErrorKind> Context<T, E> for Result<T, E> {
+    fn context(self, what: &'static str, detail: impl fmt::Display) -> Result<T, Error<E>> {
+        self.map_err(|kind| Error::new(kind).context(what, detail))
+    }
+}
+
+impl<T, E> Context<T, E> for Result<T, Error<E>> {
+    fn context(self, what: &'static str, detail: impl fmt::Display) -> Result<T, Error<E>> {
+        self.map_err(|error| error.context(what, detail))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::format;
+    use std::vec::Vec;
+
+    fn connect() -> Result<(), NetError> {
+        Err(NetError::ConnectionRefused)
+    }
+
+    #[test]
+    fn display_lists_frames_innermost_first() {
+        let error = connect()
+            .context("connecting to", "10.0.2.2:80")
+            .context("fetching", "/index.html")
+            .unwrap_err();
+        assert_eq!(*error.kind(), NetError::ConnectionRefused);
+        assert_eq!(
+            format!("{}", error),
+            "connection refused, while connecting to 10.0.2.2:80, while fetching /index.html"
+        );
+        assert_eq!(
+            format!("{:#}", error),
+            "connection refused\n  while connecting to 10.0.2.2:80\n  while fetching /index.html"
+        );
+    }
+
+    #[test]
+    fn frames_without_detail() {
+        let error = Error::new(NetError::NoAddress).context("starting DHCP", "");
+        assert_eq!(
+            format!("{}", error),
+            "no IP address configured, while starting DHCP"
+        );
+    }
+
+    #[test]
+    fn long_details_are_cut_on_a_char_boundary() {
+        let path = "\u{e9}".repeat(MAX_DETAIL);
+        let error = Error::new(NetError::IoError).context("loading", &path);
+        let frame = error.frames().next().unwrap();
+        assert_eq!(frame.detail.len(), MAX_DETAIL);
+        assert!(frame.truncated);
+        assert_eq!(
+            format!("{}", frame),
+            format!("while loading {}...", frame.detail)
+        );
+
+        let ascii = "a".repeat(MAX_DETAIL - 1);
+        let error =
+            Error::new(NetError::IoError).context("loading", format_args!("{}\u{e9}", ascii));
+        assert_eq!(error.frames().next().unwrap().detail, ascii);
+    }
+
+    #[test]
+    fn details_share_the_buffer() {
+        let error = Error::new(NetError::IoError)
+            .context("reading", "a".repeat(MAX_DETAIL - 4))
+            .context("loading", "bbbbbbbb");
+        let frames: Vec<Frame> = error.frames().collect();
+        assert!(!frames[0].truncated);
+        assert_eq!(frames[1].detail, "bbbb");
+        assert!(frames[1].truncated);
+        let error = error.context("running", "c");
+        assert_eq!(error.frames().nth(2).unwrap().detail, "");
+    }
+
+    #[test]
+    fn error_is_small() {
+        assert!(core::mem::size_of::<Error<NetError>>() <= 104);
+    }
+
+    #[test]
+    fn frames_past_the_limit_are_counted() {
+        let mut error = Error::new(NetError::Timeout);
+        for _ in 0..MAX_FRAMES + 2 {
+            error = error.context("retrying", "");
+        }
+        assert_eq!(error.frames().count(), MAX_FRAMES);
+        assert!(format!("{}", error).ends_with(", (2 more)"));
+    }
+
+    #[test]
+    fn map_kind_keeps_context() {
+        let error = Error::new(NetError::DnsError)
+            .context("resolving", "example.com")
+            .map_kind(|_| "lookup failed");
+        assert_eq!(
+            format!("{}", error),
+            "lookup failed, while resolving example.com"
+        );
+    }
+}
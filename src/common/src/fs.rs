@@ -0,0 +1,122 @@
+//! Directory listings passed from the kernel to WASM processes.
+//!
+//! `sp_fs_readdir` writes the entries of a directory as a `codec`
+//! sequence of `DirEntryRecord`s: each entry's name, kind and size. A
+//! directory may not fit the caller's buffer at once, so the call starts
+//! at a given entry and writes as many as `fit` says fit; the caller asks
+//! again from where the last batch ended until one comes back empty.
+
+use crate::codec::{self, CodecError, Decode, Encode, Reader, Writer};
+
+/// What a directory entry is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// A regular file.
+    File,
+    /// A directory, or a directory mounted there.
+    Directory,
+    /// A device node.
+    Device,
+}
+
+impl Encode for EntryKind {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+        writer.encode(&match self {
+            EntryKind::File => 0u8,
+            EntryKind::Directory => 1,
+            EntryKind::Device => 2,
+        })
+    }
+}
+
+impl Decode<'_> for EntryKind {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, CodecError> {
+        match reader.decode::<u8>()? {
+            0 => Ok(EntryKind::File),
+            1 => Ok(EntryKind::Directory),
+            2 => Ok(EntryKind::Device),
+            _ => Err(CodecError::Invalid),
+        }
+    }
+}
+
+/// A directory entry as `sp_fs_readdir` lists it, encoded with `codec` as
+/// its name, kind and size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirEntryRecord<'a> {
+    /// Name within the directory.
+    pub name: &'a str,
+    /// What the entry is.
+    pub kind: EntryKind,
+    /// Size in bytes of a file; 0 for anything else.
+    pub size: u64,
+}
+
+impl Encode for DirEntryRecord<'_> {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), CodecError> {
+        writer.encode(self.name)?;
+        writer.encode(&self.kind)?;
+        writer.encode(&self.size)
+    }
+}
+
+impl<'a> Decode<'a> for DirEntryRecord<'a> {
+    fn decode(reader: &mut Reader<'a>) -> Result<Self, CodecError> {
+        Ok(Self {
+            name: reader.decode()?,
+            kind: reader.decode()?,
+            size: reader.decode()?,
+        })
+    }
+}
+
+/// How many of `entries`, from the first, fit in `len` bytes encoded as a
+/// `codec::Seq`.
+pub fn fit(entries: &[DirEntryRecord<'_>], len: usize) -> usize {
+    let mut items = 0;
+    for (count, entry) in entries.iter().enumerate() {
+        items += codec::encoded_len(entry);
+        if codec::encoded_len(&(count as u64 + 1)) + items > len {
+            return count;
+        }
+    }
+    entries.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Seq;
+
+    #[test]
+    fn listings_are_cut_to_fit() {
+        let entries = [
+            DirEntryRecord {
+                name: "a.txt",
+                kind: EntryKind::File,
+                size: 300,
+            },
+            DirEntryRecord {
+                name: "sub",
+                kind: EntryKind::Directory,
+                size: 0,
+            },
+        ];
+        // Count, then "a.txt" (6), kind (1) and size (2), then "sub" (4),
+        // kind and size (1 each)
+        assert_eq!(codec::encoded_len(&Seq(&entries)), 16);
+        assert_eq!(fit(&entries, 16), 2);
+        assert_eq!(fit(&entries, 15), 1);
+        assert_eq!(fit(&entries, 9), 0);
+
+        let mut buf = [0u8; 16];
+        let len = codec::to_slice(&Seq(&entries), &mut buf).unwrap();
+        let mut reader = Reader::new(&buf[..len]);
+        let decoded: std::vec::Vec<_> = reader
+            .seq::<DirEntryRecord>()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(decoded, entries);
+    }
+}
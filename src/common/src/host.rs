@@ -0,0 +1,85 @@
+//! The interface between WASM processes and the host functions.
+//!
+//! The kernel (`wasm::host`) and the simulator (`src/sim`) implement the
+//! same host functions; the error codes they return and the limits they
+//! apply are defined here, once, so the two cannot disagree.
+
+/// Most bytes one `sp_fs_write` call writes; longer writes are short.
+///
+/// Keeps the fuel cost of a call well within a slice.
+pub const FS_WRITE_MAX: usize = 16 * 1024;
+
+/// Longest path `sp_fs_unlink` and `sp_fs_rename` take.
+pub const FS_PATH_MAX: usize = 1024;
+
+/// Bytes a process may add to files, unless granted otherwise.
+pub const DEFAULT_FS_QUOTA: u64 = 1024 * 1024;
+
+/// Most sockets one process may hold open, so a single process cannot
+/// take the whole socket table.
+pub const MAX_PROCESS_SOCKETS: usize = 8;
+
+/// Host function error codes returned to WASM modules.
+///
+/// These are returned as negative i32/i64 values from host functions.
+pub mod error {
+    /// Capability not found or generation mismatch.
+    pub const CAP_NOT_FOUND: i64 = -1;
+    /// WASM module did not export a "memory" object.
+    pub const NO_MEMORY_EXPORT: i64 = -2;
+    /// Failed to read from WASM linear memory.
+    pub const MEMORY_READ_FAILED: i64 = -3;
+    /// Path string was not valid UTF-8.
+    pub const INVALID_UTF8: i64 = -4;
+    /// Capability lacks required rights for operation.
+    pub const PERMISSION_DENIED: i64 = -5;
+    /// Expected a directory capability, got something else.
+    pub const NOT_A_DIRECTORY: i64 = -6;
+    /// Filesystem operation failed.
+    pub const FS_ERROR: i64 = -7;
+    /// Buffer provided was too small.
+    pub const BUFFER_TOO_SMALL: i64 = -8;
+    /// Failed to write to WASM linear memory.
+    pub const MEMORY_WRITE_FAILED: i64 = -9;
+    /// Expected a file capability, got something else.
+    pub const NOT_A_FILE: i64 = -10;
+    /// Mutex is currently locked (for try_lock).
+    pub const MUTEX_LOCKED: i64 = -11;
+    /// Semaphore has no available permits (for try_acquire).
+    pub const SEM_NO_PERMITS: i64 = -12;
+    /// Invalid handle (mutex/semaphore/timer not found).
+    pub const INVALID_HANDLE: i64 = -13;
+    /// Process already owns the maximum number of timers.
+    pub const TOO_MANY_TIMERS: i64 = -14;
+    /// Argument out of range (e.g. a negative duration).
+    pub const INVALID_ARGUMENT: i64 = -15;
+    /// Target process does not exist (any more).
+    pub const NO_SUCH_PROCESS: i64 = -16;
+    /// Target process's event queue is full.
+    pub const QUEUE_FULL: i64 = -17;
+    /// Expected a serial port capability, got something else.
+    pub const NOT_A_SERIAL_PORT: i64 = -18;
+    /// The serial port is not enabled.
+    pub const DEVICE_UNAVAILABLE: i64 = -19;
+    /// The write would exceed the process's filesystem quota.
+    pub const QUOTA_EXCEEDED: i64 = -20;
+    /// stdin is empty; try again later.
+    pub const WOULD_BLOCK: i64 = -21;
+    /// The process reading stdout has exited.
+    pub const BROKEN_PIPE: i64 = -22;
+    /// The configuration key is not set.
+    pub const NO_SUCH_KEY: i64 = -23;
+    /// The network stack refused the operation (no address, connection
+    /// refused or reset, port in use).
+    pub const NET_ERROR: i64 = -24;
+    /// The kernel has no memory (or socket table slot) for another socket.
+    pub const OUT_OF_MEMORY: i64 = -25;
+    /// Process already holds `MAX_PROCESS_SOCKETS` sockets.
+    pub const TOO_MANY_SOCKETS: i64 = -26;
+    /// The futex word did not hold the expected value.
+    pub const VALUE_CHANGED: i64 = -27;
+    /// Nothing happened before the timeout.
+    pub const TIMED_OUT: i64 = -28;
+    /// The file capability holds no lock to release.
+    pub const NOT_LOCKED: i64 = -29;
+}
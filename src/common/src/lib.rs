@@ -3,5 +3,9 @@
 extern crate std;
 
 pub mod capability;
+pub mod codec;
 pub mod error;
+pub mod fs;
+pub mod host;
 pub mod net;
+pub mod rate;
@@ -0,0 +1,141 @@
+//! Rate limits on capability use.
+//!
+//! A `RateLimit` says how often a capability may be used; a `TokenBucket`
+//! enforces one. The bucket holds up to `burst` tokens and refills at
+//! `per_second` tokens a second. Each use takes a token, and a use that
+//! finds the bucket empty still takes one, leaving the bucket in debt: the
+//! caller is told when the debt is paid off and waits until then. Time is
+//! passed in as milliseconds, so the bucket needs no clock of its own.
+
+use core::fmt;
+
+/// Thousandths of a token, the unit the bucket counts in, so refilling
+/// needs no division.
+const MILLI: i64 = 1000;
+
+/// How often a capability may be used: `per_second` operations a second,
+/// with bursts of up to `burst` at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Operations allowed per second on average.
+    pub per_second: u32,
+    /// Operations allowed at once after a pause.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// A limit of `per_second` operations a second in bursts of `burst`.
+    ///
+    /// Returns `None` if either is zero.
+    pub fn new(per_second: u32, burst: u32) -> Option<Self> {
+        (per_second > 0 && burst > 0).then_some(Self { per_second, burst })
+    }
+
+    /// Parse `<per_second>[/<burst>]`; the burst defaults to one second's
+    /// worth.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (per_second, burst) = match spec.split_once('/') {
+            Some((per_second, burst)) => (per_second.parse().ok()?, burst.parse().ok()?),
+            None => {
+                let per_second = spec.parse().ok()?;
+                (per_second, per_second)
+            }
+        };
+        Self::new(per_second, burst)
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/s", self.per_second)?;
+        if self.burst != self.per_second {
+            write!(f, " (burst {})", self.burst)?;
+        }
+        Ok(())
+    }
+}
+
+/// Tokens left under a `RateLimit`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    /// Thousandths of a token; negative while in debt.
+    tokens: i64,
+    /// When `tokens` was last brought up to date (ms).
+    updated_ms: u64,
+}
+
+impl TokenBucket {
+    /// A full bucket for `limit`.
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: i64::from(limit.burst) * MILLI,
+            updated_ms: 0,
+        }
+    }
+
+    /// The limit the bucket enforces.
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Add what has refilled by `now_ms`.
+    fn refill(&mut self, now_ms: u64) {
+        let elapsed = now_ms.saturating_sub(self.updated_ms);
+        let refilled = elapsed.saturating_mul(u64::from(self.limit.per_second));
+        let full = i64::from(self.limit.burst) * MILLI;
+        self.tokens = self
+            .tokens
+            .saturating_add(i64::try_from(refilled).unwrap_or(i64::MAX))
+            .min(full);
+        self.updated_ms = self.updated_ms.max(now_ms);
+    }
+
+    /// Take a token at `now_ms`.
+    ///
+    /// Returns `None` if one was available, or else the time (ms) at which
+    /// the bucket is out of debt again, which the caller waits for.
+    pub fn take(&mut self, now_ms: u64) -> Option<u64> {
+        self.refill(now_ms);
+        self.tokens -= MILLI;
+        if self.tokens >= 0 {
+            return None;
+        }
+        let per_ms = i64::from(self.limit.per_second);
+        let wait = (-self.tokens + per_ms - 1) / per_ms;
+        Some(self.updated_ms + wait as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rate_and_burst() {
+        assert_eq!(RateLimit::parse("100"), RateLimit::new(100, 100));
+        assert_eq!(RateLimit::parse("10/50"), RateLimit::new(10, 50));
+        assert_eq!(RateLimit::parse("0"), None);
+        assert_eq!(RateLimit::parse("10/0"), None);
+        assert_eq!(RateLimit::parse("fast"), None);
+        let limit = RateLimit::new(10, 50).expect("nonzero");
+        assert_eq!(std::format!("{}", limit), "10/s (burst 50)");
+    }
+
+    #[test]
+    fn bucket_allows_bursts_then_paces() {
+        let mut bucket = TokenBucket::new(RateLimit::new(10, 3).expect("nonzero"));
+        for _ in 0..3 {
+            assert_eq!(bucket.take(1000), None);
+        }
+        // Empty: the next token arrives 100 ms later
+        assert_eq!(bucket.take(1000), Some(1100));
+        assert_eq!(bucket.take(1100), Some(1200));
+        // A long pause refills no more than the burst
+        for _ in 0..3 {
+            assert_eq!(bucket.take(60_000), None);
+        }
+        assert!(bucket.take(60_000).is_some());
+    }
+}
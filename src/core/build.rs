@@ -0,0 +1,54 @@
+//! Build script for the shared kernel code.
+//!
+//! `SOVELMA_APPS` names a directory of WASM modules (see `scripts/apps.sh`).
+//! Every `*.wasm` in it is listed in `$OUT_DIR/apps.rs`, which `fs::initrd`
+//! includes to install them under `apps/`; without it the list is empty.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    embed_apps("SOVELMA_APPS", "apps.rs");
+}
+
+/// Write `$OUT_DIR/name`: a slice expression of `(file name, contents)` for
+/// every `*.wasm` in the directory named by `var`, in name order.
+fn embed_apps(var: &str, name: &str) {
+    println!("cargo:rerun-if-env-changed={}", var);
+
+    let out_dir = env::var_os("OUT_DIR").expect("cargo sets OUT_DIR");
+    let out = PathBuf::from(out_dir).join(name);
+
+    let mut apps = Vec::new();
+    if let Some(dir) = env::var_os(var) {
+        let dir = PathBuf::from(dir);
+        println!("cargo:rerun-if-changed={}", dir.display());
+        let entries =
+            fs::read_dir(&dir).unwrap_or_else(|e| panic!("cannot read {}: {}", dir.display(), e));
+        for entry in entries {
+            let path = entry
+                .unwrap_or_else(|e| panic!("cannot read {}: {}", dir.display(), e))
+                .path();
+            if path.extension().is_some_and(|ext| ext == "wasm") {
+                println!("cargo:rerun-if-changed={}", path.display());
+                apps.push(path);
+            }
+        }
+    }
+    apps.sort();
+
+    let mut list = String::from("&[\n");
+    for path in &apps {
+        let file_name = path.file_name().expect("read_dir yields names");
+        let path = fs::canonicalize(path)
+            .unwrap_or_else(|e| panic!("cannot resolve {}: {}", path.display(), e));
+        list += &format!(
+            "    ({:?}, include_bytes!({:?})),\n",
+            file_name.to_string_lossy(),
+            path.display().to_string()
+        );
+    }
+    list += "]\n";
+    fs::write(&out, list).unwrap_or_else(|e| panic!("cannot write {}: {}", name, e));
+}
@@ -0,0 +1,22 @@
+//! Device nodes under `/dev`.
+//!
+//! Devices are nodes in the root filesystem that name hardware instead of
+//! holding data. Opening one from WASM (`sp_fs_open`) yields a capability
+//! for the device itself, e.g. `CapabilityType::Serial`, with the rights of
+//! the directory capability it was opened through.
+
+use super::{Device, ROOT_FS};
+use alloc::string::String;
+
+/// Directory holding the device nodes.
+pub const DEV_DIR: &str = "dev";
+
+/// Path of the node for serial port `com`: `dev/serial<com>`.
+pub fn serial_path(com: u8) -> String {
+    alloc::format!("{}/serial{}", DEV_DIR, com)
+}
+
+/// Add the node for serial port `com` at I/O base `port`.
+pub fn add_serial(com: u8, port: u16) {
+    ROOT_FS.add_device(&serial_path(com), Device::Serial { com, port });
+}
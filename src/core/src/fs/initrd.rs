@@ -0,0 +1,23 @@
+//! WASM modules built into the kernel image.
+//!
+//! `scripts/apps.sh` builds the example apps and points `SOVELMA_APPS` at
+//! them; the build script lists them in `apps.rs`. `install` copies each
+//! one into the RAM filesystem under `APPS_DIR`, where `wasm run` and
+//! `apps` find them.
+
+use super::ROOT_FS;
+use alloc::format;
+
+/// Directory the modules are installed in.
+pub const APPS_DIR: &str = "apps";
+
+/// The embedded modules, as (file name, contents).
+static APPS: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/apps.rs"));
+
+/// Install the embedded modules. Returns how many there were.
+pub fn install() -> usize {
+    for (name, module) in APPS {
+        ROOT_FS.add_file(&format!("{}/{}", APPS_DIR, name), module);
+    }
+    APPS.len()
+}
@@ -0,0 +1,156 @@
+//! Filesystem Traits and Types.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use sovelma_common::error::{Context, Error, ErrorKind};
+use sovelma_common::fs::EntryKind;
+
+/// Error type for filesystem operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    /// File or directory not found.
+    NotFound,
+    /// Permission denied.
+    PermissionDenied,
+    /// Invalid file handle.
+    InvalidHandle,
+    /// The quota of a tmpfs is used up.
+    NoSpace,
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FsError::NotFound => "not found",
+            FsError::PermissionDenied => "permission denied",
+            FsError::InvalidHandle => "invalid file handle",
+            FsError::NoSpace => "no space left",
+        })
+    }
+}
+
+impl ErrorKind for FsError {}
+
+/// The hardware behind a device node (see `devfs`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    /// A serial port.
+    Serial {
+        /// Port number (COM1 is 1).
+        com: u8,
+        /// The I/O port address.
+        port: u16,
+    },
+}
+
+/// A handle to an open file or directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileHandle(pub u32);
+
+/// An entry of a directory, as `FileSystem::readdir` lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// Name within the directory.
+    pub name: String,
+    /// What the entry is.
+    pub kind: EntryKind,
+    /// Size in bytes of a file; 0 for anything else.
+    pub size: usize,
+}
+
+/// A change watch on a filesystem path.
+///
+/// Created by `RamFs::watch`; `RamFs::changed` reports whether the path
+/// was modified since the watch last observed it.
+#[derive(Debug, Clone)]
+pub struct FsWatch {
+    path: String,
+    seen: u64,
+}
+
+impl FsWatch {
+    /// Normalized path being watched.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+/// Trait for a filesystem.
+pub trait FileSystem {
+    /// Open a file by path.
+    fn open(&self, path: &str) -> Result<FileHandle, FsError>;
+
+    /// Open a file relative to a directory handle.
+    fn open_at(&self, base: FileHandle, path: &str) -> Result<FileHandle, FsError>;
+
+    /// Create a new directory.
+    fn mkdir(&self, path: &str) -> Result<(), FsError>;
+
+    /// Create a new directory relative to an existing directory handle.
+    fn mkdir_at(&self, base: FileHandle, path: &str) -> Result<(), FsError>;
+
+    /// Remove a file, device node or empty directory by path.
+    fn unlink(&self, path: &str) -> Result<(), FsError>;
+
+    /// Remove a file, device node or empty directory relative to a
+    /// directory handle. Handles open on it keep working.
+    fn unlink_at(&self, base: FileHandle, path: &str) -> Result<(), FsError>;
+
+    /// Move `from` to `to`, which must not exist yet.
+    fn rename(&self, from: &str, to: &str) -> Result<(), FsError>;
+
+    /// Move `from` to `to`, both relative to a directory handle. Handles
+    /// open on what is moved keep working.
+    fn rename_at(&self, base: FileHandle, from: &str, to: &str) -> Result<(), FsError>;
+
+    /// Read from an open file.
+    fn read(&self, handle: FileHandle, buffer: &mut [u8], offset: usize) -> Result<usize, FsError>;
+
+    /// Write to an open file at `offset`, growing it as needed (a gap is
+    /// filled with zeros). Returns the number of bytes written.
+    fn write(&self, handle: FileHandle, data: &[u8], offset: usize) -> Result<usize, FsError>;
+
+    /// Get file size.
+    fn size(&self, handle: FileHandle) -> Result<usize, FsError>;
+
+    /// List the directory `handle` refers to, sorted by name.
+    fn readdir(&self, handle: FileHandle) -> Result<Vec<DirEntry>, FsError>;
+
+    /// Check if a handle refers to a directory.
+    fn is_dir(&self, handle: FileHandle) -> bool;
+
+    /// The device a handle refers to, if it is a device node.
+    fn device(&self, handle: FileHandle) -> Option<Device>;
+
+    /// Close a file handle.
+    fn close(&self, handle: FileHandle);
+}
+
+// Global FS instance
+use self::ramfs::RamFs;
+use lazy_static::lazy_static;
+
+pub mod devfs;
+pub mod initrd;
+pub mod ramfs;
+
+lazy_static! {
+    /// The root filesystem.
+    pub static ref ROOT_FS: RamFs = RamFs::new();
+}
+
+/// Path of the root directory of `ROOT_FS`.
+pub const ROOT_PATH: &str = "/";
+
+/// Read the whole file at `path` in the root filesystem. The error names
+/// the path.
+pub fn read_file(path: &str) -> Result<Vec<u8>, Error<FsError>> {
+    let handle = ROOT_FS.open(path).context("opening", path)?;
+    let result = ROOT_FS.size(handle).and_then(|size| {
+        let mut buffer = alloc::vec![0u8; size];
+        ROOT_FS.read(handle, &mut buffer, 0).map(|_| buffer)
+    });
+    ROOT_FS.close(handle);
+    result.context("reading", path)
+}
@@ -0,0 +1,891 @@
+//! RAM Filesystem implementation (Hierarchical).
+//!
+//! Besides the tree under the root, the filesystem holds mount namespaces:
+//! directories that nothing under the root reaches, made with `namespace`,
+//! into which `mount` binds directories of the tree, optionally read-only.
+//! A handle to a namespace is a view of exactly what was mounted in it;
+//! directories created in it are private to it, and it is freed with the
+//! last handle into it. `mount_tmpfs` mounts a new empty directory whose
+//! files may only grow to a fixed total size, freed with the namespace.
+//!
+//! Advisory locks (`try_lock`, `unlock`) are held by open handles on the
+//! node they refer to: any number of shared locks or one exclusive lock,
+//! released with `unlock` or when the handle is closed. They only keep
+//! other lockers out; reads and writes do not check them.
+//!
+//! Handles refer to nodes, not paths, so a node that is renamed keeps its
+//! handles and their locks, and a file that is unlinked while open stays
+//! readable and writable through its handles. Its bytes only go back to
+//! its tmpfs's quota when the last of them is closed. A directory cannot
+//! be unlinked while it has entries or open handles, and mounts cannot be
+//! unlinked or renamed at all.
+
+use super::{Device, DirEntry, FileHandle, FileSystem, FsError, FsWatch};
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use sovelma_common::fs::EntryKind;
+use spin::{Mutex, RwLock}; // Use RwLock for nodes
+
+#[derive(Clone)]
+enum Node {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<RwLock<Node>>>),
+    Device(Device),
+    /// A directory bound into a namespace by `RamFs::mount` or
+    /// `RamFs::mount_tmpfs`.
+    Mount {
+        target: Arc<RwLock<Node>>,
+        read_only: bool,
+        quota: Option<Arc<Quota>>,
+    },
+}
+
+/// Space for the files of a tmpfs.
+struct Quota {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl Quota {
+    /// Take `bytes` from the quota, if that many are left.
+    fn reserve(&self, bytes: usize) -> Result<(), FsError> {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|&used| used <= self.limit)
+            })
+            .map(|_| ())
+            .map_err(|_| FsError::NoSpace)
+    }
+
+    /// Give `bytes` back to the quota.
+    fn release(&self, bytes: usize) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(bytes))
+            });
+    }
+}
+
+/// Whether `a` and `b` are the same tmpfs's quota, or both none.
+fn same_quota(a: &Option<Arc<Quota>>, b: &Option<Arc<Quota>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// An open handle, or what a lookup has reached.
+#[derive(Clone)]
+struct Open {
+    node: Arc<RwLock<Node>>,
+    /// Whether the node was reached through a read-only mount.
+    read_only: bool,
+    /// The quota of the tmpfs the node is in.
+    quota: Option<Arc<Quota>>,
+    /// The advisory lock the handle holds; `Some(true)` if exclusive.
+    lock: Option<bool>,
+    /// Whether the node is a file that was unlinked while open, whose
+    /// bytes are released from `quota` when its last handle is closed.
+    unlinked: bool,
+}
+
+impl Open {
+    fn new(node: Arc<RwLock<Node>>) -> Self {
+        Self {
+            node,
+            read_only: false,
+            quota: None,
+            lock: None,
+            unlinked: false,
+        }
+    }
+}
+
+/// Whether `handle` may take a lock (exclusive if `exclusive`) on its
+/// node, given the locks the other handles in `handles` hold.
+fn lock_free(handles: &BTreeMap<FileHandle, Open>, handle: FileHandle, exclusive: bool) -> bool {
+    let Some(open) = handles.get(&handle) else {
+        return false;
+    };
+    handles.iter().all(|(other, other_open)| {
+        *other == handle
+            || !Arc::ptr_eq(&other_open.node, &open.node)
+            || match other_open.lock {
+                None => true,
+                Some(other_exclusive) => !exclusive && !other_exclusive,
+            }
+    })
+}
+
+/// A hierarchical in-memory filesystem.
+///
+/// Each node has its own lock. Locks are taken in the order
+/// `open_handles`, nodes, `versions`; a lookup holds one node at a time,
+/// releasing the parent before locking the child, so no two node locks
+/// are ever held together.
+pub struct RamFs {
+    root: Arc<RwLock<Node>>,
+    open_handles: Mutex<BTreeMap<FileHandle, Open>>,
+    /// Next handle `open` or `open_at` returns.
+    next_handle: AtomicU32,
+    /// Modification counters by normalized path, for `FsWatch`.
+    versions: Mutex<BTreeMap<String, u64>>,
+}
+
+impl RamFs {
+    /// Create a new empty RAM filesystem.
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(RwLock::new(Node::Directory(BTreeMap::new()))),
+            open_handles: Mutex::new(BTreeMap::new()),
+            next_handle: AtomicU32::new(1),
+            versions: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Number of open handles.
+    pub fn open_count(&self) -> usize {
+        self.open_handles.lock().len()
+    }
+
+    /// Register `open` under a new handle.
+    fn insert_handle(&self, open: Open) -> FileHandle {
+        let handle = FileHandle(self.next_handle.fetch_add(1, Ordering::Relaxed));
+        self.open_handles.lock().insert(handle, open);
+        handle
+    }
+
+    /// What `handle` refers to, without its lock.
+    fn handle_node(&self, handle: FileHandle) -> Result<Open, FsError> {
+        let handles = self.open_handles.lock();
+        let open = handles.get(&handle).ok_or(FsError::InvalidHandle)?;
+        Ok(Open {
+            lock: None,
+            ..open.clone()
+        })
+    }
+
+    /// What `base` refers to, or the root for `FileHandle(0)`.
+    fn base_node(&self, base: FileHandle) -> Result<Open, FsError> {
+        if base.0 == 0 {
+            Ok(Open::new(self.root.clone()))
+        } else {
+            self.handle_node(base)
+        }
+    }
+
+    /// Whether `handle` was opened through a read-only mount, so its file
+    /// cannot be written nor directories created below it.
+    pub fn is_read_only(&self, handle: FileHandle) -> bool {
+        self.open_handles
+            .lock()
+            .get(&handle)
+            .is_some_and(|open| open.read_only)
+    }
+
+    /// Take an advisory lock on the node `handle` refers to: exclusive if
+    /// `exclusive`, else shared. A lock the handle holds already is
+    /// replaced.
+    ///
+    /// Returns whether it was taken; it is not while another handle holds
+    /// an exclusive lock, or any lock if `exclusive`.
+    pub fn try_lock(&self, handle: FileHandle, exclusive: bool) -> Result<bool, FsError> {
+        let mut handles = self.open_handles.lock();
+        if !handles.contains_key(&handle) {
+            return Err(FsError::InvalidHandle);
+        }
+        if !lock_free(&handles, handle, exclusive) {
+            return Ok(false);
+        }
+        if let Some(open) = handles.get_mut(&handle) {
+            open.lock = Some(exclusive);
+        }
+        Ok(true)
+    }
+
+    /// Whether `try_lock` would take the lock now.
+    pub fn can_lock(&self, handle: FileHandle, exclusive: bool) -> bool {
+        lock_free(&self.open_handles.lock(), handle, exclusive)
+    }
+
+    /// Release the advisory lock `handle` holds.
+    ///
+    /// Returns whether it held one.
+    pub fn unlock(&self, handle: FileHandle) -> Result<bool, FsError> {
+        let mut handles = self.open_handles.lock();
+        let open = handles.get_mut(&handle).ok_or(FsError::InvalidHandle)?;
+        Ok(open.lock.take().is_some())
+    }
+
+    /// Path of the node `handle` refers to, from the root (`/` for the
+    /// root itself); `None` for a node the root does not reach, such as a
+    /// namespace or what was created in one.
+    pub fn path(&self, handle: FileHandle) -> Option<String> {
+        let node = self.handle_node(handle).ok()?.node;
+        if Arc::ptr_eq(&node, &self.root) {
+            return Some(String::from("/"));
+        }
+        find_path(&self.root, &node, "")
+    }
+
+    /// Bytes used and the limit of the tmpfs `handle` is in, if any.
+    pub fn quota(&self, handle: FileHandle) -> Option<(usize, usize)> {
+        let handles = self.open_handles.lock();
+        let quota = handles.get(&handle)?.quota.as_ref()?;
+        Some((quota.used.load(Ordering::Relaxed), quota.limit))
+    }
+
+    /// Create an empty mount namespace and open it.
+    ///
+    /// The namespace is a directory outside the tree: only handles opened
+    /// from the returned one reach it or what is mounted in it.
+    pub fn namespace(&self) -> FileHandle {
+        self.insert_handle(Open::new(Arc::new(RwLock::new(Node::Directory(
+            BTreeMap::new(),
+        )))))
+    }
+
+    /// Bind the directory `dir` at `path` in the namespace `namespace`,
+    /// read-only if `read_only` or if `dir` itself is.
+    ///
+    /// Directories missing on the way are created in the namespace.
+    /// Nothing may be mounted at a path that already exists or that leads
+    /// through another mount, so mounting never changes the directories
+    /// it binds.
+    pub fn mount(
+        &self,
+        namespace: FileHandle,
+        path: &str,
+        dir: FileHandle,
+        read_only: bool,
+    ) -> Result<(), FsError> {
+        let dir = self.handle_node(dir)?;
+        if !matches!(*dir.node.read(), Node::Directory(_)) {
+            return Err(FsError::InvalidHandle);
+        }
+        let mount = Node::Mount {
+            target: dir.node,
+            read_only: read_only || dir.read_only,
+            quota: dir.quota,
+        };
+        self.bind(namespace, path, mount)
+    }
+
+    /// Mount a new empty directory at `path` in the namespace `namespace`,
+    /// in which files may take up to `limit` bytes in all.
+    ///
+    /// Like the rest of the namespace, it is freed once no handle into
+    /// the namespace is left.
+    pub fn mount_tmpfs(
+        &self,
+        namespace: FileHandle,
+        path: &str,
+        limit: usize,
+    ) -> Result<(), FsError> {
+        let mount = Node::Mount {
+            target: Arc::new(RwLock::new(Node::Directory(BTreeMap::new()))),
+            read_only: false,
+            quota: Some(Arc::new(Quota {
+                limit,
+                used: AtomicUsize::new(0),
+            })),
+        };
+        self.bind(namespace, path, mount)
+    }
+
+    /// Add the mount `mount` at `path` in the namespace `namespace`.
+    fn bind(&self, namespace: FileHandle, path: &str, mount: Node) -> Result<(), FsError> {
+        let namespace = self.handle_node(namespace)?;
+        let Node::Mount { ref target, .. } = mount else {
+            return Err(FsError::InvalidHandle);
+        };
+        if namespace.read_only || Arc::ptr_eq(&namespace.node, target) {
+            return Err(FsError::PermissionDenied);
+        }
+        let mut current = namespace.node;
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let Some((name, parents)) = parts.split_last() else {
+            return Err(FsError::PermissionDenied);
+        };
+
+        for part in parents {
+            let next = {
+                let mut guard = current.write();
+                let Node::Directory(ref mut map) = *guard else {
+                    return Err(FsError::PermissionDenied); // Leads through a mount
+                };
+                map.entry((*part).to_string())
+                    .or_insert_with(|| Arc::new(RwLock::new(Node::Directory(BTreeMap::new()))))
+                    .clone()
+            };
+            current = next;
+        }
+
+        let mut guard = current.write();
+        let Node::Directory(ref mut map) = *guard else {
+            return Err(FsError::PermissionDenied);
+        };
+        if map.contains_key(*name) {
+            return Err(FsError::PermissionDenied); // Already exists
+        }
+        map.insert((*name).to_string(), Arc::new(RwLock::new(mount)));
+        Ok(())
+    }
+
+    /// Start watching a path for modifications.
+    ///
+    /// The path does not need to exist yet; creating it counts as a change.
+    pub fn watch(&self, path: &str) -> FsWatch {
+        let path = normalize(path);
+        let seen = self.versions.lock().get(&path).copied().unwrap_or(0);
+        FsWatch { path, seen }
+    }
+
+    /// Check whether a watched path changed since the last call.
+    pub fn changed(&self, watch: &mut FsWatch) -> bool {
+        let current = self.versions.lock().get(&watch.path).copied().unwrap_or(0);
+        if current != watch.seen {
+            watch.seen = current;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Record a modification of `path` for any watchers.
+    fn notify(&self, path: &str) {
+        *self.versions.lock().entry(normalize(path)).or_insert(0) += 1;
+    }
+
+    /// Record a modification of `node` by its path from the root; watches
+    /// are keyed by that path, so nothing is recorded for a node the root
+    /// does not reach. No node lock may be held.
+    fn notify_node(&self, node: &Arc<RwLock<Node>>) {
+        if let Some(path) = find_path(&self.root, node, "") {
+            self.notify(&path);
+        }
+    }
+
+    /// Record a modification of the entry `name` of the directory `parent`,
+    /// as `notify_node` does.
+    fn notify_child(&self, parent: &Arc<RwLock<Node>>, name: &str) {
+        let parent_path = if Arc::ptr_eq(parent, &self.root) {
+            String::new()
+        } else {
+            match find_path(&self.root, parent, "") {
+                Some(path) => path,
+                None => return,
+            }
+        };
+        self.notify(&alloc::format!("{}/{}", parent_path, name));
+    }
+
+    /// Add a file at a specific path (mkdir -p logic included).
+    pub fn add_file(&self, path: &str, content: &[u8]) {
+        self.add_node(path, Node::File(content.to_vec()));
+    }
+
+    /// Add a device node at a specific path (mkdir -p logic included).
+    pub fn add_device(&self, path: &str, device: Device) {
+        self.add_node(path, Node::Device(device));
+    }
+
+    fn add_node(&self, path: &str, node: Node) {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        if parts.is_empty() {
+            return;
+        }
+
+        let mut current = self.root.clone();
+
+        // Traverse/Create directories
+        for part in &parts[..parts.len() - 1] {
+            let next_node = {
+                let mut guard = current.write();
+                if let Node::Directory(ref mut map) = *guard {
+                    map.entry((*part).to_string())
+                        .or_insert_with(|| Arc::new(RwLock::new(Node::Directory(BTreeMap::new()))))
+                        .clone()
+                } else {
+                    return; // Error: Path component is not a directory
+                }
+            };
+            current = next_node;
+        }
+
+        // Create File
+        // Safety: parts is non-empty (checked above), so last() always succeeds
+        let Some(filename) = parts.last() else {
+            return; // Unreachable due to early return above, but satisfies no-unwrap rule
+        };
+        let mut guard = current.write();
+        if let Node::Directory(ref mut map) = *guard {
+            map.insert((*filename).to_string(), Arc::new(RwLock::new(node)));
+        }
+        self.notify(path);
+    }
+
+    /// Create an empty file at `path` below the directory `base` and open
+    /// it. Fails if something is at `path` already.
+    pub fn create_at(&self, base: FileHandle, path: &str) -> Result<FileHandle, FsError> {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let Some((name, parents)) = parts.split_last() else {
+            return Err(FsError::PermissionDenied);
+        };
+        let parent = lookup(self.handle_node(base)?, parents)?;
+        if parent.read_only {
+            return Err(FsError::PermissionDenied);
+        }
+        let file = Arc::new(RwLock::new(Node::File(Vec::new())));
+        {
+            let mut guard = parent.node.write();
+            let Node::Directory(ref mut map) = *guard else {
+                return Err(FsError::InvalidHandle); // Parent is not dir
+            };
+            if map.contains_key(*name) {
+                return Err(FsError::PermissionDenied); // Already exists
+            }
+            map.insert((*name).to_string(), file.clone());
+        }
+        self.notify_child(&parent.node, name);
+        Ok(self.insert_handle(Open {
+            node: file,
+            ..parent
+        }))
+    }
+
+    /// Paths of all files and device nodes, in sorted order.
+    pub fn files(&self) -> Vec<String> {
+        let mut files = Vec::new();
+        collect_files(&self.root, "", &mut files);
+        files
+    }
+}
+
+/// Follow `parts` down from `current`, entering mounts on the way; a
+/// read-only mount makes what is reached read-only, and in a tmpfs it
+/// takes the tmpfs's quota.
+fn lookup(mut current: Open, parts: &[&str]) -> Result<Open, FsError> {
+    for part in parts {
+        let next = {
+            let guard = current.node.read();
+            match *guard {
+                Node::Directory(ref map) => map.get(*part).cloned(),
+                _ => return Err(FsError::NotFound),
+            }
+        };
+        let Some(node) = next else {
+            return Err(FsError::NotFound);
+        };
+        let mount = match *node.read() {
+            Node::Mount {
+                ref target,
+                read_only,
+                ref quota,
+            } => Some((target.clone(), read_only, quota.clone())),
+            _ => None,
+        };
+        match mount {
+            Some((target, read_only, quota)) => {
+                current.node = target;
+                current.read_only |= read_only;
+                current.quota = quota.or(current.quota);
+            }
+            None => current.node = node,
+        }
+    }
+    Ok(current)
+}
+
+/// The entry `name` of the directory `dir`, without entering it if it is
+/// a mount.
+fn child(dir: &Arc<RwLock<Node>>, name: &str) -> Result<Arc<RwLock<Node>>, FsError> {
+    match *dir.read() {
+        Node::Directory(ref map) => map.get(name).cloned().ok_or(FsError::NotFound),
+        _ => Err(FsError::InvalidHandle), // Not a directory
+    }
+}
+
+/// Remove the entry `name` from the directory `dir`, if it is still
+/// `node`.
+fn take_child(
+    dir: &Arc<RwLock<Node>>,
+    name: &str,
+    node: &Arc<RwLock<Node>>,
+) -> Result<(), FsError> {
+    let mut guard = dir.write();
+    let Node::Directory(ref mut map) = *guard else {
+        return Err(FsError::InvalidHandle);
+    };
+    if !map.get(name).is_some_and(|entry| Arc::ptr_eq(entry, node)) {
+        return Err(FsError::NotFound); // Replaced meanwhile
+    }
+    map.remove(name);
+    Ok(())
+}
+
+/// Size of `node` if it is a file, else 0.
+fn file_len(node: &Arc<RwLock<Node>>) -> usize {
+    match *node.read() {
+        Node::File(ref content) => content.len(),
+        _ => 0,
+    }
+}
+
+/// Append the paths of the files below `node` (at `prefix`) to `out`.
+fn collect_files(node: &Arc<RwLock<Node>>, prefix: &str, out: &mut Vec<String>) {
+    if let Node::Directory(ref map) = *node.read() {
+        for (name, child) in map {
+            let path = if prefix.is_empty() {
+                name.clone()
+            } else {
+                alloc::format!("{}/{}", prefix, name)
+            };
+            if matches!(*child.read(), Node::Directory(_)) {
+                collect_files(child, &path, out);
+            } else {
+                out.push(path);
+            }
+        }
+    }
+}
+
+/// Path of `target` below `node` (at `prefix`), searching depth first.
+fn find_path(node: &Arc<RwLock<Node>>, target: &Arc<RwLock<Node>>, prefix: &str) -> Option<String> {
+    if let Node::Directory(ref map) = *node.read() {
+        for (name, child) in map {
+            let path = alloc::format!("{}/{}", prefix, name);
+            if Arc::ptr_eq(child, target) {
+                return Some(path);
+            }
+            if let Some(path) = find_path(child, target, &path) {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+/// Normalize a path to its `/`-separated components without empty segments.
+fn normalize(path: &str) -> String {
+    let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    parts.join("/")
+}
+
+impl Default for RamFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSystem for RamFs {
+    fn open(&self, path: &str) -> Result<FileHandle, FsError> {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let open = lookup(Open::new(self.root.clone()), &parts)?;
+        Ok(self.insert_handle(open))
+    }
+
+    fn open_at(&self, base: FileHandle, path: &str) -> Result<FileHandle, FsError> {
+        // `handle_node` releases `open_handles` before the nodes are locked
+        let base = self.handle_node(base)?;
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let open = lookup(base, &parts)?;
+        Ok(self.insert_handle(open))
+    }
+
+    fn mkdir(&self, path: &str) -> Result<(), FsError> {
+        self.mkdir_at(FileHandle(0), path) // FileHandle(0) doesn't exist but we can handle root as base
+    }
+
+    fn mkdir_at(&self, base: FileHandle, path: &str) -> Result<(), FsError> {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        if parts.is_empty() {
+            return Err(FsError::PermissionDenied);
+        }
+
+        let parent_parts = &parts[..parts.len() - 1];
+        // Safety: parts is non-empty (checked above), so last() always succeeds
+        let Some(dirname) = parts.last() else {
+            return Err(FsError::PermissionDenied); // Unreachable, but satisfies no-unwrap rule
+        };
+
+        // Resolve parent
+        let current = lookup(self.base_node(base)?, parent_parts)?;
+        if current.read_only {
+            return Err(FsError::PermissionDenied);
+        }
+
+        // Create dir in parent
+        {
+            let mut guard = current.node.write();
+            let Node::Directory(ref mut map) = *guard else {
+                return Err(FsError::InvalidHandle); // Parent is not dir
+            };
+            if map.contains_key(*dirname) {
+                return Err(FsError::PermissionDenied); // Already exists
+            }
+            map.insert(
+                dirname.to_string(),
+                Arc::new(RwLock::new(Node::Directory(BTreeMap::new()))),
+            );
+        }
+        self.notify_child(&current.node, dirname);
+        Ok(())
+    }
+
+    fn unlink(&self, path: &str) -> Result<(), FsError> {
+        self.unlink_at(FileHandle(0), path)
+    }
+
+    /// In a tmpfs, an unlinked file's bytes go back to the quota, once
+    /// no handle is open on it.
+    fn unlink_at(&self, base: FileHandle, path: &str) -> Result<(), FsError> {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let Some((name, parents)) = parts.split_last() else {
+            return Err(FsError::PermissionDenied);
+        };
+        let parent = lookup(self.base_node(base)?, parents)?;
+        if parent.read_only {
+            return Err(FsError::PermissionDenied);
+        }
+        let node = child(&parent.node, name)?;
+        let is_dir = match *node.read() {
+            Node::Directory(ref map) if !map.is_empty() => return Err(FsError::PermissionDenied),
+            Node::Mount { .. } => return Err(FsError::PermissionDenied),
+            Node::Directory(_) => true,
+            _ => false,
+        };
+
+        // Held until the file's bytes are accounted for, so that no write
+        // or close comes in between
+        let mut handles = self.open_handles.lock();
+        let open = handles.values().any(|open| Arc::ptr_eq(&open.node, &node));
+        if is_dir && open {
+            return Err(FsError::PermissionDenied);
+        }
+        take_child(&parent.node, name, &node)?;
+        if open {
+            for other in handles.values_mut() {
+                if Arc::ptr_eq(&other.node, &node) {
+                    other.unlinked = true;
+                }
+            }
+        } else if let Some(quota) = &parent.quota {
+            quota.release(file_len(&node));
+        }
+        drop(handles);
+
+        self.notify_child(&parent.node, name);
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), FsError> {
+        self.rename_at(FileHandle(0), from, to)
+    }
+
+    /// Both paths must be in the same tmpfs, or neither in one, and a
+    /// directory cannot be moved into itself.
+    fn rename_at(&self, base: FileHandle, from: &str, to: &str) -> Result<(), FsError> {
+        let from_parts: Vec<&str> = from.split('/').filter(|s| !s.is_empty()).collect();
+        let to_parts: Vec<&str> = to.split('/').filter(|s| !s.is_empty()).collect();
+        let (Some((from_name, from_parents)), Some((to_name, to_parents))) =
+            (from_parts.split_last(), to_parts.split_last())
+        else {
+            return Err(FsError::PermissionDenied);
+        };
+        let base = self.base_node(base)?;
+        let from_parent = lookup(base.clone(), from_parents)?;
+        let to_parent = lookup(base, to_parents)?;
+        if from_parent.read_only
+            || to_parent.read_only
+            || !same_quota(&from_parent.quota, &to_parent.quota)
+        {
+            return Err(FsError::PermissionDenied);
+        }
+        let node = child(&from_parent.node, from_name)?;
+        let is_dir = match *node.read() {
+            Node::Mount { .. } => return Err(FsError::PermissionDenied),
+            Node::Directory(_) => true,
+            _ => false,
+        };
+        if is_dir
+            && (Arc::ptr_eq(&node, &to_parent.node)
+                || find_path(&node, &to_parent.node, "").is_some())
+        {
+            return Err(FsError::PermissionDenied); // Into itself
+        }
+
+        if Arc::ptr_eq(&from_parent.node, &to_parent.node) {
+            let mut guard = from_parent.node.write();
+            let Node::Directory(ref mut map) = *guard else {
+                return Err(FsError::InvalidHandle);
+            };
+            if from_name == to_name {
+                return Ok(());
+            }
+            if map.contains_key(*to_name) {
+                return Err(FsError::PermissionDenied); // Already exists
+            }
+            map.remove(*from_name);
+            map.insert((*to_name).to_string(), node);
+        } else {
+            // One directory at a time, so the node is briefly in neither
+            match *to_parent.node.read() {
+                Node::Directory(ref map) if map.contains_key(*to_name) => {
+                    return Err(FsError::PermissionDenied); // Already exists
+                }
+                Node::Directory(_) => {}
+                _ => return Err(FsError::InvalidHandle),
+            }
+            take_child(&from_parent.node, from_name, &node)?;
+            let inserted = match *to_parent.node.write() {
+                Node::Directory(ref mut map) if !map.contains_key(*to_name) => {
+                    map.insert((*to_name).to_string(), node.clone());
+                    true
+                }
+                _ => false,
+            };
+            if !inserted {
+                if let Node::Directory(ref mut map) = *from_parent.node.write() {
+                    map.insert((*from_name).to_string(), node);
+                }
+                return Err(FsError::PermissionDenied);
+            }
+        }
+
+        self.notify_child(&from_parent.node, from_name);
+        self.notify_child(&to_parent.node, to_name);
+        Ok(())
+    }
+
+    fn read(&self, handle: FileHandle, buffer: &mut [u8], offset: usize) -> Result<usize, FsError> {
+        let handles = self.open_handles.lock();
+        if let Some(open) = handles.get(&handle) {
+            let guard = open.node.read();
+            if let Node::File(ref content) = *guard {
+                if offset >= content.len() {
+                    return Ok(0);
+                }
+                let end = core::cmp::min(offset + buffer.len(), content.len());
+                let bytes_read = end - offset;
+                buffer[..bytes_read].copy_from_slice(&content[offset..end]);
+                Ok(bytes_read)
+            } else {
+                Err(FsError::InvalidHandle) // Is a directory or device
+            }
+        } else {
+            Err(FsError::InvalidHandle)
+        }
+    }
+
+    /// Watches on the file's path from the root are notified. In a tmpfs,
+    /// growing the file takes from its quota (`NoSpace` once that is used
+    /// up).
+    fn write(&self, handle: FileHandle, data: &[u8], offset: usize) -> Result<usize, FsError> {
+        let node = {
+            let handles = self.open_handles.lock();
+            let open = handles.get(&handle).ok_or(FsError::InvalidHandle)?;
+            if open.read_only {
+                return Err(FsError::PermissionDenied);
+            }
+            let mut guard = open.node.write();
+            let Node::File(ref mut content) = *guard else {
+                return Err(FsError::InvalidHandle); // Is a directory or device
+            };
+            let end = offset
+                .checked_add(data.len())
+                .ok_or(FsError::PermissionDenied)?;
+            if let Some(quota) = &open.quota {
+                quota.reserve(end.saturating_sub(content.len()))?;
+            }
+            if end > content.len() {
+                content.resize(end, 0);
+            }
+            content[offset..end].copy_from_slice(data);
+            open.node.clone()
+        };
+        self.notify_node(&node);
+        Ok(data.len())
+    }
+
+    fn size(&self, handle: FileHandle) -> Result<usize, FsError> {
+        let handles = self.open_handles.lock();
+        if let Some(open) = handles.get(&handle) {
+            let guard = open.node.read();
+            match *guard {
+                Node::File(ref content) => Ok(content.len()),
+                // Dirs have size 0 for now; handles never refer to a mount itself
+                Node::Directory(_) | Node::Device(_) | Node::Mount { .. } => Ok(0),
+            }
+        } else {
+            Err(FsError::InvalidHandle)
+        }
+    }
+
+    /// A mount is listed as the directory it binds.
+    fn readdir(&self, handle: FileHandle) -> Result<Vec<DirEntry>, FsError> {
+        let dir = self.handle_node(handle)?;
+        // Copied out so that no two node locks are held together
+        let children: Vec<(String, Arc<RwLock<Node>>)> = match *dir.node.read() {
+            Node::Directory(ref map) => map
+                .iter()
+                .map(|(name, child)| (name.clone(), child.clone()))
+                .collect(),
+            _ => return Err(FsError::InvalidHandle), // Not a directory
+        };
+        Ok(children
+            .into_iter()
+            .map(|(name, child)| {
+                let (kind, size) = match *child.read() {
+                    Node::File(ref content) => (EntryKind::File, content.len()),
+                    Node::Directory(_) | Node::Mount { .. } => (EntryKind::Directory, 0),
+                    Node::Device(_) => (EntryKind::Device, 0),
+                };
+                DirEntry { name, kind, size }
+            })
+            .collect())
+    }
+
+    fn is_dir(&self, handle: FileHandle) -> bool {
+        let handles = self.open_handles.lock();
+        if let Some(open) = handles.get(&handle) {
+            let guard = open.node.read();
+            matches!(*guard, Node::Directory(_))
+        } else {
+            false
+        }
+    }
+
+    fn device(&self, handle: FileHandle) -> Option<Device> {
+        let handles = self.open_handles.lock();
+        let guard = handles.get(&handle)?.node.read();
+        match *guard {
+            Node::Device(device) => Some(device),
+            _ => None,
+        }
+    }
+
+    /// Closing the last handle on an unlinked file in a tmpfs gives its
+    /// bytes back to the quota.
+    fn close(&self, handle: FileHandle) {
+        let mut handles = self.open_handles.lock();
+        let Some(open) = handles.remove(&handle) else {
+            return;
+        };
+        if !open.unlinked
+            || handles
+                .values()
+                .any(|other| Arc::ptr_eq(&other.node, &open.node))
+        {
+            return;
+        }
+        if let Some(quota) = &open.quota {
+            quota.release(file_len(&open.node));
+        }
+    }
+}
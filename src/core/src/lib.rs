@@ -0,0 +1,15 @@
+//! Kernel code that is plain Rust on `alloc`.
+//!
+//! The RAM filesystem (`fs`), the capabilities of a process and what host
+//! functions do with them, and the contract between the scheduler and a
+//! WASM runtime (`wasm`) need no hardware, so they live here where the
+//! host builds them too: the kernel re-exports them as its own, and the
+//! simulator (`src/sim`) runs processes against the same code.
+
+#![no_std]
+#![warn(missing_docs)]
+
+extern crate alloc;
+
+pub mod fs;
+pub mod wasm;
@@ -0,0 +1,376 @@
+//! What host functions do once their arguments are out of linear memory.
+//!
+//! The kernel's host functions (`wasm::host`) and the simulator's both
+//! call these: each checks the capability it is passed in the process's
+//! `ProcessCapabilities` and does the work on `ROOT_FS`, returning a value or
+//! one of the error codes of `sovelma_common::host::error`. Copying to and
+//! from linear memory, fuel, rate limits and blocking are left to the
+//! caller, as are sockets, which each side keeps on its own network stack.
+//!
+//! Capabilities opened or created below a directory are derived from it
+//! (`ProcessCapabilities::derive`), with no rights it lacks.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use sovelma_common::capability::{
+    Capability, CapabilityDescriptor, CapabilityList, CapabilityRights, CapabilityType,
+};
+use sovelma_common::codec;
+use sovelma_common::fs::{self, DirEntryRecord};
+use sovelma_common::host::{error, FS_WRITE_MAX};
+
+use super::capabilities::ProcessCapabilities;
+use super::handles::Handle;
+use crate::fs::{Device, FileHandle, FileSystem, FsError, ROOT_FS};
+
+/// The capabilities `caps` holds, encoded in capability list format
+/// `version` if that takes at most `len` bytes, with how many there are
+/// (`sp_get_capabilities` is version 1, `sp_describe_capabilities` any).
+pub fn capability_list(
+    caps: &ProcessCapabilities,
+    version: u32,
+    len: usize,
+) -> Result<(Vec<u8>, usize), i32> {
+    let held = caps.held();
+    // Only descriptors have a place for paths
+    let paths: Vec<Option<String>> = held
+        .iter()
+        .map(|(_, cap)| match cap.object {
+            CapabilityType::Directory(handle) | CapabilityType::File(handle) if version >= 2 => {
+                ROOT_FS.path(FileHandle(handle as u32))
+            }
+            _ => None,
+        })
+        .collect();
+    let descriptors: Vec<CapabilityDescriptor> = held
+        .iter()
+        .zip(&paths)
+        .map(|((handle, cap), path)| {
+            CapabilityDescriptor::new(handle.as_raw(), cap, path.as_deref())
+        })
+        .collect();
+    let list = CapabilityList::new(version, &descriptors).ok_or(error::INVALID_ARGUMENT as i32)?;
+    let mut bytes = alloc::vec![0u8; codec::encoded_len(&list)];
+    if len < bytes.len() {
+        return Err(error::BUFFER_TOO_SMALL as i32);
+    }
+    // Sized by `encoded_len`, so it fits
+    let _ = codec::to_slice(&list, &mut bytes);
+    Ok((bytes, list.len()))
+}
+
+/// The directory `dir_cap` grants `rights` on, with all its rights.
+pub fn directory(
+    caps: &ProcessCapabilities,
+    dir_cap: i64,
+    rights: CapabilityRights,
+) -> Result<(FileHandle, CapabilityRights), i64> {
+    let cap = caps.capability(dir_cap).ok_or(error::CAP_NOT_FOUND)?;
+    let CapabilityType::Directory(handle) = cap.object else {
+        return Err(error::NOT_A_DIRECTORY);
+    };
+    if !cap.rights.contains(rights) {
+        return Err(error::PERMISSION_DENIED);
+    }
+    Ok((FileHandle(handle as u32), cap.rights))
+}
+
+/// The file `file_cap` grants `rights` on.
+pub fn file(
+    caps: &ProcessCapabilities,
+    file_cap: i64,
+    rights: CapabilityRights,
+) -> Result<FileHandle, i32> {
+    let cap = caps
+        .capability(file_cap)
+        .ok_or(error::CAP_NOT_FOUND as i32)?;
+    let CapabilityType::File(handle) = cap.object else {
+        return Err(error::NOT_A_FILE as i32);
+    };
+    if !cap.rights.contains(rights) {
+        return Err(error::PERMISSION_DENIED as i32);
+    }
+    Ok(FileHandle(handle as u32))
+}
+
+/// `rights` for something opened as `handle`: without WRITE if it was
+/// reached through a read-only mount.
+pub fn mount_rights(handle: FileHandle, rights: CapabilityRights) -> CapabilityRights {
+    if ROOT_FS.is_read_only(handle) {
+        rights.difference(CapabilityRights::WRITE)
+    } else {
+        rights
+    }
+}
+
+/// `sp_fs_open`: open `path` below the directory `dir_cap` grants READ
+/// on. A device node yields a capability for the device itself.
+///
+/// Returns the handle of the new capability, whose rights are the
+/// directory's that apply to what was opened, or an error code.
+pub fn fs_open(caps: &mut ProcessCapabilities, dir_cap: i64, path: &str) -> i64 {
+    let (dir, parent_rights) = match directory(caps, dir_cap, CapabilityRights::READ) {
+        Ok(dir) => dir,
+        Err(code) => return code,
+    };
+    let Ok(opened) = ROOT_FS.open_at(dir, path) else {
+        return error::FS_ERROR;
+    };
+
+    if let Some(Device::Serial { port, .. }) = ROOT_FS.device(opened) {
+        let rights = parent_rights & (CapabilityRights::READ | CapabilityRights::WRITE);
+        let rights = mount_rights(opened, rights);
+        ROOT_FS.close(opened);
+        let cap = Capability::new(CapabilityType::Serial { port }, rights);
+        return caps.derive(dir_cap, cap).as_raw();
+    }
+
+    // Never more than the directory holds, and only what applies
+    let (object, applicable) = if ROOT_FS.is_dir(opened) {
+        (
+            CapabilityType::Directory(u64::from(opened.0)),
+            CapabilityRights::READ
+                | CapabilityRights::WRITE
+                | CapabilityRights::EXECUTE
+                | CapabilityRights::GRANT,
+        )
+    } else {
+        (
+            CapabilityType::File(u64::from(opened.0)),
+            CapabilityRights::READ | CapabilityRights::WRITE,
+        )
+    };
+    let cap = Capability::new(object, mount_rights(opened, parent_rights & applicable));
+    caps.derive(dir_cap, cap).as_raw()
+}
+
+/// `sp_fs_opendir_restricted`: open the directory at `path` below the
+/// one `dir_cap` grants READ on, as a capability with only the rights
+/// both `dir_cap` and `rights` hold.
+///
+/// Returns the process's handle for it, or an error code.
+pub fn fs_opendir_restricted(
+    caps: &mut ProcessCapabilities,
+    dir_cap: i64,
+    path: &str,
+    rights: u32,
+) -> Result<Handle, i64> {
+    let requested = CapabilityRights::from_bits(rights).ok_or(error::INVALID_ARGUMENT)?;
+    let (dir, parent_rights) = directory(caps, dir_cap, CapabilityRights::READ)?;
+    let opened = ROOT_FS.open_at(dir, path).map_err(|_| error::FS_ERROR)?;
+    if !ROOT_FS.is_dir(opened) {
+        ROOT_FS.close(opened);
+        return Err(error::NOT_A_DIRECTORY);
+    }
+    let cap = Capability::new(
+        CapabilityType::Directory(u64::from(opened.0)),
+        mount_rights(opened, parent_rights & requested),
+    );
+    Ok(caps.derive(dir_cap, cap))
+}
+
+/// `sp_fs_read`: read from the file `file_cap` grants READ on at
+/// `offset` into `buf`.
+///
+/// Returns bytes read, or an error code.
+pub fn fs_read(caps: &ProcessCapabilities, file_cap: i64, buf: &mut [u8], offset: usize) -> i32 {
+    let handle = match file(caps, file_cap, CapabilityRights::READ) {
+        Ok(handle) => handle,
+        Err(code) => return code,
+    };
+    match ROOT_FS.read(handle, buf, offset) {
+        Ok(count) => count as i32,
+        Err(_) => error::FS_ERROR as i32,
+    }
+}
+
+/// `sp_fs_write`: write `data`, at most `FS_WRITE_MAX` bytes of it, at
+/// `offset` to the file `file_cap` grants WRITE on.
+///
+/// Bytes that grow the file are taken from `quota`, and from the tmpfs's
+/// if the file is in one. Returns bytes written, or an error code.
+pub fn fs_write(
+    caps: &ProcessCapabilities,
+    quota: &mut u64,
+    file_cap: i64,
+    data: &[u8],
+    offset: usize,
+) -> i32 {
+    let handle = match file(caps, file_cap, CapabilityRights::WRITE) {
+        Ok(handle) => handle,
+        Err(code) => return code,
+    };
+    let data = &data[..data.len().min(FS_WRITE_MAX)];
+    let Ok(size) = ROOT_FS.size(handle) else {
+        return error::FS_ERROR as i32;
+    };
+    let growth = (offset + data.len()).saturating_sub(size) as u64;
+    if growth > *quota {
+        return error::QUOTA_EXCEEDED as i32;
+    }
+    match ROOT_FS.write(handle, data, offset) {
+        Ok(written) => {
+            *quota -= growth;
+            written as i32
+        }
+        Err(FsError::NoSpace) => error::QUOTA_EXCEEDED as i32,
+        Err(_) => error::FS_ERROR as i32,
+    }
+}
+
+/// `sp_fs_size`: size of the file or directory `file_cap` names.
+pub fn fs_size(caps: &ProcessCapabilities, file_cap: i64) -> i32 {
+    let Some(cap) = caps.capability(file_cap) else {
+        return error::CAP_NOT_FOUND as i32;
+    };
+    let (CapabilityType::File(handle) | CapabilityType::Directory(handle)) = cap.object else {
+        return error::NOT_A_FILE as i32;
+    };
+    match ROOT_FS.size(FileHandle(handle as u32)) {
+        Ok(size) => size as i32,
+        Err(_) => error::FS_ERROR as i32,
+    }
+}
+
+/// `sp_fs_close`: give up `file_cap`, closing the file or directory.
+pub fn fs_close(caps: &mut ProcessCapabilities, file_cap: i64) {
+    if let Some(cap) = caps.close(file_cap) {
+        if let CapabilityType::File(handle) | CapabilityType::Directory(handle) = cap.object {
+            ROOT_FS.close(FileHandle(handle as u32));
+        }
+    }
+}
+
+/// `sp_fs_mkdir`: create `path` below the directory `dir_cap` grants
+/// WRITE on. Returns 0, or an error code.
+pub fn fs_mkdir(caps: &ProcessCapabilities, dir_cap: i64, path: &str) -> i32 {
+    let dir = match directory(caps, dir_cap, CapabilityRights::WRITE) {
+        Ok((dir, _)) => dir,
+        Err(code) => return code as i32,
+    };
+    match ROOT_FS.mkdir_at(dir, path) {
+        Ok(()) => 0,
+        Err(_) => error::FS_ERROR as i32,
+    }
+}
+
+/// `sp_fs_create`: create an empty file at `path` below the directory
+/// `dir_cap` grants WRITE on.
+///
+/// Returns the handle of a capability for it with the directory's READ
+/// and WRITE, or an error code.
+pub fn fs_create(caps: &mut ProcessCapabilities, dir_cap: i64, path: &str) -> i64 {
+    let (dir, parent_rights) = match directory(caps, dir_cap, CapabilityRights::WRITE) {
+        Ok(dir) => dir,
+        Err(code) => return code,
+    };
+    let Ok(created) = ROOT_FS.create_at(dir, path) else {
+        return error::FS_ERROR;
+    };
+    let rights = parent_rights & (CapabilityRights::READ | CapabilityRights::WRITE);
+    let cap = Capability::new(CapabilityType::File(u64::from(created.0)), rights);
+    caps.derive(dir_cap, cap).as_raw()
+}
+
+/// `sp_fs_unlink`: remove the file, device node or empty directory at
+/// `path` below the directory `dir_cap` grants WRITE on. Capabilities
+/// open on a file keep working until closed.
+///
+/// Returns 0, or an error code.
+pub fn fs_unlink(caps: &ProcessCapabilities, dir_cap: i64, path: &str) -> i32 {
+    let dir = match directory(caps, dir_cap, CapabilityRights::WRITE) {
+        Ok((dir, _)) => dir,
+        Err(code) => return code as i32,
+    };
+    match ROOT_FS.unlink_at(dir, path) {
+        Ok(()) => 0,
+        Err(_) => error::FS_ERROR as i32,
+    }
+}
+
+/// `sp_fs_rename`: move `from` to `to`, which must not exist, both below
+/// the directory `dir_cap` grants WRITE on.
+///
+/// Returns 0, or an error code.
+pub fn fs_rename(caps: &ProcessCapabilities, dir_cap: i64, from: &str, to: &str) -> i32 {
+    let dir = match directory(caps, dir_cap, CapabilityRights::WRITE) {
+        Ok((dir, _)) => dir,
+        Err(code) => return code as i32,
+    };
+    match ROOT_FS.rename_at(dir, from, to) {
+        Ok(()) => 0,
+        Err(_) => error::FS_ERROR as i32,
+    }
+}
+
+/// `sp_fs_readdir`: the entries of the directory `dir_cap` grants READ
+/// on from the `start`th, as many as fit in `len` bytes, encoded as a
+/// sequence of `DirEntryRecord`s, with how many there are. Nothing is
+/// encoded once `start` is past the last entry.
+pub fn fs_readdir(
+    caps: &ProcessCapabilities,
+    dir_cap: i64,
+    start: usize,
+    len: usize,
+) -> Result<(Vec<u8>, usize), i32> {
+    let (dir, _) = directory(caps, dir_cap, CapabilityRights::READ).map_err(|code| code as i32)?;
+    let entries = ROOT_FS.readdir(dir).map_err(|_| error::FS_ERROR as i32)?;
+    let records: Vec<DirEntryRecord> = entries
+        .iter()
+        .skip(start)
+        .map(|entry| DirEntryRecord {
+            name: &entry.name,
+            kind: entry.kind,
+            size: entry.size as u64,
+        })
+        .collect();
+    if records.is_empty() {
+        return Ok((Vec::new(), 0));
+    }
+    let count = fs::fit(&records, len);
+    if count == 0 {
+        return Err(error::BUFFER_TOO_SMALL as i32);
+    }
+    let batch = codec::Seq(&records[..count]);
+    let mut bytes = alloc::vec![0u8; codec::encoded_len(&batch)];
+    // Sized by `encoded_len`, so it fits
+    let _ = codec::to_slice(&batch, &mut bytes);
+    Ok((bytes, count))
+}
+
+/// The Network capability `net_cap` if it has `rights`, for
+/// `sp_net_connect` (CONNECT) and `sp_net_listen`.
+pub fn network(
+    caps: &ProcessCapabilities,
+    net_cap: i64,
+    rights: CapabilityRights,
+) -> Result<Capability, i32> {
+    let cap = caps
+        .capability(net_cap)
+        .ok_or(error::CAP_NOT_FOUND as i32)?;
+    if !matches!(cap.object, CapabilityType::Network { .. }) {
+        return Err(error::INVALID_HANDLE as i32);
+    }
+    if !cap.rights.contains(rights) {
+        return Err(error::PERMISSION_DENIED as i32);
+    }
+    Ok(cap.clone())
+}
+
+/// Handle of the socket `sock_cap` grants `rights` on.
+pub fn socket(
+    caps: &ProcessCapabilities,
+    sock_cap: i64,
+    rights: CapabilityRights,
+) -> Result<u64, i32> {
+    let cap = caps
+        .capability(sock_cap)
+        .ok_or(error::CAP_NOT_FOUND as i32)?;
+    let CapabilityType::Socket(handle) = cap.object else {
+        return Err(error::INVALID_HANDLE as i32);
+    };
+    if !cap.rights.contains(rights) {
+        return Err(error::PERMISSION_DENIED as i32);
+    }
+    Ok(handle)
+}
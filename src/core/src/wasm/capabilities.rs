@@ -0,0 +1,155 @@
+//! The capabilities a process holds.
+//!
+//! `ProcessCapabilities` keeps a process's capabilities by `CapId` together
+//! with its handles for them (see `handles`), and the token buckets of the
+//! rate-limited ones. Every host function goes through it: a handle only
+//! resolves while the capability is held and its generation matches the
+//! `CapId`, so a revoked or stale capability is never found again, and a
+//! capability derived from a rate-limited parent draws on the parent's
+//! bucket.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use sovelma_common::capability::{CapId, Capability, CapabilityRights, CapabilityType};
+use sovelma_common::rate::TokenBucket;
+use spin::Mutex;
+
+use super::handles::{Handle, HandleTable};
+
+/// Capabilities of one process, with its handles for them.
+#[derive(Default)]
+pub struct ProcessCapabilities {
+    capabilities: BTreeMap<CapId, Capability>,
+    handles: HandleTable,
+    /// Token buckets of the rate-limited capabilities, shared by those
+    /// derived from the same one.
+    buckets: BTreeMap<CapId, Arc<Mutex<TokenBucket>>>,
+}
+
+impl ProcessCapabilities {
+    /// A table holding nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a capability and return the process's handle for it.
+    pub fn grant(&mut self, cap: Capability) -> Handle {
+        let handle = self.handles.insert(cap.id);
+        self.add_bucket(&cap);
+        self.capabilities.insert(cap.id, cap);
+        handle
+    }
+
+    /// Add a capability at `handle`, replacing what it named before, as
+    /// when restoring a snapshot.
+    pub fn grant_at(&mut self, handle: Handle, cap: Capability) {
+        if let Some(old) = self.handles.get(handle) {
+            self.capabilities.remove(&old);
+            self.buckets.remove(&old);
+        }
+        self.handles.insert_at(handle, cap.id);
+        self.add_bucket(&cap);
+        self.capabilities.insert(cap.id, cap);
+    }
+
+    /// Add `cap`, derived from the capability `parent` names, and return
+    /// the process's handle for it. It is under the parent's rate limit,
+    /// drawing on the same bucket.
+    pub fn derive(&mut self, parent: i64, cap: Capability) -> Handle {
+        let parent = Handle::from_raw(parent).and_then(|handle| self.handles.get(handle));
+        let Some(bucket) = parent.and_then(|id| self.buckets.get(&id)).cloned() else {
+            return self.grant(cap);
+        };
+        let cap = Capability {
+            rate_limit: Some(bucket.lock().limit()),
+            ..cap
+        };
+        let handle = self.handles.insert(cap.id);
+        self.buckets.insert(cap.id, bucket);
+        self.capabilities.insert(cap.id, cap);
+        handle
+    }
+
+    /// Give a rate-limited `cap` a bucket of its own.
+    fn add_bucket(&mut self, cap: &Capability) {
+        if let Some(limit) = cap.rate_limit {
+            let bucket = Arc::new(Mutex::new(TokenBucket::new(limit)));
+            self.buckets.insert(cap.id, bucket);
+        }
+    }
+
+    /// Take a token for a call through `handle` at `now`, if the
+    /// capability is rate-limited. Returns when the caller may go on if
+    /// the bucket was empty.
+    pub fn take_token(&self, handle: i64, now: u64) -> Option<u64> {
+        let id = self.handles.get(Handle::from_raw(handle)?)?;
+        self.buckets.get(&id)?.lock().take(now)
+    }
+
+    /// The capability a host function was passed as `handle`, if the
+    /// process holds it.
+    pub fn capability(&self, handle: i64) -> Option<&Capability> {
+        let id = self.handles.get(Handle::from_raw(handle)?)?;
+        self.get(id)
+    }
+
+    /// The capability `id`, if the process holds it and its generation
+    /// matches; a revoked capability is never found.
+    pub fn get(&self, id: CapId) -> Option<&Capability> {
+        let cap = self.capabilities.get(&id)?;
+        (cap.generation as u32 == id.generation()).then_some(cap)
+    }
+
+    /// Capabilities the process holds with their handles, lowest first.
+    pub fn held(&self) -> Vec<(Handle, Capability)> {
+        self.handles
+            .iter()
+            .filter_map(|(handle, id)| Some((handle, self.get(id)?.clone())))
+            .collect()
+    }
+
+    /// Give up the capability named by `handle`, returning it.
+    pub fn close(&mut self, handle: i64) -> Option<Capability> {
+        let id = self.handles.remove(Handle::from_raw(handle)?)?;
+        self.buckets.remove(&id);
+        self.capabilities.remove(&id)
+    }
+
+    /// Revoke a capability by ID.
+    pub fn revoke(&mut self, id: CapId) {
+        self.capabilities.remove(&id);
+        self.buckets.remove(&id);
+        if let Some(handle) = self.handles.handle_of(id) {
+            self.handles.remove(handle);
+        }
+    }
+
+    /// Remove every capability, returning them; all handles are freed.
+    pub fn take_all(&mut self) -> Vec<Capability> {
+        self.handles = HandleTable::new();
+        self.buckets = BTreeMap::new();
+        core::mem::take(&mut self.capabilities)
+            .into_values()
+            .collect()
+    }
+
+    /// Check whether the process holds a Timer capability with `rights`.
+    ///
+    /// READ allows reading the clock; CALL allows sleeping and timers.
+    pub fn has_timer_rights(&self, rights: CapabilityRights) -> bool {
+        self.capabilities
+            .values()
+            .any(|cap| cap.object == CapabilityType::Timer && cap.rights.contains(rights))
+    }
+
+    /// Number of capabilities held.
+    pub fn len(&self) -> usize {
+        self.capabilities.len()
+    }
+
+    /// Check whether no capabilities are held.
+    pub fn is_empty(&self) -> bool {
+        self.capabilities.is_empty()
+    }
+}
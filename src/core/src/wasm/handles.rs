@@ -0,0 +1,120 @@
+//! Per-process capability handles.
+//!
+//! WASM code never sees kernel `CapId`s, which are allocated globally and
+//! would tell a process how many capabilities others were granted. It names
+//! its capabilities by handles instead: small integers private to the
+//! process, allocated lowest-free-first like file descriptors. Host
+//! functions translate a handle to its `CapId` on the way in and hand out
+//! handles on the way out.
+//!
+//! Handle 0 is never allocated, so a zeroed variable never names a
+//! capability; the initial capabilities are handles 1, 2, ... in the order
+//! they were granted.
+
+use alloc::vec::Vec;
+use sovelma_common::capability::CapId;
+
+/// A process's name for one of its capabilities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Handle(u32);
+
+impl Handle {
+    /// Largest handle; handles fit in a WASM `i32`.
+    pub const MAX: u32 = i32::MAX as u32;
+
+    /// Handle `value`, if it is one that may be allocated.
+    pub fn new(value: u32) -> Option<Self> {
+        (1..=Self::MAX).contains(&value).then_some(Self(value))
+    }
+
+    /// The handle passed as `raw` to a host function, if it is one.
+    pub fn from_raw(raw: i64) -> Option<Self> {
+        u32::try_from(raw).ok().and_then(Self::new)
+    }
+
+    /// The handle as returned to WASM code.
+    pub fn as_raw(self) -> i64 {
+        i64::from(self.0)
+    }
+
+    /// The handle's number.
+    pub fn value(self) -> u32 {
+        self.0
+    }
+}
+
+/// Handles of one process, mapping to the capabilities they name.
+#[derive(Debug, Default)]
+pub struct HandleTable {
+    /// Slot `i` holds handle `i + 1`.
+    slots: Vec<Option<CapId>>,
+}
+
+impl HandleTable {
+    /// An empty table.
+    pub fn new() -> Self {
+        Self { slots: Vec::new() }
+    }
+
+    /// Give `id` the lowest free handle.
+    pub fn insert(&mut self, id: CapId) -> Handle {
+        let index = match self.slots.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        };
+        self.slots[index] = Some(id);
+        Handle(index as u32 + 1)
+    }
+
+    /// Give `id` the handle `handle`, replacing what it named before.
+    pub fn insert_at(&mut self, handle: Handle, id: CapId) {
+        let index = handle.0 as usize - 1;
+        if self.slots.len() <= index {
+            self.slots.resize(index + 1, None);
+        }
+        self.slots[index] = Some(id);
+    }
+
+    /// The capability `handle` names.
+    pub fn get(&self, handle: Handle) -> Option<CapId> {
+        self.slots.get(handle.0 as usize - 1).copied().flatten()
+    }
+
+    /// The handle naming `id`, if any.
+    pub fn handle_of(&self, id: CapId) -> Option<Handle> {
+        self.slots
+            .iter()
+            .position(|slot| *slot == Some(id))
+            .map(|index| Handle(index as u32 + 1))
+    }
+
+    /// Free `handle`, returning the capability it named.
+    pub fn remove(&mut self, handle: Handle) -> Option<CapId> {
+        let id = self.slots.get_mut(handle.0 as usize - 1)?.take();
+        while self.slots.last() == Some(&None) {
+            self.slots.pop();
+        }
+        id
+    }
+
+    /// Handles in use, lowest first, with their capabilities.
+    pub fn iter(&self) -> impl Iterator<Item = (Handle, CapId)> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| Some((Handle(index as u32 + 1), (*slot)?)))
+    }
+
+    /// Number of handles in use.
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Check whether no handles are in use.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,189 @@
+//! Module manifests.
+//!
+//! A module can describe itself in a `sovelma.manifest` custom section:
+//! UTF-8 text with one `key = value` per line (`#` starts a comment).
+//!
+//! ```text
+//! name = hello
+//! version = 0.1.0
+//! entry = _start
+//! capabilities = timer, directory
+//! ```
+//!
+//! `name` and `version` are required; `entry` defaults to `_start`.
+//! `capabilities` lists the capability kinds (see
+//! `CapabilityType::KIND_NAMES`) the module needs to run; `wasm run`
+//! refuses to start it unless each of them is granted. Unknown keys are
+//! ignored so that newer manifests still load.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use sovelma_common::capability::{Capability, CapabilityType};
+use sovelma_common::error::ErrorKind;
+
+/// Name of the custom section holding the manifest.
+pub const MANIFEST_SECTION: &str = "sovelma.manifest";
+
+/// Entry point used when the manifest does not name one.
+pub const DEFAULT_ENTRY: &str = "_start";
+
+/// WASM binary magic and version 1.
+const WASM_HEADER: [u8; 8] = *b"\0asm\x01\0\0\0";
+
+/// Section ID of custom sections.
+const CUSTOM_SECTION_ID: u8 = 0;
+
+/// Errors from reading a manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestError {
+    /// The file is not a WASM module.
+    NotWasm,
+    /// A section runs past the end of the module.
+    Truncated,
+    /// The manifest is not valid UTF-8.
+    InvalidUtf8,
+    /// A line is not of the form `key = value`.
+    Syntax(usize),
+    /// A required key is missing.
+    MissingField(&'static str),
+    /// A capability kind this kernel does not know.
+    UnknownCapability(String),
+}
+
+impl ErrorKind for ManifestError {}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::NotWasm => write!(f, "not a WASM module"),
+            ManifestError::Truncated => write!(f, "module truncated"),
+            ManifestError::InvalidUtf8 => write!(f, "invalid UTF-8 in manifest"),
+            ManifestError::Syntax(line) => {
+                write!(f, "manifest line {}: expected key = value", line)
+            }
+            ManifestError::MissingField(key) => write!(f, "manifest has no {}", key),
+            ManifestError::UnknownCapability(kind) => {
+                write!(f, "unknown capability kind {}", kind)
+            }
+        }
+    }
+}
+
+/// Metadata a module declares about itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// Application name.
+    pub name: String,
+    /// Application version.
+    pub version: String,
+    /// Export to run.
+    pub entry: String,
+    /// Capability kinds the module requires.
+    pub capabilities: Vec<String>,
+}
+
+impl Manifest {
+    /// Parse manifest text.
+    pub fn parse(text: &[u8]) -> Result<Self, ManifestError> {
+        let text = core::str::from_utf8(text).map_err(|_| ManifestError::InvalidUtf8)?;
+        let mut name = None;
+        let mut version = None;
+        let mut entry = None;
+        let mut capabilities = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(ManifestError::Syntax(index + 1))?;
+            let value = value.trim();
+            match key.trim() {
+                "name" => name = Some(value.to_string()),
+                "version" => version = Some(value.to_string()),
+                "entry" => entry = Some(value.to_string()),
+                "capabilities" => {
+                    for kind in value.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+                        if !CapabilityType::KIND_NAMES.contains(&kind) {
+                            return Err(ManifestError::UnknownCapability(kind.to_string()));
+                        }
+                        capabilities.push(kind.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            name: name.ok_or(ManifestError::MissingField("name"))?,
+            version: version.ok_or(ManifestError::MissingField("version"))?,
+            entry: entry.unwrap_or_else(|| DEFAULT_ENTRY.to_string()),
+            capabilities,
+        })
+    }
+
+    /// Read the manifest of a module, if it has one.
+    pub fn from_module(wasm: &[u8]) -> Result<Option<Self>, ManifestError> {
+        custom_section(wasm, MANIFEST_SECTION)?
+            .map(Self::parse)
+            .transpose()
+    }
+
+    /// Required capability kinds not covered by `granted`.
+    pub fn missing_capabilities(&self, granted: &[Capability]) -> Vec<&str> {
+        self.capabilities
+            .iter()
+            .map(String::as_str)
+            .filter(|kind| !granted.iter().any(|cap| cap.object.kind_name() == *kind))
+            .collect()
+    }
+}
+
+/// Find the payload of the first custom section called `name`.
+pub fn custom_section<'a>(wasm: &'a [u8], name: &str) -> Result<Option<&'a [u8]>, ManifestError> {
+    if wasm.get(..WASM_HEADER.len()) != Some(&WASM_HEADER[..]) {
+        return Err(ManifestError::NotWasm);
+    }
+    let mut pos = WASM_HEADER.len();
+    while pos < wasm.len() {
+        let id = wasm[pos];
+        pos += 1;
+        let size = read_leb_u32(wasm, &mut pos)? as usize;
+        let end = pos.checked_add(size).ok_or(ManifestError::Truncated)?;
+        let section = wasm.get(pos..end).ok_or(ManifestError::Truncated)?;
+        pos = end;
+        if id != CUSTOM_SECTION_ID {
+            continue;
+        }
+
+        let mut offset = 0;
+        let name_len = read_leb_u32(section, &mut offset)? as usize;
+        let name_end = offset
+            .checked_add(name_len)
+            .ok_or(ManifestError::Truncated)?;
+        let section_name = section
+            .get(offset..name_end)
+            .ok_or(ManifestError::Truncated)?;
+        if section_name == name.as_bytes() {
+            return Ok(Some(&section[name_end..]));
+        }
+    }
+    Ok(None)
+}
+
+/// Decode an unsigned LEB128 `u32` at `*pos`, advancing past it.
+fn read_leb_u32(bytes: &[u8], pos: &mut usize) -> Result<u32, ManifestError> {
+    let mut value: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = *bytes.get(*pos).ok_or(ManifestError::Truncated)?;
+        *pos += 1;
+        value |= u32::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(ManifestError::NotWasm)
+}
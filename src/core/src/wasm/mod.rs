@@ -0,0 +1,9 @@
+//! The parts of the kernel's `wasm` module that do not depend on the
+//! interpreter: capability handles and tables, the host calls' checks and
+//! filesystem work, manifests and the runtime interface.
+
+pub mod calls;
+pub mod capabilities;
+pub mod handles;
+pub mod manifest;
+pub mod runtime;
@@ -0,0 +1,111 @@
+//! Runtime backend interface.
+//!
+//! The kernel talks to the WASM interpreter through the `Engine`/`Process`
+//! trait pair, so the backend (currently wasmi, see `WasmEngine`) can be
+//! replaced without touching the scheduler side. Scheduling semantics are
+//! defined here rather than by the backend:
+//!
+//! - A process runs in time slices. Before each slice `Process::begin_slice`
+//!   grants a budget of `FUEL_PER_SLICE` and reports whether a suspended
+//!   invocation may continue (it may not while sleeping or waiting for
+//!   events).
+//! - A slice ends with the invocation either finished or suspended.
+//!   Suspension covers explicit yields, blocking host calls and a host fuel
+//!   check running out; the backend must be able to resume the invocation
+//!   exactly where it stopped.
+//! - Errors (traps, missing exports) end the invocation.
+//! - Processes are charged for the fuel they consume (`fuel_consumed`),
+//!   which the kernel converts to CPU time (see `cpu`).
+//!
+//! `poll_slice` implements these rules once for every future that drives a
+//! process.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::task::{Context, Poll};
+use sovelma_common::capability::Capability;
+
+/// Fuel units granted per scheduler time slice.
+///
+/// This value controls how much computation a task can perform before
+/// yielding to the scheduler. Higher values = longer time slices.
+pub const FUEL_PER_SLICE: u64 = 10_000;
+
+/// Outcome of running an invocation for one time slice.
+pub enum Slice<S> {
+    /// The function returned.
+    Finished,
+    /// The invocation was suspended and can be resumed later.
+    Suspended(S),
+}
+
+/// A WASM backend: compiles modules and creates processes.
+pub trait Engine {
+    /// Process type of this backend.
+    type Process: Process;
+    /// Error from compiling or instantiating a module.
+    type Error: fmt::Debug;
+
+    /// Instantiate `wasm_bytes` as a process holding `capabilities`.
+    fn instantiate(
+        &self,
+        wasm_bytes: &[u8],
+        capabilities: Vec<Capability>,
+    ) -> Result<Self::Process, Self::Error>;
+}
+
+/// A WASM instance that can be run in time slices.
+pub trait Process {
+    /// A suspended invocation.
+    type Suspended;
+    /// Error that ends an invocation.
+    type Error: fmt::Debug;
+
+    /// Prepare the next slice and refill the budget.
+    ///
+    /// Returns `false` if `suspended` cannot continue yet, in which case the
+    /// slice is skipped.
+    fn begin_slice(&mut self, suspended: Option<&Self::Suspended>) -> bool;
+
+    /// Start calling the exported function `entry`.
+    fn start(&mut self, entry: &str) -> Result<Slice<Self::Suspended>, Self::Error>;
+
+    /// Continue a suspended invocation.
+    fn resume(&mut self, suspended: Self::Suspended)
+        -> Result<Slice<Self::Suspended>, Self::Error>;
+
+    /// Total fuel consumed since the process was created.
+    fn fuel_consumed(&self) -> u64;
+}
+
+/// Run one slice of `entry` on `process`, starting the invocation or
+/// resuming the one in `suspended`.
+///
+/// Returns `Poll::Pending` (having arranged to be polled again) while the
+/// invocation is suspended or blocked.
+pub fn poll_slice<P: Process>(
+    process: &mut P,
+    entry: &str,
+    suspended: &mut Option<P::Suspended>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<(), P::Error>> {
+    if !process.begin_slice(suspended.as_ref()) {
+        cx.waker().wake_by_ref();
+        return Poll::Pending;
+    }
+
+    let result = match suspended.take() {
+        None => process.start(entry),
+        Some(invocation) => process.resume(invocation),
+    };
+
+    match result {
+        Ok(Slice::Finished) => Poll::Ready(Ok(())),
+        Ok(Slice::Suspended(invocation)) => {
+            *suspended = Some(invocation);
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+        Err(e) => Poll::Ready(Err(e)),
+    }
+}
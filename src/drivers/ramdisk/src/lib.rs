@@ -0,0 +1,77 @@
+//! RAM disk driver: a block device `ram0` backed by kernel heap memory.
+//!
+//! An example of a driver outside the kernel tree; build the kernel with
+//! `--features driver-ramdisk` to link it in. It needs no hardware, so it
+//! only uses the registration part of the driver interface.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use sovelma_hal::driver::prelude::*;
+
+/// Name the disk is registered under.
+pub const NAME: &str = "ram0";
+
+/// Size of a block in bytes.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Number of blocks (64 KiB).
+pub const BLOCK_COUNT: u64 = 128;
+
+/// A zero-filled disk in memory.
+struct RamDisk {
+    data: Vec<u8>,
+}
+
+impl RamDisk {
+    /// The bytes of the whole blocks from `first` covering `len` bytes.
+    fn range(&self, first: u64, len: usize) -> Result<core::ops::Range<usize>, DeviceError> {
+        if len / BLOCK_SIZE * BLOCK_SIZE != len {
+            return Err(DeviceError::Unsupported);
+        }
+        let start = usize::try_from(first)
+            .ok()
+            .and_then(|block| block.checked_mul(BLOCK_SIZE))
+            .ok_or(DeviceError::OutOfRange)?;
+        let end = start.checked_add(len).ok_or(DeviceError::OutOfRange)?;
+        if end > self.data.len() {
+            return Err(DeviceError::OutOfRange);
+        }
+        Ok(start..end)
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        BLOCK_COUNT
+    }
+
+    fn read_blocks(&mut self, first: u64, buf: &mut [u8]) -> Result<(), DeviceError> {
+        let range = self.range(first, buf.len())?;
+        buf.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, first: u64, buf: &[u8]) -> Result<(), DeviceError> {
+        let range = self.range(first, buf.len())?;
+        self.data[range].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// Register `ram0`.
+pub fn init(host: &dyn DriverHost) -> Result<(), DriverError> {
+    let disk = RamDisk {
+        data: vec![0; BLOCK_SIZE * BLOCK_COUNT as usize],
+    };
+    host.register_block(NAME, Box::new(disk))?;
+    info!("ramdisk: {} blocks of {} bytes", BLOCK_COUNT, BLOCK_SIZE);
+    Ok(())
+}
@@ -0,0 +1,247 @@
+//! Interface between the kernel and device drivers.
+//!
+//! A driver crate depends on this crate only, so it can live outside the
+//! kernel and be linked into it behind a feature. It exports a
+//! `DriverInit` function; at boot the kernel calls it with itself as the
+//! `DriverHost`, and the driver registers its devices, asks for interrupt
+//! lines and allocates DMA memory through the host.
+//!
+//! ```ignore
+//! use sovelma_hal::driver::prelude::*;
+//!
+//! pub fn init(host: &dyn DriverHost) -> Result<(), DriverError> {
+//!     let id = host.register_char("null0", Box::new(Null))?;
+//!     info!("null: registered as device {}", id);
+//!     Ok(())
+//! }
+//! ```
+
+use alloc::boxed::Box;
+use core::fmt;
+
+/// What kind of device a driver registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    /// Fixed-size blocks addressed by number (disks).
+    Block,
+    /// A byte stream (serial lines, terminals).
+    Char,
+    /// Ethernet frames.
+    Net,
+}
+
+impl DeviceKind {
+    /// Short lowercase name.
+    pub fn name(self) -> &'static str {
+        match self {
+            DeviceKind::Block => "block",
+            DeviceKind::Char => "char",
+            DeviceKind::Net => "net",
+        }
+    }
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Identifies a registered device; ids are not reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DeviceId(pub u32);
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Why a device operation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceError {
+    /// The hardware reported an error.
+    Io,
+    /// The block or offset is past the end of the device.
+    OutOfRange,
+    /// Nothing to read or no room to write yet.
+    WouldBlock,
+    /// The device cannot do this.
+    Unsupported,
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DeviceError::Io => "I/O error",
+            DeviceError::OutOfRange => "out of range",
+            DeviceError::WouldBlock => "would block",
+            DeviceError::Unsupported => "not supported",
+        })
+    }
+}
+
+/// Why the kernel refused a driver's request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriverError {
+    /// Another device already has the name.
+    NameTaken,
+    /// Drivers cannot have this interrupt line.
+    IrqUnavailable(u8),
+    /// The line already has as many handlers as it can take.
+    IrqBusy(u8),
+    /// Not enough memory.
+    OutOfMemory,
+    /// The device the driver looked for is not there.
+    NoDevice,
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverError::NameTaken => f.write_str("device name taken"),
+            DriverError::IrqUnavailable(irq) => write!(f, "IRQ {} not available", irq),
+            DriverError::IrqBusy(irq) => write!(f, "IRQ {} has no free handler slot", irq),
+            DriverError::OutOfMemory => f.write_str("out of memory"),
+            DriverError::NoDevice => f.write_str("no device"),
+        }
+    }
+}
+
+/// A device of fixed-size blocks.
+pub trait BlockDevice: Send {
+    /// Size of a block in bytes.
+    fn block_size(&self) -> usize;
+    /// Number of blocks.
+    fn block_count(&self) -> u64;
+    /// Read whole blocks from `first` into `buf`, whose length is a
+    /// multiple of the block size.
+    fn read_blocks(&mut self, first: u64, buf: &mut [u8]) -> Result<(), DeviceError>;
+    /// Write whole blocks from `buf` starting at block `first`.
+    fn write_blocks(&mut self, first: u64, buf: &[u8]) -> Result<(), DeviceError>;
+}
+
+/// A byte-stream device.
+pub trait CharDevice: Send {
+    /// Read what is available into `buf`; returns the number of bytes.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, DeviceError>;
+    /// Write from `buf`; returns the number of bytes taken.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, DeviceError>;
+}
+
+/// An Ethernet interface.
+pub trait NetDevice: Send {
+    /// The interface's MAC address.
+    fn mac_address(&self) -> [u8; 6];
+    /// Send one frame.
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), DeviceError>;
+    /// Copy the next received frame into `buf`; returns its length, or
+    /// `None` if none is waiting.
+    fn receive(&mut self, buf: &mut [u8]) -> Option<usize>;
+}
+
+/// Called with the line number when an interrupt arrives on a requested
+/// line. It runs in interrupt context: it must not block, allocate or
+/// take locks that code with interrupts enabled holds. Lines are shared,
+/// so it should check that its device raised the interrupt.
+pub type IrqHandler = fn(irq: u8);
+
+/// Physically contiguous memory a device can reach by bus address.
+///
+/// Given back with `DriverHost::dma_free`; dropping it leaks the memory.
+#[derive(Debug)]
+pub struct DmaRegion {
+    phys: u64,
+    virt: *mut u8,
+    len: usize,
+}
+
+// SAFETY: The region is memory the kernel handed to its one owner; no
+// other reference to it exists.
+unsafe impl Send for DmaRegion {}
+
+impl DmaRegion {
+    /// Wrap memory the host allocated.
+    ///
+    /// # Safety
+    ///
+    /// `len` bytes at `virt` must be mapped, unused by anything else and
+    /// at bus address `phys`.
+    pub unsafe fn new(phys: u64, virt: *mut u8, len: usize) -> Self {
+        DmaRegion { phys, virt, len }
+    }
+
+    /// Bus address of the first byte, to give to the device.
+    pub fn phys(&self) -> u64 {
+        self.phys
+    }
+
+    /// Size in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The memory, for the CPU's side of the transfer.
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: `new`'s contract: the bytes are mapped and ours.
+        unsafe { core::slice::from_raw_parts(self.virt, self.len) }
+    }
+
+    /// The memory, mutably.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: As for `as_slice`; `&mut self` makes it exclusive.
+        unsafe { core::slice::from_raw_parts_mut(self.virt, self.len) }
+    }
+}
+
+/// What the kernel offers drivers.
+pub trait DriverHost: Sync {
+    /// Register a block device under `name`.
+    fn register_block(
+        &self,
+        name: &str,
+        device: Box<dyn BlockDevice>,
+    ) -> Result<DeviceId, DriverError>;
+    /// Register a character device under `name`.
+    fn register_char(
+        &self,
+        name: &str,
+        device: Box<dyn CharDevice>,
+    ) -> Result<DeviceId, DriverError>;
+    /// Register a network interface under `name`.
+    fn register_net(&self, name: &str, device: Box<dyn NetDevice>)
+        -> Result<DeviceId, DriverError>;
+    /// Call `handler` for every interrupt on `irq`, and let the line
+    /// through.
+    fn request_irq(&self, irq: u8, handler: IrqHandler) -> Result<(), DriverError>;
+    /// Stop calling `handler` for `irq`.
+    fn free_irq(&self, irq: u8, handler: IrqHandler);
+    /// Allocate at least `len` bytes of zeroed, physically contiguous
+    /// memory, page aligned.
+    fn dma_alloc(&self, len: usize) -> Result<DmaRegion, DriverError>;
+    /// Give back a region from `dma_alloc`.
+    ///
+    /// # Safety
+    ///
+    /// The device must no longer use it.
+    unsafe fn dma_free(&self, region: DmaRegion);
+}
+
+/// A driver's entry point, called once at boot.
+pub type DriverInit = fn(host: &dyn DriverHost) -> Result<(), DriverError>;
+
+/// Everything a driver usually needs, including the logging macros; the
+/// kernel's logger prints what drivers log.
+pub mod prelude {
+    pub use super::{
+        BlockDevice, CharDevice, DeviceError, DeviceId, DeviceKind, DmaRegion, DriverError,
+        DriverHost, DriverInit, IrqHandler, NetDevice,
+    };
+    pub use alloc::boxed::Box;
+    pub use log::{debug, error, info, trace, warn};
+}
@@ -1,9 +1,14 @@
 //! SovelmaOS Hardware Abstraction Layer (HAL) traits.
 //!
-//! This crate defines traits that abstract away platform-specific hardware details.
+//! This crate defines traits that abstract away platform-specific hardware details,
+//! and in `driver` the interface between the kernel and device drivers.
 
 #![no_std]
 
+extern crate alloc;
+
+pub mod driver;
+
 /// Trait for a serial port or similar character-based communication channel.
 pub trait Serial {
     /// Writes a single byte to the serial port.
@@ -35,3 +40,13 @@ pub trait Timer {
     /// Returns the number of ticks since the system started.
     fn current_ticks(&self) -> u64;
 }
+
+/// Trait for ending a run under an emulator with a status code.
+///
+/// Test runs use it to report their result to the host: on x86_64 QEMU
+/// this is the `isa-debug-exit` device; other platforms would use e.g.
+/// PSCI `SYSTEM_OFF` or a semihosting exit call.
+pub trait DebugExit {
+    /// Exits with `code`. Returns only if no exit device is present.
+    fn exit(&mut self, code: u32);
+}
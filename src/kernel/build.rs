@@ -0,0 +1,99 @@
+//! Build script for the kernel.
+//!
+//! Copies files named by environment variables to `$OUT_DIR`, where the
+//! kernel embeds them:
+//!
+//! - `SOVELMA_KSYMS`: symbol map (an `nm -n` listing of a previous kernel
+//!   build), embedded by `ksym` as `kernel.sym`.
+//! - `SOVELMA_REPLAY`: input recording, embedded by `replay` as
+//!   `replay.rec`.
+//! - `SOVELMA_MOTD`: message of the day, embedded by `boot::banner` as
+//!   `motd.txt`.
+//!
+//! Without a variable an empty file is embedded.
+//!
+//! `buildinfo` reads what the kernel is built from out of variables set
+//! for it here: `SOVELMA_GIT_HASH` (the short hash of the commit, or
+//! `unknown` outside a git checkout), `SOVELMA_PROFILE`,
+//! `SOVELMA_FEATURES` (the enabled features, space-separated) and
+//! `SOVELMA_RUSTC_VERSION`.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    embed("SOVELMA_KSYMS", "kernel.sym");
+    embed("SOVELMA_REPLAY", "replay.rec");
+    embed("SOVELMA_MOTD", "motd.txt");
+    git_hash();
+    build_info();
+}
+
+/// Copy the file named by `var` (or nothing) to `$OUT_DIR/name`.
+fn embed(var: &str, name: &str) {
+    println!("cargo:rerun-if-env-changed={}", var);
+
+    let out_dir = env::var_os("OUT_DIR").expect("cargo sets OUT_DIR");
+    let out = PathBuf::from(out_dir).join(name);
+
+    let contents = match env::var_os(var) {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            println!("cargo:rerun-if-changed={}", path.display());
+            fs::read(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e))
+        }
+        None => Vec::new(),
+    };
+    fs::write(&out, contents).unwrap_or_else(|e| panic!("cannot write {}: {}", name, e));
+}
+
+/// Output of `git args...` run in the kernel directory, if it succeeds.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Set `SOVELMA_GIT_HASH`, and build again when the checked out commit
+/// changes.
+fn git_hash() {
+    let hash = git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| String::from("unknown"));
+    println!("cargo:rustc-env=SOVELMA_GIT_HASH={}", hash);
+
+    // HEAD names the branch; the branch's ref file the commit
+    for path in ["HEAD", "refs/heads"] {
+        if let Some(path) = git(&["rev-parse", "--git-path", path]) {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
+
+/// Set `SOVELMA_PROFILE`, `SOVELMA_FEATURES` and `SOVELMA_RUSTC_VERSION`.
+fn build_info() {
+    let profile = env::var("PROFILE").unwrap_or_else(|_| String::from("unknown"));
+    println!("cargo:rustc-env=SOVELMA_PROFILE={}", profile);
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(name, _)| {
+            let feature = name.strip_prefix("CARGO_FEATURE_")?;
+            // `default` only names others
+            (feature != "DEFAULT").then(|| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=SOVELMA_FEATURES={}", features.join(" "));
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| String::from("unknown"));
+    println!("cargo:rustc-env=SOVELMA_RUSTC_VERSION={}", version);
+}
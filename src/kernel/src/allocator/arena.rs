@@ -0,0 +1,264 @@
+//! Per-process memory arenas.
+//!
+//! A WASM process's linear memory is a large heap buffer owned by wasmi.
+//! Kept in the 1 MiB kernel heap it would fragment it and cap guest memory
+//! at a fraction of a megabyte, so each process gets an arena instead: a
+//! window of virtual addresses of its own, backed by frames mapped on
+//! demand (see `memory::map_pages`).
+//!
+//! Only the linear memory goes there. Before wasmi allocates a memory's
+//! buffer it asks the store's resource limiter, which calls `expect_growth`
+//! with the size wanted; while the process runs (`ProcessMemory::enter`),
+//! the next allocation at least that large is served from its arena.
+//! Everything else, such as the buffers host calls make, stays on the
+//! kernel heap, so nothing that outlives the process holds on to its
+//! arena. Frees are routed by address. When the process exits its arena is
+//! unmapped and the frames returned; if a block from it is still alive,
+//! that waits for the last free.
+
+use crate::memory::{self, PAGE_SIZE};
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use linked_list_allocator::Heap;
+use spin::Mutex;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/// Start of the first arena window.
+pub const ARENA_BASE: u64 = 0x0000_5000_0000_0000;
+
+/// Virtual size of each arena, the most memory one process can hold.
+pub const ARENA_WINDOW: u64 = 256 * 1024 * 1024;
+
+/// Number of arenas, and so of processes with their own memory.
+pub const MAX_ARENAS: usize = 16;
+
+/// Arenas grow by at least this much at a time.
+const GROW_STEP: u64 = 256 * 1024;
+
+/// No process is running.
+const NONE: usize = usize::MAX;
+
+/// Arena of the running process, or `NONE`.
+static CURRENT: AtomicUsize = AtomicUsize::new(NONE);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Free,
+    Owned,
+    /// The process exited while blocks were still allocated.
+    Retired,
+}
+
+struct Arena {
+    state: State,
+    heap: Heap,
+    /// Bytes mapped from the window base.
+    mapped: u64,
+    /// Size of the linear memory allocation wasmi is about to make, or 0.
+    expected: usize,
+    /// Bytes allocated for the linear memory, which grows in place until
+    /// it needs more.
+    linear: usize,
+}
+
+impl Arena {
+    const fn new() -> Self {
+        Self {
+            state: State::Free,
+            heap: Heap::empty(),
+            mapped: 0,
+            expected: 0,
+            linear: 0,
+        }
+    }
+
+    /// Map enough memory to fit `layout`. Returns false at the window's end
+    /// or when out of frames.
+    fn grow(&mut self, base: u64, layout: Layout) -> bool {
+        // Room for the block, its alignment and the allocator's hole header
+        let needed = (layout.size() + layout.align() + 2 * PAGE_SIZE as usize) as u64;
+        let by = needed.max(GROW_STEP).next_multiple_of(PAGE_SIZE);
+        if self.mapped + by > ARENA_WINDOW {
+            return false;
+        }
+        let start = VirtAddr::new(base + self.mapped);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        if memory::map_pages(start, by / PAGE_SIZE, flags).is_err() {
+            return false;
+        }
+        if self.mapped == 0 {
+            // SAFETY: The range was just mapped and belongs to this arena.
+            unsafe { self.heap.init(start.as_mut_ptr(), by as usize) };
+        } else {
+            // SAFETY: The range was just mapped directly after the heap.
+            unsafe { self.heap.extend(by as usize) };
+        }
+        self.mapped += by;
+        true
+    }
+
+    /// Unmap everything and make the arena available again.
+    fn release(&mut self, base: u64) {
+        // SAFETY: No block of the arena is allocated any more.
+        unsafe { memory::unmap_pages(VirtAddr::new(base), self.mapped / PAGE_SIZE) };
+        *self = Self::new();
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_ARENA: Mutex<Arena> = Mutex::new(Arena::new());
+
+/// The arenas. Plain spin locks: taken inside the global allocator.
+static ARENAS: [Mutex<Arena>; MAX_ARENAS] = [EMPTY_ARENA; MAX_ARENAS];
+
+/// Base address of arena `slot`.
+fn base(slot: usize) -> u64 {
+    ARENA_BASE + slot as u64 * ARENA_WINDOW
+}
+
+/// Arena whose window holds `ptr`, if any.
+pub fn slot_of(ptr: *const u8) -> Option<usize> {
+    let offset = (ptr as u64).checked_sub(ARENA_BASE)?;
+    let slot = (offset / ARENA_WINDOW) as usize;
+    (slot < MAX_ARENAS).then_some(slot)
+}
+
+/// Expect the running process's linear memory to grow to `desired` bytes,
+/// so that the allocation for it is served from the process's arena.
+/// Nothing is expected while the memory has room to grow in place.
+pub fn expect_growth(desired: usize) {
+    if let Some(arena) = ARENAS.get(CURRENT.load(Ordering::Relaxed)) {
+        let mut arena = arena.lock();
+        arena.expected = if desired > arena.linear { desired } else { 0 };
+    }
+}
+
+/// Forget an expected growth that failed before allocating.
+pub fn growth_failed() {
+    if let Some(arena) = ARENAS.get(CURRENT.load(Ordering::Relaxed)) {
+        arena.lock().expected = 0;
+    }
+}
+
+/// Allocate from the running process's arena, if there is one and it
+/// expects an allocation as large as `layout` for its linear memory.
+pub(super) fn alloc(layout: Layout) -> Option<*mut u8> {
+    let slot = CURRENT.load(Ordering::Relaxed);
+    let mut arena = ARENAS.get(slot)?.lock();
+    if arena.state != State::Owned || arena.expected == 0 || layout.size() < arena.expected {
+        return None;
+    }
+    arena.expected = 0;
+    arena.linear = layout.size();
+    if let Ok(ptr) = arena.heap.allocate_first_fit(layout) {
+        return Some(ptr.as_ptr());
+    }
+    if !arena.grow(base(slot), layout) {
+        return None;
+    }
+    arena
+        .heap
+        .allocate_first_fit(layout)
+        .ok()
+        .map(NonNull::as_ptr)
+}
+
+/// Free a block if it came from an arena. Returns false if it did not.
+///
+/// # Safety
+///
+/// As for `GlobalAlloc::dealloc`.
+pub(super) unsafe fn dealloc(ptr: *mut u8, layout: Layout) -> bool {
+    let Some(slot) = slot_of(ptr) else {
+        return false;
+    };
+    let mut arena = ARENAS[slot].lock();
+    if let Some(ptr) = NonNull::new(ptr) {
+        // SAFETY: The caller guarantees the block was allocated with
+        // `layout`, and its address places it in this arena.
+        unsafe { arena.heap.deallocate(ptr, layout) };
+    }
+    if arena.state == State::Retired && arena.heap.used() == 0 {
+        arena.release(base(slot));
+    }
+    true
+}
+
+/// The memory of one process: owns an arena until dropped.
+pub struct ProcessMemory {
+    slot: usize,
+}
+
+impl ProcessMemory {
+    /// Claim a free arena. Returns `None` if all are in use.
+    pub fn new() -> Option<Self> {
+        ARENAS.iter().enumerate().find_map(|(slot, arena)| {
+            let mut arena = arena.lock();
+            (arena.state == State::Free).then(|| {
+                arena.state = State::Owned;
+                Self { slot }
+            })
+        })
+    }
+
+    /// Serve the linear memory's allocations from this arena until the
+    /// guard is dropped.
+    pub fn enter(&self) -> ArenaGuard {
+        ArenaGuard {
+            previous: CURRENT.swap(self.slot, Ordering::Relaxed),
+        }
+    }
+
+    /// Bytes currently mapped for the arena.
+    pub fn mapped_bytes(&self) -> u64 {
+        ARENAS[self.slot].lock().mapped
+    }
+
+    /// Bytes allocated from the arena.
+    pub fn used_bytes(&self) -> usize {
+        let arena = ARENAS[self.slot].lock();
+        if arena.mapped == 0 {
+            0
+        } else {
+            arena.heap.used()
+        }
+    }
+}
+
+impl Drop for ProcessMemory {
+    fn drop(&mut self) {
+        let _ = CURRENT.compare_exchange(self.slot, NONE, Ordering::Relaxed, Ordering::Relaxed);
+        let mut arena = ARENAS[self.slot].lock();
+        if arena.mapped == 0 || arena.heap.used() == 0 {
+            arena.release(base(self.slot));
+        } else {
+            arena.state = State::Retired;
+        }
+    }
+}
+
+/// Returned by `ProcessMemory::enter`; restores the previous arena.
+pub struct ArenaGuard {
+    previous: usize,
+}
+
+impl Drop for ArenaGuard {
+    fn drop(&mut self) {
+        CURRENT.store(self.previous, Ordering::Relaxed);
+    }
+}
+
+/// Arenas in use (owned by a process or waiting for their last free).
+pub fn in_use() -> usize {
+    ARENAS
+        .iter()
+        .filter(|arena| arena.lock().state != State::Free)
+        .count()
+}
+
+/// Bytes mapped for all arenas together.
+pub fn mapped_total() -> u64 {
+    ARENAS.iter().map(|arena| arena.lock().mapped).sum()
+}
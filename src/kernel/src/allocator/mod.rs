@@ -1,5 +1,14 @@
 //! Kernel heap allocation.
+//!
+//! Allocations come from the fixed kernel heap, except large ones made
+//! while a WASM process runs, which go to that process's arena (see
+//! `arena`). With the `heap-poison` feature, heap blocks get red zones and
+//! freed ones are poisoned and quarantined (see `poison`).
 
+pub mod arena;
+pub mod poison;
+
+use core::alloc::{GlobalAlloc, Layout};
 use linked_list_allocator::LockedHeap;
 use x86_64::{
     structures::paging::{
@@ -19,8 +28,46 @@ pub const HEAP_START: usize = 0x_4444_4444_0000;
 /// - General allocations: ~256 KiB
 pub const HEAP_SIZE: usize = 1024 * 1024; // 1 MiB
 
+/// The kernel heap, with process arenas in front of it.
+struct KernelAllocator {
+    heap: LockedHeap,
+}
+
+// SAFETY: Every block is freed by the allocator that produced it: arena
+// blocks are recognized by their address, which never overlaps the heap.
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(ptr) = arena::alloc(layout) {
+            return ptr;
+        }
+        if cfg!(feature = "heap-poison") {
+            // SAFETY: Forwarded from the caller.
+            unsafe { poison::alloc(&self.heap, layout) }
+        } else {
+            // SAFETY: Forwarded from the caller.
+            unsafe { self.heap.alloc(layout) }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: Forwarded from the caller.
+        unsafe {
+            if arena::dealloc(ptr, layout) {
+                return;
+            }
+            if cfg!(feature = "heap-poison") {
+                poison::dealloc(&self.heap, ptr, layout);
+            } else {
+                self.heap.dealloc(ptr, layout);
+            }
+        }
+    }
+}
+
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: KernelAllocator = KernelAllocator {
+    heap: LockedHeap::empty(),
+};
 
 /// Initialize the kernel heap.
 pub fn init_heap(
@@ -53,8 +100,13 @@ pub fn init_heap(
     // permissions. HEAP_START and HEAP_SIZE define a valid, properly aligned
     // memory region. This function is only called once during kernel initialization.
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
+        ALLOCATOR.heap.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
     }
 
     Ok(())
 }
+
+/// Bytes of the kernel heap currently allocated.
+pub fn heap_used() -> usize {
+    ALLOCATOR.heap.lock().used()
+}
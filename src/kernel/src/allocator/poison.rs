@@ -0,0 +1,274 @@
+//! Red zones and freed-memory poisoning for the kernel heap.
+//!
+//! Enabled by the `heap-poison` feature. Every heap block is surrounded by
+//! red zones filled with `REDZONE_BYTE`:
+//!
+//! ```text
+//! | red zone (>= 16, keeps alignment) | caller's bytes | red zone (16) |
+//! ```
+//!
+//! Freeing a block checks that both red zones are intact (catching
+//! overflows and underflows), fills the caller's bytes with `FREED_BYTE`
+//! and parks the block in a quarantine instead of returning it to the
+//! heap. A block leaving the quarantine, and every block in it when the
+//! scrubber task runs `scrub`, must still hold only `FREED_BYTE`: anything
+//! else is a write after free.
+//!
+//! Violations are counted and reported through `irq_log!`, since they are
+//! found inside the allocator, where printing (which may allocate) is not
+//! possible.
+
+use crate::irq_log;
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use linked_list_allocator::LockedHeap;
+use log::Level;
+use spin::Mutex;
+
+/// Bytes of red zone after each block, and the least before it.
+pub const REDZONE: usize = 16;
+
+/// Fill of the red zones.
+pub const REDZONE_BYTE: u8 = 0xFB;
+
+/// Fill of freed blocks.
+pub const FREED_BYTE: u8 = 0xFD;
+
+/// Freed blocks held back from reuse.
+const QUARANTINE_SLOTS: usize = 128;
+
+/// Heap bytes the quarantine may hold back at once.
+const QUARANTINE_BYTES: usize = 64 * 1024;
+
+/// How often the scrubber task runs `scrub`.
+pub const SCRUB_INTERVAL_MS: u64 = 1000;
+
+/// What a check found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// The red zone before a block was overwritten.
+    Underflow,
+    /// The red zone after a block was overwritten.
+    Overflow,
+    /// A freed block was written to.
+    UseAfterFree,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Underflow => write!(f, "heap underflow"),
+            Self::Overflow => write!(f, "heap overflow"),
+            Self::UseAfterFree => write!(f, "write after free"),
+        }
+    }
+}
+
+static VIOLATIONS: AtomicU64 = AtomicU64::new(0);
+static SCRUBS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of violations found so far.
+pub fn violations() -> u64 {
+    VIOLATIONS.load(Ordering::Relaxed)
+}
+
+/// Number of times `scrub` has run.
+pub fn scrubs() -> u64 {
+    SCRUBS.load(Ordering::Relaxed)
+}
+
+fn report(violation: Violation, ptr: *const u8, layout: Layout) {
+    VIOLATIONS.fetch_add(1, Ordering::Relaxed);
+    irq_log!(
+        Level::Error,
+        "heap",
+        "{} in {}-byte block at {:p}",
+        violation,
+        layout.size(),
+        ptr
+    );
+}
+
+/// Bytes of red zone before a block with `layout`; a multiple of its
+/// alignment.
+fn front(layout: Layout) -> usize {
+    layout.align().max(REDZONE)
+}
+
+/// Layout of the whole allocation around a block with `layout`.
+fn outer(layout: Layout) -> Option<Layout> {
+    let front = front(layout);
+    let size = front.checked_add(layout.size())?.checked_add(REDZONE)?;
+    Layout::from_size_align(size, front).ok()
+}
+
+/// Whether all `len` bytes at `ptr` equal `byte`.
+///
+/// # Safety
+///
+/// The range must be readable.
+unsafe fn filled(ptr: *const u8, len: usize, byte: u8) -> bool {
+    // SAFETY: Guaranteed by the caller.
+    unsafe { core::slice::from_raw_parts(ptr, len) }
+        .iter()
+        .all(|&b| b == byte)
+}
+
+/// Allocate a block surrounded by red zones.
+///
+/// # Safety
+///
+/// As for `GlobalAlloc::alloc`.
+pub(super) unsafe fn alloc(heap: &LockedHeap, layout: Layout) -> *mut u8 {
+    let Some(outer) = outer(layout) else {
+        return core::ptr::null_mut();
+    };
+    // SAFETY: `outer` has a non-zero size.
+    let base = unsafe { heap.alloc(outer) };
+    if base.is_null() {
+        return base;
+    }
+    let front = front(layout);
+    // SAFETY: Both red zones lie inside the allocation.
+    unsafe {
+        base.write_bytes(REDZONE_BYTE, front);
+        base.add(front + layout.size())
+            .write_bytes(REDZONE_BYTE, REDZONE);
+        base.add(front)
+    }
+}
+
+/// Check a block's red zones, poison it and quarantine it.
+///
+/// # Safety
+///
+/// As for `GlobalAlloc::dealloc`; `ptr` must come from `alloc`.
+pub(super) unsafe fn dealloc(heap: &LockedHeap, ptr: *mut u8, layout: Layout) {
+    let front = front(layout);
+    // SAFETY: `alloc` placed the red zones around `ptr`.
+    unsafe {
+        if !filled(ptr.sub(front), front, REDZONE_BYTE) {
+            report(Violation::Underflow, ptr, layout);
+        }
+        if !filled(ptr.add(layout.size()), REDZONE, REDZONE_BYTE) {
+            report(Violation::Overflow, ptr, layout);
+        }
+        ptr.write_bytes(FREED_BYTE, layout.size());
+    }
+
+    let block = Freed {
+        ptr: ptr as usize,
+        layout,
+    };
+    let size = block.outer_size();
+    if size > QUARANTINE_BYTES / 4 {
+        // Too large to hold back without starving the heap
+        // SAFETY: The block came from `alloc` and is no longer used.
+        unsafe { block.release(heap) };
+        return;
+    }
+    let mut quarantine = QUARANTINE.lock();
+    while !quarantine.has_room(size) {
+        let Some(oldest) = quarantine.pop() else {
+            break;
+        };
+        // SAFETY: Quarantined blocks came from `alloc` and are unused.
+        unsafe { oldest.release(heap) };
+    }
+    quarantine.push(block);
+}
+
+/// Check every quarantined block for writes after free.
+pub fn scrub() {
+    SCRUBS.fetch_add(1, Ordering::Relaxed);
+    for block in QUARANTINE.lock().blocks.iter().flatten() {
+        block.check();
+    }
+}
+
+/// Number of blocks in quarantine.
+pub fn quarantined() -> usize {
+    QUARANTINE.lock().len
+}
+
+/// A freed block, poisoned and held back from reuse.
+#[derive(Clone, Copy)]
+struct Freed {
+    /// Address handed out by `alloc`.
+    ptr: usize,
+    layout: Layout,
+}
+
+impl Freed {
+    /// Report a write after free, and poison the block again so the next
+    /// scrub does not report it twice.
+    fn check(&self) {
+        let ptr = self.ptr as *mut u8;
+        // SAFETY: Quarantined blocks stay allocated from the heap.
+        unsafe {
+            if !filled(ptr, self.layout.size(), FREED_BYTE) {
+                report(Violation::UseAfterFree, ptr, self.layout);
+                ptr.write_bytes(FREED_BYTE, self.layout.size());
+            }
+        }
+    }
+
+    /// Heap bytes the block occupies.
+    fn outer_size(&self) -> usize {
+        outer(self.layout).map_or(0, |outer| outer.size())
+    }
+
+    /// Check the block once more and return it to the heap.
+    ///
+    /// # Safety
+    ///
+    /// The block must have come from `alloc` and not be released yet.
+    unsafe fn release(self, heap: &LockedHeap) {
+        self.check();
+        let Some(outer) = outer(self.layout) else {
+            return;
+        };
+        let base = (self.ptr - front(self.layout)) as *mut u8;
+        // SAFETY: `base` and `outer` are what `alloc` got from the heap.
+        unsafe { heap.dealloc(base, outer) };
+    }
+}
+
+/// Ring of freed blocks, oldest first out.
+struct Quarantine {
+    blocks: [Option<Freed>; QUARANTINE_SLOTS],
+    /// Slot of the oldest block.
+    head: usize,
+    len: usize,
+    /// Heap bytes held.
+    bytes: usize,
+}
+
+impl Quarantine {
+    /// Whether a block of `size` heap bytes fits without evicting one.
+    fn has_room(&self, size: usize) -> bool {
+        self.len < QUARANTINE_SLOTS && self.bytes + size <= QUARANTINE_BYTES
+    }
+
+    fn push(&mut self, block: Freed) {
+        self.blocks[(self.head + self.len) % QUARANTINE_SLOTS] = Some(block);
+        self.len += 1;
+        self.bytes += block.outer_size();
+    }
+
+    fn pop(&mut self) -> Option<Freed> {
+        let block = self.blocks[self.head].take()?;
+        self.head = (self.head + 1) % QUARANTINE_SLOTS;
+        self.len -= 1;
+        self.bytes -= block.outer_size();
+        Some(block)
+    }
+}
+
+static QUARANTINE: Mutex<Quarantine> = Mutex::new(Quarantine {
+    blocks: [None; QUARANTINE_SLOTS],
+    head: 0,
+    len: 0,
+    bytes: 0,
+});
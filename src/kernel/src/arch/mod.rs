@@ -8,3 +8,9 @@ pub mod x86_64;
 
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::*;
+
+/// The platform's device for ending a run under an emulator.
+#[cfg(target_arch = "x86_64")]
+pub fn debug_exit() -> impl sovelma_hal::DebugExit {
+    x86_64::debug_exit::IsaDebugExit
+}
@@ -0,0 +1,97 @@
+//! Unicode to code page 437 mapping.
+//!
+//! The VGA text mode font is code page 437: ASCII plus accented letters,
+//! box drawing, shading, Greek and math symbols, and glyphs for the control
+//! codes. `encode` maps a character to the glyph that shows it, falling
+//! back to a similar-looking one (rounded and heavy box corners to light
+//! ones, typographic quotes and dashes to ASCII) and then to `PLACEHOLDER`.
+
+/// Glyph shown for characters the font cannot show (a small square).
+pub const PLACEHOLDER: u8 = 0xFE;
+
+/// Characters of glyphs 0x01 to 0x1F, which stand in for control codes.
+#[rustfmt::skip]
+const LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// Glyph 0x7F.
+const HOUSE: char = '⌂';
+
+/// Characters of glyphs 0x80 to 0xFF.
+#[rustfmt::skip]
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Look-alikes for common characters the font lacks.
+const SIMILAR: [(char, u8); 22] = [
+    // Rounded and heavy box drawing
+    ('╭', 0xDA),
+    ('╮', 0xBF),
+    ('╯', 0xD9),
+    ('╰', 0xC0),
+    ('━', 0xC4),
+    ('┃', 0xB3),
+    ('┏', 0xDA),
+    ('┓', 0xBF),
+    ('┗', 0xC0),
+    ('┛', 0xD9),
+    // Typography
+    ('‘', b'\''),
+    ('’', b'\''),
+    ('“', b'"'),
+    ('”', b'"'),
+    ('–', b'-'),
+    ('—', b'-'),
+    ('−', b'-'),
+    ('…', 0xFA),
+    // Letters with a twin in another script
+    ('β', 0xE1),
+    ('μ', 0xE6),
+    ('Ø', 0xED),
+    ('€', b'E'),
+];
+
+/// The glyph for `c`, if the font has it or something like it.
+///
+/// Printable ASCII maps to itself; control characters have no glyph.
+pub fn encode(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        HOUSE => Some(0x7F),
+        _ => position(&LOW, c)
+            .map(|i| i as u8 + 0x01)
+            .or_else(|| position(&HIGH, c).map(|i| i as u8 + 0x80))
+            .or_else(|| {
+                SIMILAR
+                    .iter()
+                    .find(|(similar, _)| *similar == c)
+                    .map(|(_, glyph)| *glyph)
+            }),
+    }
+}
+
+fn position(table: &[char], c: char) -> Option<usize> {
+    table.iter().position(|entry| *entry == c)
+}
+
+/// The character glyph `byte` shows; inverse of `encode` for the glyphs
+/// that have one.
+pub fn decode(byte: u8) -> Option<char> {
+    match byte {
+        0x20..=0x7E => Some(byte as char),
+        0x7F => Some(HOUSE),
+        0x01..=0x1F => Some(LOW[byte as usize - 0x01]),
+        0x80..=0xFF => Some(HIGH[byte as usize - 0x80]),
+        _ => None,
+    }
+}
@@ -0,0 +1,266 @@
+//! CPU identification and feature detection.
+//!
+//! Subsystems that can use an optional instruction set extension (RDRAND
+//! for entropy, the TSC-deadline timer, SSE/AVX code paths) check `info()`
+//! instead of assuming the feature is there. Detection runs once, on first
+//! use; the result never changes while the kernel runs.
+
+use core::arch::x86_64::{__cpuid_count, CpuidResult};
+use spin::Once;
+
+/// Leaf 0: highest standard leaf and vendor string.
+const LEAF_VENDOR: u32 = 0x0000_0000;
+/// Leaf 1: family/model/stepping and the basic feature flags.
+const LEAF_FEATURES: u32 = 0x0000_0001;
+/// Leaf 7 subleaf 0: structured extended feature flags.
+const LEAF_EXTENDED_FEATURES: u32 = 0x0000_0007;
+/// Highest extended leaf.
+const LEAF_EXTENDED_MAX: u32 = 0x8000_0000;
+/// Leaves 0x8000_0002..=0x8000_0004: processor brand string.
+const LEAF_BRAND: u32 = 0x8000_0002;
+/// Leaf 0xD: processor extended state enumeration.
+const LEAF_XSAVE: u32 = 0x0000_000D;
+/// Leaf 0x8000_0007: advanced power management (invariant TSC).
+const LEAF_POWER: u32 = 0x8000_0007;
+
+// Leaf 1 ECX
+const ECX_SSE3: u32 = 1 << 0;
+const ECX_SSSE3: u32 = 1 << 9;
+const ECX_SSE4_1: u32 = 1 << 19;
+const ECX_SSE4_2: u32 = 1 << 20;
+const ECX_X2APIC: u32 = 1 << 21;
+const ECX_TSC_DEADLINE: u32 = 1 << 24;
+const ECX_XSAVE: u32 = 1 << 26;
+const ECX_AVX: u32 = 1 << 28;
+const ECX_RDRAND: u32 = 1 << 30;
+// Leaf 1 EDX
+const EDX_FXSR: u32 = 1 << 24;
+const EDX_SSE: u32 = 1 << 25;
+const EDX_SSE2: u32 = 1 << 26;
+// Leaf 7 EBX
+const EBX_AVX2: u32 = 1 << 5;
+const EBX_RDSEED: u32 = 1 << 18;
+// Leaf 0x8000_0007 EDX
+const EDX_INVARIANT_TSC: u32 = 1 << 8;
+
+/// Optional CPU features the kernel cares about.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features {
+    /// FXSAVE/FXRSTOR.
+    pub fxsr: bool,
+    /// SSE.
+    pub sse: bool,
+    /// SSE2 (part of the x86_64 baseline).
+    pub sse2: bool,
+    /// SSE3.
+    pub sse3: bool,
+    /// Supplemental SSE3.
+    pub ssse3: bool,
+    /// SSE4.1.
+    pub sse4_1: bool,
+    /// SSE4.2.
+    pub sse4_2: bool,
+    /// XSAVE/XRSTOR.
+    pub xsave: bool,
+    /// AVX.
+    pub avx: bool,
+    /// AVX2.
+    pub avx2: bool,
+    /// RDRAND instruction.
+    pub rdrand: bool,
+    /// RDSEED instruction.
+    pub rdseed: bool,
+    /// Local APIC TSC-deadline timer mode.
+    pub tsc_deadline: bool,
+    /// TSC runs at a constant rate in all power states.
+    pub invariant_tsc: bool,
+    /// x2APIC mode.
+    pub x2apic: bool,
+}
+
+impl Features {
+    /// Each feature with its conventional name, in display order.
+    pub fn list(&self) -> [(&'static str, bool); 15] {
+        [
+            ("fxsr", self.fxsr),
+            ("sse", self.sse),
+            ("sse2", self.sse2),
+            ("sse3", self.sse3),
+            ("ssse3", self.ssse3),
+            ("sse4.1", self.sse4_1),
+            ("sse4.2", self.sse4_2),
+            ("xsave", self.xsave),
+            ("avx", self.avx),
+            ("avx2", self.avx2),
+            ("rdrand", self.rdrand),
+            ("rdseed", self.rdseed),
+            ("tsc-deadline", self.tsc_deadline),
+            ("invariant-tsc", self.invariant_tsc),
+            ("x2apic", self.x2apic),
+        ]
+    }
+
+    /// Names of the features present.
+    pub fn present(&self) -> impl Iterator<Item = &'static str> {
+        self.list()
+            .into_iter()
+            .filter_map(|(name, present)| present.then_some(name))
+    }
+
+    /// Decode the feature registers of leaves 1, 7 and 0x8000_0007.
+    fn decode(leaf1: CpuidResult, leaf7: CpuidResult, power: CpuidResult) -> Self {
+        let ecx = |bit| leaf1.ecx & bit != 0;
+        let edx = |bit| leaf1.edx & bit != 0;
+        Self {
+            fxsr: edx(EDX_FXSR),
+            sse: edx(EDX_SSE),
+            sse2: edx(EDX_SSE2),
+            sse3: ecx(ECX_SSE3),
+            ssse3: ecx(ECX_SSSE3),
+            sse4_1: ecx(ECX_SSE4_1),
+            sse4_2: ecx(ECX_SSE4_2),
+            xsave: ecx(ECX_XSAVE),
+            avx: ecx(ECX_AVX),
+            avx2: leaf7.ebx & EBX_AVX2 != 0,
+            rdrand: ecx(ECX_RDRAND),
+            rdseed: leaf7.ebx & EBX_RDSEED != 0,
+            tsc_deadline: ecx(ECX_TSC_DEADLINE),
+            invariant_tsc: power.edx & EDX_INVARIANT_TSC != 0,
+            x2apic: ecx(ECX_X2APIC),
+        }
+    }
+}
+
+/// What the CPU reports about itself.
+#[derive(Debug, Clone, Copy)]
+pub struct CpuInfo {
+    vendor: [u8; 12],
+    brand: [u8; 48],
+    /// Display family (base family plus extended family).
+    pub family: u32,
+    /// Display model (including the extended model where it applies).
+    pub model: u32,
+    /// Stepping.
+    pub stepping: u32,
+    /// Optional features.
+    pub features: Features,
+}
+
+impl CpuInfo {
+    /// Vendor string, e.g. `GenuineIntel` or `AuthenticAMD`.
+    pub fn vendor(&self) -> &str {
+        text(&self.vendor)
+    }
+
+    /// Brand string, or an empty string if the CPU does not report one.
+    pub fn brand(&self) -> &str {
+        text(&self.brand).trim()
+    }
+}
+
+/// The bytes up to the first NUL, if they are valid UTF-8.
+fn text(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).unwrap_or("")
+}
+
+static INFO: Once<CpuInfo> = Once::new();
+
+/// The CPU's identification and features.
+pub fn info() -> &'static CpuInfo {
+    INFO.call_once(detect)
+}
+
+/// Shorthand for `info().features`.
+pub fn features() -> Features {
+    info().features
+}
+
+/// Bytes the XSAVE area needs for the components currently enabled in
+/// XCR0, or 0 without XSAVE.
+///
+/// Unlike `info()` this is read on every call, since XCR0 can change.
+pub fn xsave_size() -> u32 {
+    if !features().xsave {
+        return 0;
+    }
+    cpuid(LEAF_XSAVE).ebx
+}
+
+/// Run CPUID for `leaf`, subleaf 0.
+fn cpuid(leaf: u32) -> CpuidResult {
+    // SAFETY: CPUID is available on every x86_64 CPU and has no side
+    // effects; unsupported leaves return zeros or the highest leaf's data,
+    // which callers avoid by checking the maximum leaf first.
+    unsafe { __cpuid_count(leaf, 0) }
+}
+
+/// Decode (family, model, stepping) from leaf 1 EAX.
+pub fn decode_signature(eax: u32) -> (u32, u32, u32) {
+    let stepping = eax & 0x0F;
+    let base_model = (eax >> 4) & 0x0F;
+    let base_family = (eax >> 8) & 0x0F;
+    let ext_model = (eax >> 16) & 0x0F;
+    let ext_family = (eax >> 20) & 0xFF;
+
+    let family = if base_family == 0x0F {
+        base_family + ext_family
+    } else {
+        base_family
+    };
+    let model = if base_family == 0x06 || base_family == 0x0F {
+        ext_model << 4 | base_model
+    } else {
+        base_model
+    };
+    (family, model, stepping)
+}
+
+fn detect() -> CpuInfo {
+    let leaf0 = cpuid(LEAF_VENDOR);
+    let max_leaf = leaf0.eax;
+    let mut vendor = [0u8; 12];
+    vendor[0..4].copy_from_slice(&leaf0.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&leaf0.edx.to_le_bytes());
+    vendor[8..12].copy_from_slice(&leaf0.ecx.to_le_bytes());
+
+    let empty = CpuidResult {
+        eax: 0,
+        ebx: 0,
+        ecx: 0,
+        edx: 0,
+    };
+    let leaf1 = cpuid(LEAF_FEATURES);
+    let leaf7 = if max_leaf >= LEAF_EXTENDED_FEATURES {
+        cpuid(LEAF_EXTENDED_FEATURES)
+    } else {
+        empty
+    };
+
+    let max_extended = cpuid(LEAF_EXTENDED_MAX).eax;
+    let power = if max_extended >= LEAF_POWER {
+        cpuid(LEAF_POWER)
+    } else {
+        empty
+    };
+
+    let mut brand = [0u8; 48];
+    if max_extended >= LEAF_BRAND + 2 {
+        for (i, chunk) in brand.chunks_exact_mut(16).enumerate() {
+            let regs = cpuid(LEAF_BRAND + i as u32);
+            for (j, reg) in [regs.eax, regs.ebx, regs.ecx, regs.edx].iter().enumerate() {
+                chunk[j * 4..j * 4 + 4].copy_from_slice(&reg.to_le_bytes());
+            }
+        }
+    }
+
+    let (family, model, stepping) = decode_signature(leaf1.eax);
+    CpuInfo {
+        vendor,
+        brand,
+        family,
+        model,
+        stepping,
+        features: Features::decode(leaf1, leaf7, power),
+    }
+}
@@ -0,0 +1,26 @@
+//! QEMU `isa-debug-exit` device.
+//!
+//! Writing a value to the device's port ends QEMU with exit status
+//! `(value << 1) | 1`. QEMU must be started with
+//! `-device isa-debug-exit,iobase=0xf4,iosize=0x04`; without it the write
+//! is ignored.
+
+use x86_64::instructions::port::Port;
+
+/// I/O port the device is configured at.
+pub const ISA_DEBUG_EXIT_PORT: u16 = 0xf4;
+
+/// The `isa-debug-exit` device.
+pub struct IsaDebugExit;
+
+impl sovelma_hal::DebugExit for IsaDebugExit {
+    fn exit(&mut self, code: u32) {
+        let mut port: Port<u32> = Port::new(ISA_DEBUG_EXIT_PORT);
+        // SAFETY: Writing to the isa-debug-exit device port is safe when QEMU
+        // is configured with this device. It triggers a QEMU exit; on other
+        // machines nothing decodes the port.
+        unsafe {
+            port.write(code);
+        }
+    }
+}
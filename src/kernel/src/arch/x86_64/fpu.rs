@@ -0,0 +1,245 @@
+//! x87/SSE/AVX register state and lazy switching between tasks.
+//!
+//! The kernel itself is built for a soft-float target and never touches
+//! the FPU or vector registers, but code compiled with those features
+//! enabled (a WASM engine, crypto routines) may, and the executor switches
+//! tasks without saving them. This module sets up CR0/CR4/XCR0 so the
+//! registers can be used, and switches their contents lazily:
+//!
+//! - Each task owns an `FpuState` save area. Before the executor polls a
+//!   task it calls `switch_to`, which sets CR0.TS unless the registers
+//!   already hold that task's state.
+//! - The first FPU/SSE instruction the task executes with TS set raises
+//!   #NM. The handler saves the registers into the previous owner's area
+//!   and loads the current task's (or the clean initial state), then
+//!   clears TS so the instruction is retried.
+//!
+//! Tasks that never use floating point therefore cost nothing beyond the
+//! CR0 write. Interrupt handlers are soft-float too, so they need no save.
+
+use super::cpuid;
+use core::arch::asm;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+use spin::Once;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+
+/// Bytes reserved per save area: the standard-format XSAVE area for x87,
+/// SSE and AVX needs 832.
+pub const STATE_SIZE: usize = 1024;
+
+/// Saved register state of one task.
+#[repr(C, align(64))]
+pub struct FpuState {
+    area: [u8; STATE_SIZE],
+    /// Set once `area` holds state saved from the registers.
+    saved: bool,
+}
+
+impl FpuState {
+    /// Create an area with no saved state; the task starts from the clean
+    /// initial state on its first FPU instruction.
+    pub const fn new() -> Self {
+        Self {
+            area: [0; STATE_SIZE],
+            saved: false,
+        }
+    }
+
+    /// Check whether the area holds saved state.
+    pub fn is_saved(&self) -> bool {
+        self.saved
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        // The registers' owner is going away: nothing to save them into
+        let this = self as *mut FpuState;
+        let _ = OWNER.compare_exchange(this, ptr::null_mut(), Ordering::AcqRel, Ordering::Relaxed);
+        let _ =
+            CURRENT.compare_exchange(this, ptr::null_mut(), Ordering::AcqRel, Ordering::Relaxed);
+    }
+}
+
+/// Area whose state is in the registers; null if none (initial state).
+static OWNER: AtomicPtr<FpuState> = AtomicPtr::new(ptr::null_mut());
+
+/// Area of the task being polled; null outside tasks.
+static CURRENT: AtomicPtr<FpuState> = AtomicPtr::new(ptr::null_mut());
+
+/// Save with XSAVE (else FXSAVE).
+static USE_XSAVE: AtomicBool = AtomicBool::new(false);
+
+/// XCR0 components saved and restored.
+static XSAVE_MASK: AtomicU64 = AtomicU64::new(0);
+
+/// Register state right after `init`, loaded for tasks without their own.
+static INITIAL: Once<FpuState> = Once::new();
+
+/// Lazy switches performed by the #NM handler.
+static SWITCHES: AtomicU64 = AtomicU64::new(0);
+
+/// Enable the FPU, SSE and (if supported) XSAVE/AVX, and capture the clean
+/// initial state. Call once at boot, after the IDT is loaded.
+pub fn init() {
+    let features = cpuid::features();
+
+    // SAFETY: Clearing EM and setting MP/NE enables native FPU error
+    // reporting; the kernel does not rely on emulation.
+    unsafe {
+        Cr0::update(|cr0| {
+            cr0.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            cr0.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+    }
+
+    let mut cr4 = Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE;
+    if features.xsave {
+        cr4 |= Cr4Flags::OSXSAVE;
+    }
+    // SAFETY: FXSAVE/SSE are part of the x86_64 baseline and OSXSAVE is
+    // only set when CPUID reports XSAVE.
+    unsafe { Cr4::update(|flags| flags.insert(cr4)) };
+
+    if features.xsave {
+        let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+        if features.avx {
+            xcr0 |= XCr0Flags::AVX;
+        }
+        // SAFETY: OSXSAVE is set, and AVX is only enabled when supported.
+        unsafe { XCr0::write(xcr0) };
+        if cpuid::xsave_size() <= STATE_SIZE as u32 {
+            XSAVE_MASK.store(xcr0.bits(), Ordering::Relaxed);
+            USE_XSAVE.store(true, Ordering::Relaxed);
+        } else {
+            // Does not fit the save area: stay with x87/SSE
+            // SAFETY: As above, with fewer components.
+            unsafe { XCr0::write(XCr0Flags::X87 | XCr0Flags::SSE) };
+        }
+    }
+
+    // SAFETY: The FPU was enabled above; FNINIT only resets its state.
+    unsafe { asm!("fninit", options(nomem, nostack)) };
+    INITIAL.call_once(|| {
+        let mut state = FpuState::new();
+        // SAFETY: `state` is a valid, suitably aligned save area.
+        unsafe { save(&mut state) };
+        state
+    });
+}
+
+/// Name of the save mechanism in use.
+pub fn mode() -> &'static str {
+    if USE_XSAVE.load(Ordering::Relaxed) {
+        "xsave"
+    } else {
+        "fxsave"
+    }
+}
+
+/// Number of lazy switches performed so far.
+pub fn switches() -> u64 {
+    SWITCHES.load(Ordering::Relaxed)
+}
+
+/// Make `state` the current task's area (or none, outside tasks).
+///
+/// Called by the executor before and after polling a task. Sets CR0.TS so
+/// the next FPU instruction traps, unless the registers already hold
+/// `state`.
+pub fn switch_to(state: Option<&mut FpuState>) {
+    if INITIAL.get().is_none() {
+        return;
+    }
+    let current = state.map_or(ptr::null_mut(), |state| state as *mut FpuState);
+    CURRENT.store(current, Ordering::Release);
+    let owned = OWNER.load(Ordering::Acquire) == current;
+    // SAFETY: TS only controls when the #NM handler runs; the handler
+    // restores the right state before any FPU instruction completes.
+    unsafe {
+        Cr0::update(|cr0| cr0.set(Cr0Flags::TASK_SWITCHED, !owned));
+    }
+}
+
+/// Handle #NM: give the registers to the current task.
+///
+/// Runs in the faulting task's context with interrupts disabled.
+pub fn handle_device_not_available() {
+    // SAFETY: Clearing TS lets the save/restore below (and the retried
+    // instruction) execute.
+    unsafe { asm!("clts", options(nomem, nostack)) };
+
+    let current = CURRENT.load(Ordering::Acquire);
+    let owner = OWNER.load(Ordering::Acquire);
+    if owner == current {
+        return;
+    }
+    // SAFETY: Non-null areas belong to live tasks: `FpuState::drop` clears
+    // both pointers before the area is freed, and the executor does not
+    // touch the area while the task is being polled.
+    unsafe {
+        if let Some(owner) = owner.as_mut() {
+            save(owner);
+            owner.saved = true;
+        }
+        match current.as_ref() {
+            Some(state) if state.saved => restore(state),
+            _ => {
+                if let Some(initial) = INITIAL.get() {
+                    restore(initial);
+                }
+            }
+        }
+    }
+    OWNER.store(current, Ordering::Release);
+    SWITCHES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Save the registers into `state`.
+///
+/// # Safety
+///
+/// The FPU must be enabled (`init`) and CR0.TS clear.
+unsafe fn save(state: &mut FpuState) {
+    let area = state.area.as_mut_ptr();
+    if USE_XSAVE.load(Ordering::Relaxed) {
+        let mask = XSAVE_MASK.load(Ordering::Relaxed);
+        // SAFETY: `area` is 64-byte aligned and large enough for the
+        // enabled components (checked in `init`).
+        unsafe {
+            asm!("xsave64 [{}]", in(reg) area, in("eax") mask as u32,
+                 in("edx") (mask >> 32) as u32, options(nostack));
+        }
+    } else {
+        // SAFETY: `area` is 16-byte aligned and holds 512 bytes.
+        unsafe { asm!("fxsave64 [{}]", in(reg) area, options(nostack)) };
+    }
+}
+
+/// Load the registers from `state`.
+///
+/// # Safety
+///
+/// As for `save`; `state` must hold state saved by `save`.
+unsafe fn restore(state: &FpuState) {
+    let area = state.area.as_ptr();
+    if USE_XSAVE.load(Ordering::Relaxed) {
+        let mask = XSAVE_MASK.load(Ordering::Relaxed);
+        // SAFETY: `area` was written by XSAVE with the same mask.
+        unsafe {
+            asm!("xrstor64 [{}]", in(reg) area, in("eax") mask as u32,
+                 in("edx") (mask >> 32) as u32, options(nostack, readonly));
+        }
+    } else {
+        // SAFETY: `area` was written by FXSAVE.
+        unsafe { asm!("fxrstor64 [{}]", in(reg) area, options(nostack, readonly)) };
+    }
+}
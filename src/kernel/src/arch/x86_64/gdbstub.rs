@@ -0,0 +1,703 @@
+//! GDB remote serial protocol stub.
+//!
+//! Lets `gdb` attach to a running kernel over COM2 without QEMU's `-s`
+//! gdbserver. Enabled by the `gdb` kernel command-line flag; the kernel
+//! then stops early in boot until the debugger connects. While running,
+//! Ctrl-C in gdb (a 0x03 byte on the line) is noticed by the timer
+//! interrupt, which single-steps the interrupted code into the stub.
+//!
+//! Supported: register read/write (`g`/`G`), memory read/write (`m`/`M`),
+//! software breakpoints (`Z0`/`z0`, patched in as `int3`), continue (`c`)
+//! and single-step (`s`, via RFLAGS.TF).
+//!
+//! The #BP and #DB vectors go through an assembly trampoline that saves
+//! every general-purpose register, since the `x86-interrupt` ABI only
+//! exposes the hardware frame.
+
+use crate::arch::x86_64::serial::{self, COM2_PORT};
+use crate::boot::cmdline;
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+use uart_16550::SerialPort;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3};
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+use x86_64::VirtAddr;
+
+/// Vector number of the debug exception (#DB).
+const VECTOR_DEBUG: u64 = 1;
+/// Vector number of the breakpoint exception (#BP).
+const VECTOR_BREAKPOINT: u64 = 3;
+
+/// RFLAGS trap flag (single-step).
+const RFLAGS_TF: u64 = 1 << 8;
+
+/// `int3` opcode.
+const INT3: u8 = 0xCC;
+
+/// GDB's interrupt request byte (Ctrl-C).
+const BREAK_BYTE: u8 = 0x03;
+
+/// Largest packet payload exchanged with gdb.
+const PACKET_SIZE: usize = 1024;
+
+/// Maximum number of software breakpoints.
+const MAX_BREAKPOINTS: usize = 32;
+
+/// Stop reply: SIGTRAP.
+const STOP_REPLY: &[u8] = b"S05";
+
+/// Size of a page-table walk's smallest page.
+const PAGE_SIZE: u64 = 4096;
+
+/// Registers saved by the trampoline, followed by the hardware frame.
+///
+/// The layout must match the push order in the assembly below.
+#[repr(C)]
+#[derive(Debug)]
+pub struct TrapFrame {
+    /// Saved `rax`.
+    pub rax: u64,
+    /// Saved `rbx`.
+    pub rbx: u64,
+    /// Saved `rcx`.
+    pub rcx: u64,
+    /// Saved `rdx`.
+    pub rdx: u64,
+    /// Saved `rsi`.
+    pub rsi: u64,
+    /// Saved `rdi`.
+    pub rdi: u64,
+    /// Saved `rbp`.
+    pub rbp: u64,
+    /// Saved `r8`.
+    pub r8: u64,
+    /// Saved `r9`.
+    pub r9: u64,
+    /// Saved `r10`.
+    pub r10: u64,
+    /// Saved `r11`.
+    pub r11: u64,
+    /// Saved `r12`.
+    pub r12: u64,
+    /// Saved `r13`.
+    pub r13: u64,
+    /// Saved `r14`.
+    pub r14: u64,
+    /// Saved `r15`.
+    pub r15: u64,
+    /// Exception vector pushed by the entry stub.
+    pub vector: u64,
+    /// Interrupted instruction pointer.
+    pub rip: u64,
+    /// Interrupted code segment.
+    pub cs: u64,
+    /// Interrupted flags.
+    pub rflags: u64,
+    /// Interrupted stack pointer.
+    pub rsp: u64,
+    /// Interrupted stack segment.
+    pub ss: u64,
+}
+
+global_asm!(
+    ".global gdb_breakpoint_entry",
+    "gdb_breakpoint_entry:",
+    "    push 3",
+    "    jmp gdb_trap_common",
+    ".global gdb_debug_entry",
+    "gdb_debug_entry:",
+    "    push 1",
+    "    jmp gdb_trap_common",
+    "gdb_trap_common:",
+    "    push r15",
+    "    push r14",
+    "    push r13",
+    "    push r12",
+    "    push r11",
+    "    push r10",
+    "    push r9",
+    "    push r8",
+    "    push rbp",
+    "    push rdi",
+    "    push rsi",
+    "    push rdx",
+    "    push rcx",
+    "    push rbx",
+    "    push rax",
+    "    mov rdi, rsp",
+    // 5 hardware words + vector + 15 registers leave rsp 8 bytes off
+    // the 16-byte alignment the C ABI expects
+    "    sub rsp, 8",
+    "    call {handler}",
+    "    add rsp, 8",
+    "    pop rax",
+    "    pop rbx",
+    "    pop rcx",
+    "    pop rdx",
+    "    pop rsi",
+    "    pop rdi",
+    "    pop rbp",
+    "    pop r8",
+    "    pop r9",
+    "    pop r10",
+    "    pop r11",
+    "    pop r12",
+    "    pop r13",
+    "    pop r14",
+    "    pop r15",
+    "    add rsp, 8",
+    "    iretq",
+    handler = sym gdb_trap,
+);
+
+extern "C" {
+    fn gdb_breakpoint_entry();
+    fn gdb_debug_entry();
+}
+
+/// Address of the #BP entry trampoline for the IDT.
+pub fn breakpoint_entry() -> VirtAddr {
+    VirtAddr::new(gdb_breakpoint_entry as unsafe extern "C" fn() as usize as u64)
+}
+
+/// Address of the #DB entry trampoline for the IDT.
+pub fn debug_entry() -> VirtAddr {
+    VirtAddr::new(gdb_debug_entry as unsafe extern "C" fn() as usize as u64)
+}
+
+/// Set once `init` has opened the debug port.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// A `$` was consumed by `poll_break`; the next packet starts mid-frame.
+static PENDING_PACKET: AtomicBool = AtomicBool::new(false);
+
+/// Offset at which physical memory is mapped, for page-table walks.
+static PHYS_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Stub state, only touched from the trap handler (interrupts disabled).
+static STUB: Mutex<Stub> = Mutex::new(Stub::new());
+
+/// Check whether the kernel command line asks for the stub.
+///
+/// The stub shares COM2 with SLIP, so `net=slip` wins.
+pub fn requested() -> bool {
+    cmdline::get("gdb").is_some() && cmdline::get("net") != Some("slip")
+}
+
+/// Open the debug port and stop until gdb attaches.
+///
+/// The IDT must already route #BP and #DB through the trampolines, which
+/// `interrupts::init_idt` does when `requested()` is true.
+pub fn init(physical_memory_offset: VirtAddr) {
+    PHYS_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+    // SAFETY: COM2 is reserved for the stub whenever it is requested
+    // (SLIP is excluded by `requested`).
+    let port = unsafe { serial::init_polled(COM2_PORT) };
+    STUB.lock().port = Some(port);
+    ACTIVE.store(true, Ordering::SeqCst);
+
+    x86_64::instructions::interrupts::int3();
+}
+
+/// Check the debug port for a break request from gdb.
+///
+/// Called from the timer interrupt. On Ctrl-C (or a new packet) the trap
+/// flag is set in the interrupted frame, so the next instruction of the
+/// interrupted code traps into the stub.
+pub fn poll_break(stack_frame: &mut InterruptStackFrame) {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return;
+    }
+    // SAFETY: COM2 belongs to the stub once it is active; reading the
+    // line status and data registers has no other side effects.
+    let mut port = unsafe { SerialPort::new(COM2_PORT) };
+    let Ok(byte) = port.try_receive() else {
+        return;
+    };
+    if byte != BREAK_BYTE && byte != b'$' {
+        return;
+    }
+    PENDING_PACKET.store(byte == b'$', Ordering::Relaxed);
+    // SAFETY: Setting TF only makes the interrupted code raise #DB after
+    // its next instruction, which the stub handles.
+    unsafe {
+        stack_frame
+            .as_mut()
+            .update(|frame| frame.cpu_flags |= RFLAGS_TF);
+    }
+}
+
+/// Common handler for #BP and #DB, called by the trampoline.
+extern "C" fn gdb_trap(frame: &mut TrapFrame) {
+    if !ACTIVE.load(Ordering::SeqCst) {
+        if frame.vector == VECTOR_BREAKPOINT {
+            crate::serial_println!("EXCEPTION: BREAKPOINT at {:#x}", frame.rip);
+        }
+        return;
+    }
+    STUB.lock().enter(frame);
+}
+
+/// How to leave the stub.
+enum Resume {
+    /// Keep processing packets.
+    Stay,
+    /// Return to the kernel.
+    Continue,
+    /// Return with single-stepping enabled.
+    Step,
+}
+
+/// A patched-in software breakpoint.
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    addr: u64,
+    saved: u8,
+}
+
+/// Outgoing packet buffer.
+struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn push(&mut self, byte: u8) {
+        if self.len < PACKET_SIZE {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn push_str(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(byte);
+        }
+    }
+
+    fn push_hex(&mut self, byte: u8) {
+        self.push(hex_digit(byte >> 4));
+        self.push(hex_digit(byte & 0xF));
+    }
+
+    /// Append `value` as `width` little-endian bytes, gdb's register format.
+    fn push_le(&mut self, value: u64, width: usize) {
+        for byte in &value.to_le_bytes()[..width] {
+            self.push_hex(*byte);
+        }
+    }
+}
+
+/// Debugger connection state.
+struct Stub {
+    port: Option<SerialPort>,
+    attached: bool,
+    packet: [u8; PACKET_SIZE],
+    reply: Reply,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+}
+
+impl Stub {
+    const fn new() -> Self {
+        Self {
+            port: None,
+            attached: false,
+            packet: [0; PACKET_SIZE],
+            reply: Reply {
+                buf: [0; PACKET_SIZE],
+                len: 0,
+            },
+            breakpoints: [None; MAX_BREAKPOINTS],
+        }
+    }
+
+    /// Talk to gdb until it resumes the kernel.
+    fn enter(&mut self, frame: &mut TrapFrame) {
+        // int3 leaves RIP after the opcode; gdb expects the breakpoint address
+        if frame.vector == VECTOR_BREAKPOINT && self.breakpoint_at(frame.rip - 1).is_some() {
+            frame.rip -= 1;
+        }
+        if frame.vector == VECTOR_DEBUG {
+            frame.rflags &= !RFLAGS_TF;
+        }
+
+        if self.attached {
+            send(&mut self.port, STOP_REPLY);
+        }
+
+        loop {
+            let len = self.receive();
+            self.reply.len = 0;
+            match self.handle(len, frame) {
+                Resume::Stay => {
+                    send(&mut self.port, &self.reply.buf[..self.reply.len]);
+                }
+                Resume::Continue => return,
+                Resume::Step => {
+                    frame.rflags |= RFLAGS_TF;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Execute one packet, filling `reply`.
+    fn handle(&mut self, len: usize, frame: &mut TrapFrame) -> Resume {
+        let packet = &self.packet[..len];
+        let Some((&command, args)) = packet.split_first() else {
+            return Resume::Stay;
+        };
+        let reply = &mut self.reply;
+
+        match command {
+            b'?' => reply.push_str(STOP_REPLY),
+            b'g' => write_registers(reply, frame),
+            b'G' => {
+                if read_registers(args, frame) {
+                    reply.push_str(b"OK");
+                } else {
+                    reply.push_str(b"E01");
+                }
+            }
+            b'm' => {
+                let Some((addr, count)) = parse_addr_len(args) else {
+                    reply.push_str(b"E01");
+                    return Resume::Stay;
+                };
+                let count = count.min(((PACKET_SIZE - 1) / 2) as u64);
+                for offset in 0..count {
+                    let Some(byte) = read_byte(addr.wrapping_add(offset)) else {
+                        if offset == 0 {
+                            reply.push_str(b"E14");
+                        }
+                        break;
+                    };
+                    reply.push_hex(byte);
+                }
+            }
+            b'M' => {
+                let ok = split_once(args, b':').and_then(|(range, data)| {
+                    let (addr, count) = parse_addr_len(range)?;
+                    if data.len() as u64 != count * 2 {
+                        return None;
+                    }
+                    for (i, pair) in data.chunks(2).enumerate() {
+                        let value = parse_hex(pair)? as u8;
+                        write_byte(addr.wrapping_add(i as u64), value)?;
+                    }
+                    Some(())
+                });
+                reply.push_str(if ok.is_some() { b"OK" } else { b"E14" });
+            }
+            b'Z' | b'z' => {
+                // Only software breakpoints (type 0) are supported
+                let Some(rest) = args.strip_prefix(b"0,") else {
+                    return Resume::Stay;
+                };
+                let Some(addr) = split_once(rest, b',').and_then(|(a, _)| parse_hex(a)) else {
+                    reply.push_str(b"E01");
+                    return Resume::Stay;
+                };
+                let ok = if command == b'Z' {
+                    insert_breakpoint(&mut self.breakpoints, addr)
+                } else {
+                    remove_breakpoint(&mut self.breakpoints, addr)
+                };
+                reply.push_str(if ok { b"OK" } else { b"E0E" });
+            }
+            b'c' | b's' => {
+                if let Some(addr) = parse_hex(args) {
+                    frame.rip = addr;
+                }
+                return if command == b'c' {
+                    Resume::Continue
+                } else {
+                    Resume::Step
+                };
+            }
+            b'D' | b'k' => {
+                for slot in self.breakpoints.iter_mut() {
+                    if let Some(bp) = slot.take() {
+                        write_byte(bp.addr, bp.saved);
+                    }
+                }
+                self.attached = false;
+                if command == b'D' {
+                    send(&mut self.port, b"OK");
+                }
+                return Resume::Continue;
+            }
+            b'H' => reply.push_str(b"OK"),
+            b'q' => {
+                if args.starts_with(b"Supported") {
+                    reply.push_str(b"PacketSize=400");
+                } else if args == b"Attached" {
+                    reply.push_str(b"1");
+                } else if args == b"C" {
+                    reply.push_str(b"QC1");
+                } else if args == b"fThreadInfo" {
+                    reply.push_str(b"m1");
+                } else if args == b"sThreadInfo" {
+                    reply.push_str(b"l");
+                }
+            }
+            // Unsupported commands get an empty reply
+            _ => {}
+        }
+        Resume::Stay
+    }
+
+    fn breakpoint_at(&self, addr: u64) -> Option<&Breakpoint> {
+        self.breakpoints.iter().flatten().find(|bp| bp.addr == addr)
+    }
+
+    /// Receive one packet into `self.packet`, acknowledging it.
+    fn receive(&mut self) -> usize {
+        loop {
+            if !PENDING_PACKET.swap(false, Ordering::Relaxed) {
+                while self.read() != b'$' {}
+            }
+
+            let mut len = 0;
+            let mut sum: u8 = 0;
+            loop {
+                let byte = self.read();
+                if byte == b'#' {
+                    break;
+                }
+                if len < PACKET_SIZE {
+                    self.packet[len] = byte;
+                    len += 1;
+                }
+                sum = sum.wrapping_add(byte);
+            }
+            let checksum = [self.read(), self.read()];
+
+            if parse_hex(&checksum) == Some(u64::from(sum)) {
+                self.write(b'+');
+                self.attached = true;
+                return len;
+            }
+            self.write(b'-');
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        read_port(&mut self.port)
+    }
+
+    fn write(&mut self, byte: u8) {
+        write_port(&mut self.port, byte);
+    }
+}
+
+/// Send one packet, retransmitting until gdb acknowledges it.
+fn send(port: &mut Option<SerialPort>, data: &[u8]) {
+    let sum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    loop {
+        write_port(port, b'$');
+        for &byte in data {
+            write_port(port, byte);
+        }
+        write_port(port, b'#');
+        write_port(port, hex_digit(sum >> 4));
+        write_port(port, hex_digit(sum & 0xF));
+
+        if read_port(port) != b'-' {
+            return;
+        }
+    }
+}
+
+fn read_port(port: &mut Option<SerialPort>) -> u8 {
+    match port.as_mut() {
+        Some(port) => port.receive(),
+        None => b'#',
+    }
+}
+
+fn write_port(port: &mut Option<SerialPort>, byte: u8) {
+    if let Some(port) = port.as_mut() {
+        port.send_raw(byte);
+    }
+}
+
+/// Append the registers in gdb's amd64 `g` packet order.
+fn write_registers(reply: &mut Reply, frame: &TrapFrame) {
+    for value in [
+        frame.rax, frame.rbx, frame.rcx, frame.rdx, frame.rsi, frame.rdi, frame.rbp, frame.rsp,
+        frame.r8, frame.r9, frame.r10, frame.r11, frame.r12, frame.r13, frame.r14, frame.r15,
+        frame.rip,
+    ] {
+        reply.push_le(value, 8);
+    }
+    // eflags, cs, ss, then ds/es/fs/gs (flat, reported as zero)
+    for value in [frame.rflags, frame.cs, frame.ss, 0, 0, 0, 0] {
+        reply.push_le(value, 4);
+    }
+}
+
+/// Load registers from a `G` packet. Segment registers are ignored.
+fn read_registers(data: &[u8], frame: &mut TrapFrame) -> bool {
+    let mut values = [0u64; 18];
+    for (i, value) in values.iter_mut().enumerate() {
+        let width = if i < 17 { 16 } else { 8 };
+        let start = if i < 17 { i * 16 } else { 17 * 16 };
+        let Some(field) = data.get(start..start + width) else {
+            return false;
+        };
+        let mut bytes = [0u8; 8];
+        for (j, pair) in field.chunks(2).enumerate() {
+            let Some(byte) = parse_hex(pair) else {
+                return false;
+            };
+            bytes[j] = byte as u8;
+        }
+        *value = u64::from_le_bytes(bytes);
+    }
+
+    let [rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8, r9, r10, r11, r12, r13, r14, r15, rip, rflags] =
+        values;
+    frame.rax = rax;
+    frame.rbx = rbx;
+    frame.rcx = rcx;
+    frame.rdx = rdx;
+    frame.rsi = rsi;
+    frame.rdi = rdi;
+    frame.rbp = rbp;
+    frame.rsp = rsp;
+    frame.r8 = r8;
+    frame.r9 = r9;
+    frame.r10 = r10;
+    frame.r11 = r11;
+    frame.r12 = r12;
+    frame.r13 = r13;
+    frame.r14 = r14;
+    frame.r15 = r15;
+    frame.rip = rip;
+    frame.rflags = rflags;
+    true
+}
+
+/// Patch an `int3` in at `addr`, remembering the original byte.
+fn insert_breakpoint(breakpoints: &mut [Option<Breakpoint>], addr: u64) -> bool {
+    if breakpoints.iter().flatten().any(|bp| bp.addr == addr) {
+        return true;
+    }
+    let Some(slot) = breakpoints.iter_mut().find(|slot| slot.is_none()) else {
+        return false;
+    };
+    let Some(saved) = read_byte(addr) else {
+        return false;
+    };
+    if write_byte(addr, INT3).is_none() {
+        return false;
+    }
+    *slot = Some(Breakpoint { addr, saved });
+    true
+}
+
+/// Restore the original byte under a breakpoint.
+fn remove_breakpoint(breakpoints: &mut [Option<Breakpoint>], addr: u64) -> bool {
+    let Some(slot) = breakpoints
+        .iter_mut()
+        .find(|slot| slot.map(|bp| bp.addr) == Some(addr))
+    else {
+        return false;
+    };
+    if let Some(bp) = slot.take() {
+        write_byte(bp.addr, bp.saved);
+    }
+    true
+}
+
+/// Read a byte of kernel memory, failing on unmapped addresses.
+fn read_byte(addr: u64) -> Option<u8> {
+    if !is_mapped(addr) {
+        return None;
+    }
+    // SAFETY: The page containing `addr` is present in the active page
+    // table, so the read cannot fault.
+    Some(unsafe { core::ptr::read_volatile(addr as *const u8) })
+}
+
+/// Write a byte of kernel memory, including read-only text.
+fn write_byte(addr: u64, value: u8) -> Option<()> {
+    if !is_mapped(addr) {
+        return None;
+    }
+    let cr0 = Cr0::read();
+    // SAFETY: The page is present. Clearing CR0.WP lets ring 0 write to
+    // read-only pages (to patch code); interrupts are disabled in the trap
+    // handler, so nothing else runs before WP is restored.
+    unsafe {
+        Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+        core::ptr::write_volatile(addr as *mut u8, value);
+        Cr0::write(cr0);
+    }
+    Some(())
+}
+
+/// Walk the active page table to check that `addr` is mapped.
+fn is_mapped(addr: u64) -> bool {
+    let offset = PHYS_OFFSET.load(Ordering::Relaxed);
+    let Ok(virt) = VirtAddr::try_new(addr) else {
+        return false;
+    };
+    if offset == 0 {
+        return false;
+    }
+
+    let indexes = [
+        virt.p4_index(),
+        virt.p3_index(),
+        virt.p2_index(),
+        virt.p1_index(),
+    ];
+    let (frame, _) = Cr3::read();
+    let mut table_phys = frame.start_address().as_u64();
+    for (level, index) in indexes.into_iter().enumerate() {
+        // SAFETY: All physical memory is mapped at `offset`, and page-table
+        // frames hold valid `PageTable`s; the table is only read.
+        let table = unsafe { &*((offset + table_phys) as *const PageTable) };
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return false;
+        }
+        // 1 GiB and 2 MiB pages end the walk early
+        if (level == 1 || level == 2) && entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return true;
+        }
+        table_phys = entry.addr().as_u64() & !(PAGE_SIZE - 1);
+    }
+    true
+}
+
+/// Split `data` at the first `separator`.
+fn split_once(data: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let pos = data.iter().position(|b| *b == separator)?;
+    Some((&data[..pos], &data[pos + 1..]))
+}
+
+/// Parse `addr,length`.
+fn parse_addr_len(data: &[u8]) -> Option<(u64, u64)> {
+    let (addr, len) = split_once(data, b',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+/// Parse a big-endian hex number (at most 16 digits).
+fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |value, digit| {
+        let nibble = (*digit as char).to_digit(16)?;
+        Some(value << 4 | u64::from(nibble))
+    })
+}
+
+fn hex_digit(nibble: u8) -> u8 {
+    b"0123456789abcdef"[usize::from(nibble & 0xF)]
+}
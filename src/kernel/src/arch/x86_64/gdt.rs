@@ -2,6 +2,10 @@
 //!
 //! The GDT is used to define memory segments and their permissions.
 //! The TSS is used for hardware-level task switching and interrupt stack tables.
+//!
+//! Besides the kernel segments the GDT holds ring 3 code and data segments
+//! for `usermode`. Their order (kernel code, kernel data, user data, user
+//! code) is the one SYSCALL/SYSRET require.
 
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
@@ -11,6 +15,10 @@ use x86_64::VirtAddr;
 /// The index of the Double Fault stack in the Interrupt Stack Table (IST).
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+/// Size of the stack the CPU switches to (TSS RSP0) when an interrupt or
+/// exception arrives in ring 3.
+const PRIVILEGE_STACK_SIZE: usize = 4096 * 5;
+
 lazy_static! {
     /// The Task State Segment (TSS) used for interrupt stack switching.
     static ref TSS: TaskStateSegment = {
@@ -25,6 +33,13 @@ lazy_static! {
             let stack_start = unsafe { VirtAddr::from_ptr(&raw const STACK as *const u8) };
             stack_start + STACK_SIZE
         };
+        tss.privilege_stack_table[0] = {
+            static mut STACK: [u8; PRIVILEGE_STACK_SIZE] = [0; PRIVILEGE_STACK_SIZE];
+
+            // SAFETY: As above; only the address is taken.
+            let stack_start = unsafe { VirtAddr::from_ptr(&raw const STACK as *const u8) };
+            stack_start + PRIVILEGE_STACK_SIZE
+        };
         tss
     };
 }
@@ -34,21 +49,49 @@ lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+        let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
         let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (gdt, Selectors { code_selector, tss_selector })
+        (gdt, Selectors {
+            code_selector,
+            data_selector,
+            user_code_selector,
+            user_data_selector,
+            tss_selector,
+        })
     };
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
+/// Segment selectors of the GDT entries.
+#[derive(Debug, Clone, Copy)]
+pub struct Selectors {
+    /// Kernel (ring 0) code segment.
+    pub code_selector: SegmentSelector,
+    /// Kernel (ring 0) data/stack segment.
+    pub data_selector: SegmentSelector,
+    /// User (ring 3) code segment, RPL 3.
+    pub user_code_selector: SegmentSelector,
+    /// User (ring 3) data/stack segment, RPL 3.
+    pub user_data_selector: SegmentSelector,
     tss_selector: SegmentSelector,
 }
 
+/// The GDT's segment selectors.
+pub fn selectors() -> Selectors {
+    GDT.1
+}
+
+/// Top of the stack interrupts from ring 3 run on (TSS RSP0).
+pub fn privilege_stack_top() -> VirtAddr {
+    TSS.privilege_stack_table[0]
+}
+
 /// Initializes the GDT and TSS.
 ///
 /// This function loads the GDT, sets up the code segment, and loads the TSS.
 pub fn init() {
-    use x86_64::instructions::segmentation::{Segment, CS};
+    use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
     use x86_64::instructions::tables::load_tss;
 
     GDT.0.load();
@@ -57,6 +100,9 @@ pub fn init() {
     // kernel initialization before any user code runs.
     unsafe {
         CS::set_reg(GDT.1.code_selector);
+        SS::set_reg(GDT.1.data_selector);
+        DS::set_reg(GDT.1.data_selector);
+        ES::set_reg(GDT.1.data_selector);
         load_tss(GDT.1.tss_selector);
     }
 }
@@ -1,16 +1,28 @@
 //! Interrupt Descriptor Table (IDT) and exception handlers for x86_64.
 
-use crate::arch::x86_64::gdt;
-use crate::arch::x86_64::pic::{InterruptIndex, PICS};
-use crate::println;
+use crate::arch::x86_64::pic::{InterruptIndex, PICS, PIC_1_OFFSET};
+use crate::arch::x86_64::{fpu, gdbstub, gdt, pit, ps2};
+use crate::irq_log;
+use crate::klog::irq;
+use crate::trace::{self, Category};
 use lazy_static::lazy_static;
+use log::Level;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 
 lazy_static! {
     /// The Interrupt Descriptor Table (IDT).
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        if gdbstub::requested() {
+            // SAFETY: The trampolines save all registers, call the stub and
+            // return with iretq, forming valid handlers for #BP and #DB.
+            unsafe {
+                idt.breakpoint.set_handler_addr(gdbstub::breakpoint_entry());
+                idt.debug.set_handler_addr(gdbstub::debug_entry());
+            }
+        } else {
+            idt.breakpoint.set_handler_fn(breakpoint_handler);
+        }
         unsafe {
             idt.double_fault.set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
@@ -18,42 +30,54 @@ lazy_static! {
         idt.page_fault.set_handler_fn(page_fault_handler);
         idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
         idt.divide_error.set_handler_fn(divide_error_handler);
+        idt.device_not_available.set_handler_fn(device_not_available_handler);
+        idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
 
         // Hardware interrupts
         idt[InterruptIndex::Timer.as_usize()]
             .set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()]
             .set_handler_fn(keyboard_interrupt_handler);
+        // Must cover pic::PCI_IRQS
+        idt[usize::from(PIC_1_OFFSET + 5)].set_handler_fn(pci_interrupt_handler::<5>);
+        idt[usize::from(PIC_1_OFFSET + 9)].set_handler_fn(pci_interrupt_handler::<9>);
+        idt[usize::from(PIC_1_OFFSET + 10)].set_handler_fn(pci_interrupt_handler::<10>);
+        idt[usize::from(PIC_1_OFFSET + 11)].set_handler_fn(pci_interrupt_handler::<11>);
 
         idt
     };
 }
 
-/// Initializes the IDT and enables hardware interrupts.
+/// Initializes the IDT, programs the timer and enables hardware interrupts.
 pub fn init_idt() {
     IDT.load();
     unsafe {
         PICS.lock().initialize();
     }
+    pit::init(crate::time::TICK_HZ);
     x86_64::instructions::interrupts::enable();
 }
 
-/// Handler for the timer interrupt.
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    // print!("."); // Heartbeat
+/// Handler for the timer interrupt: the kernel clock tick.
+extern "x86-interrupt" fn timer_interrupt_handler(mut stack_frame: InterruptStackFrame) {
+    let _span = trace::span(Category::Interrupt, "timer", 0);
+    crate::time::tick();
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
+    gdbstub::poll_break(&mut stack_frame);
 }
 
 /// Handler for the keyboard interrupt.
+///
+/// Answers to keyboard commands are consumed by the PS/2 driver; only
+/// scancodes are queued.
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use x86_64::instructions::port::Port;
-
-    let mut port = Port::new(0x60);
-    let scancode: u8 = unsafe { port.read() };
-    crate::task::keyboard::add_scancode(scancode);
+    let _span = trace::span(Category::Interrupt, "keyboard", 1);
+    if let Some(scancode) = ps2::receive() {
+        crate::task::keyboard::add_scancode(scancode);
+    }
 
     unsafe {
         PICS.lock()
@@ -61,11 +85,33 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     }
 }
 
+/// Handler for PCI IRQ line `IRQ`.
+///
+/// The e1000 is the only PCI device that raises interrupts.
+extern "x86-interrupt" fn pci_interrupt_handler<const IRQ: u8>(_stack_frame: InterruptStackFrame) {
+    let _span = trace::span(Category::Interrupt, "pci", u64::from(IRQ));
+    #[cfg(feature = "net")]
+    crate::net::e1000::handle_interrupt();
+    crate::driver::dispatch_irq(IRQ);
+
+    // SAFETY: This is the handler for vector PIC_1_OFFSET + IRQ, so that
+    // interrupt is in service.
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + IRQ);
+    }
+}
+
 /// Handler for the breakpoint exception (INT3).
 ///
-/// Used for debugging - logs to serial only to keep VGA clean during boot.
+/// Used for debugging - logged (to serial) rather than printed to keep VGA
+/// clean during boot.
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    crate::serial_println!("EXCEPTION: BREAKPOINT at {:#x}", stack_frame.instruction_pointer.as_u64());
+    irq_log!(
+        Level::Info,
+        "exception",
+        "BREAKPOINT at {:#x}",
+        stack_frame.instruction_pointer.as_u64()
+    );
 }
 
 /// Handler for the double fault exception.
@@ -83,11 +129,15 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::control::Cr2;
 
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    crate::arch::x86_64::halt_loop();
+    irq_log!(Level::Error, "exception", "PAGE FAULT");
+    irq_log!(
+        Level::Error,
+        "exception",
+        "Accessed Address: {:?}",
+        Cr2::read()
+    );
+    irq_log!(Level::Error, "exception", "Error Code: {:?}", error_code);
+    fatal(&stack_frame);
 }
 
 /// Handler for the general protection fault exception.
@@ -95,14 +145,50 @@ extern "x86-interrupt" fn general_protection_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
-    println!("EXCEPTION: GENERAL PROTECTION FAULT");
-    println!("Error Code: {:#x}", error_code);
-    println!("{:#?}", stack_frame);
-    crate::arch::x86_64::halt_loop();
+    irq_log!(Level::Error, "exception", "GENERAL PROTECTION FAULT");
+    irq_log!(Level::Error, "exception", "Error Code: {:#x}", error_code);
+    fatal(&stack_frame);
 }
 
 /// Handler for the divide error exception.
 extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: DIVIDE ERROR\n{:#?}", stack_frame);
+    irq_log!(Level::Error, "exception", "DIVIDE ERROR");
+    fatal(&stack_frame);
+}
+
+/// Handler for the device-not-available exception (#NM).
+///
+/// Raised by the first FPU/SSE instruction after a task switch; loads the
+/// task's register state (see `fpu`) and retries the instruction.
+extern "x86-interrupt" fn device_not_available_handler(_stack_frame: InterruptStackFrame) {
+    fpu::handle_device_not_available();
+}
+
+/// Handler for the SIMD floating-point exception (#XM).
+extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    irq_log!(Level::Error, "exception", "SIMD FLOATING-POINT EXCEPTION");
+    fatal(&stack_frame);
+}
+
+/// Report the faulting context and halt.
+///
+/// The logging task never runs again, so the records are flushed here.
+fn fatal(stack_frame: &InterruptStackFrame) -> ! {
+    irq_log!(
+        Level::Error,
+        "exception",
+        "RIP {:#x} CS {:#x} RFLAGS {:#x}",
+        stack_frame.instruction_pointer.as_u64(),
+        stack_frame.code_segment,
+        stack_frame.cpu_flags
+    );
+    irq_log!(
+        Level::Error,
+        "exception",
+        "RSP {:#x} SS {:#x}",
+        stack_frame.stack_pointer.as_u64(),
+        stack_frame.stack_segment
+    );
+    irq::flush_fatal();
     crate::arch::x86_64::halt_loop();
 }
@@ -1,18 +1,61 @@
 //! x86_64 architecture support.
 //!
-//! Provides VGA text mode output, serial port communication, and PCI access
-//! for x86_64 platforms.
+//! Provides VGA text mode output, serial port communication, the PS/2
+//! keyboard controller, CPU feature detection, FPU/SSE state, ring 3
+//! execution, PCI access, and QEMU's debug exit device for x86_64
+//! platforms.
 
+pub mod cp437;
+pub mod cpuid;
+pub mod debug_exit;
+pub mod fpu;
+pub mod gdbstub;
 pub mod gdt;
 pub mod interrupts;
 pub mod pci;
 pub mod pic;
+pub mod pit;
+pub mod ps2;
 pub mod serial;
+pub mod usermode;
 pub mod vga;
 
 pub use serial::SERIAL;
 pub use vga::{Color, Writer, WRITER};
 
+/// Reads the time stamp counter.
+#[inline]
+pub fn rdtsc() -> u64 {
+    // SAFETY: RDTSC has no side effects and is available on every x86_64 CPU.
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Attempts RDRAND gives before reporting failure, as Intel recommends.
+const RDRAND_RETRIES: usize = 10;
+
+/// Reads a hardware random number, if the CPU has RDRAND.
+pub fn rdrand() -> Option<u64> {
+    if !cpuid::features().rdrand {
+        return None;
+    }
+    let mut value = 0;
+    for _ in 0..RDRAND_RETRIES {
+        // SAFETY: CPUID reported RDRAND, and `value` is a valid u64.
+        if unsafe { core::arch::x86_64::_rdrand64_step(&mut value) } == 1 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// A seed for non-cryptographic randomness (protocol IDs, port choice).
+///
+/// Uses RDRAND when available; otherwise mixes the time stamp counter,
+/// which at least differs between boots.
+pub fn random_seed() -> u64 {
+    rdrand().unwrap_or_else(|| rdtsc().wrapping_mul(0x9E37_79B9_7F4A_7C15).rotate_left(17))
+}
+
 /// Halts the CPU until the next interrupt.
 ///
 /// Used in idle loops to reduce power consumption.
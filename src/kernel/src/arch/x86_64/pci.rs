@@ -253,6 +253,35 @@ impl PciDevice {
         }
     }
 
+    /// Size in bytes of the memory region BAR0 decodes.
+    ///
+    /// Found the standard way: write all ones to the BAR and read back
+    /// which address bits are writable. Memory decoding is disabled while
+    /// the BAR is changed. Returns `None` if BAR0 is not a memory BAR.
+    pub fn mmio_size(&self) -> Option<u64> {
+        self.mmio_base()?;
+        let is_64bit = (self.bar0 >> 1) & 0x3 == 0b10;
+
+        let command = read_config_u16(self.addr, reg::COMMAND);
+        write_config_u16(self.addr, reg::COMMAND, command & !cmd::MEM_SPACE);
+        write_config_u32(self.addr, reg::BAR0, u32::MAX);
+        let low = read_config_u32(self.addr, reg::BAR0) & 0xFFFF_FFF0;
+        write_config_u32(self.addr, reg::BAR0, self.bar0);
+        let high = if is_64bit {
+            write_config_u32(self.addr, reg::BAR1, u32::MAX);
+            let high = read_config_u32(self.addr, reg::BAR1);
+            write_config_u32(self.addr, reg::BAR1, self.bar1);
+            high
+        } else {
+            u32::MAX
+        };
+        write_config_u16(self.addr, reg::COMMAND, command);
+
+        let mask = (high as u64) << 32 | low as u64;
+        let size = (!mask).wrapping_add(1);
+        (low != 0 && size != 0).then_some(size)
+    }
+
     /// Enable bus mastering and memory space access for this device.
     pub fn enable(&self) {
         let current = read_config_u16(self.addr, reg::COMMAND);
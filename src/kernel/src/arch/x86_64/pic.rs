@@ -21,6 +21,38 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub static PICS: Mutex<ChainedPics> =
     Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
+/// Legacy IRQ lines firmware assigns to PCI devices. Each has a handler
+/// that serves the devices that may sit on it.
+pub const PCI_IRQS: [u8; 4] = [5, 9, 10, 11];
+
+/// IRQ line the secondary PIC cascades through.
+const CASCADE_IRQ: u8 = 2;
+
+/// Let PCI IRQ line `irq` through to the CPU. Returns `false` if it is
+/// not one of `PCI_IRQS`.
+pub fn unmask_pci(irq: u8) -> bool {
+    if !PCI_IRQS.contains(&irq) {
+        return false;
+    }
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        // SAFETY: Reading and writing the mask registers only changes which
+        // lines are delivered, and the IDT has a handler for each of
+        // PCI_IRQS.
+        unsafe {
+            let [mut primary, mut secondary] = pics.read_masks();
+            if irq < 8 {
+                primary &= !(1 << irq);
+            } else {
+                primary &= !(1 << CASCADE_IRQ);
+                secondary &= !(1 << (irq - 8));
+            }
+            pics.write_masks(primary, secondary);
+        }
+    });
+    true
+}
+
 /// Possible IRQ indices.
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -0,0 +1,37 @@
+//! 8253/8254 Programmable Interval Timer.
+//!
+//! Channel 0 drives IRQ 0, which the kernel uses as its clock tick.
+
+use x86_64::instructions::port::{Port, PortWriteOnly};
+
+/// Input clock of the PIT in Hz.
+pub const PIT_FREQUENCY_HZ: u32 = 1_193_182;
+
+/// Channel 0 data port.
+const CHANNEL0_PORT: u16 = 0x40;
+
+/// Mode/command register.
+const COMMAND_PORT: u16 = 0x43;
+
+/// Channel 0, lobyte/hibyte access, mode 3 (square wave), binary.
+const CHANNEL0_SQUARE_WAVE: u8 = 0b0011_0110;
+
+/// Program channel 0 to interrupt `frequency_hz` times per second.
+///
+/// The divisor is clamped to the 16-bit range the PIT supports, so the
+/// actual rate is between about 18.2 Hz and the input clock.
+pub fn init(frequency_hz: u32) {
+    let divisor = (PIT_FREQUENCY_HZ / frequency_hz.max(1)).clamp(1, u32::from(u16::MAX)) as u16;
+    let [low, high] = divisor.to_le_bytes();
+
+    let mut command: PortWriteOnly<u8> = PortWriteOnly::new(COMMAND_PORT);
+    let mut data: Port<u8> = Port::new(CHANNEL0_PORT);
+    // SAFETY: 0x40/0x43 are the standard PIT ports; writing the command
+    // byte followed by the two divisor bytes is the documented sequence and
+    // only changes the timer interrupt rate.
+    unsafe {
+        command.write(CHANNEL0_SQUARE_WAVE);
+        data.write(low);
+        data.write(high);
+    }
+}
@@ -0,0 +1,416 @@
+//! 8042 PS/2 controller and keyboard.
+//!
+//! `init` puts the controller into a known state instead of trusting the
+//! firmware: both ports are disabled, stale output is flushed, the
+//! controller and the first port are self-tested, and the keyboard port is
+//! re-enabled with scancode translation (the terminal decodes set 1) and
+//! its interrupt on.
+//!
+//! After that, commands to the keyboard (LEDs, typematic rate) are sent
+//! one byte at a time from a small queue: each byte is written when the
+//! previous one was acknowledged, which the keyboard interrupt reports
+//! through `receive`. Acknowledgements never reach the scancode queue.
+//!
+//! The typematic rate can be set on the command line with
+//! `kbd_rate=<chars/s>` and `kbd_delay=<ms>`.
+
+use crate::boot::cmdline;
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+/// Data port: scancodes and responses in, device commands out.
+const DATA_PORT: u16 = 0x60;
+
+/// Status register (read) and controller command register (write).
+const STATUS_PORT: u16 = 0x64;
+
+/// Status bit: a byte is waiting in the output buffer.
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+
+/// Status bit: the controller has not consumed the last byte written.
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+/// Controller commands.
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_PORT2: u8 = 0xA7;
+const CMD_SELF_TEST: u8 = 0xAA;
+const CMD_TEST_PORT1: u8 = 0xAB;
+const CMD_DISABLE_PORT1: u8 = 0xAD;
+const CMD_ENABLE_PORT1: u8 = 0xAE;
+
+/// Configuration byte bits.
+const CONFIG_PORT1_IRQ: u8 = 1 << 0;
+const CONFIG_PORT2_IRQ: u8 = 1 << 1;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// Controller responses.
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+/// Keyboard commands.
+const KBD_SET_LEDS: u8 = 0xED;
+const KBD_SET_TYPEMATIC: u8 = 0xF3;
+const KBD_ENABLE_SCANNING: u8 = 0xF4;
+
+/// Keyboard responses.
+const KBD_ACK: u8 = 0xFA;
+const KBD_RESEND: u8 = 0xFE;
+
+/// Status polls before giving up on the controller.
+const POLL_LIMIT: u32 = 100_000;
+
+/// Bytes to drain from the output buffer when flushing.
+const FLUSH_LIMIT: usize = 16;
+
+/// Times a byte is re-sent when the keyboard asks for it.
+const MAX_RESENDS: u8 = 3;
+
+/// Capacity of the outgoing command queue.
+const QUEUE_LEN: usize = 8;
+
+/// Typematic delay steps, in milliseconds.
+const DELAY_STEP_MS: u32 = 250;
+
+/// Default typematic rate, in characters per second.
+pub const DEFAULT_RATE: u32 = 10;
+
+/// Default typematic delay, in milliseconds.
+pub const DEFAULT_DELAY_MS: u32 = 500;
+
+/// Errors initializing the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// The controller did not respond in time.
+    Timeout,
+    /// The controller self-test returned this instead of 0x55.
+    SelfTestFailed(u8),
+    /// The keyboard port test returned this error code.
+    PortTestFailed(u8),
+    /// The keyboard answered a command with this instead of an ACK.
+    NoAck(u8),
+}
+
+impl fmt::Display for Ps2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ps2Error::Timeout => f.write_str("controller timeout"),
+            Ps2Error::SelfTestFailed(code) => write!(f, "self-test failed ({:#04x})", code),
+            Ps2Error::PortTestFailed(code) => {
+                write!(f, "keyboard port test failed ({:#04x})", code)
+            }
+            Ps2Error::NoAck(code) => write!(f, "keyboard did not acknowledge ({:#04x})", code),
+        }
+    }
+}
+
+/// Keyboard LED state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Leds {
+    /// Scroll Lock.
+    pub scroll_lock: bool,
+    /// Num Lock.
+    pub num_lock: bool,
+    /// Caps Lock.
+    pub caps_lock: bool,
+}
+
+impl Leds {
+    /// The `SET_LEDS` data byte.
+    pub fn bits(self) -> u8 {
+        u8::from(self.scroll_lock) | u8::from(self.num_lock) << 1 | u8::from(self.caps_lock) << 2
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        Self {
+            scroll_lock: bits & 1 != 0,
+            num_lock: bits & 2 != 0,
+            caps_lock: bits & 4 != 0,
+        }
+    }
+}
+
+/// Typematic byte for a repeat rate of about `rate` characters per second
+/// after `delay_ms` (rounded to 250–1000 ms).
+pub fn typematic_byte(rate: u32, delay_ms: u32) -> u8 {
+    let wanted = rate.clamp(2, 30) * 100;
+    let code = (0..32u8)
+        .min_by_key(|&code| typematic_centihertz(code).abs_diff(wanted))
+        .unwrap_or(0);
+    let delay = ((delay_ms + DELAY_STEP_MS / 2) / DELAY_STEP_MS).clamp(1, 4) - 1;
+    (delay as u8) << 5 | code
+}
+
+/// Repeat rate of typematic rate code `code`, in hundredths of a hertz.
+///
+/// The period is (8 + A) * 2^B * 4.17 ms, with A in bits 0–2 and B in
+/// bits 3–4 of the code.
+pub fn typematic_centihertz(code: u8) -> u32 {
+    let a = u32::from(code & 0x07);
+    let b = u32::from((code >> 3) & 0x03);
+    10_000_000 / ((8 + a) * (1 << b) * 417)
+}
+
+/// Bytes waiting to be sent to the keyboard.
+struct Outbox {
+    queue: [u8; QUEUE_LEN],
+    len: usize,
+    /// Byte written and not yet acknowledged.
+    in_flight: Option<u8>,
+    resends: u8,
+}
+
+impl Outbox {
+    /// Write the next queued byte if none is in flight.
+    fn pump(&mut self) {
+        if self.in_flight.is_some() || self.len == 0 {
+            return;
+        }
+        let byte = self.queue[0];
+        self.queue.copy_within(1..self.len, 0);
+        self.len -= 1;
+        self.in_flight = Some(byte);
+        self.resends = 0;
+        let _ = write_data(byte);
+    }
+}
+
+/// Outgoing keyboard commands. Only locked with interrupts disabled.
+static OUTBOX: Mutex<Outbox> = Mutex::new(Outbox {
+    queue: [0; QUEUE_LEN],
+    len: 0,
+    in_flight: None,
+    resends: 0,
+});
+
+/// LED state last sent to the keyboard.
+static LEDS: AtomicU8 = AtomicU8::new(0);
+
+/// Typematic byte last sent to the keyboard.
+static TYPEMATIC: AtomicU8 = AtomicU8::new(0);
+
+fn status() -> u8 {
+    let mut port: Port<u8> = Port::new(STATUS_PORT);
+    // SAFETY: Reading the 8042 status register has no side effects.
+    unsafe { port.read() }
+}
+
+/// Wait until the controller accepts a byte.
+fn wait_input_empty() -> Result<(), Ps2Error> {
+    for _ in 0..POLL_LIMIT {
+        if status() & STATUS_INPUT_FULL == 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+/// Wait for a byte in the output buffer and read it.
+fn read_data() -> Result<u8, Ps2Error> {
+    for _ in 0..POLL_LIMIT {
+        if status() & STATUS_OUTPUT_FULL != 0 {
+            let mut port: Port<u8> = Port::new(DATA_PORT);
+            // SAFETY: The output buffer is full; reading the data port
+            // consumes that byte.
+            return Ok(unsafe { port.read() });
+        }
+        core::hint::spin_loop();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+fn write_command(command: u8) -> Result<(), Ps2Error> {
+    wait_input_empty()?;
+    let mut port: Port<u8> = Port::new(STATUS_PORT);
+    // SAFETY: Writing a documented controller command to port 0x64.
+    unsafe { port.write(command) };
+    Ok(())
+}
+
+fn write_data(byte: u8) -> Result<(), Ps2Error> {
+    wait_input_empty()?;
+    let mut port: Port<u8> = Port::new(DATA_PORT);
+    // SAFETY: Writing to port 0x60 sends the byte to the keyboard, or to
+    // the controller after CMD_WRITE_CONFIG.
+    unsafe { port.write(byte) };
+    Ok(())
+}
+
+/// Send a byte to the keyboard and wait for its ACK (interrupts off).
+fn keyboard_command(byte: u8) -> Result<(), Ps2Error> {
+    for _ in 0..=MAX_RESENDS {
+        write_data(byte)?;
+        match read_data()? {
+            KBD_ACK => return Ok(()),
+            KBD_RESEND => continue,
+            other => return Err(Ps2Error::NoAck(other)),
+        }
+    }
+    Err(Ps2Error::NoAck(KBD_RESEND))
+}
+
+/// Reset the controller to a known state and enable the keyboard.
+///
+/// Runs with interrupts disabled so the keyboard interrupt cannot take the
+/// controller's responses.
+pub fn init() -> Result<(), Ps2Error> {
+    interrupts::without_interrupts(|| {
+        write_command(CMD_DISABLE_PORT1)?;
+        write_command(CMD_DISABLE_PORT2)?;
+        flush();
+
+        write_command(CMD_READ_CONFIG)?;
+        let config = read_data()? & !(CONFIG_PORT1_IRQ | CONFIG_PORT2_IRQ);
+        write_command(CMD_WRITE_CONFIG)?;
+        write_data(config)?;
+
+        write_command(CMD_SELF_TEST)?;
+        match read_data()? {
+            SELF_TEST_PASSED => {}
+            code => return Err(Ps2Error::SelfTestFailed(code)),
+        }
+        write_command(CMD_TEST_PORT1)?;
+        match read_data()? {
+            PORT_TEST_PASSED => {}
+            code => return Err(Ps2Error::PortTestFailed(code)),
+        }
+
+        // The self-test may reset the configuration; write it again
+        write_command(CMD_ENABLE_PORT1)?;
+        write_command(CMD_WRITE_CONFIG)?;
+        write_data(config | CONFIG_PORT1_IRQ | CONFIG_TRANSLATION)?;
+        flush();
+
+        let leds = Leds {
+            num_lock: true,
+            ..Leds::default()
+        };
+        keyboard_command(KBD_SET_LEDS)?;
+        keyboard_command(leds.bits())?;
+        LEDS.store(leds.bits(), Ordering::Relaxed);
+
+        let typematic = typematic_byte(configured_rate(), configured_delay_ms());
+        keyboard_command(KBD_SET_TYPEMATIC)?;
+        keyboard_command(typematic)?;
+        TYPEMATIC.store(typematic, Ordering::Relaxed);
+
+        keyboard_command(KBD_ENABLE_SCANNING)
+    })
+}
+
+/// Discard whatever is waiting in the output buffer.
+fn flush() {
+    let mut port: Port<u8> = Port::new(DATA_PORT);
+    for _ in 0..FLUSH_LIMIT {
+        if status() & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        // SAFETY: Reading the data port only consumes the waiting byte.
+        unsafe { port.read() };
+    }
+}
+
+/// Typematic rate from `kbd_rate=` (characters per second).
+fn configured_rate() -> u32 {
+    cmdline::get("kbd_rate")
+        .and_then(|rate| rate.parse().ok())
+        .unwrap_or(DEFAULT_RATE)
+}
+
+/// Typematic delay from `kbd_delay=` (milliseconds).
+fn configured_delay_ms() -> u32 {
+    cmdline::get("kbd_delay")
+        .and_then(|delay| delay.parse().ok())
+        .unwrap_or(DEFAULT_DELAY_MS)
+}
+
+/// Read the byte that raised the keyboard interrupt.
+///
+/// Returns the scancode, or `None` if there was nothing to read or the
+/// byte answered a command (which then lets the next one go out).
+pub fn receive() -> Option<u8> {
+    if status() & STATUS_OUTPUT_FULL == 0 {
+        return None;
+    }
+    let mut port: Port<u8> = Port::new(DATA_PORT);
+    // SAFETY: The output buffer is full; reading consumes the byte.
+    let byte = unsafe { port.read() };
+
+    let mut outbox = OUTBOX.lock();
+    match (byte, outbox.in_flight) {
+        (KBD_ACK, Some(_)) => {
+            outbox.in_flight = None;
+            outbox.pump();
+            None
+        }
+        (KBD_RESEND, Some(sent)) => {
+            if outbox.resends < MAX_RESENDS {
+                outbox.resends += 1;
+                let _ = write_data(sent);
+            } else {
+                outbox.in_flight = None;
+                outbox.pump();
+            }
+            None
+        }
+        // A late answer to a command sent during `init`
+        (KBD_ACK | KBD_RESEND, None) => None,
+        _ => Some(byte),
+    }
+}
+
+/// Queue bytes for the keyboard. Returns false if the queue is full.
+fn send(bytes: &[u8]) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut outbox = OUTBOX.lock();
+        if outbox.len + bytes.len() > QUEUE_LEN {
+            return false;
+        }
+        let len = outbox.len;
+        outbox.queue[len..len + bytes.len()].copy_from_slice(bytes);
+        outbox.len += bytes.len();
+        outbox.pump();
+        true
+    })
+}
+
+/// The LED state last sent.
+pub fn leds() -> Leds {
+    Leds::from_bits(LEDS.load(Ordering::Relaxed))
+}
+
+/// Light the LEDs as `leds`, if that is not their state already.
+pub fn set_leds(leds: Leds) {
+    let bits = leds.bits();
+    let previous = LEDS.swap(bits, Ordering::Relaxed);
+    if previous != bits && !send(&[KBD_SET_LEDS, bits]) {
+        // Queue full: try again on the next change
+        LEDS.store(previous, Ordering::Relaxed);
+    }
+}
+
+/// The typematic byte last sent.
+pub fn typematic() -> u8 {
+    TYPEMATIC.load(Ordering::Relaxed)
+}
+
+/// Set the repeat rate (characters per second) and delay (milliseconds).
+///
+/// Returns the typematic byte sent, or `None` if the queue was full.
+pub fn set_typematic(rate: u32, delay_ms: u32) -> Option<u8> {
+    let byte = typematic_byte(rate, delay_ms);
+    if !send(&[KBD_SET_TYPEMATIC, byte]) {
+        return None;
+    }
+    TYPEMATIC.store(byte, Ordering::Relaxed);
+    Some(byte)
+}
+
+/// Delay encoded in a typematic byte, in milliseconds.
+pub fn typematic_delay_ms(byte: u8) -> u32 {
+    (u32::from(byte >> 5 & 0x03) + 1) * DELAY_STEP_MS
+}
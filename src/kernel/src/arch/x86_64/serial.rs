@@ -1,19 +1,75 @@
 //! Serial port driver for x86_64.
 //!
 //! Provides serial output via COM1 (0x3F8) for debugging and logging.
+//!
+//! COM2–COM4 can be enabled as general-purpose ports from the kernel
+//! command line, with an optional baud rate each; `baud=` sets COM1's:
+//!
+//! ```text
+//! SOVELMA_CMDLINE="baud=115200 serial=3:9600,4" cargo run
+//! ```
+//!
+//! Enabled ports are driven by polling and appear as `/dev/serialN`
+//! (see `fs::devfs`). COM2 stays reserved while SLIP or the GDB stub uses
+//! it; SLIP still takes its baud rate from `serial=2:<baud>`.
 
+use crate::boot::cmdline;
+use crate::sync::TrackedMutex;
+use alloc::vec::Vec;
 use core::fmt::{self, Write};
-use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
 
 /// COM1 I/O port address.
 const COM1_PORT: u16 = 0x3F8;
 
+/// COM2 I/O port address (used by the SLIP network device or the GDB stub).
+pub const COM2_PORT: u16 = 0x2F8;
+
+/// COM3 I/O port address.
+pub const COM3_PORT: u16 = 0x3E8;
+
+/// COM4 I/O port address.
+pub const COM4_PORT: u16 = 0x2E8;
+
+/// I/O bases of COM1–COM4, by port number minus one.
+pub const COM_PORTS: [u16; 4] = [COM1_PORT, COM2_PORT, COM3_PORT, COM4_PORT];
+
+/// Baud rate at divisor 1 (the 1.8432 MHz UART clock over 16).
+pub const MAX_BAUD: u32 = 115_200;
+
+/// Baud rate programmed by `SerialPort::init`.
+pub const DEFAULT_BAUD: u32 = 38_400;
+
+/// Offset of the UART line control register from the port base.
+const UART_LINE_CONTROL: u16 = 3;
+
+/// Offset of the UART scratch register from the port base.
+const UART_SCRATCH: u16 = 7;
+
+/// Line control bit exposing the divisor latch at offsets 0 and 1.
+const LINE_CONTROL_DLAB: u8 = 1 << 7;
+
+/// Line control value for 8 data bits, no parity, 1 stop bit.
+const LINE_CONTROL_8N1: u8 = 0x03;
+
+/// Pattern written to the scratch register to detect a UART.
+const SCRATCH_PROBE: u8 = 0x5A;
+
+/// Offset of the UART interrupt-enable register from the port base.
+const UART_INT_ENABLE: u16 = 1;
+
+/// Offset of the UART line status register from the port base.
+const UART_LINE_STATUS: u16 = 5;
+
+/// Line status bit set when the transmit holding register is empty.
+const LINE_STATUS_THR_EMPTY: u8 = 1 << 5;
+
 /// Global serial port instance, lazily initialized.
 ///
 /// Uses a spinlock for safe concurrent access from multiple contexts,
 /// including interrupt handlers.
-pub static SERIAL: spin::Once<Mutex<SerialPort>> = spin::Once::new();
+pub static SERIAL: spin::Once<TrackedMutex<SerialPort>> = spin::Once::new();
 
 /// Initializes the global serial port.
 ///
@@ -26,12 +82,247 @@ pub fn init() {
         // The uart_16550 crate handles the port initialization sequence correctly.
         let mut serial = unsafe { SerialPort::new(COM1_PORT) };
         serial.init();
-        Mutex::new(serial)
+        TrackedMutex::new("serial", serial)
     });
 }
 
+/// Initializes a secondary UART (38400 8N1) for polled use.
+///
+/// `SerialPort::init` enables the receive interrupt, but only COM1 is
+/// ever read from an interrupt context, so it is switched off again.
+///
+/// # Safety
+///
+/// `base` must be the I/O base of a 16550 UART that nothing else drives.
+pub unsafe fn init_polled(base: u16) -> SerialPort {
+    // SAFETY: The caller guarantees `base` is an unused 16550 UART.
+    let mut port = unsafe { SerialPort::new(base) };
+    port.init();
+
+    let mut int_enable: Port<u8> = Port::new(base + UART_INT_ENABLE);
+    // SAFETY: Writing 0 to the interrupt-enable register only disables
+    // the UART's interrupts; the port belongs to the caller.
+    unsafe { int_enable.write(0) };
+    port
+}
+
+/// Errors configuring or using COM2–COM4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialError {
+    /// Not a port number from 1 to 4.
+    InvalidPort(u8),
+    /// Not a rate that divides `MAX_BAUD`.
+    InvalidBaud(u32),
+    /// No UART answers at the port.
+    NotPresent(u8),
+    /// The port is the log console or reserved by SLIP or the GDB stub.
+    InUse(u8),
+    /// The port was not enabled.
+    NotOpen(u8),
+    /// The `serial=` option could not be parsed.
+    Syntax,
+}
+
+impl fmt::Display for SerialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerialError::InvalidPort(com) => write!(f, "no such port COM{}", com),
+            SerialError::InvalidBaud(baud) => write!(f, "unsupported baud rate {}", baud),
+            SerialError::NotPresent(com) => write!(f, "COM{} not present", com),
+            SerialError::InUse(com) => write!(f, "COM{} is in use", com),
+            SerialError::NotOpen(com) => write!(f, "COM{} is not enabled", com),
+            SerialError::Syntax => f.write_str("expected serial=<port>[:<baud>],..."),
+        }
+    }
+}
+
+/// A port selected on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortConfig {
+    /// Port number, 1 to 4.
+    pub com: u8,
+    /// Baud rate.
+    pub baud: u32,
+}
+
+/// I/O base of port `com` (1 to 4).
+pub fn port_base(com: u8) -> Result<u16, SerialError> {
+    match com {
+        1..=4 => Ok(COM_PORTS[usize::from(com) - 1]),
+        _ => Err(SerialError::InvalidPort(com)),
+    }
+}
+
+/// Divisor latch value for `baud`.
+pub fn divisor(baud: u32) -> Result<u16, SerialError> {
+    if baud == 0 || baud > MAX_BAUD {
+        return Err(SerialError::InvalidBaud(baud));
+    }
+    let divisor = MAX_BAUD / baud;
+    if divisor * baud != MAX_BAUD {
+        return Err(SerialError::InvalidBaud(baud));
+    }
+    // Only 1 baud needs a divisor over u16::MAX
+    u16::try_from(divisor).map_err(|_| SerialError::InvalidBaud(baud))
+}
+
+/// Parse a `serial=` value: comma-separated `<port>[:<baud>]` entries.
+pub fn parse_ports(value: &str) -> Result<Vec<PortConfig>, SerialError> {
+    let mut ports = Vec::new();
+    for entry in value.split(',').filter(|entry| !entry.is_empty()) {
+        let (com, baud) = match entry.split_once(':') {
+            Some((com, baud)) => (com, baud.parse().map_err(|_| SerialError::Syntax)?),
+            None => (entry, DEFAULT_BAUD),
+        };
+        let com = com.parse().map_err(|_| SerialError::Syntax)?;
+        port_base(com)?;
+        divisor(baud)?;
+        ports.push(PortConfig { com, baud });
+    }
+    Ok(ports)
+}
+
+/// Ports selected with `serial=` on the kernel command line.
+pub fn configured() -> Result<Vec<PortConfig>, SerialError> {
+    parse_ports(cmdline::get("serial").unwrap_or(""))
+}
+
+/// Baud rate chosen for `com` on the command line, if any.
+pub fn configured_baud(com: u8) -> Option<u32> {
+    if com == 1 {
+        return cmdline::get("baud").and_then(|baud| baud.parse().ok());
+    }
+    configured()
+        .ok()?
+        .into_iter()
+        .find(|port| port.com == com)
+        .map(|port| port.baud)
+}
+
+/// Program the baud rate of the UART at `base`, keeping 8N1.
+///
+/// # Safety
+///
+/// `base` must be the I/O base of a 16550 UART owned by the caller.
+pub unsafe fn set_baud(base: u16, baud: u32) -> Result<(), SerialError> {
+    let [low, high] = divisor(baud)?.to_le_bytes();
+    let mut line_control: Port<u8> = Port::new(base + UART_LINE_CONTROL);
+    let mut divisor_low: Port<u8> = Port::new(base);
+    let mut divisor_high: Port<u8> = Port::new(base + UART_INT_ENABLE);
+    // SAFETY: The caller owns the UART; with DLAB set, offsets 0 and 1 are
+    // the divisor latch, and clearing it restores the data registers.
+    unsafe {
+        line_control.write(LINE_CONTROL_DLAB);
+        divisor_low.write(low);
+        divisor_high.write(high);
+        line_control.write(LINE_CONTROL_8N1);
+    }
+    Ok(())
+}
+
+/// Check whether a UART answers at `base`, using its scratch register.
+///
+/// # Safety
+///
+/// `base` must be a COM port base that nothing else is driving.
+pub unsafe fn probe(base: u16) -> bool {
+    let mut scratch: Port<u8> = Port::new(base + UART_SCRATCH);
+    // SAFETY: The scratch register has no function besides storage.
+    unsafe {
+        scratch.write(SCRATCH_PROBE);
+        scratch.read() == SCRATCH_PROBE
+    }
+}
+
+/// Whether COM2 belongs to SLIP or the GDB stub.
+fn com2_reserved() -> bool {
+    cmdline::get("net") == Some("slip") || cmdline::get("gdb").is_some()
+}
+
+/// COM2–COM4 once enabled, by port number minus one.
+static PORTS: TrackedMutex<[Option<SerialPort>; 4]> =
+    TrackedMutex::new("serial_ports", [None, None, None, None]);
+
+/// Enable a general-purpose port.
+pub fn open(config: PortConfig) -> Result<(), SerialError> {
+    let base = port_base(config.com)?;
+    divisor(config.baud)?;
+    if config.com == 1 || (config.com == 2 && com2_reserved()) {
+        return Err(SerialError::InUse(config.com));
+    }
+    let mut ports = PORTS.lock();
+    let slot = &mut ports[usize::from(config.com) - 1];
+    if slot.is_none() {
+        // SAFETY: COM1 and a reserved COM2 were excluded above, and the
+        // port is not open yet, so nothing else drives it.
+        if !unsafe { probe(base) } {
+            return Err(SerialError::NotPresent(config.com));
+        }
+        // SAFETY: As above; the UART was just detected.
+        *slot = Some(unsafe { init_polled(base) });
+    }
+    // SAFETY: The port is owned by PORTS, which is locked.
+    unsafe { set_baud(base, config.baud) }
+}
+
+/// Whether port `com` has been enabled.
+pub fn is_open(com: u8) -> bool {
+    port_base(com).is_ok() && PORTS.lock()[usize::from(com) - 1].is_some()
+}
+
+/// Write `bytes` to an enabled port, waiting for the transmitter.
+pub fn write_port(com: u8, bytes: &[u8]) -> Result<usize, SerialError> {
+    port_base(com)?;
+    let mut ports = PORTS.lock();
+    let port = ports[usize::from(com) - 1]
+        .as_mut()
+        .ok_or(SerialError::NotOpen(com))?;
+    for &byte in bytes {
+        port.send_raw(byte);
+    }
+    Ok(bytes.len())
+}
+
+/// Read the bytes an enabled port has received, without waiting.
+pub fn read_port(com: u8, buffer: &mut [u8]) -> Result<usize, SerialError> {
+    port_base(com)?;
+    let mut ports = PORTS.lock();
+    let port = ports[usize::from(com) - 1]
+        .as_mut()
+        .ok_or(SerialError::NotOpen(com))?;
+    let mut count = 0;
+    while count < buffer.len() {
+        match port.try_receive() {
+            Ok(byte) => {
+                buffer[count] = byte;
+                count += 1;
+            }
+            Err(_) => break,
+        }
+    }
+    Ok(count)
+}
+
+/// A port selected on the command line and whether enabling it worked.
+pub type PortStatus = (PortConfig, Result<(), SerialError>);
+
+/// Apply `baud=` to COM1 and enable the `serial=` ports.
+///
+/// Returns the outcome for each port, for the boot log.
+pub fn init_from_cmdline() -> Result<Vec<PortStatus>, SerialError> {
+    if let Some(baud) = configured_baud(1) {
+        let _console = get_serial().lock();
+        // SAFETY: COM1 is the kernel's console and its lock is held.
+        unsafe { set_baud(COM1_PORT, baud)? };
+    }
+    Ok(configured()?
+        .into_iter()
+        .map(|config| (config, open(config)))
+        .collect())
+}
+
 /// Returns a reference to the serial port, initializing if necessary.
-fn get_serial() -> &'static Mutex<SerialPort> {
+fn get_serial() -> &'static TrackedMutex<SerialPort> {
     init();
     SERIAL.get().expect("serial port not initialized")
 }
@@ -56,6 +347,27 @@ macro_rules! serial_println {
 pub fn _print(args: fmt::Arguments) {
     let serial = get_serial();
     serial.lock().write_fmt(args).expect("serial write failed");
+    crate::testutil::record(args);
+}
+
+/// Write bytes to COM1 by polling the UART directly.
+///
+/// Takes no lock and allocates nothing, so it works from a panic at any
+/// stage of boot, even while `SERIAL` is held or before `init`.
+pub fn write_raw(bytes: &[u8]) {
+    let mut data: Port<u8> = Port::new(COM1_PORT);
+    let mut status: Port<u8> = Port::new(COM1_PORT + UART_LINE_STATUS);
+    for &byte in bytes {
+        // SAFETY: COM1 is the kernel's own debug UART; polling its line
+        // status and writing its data register has no other side effects.
+        // A missing UART reads as 0xFF, so the loop cannot spin forever.
+        unsafe {
+            while status.read() & LINE_STATUS_THR_EMPTY == 0 {
+                core::hint::spin_loop();
+            }
+            data.write(byte);
+        }
+    }
 }
 
 /// A wrapper to implement HAL traits for the serial port.
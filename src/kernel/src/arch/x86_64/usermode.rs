@@ -0,0 +1,344 @@
+//! Ring 3 execution and the SYSCALL/SYSRET entry path.
+//!
+//! Applications are WASM modules sandboxed by the interpreter; this is a
+//! second, hardware-enforced isolation mechanism for native code. It is
+//! groundwork: one user region, one program at a time, run synchronously
+//! by the caller until it exits.
+//!
+//! - `init` enables SYSCALL (EFER.SCE), points STAR at the GDT's kernel and
+//!   user segments and LSTAR at the entry stub, and maps the user region:
+//!   a code page and a stack, the only pages with the user bit set.
+//! - `run` saves the kernel context and drops to ring 3 with SYSRET.
+//!   Interrupts taken in ring 3 arrive on the TSS RSP0 stack.
+//! - A SYSCALL lands on the kernel stack `run` was called on and is
+//!   dispatched with interrupts masked. `SYS_EXIT` unwinds back to `run`,
+//!   which returns the exit status.
+//!
+//! System call ABI: number in RAX, arguments in RDI, RSI, RDX; the result
+//! is returned in RAX. RCX, R11 and the argument registers are clobbered.
+
+use super::gdt;
+use core::arch::global_asm;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::paging::{
+    mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+/// Start of the user region (its own PML4 slot, away from kernel mappings).
+pub const USER_BASE: u64 = 0x0000_2000_0000_0000;
+
+/// Bytes of program code mapped at `USER_BASE`.
+pub const USER_CODE_SIZE: u64 = 4096;
+
+/// Start of the user stack.
+pub const USER_STACK_BASE: u64 = USER_BASE + 0x0010_0000;
+
+/// Bytes of user stack.
+pub const USER_STACK_SIZE: u64 = 4096 * 4;
+
+/// End of the user region (exclusive).
+pub const USER_END: u64 = USER_STACK_BASE + USER_STACK_SIZE;
+
+/// Terminate the program; argument: exit status.
+pub const SYS_EXIT: u64 = 0;
+
+/// Write text to the console; arguments: pointer, length.
+pub const SYS_WRITE: u64 = 1;
+
+/// Bytes `SYS_WRITE` accepts per call.
+pub const MAX_WRITE: u64 = 4096;
+
+/// Returned in RAX for an unknown call or invalid argument.
+pub const EINVAL: u64 = u64::MAX;
+
+/// Exit status of the built-in demo program.
+pub const DEMO_EXIT_STATUS: u64 = 42;
+
+/// Errors from setting up or entering ring 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserModeError {
+    /// `init` has not run (or failed).
+    NotInitialized,
+    /// The GDT segments are not laid out as SYSCALL/SYSRET require.
+    BadSegments(&'static str),
+    /// Mapping the user region failed.
+    MapFailed,
+    /// The program does not fit the code page.
+    ProgramTooLarge,
+}
+
+impl fmt::Display for UserModeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotInitialized => write!(f, "ring 3 support not initialized"),
+            Self::BadSegments(reason) => write!(f, "bad segment layout: {}", reason),
+            Self::MapFailed => write!(f, "cannot map the user region"),
+            Self::ProgramTooLarge => write!(f, "program larger than the code page"),
+        }
+    }
+}
+
+impl From<MapToError<Size4KiB>> for UserModeError {
+    fn from(_: MapToError<Size4KiB>) -> Self {
+        Self::MapFailed
+    }
+}
+
+/// Set by `init` once the MSRs and the user region are ready.
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Offset of the physical memory mapping, for writing user pages.
+static PHYS_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Physical frame of the code page.
+static CODE_FRAME: AtomicU64 = AtomicU64::new(0);
+
+/// Kernel stack pointer saved by `run`; system calls run below it.
+static KERNEL_RSP: AtomicU64 = AtomicU64::new(0);
+
+/// User stack pointer saved on system call entry.
+static USER_RSP: AtomicU64 = AtomicU64::new(0);
+
+/// System calls handled since boot.
+static SYSCALLS: AtomicU64 = AtomicU64::new(0);
+
+global_asm!(
+    // u64 user_enter(u64 entry, u64 stack_top)
+    ".global user_enter",
+    "user_enter:",
+    "    push rbx",
+    "    push rbp",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    pushfq",
+    "    cli",
+    "    mov [rip + {kernel_rsp}], rsp",
+    "    mov rcx, rdi",
+    // RFLAGS for ring 3: interrupts enabled, reserved bit 1 set
+    "    mov r11, 0x202",
+    "    mov rsp, rsi",
+    "    sysretq",
+    ".global user_syscall_entry",
+    "user_syscall_entry:",
+    "    mov [rip + {user_rsp}], rsp",
+    "    mov rsp, [rip + {kernel_rsp}]",
+    "    push rcx",
+    "    push r11",
+    // `user_enter`'s return address, the saved context (7 words) and the
+    // two above are 10 words, so rsp keeps the 16-byte alignment the C
+    // ABI expects at the call
+    "    mov rcx, rdx",
+    "    mov rdx, rsi",
+    "    mov rsi, rdi",
+    "    mov rdi, rax",
+    "    call {dispatch}",
+    "    pop r11",
+    "    pop rcx",
+    "    test rdx, rdx",
+    "    jnz user_exit",
+    "    mov rsp, [rip + {user_rsp}]",
+    "    sysretq",
+    // SYS_EXIT: rsp is back at the context `user_enter` saved
+    "user_exit:",
+    "    popfq",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop rbp",
+    "    pop rbx",
+    "    ret",
+    kernel_rsp = sym KERNEL_RSP,
+    user_rsp = sym USER_RSP,
+    dispatch = sym syscall_dispatch,
+);
+
+// The demo program: position-independent, copied to `USER_BASE`. The call
+// numbers are SYS_WRITE (1) and SYS_EXIT (0), the status DEMO_EXIT_STATUS.
+global_asm!(
+    ".global user_demo_start",
+    "user_demo_start:",
+    "    mov eax, 1",
+    "    lea rdi, [rip + user_demo_message]",
+    "    lea rsi, [rip + user_demo_end]",
+    "    sub rsi, rdi",
+    "    syscall",
+    "    mov eax, 0",
+    "    mov edi, 42",
+    "    syscall",
+    "    ud2",
+    "user_demo_message:",
+    "    .ascii \"Hello from ring 3\\n\"",
+    ".global user_demo_end",
+    "user_demo_end:",
+);
+
+extern "C" {
+    fn user_enter(entry: u64, stack_top: u64) -> u64;
+    fn user_syscall_entry();
+    static user_demo_start: u8;
+    static user_demo_end: u8;
+}
+
+/// Value and exit flag handed back to the entry stub in RAX:RDX.
+#[repr(C)]
+struct SyscallReturn {
+    value: u64,
+    exit: u64,
+}
+
+/// Dispatch a system call from ring 3. Runs with interrupts masked.
+extern "C" fn syscall_dispatch(number: u64, arg0: u64, arg1: u64, _arg2: u64) -> SyscallReturn {
+    SYSCALLS.fetch_add(1, Ordering::Relaxed);
+    let value = match number {
+        SYS_EXIT => {
+            return SyscallReturn {
+                value: arg0,
+                exit: 1,
+            }
+        }
+        SYS_WRITE => sys_write(arg0, arg1),
+        _ => EINVAL,
+    };
+    SyscallReturn { value, exit: 0 }
+}
+
+fn sys_write(ptr: u64, len: u64) -> u64 {
+    if len > MAX_WRITE || !is_user_range(ptr, len) {
+        return EINVAL;
+    }
+    // SAFETY: The range lies in the user region, which is mapped for as
+    // long as the kernel runs, and the program is stopped while we read.
+    let bytes = unsafe { core::slice::from_raw_parts(ptr as *const u8, len as usize) };
+    crate::print!("{}", alloc::string::String::from_utf8_lossy(bytes));
+    len
+}
+
+/// Check whether `len` bytes at `ptr` lie inside the user region.
+pub fn is_user_range(ptr: u64, len: u64) -> bool {
+    ptr >= USER_BASE && ptr.checked_add(len).is_some_and(|end| end <= USER_END)
+}
+
+/// Number of system calls handled since boot.
+pub fn syscalls() -> u64 {
+    SYSCALLS.load(Ordering::Relaxed)
+}
+
+/// Enable SYSCALL/SYSRET and map the user region.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    physical_memory_offset: VirtAddr,
+) -> Result<(), UserModeError> {
+    let selectors = gdt::selectors();
+    Star::write(
+        selectors.user_code_selector,
+        selectors.user_data_selector,
+        selectors.code_selector,
+        selectors.data_selector,
+    )
+    .map_err(UserModeError::BadSegments)?;
+    LStar::write(VirtAddr::new(
+        user_syscall_entry as unsafe extern "C" fn() as usize as u64,
+    ));
+    // Entered with interrupts off, a clear direction flag and no single-step
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+    // SAFETY: LSTAR and STAR are set up above, so SYSCALL has a valid target.
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+
+    let user = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let code_frame = map(mapper, frame_allocator, USER_BASE, user)?;
+    let stack_pages = USER_STACK_SIZE / USER_CODE_SIZE;
+    for i in 0..stack_pages {
+        let addr = USER_STACK_BASE + i * USER_CODE_SIZE;
+        map(
+            mapper,
+            frame_allocator,
+            addr,
+            user | PageTableFlags::WRITABLE,
+        )?;
+    }
+
+    PHYS_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+    CODE_FRAME.store(code_frame.start_address().as_u64(), Ordering::Relaxed);
+    READY.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Map one fresh frame at `addr` with `flags`; parent tables get the user
+/// bit so the leaf flags decide access.
+fn map(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    addr: u64,
+    flags: PageTableFlags,
+) -> Result<PhysFrame, UserModeError> {
+    let page = Page::containing_address(VirtAddr::new(addr));
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let parent =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    // SAFETY: The frame is fresh and the user region is reserved for this
+    // module, so no existing mapping is changed.
+    unsafe {
+        mapper
+            .map_to_with_table_flags(page, frame, flags, parent, frame_allocator)?
+            .flush();
+    }
+    Ok(frame)
+}
+
+/// Load `program` into the code page (through the physical mapping, since
+/// ring 3 cannot write it).
+pub fn load(program: &[u8]) -> Result<(), UserModeError> {
+    if !READY.load(Ordering::Acquire) {
+        return Err(UserModeError::NotInitialized);
+    }
+    if program.len() as u64 > USER_CODE_SIZE {
+        return Err(UserModeError::ProgramTooLarge);
+    }
+    let phys = PHYS_OFFSET.load(Ordering::Relaxed) + CODE_FRAME.load(Ordering::Relaxed);
+    // SAFETY: The code frame is mapped at the physical offset, belongs to
+    // the user region and holds `USER_CODE_SIZE` bytes; nothing runs from
+    // it while we copy (programs run synchronously).
+    unsafe {
+        core::ptr::copy_nonoverlapping(program.as_ptr(), phys as *mut u8, program.len());
+    }
+    Ok(())
+}
+
+/// Run the loaded program in ring 3 until it calls `SYS_EXIT`.
+///
+/// Returns the exit status. A fault in ring 3 is fatal to the kernel.
+pub fn run() -> Result<u64, UserModeError> {
+    if !READY.load(Ordering::Acquire) {
+        return Err(UserModeError::NotInitialized);
+    }
+    // SAFETY: The code page holds a program and the stack is mapped; the
+    // stub saves the kernel context and `SYS_EXIT` restores it.
+    Ok(unsafe { user_enter(USER_BASE, USER_END) })
+}
+
+/// The demo program's machine code.
+pub fn demo_program() -> &'static [u8] {
+    // SAFETY: Both symbols label the same `global_asm!` block, in order.
+    unsafe {
+        let start = &user_demo_start as *const u8;
+        let end = &user_demo_end as *const u8;
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+/// Load and run the demo program: it prints a greeting and exits with
+/// `DEMO_EXIT_STATUS`.
+pub fn run_demo() -> Result<u64, UserModeError> {
+    load(demo_program())?;
+    run()
+}
@@ -1,19 +1,30 @@
 //! VGA text mode driver for x86_64.
 //!
 //! Provides colored text output to the VGA text buffer at 0xB8000.
+//! Text is UTF-8; characters outside ASCII are shown with their code page
+//! 437 glyph where the font has one (see `cp437`).
+//! Writes go to a shadow buffer first and only changed rows are copied to
+//! video memory, so redraws do not flicker.
+//!
+//! The few ANSI sequences the shell's line editor sends to move over a
+//! line that wraps are understood, as a telnet client would: `ESC [ n A`,
+//! `B`, `C` and `D` move the cursor up, down, right and left, `ESC [ J`
+//! erases to the end of the screen and `ESC [ K` to the end of the row.
+//! Other sequences are dropped.
 
+use super::cp437;
+use crate::sync::TrackedMutex;
 use core::fmt::{self, Write};
 use core::ptr;
-use spin::Mutex;
 
 /// VGA text buffer memory-mapped I/O address.
 const VGA_BUFFER_ADDR: usize = 0xB8000;
 
 /// Number of rows in VGA text mode.
-const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_HEIGHT: usize = 25;
 
 /// Number of columns in VGA text mode.
-const BUFFER_WIDTH: usize = 80;
+pub const BUFFER_WIDTH: usize = 80;
 
 /// VGA color codes.
 ///
@@ -81,32 +92,60 @@ struct Buffer {
     chars: [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+/// Dirty-row mask with every row set.
+const ALL_ROWS: u32 = (1 << BUFFER_HEIGHT) - 1;
+
+/// Where the writer is in an ANSI escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    /// Not in a sequence.
+    None,
+    /// After `ESC`.
+    Started,
+    /// After `ESC [` and the digits of its parameter so far.
+    Csi(usize),
+}
+
 /// Global VGA writer instance.
 ///
 /// Uses a spinlock for safe concurrent access.
-pub static WRITER: spin::Once<Mutex<Writer>> = spin::Once::new();
+pub static WRITER: spin::Once<TrackedMutex<Writer>> = spin::Once::new();
 
 /// Initializes the global VGA writer.
 ///
 /// Idempotent - safe to call multiple times.
 pub fn init() {
-    WRITER.call_once(|| Mutex::new(Writer::new()));
+    WRITER.call_once(|| TrackedMutex::new("vga", Writer::new()));
 }
 
 /// Returns a reference to the VGA writer, initializing if necessary.
-fn get_writer() -> &'static Mutex<Writer> {
+fn get_writer() -> &'static TrackedMutex<Writer> {
     init();
     WRITER.get().expect("VGA writer not initialized")
 }
 
 /// VGA text mode writer.
 ///
-/// Manages cursor position and color state for writing to the VGA buffer.
+/// Manages cursor position and color state. Text is drawn into a shadow
+/// copy of the screen; rows that changed are copied to the VGA buffer by
+/// `flush`, either after every write or, once deferred, by the console task
+/// once per tick.
 pub struct Writer {
     /// Current column position (0 to BUFFER_WIDTH-1).
     column_position: usize,
+    /// Current row; output starts on the bottom one and only moves up
+    /// with `ESC [ A`.
+    row_position: usize,
+    /// Escape sequence being read.
+    escape: Escape,
     /// Current color code for new characters.
     color_code: ColorCode,
+    /// What the screen should show.
+    shadow: Buffer,
+    /// Rows of `shadow` not yet copied to the screen, one bit per row.
+    dirty: u32,
+    /// Whether flushing is left to `flush` instead of done on every write.
+    deferred: bool,
     /// Pointer to the VGA buffer.
     ///
     /// SAFETY: This pointer is valid for the lifetime of the kernel.
@@ -122,9 +161,20 @@ unsafe impl Send for Writer {}
 impl Writer {
     /// Creates a new VGA writer.
     fn new() -> Self {
+        let color_code = ColorCode::new(Color::White, Color::Black);
         Writer {
             column_position: 0,
-            color_code: ColorCode::new(Color::White, Color::Black),
+            row_position: BUFFER_HEIGHT - 1,
+            escape: Escape::None,
+            color_code,
+            shadow: Buffer {
+                chars: [[ScreenChar {
+                    ascii_character: b' ',
+                    color_code,
+                }; BUFFER_WIDTH]; BUFFER_HEIGHT],
+            },
+            dirty: 0,
+            deferred: false,
             // SAFETY: VGA_BUFFER_ADDR (0xB8000) is the standard VGA text buffer
             // address on x86 systems. This memory is always present and mapped
             // when running on x86 hardware or in QEMU.
@@ -137,54 +187,92 @@ impl Writer {
         self.color_code = ColorCode::new(foreground, background);
     }
 
-    /// Writes a single byte to the VGA buffer.
+    /// Writes a single byte to the screen.
     ///
-    /// Handles newlines and automatic line wrapping.
+    /// Handles newlines, carriage returns and automatic line wrapping.
     pub fn write_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
+            b'\r' => self.column_position = 0,
             byte => {
                 // Check bounds BEFORE writing to prevent overflow
                 if self.column_position >= BUFFER_WIDTH {
                     self.new_line();
                 }
 
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column_position;
-
-                // SAFETY: We've verified col < BUFFER_WIDTH above, and row is
-                // constant at BUFFER_HEIGHT - 1. The buffer pointer was validated
-                // at construction time. Using volatile write because the VGA buffer
-                // is memory-mapped I/O that may be read by hardware at any time.
-                unsafe {
-                    ptr::write_volatile(
-                        &mut (*self.buffer).chars[row][col],
-                        ScreenChar {
-                            ascii_character: byte,
-                            color_code: self.color_code,
-                        },
-                    );
-                }
+                let row = self.row_position;
+                self.shadow.chars[row][self.column_position] = ScreenChar {
+                    ascii_character: byte,
+                    color_code: self.color_code,
+                };
+                self.dirty |= 1 << row;
                 self.column_position += 1;
             }
         }
     }
 
-    /// Scrolls the screen up by one line.
+    /// Moves to the start of the next row, scrolling the screen up by one
+    /// line on the bottom row.
     fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                // SAFETY: row is in range [1, BUFFER_HEIGHT), col is in range [0, BUFFER_WIDTH).
-                // row-1 is in range [0, BUFFER_HEIGHT-1). All indices are valid.
-                // Using volatile operations because VGA buffer is memory-mapped I/O.
-                unsafe {
-                    let character = ptr::read_volatile(&(*self.buffer).chars[row][col]);
-                    ptr::write_volatile(&mut (*self.buffer).chars[row - 1][col], character);
+        self.column_position = 0;
+        if self.row_position < BUFFER_HEIGHT - 1 {
+            self.row_position += 1;
+            return;
+        }
+        self.shadow.chars.copy_within(1.., 0);
+        self.clear_row(BUFFER_HEIGHT - 1);
+        self.dirty = ALL_ROWS;
+    }
+
+    /// Feeds one character of an escape sequence, returning `false` once
+    /// `c` is not part of one.
+    fn escape(&mut self, c: char) -> bool {
+        self.escape = match (self.escape, c) {
+            (Escape::None, '\x1b') => Escape::Started,
+            (Escape::None, _) => return false,
+            (Escape::Started, '[') => Escape::Csi(0),
+            (Escape::Csi(n), '0'..='9') => {
+                let digit = c as usize - '0' as usize;
+                Escape::Csi(n.saturating_mul(10).saturating_add(digit))
+            }
+            (Escape::Csi(n), _) => {
+                self.control(c, n);
+                Escape::None
+            }
+            (Escape::Started, _) => Escape::None,
+        };
+        true
+    }
+
+    /// Carries out the control sequence `ESC [ n final`.
+    fn control(&mut self, final_byte: char, n: usize) {
+        let count = n.max(1);
+        match final_byte {
+            'A' => self.row_position = self.row_position.saturating_sub(count),
+            'B' => self.row_position = (self.row_position + count).min(BUFFER_HEIGHT - 1),
+            'C' => self.column_position = (self.column_position + count).min(BUFFER_WIDTH - 1),
+            'D' => self.column_position = self.column_position.saturating_sub(count),
+            'J' => {
+                self.clear_from_cursor();
+                for row in self.row_position + 1..BUFFER_HEIGHT {
+                    self.clear_row(row);
                 }
             }
+            'K' => self.clear_from_cursor(),
+            _ => {}
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
-        self.column_position = 0;
+    }
+
+    /// Blanks the current row from the cursor to its end.
+    fn clear_from_cursor(&mut self) {
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        let row = self.row_position;
+        let column = self.column_position.min(BUFFER_WIDTH);
+        self.shadow.chars[row][column..].fill(blank);
+        self.dirty |= 1 << row;
     }
 
     /// Clears a single row by filling it with spaces.
@@ -195,13 +283,8 @@ impl Writer {
             ascii_character: b' ',
             color_code: self.color_code,
         };
-        for col in 0..BUFFER_WIDTH {
-            // SAFETY: row is asserted to be < BUFFER_HEIGHT, col is in [0, BUFFER_WIDTH).
-            // Using volatile write because VGA buffer is memory-mapped I/O.
-            unsafe {
-                ptr::write_volatile(&mut (*self.buffer).chars[row][col], blank);
-            }
-        }
+        self.shadow.chars[row] = [blank; BUFFER_WIDTH];
+        self.dirty |= 1 << row;
     }
 
     /// Clears the entire screen.
@@ -210,19 +293,57 @@ impl Writer {
             self.clear_row(row);
         }
         self.column_position = 0;
+        self.row_position = BUFFER_HEIGHT - 1;
+        self.commit();
+    }
+
+    /// Whether the shadow buffer has changes not yet on screen.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty != 0
+    }
+
+    /// Copies the changed rows to the VGA buffer.
+    pub fn flush(&mut self) {
+        for row in 0..BUFFER_HEIGHT {
+            if self.dirty & (1 << row) == 0 {
+                continue;
+            }
+            for col in 0..BUFFER_WIDTH {
+                // SAFETY: row < BUFFER_HEIGHT and col < BUFFER_WIDTH, and the
+                // buffer pointer was validated at construction time. Using
+                // volatile writes because the VGA buffer is memory-mapped I/O.
+                unsafe {
+                    ptr::write_volatile(
+                        &mut (*self.buffer).chars[row][col],
+                        self.shadow.chars[row][col],
+                    );
+                }
+            }
+        }
+        self.dirty = 0;
+    }
+
+    /// Flushes unless flushing is deferred.
+    fn commit(&mut self) {
+        if !self.deferred {
+            self.flush();
+        }
     }
 }
 
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for byte in s.bytes() {
-            match byte {
-                // Printable ASCII or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // Non-printable: show placeholder
-                _ => self.write_byte(0xfe),
+        for c in s.chars() {
+            if self.escape(c) {
+                continue;
+            }
+            match c {
+                '\n' | '\r' => self.write_byte(c as u8),
+                // Best-effort code page 437 glyph, else a placeholder
+                _ => self.write_byte(cp437::encode(c).unwrap_or(cp437::PLACEHOLDER)),
             }
         }
+        self.commit();
         Ok(())
     }
 }
@@ -255,16 +376,66 @@ macro_rules! println {
 /// Internal print function used by macros.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
+    if crate::terminal::io::route_print(args) {
+        return;
+    }
     let writer = get_writer();
     writer.lock().write_fmt(args).expect("vga write failed");
 }
 
 /// Sets the VGA output color.
+///
+/// Like `print!`, this applies to the current task's terminal sink if it
+/// has one attached (see `terminal::io`).
 pub fn set_color(foreground: Color, background: Color) {
+    if crate::terminal::io::route_set_color(foreground, background) {
+        return;
+    }
     get_writer().lock().set_color(foreground, background);
 }
 
 /// Clears the VGA screen.
 pub fn clear_screen() {
+    if crate::terminal::io::route_clear() {
+        return;
+    }
     get_writer().lock().clear_screen();
 }
+
+/// Writes to the screen only if the writer is free, bypassing any terminal
+/// sink. Returns whether the text was written.
+///
+/// For contexts that must not wait on the lock (see `klog::irq`).
+pub fn try_write_str(s: &str) -> bool {
+    let Some(writer) = WRITER.get() else {
+        return false;
+    };
+    match writer.try_lock() {
+        Some(mut writer) => {
+            let _ = fmt::Write::write_str(&mut *writer, s);
+            writer.flush();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Copies pending changes to the screen. Called by the console task.
+pub fn flush() {
+    if let Some(writer) = WRITER.get() {
+        let mut writer = writer.lock();
+        if writer.is_dirty() {
+            writer.flush();
+        }
+    }
+}
+
+/// Leave flushing to `flush` (true) or draw every write at once (false).
+///
+/// The console task defers once it runs; the panic handler switches back so
+/// its report appears even though no task will run again.
+pub fn set_deferred(deferred: bool) {
+    let mut writer = get_writer().lock();
+    writer.deferred = deferred;
+    writer.flush();
+}
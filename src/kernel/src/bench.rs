@@ -0,0 +1,470 @@
+//! Micro-benchmarks of core kernel paths.
+//!
+//! `bench` runs each benchmark for a fixed number of operations and prints
+//! one row per benchmark, so the numbers of two builds on the same machine
+//! can be compared directly:
+//!
+//! - `heap`: allocate and free a block, cycling through `HEAP_SIZES`
+//! - `switch`: two tasks handing the CPU to each other on an executor
+//! - `mutex`: two tasks contending for an `AsyncMutex`
+//! - `ramfs`: `RAMFS_CHUNK`-byte reads from a RAM filesystem file
+//! - `hostcall`: a WASM module calling `sp_clock_monotonic_ms`
+//! - `fsread`: a WASM module reading a file with one `sp_fs_read` per read
+//! - `batch`: the same reads, `BATCH_OPS` per `sp_batch` call
+//! - `loopback`: full-size frames sent and received through the loopback
+//!   device
+//!
+//! The WASM benchmarks need the `wasm` feature and `loopback` needs `net`;
+//! a kernel built without them leaves them out.
+//!
+//! Times come from the TSC (cycles) and the kernel clock (milliseconds);
+//! rates are left out of runs shorter than a clock tick.
+
+use crate::arch::x86_64::rdtsc;
+use crate::fs::ramfs::RamFs;
+use crate::fs::FileSystem;
+#[cfg(feature = "wasm")]
+use crate::fs::ROOT_FS;
+#[cfg(feature = "net")]
+use crate::net::device::QemuE1000;
+use crate::sync::AsyncMutex;
+use crate::task::executor::Executor;
+use crate::task::{yield_now, Task};
+#[cfg(feature = "wasm")]
+use crate::wasm::runtime::poll_slice;
+#[cfg(feature = "wasm")]
+use crate::wasm::{WasmEngine, WasmProcess};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::hint::black_box;
+use core::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "wasm")]
+use core::task::{Context, Poll};
+#[cfg(feature = "net")]
+use smoltcp::phy::{Device, RxToken, TxToken};
+#[cfg(feature = "wasm")]
+use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType};
+
+/// Block sizes the `heap` benchmark cycles through.
+pub const HEAP_SIZES: [usize; 4] = [16, 64, 256, 1024];
+
+/// Size of the file the `ramfs` benchmark reads.
+pub const RAMFS_FILE: usize = 64 * 1024;
+
+/// Bytes per `ramfs` read.
+pub const RAMFS_CHUNK: usize = 4096;
+
+/// Frame size of the `loopback` benchmark (Ethernet header and MTU).
+pub const FRAME_SIZE: usize = 1514;
+
+/// Frames the loopback device queues in each direction.
+#[cfg(feature = "net")]
+const LOOPBACK_BATCH: usize = 16;
+
+/// Host calls per invocation of the `hostcall` module.
+pub const HOST_CALLS: u64 = 10_000;
+
+/// Exports `run: () -> i32`, which calls `sp_clock_monotonic_ms`
+/// `HOST_CALLS` times in a loop.
+#[cfg(feature = "wasm")]
+#[rustfmt::skip]
+const HOSTCALL_MODULE: &[u8] = &[
+    // Header
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    // Types: () -> i64, () -> i32
+    0x01, 0x09, 0x02, 0x60, 0x00, 0x01, 0x7e, 0x60, 0x00, 0x01, 0x7f,
+    // Import env.sp_clock_monotonic_ms as function 0
+    0x02, 0x1d, 0x01, 0x03, b'e', b'n', b'v',
+    0x15, b's', b'p', b'_', b'c', b'l', b'o', b'c', b'k', b'_', b'm', b'o', b'n', b'o', b't',
+    b'o', b'n', b'i', b'c', b'_', b'm', b's', 0x00, 0x00,
+    // Function 1 has type 1 and is exported as `run`
+    0x03, 0x02, 0x01, 0x01,
+    0x07, 0x07, 0x01, 0x03, b'r', b'u', b'n', 0x00, 0x01,
+    // Code: one i32 local, counting calls
+    0x0a, 0x1c, 0x01, 0x1a, 0x01, 0x01, 0x7f,
+    // loop; call 0; drop
+    0x03, 0x40, 0x10, 0x00, 0x1a,
+    // local 0 += 1; br_if 0 while local 0 < 10000
+    0x20, 0x00, 0x41, 0x01, 0x6a, 0x22, 0x00, 0x41, 0x90, 0xce, 0x00, 0x49, 0x0d, 0x00,
+    // end loop; return local 0
+    0x0b, 0x20, 0x00, 0x0b,
+];
+
+/// File the `fsread` and `batch` modules read.
+#[cfg(feature = "wasm")]
+const FS_BENCH_FILE: &str = "/tmp/bench";
+
+/// Bytes per `fsread` and `batch` read.
+pub const FS_READ_SIZE: usize = 64;
+
+/// Reads per invocation of the `fsread` and `batch` modules, few enough
+/// for one slice's fuel.
+pub const FS_READS: u64 = 32;
+
+/// Reads per `sp_batch` call in the `batch` module.
+pub const BATCH_OPS: u64 = 16;
+
+/// Exports `run: () -> i32`, which calls `sp_fs_read(1, 0, 64, 0)`
+/// `FS_READS` times in a loop.
+#[cfg(feature = "wasm")]
+#[rustfmt::skip]
+const FSREAD_MODULE: &[u8] = &[
+    // Header
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    // Types: (i64, i32, i32, i32) -> i32, () -> i32
+    0x01, 0x0d, 0x02, 0x60, 0x04, 0x7e, 0x7f, 0x7f, 0x7f, 0x01, 0x7f, 0x60, 0x00, 0x01, 0x7f,
+    // Import env.sp_fs_read as function 0
+    0x02, 0x12, 0x01, 0x03, b'e', b'n', b'v',
+    0x0a, b's', b'p', b'_', b'f', b's', b'_', b'r', b'e', b'a', b'd', 0x00, 0x00,
+    // Function 1 has type 1; one page of memory
+    0x03, 0x02, 0x01, 0x01,
+    0x05, 0x03, 0x01, 0x00, 0x01,
+    // Export `run` and `memory`
+    0x07, 0x10, 0x02, 0x03, b'r', b'u', b'n', 0x00, 0x01,
+    0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00,
+    // Code: one i32 local, counting calls
+    0x0a, 0x23, 0x01, 0x21, 0x01, 0x01, 0x7f,
+    // loop; call 0 (1, 0, 64, 0); drop
+    0x03, 0x40, 0x42, 0x01, 0x41, 0x00, 0x41, 0xc0, 0x00, 0x41, 0x00, 0x10, 0x00, 0x1a,
+    // local 0 += 1; br_if 0 while local 0 < 32
+    0x20, 0x00, 0x41, 0x01, 0x6a, 0x22, 0x00, 0x41, 0x20, 0x49, 0x0d, 0x00,
+    // end loop; return local 0
+    0x0b, 0x20, 0x00, 0x0b,
+];
+
+/// Exports `run: () -> i32`, which fills 16 `sp_batch` records at 1024
+/// with `sp_fs_read(1, 0, 64, 0)` and submits them twice.
+#[cfg(feature = "wasm")]
+#[rustfmt::skip]
+const BATCH_MODULE: &[u8] = &[
+    // Header
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    // Types: (i32, i32) -> i32, () -> i32
+    0x01, 0x0b, 0x02, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, 0x60, 0x00, 0x01, 0x7f,
+    // Import env.sp_batch as function 0
+    0x02, 0x10, 0x01, 0x03, b'e', b'n', b'v',
+    0x08, b's', b'p', b'_', b'b', b'a', b't', b'c', b'h', 0x00, 0x00,
+    // Function 1 has type 1; one page of memory
+    0x03, 0x02, 0x01, 0x01,
+    0x05, 0x03, 0x01, 0x00, 0x01,
+    // Export `run` and `memory`
+    0x07, 0x10, 0x02, 0x03, b'r', b'u', b'n', 0x00, 0x01,
+    0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00,
+    // Code: one i32 local, the record offset
+    0x0a, 0x3e, 0x01, 0x3c, 0x01, 0x01, 0x7f,
+    // loop; op = 1 (FS_READ), len = 64, cap = 1 for the record at 1024 + local 0
+    0x03, 0x40, 0x20, 0x00, 0x41, 0x01, 0x36, 0x02, 0x80, 0x08,
+    0x20, 0x00, 0x41, 0xc0, 0x00, 0x36, 0x02, 0x84, 0x08,
+    0x20, 0x00, 0x42, 0x01, 0x37, 0x03, 0x88, 0x08,
+    // local 0 += 32; br_if 0 while local 0 < 512; end loop
+    0x20, 0x00, 0x41, 0x20, 0x6a, 0x22, 0x00, 0x41, 0x80, 0x04, 0x49, 0x0d, 0x00, 0x0b,
+    // return sp_batch(1024, 16) + sp_batch(1024, 16)
+    0x41, 0x80, 0x08, 0x41, 0x10, 0x10, 0x00,
+    0x41, 0x80, 0x08, 0x41, 0x10, 0x10, 0x00, 0x6a, 0x0b,
+];
+
+/// A benchmark `bench` can run.
+pub struct Benchmark {
+    /// Name given to `bench`.
+    pub name: &'static str,
+    /// What one operation is.
+    pub op: &'static str,
+    /// Operations in a default run.
+    pub default_ops: u64,
+    run: fn(u64) -> Sample,
+}
+
+impl Benchmark {
+    /// Run the benchmark for at least `ops` operations.
+    pub fn run(&self, ops: u64) -> Sample {
+        Sample {
+            name: self.name,
+            ..(self.run)(ops)
+        }
+    }
+}
+
+/// All benchmarks, in the order `bench` runs them.
+pub const BENCHMARKS: &[Benchmark] = &[
+    Benchmark {
+        name: "heap",
+        op: "alloc+free",
+        default_ops: 200_000,
+        run: bench_heap,
+    },
+    Benchmark {
+        name: "switch",
+        op: "task switch",
+        default_ops: 200_000,
+        run: bench_switch,
+    },
+    Benchmark {
+        name: "mutex",
+        op: "contended lock",
+        default_ops: 50_000,
+        run: bench_mutex,
+    },
+    Benchmark {
+        name: "ramfs",
+        op: "4 KiB read",
+        default_ops: 50_000,
+        run: bench_ramfs,
+    },
+    #[cfg(feature = "wasm")]
+    Benchmark {
+        name: "hostcall",
+        op: "host call",
+        default_ops: 10 * HOST_CALLS,
+        run: bench_hostcall,
+    },
+    #[cfg(feature = "wasm")]
+    Benchmark {
+        name: "fsread",
+        op: "64 B read call",
+        default_ops: 50_000,
+        run: |ops| bench_fs_module(FSREAD_MODULE, ops),
+    },
+    #[cfg(feature = "wasm")]
+    Benchmark {
+        name: "batch",
+        op: "64 B batched read",
+        default_ops: 50_000,
+        run: |ops| bench_fs_module(BATCH_MODULE, ops),
+    },
+    #[cfg(feature = "net")]
+    Benchmark {
+        name: "loopback",
+        op: "frame",
+        default_ops: 50_000,
+        run: bench_loopback,
+    },
+];
+
+/// Look up a benchmark by name.
+pub fn find(name: &str) -> Option<&'static Benchmark> {
+    BENCHMARKS.iter().find(|bench| bench.name == name)
+}
+
+/// The result of one benchmark run.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    /// Benchmark name.
+    pub name: &'static str,
+    /// Operations performed.
+    pub ops: u64,
+    /// Payload bytes moved, for throughput benchmarks.
+    pub bytes: u64,
+    /// TSC cycles taken.
+    pub cycles: u64,
+    /// Milliseconds taken, at clock tick resolution.
+    pub ms: u64,
+}
+
+impl Sample {
+    /// Average cycles per operation.
+    pub fn cycles_per_op(&self) -> u64 {
+        self.cycles / self.ops.max(1)
+    }
+
+    /// Operations per second; `None` if the run was shorter than a tick.
+    pub fn ops_per_sec(&self) -> Option<u64> {
+        (self.ms > 0).then(|| self.ops * 1000 / self.ms)
+    }
+
+    /// Bytes per second; `None` without payload or for runs shorter than
+    /// a tick.
+    pub fn bytes_per_sec(&self) -> Option<u64> {
+        (self.ms > 0 && self.bytes > 0).then(|| self.bytes * 1000 / self.ms)
+    }
+}
+
+/// Measures the time from `start` to `stop`.
+struct Stopwatch {
+    cycles: u64,
+    ms: u64,
+}
+
+impl Stopwatch {
+    fn start() -> Self {
+        Self {
+            cycles: rdtsc(),
+            ms: crate::time::now_ms(),
+        }
+    }
+
+    fn stop(self, ops: u64, bytes: u64) -> Sample {
+        Sample {
+            name: "",
+            ops,
+            bytes,
+            cycles: rdtsc().saturating_sub(self.cycles),
+            ms: crate::time::now_ms() - self.ms,
+        }
+    }
+}
+
+fn bench_heap(ops: u64) -> Sample {
+    let watch = Stopwatch::start();
+    for i in 0..ops {
+        let size = HEAP_SIZES[i as usize % HEAP_SIZES.len()];
+        black_box(Vec::<u8>::with_capacity(size));
+    }
+    watch.stop(ops, 0)
+}
+
+fn bench_switch(ops: u64) -> Sample {
+    let turn = Arc::new(AtomicU64::new(0));
+    let mut executor = Executor::new();
+    for side in 0..2 {
+        let turn = turn.clone();
+        executor.spawn(Task::new(async move {
+            loop {
+                let current = turn.load(Ordering::Relaxed);
+                if current >= ops {
+                    break;
+                }
+                if current % 2 == side {
+                    turn.store(current + 1, Ordering::Relaxed);
+                }
+                yield_now().await;
+            }
+        }));
+    }
+    let watch = Stopwatch::start();
+    executor.run_until_idle();
+    watch.stop(turn.load(Ordering::Relaxed), 0)
+}
+
+fn bench_mutex(ops: u64) -> Sample {
+    let counter = AsyncMutex::new_shared(0u64);
+    let mut executor = Executor::new();
+    for _ in 0..2 {
+        let counter = counter.clone();
+        executor.spawn(Task::new(async move {
+            loop {
+                let mut count = counter.lock().await;
+                if *count >= ops {
+                    break;
+                }
+                *count += 1;
+                // Hold the lock across a switch so the other task waits
+                yield_now().await;
+                drop(count);
+                yield_now().await;
+            }
+        }));
+    }
+    let watch = Stopwatch::start();
+    executor.run_until_idle();
+    let ops = counter.try_lock().map_or(0, |count| *count);
+    watch.stop(ops, 0)
+}
+
+fn bench_ramfs(ops: u64) -> Sample {
+    let fs = RamFs::new();
+    fs.add_file("bench", &[0xA5; RAMFS_FILE]);
+    let Ok(handle) = fs.open("bench") else {
+        return Stopwatch::start().stop(0, 0);
+    };
+    let mut buffer = [0u8; RAMFS_CHUNK];
+    let chunks = (RAMFS_FILE / RAMFS_CHUNK) as u64;
+    let mut bytes = 0;
+
+    let watch = Stopwatch::start();
+    for i in 0..ops {
+        let offset = (i % chunks) as usize * RAMFS_CHUNK;
+        bytes += fs.read(handle, &mut buffer, offset).unwrap_or(0) as u64;
+    }
+    let sample = watch.stop(ops, bytes);
+    fs.close(handle);
+    sample
+}
+
+#[cfg(feature = "wasm")]
+fn bench_hostcall(ops: u64) -> Sample {
+    let engine = WasmEngine::new();
+    let timer = Capability::new(CapabilityType::Timer, CapabilityRights::READ);
+    let Ok(mut process) = engine.spawn_process_with_caps(HOSTCALL_MODULE, alloc::vec![timer])
+    else {
+        return Stopwatch::start().stop(0, 0);
+    };
+    let watch = Stopwatch::start();
+    let calls = run_module(&mut process, ops.div_ceil(HOST_CALLS)) * HOST_CALLS;
+    watch.stop(calls, 0)
+}
+
+/// Run `module`, which reads `FS_READS` times from the file capability
+/// with handle 1 (the first granted), until it has read at least `ops`
+/// times.
+#[cfg(feature = "wasm")]
+fn bench_fs_module(module: &[u8], ops: u64) -> Sample {
+    ROOT_FS.add_file(FS_BENCH_FILE, &[0xA5; FS_READ_SIZE]);
+    let Ok(handle) = ROOT_FS.open(FS_BENCH_FILE) else {
+        return Stopwatch::start().stop(0, 0);
+    };
+    let file = Capability::new(
+        CapabilityType::File(u64::from(handle.0)),
+        CapabilityRights::READ,
+    );
+    let engine = WasmEngine::new();
+    let Ok(mut process) = engine.spawn_process_with_caps(module, alloc::vec![file]) else {
+        ROOT_FS.close(handle);
+        return Stopwatch::start().stop(0, 0);
+    };
+
+    let watch = Stopwatch::start();
+    let reads = run_module(&mut process, ops.div_ceil(FS_READS)) * FS_READS;
+    let sample = watch.stop(reads, reads * FS_READ_SIZE as u64);
+    ROOT_FS.close(handle);
+    sample
+}
+
+/// Call `run` in `process` `runs` times, returning how many finished.
+#[cfg(feature = "wasm")]
+fn run_module(process: &mut WasmProcess, runs: u64) -> u64 {
+    let waker = futures_util::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    for run in 0..runs {
+        // Runs in slices, like a process task, suspending as fuel runs out
+        let mut suspended = None;
+        let result = loop {
+            if let Poll::Ready(result) = poll_slice(process, "run", &mut suspended, &mut cx) {
+                break result;
+            }
+        };
+        if result.is_err() {
+            return run;
+        }
+    }
+    runs
+}
+
+#[cfg(feature = "net")]
+fn bench_loopback(ops: u64) -> Sample {
+    let mut device = QemuE1000::new();
+    let now = crate::services::now();
+    let mut frames = 0;
+    let mut bytes = 0;
+
+    let watch = Stopwatch::start();
+    while frames < ops {
+        for _ in 0..LOOPBACK_BATCH {
+            let Some(token) = device.transmit(now) else {
+                break;
+            };
+            token.consume(FRAME_SIZE, |frame| frame.fill(0x5A));
+        }
+        for frame in device.drain_tx() {
+            device.inject_rx(&frame);
+        }
+        let received = frames;
+        while let Some((token, _)) = device.receive(now) {
+            bytes += token.consume(|frame| black_box(frame).len()) as u64;
+            frames += 1;
+        }
+        if frames == received {
+            break;
+        }
+    }
+    watch.stop(frames, bytes)
+}
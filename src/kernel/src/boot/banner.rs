@@ -1,18 +1,73 @@
 //! Boot banner and branding.
+//!
+//! The banner is the message of the day from `/etc/motd` if there is one,
+//! else the built-in logo, followed by the version, the commit and the
+//! profile the kernel was built from (see `buildinfo`). The boot banner comes before the filesystem is
+//! mounted, so it is always the logo; the local shell shows the message
+//! of the day before its first prompt, telnet sessions and `motd` with
+//! the rest of the banner.
+//!
+//! A message of the day can be built in: `SOVELMA_MOTD` names a file the
+//! build script embeds, which is installed as `/etc/motd` at boot.
 
-use crate::arch::x86_64::vga::{self, Color};
-use crate::println;
+use crate::buildinfo;
+use crate::fs::ROOT_FS;
+use crate::terminal::theme::{self, Role};
+use crate::{print, println};
+
+/// File the message of the day is read from.
+pub const MOTD_PATH: &str = "/etc/motd";
+
+/// The built-in message of the day; empty without `SOVELMA_MOTD`.
+static BUILTIN_MOTD: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/motd.txt"));
+
+/// Install the built-in message of the day as `/etc/motd`, if there is
+/// one.
+pub fn install_motd() {
+    if !BUILTIN_MOTD.is_empty() {
+        ROOT_FS.add_file(MOTD_PATH, BUILTIN_MOTD);
+    }
+}
+
+/// Print `/etc/motd`. Returns `false`, printing nothing, if it is missing
+/// or empty.
+pub fn print_motd() -> bool {
+    let Ok(motd) = crate::fs::read_file(MOTD_PATH) else {
+        return false;
+    };
+    if motd.is_empty() {
+        return false;
+    }
+    let text = alloc::string::String::from_utf8_lossy(&motd);
+    print!("{}", text);
+    if !text.ends_with('\n') {
+        println!();
+    }
+    true
+}
 
 /// Print the SovelmaOS boot banner.
 pub fn print_banner() {
-    vga::set_color(Color::Cyan, Color::Black);
+    if !print_motd() {
+        print_logo();
+    }
+    println!(
+        " SovelmaOS v{} ({}, {})",
+        buildinfo::VERSION,
+        buildinfo::GIT_HASH,
+        buildinfo::PROFILE
+    );
+    println!();
+}
+
+/// Print the built-in logo.
+fn print_logo() {
+    theme::set(Role::Accent);
     println!("  ____                 _              ___  ____  ");
     println!(" / ___|  _____   _____| |_ __ ___   / _ \\/ ___| ");
     println!(" \\___ \\ / _ \\ \\ / / _ \\ | '_ ` _ \\ | | | \\___ \\ ");
     println!("  ___) | (_) \\ V /  __/ | | | | | || |_| |___) |");
     println!(" |____/ \\___/ \\_/ \\___|_|_| |_| |_| \\___/|____/ ");
     println!();
-    vga::set_color(Color::White, Color::Black);
-    println!(" SovelmaOS v0.1.0");
-    println!();
+    theme::reset();
 }
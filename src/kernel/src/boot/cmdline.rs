@@ -0,0 +1,34 @@
+//! Kernel command line.
+//!
+//! bootloader 0.9 does not hand the kernel a command line, so it is baked
+//! in at build time from the `SOVELMA_CMDLINE` environment variable:
+//!
+//! ```text
+//! SOVELMA_CMDLINE="net=slip ip=10.0.3.2/24 gw=10.0.3.1" cargo run
+//! ```
+//!
+//! Options are whitespace-separated `key=value` pairs or bare flags.
+
+/// The command line the kernel was built with.
+pub const CMDLINE: &str = match option_env!("SOVELMA_CMDLINE") {
+    Some(line) => line,
+    None => "",
+};
+
+/// Get the value of option `key` from the kernel command line.
+///
+/// A bare flag yields `Some("")`. The last occurrence wins.
+pub fn get(key: &str) -> Option<&'static str> {
+    lookup(CMDLINE, key)
+}
+
+/// Get the value of option `key` from `line`.
+pub fn lookup<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    line.split_whitespace()
+        .rev()
+        .find_map(|option| match option.split_once('=') {
+            Some((k, value)) if k == key => Some(value),
+            None if option == key => Some(""),
+            _ => None,
+        })
+}
@@ -0,0 +1,201 @@
+//! Crash-loop protection and safe mode.
+//!
+//! A kernel that panics early in boot, every boot, is stuck: whatever
+//! breaks it comes up again before there is a shell to fix it from. So
+//! each boot counts as failed from `begin` until `complete`, and after
+//! `CRASH_LOOP_LIMIT` failed boots in a row the kernel boots in safe
+//! mode: no network device, DHCP, telnet shell or control channel, just
+//! the local shell under a banner saying why (`print_banner`). The `safe`
+//! flag on the command line asks for safe mode outright.
+//!
+//! There is no persistent filesystem yet, so the count lives in a
+//! `BootRecord` in RAM that a warm reset leaves alone: the frame
+//! `memory::persistent_frame` keeps out of allocation. The bootloader
+//! reloads the kernel image on every boot, so a static would not do. A
+//! cold boot finds no record there and starts from zero. The panic
+//! handler also keeps the message of a panic before `complete` in the
+//! record (`record_panic`), for the banner of the next boot.
+
+use super::panic::FixedWriter;
+use crate::println;
+use crate::terminal::theme::{self, Role};
+use alloc::string::String;
+use bootloader::BootInfo;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering};
+
+/// Failed boots in a row after which the kernel boots in safe mode.
+pub const CRASH_LOOP_LIMIT: u32 = 3;
+
+/// Bytes of a panic message the record keeps.
+pub const PANIC_MESSAGE_MAX: usize = 256;
+
+/// Marks a `BootRecord` the kernel wrote; anything else in its frame is
+/// what a cold boot left there.
+const MAGIC: u64 = 0x534f_564c_4d41_4254;
+
+/// What the kernel keeps across warm resets.
+///
+/// Every field is a plain integer, so any bytes are a valid record and
+/// `MAGIC` tells one the kernel wrote.
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct BootRecord {
+    magic: u64,
+    /// Boots in a row that started and did not complete.
+    failed_boots: u32,
+    /// Length of the message in `panic`.
+    panic_len: u32,
+    /// The message of the last panic, cut to fit.
+    panic: [u8; PANIC_MESSAGE_MAX],
+}
+
+impl BootRecord {
+    /// A record of no failed boots.
+    pub const fn new() -> Self {
+        Self {
+            magic: MAGIC,
+            failed_boots: 0,
+            panic_len: 0,
+            panic: [0; PANIC_MESSAGE_MAX],
+        }
+    }
+
+    /// Start a boot: reset the record if it is not one the kernel wrote,
+    /// and count the boot as failed until `complete`.
+    ///
+    /// Returns the number of boots in a row that failed before it.
+    pub fn start(&mut self) -> u32 {
+        if self.magic != MAGIC || self.panic_len as usize > PANIC_MESSAGE_MAX {
+            *self = Self::new();
+        }
+        let failed = self.failed_boots;
+        self.failed_boots = failed.saturating_add(1);
+        failed
+    }
+
+    /// Note that the boot completed, which forgets the failed ones.
+    pub fn complete(&mut self) {
+        self.failed_boots = 0;
+        self.panic_len = 0;
+    }
+
+    /// Keep `message` as the last panic's, cut to fit.
+    pub fn set_panic(&mut self, message: fmt::Arguments<'_>) {
+        let mut writer = FixedWriter::new(&mut self.panic);
+        let _ = writer.write_fmt(message);
+        self.panic_len = writer.as_bytes().len() as u32;
+    }
+
+    /// The message of the last panic since a boot last completed.
+    pub fn last_panic(&self) -> Option<&str> {
+        let bytes = self.panic.get(..self.panic_len as usize)?;
+        // Cutting to fit may have split a character
+        let text = match core::str::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).ok()?,
+        };
+        (!text.is_empty()).then_some(text)
+    }
+}
+
+impl Default for BootRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The record in the persistent frame; null before `begin` or without
+/// such a frame.
+static RECORD: AtomicPtr<BootRecord> = AtomicPtr::new(ptr::null_mut());
+
+/// Failed boots in a row before this one.
+static FAILED_BOOTS: AtomicU32 = AtomicU32::new(0);
+
+/// Whether this boot is in safe mode.
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Set by `complete`; later panics are not boot failures.
+static COMPLETED: AtomicBool = AtomicBool::new(false);
+
+/// Set by the first panic `record_panic` keeps.
+static PANIC_RECORDED: AtomicBool = AtomicBool::new(false);
+
+/// Run `f` on the record, if there is one.
+fn with_record<R>(f: impl FnOnce(&mut BootRecord) -> R) -> Option<R> {
+    let record = RECORD.load(Ordering::Acquire);
+    // SAFETY: `begin` stored a pointer to the persistent frame, which is
+    // mapped at the physical memory offset, never allocated and only
+    // touched here. Boot is single-threaded, and `record_panic` lets one
+    // panic in.
+    unsafe { record.as_mut() }.map(f)
+}
+
+/// Find the boot record and count this boot as failed until `complete`,
+/// then decide whether to boot in safe mode.
+///
+/// Needs neither the heap nor the page mapper, so it can run first.
+pub fn begin(boot_info: &'static BootInfo) {
+    if let Some(frame) = crate::memory::persistent_frame(&boot_info.memory_map) {
+        let addr = boot_info.physical_memory_offset + frame.start_address().as_u64();
+        RECORD.store(addr as *mut BootRecord, Ordering::Release);
+    }
+    let failed = with_record(BootRecord::start).unwrap_or(0);
+    FAILED_BOOTS.store(failed, Ordering::Relaxed);
+    let requested = super::cmdline::get("safe").is_some();
+    SAFE_MODE.store(requested || failed >= CRASH_LOOP_LIMIT, Ordering::Relaxed);
+}
+
+/// Note that the boot completed: the next one starts counting afresh.
+pub fn complete() {
+    COMPLETED.store(true, Ordering::Relaxed);
+    with_record(BootRecord::complete);
+}
+
+/// Keep the message of a panic during boot for the next boot's banner.
+///
+/// Called from the panic handler, so it takes no lock and allocates
+/// nothing; only the first panic is kept.
+pub fn record_panic(info: &PanicInfo) {
+    if COMPLETED.load(Ordering::Relaxed) || PANIC_RECORDED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    with_record(|record| record.set_panic(format_args!("{}", info)));
+}
+
+/// Whether this boot is in safe mode.
+pub fn safe_mode() -> bool {
+    SAFE_MODE.load(Ordering::Relaxed)
+}
+
+/// Boots in a row that failed before this one.
+pub fn failed_boots() -> u32 {
+    FAILED_BOOTS.load(Ordering::Relaxed)
+}
+
+/// The message of the last panic during a failed boot, until a boot
+/// completes.
+pub fn last_panic() -> Option<String> {
+    with_record(|record| record.last_panic().map(String::from)).flatten()
+}
+
+/// Say why the kernel is in safe mode and what it left out.
+pub fn print_banner() {
+    theme::set(Role::Warning);
+    println!("*** SAFE MODE ***");
+    theme::reset();
+    let failed = failed_boots();
+    if failed >= CRASH_LOOP_LIMIT {
+        println!("The last {} boots failed before completing.", failed);
+    } else {
+        println!("Requested on the kernel command line (safe).");
+    }
+    if let Some(message) = last_panic() {
+        println!("Last panic: {}", message);
+    }
+    println!("The network device, DHCP, the telnet shell and the control channel");
+    println!("are not started. Reboot to try a normal boot.");
+    println!();
+}
@@ -0,0 +1,146 @@
+//! Which subsystems came up, and which run degraded.
+//!
+//! Boot phases return a `Result`. Those the kernel cannot run without go
+//! through `require`, which stops the boot; the rest go through `check`,
+//! which logs the failure as `[FAIL]`, records the subsystem as degraded
+//! and lets the boot go on without it: with no NIC the network runs on
+//! loopback, with no filesystem the saved settings are not loaded.
+//! Subsystems that fail later call `degrade` directly.
+//!
+//! The record is shown by `sysinfo` (and `sysinfo --json`, which is how
+//! tools on the control channel read it). There is no kernel-wide event
+//! bus, so each degradation is also logged under target `boot` at error
+//! level, which reaches serial, `dmesg` and the remote syslog sink.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::Status;
+use crate::sync::TrackedMutex;
+
+/// A part of the kernel that can fail without stopping it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    /// The PS/2 keyboard controller.
+    Keyboard,
+    /// Ring 3 (syscall/sysret) support.
+    Usermode,
+    /// A linked driver.
+    Drivers,
+    /// The root filesystem.
+    Filesystem,
+    /// The network device.
+    Network,
+    /// The telnet shell.
+    RemoteShell,
+}
+
+impl Subsystem {
+    /// Short lowercase name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Keyboard => "keyboard",
+            Subsystem::Usermode => "usermode",
+            Subsystem::Drivers => "drivers",
+            Subsystem::Filesystem => "filesystem",
+            Subsystem::Network => "network",
+            Subsystem::RemoteShell => "telnet",
+        }
+    }
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A subsystem that failed, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Degradation {
+    /// What failed.
+    pub subsystem: Subsystem,
+    /// The error it failed with.
+    pub reason: String,
+}
+
+/// Degradations in the order they happened.
+#[derive(Debug, Default)]
+pub struct Health {
+    degraded: Vec<Degradation>,
+}
+
+impl Health {
+    /// A record with nothing degraded.
+    pub const fn new() -> Self {
+        Self {
+            degraded: Vec::new(),
+        }
+    }
+
+    /// Record that `subsystem` failed with `reason`.
+    pub fn record(&mut self, subsystem: Subsystem, reason: &dyn fmt::Display) {
+        self.degraded.push(Degradation {
+            subsystem,
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Whether `subsystem` failed.
+    pub fn is_degraded(&self, subsystem: Subsystem) -> bool {
+        self.degraded
+            .iter()
+            .any(|degradation| degradation.subsystem == subsystem)
+    }
+
+    /// Whether every subsystem came up.
+    pub fn is_healthy(&self) -> bool {
+        self.degraded.is_empty()
+    }
+
+    /// The degradations, oldest first.
+    pub fn degraded(&self) -> &[Degradation] {
+        &self.degraded
+    }
+}
+
+/// The kernel's record.
+static HEALTH: TrackedMutex<Health> = TrackedMutex::new("health", Health::new());
+
+/// Mark `subsystem` degraded: log `[FAIL]` with the reason and record it.
+pub fn degrade(subsystem: Subsystem, reason: &dyn fmt::Display) {
+    super::log(
+        Status::Fail,
+        &alloc::format!("{}: {} (running degraded)", subsystem, reason),
+    );
+    HEALTH.lock().record(subsystem, reason);
+}
+
+/// The value of a phase that may fail; on failure `subsystem` is marked
+/// degraded and the boot goes on without it.
+pub fn check<T, E: fmt::Display>(subsystem: Subsystem, result: Result<T, E>) -> Option<T> {
+    result.map_err(|e| degrade(subsystem, &e)).ok()
+}
+
+/// The value of a phase the kernel cannot run without; panics if it
+/// failed.
+///
+/// Nothing is logged first, as the logger may need what failed (the
+/// heap); the panic handler reports it.
+pub fn require<T, E: fmt::Debug>(what: &str, result: Result<T, E>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => panic!("{} failed: {:?}", what, e),
+    }
+}
+
+/// Whether `subsystem` is degraded.
+pub fn is_degraded(subsystem: Subsystem) -> bool {
+    HEALTH.lock().is_degraded(subsystem)
+}
+
+/// The degraded subsystems, oldest first.
+pub fn degraded() -> Vec<Degradation> {
+    HEALTH.lock().degraded().to_vec()
+}
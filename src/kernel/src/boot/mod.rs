@@ -1,10 +1,16 @@
 //! Boot logging with colored status indicators.
 //!
 //! Provides Linux-style boot messages with colored status brackets.
+//! Subsystems that fail to come up are recorded in `health`; boots that
+//! keep failing lead to safe mode (`crashloop`).
 
 pub mod banner;
+pub mod cmdline;
+pub mod crashloop;
+pub mod health;
+pub mod panic;
 
-use crate::arch::x86_64::vga::{self, Color};
+use crate::terminal::theme::{self, Role};
 use crate::{print, println};
 
 /// Boot status indicators.
@@ -22,10 +28,18 @@ pub enum Status {
 
 /// Log a boot stage with status.
 ///
-/// Format: `[ OK ] Message text`
+/// Format: `[ OK ] Message text`. The message is also passed to the kernel
+/// logger (target `boot`), so it reaches serial and any remote sink.
 pub fn log(status: Status, message: &str) {
     print_status(status);
     println!(" {}", message);
+
+    let level = match status {
+        Status::Fail => log::Level::Error,
+        Status::Warn => log::Level::Warn,
+        Status::Ok | Status::Info => log::Level::Info,
+    };
+    log::log!(target: "boot", level, "{}", message);
 }
 
 /// Log an indented detail line (for sub-items).
@@ -40,19 +54,19 @@ pub fn log_detail(message: &str) {
 /// Prints a blank line before the header for visual separation.
 pub fn log_section(name: &str) {
     println!();
-    vga::set_color(Color::LightCyan, Color::Black);
+    theme::set(Role::Info);
     println!("── {} ──", name);
-    vga::set_color(Color::White, Color::Black);
+    theme::reset();
 }
 
 fn print_status(status: Status) {
-    let (text, color) = match status {
-        Status::Ok => ("[ OK ]", Color::LightGreen),
-        Status::Fail => ("[FAIL]", Color::LightRed),
-        Status::Warn => ("[WARN]", Color::Yellow),
-        Status::Info => ("[INFO]", Color::LightCyan),
+    let (text, role) = match status {
+        Status::Ok => ("[ OK ]", Role::Success),
+        Status::Fail => ("[FAIL]", Role::Error),
+        Status::Warn => ("[WARN]", Role::Warning),
+        Status::Info => ("[INFO]", Role::Info),
     };
-    vga::set_color(color, Color::Black);
+    theme::set(role);
     print!("{}", text);
-    vga::set_color(Color::White, Color::Black);
+    theme::reset();
 }
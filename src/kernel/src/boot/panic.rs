@@ -0,0 +1,92 @@
+//! Panic handling before the kernel is fully up.
+//!
+//! Until the logger flags itself ready (`klog::mark_ready`) the heap and
+//! the VGA writer may not exist yet, and the serial lock may be held by the
+//! code that panicked. The early handler formats into a fixed static
+//! buffer and writes it to COM1 with raw port I/O, so it needs neither
+//! allocation nor locks.
+
+use crate::arch::x86_64::{self, serial};
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Size of the buffer the panic message is formatted into.
+///
+/// Longer messages are truncated.
+pub const EARLY_PANIC_BUFFER: usize = 512;
+
+/// Appended to a message that did not fit.
+const TRUNCATED: &[u8] = b" [...]";
+
+/// Set by the first panic to reach the early handler.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Formatting buffer, only touched by the first panicking context.
+static mut BUFFER: [u8; EARLY_PANIC_BUFFER] = [0; EARLY_PANIC_BUFFER];
+
+/// Formats into a fixed byte slice, dropping whatever does not fit.
+pub struct FixedWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    truncated: bool,
+}
+
+impl<'a> FixedWriter<'a> {
+    /// Create a writer over `buffer`.
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            buffer,
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    /// The bytes written so far.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
+    /// Whether output was dropped because the buffer was full.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl Write for FixedWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let free = self.buffer.len() - self.len;
+        let count = s.len().min(free);
+        self.buffer[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        if count < s.len() {
+            self.truncated = true;
+        }
+        // Keep formatting; the rest is dropped but the message still prints
+        Ok(())
+    }
+}
+
+/// Report a panic on COM1 without the heap, the screen or any lock, then
+/// halt.
+pub fn early(info: &PanicInfo) -> ! {
+    ::x86_64::instructions::interrupts::disable();
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        serial::write_raw(b"\nEARLY KERNEL PANIC while reporting a panic\n");
+        x86_64::halt_loop();
+    }
+
+    // SAFETY: PANICKING lets only the first panicking context get here, and
+    // interrupts are off, so this is the only reference to BUFFER.
+    let buffer = unsafe { &mut *core::ptr::addr_of_mut!(BUFFER) };
+    let mut writer = FixedWriter::new(buffer);
+    let _ = write!(writer, "{}", info);
+
+    serial::write_raw(b"\nEARLY KERNEL PANIC: ");
+    serial::write_raw(writer.as_bytes());
+    if writer.is_truncated() {
+        serial::write_raw(TRUNCATED);
+    }
+    serial::write_raw(b"\n");
+    x86_64::halt_loop()
+}
@@ -0,0 +1,37 @@
+//! What the kernel was built from.
+//!
+//! The build script sets these (see `build.rs`); `version` shows them, the
+//! boot banner the version and commit.
+
+use crate::terminal::json::Json;
+use alloc::vec::Vec;
+
+/// Version of the kernel crate.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Short hash of the commit, or `unknown` outside a git checkout.
+pub const GIT_HASH: &str = env!("SOVELMA_GIT_HASH");
+
+/// Cargo profile, e.g. `debug` or `release`.
+pub const PROFILE: &str = env!("SOVELMA_PROFILE");
+
+/// Enabled features, space-separated.
+pub const FEATURES: &str = env!("SOVELMA_FEATURES");
+
+/// `rustc --version` of the compiler.
+pub const RUSTC_VERSION: &str = env!("SOVELMA_RUSTC_VERSION");
+
+/// Enabled features, in name order.
+pub fn features() -> impl Iterator<Item = &'static str> {
+    FEATURES.split_whitespace()
+}
+
+/// Everything above as JSON.
+pub fn json() -> Json {
+    Json::object()
+        .with("version", VERSION)
+        .with("commit", GIT_HASH)
+        .with("profile", PROFILE)
+        .with("features", features().collect::<Vec<_>>())
+        .with("rustc", RUSTC_VERSION)
+}
@@ -0,0 +1,253 @@
+//! System configuration store.
+//!
+//! A small key-value store that gives services a standard place for
+//! settings. The shell edits it with `config get/set/unset`; WASM processes
+//! holding a Config capability use `sp_cfg_get` (READ) and `sp_cfg_set`
+//! (WRITE).
+//!
+//! Every change is written to `CONFIG_PATH`, which is read again at boot,
+//! one `key = value` per line (`#` starts a comment). Keys are ASCII
+//! letters, digits, `.`, `_` and `-`, conventionally dotted by service
+//! (`httpd.port`); values are one line of text without `#`, with
+//! surrounding spaces dropped.
+//!
+//! The file starts with the schema it is written in (`# schema 1`); one
+//! without is schema 1. A file of a later schema is not loaded, so an
+//! older kernel leaves settings it does not understand alone until they
+//! are changed. `config save` writes the file again and `config reset`
+//! drops every setting.
+//!
+//! The root filesystem lives in RAM, so the file only outlasts a reboot
+//! once there is a persistent filesystem to keep it on.
+
+use crate::fs::{FileSystem, ROOT_FS};
+use crate::sync::TrackedMutex;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use core::fmt;
+
+/// Where the configuration is saved.
+pub const CONFIG_PATH: &str = "/etc/config";
+
+/// Longest key accepted.
+pub const MAX_KEY_LEN: usize = 64;
+
+/// Longest value accepted, in bytes.
+pub const MAX_VALUE_LEN: usize = 256;
+
+/// Most keys the store holds.
+pub const MAX_ENTRIES: usize = 128;
+
+/// Schema `Config::encode` writes and `Config::decode` reads.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Prefix of the line giving the schema.
+const SCHEMA_PREFIX: &str = "# schema ";
+
+/// Why a setting was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The key is empty, too long or has characters other than ASCII
+    /// letters, digits, `.`, `_` and `-`.
+    InvalidKey,
+    /// The value is longer than `MAX_VALUE_LEN`, spans several lines or
+    /// contains `#`.
+    InvalidValue,
+    /// The store already holds `MAX_ENTRIES` keys.
+    Full,
+    /// The saved configuration is written in a later schema than
+    /// `SCHEMA_VERSION`.
+    Schema(u32),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidKey => write!(f, "invalid key"),
+            Self::InvalidValue => write!(
+                f,
+                "value must be one line of at most {} bytes, without '#'",
+                MAX_VALUE_LEN
+            ),
+            Self::Full => write!(f, "too many settings (at most {})", MAX_ENTRIES),
+            Self::Schema(version) => write!(
+                f,
+                "written in schema {}, this kernel reads up to {}",
+                version, SCHEMA_VERSION
+            ),
+        }
+    }
+}
+
+/// Check that `key` may be stored.
+pub fn validate_key(key: &str) -> Result<(), ConfigError> {
+    let valid = !key.is_empty()
+        && key.len() <= MAX_KEY_LEN
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(ConfigError::InvalidKey)
+    }
+}
+
+/// A set of settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    entries: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Create an empty configuration.
+    pub const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Value of `key`, if set.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    /// Set `key` to `value`, replacing any previous value.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        validate_key(key)?;
+        let value = value.trim();
+        if value.len() > MAX_VALUE_LEN || value.contains(['\n', '\r', '#']) {
+            return Err(ConfigError::InvalidValue);
+        }
+        if !self.entries.contains_key(key) && self.entries.len() >= MAX_ENTRIES {
+            return Err(ConfigError::Full);
+        }
+        self.entries.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    /// Remove `key`. Returns `false` if it was not set.
+    pub fn unset(&mut self, key: &str) -> bool {
+        self.entries.remove(key).is_some()
+    }
+
+    /// Settings in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Number of keys set.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether nothing is set.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Parse `CONFIG_PATH` text. Invalid lines are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut config = Self::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            if let Some((key, value)) = line.split_once('=') {
+                let _ = config.set(key.trim(), value.trim());
+            }
+        }
+        config
+    }
+
+    /// Parse `CONFIG_PATH` text, refusing a schema later than
+    /// `SCHEMA_VERSION`.
+    pub fn decode(text: &str) -> Result<Self, ConfigError> {
+        let version = text
+            .lines()
+            .find_map(|line| line.strip_prefix(SCHEMA_PREFIX))
+            .and_then(|version| version.trim().parse().ok())
+            .unwrap_or(1);
+        if version > SCHEMA_VERSION {
+            return Err(ConfigError::Schema(version));
+        }
+        Ok(Self::parse(text))
+    }
+
+    /// Serialize for `CONFIG_PATH`; `decode` reads it back unchanged.
+    pub fn encode(&self) -> String {
+        let mut text = String::from("# System configuration, written by `config set`\n");
+        text.push_str(&alloc::format!("{}{}\n", SCHEMA_PREFIX, SCHEMA_VERSION));
+        for (key, value) in self.iter() {
+            text.push_str(&alloc::format!("{} = {}\n", key, value));
+        }
+        text
+    }
+}
+
+static CONFIG: TrackedMutex<Config> = TrackedMutex::new("config", Config::new());
+
+/// Value of `key` in the system configuration.
+pub fn get(key: &str) -> Option<String> {
+    CONFIG.lock().get(key).map(String::from)
+}
+
+/// Set `key` in the system configuration and save it.
+pub fn set(key: &str, value: &str) -> Result<(), ConfigError> {
+    let mut config = CONFIG.lock();
+    config.set(key, value)?;
+    ROOT_FS.add_file(CONFIG_PATH, config.encode().as_bytes());
+    Ok(())
+}
+
+/// Remove `key` from the system configuration and save it. Returns `false`
+/// if it was not set.
+pub fn unset(key: &str) -> bool {
+    let mut config = CONFIG.lock();
+    if !config.unset(key) {
+        return false;
+    }
+    ROOT_FS.add_file(CONFIG_PATH, config.encode().as_bytes());
+    true
+}
+
+/// Write the system configuration to `CONFIG_PATH`. Returns the number
+/// of settings saved.
+pub fn save() -> usize {
+    let config = CONFIG.lock();
+    ROOT_FS.add_file(CONFIG_PATH, config.encode().as_bytes());
+    config.len()
+}
+
+/// Remove every setting and save. Returns the number removed.
+pub fn reset() -> usize {
+    let mut config = CONFIG.lock();
+    let removed = config.len();
+    *config = Config::new();
+    ROOT_FS.add_file(CONFIG_PATH, config.encode().as_bytes());
+    removed
+}
+
+/// A copy of the system configuration.
+pub fn current() -> Config {
+    CONFIG.lock().clone()
+}
+
+/// Load the configuration saved in `CONFIG_PATH`. Returns the number of
+/// settings read, or `None` if there is no saved configuration.
+pub fn load() -> Option<Result<usize, ConfigError>> {
+    let handle = ROOT_FS.open(CONFIG_PATH).ok()?;
+    let size = ROOT_FS.size(handle).unwrap_or(0);
+    let mut buffer = vec![0u8; size];
+    let result = ROOT_FS.read(handle, &mut buffer, 0);
+    ROOT_FS.close(handle);
+    let len = result.ok()?;
+    let config = match Config::decode(&String::from_utf8_lossy(&buffer[..len])) {
+        Ok(config) => config,
+        Err(e) => return Some(Err(e)),
+    };
+    let count = config.len();
+    *CONFIG.lock() = config;
+    Some(Ok(count))
+}
@@ -0,0 +1,477 @@
+//! Host control protocol, spoken by `scripts/sovelmactl.py`.
+//!
+//! With `ctl=<n>` on the kernel command line, serial port COM<n> carries a
+//! line-based protocol that lets a host tool push files into the RAM
+//! filesystem, fetch them, run shell commands and fetch the log. The port
+//! must be enabled with `serial=`:
+//!
+//! ```text
+//! SOVELMA_CMDLINE="serial=2 ctl=2:rx" cargo run -- -serial tcp::4444,server,nowait
+//! ```
+//!
+//! The channel holds a capability for the port whose rights scope what the
+//! host may do: READ (`r`) allows `get` and `log`, WRITE (`w`) `put` and
+//! EXECUTE (`x`) `run`. `ctl=<n>` alone grants all three.
+//!
+//! # Framing
+//!
+//! Every line is ASCII text, `*`, a checksum (the XOR of the text's bytes,
+//! as two hex digits) and `\n`. Binary data travels hex-encoded. A request
+//!
+//! ```text
+//! <id> <op> [args...]*<checksum>
+//! ```
+//!
+//! is answered by zero or more `<id> data <hex>` lines, then `<id> ok
+//! [result]` or `<id> err <message>`. The id is chosen by the host and
+//! echoed as is. Requests:
+//!
+//! - `hello`: `ok sovelma <version> <rights>`; always allowed
+//! - `put <path> <offset> <hex>`: write at most `MAX_CHUNK` bytes to a
+//!   file, replacing it if `offset` is 0; `ok <bytes written>`
+//! - `get <path> <offset> <len>`: one `data` line with up to `len` (at most
+//!   `MAX_CHUNK`) bytes from `offset`; `ok <file size>`
+//! - `run <command line>`: the command's output, as text without colors,
+//!   in `data` lines; `ok`
+//! - `log <since>`: one `data` line per log record numbered `since` or
+//!   later (`<seq> <mono ns> <wall ns|-> <level> <target> <message>`, see
+//!   `klog::history`); `ok <next since>`
+//! - `time <unix ns>`: set the kernel's wall clock, which stamps later log
+//!   records; `ok <mono ns>`, the kernel's monotonic time it was set at
+
+use crate::arch::x86_64::serial;
+use crate::arch::x86_64::vga::Color;
+use crate::fs::{FileSystem, ROOT_FS};
+use crate::services::Shell;
+use crate::sync::TrackedMutex;
+use crate::terminal::io::{self, TerminalIo};
+use crate::terminal::{Command, Terminal};
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType};
+use spin::Mutex;
+
+/// Most data bytes in one `put`, `get` or `data` line.
+pub const MAX_CHUNK: usize = 512;
+
+/// Longest request line accepted, including the framing.
+pub const MAX_LINE: usize = 2 * MAX_CHUNK + 256;
+
+/// Rights granted when `ctl=` names none.
+const DEFAULT_RIGHTS: CapabilityRights = CapabilityRights::READ
+    .union(CapabilityRights::WRITE)
+    .union(CapabilityRights::EXECUTE);
+
+/// Why a request was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CtlError {
+    /// The checksum is missing or wrong.
+    BadChecksum,
+    /// The line is longer than `MAX_LINE`.
+    LineTooLong,
+    /// Arguments are missing or invalid.
+    BadRequest,
+    /// Not a known operation.
+    UnknownOp(String),
+    /// The channel's capability lacks the rights the operation needs.
+    PermissionDenied,
+    /// A filesystem operation failed.
+    Fs(String),
+}
+
+impl fmt::Display for CtlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadChecksum => write!(f, "bad checksum"),
+            Self::LineTooLong => write!(f, "line too long"),
+            Self::BadRequest => write!(f, "bad request"),
+            Self::UnknownOp(op) => write!(f, "unknown operation {}", op),
+            Self::PermissionDenied => write!(f, "permission denied"),
+            Self::Fs(e) => write!(f, "filesystem: {}", e),
+        }
+    }
+}
+
+/// XOR of the bytes of `text`.
+pub fn checksum(text: &str) -> u8 {
+    text.bytes().fold(0, |sum, byte| sum ^ byte)
+}
+
+/// Frame `text` as a line.
+pub fn frame(text: &str) -> String {
+    alloc::format!("{}*{:02x}\n", text, checksum(text))
+}
+
+/// The text of a line (without its newline), if its checksum is right.
+pub fn unframe(line: &str) -> Result<&str, CtlError> {
+    let (text, sum) = line.rsplit_once('*').ok_or(CtlError::BadChecksum)?;
+    if sum.len() == 2 && u8::from_str_radix(sum, 16) == Ok(checksum(text)) {
+        Ok(text)
+    } else {
+        Err(CtlError::BadChecksum)
+    }
+}
+
+/// Lowercase hex encoding of `data`.
+pub fn hex_encode(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(data.len() * 2);
+    for byte in data {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+/// Decode hex (either case); `None` if it is not valid hex.
+pub fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    let nibble = |digit: u8| char::from(digit).to_digit(16).map(|value| value as u8);
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => Some(nibble(*high)? << 4 | nibble(*low)?),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parse rights letters (`r`, `w`, `x`).
+pub fn parse_rights(letters: &str) -> Option<CapabilityRights> {
+    letters
+        .chars()
+        .try_fold(CapabilityRights::empty(), |rights, c| {
+            let right = match c {
+                'r' => CapabilityRights::READ,
+                'w' => CapabilityRights::WRITE,
+                'x' => CapabilityRights::EXECUTE,
+                _ => return None,
+            };
+            Some(rights | right)
+        })
+}
+
+/// Rights as letters, as accepted by `parse_rights`.
+pub fn rights_letters(rights: CapabilityRights) -> String {
+    [
+        (CapabilityRights::READ, 'r'),
+        (CapabilityRights::WRITE, 'w'),
+        (CapabilityRights::EXECUTE, 'x'),
+    ]
+    .iter()
+    .map(|(right, letter)| {
+        if rights.contains(*right) {
+            *letter
+        } else {
+            '-'
+        }
+    })
+    .collect()
+}
+
+/// A parsed request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op<'a> {
+    /// Identify the kernel and the channel's rights.
+    Hello,
+    /// Write a chunk of a file.
+    Put {
+        /// File path.
+        path: &'a str,
+        /// Where the chunk goes; 0 replaces the file.
+        offset: usize,
+        /// The chunk.
+        data: Vec<u8>,
+    },
+    /// Read a chunk of a file.
+    Get {
+        /// File path.
+        path: &'a str,
+        /// Where to start reading.
+        offset: usize,
+        /// Most bytes wanted.
+        len: usize,
+    },
+    /// Run a shell command.
+    Run(&'a str),
+    /// Fetch log records.
+    Log {
+        /// First record number wanted.
+        since: u64,
+    },
+    /// Set the wall clock.
+    Time {
+        /// Nanoseconds since the Unix epoch.
+        unix_ns: u64,
+    },
+}
+
+impl<'a> Op<'a> {
+    /// Parse the operation and arguments of a request.
+    pub fn parse(op: &str, args: &'a str) -> Result<Self, CtlError> {
+        let mut words = args.split_whitespace();
+        let mut next = || words.next().ok_or(CtlError::BadRequest);
+        Ok(match op {
+            "hello" => Op::Hello,
+            "put" => {
+                let path = next()?;
+                let offset = next()?.parse().map_err(|_| CtlError::BadRequest)?;
+                let data = hex_decode(next().unwrap_or("")).ok_or(CtlError::BadRequest)?;
+                if data.len() > MAX_CHUNK {
+                    return Err(CtlError::BadRequest);
+                }
+                Op::Put { path, offset, data }
+            }
+            "get" => Op::Get {
+                path: next()?,
+                offset: next()?.parse().map_err(|_| CtlError::BadRequest)?,
+                len: next()?.parse().map_err(|_| CtlError::BadRequest)?,
+            },
+            "run" => match args.trim() {
+                "" => return Err(CtlError::BadRequest),
+                line => Op::Run(line),
+            },
+            "log" => Op::Log {
+                since: next()?.parse().map_err(|_| CtlError::BadRequest)?,
+            },
+            "time" => Op::Time {
+                unix_ns: next()?.parse().map_err(|_| CtlError::BadRequest)?,
+            },
+            op => return Err(CtlError::UnknownOp(op.to_string())),
+        })
+    }
+
+    /// Rights the channel needs for this operation.
+    pub fn rights(&self) -> CapabilityRights {
+        match self {
+            Op::Hello => CapabilityRights::empty(),
+            Op::Get { .. } | Op::Log { .. } => CapabilityRights::READ,
+            Op::Put { .. } | Op::Time { .. } => CapabilityRights::WRITE,
+            Op::Run(_) => CapabilityRights::EXECUTE,
+        }
+    }
+}
+
+/// Split a request's text into its id and the rest.
+fn split_id(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    text.split_once(' ').unwrap_or((text, ""))
+}
+
+/// The serial channel selected with `ctl=` and its capability.
+///
+/// Returns `Ok(None)` without `ctl=`.
+pub fn channel() -> Result<Option<(u8, Capability)>, String> {
+    let Some(value) = crate::boot::cmdline::get("ctl") else {
+        return Ok(None);
+    };
+    let (com, letters) = value.split_once(':').unwrap_or((value, ""));
+    let com: u8 = com
+        .parse()
+        .map_err(|_| alloc::format!("ctl={}: not a port number", value))?;
+    let rights = match letters {
+        "" => DEFAULT_RIGHTS,
+        letters => parse_rights(letters)
+            .ok_or_else(|| alloc::format!("ctl={}: rights are r, w and x", value))?,
+    };
+    if !serial::is_open(com) {
+        return Err(alloc::format!("COM{} is not enabled (serial={})", com, com));
+    }
+    let port = serial::port_base(com).map_err(|e| e.to_string())?;
+    Ok(Some((
+        com,
+        Capability::new(CapabilityType::Serial { port }, rights),
+    )))
+}
+
+/// Output the shell prints outside of a command's captured output.
+struct Collect(Arc<Mutex<String>>);
+
+impl TerminalIo for Collect {
+    fn write_str(&mut self, s: &str) {
+        self.0.lock().push_str(s);
+    }
+
+    fn set_color(&mut self, _foreground: Color, _background: Color) {}
+
+    fn clear(&mut self) {}
+}
+
+/// A control session over one channel.
+struct Session {
+    cap: Capability,
+    shell: Shell,
+    terminal: TrackedMutex<Terminal>,
+    printed: Arc<Mutex<String>>,
+}
+
+impl Session {
+    /// Answer one line, returning the framed reply lines.
+    async fn handle(&self, line: &str) -> Vec<String> {
+        let (id, reply) = match unframe(line) {
+            Ok(text) => {
+                let (id, rest) = split_id(text);
+                let (op, args) = rest.split_once(' ').unwrap_or((rest, ""));
+                let mut data = Vec::new();
+                (
+                    id,
+                    self.execute(op, args, &mut data).await.map(|ok| (data, ok)),
+                )
+            }
+            // The id may be damaged too, so it is not echoed
+            Err(e) => ("-", Err(e)),
+        };
+        let mut lines = Vec::new();
+        match reply {
+            Ok((data, ok)) => {
+                for chunk in data {
+                    lines.push(frame(&alloc::format!("{} data {}", id, hex_encode(&chunk))));
+                }
+                let ok = match ok.as_str() {
+                    "" => alloc::format!("{} ok", id),
+                    result => alloc::format!("{} ok {}", id, result),
+                };
+                lines.push(frame(&ok));
+            }
+            Err(e) => lines.push(frame(&alloc::format!("{} err {}", id, e))),
+        }
+        lines
+    }
+
+    /// Carry out a request, collecting `data` chunks; returns the `ok`
+    /// result.
+    async fn execute(
+        &self,
+        op: &str,
+        args: &str,
+        data: &mut Vec<Vec<u8>>,
+    ) -> Result<String, CtlError> {
+        let op = Op::parse(op, args)?;
+        if !self.cap.rights.contains(op.rights()) {
+            return Err(CtlError::PermissionDenied);
+        }
+        match op {
+            Op::Hello => Ok(alloc::format!(
+                "sovelma {} {}",
+                crate::buildinfo::VERSION,
+                rights_letters(self.cap.rights)
+            )),
+            Op::Put { path, offset, data } => put(path, offset, &data).map(|n| n.to_string()),
+            Op::Get { path, offset, len } => {
+                let (chunk, size) = get(path, offset, len.min(MAX_CHUNK))?;
+                data.push(chunk);
+                Ok(size.to_string())
+            }
+            Op::Run(line) => {
+                let command = Command::parse_line(line).ok_or(CtlError::BadRequest)?;
+                let output = self.shell.execute(command, &self.terminal).await;
+                let mut text = core::mem::take(&mut *self.printed.lock());
+                if let Some(output) = output {
+                    text.push_str(&output.text());
+                }
+                data.extend(text.as_bytes().chunks(MAX_CHUNK).map(<[u8]>::to_vec));
+                Ok(String::new())
+            }
+            Op::Log { since } => {
+                let mut next = since;
+                for (seq, record) in crate::klog::history(since) {
+                    let wall = match record.wall_ns {
+                        Some(ns) => ns.to_string(),
+                        None => String::from("-"),
+                    };
+                    let line = alloc::format!(
+                        "{} {} {} {} {} {}",
+                        seq,
+                        record.mono_ns,
+                        wall,
+                        record.level,
+                        record.target,
+                        record.message
+                    );
+                    data.push(line.into_bytes());
+                    next = seq + 1;
+                }
+                Ok(next.to_string())
+            }
+            Op::Time { unix_ns } => {
+                let mono_ns = crate::time::now_ns();
+                crate::time::set_wall_clock(unix_ns);
+                log::info!(
+                    target: "ctl",
+                    "Wall clock set to {}",
+                    crate::time::WallTime(unix_ns)
+                );
+                Ok(mono_ns.to_string())
+            }
+        }
+    }
+}
+
+/// Write `data` to `path` at `offset`; offset 0 replaces the file.
+fn put(path: &str, offset: usize, data: &[u8]) -> Result<usize, CtlError> {
+    if offset == 0 {
+        ROOT_FS.add_file(path, data);
+        return Ok(data.len());
+    }
+    let handle = ROOT_FS
+        .open(path)
+        .map_err(|e| CtlError::Fs(alloc::format!("{:?}", e)))?;
+    let result = ROOT_FS.write(handle, data, offset);
+    ROOT_FS.close(handle);
+    result.map_err(|e| CtlError::Fs(alloc::format!("{:?}", e)))
+}
+
+/// Read up to `len` bytes of `path` from `offset`, with the file's size.
+fn get(path: &str, offset: usize, len: usize) -> Result<(Vec<u8>, usize), CtlError> {
+    let handle = ROOT_FS
+        .open(path)
+        .map_err(|e| CtlError::Fs(alloc::format!("{:?}", e)))?;
+    let size = ROOT_FS.size(handle).unwrap_or(0);
+    let mut buffer = alloc::vec![0u8; len.min(size.saturating_sub(offset))];
+    let result = ROOT_FS.read(handle, &mut buffer, offset);
+    ROOT_FS.close(handle);
+    let count = result.map_err(|e| CtlError::Fs(alloc::format!("{:?}", e)))?;
+    buffer.truncate(count);
+    Ok((buffer, size))
+}
+
+/// Serve the control protocol on port `com`, scoped by `cap`.
+pub async fn serve(com: u8, cap: Capability, shell: Shell) {
+    // Messages printed outside of captured output (host resolution) are
+    // returned with the command's output instead of going to the screen
+    let printed = Arc::new(Mutex::new(String::new()));
+    io::attach(Box::new(Collect(printed.clone())));
+    let session = Session {
+        cap,
+        shell,
+        terminal: TrackedMutex::new("terminal", Terminal::new()),
+        printed,
+    };
+
+    let mut line = Vec::with_capacity(MAX_LINE);
+    let mut overflow = false;
+    let mut buffer = [0u8; 64];
+    loop {
+        let count = serial::read_port(com, &mut buffer).unwrap_or(0);
+        for &byte in &buffer[..count] {
+            match byte {
+                b'\n' => {
+                    let reply = if overflow {
+                        alloc::vec![frame(&alloc::format!("- err {}", CtlError::LineTooLong))]
+                    } else {
+                        let text = String::from_utf8_lossy(&line);
+                        session.handle(text.trim_end_matches('\r')).await
+                    };
+                    for reply in reply {
+                        let _ = serial::write_port(com, reply.as_bytes());
+                    }
+                    line.clear();
+                    overflow = false;
+                }
+                _ if line.len() >= MAX_LINE => overflow = true,
+                byte => line.push(byte),
+            }
+        }
+        crate::task::yield_now().await;
+    }
+}
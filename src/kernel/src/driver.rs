@@ -0,0 +1,312 @@
+//! Device drivers and the devices they register.
+//!
+//! Drivers are written against `sovelma_hal::driver` and get the kernel as
+//! a `DriverHost`, so they can live in crates of their own: the kernel
+//! links one in behind a `driver-<name>` feature (see `LINKED`) and `init`
+//! starts it at boot. A driver crate cannot depend on this crate, which
+//! would depend on it in turn; kernel code uses the same interface through
+//! `prelude`, with the host's methods as free functions.
+//!
+//! Registered devices are kept by id and name for the rest of the kernel
+//! (`devices`, `with_block`, ...). Drivers may have the PCI interrupt lines
+//! (`pic::PCI_IRQS`); their handlers run from the lines' interrupt handlers,
+//! after the built-in e1000 driver's.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use x86_64::PhysAddr;
+
+use crate::arch::x86_64::pic;
+use crate::memory::{self, PAGE_SIZE};
+use crate::sync::TrackedMutex;
+
+pub use sovelma_hal::driver::{
+    BlockDevice, CharDevice, DeviceError, DeviceId, DeviceKind, DmaRegion, DriverError, DriverHost,
+    DriverInit, IrqHandler, NetDevice,
+};
+
+/// The driver interface for kernel code: the hal prelude plus the host's
+/// functions.
+pub mod prelude {
+    pub use super::{
+        dma_alloc, dma_free, free_irq, register_block, register_char, register_net, request_irq,
+    };
+    pub use sovelma_hal::driver::prelude::*;
+}
+
+/// Drivers linked into this kernel, in the order `init` starts them.
+const LINKED: &[(&str, DriverInit)] = &[
+    #[cfg(feature = "driver-ramdisk")]
+    ("ramdisk", sovelma_driver_ramdisk::init),
+];
+
+/// Handlers one interrupt line can have.
+pub const MAX_IRQ_HANDLERS: usize = 4;
+
+/// A registered device.
+enum Device {
+    Block(Box<dyn BlockDevice>),
+    Char(Box<dyn CharDevice>),
+    Net(Box<dyn NetDevice>),
+}
+
+impl Device {
+    fn kind(&self) -> DeviceKind {
+        match self {
+            Device::Block(_) => DeviceKind::Block,
+            Device::Char(_) => DeviceKind::Char,
+            Device::Net(_) => DeviceKind::Net,
+        }
+    }
+}
+
+struct Entry {
+    id: DeviceId,
+    name: String,
+    device: Device,
+}
+
+/// Registered devices, in registration order.
+static DEVICES: TrackedMutex<Vec<Entry>> = TrackedMutex::new("devices", Vec::new());
+
+/// Next device id to hand out.
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLERS: [AtomicUsize; MAX_IRQ_HANDLERS] = [NO_HANDLER; MAX_IRQ_HANDLERS];
+
+/// Handlers by line (index in `PCI_IRQS`), as addresses; 0 is a free
+/// slot. Atomics rather than a lock, since interrupts read them.
+static IRQ_HANDLERS: [[AtomicUsize; MAX_IRQ_HANDLERS]; pic::PCI_IRQS.len()] =
+    [NO_HANDLERS; pic::PCI_IRQS.len()];
+
+/// A registered device, as `devices` lists it.
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    /// Its id.
+    pub id: DeviceId,
+    /// Name it was registered under.
+    pub name: String,
+    /// What kind of device it is.
+    pub kind: DeviceKind,
+}
+
+/// The kernel as drivers see it.
+struct KernelHost;
+
+impl DriverHost for KernelHost {
+    fn register_block(
+        &self,
+        name: &str,
+        device: Box<dyn BlockDevice>,
+    ) -> Result<DeviceId, DriverError> {
+        register_block(name, device)
+    }
+
+    fn register_char(
+        &self,
+        name: &str,
+        device: Box<dyn CharDevice>,
+    ) -> Result<DeviceId, DriverError> {
+        register_char(name, device)
+    }
+
+    fn register_net(
+        &self,
+        name: &str,
+        device: Box<dyn NetDevice>,
+    ) -> Result<DeviceId, DriverError> {
+        register_net(name, device)
+    }
+
+    fn request_irq(&self, irq: u8, handler: IrqHandler) -> Result<(), DriverError> {
+        request_irq(irq, handler)
+    }
+
+    fn free_irq(&self, irq: u8, handler: IrqHandler) {
+        free_irq(irq, handler)
+    }
+
+    fn dma_alloc(&self, len: usize) -> Result<DmaRegion, DriverError> {
+        dma_alloc(len)
+    }
+
+    unsafe fn dma_free(&self, region: DmaRegion) {
+        // SAFETY: The caller's contract is ours.
+        unsafe { dma_free(region) }
+    }
+}
+
+/// Start the linked drivers. Returns each one's name and result.
+///
+/// Needs the heap and `memory::install`.
+pub fn init() -> Vec<(&'static str, Result<(), DriverError>)> {
+    LINKED
+        .iter()
+        .map(|&(name, init)| (name, init(&KernelHost)))
+        .collect()
+}
+
+/// Names of the drivers linked into this kernel.
+pub fn linked() -> impl Iterator<Item = &'static str> {
+    LINKED.iter().map(|&(name, _)| name)
+}
+
+fn register(name: &str, device: Device) -> Result<DeviceId, DriverError> {
+    let mut devices = DEVICES.lock();
+    if devices.iter().any(|entry| entry.name == name) {
+        return Err(DriverError::NameTaken);
+    }
+    let id = DeviceId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let kind = device.kind();
+    devices.push(Entry {
+        id,
+        name: String::from(name),
+        device,
+    });
+    drop(devices);
+    log::info!("driver: {} device {} registered as {}", kind, name, id);
+    Ok(id)
+}
+
+/// Register a block device under `name`.
+pub fn register_block(name: &str, device: Box<dyn BlockDevice>) -> Result<DeviceId, DriverError> {
+    register(name, Device::Block(device))
+}
+
+/// Register a character device under `name`.
+pub fn register_char(name: &str, device: Box<dyn CharDevice>) -> Result<DeviceId, DriverError> {
+    register(name, Device::Char(device))
+}
+
+/// Register a network interface under `name`.
+pub fn register_net(name: &str, device: Box<dyn NetDevice>) -> Result<DeviceId, DriverError> {
+    register(name, Device::Net(device))
+}
+
+/// Remove device `id`, e.g. when its hardware has gone. Returns whether
+/// it was registered.
+pub fn unregister(id: DeviceId) -> bool {
+    let mut devices = DEVICES.lock();
+    let before = devices.len();
+    devices.retain(|entry| entry.id != id);
+    devices.len() != before
+}
+
+/// The registered devices, in registration order.
+pub fn devices() -> Vec<DeviceInfo> {
+    DEVICES
+        .lock()
+        .iter()
+        .map(|entry| DeviceInfo {
+            id: entry.id,
+            name: entry.name.clone(),
+            kind: entry.device.kind(),
+        })
+        .collect()
+}
+
+/// Run `f` on the block device called `name`; `None` if there is none.
+pub fn with_block<R>(name: &str, f: impl FnOnce(&mut dyn BlockDevice) -> R) -> Option<R> {
+    let mut devices = DEVICES.lock();
+    match &mut devices.iter_mut().find(|entry| entry.name == name)?.device {
+        Device::Block(device) => Some(f(device.as_mut())),
+        _ => None,
+    }
+}
+
+/// Run `f` on the character device called `name`; `None` if there is
+/// none.
+pub fn with_char<R>(name: &str, f: impl FnOnce(&mut dyn CharDevice) -> R) -> Option<R> {
+    let mut devices = DEVICES.lock();
+    match &mut devices.iter_mut().find(|entry| entry.name == name)?.device {
+        Device::Char(device) => Some(f(device.as_mut())),
+        _ => None,
+    }
+}
+
+/// Run `f` on the network interface called `name`; `None` if there is
+/// none.
+pub fn with_net<R>(name: &str, f: impl FnOnce(&mut dyn NetDevice) -> R) -> Option<R> {
+    let mut devices = DEVICES.lock();
+    match &mut devices.iter_mut().find(|entry| entry.name == name)?.device {
+        Device::Net(device) => Some(f(device.as_mut())),
+        _ => None,
+    }
+}
+
+/// Handler slots of `irq`, if drivers may have it.
+fn irq_slots(irq: u8) -> Option<&'static [AtomicUsize; MAX_IRQ_HANDLERS]> {
+    let line = pic::PCI_IRQS.iter().position(|&pci| pci == irq)?;
+    IRQ_HANDLERS.get(line)
+}
+
+/// Call `handler` for every interrupt on `irq`, one of `pic::PCI_IRQS`,
+/// and unmask the line. Requesting a handler twice is harmless.
+pub fn request_irq(irq: u8, handler: IrqHandler) -> Result<(), DriverError> {
+    let slots = irq_slots(irq).ok_or(DriverError::IrqUnavailable(irq))?;
+    let raw = handler as usize;
+    if !slots.iter().any(|slot| slot.load(Ordering::Acquire) == raw) {
+        slots
+            .iter()
+            .find(|slot| {
+                slot.compare_exchange(0, raw, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+            })
+            .ok_or(DriverError::IrqBusy(irq))?;
+    }
+    pic::unmask_pci(irq);
+    Ok(())
+}
+
+/// Stop calling `handler` for `irq`. The line stays unmasked; others may
+/// share it.
+pub fn free_irq(irq: u8, handler: IrqHandler) {
+    if let Some(slots) = irq_slots(irq) {
+        for slot in slots {
+            let _ = slot.compare_exchange(handler as usize, 0, Ordering::AcqRel, Ordering::Acquire);
+        }
+    }
+}
+
+/// Call the drivers' handlers for `irq`. Called from its interrupt
+/// handler.
+pub(crate) fn dispatch_irq(irq: u8) {
+    let Some(slots) = irq_slots(irq) else {
+        return;
+    };
+    for slot in slots {
+        let raw = slot.load(Ordering::Acquire);
+        if raw != 0 {
+            // SAFETY: Slots only hold 0 or an `IrqHandler` stored by
+            // `request_irq`.
+            let handler = unsafe { core::mem::transmute::<usize, IrqHandler>(raw) };
+            handler(irq);
+        }
+    }
+}
+
+/// Allocate at least `len` bytes of zeroed, physically contiguous memory,
+/// page aligned.
+pub fn dma_alloc(len: usize) -> Result<DmaRegion, DriverError> {
+    let pages = len.max(1).div_ceil(PAGE_SIZE as usize);
+    let (phys, virt) = memory::allocate_contiguous(pages as u64).ok_or(DriverError::OutOfMemory)?;
+    // SAFETY: The frames are new, mapped at `virt` and nobody else's.
+    Ok(unsafe { DmaRegion::new(phys.as_u64(), virt.as_mut_ptr(), pages * PAGE_SIZE as usize) })
+}
+
+/// Give back a region from `dma_alloc`.
+///
+/// # Safety
+///
+/// Neither the device nor the CPU may use it any more.
+pub unsafe fn dma_free(region: DmaRegion) {
+    let pages = (region.len() / PAGE_SIZE as usize) as u64;
+    // SAFETY: The caller gives the region up; it came from `dma_alloc`.
+    unsafe { memory::free_contiguous(PhysAddr::new(region.phys()), pages) };
+}
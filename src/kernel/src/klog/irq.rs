@@ -0,0 +1,204 @@
+//! Logging from interrupt context.
+//!
+//! An interrupt handler must not print or call the logger: the task it
+//! interrupted may hold the VGA or serial lock, and spinning on it with
+//! interrupts disabled hangs the machine. Handlers record messages here
+//! instead, in a fixed ring of preformatted slots that is filled without
+//! locks or allocation. The logging task (`drain`) passes the records on to
+//! the logger from task context.
+//!
+//! Handlers that never return (fatal exceptions) cannot wait for the task;
+//! they call `flush_fatal`, which writes pending records straight to COM1
+//! and to the screen if it is free.
+
+use crate::arch::x86_64::{serial, vga};
+use crate::boot::panic::FixedWriter;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use log::Level;
+
+/// Number of records the ring holds.
+pub const IRQ_LOG_SLOTS: usize = 32;
+
+/// Bytes of message kept per record; longer messages are truncated.
+pub const IRQ_RECORD_LEN: usize = 128;
+
+/// Slot states.
+const EMPTY: u8 = 0;
+const WRITING: u8 = 1;
+const READY: u8 = 2;
+
+/// A record logged from interrupt context.
+#[derive(Clone, Copy)]
+pub struct IrqRecord {
+    /// Severity.
+    pub level: Level,
+    /// Subsystem that logged the record.
+    pub target: &'static str,
+    len: usize,
+    text: [u8; IRQ_RECORD_LEN],
+}
+
+impl IrqRecord {
+    const fn empty() -> Self {
+        Self {
+            level: Level::Error,
+            target: "",
+            len: 0,
+            text: [0; IRQ_RECORD_LEN],
+        }
+    }
+
+    /// The formatted message, cut at the last whole character if truncated.
+    pub fn message(&self) -> &str {
+        let bytes = &self.text[..self.len];
+        match core::str::from_utf8(bytes) {
+            Ok(message) => message,
+            // SAFETY: `valid_up_to` is the length of the longest valid prefix.
+            Err(e) => unsafe { core::str::from_utf8_unchecked(&bytes[..e.valid_up_to()]) },
+        }
+    }
+}
+
+struct Slot {
+    state: AtomicU8,
+    record: UnsafeCell<IrqRecord>,
+}
+
+// SAFETY: `record` is only written by the producer that moved `state` from
+// EMPTY to WRITING and only read by the consumer after it saw READY, so no
+// two contexts access it at once.
+unsafe impl Sync for Slot {}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: Slot = Slot {
+    state: AtomicU8::new(EMPTY),
+    record: UnsafeCell::new(IrqRecord::empty()),
+};
+
+/// The ring; `HEAD` and `TAIL` count records ever reserved and consumed.
+static SLOTS: [Slot; IRQ_LOG_SLOTS] = [EMPTY_SLOT; IRQ_LOG_SLOTS];
+static HEAD: AtomicUsize = AtomicUsize::new(0);
+static TAIL: AtomicUsize = AtomicUsize::new(0);
+
+/// Set while a context is consuming records.
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Records discarded because the ring was full.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Log from interrupt context; see the module docs.
+///
+/// ```ignore
+/// irq_log!(Level::Warn, "keyboard", "queue full, dropped {:#x}", scancode);
+/// ```
+#[macro_export]
+macro_rules! irq_log {
+    ($level:expr, $target:expr, $($arg:tt)*) => {
+        $crate::klog::irq::record($level, $target, format_args!($($arg)*))
+    };
+}
+
+/// Format a record into the ring. Returns false if the ring was full.
+///
+/// Safe to call from any context: it takes no lock and does not allocate.
+pub fn record(level: Level, target: &'static str, args: fmt::Arguments) -> bool {
+    let mut head = HEAD.load(Ordering::Relaxed);
+    loop {
+        if head.wrapping_sub(TAIL.load(Ordering::Acquire)) >= IRQ_LOG_SLOTS {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        match HEAD.compare_exchange_weak(head, head + 1, Ordering::AcqRel, Ordering::Relaxed) {
+            Ok(_) => break,
+            Err(current) => head = current,
+        }
+    }
+
+    let slot = &SLOTS[head % IRQ_LOG_SLOTS];
+    slot.state.store(WRITING, Ordering::Relaxed);
+    // SAFETY: The slot was reserved above and the consumer moved past it
+    // (TAIL), so only this context touches the record until READY.
+    let record = unsafe { &mut *slot.record.get() };
+    record.level = level;
+    record.target = target;
+    let mut writer = FixedWriter::new(&mut record.text);
+    let _ = fmt::Write::write_fmt(&mut writer, args);
+    record.len = writer.as_bytes().len();
+    slot.state.store(READY, Ordering::Release);
+    true
+}
+
+/// Take the oldest complete record, if any.
+fn pop() -> Option<IrqRecord> {
+    let tail = TAIL.load(Ordering::Relaxed);
+    if tail == HEAD.load(Ordering::Acquire) {
+        return None;
+    }
+    let slot = &SLOTS[tail % IRQ_LOG_SLOTS];
+    if slot.state.load(Ordering::Acquire) != READY {
+        // Reserved by a context that has not finished writing it
+        return None;
+    }
+    // SAFETY: READY means the producer is done, and DRAINING makes this the
+    // only consumer.
+    let record = unsafe { *slot.record.get() };
+    slot.state.store(EMPTY, Ordering::Relaxed);
+    TAIL.store(tail + 1, Ordering::Release);
+    Some(record)
+}
+
+/// Run `f` on every pending record, unless another context is draining.
+fn consume(mut f: impl FnMut(&IrqRecord)) -> usize {
+    if DRAINING.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+    let mut count = 0;
+    while let Some(record) = pop() {
+        f(&record);
+        count += 1;
+    }
+    DRAINING.store(false, Ordering::Release);
+    count
+}
+
+/// Pass pending records to the logger. Call from task context only.
+///
+/// Returns the number of records drained.
+pub fn drain() -> usize {
+    let count = consume(|record| {
+        log::log!(target: record.target, record.level, "{}", record.message());
+    });
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        log::warn!(target: "klog", "{} interrupt log records dropped", dropped);
+    }
+    count
+}
+
+/// Write pending records to COM1 and, if its lock is free, the screen.
+///
+/// For handlers about to halt; takes no lock it would wait on.
+pub fn flush_fatal() {
+    consume(|record| {
+        let mut prefix = [0u8; IRQ_RECORD_LEN];
+        let mut writer = FixedWriter::new(&mut prefix);
+        let _ = fmt::Write::write_fmt(
+            &mut writer,
+            format_args!("[{:<5} {}] ", record.level, record.target),
+        );
+        serial::write_raw(writer.as_bytes());
+        serial::write_raw(record.message().as_bytes());
+        serial::write_raw(b"\n");
+
+        vga::try_write_str(record.message());
+        vga::try_write_str("\n");
+    });
+}
+
+/// Number of records waiting to be drained.
+pub fn pending() -> usize {
+    HEAD.load(Ordering::Acquire)
+        .wrapping_sub(TAIL.load(Ordering::Acquire))
+}
@@ -0,0 +1,220 @@
+//! Kernel logger.
+//!
+//! Backs the `log` crate macros. Every record is written to the serial
+//! port; while a remote sink is enabled (`log remote <ip>`), records are
+//! also queued for the syslog client in `net::syslog`, which ships them to
+//! a collector on the host.
+//!
+//! The remote queue is bounded: when the link stays down long enough to
+//! fill it, the oldest records are dropped and counted.
+//!
+//! The most recent `HISTORY_CAPACITY` records are also kept in memory,
+//! numbered in order, for `dmesg` and tools that fetch the log (see `ctl`).
+//!
+//! Every record is stamped when it is logged with the monotonic time and,
+//! once the wall clock has been set, the wall-clock time derived from it
+//! (see `time`), so records can be lined up with captures taken on the
+//! host.
+//!
+//! Interrupt handlers log through `irq` instead, which defers the records
+//! to the logging task.
+
+pub mod irq;
+
+use crate::time::{self, Uptime};
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Records buffered for the remote sink.
+pub const REMOTE_QUEUE_CAPACITY: usize = 128;
+
+/// Records kept in memory for `history`.
+pub const HISTORY_CAPACITY: usize = 256;
+
+/// Maximum level logged unless the command line says otherwise.
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// A log record captured for the remote sink.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    /// Severity.
+    pub level: Level,
+    /// Subsystem that logged the record.
+    pub target: String,
+    /// Formatted message.
+    pub message: String,
+    /// When it was logged, in ns since boot.
+    pub mono_ns: u64,
+    /// When it was logged, in ns since the Unix epoch, if the wall clock
+    /// was set.
+    pub wall_ns: Option<u64>,
+}
+
+impl fmt::Display for LogRecord {
+    /// The record as written to the serial port.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] [{:<5} {}] {}",
+            Uptime(self.mono_ns),
+            self.level,
+            self.target,
+            self.message
+        )
+    }
+}
+
+static LOGGER: KernelLogger = KernelLogger;
+
+/// Whether records are queued for the remote sink.
+static REMOTE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Records waiting to be shipped.
+static REMOTE_QUEUE: Mutex<VecDeque<LogRecord>> = Mutex::new(VecDeque::new());
+
+/// Records discarded because the remote queue was full.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Recent records with their sequence numbers, oldest first.
+static HISTORY: Mutex<VecDeque<(u64, LogRecord)>> = Mutex::new(VecDeque::new());
+
+/// Sequence number of the next record kept in `HISTORY`.
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the heap and screen are up; until then panics take the early path.
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Install the kernel logger.
+///
+/// The level comes from `loglevel=` on the kernel command line
+/// (`error`, `warn`, `info`, `debug` or `trace`), defaulting to `info`.
+pub fn init() {
+    let level = crate::boot::cmdline::get("loglevel")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_LEVEL);
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Flag logging as fully usable: the heap and VGA writer are initialized.
+///
+/// Before this, the panic handler uses `boot::panic::early`.
+pub fn mark_ready() {
+    READY.store(true, Ordering::SeqCst);
+}
+
+/// Whether `mark_ready` has been called.
+pub fn is_ready() -> bool {
+    READY.load(Ordering::SeqCst)
+}
+
+/// Start or stop queueing records for the remote sink.
+///
+/// Stopping discards whatever is still queued.
+pub fn set_remote(enabled: bool) {
+    REMOTE_ENABLED.store(enabled, Ordering::SeqCst);
+    if !enabled {
+        with_remote_queue(|queue| queue.clear());
+    }
+}
+
+/// Run `f` on the remote queue.
+///
+/// Interrupts are disabled meanwhile, so handlers that log cannot deadlock
+/// on the queue. `f` must not log itself.
+pub fn with_remote_queue<R>(f: impl FnOnce(&mut VecDeque<LogRecord>) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut REMOTE_QUEUE.lock()))
+}
+
+/// Number of records dropped from the remote queue so far.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Recent records numbered `since` or later, oldest first, with their
+/// sequence numbers.
+///
+/// Records older than the last `HISTORY_CAPACITY` are gone; pass the
+/// number after the last one seen to get only new records.
+pub fn history(since: u64) -> Vec<(u64, LogRecord)> {
+    interrupts::without_interrupts(|| {
+        HISTORY
+            .lock()
+            .iter()
+            .filter(|(seq, _)| *seq >= since)
+            .cloned()
+            .collect()
+    })
+}
+
+/// Sequence number the next record kept in the history will get.
+pub fn next_seq() -> u64 {
+    NEXT_SEQ.load(Ordering::Relaxed)
+}
+
+/// Keep `entry` in the history, dropping the oldest record when full.
+fn remember(entry: LogRecord) {
+    interrupts::without_interrupts(|| {
+        let mut history = HISTORY.lock();
+        if history.len() >= HISTORY_CAPACITY {
+            history.pop_front();
+        }
+        history.push_back((NEXT_SEQ.fetch_add(1, Ordering::Relaxed), entry));
+    });
+}
+
+/// `log::Log` implementation writing to serial, the history and the remote
+/// queue.
+struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mono_ns = time::now_ns();
+        crate::serial_println!(
+            "[{}] [{:<5} {}] {}",
+            Uptime(mono_ns),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        // The history needs the heap
+        if !is_ready() {
+            return;
+        }
+        let entry = LogRecord {
+            level: record.level(),
+            target: record.target().to_string(),
+            message: alloc::format!("{}", record.args()),
+            mono_ns,
+            wall_ns: time::wall_ns_at(mono_ns),
+        };
+        if REMOTE_ENABLED.load(Ordering::Relaxed) {
+            let entry = entry.clone();
+            with_remote_queue(|queue| {
+                if queue.len() >= REMOTE_QUEUE_CAPACITY {
+                    queue.pop_front();
+                    DROPPED.fetch_add(1, Ordering::Relaxed);
+                }
+                queue.push_back(entry);
+            });
+        }
+        remember(entry);
+    }
+
+    fn flush(&self) {}
+}
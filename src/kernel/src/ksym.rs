@@ -0,0 +1,201 @@
+//! Kernel symbol map.
+//!
+//! Resolves code addresses to function names for panic backtraces and the
+//! `ksym` shell command. The map is an `nm -n` listing of the kernel ELF:
+//!
+//! - embedded at build time from the file named by `SOVELMA_KSYMS` (see
+//!   `build.rs` and `scripts/ksyms.sh`), or
+//! - loaded from `/boot/kernel.sym` in the root filesystem, which takes
+//!   precedence when present.
+//!
+//! Only text symbols are kept. Lookups are lock-free once `init` has run,
+//! so they are safe from the panic handler.
+
+use crate::fs::{FileSystem, ROOT_FS};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Symbol map embedded by the build script (empty without `SOVELMA_KSYMS`).
+static EMBEDDED: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/kernel.sym"));
+
+/// Path of a symbol map shipped in the root filesystem.
+pub const SYMBOL_FILE: &str = "boot/kernel.sym";
+
+/// Largest gap between a symbol and an address still attributed to it.
+///
+/// Stops addresses past the last function (or in data) from resolving to
+/// whatever symbol happens to precede them.
+const MAX_SYMBOL_SIZE: u64 = 1024 * 1024;
+
+/// Deepest backtrace walked by `backtrace`.
+pub const MAX_BACKTRACE_DEPTH: usize = 32;
+
+/// Required alignment of a saved frame pointer.
+const FRAME_ALIGN: u64 = 8;
+
+/// Largest distance between consecutive stack frames in a backtrace.
+const MAX_FRAME_SIZE: u64 = 64 * 1024;
+
+/// The active symbol table.
+static TABLE: spin::Once<SymbolTable<'static>> = spin::Once::new();
+
+/// A resolved address: the containing symbol and the offset into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    /// Symbol name (demangled if the listing was).
+    pub name: &'a str,
+    /// Offset of the address from the symbol start.
+    pub offset: u64,
+}
+
+impl fmt::Display for Symbol<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+/// Address-sorted symbol table.
+pub struct SymbolTable<'a> {
+    entries: Vec<(u64, &'a str)>,
+}
+
+impl<'a> SymbolTable<'a> {
+    /// Parse `nm` output (`<addr> <type> <name>` per line).
+    ///
+    /// Non-text symbols and malformed lines are skipped. A trailing Rust
+    /// hash (`::h0123456789abcdef`) is stripped from demangled names.
+    pub fn parse(text: &'a str) -> Self {
+        let mut entries: Vec<(u64, &str)> = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.trim().splitn(3, ' ');
+                let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+                let kind = fields.next()?;
+                let name = fields.next()?.trim();
+                if !matches!(kind, "t" | "T" | "w" | "W") || name.is_empty() {
+                    return None;
+                }
+                Some((addr, strip_hash(name)))
+            })
+            .collect();
+        entries.sort_by_key(|(addr, _)| *addr);
+        entries.dedup_by_key(|(addr, _)| *addr);
+        Self { entries }
+    }
+
+    /// Number of symbols in the table.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the table has no symbols.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Find the symbol containing `addr`.
+    pub fn lookup(&self, addr: u64) -> Option<Symbol<'a>> {
+        let index = match self.entries.binary_search_by_key(&addr, |(a, _)| *a) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let (start, name) = self.entries[index];
+        let offset = addr - start;
+        if offset > MAX_SYMBOL_SIZE {
+            return None;
+        }
+        Some(Symbol { name, offset })
+    }
+
+    /// Find the address of a symbol by exact name.
+    pub fn address_of(&self, name: &str) -> Option<u64> {
+        self.entries
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(addr, _)| *addr)
+    }
+}
+
+/// Strip a trailing `::h<16 hex digits>` from a demangled Rust name.
+fn strip_hash(name: &str) -> &str {
+    match name.rsplit_once("::h") {
+        Some((base, hash)) if hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            base
+        }
+        _ => name,
+    }
+}
+
+/// Load the symbol table.
+///
+/// Call once the root filesystem is populated. A map in `SYMBOL_FILE`
+/// replaces the embedded one. Returns the number of symbols loaded.
+pub fn init() -> usize {
+    TABLE
+        .call_once(|| {
+            let text = read_symbol_file()
+                .unwrap_or_else(|| core::str::from_utf8(EMBEDDED).unwrap_or_default());
+            SymbolTable::parse(text)
+        })
+        .len()
+}
+
+/// Read `SYMBOL_FILE`, leaking it so the table can borrow it for good.
+fn read_symbol_file() -> Option<&'static str> {
+    let handle = ROOT_FS.open(SYMBOL_FILE).ok()?;
+    let size = ROOT_FS.size(handle).unwrap_or(0);
+    let mut buffer = alloc::vec![0u8; size];
+    let result = ROOT_FS.read(handle, &mut buffer, 0);
+    ROOT_FS.close(handle);
+    buffer.truncate(result.ok()?);
+    let text = String::from_utf8(buffer).ok()?;
+    Some(text.leak())
+}
+
+/// Resolve `addr` to a function name and offset.
+///
+/// Returns `None` before `init` or when the address is not in the map.
+pub fn symbolize(addr: u64) -> Option<Symbol<'static>> {
+    TABLE.get()?.lookup(addr)
+}
+
+/// Look up the address of a function by name.
+pub fn lookup_name(name: &str) -> Option<u64> {
+    TABLE.get()?.address_of(name)
+}
+
+/// Walk the frame-pointer chain of the caller, passing each return address
+/// to `f`.
+///
+/// Relies on `-C force-frame-pointers=yes` (set in `.cargo/config.toml`).
+/// The walk stops at a null or misaligned frame pointer, a frame that does
+/// not move up the stack, or after `MAX_BACKTRACE_DEPTH` frames.
+#[cfg(target_arch = "x86_64")]
+pub fn backtrace(mut f: impl FnMut(u64)) {
+    let mut rbp: u64;
+    // SAFETY: Reading rbp has no side effects.
+    unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)) };
+
+    for _ in 0..MAX_BACKTRACE_DEPTH {
+        if rbp == 0 || rbp & (FRAME_ALIGN - 1) != 0 {
+            break;
+        }
+        // SAFETY: With frame pointers, rbp points at the saved caller rbp,
+        // followed by the return address. The checks above and below keep
+        // the walk on the current stack.
+        let (next, ret) = unsafe {
+            let frame = rbp as *const u64;
+            (frame.read(), frame.add(1).read())
+        };
+        if ret == 0 {
+            break;
+        }
+        f(ret);
+        if next <= rbp || next - rbp > MAX_FRAME_SIZE {
+            break;
+        }
+        rbp = next;
+    }
+}
@@ -16,7 +16,6 @@
 #![warn(missing_docs)]
 #![feature(abi_x86_interrupt)]
 #![feature(raw_ref_op)]
-#![feature(pointer_byte_offsets)]
 #![feature(custom_test_frameworks)]
 #![test_runner(crate::testutil::test_runner)]
 #![reexport_test_harness_main = "test_main"]
@@ -25,15 +24,28 @@ extern crate alloc;
 
 pub mod allocator;
 pub mod arch;
+pub mod bench;
 pub mod boot;
+pub mod buildinfo;
 pub mod capability;
-pub mod fs;
+pub mod config;
+#[cfg(feature = "terminal")]
+pub mod ctl;
+pub mod driver;
+pub mod klog;
+pub mod ksym;
 pub mod memory;
+#[cfg(feature = "net")]
 pub mod net;
+pub mod replay;
+pub mod services;
 pub mod sync;
 pub mod task;
 pub mod terminal;
 pub mod tests;
+pub mod time;
+pub mod trace;
+#[cfg(feature = "wasm")]
 pub mod wasm;
 
 /// Test infrastructure for the kernel.
@@ -42,6 +54,8 @@ pub mod wasm;
 /// Used by integration test binaries.
 pub mod testutil;
 
+pub use sovelma_core::fs;
+
 /// Initializes core kernel subsystems.
 ///
 /// Called early in the boot process to set up essential services.
@@ -49,8 +63,10 @@ pub fn init() {
     #[cfg(target_arch = "x86_64")]
     {
         arch::x86_64::serial::init();
+        klog::init();
         arch::x86_64::vga::init();
         arch::x86_64::gdt::init();
         arch::x86_64::interrupts::init_idt();
+        arch::x86_64::fpu::init();
     }
 }
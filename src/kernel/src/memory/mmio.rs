@@ -0,0 +1,168 @@
+//! Memory-mapped device registers.
+//!
+//! Drivers reach their device's registers through the bootloader's mapping
+//! of all physical memory. An `MmioRegion` is one such window, checked
+//! once against the size the device actually decodes; `Mmio<T>` is a
+//! register inside it, read and written with volatile accesses of exactly
+//! `T`'s width.
+//!
+//! ```ignore
+//! let regs = MmioRegion::from_bar(&pci_dev, phys_mem_offset, REGISTER_SPACE)?;
+//! let status = regs.register::<u32>(REG_STATUS)?.read();
+//! ```
+
+use crate::arch::x86_64::pci::PciDevice;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ptr::{self, NonNull};
+
+/// Why a region or register could not be set up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmioError {
+    /// BAR0 is an I/O port BAR or not implemented.
+    NotMemory,
+    /// The device decodes fewer bytes than the driver needs.
+    TooSmall {
+        /// Bytes the BAR decodes.
+        size: u64,
+        /// Bytes the driver asked for.
+        needed: u64,
+    },
+    /// The register does not fit in the region.
+    OutOfBounds {
+        /// Offset of the register.
+        offset: usize,
+    },
+    /// The register's address is not a multiple of its width.
+    Misaligned {
+        /// Offset of the register.
+        offset: usize,
+    },
+}
+
+impl fmt::Display for MmioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotMemory => write!(f, "BAR0 is not a memory BAR"),
+            Self::TooSmall { size, needed } => {
+                write!(f, "BAR0 decodes {} bytes, {} needed", size, needed)
+            }
+            Self::OutOfBounds { offset } => write!(f, "register {:#x} out of bounds", offset),
+            Self::Misaligned { offset } => write!(f, "register {:#x} misaligned", offset),
+        }
+    }
+}
+
+/// Register widths devices are accessed with.
+pub trait RegisterValue: Copy + private::Sealed {}
+
+impl RegisterValue for u8 {}
+impl RegisterValue for u16 {}
+impl RegisterValue for u32 {}
+impl RegisterValue for u64 {}
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for u8 {}
+    impl Sealed for u16 {}
+    impl Sealed for u32 {}
+    impl Sealed for u64 {}
+}
+
+/// A window of device memory.
+#[derive(Debug)]
+pub struct MmioRegion {
+    base: NonNull<u8>,
+    size: usize,
+}
+
+// SAFETY: The region is device memory, not tied to any thread; accesses
+// are single volatile loads and stores.
+unsafe impl Send for MmioRegion {}
+// SAFETY: As above; `&MmioRegion` only allows volatile accesses.
+unsafe impl Sync for MmioRegion {}
+
+impl MmioRegion {
+    /// Map the region a device's BAR0 decodes, requiring at least `needed`
+    /// bytes of it.
+    ///
+    /// `phys_mem_offset` is where the bootloader maps physical memory.
+    pub fn from_bar(
+        device: &PciDevice,
+        phys_mem_offset: u64,
+        needed: u64,
+    ) -> Result<Self, MmioError> {
+        let phys = device.mmio_base().ok_or(MmioError::NotMemory)?;
+        let size = device.mmio_size().ok_or(MmioError::NotMemory)?;
+        if size < needed {
+            return Err(MmioError::TooSmall { size, needed });
+        }
+        let base = phys
+            .checked_add(phys_mem_offset)
+            .and_then(|virt| NonNull::new(virt as *mut u8))
+            .ok_or(MmioError::NotMemory)?;
+        Ok(Self {
+            base,
+            size: needed as usize,
+        })
+    }
+
+    /// Wrap `size` bytes at virtual address `base`.
+    ///
+    /// # Safety
+    ///
+    /// The range must be mapped and stay valid for volatile accesses for as
+    /// long as the region and its registers live.
+    pub unsafe fn from_raw(base: NonNull<u8>, size: usize) -> Self {
+        Self { base, size }
+    }
+
+    /// Size of the region in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The `T`-wide register at byte `offset`.
+    pub fn register<T: RegisterValue>(&self, offset: usize) -> Result<Mmio<'_, T>, MmioError> {
+        let width = core::mem::size_of::<T>();
+        if width > self.size || offset > self.size - width {
+            return Err(MmioError::OutOfBounds { offset });
+        }
+        // Widths are powers of two
+        if (self.base.as_ptr() as usize).wrapping_add(offset) & (width - 1) != 0 {
+            return Err(MmioError::Misaligned { offset });
+        }
+        Ok(Mmio {
+            // SAFETY: `offset + width` is within the region.
+            ptr: unsafe { self.base.as_ptr().add(offset) }.cast(),
+            _region: PhantomData,
+        })
+    }
+}
+
+/// A device register of type `T`.
+#[derive(Debug, Clone, Copy)]
+pub struct Mmio<'a, T> {
+    ptr: *mut T,
+    _region: PhantomData<&'a MmioRegion>,
+}
+
+impl<T: RegisterValue> Mmio<'_, T> {
+    /// Read the register.
+    pub fn read(&self) -> T {
+        // SAFETY: `MmioRegion::register` checked the pointer is in bounds and
+        // aligned, and the region outlives `self`.
+        unsafe { ptr::read_volatile(self.ptr) }
+    }
+
+    /// Write the register.
+    pub fn write(&self, value: T) {
+        // SAFETY: As for `read`.
+        unsafe { ptr::write_volatile(self.ptr, value) }
+    }
+
+    /// Read the register, change the value and write it back.
+    pub fn modify(&self, f: impl FnOnce(T) -> T) {
+        self.write(f(self.read()));
+    }
+}
@@ -0,0 +1,388 @@
+//! Physical memory management.
+//!
+//! At boot the page mapper and frame allocator are used by the heap and
+//! the other early mappings, then handed to `install`. From then on
+//! `map_pages`/`unmap_pages` map memory on demand (e.g. WASM process
+//! arenas, see `allocator::arena`); unmapped frames go on a free list and
+//! are reused before fresh ones. Drivers get physically contiguous runs
+//! for DMA from `allocate_contiguous`.
+//!
+//! The boot memory map stays available through `regions`, annotated with
+//! what the kernel has taken from it. One frame of it is never allocated:
+//! `persistent_frame` keeps it for what must survive a warm reset. Device
+//! registers are reached through `mmio`.
+
+pub mod mmio;
+
+use alloc::vec::Vec;
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use spin::{Mutex, Once};
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page,
+        PageTable, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// Size of a page and a frame.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Initialize a new OffsetPageTable.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`. Also, this function must be only called once
+/// to avoid aliasing `&mut` references (which is undefined behavior).
+/// Initializes the kernel page mapper.
+///
+/// # Safety
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`.
+pub unsafe fn init_mapper(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    let level_4_table = active_level_4_table(physical_memory_offset);
+    OffsetPageTable::new(level_4_table, physical_memory_offset)
+}
+
+/// Returns a mutable reference to the active level 4 table.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`. Also, this function must be only called once
+/// to avoid aliasing `&mut` references (which is undefined behavior).
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    &mut *page_table_ptr // unsafe
+}
+
+/// A FrameAllocator that returns usable frames from the bootloader's memory map.
+pub struct BootInfoFrameAllocator {
+    memory_map: &'static MemoryMap,
+    next: usize,
+    /// `persistent_frame`, which is never handed out.
+    reserved: Option<PhysFrame>,
+}
+
+impl BootInfoFrameAllocator {
+    /// Create a FrameAllocator from the passed memory map.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the passed
+    /// memory map is valid. The main constraint is that all frames that are marked
+    /// as `USABLE` in it are really unused.
+    ///
+    /// The map is also kept for `regions`.
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        MEMORY_MAP.call_once(|| memory_map);
+        BootInfoFrameAllocator {
+            memory_map,
+            next: 0,
+            reserved: persistent_frame(memory_map),
+        }
+    }
+
+    /// Returns an iterator over the usable frames specified in the memory map.
+    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        // get usable regions from memory map
+        let regions = self.memory_map.iter();
+        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
+        // map each region to its address range
+        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
+        // transform to an iterator of frame start addresses
+        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
+        // create `PhysFrame` types from the start addresses
+        let reserved = self.reserved;
+        frame_addresses
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+            .filter(move |&frame| Some(frame) != reserved)
+    }
+}
+
+/// The frame kept for data that must survive a warm reset (see
+/// `boot::crashloop`): the last usable one in the boot memory map. The
+/// map is the same on every boot of a machine, so the frame is too, and
+/// the frame allocator never hands it out.
+pub fn persistent_frame(memory_map: &MemoryMap) -> Option<PhysFrame> {
+    let region = memory_map
+        .iter()
+        .rfind(|r| r.region_type == MemoryRegionType::Usable)?;
+    let end = region.range.end_addr();
+    (end > region.range.start_addr())
+        .then(|| PhysFrame::containing_address(PhysAddr::new(end - PAGE_SIZE)))
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.usable_frames().nth(self.next);
+        self.next += 1;
+        frame
+    }
+}
+
+/// Frames: fresh ones from the boot memory map, plus those given back.
+///
+/// Returned frames form a list threaded through the frames themselves
+/// (each holds the physical address of the next), so keeping it needs no
+/// heap: the allocator itself maps memory through here.
+struct Frames {
+    boot: BootInfoFrameAllocator,
+    physical_memory_offset: VirtAddr,
+    /// Physical address of the first free frame; 0 if none.
+    free_head: u64,
+    free_count: usize,
+}
+
+// SAFETY: Frames come from usable memory or were given back unused.
+unsafe impl FrameAllocator<Size4KiB> for Frames {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if self.free_head == 0 {
+            return self.boot.allocate_frame();
+        }
+        let frame = PhysFrame::containing_address(PhysAddr::new(self.free_head));
+        // SAFETY: Frames on the list are unused and mapped at the offset;
+        // the first word holds the next entry.
+        self.free_head = unsafe { *(self.physical_memory_offset + self.free_head).as_ptr::<u64>() };
+        self.free_count -= 1;
+        Some(frame)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for Frames {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let addr = frame.start_address().as_u64();
+        // SAFETY: The caller gives up the frame; it is mapped at the offset
+        // like all physical memory.
+        unsafe { *(self.physical_memory_offset + addr).as_mut_ptr::<u64>() = self.free_head };
+        self.free_head = addr;
+        self.free_count += 1;
+    }
+}
+
+/// Page tables and frames kept after boot.
+struct KernelMemory {
+    mapper: OffsetPageTable<'static>,
+    frames: Frames,
+}
+
+/// Set by `install`. A plain spin lock: it is taken from inside the global
+/// allocator, so it must not allocate or record statistics.
+static KERNEL_MEMORY: Mutex<Option<KernelMemory>> = Mutex::new(None);
+
+/// Keep the mapper and frame allocator for mappings after boot.
+pub fn install(mapper: OffsetPageTable<'static>, frames: BootInfoFrameAllocator) {
+    let physical_memory_offset = mapper.phys_offset();
+    *KERNEL_MEMORY.lock() = Some(KernelMemory {
+        mapper,
+        frames: Frames {
+            boot: frames,
+            physical_memory_offset,
+            free_head: 0,
+            free_count: 0,
+        },
+    });
+}
+
+/// Map `count` pages from `start` to fresh frames with `flags`.
+///
+/// On failure the pages mapped so far are unmapped again.
+pub fn map_pages(
+    start: VirtAddr,
+    count: u64,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let mut guard = KERNEL_MEMORY.lock();
+    let memory = guard.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+    for i in 0..count {
+        let page = Page::containing_address(start + i * PAGE_SIZE);
+        let mapped = memory
+            .frames
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)
+            .and_then(|frame| {
+                // SAFETY: The frame is unused and callers own the virtual
+                // range, so no live mapping is replaced.
+                unsafe { memory.mapper.map_to(page, frame, flags, &mut memory.frames) }
+            });
+        match mapped {
+            Ok(flush) => flush.flush(),
+            Err(e) => {
+                unmap_locked(memory, start, i);
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Unmap `count` pages from `start` and free their frames.
+///
+/// # Safety
+///
+/// Nothing may use the range any more.
+pub unsafe fn unmap_pages(start: VirtAddr, count: u64) {
+    if let Some(memory) = KERNEL_MEMORY.lock().as_mut() {
+        unmap_locked(memory, start, count);
+    }
+}
+
+fn unmap_locked(memory: &mut KernelMemory, start: VirtAddr, count: u64) {
+    for i in 0..count {
+        let page: Page<Size4KiB> = Page::containing_address(start + i * PAGE_SIZE);
+        if let Ok((frame, flush)) = memory.mapper.unmap(page) {
+            flush.flush();
+            // SAFETY: The page was the frame's only mapping and is gone.
+            unsafe { memory.frames.deallocate_frame(frame) };
+        }
+    }
+}
+
+/// Allocate `count` physically contiguous, zeroed frames, e.g. for DMA.
+/// Returns the physical address of the first and where it is mapped.
+///
+/// The run is taken from fresh frames, which come in address order; a run
+/// cut short by the end of a usable region is freed and started again in
+/// the next one.
+pub fn allocate_contiguous(count: u64) -> Option<(PhysAddr, VirtAddr)> {
+    let mut guard = KERNEL_MEMORY.lock();
+    let frames = &mut guard.as_mut()?.frames;
+    if count == 0 {
+        return None;
+    }
+    let mut first = frames.boot.allocate_frame()?;
+    let mut taken = 1;
+    while taken < count {
+        let next = frames.boot.allocate_frame();
+        let expected = first.start_address() + taken * PAGE_SIZE;
+        if !next.is_some_and(|frame| frame.start_address() == expected) {
+            for i in 0..taken {
+                // SAFETY: The frames were just allocated and never used.
+                unsafe { frames.deallocate_frame(first + i) };
+            }
+            first = next?;
+            taken = 0;
+        }
+        taken += 1;
+    }
+    let virt = frames.physical_memory_offset + first.start_address().as_u64();
+    // SAFETY: The frames are ours and mapped at the offset like all
+    // physical memory.
+    unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, (count * PAGE_SIZE) as usize) };
+    Some((first.start_address(), virt))
+}
+
+/// Give back `count` frames from `allocate_contiguous`.
+///
+/// # Safety
+///
+/// Nothing, including devices, may use the frames any more.
+pub unsafe fn free_contiguous(start: PhysAddr, count: u64) {
+    if let Some(memory) = KERNEL_MEMORY.lock().as_mut() {
+        let first = PhysFrame::<Size4KiB>::containing_address(start);
+        for i in 0..count {
+            // SAFETY: The caller gives the frames up.
+            unsafe { memory.frames.deallocate_frame(first + i) };
+        }
+    }
+}
+
+/// Frames on the free list, ready for reuse.
+pub fn recycled_frames() -> usize {
+    KERNEL_MEMORY
+        .lock()
+        .as_ref()
+        .map_or(0, |memory| memory.frames.free_count)
+}
+
+/// The bootloader's memory map, set by `BootInfoFrameAllocator::init`.
+static MEMORY_MAP: Once<&'static MemoryMap> = Once::new();
+
+/// A region of the boot memory map.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    /// First physical address.
+    pub start: u64,
+    /// Physical address after the end.
+    pub end: u64,
+    /// What the firmware or bootloader uses it for.
+    pub kind: MemoryRegionType,
+    /// Bytes of it the frame allocator has handed out (usable regions,
+    /// once `install` has run).
+    pub allocated: u64,
+}
+
+impl Region {
+    /// Size in bytes.
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Short name of the region type.
+    pub fn kind_name(&self) -> &'static str {
+        match self.kind {
+            MemoryRegionType::Usable => "usable",
+            MemoryRegionType::InUse => "in use",
+            MemoryRegionType::Reserved => "reserved",
+            MemoryRegionType::AcpiReclaimable => "ACPI reclaimable",
+            MemoryRegionType::AcpiNvs => "ACPI NVS",
+            MemoryRegionType::BadMemory => "bad",
+            MemoryRegionType::Kernel => "kernel",
+            MemoryRegionType::KernelStack => "kernel stack",
+            MemoryRegionType::PageTable => "page tables",
+            MemoryRegionType::Bootloader => "bootloader",
+            MemoryRegionType::FrameZero => "frame zero",
+            MemoryRegionType::Empty => "empty",
+            MemoryRegionType::BootInfo => "boot info",
+            MemoryRegionType::Package => "package",
+            MemoryRegionType::UnknownUefi(_) => "UEFI (unknown)",
+            MemoryRegionType::UnknownBios(_) => "BIOS (unknown)",
+        }
+    }
+}
+
+/// Regions of the boot memory map, in address order; empty before the
+/// frame allocator is created.
+pub fn regions() -> Vec<Region> {
+    let Some(map) = MEMORY_MAP.get() else {
+        return Vec::new();
+    };
+    // Usable frames are handed out in map order
+    let mut handed_out = boot_frames_allocated() * PAGE_SIZE;
+    map.iter()
+        .map(|region| {
+            let start = region.range.start_addr();
+            let end = region.range.end_addr();
+            let allocated = if region.region_type == MemoryRegionType::Usable {
+                let taken = handed_out.min(end - start);
+                handed_out -= taken;
+                taken
+            } else {
+                0
+            };
+            Region {
+                start,
+                end,
+                kind: region.region_type,
+                allocated,
+            }
+        })
+        .collect()
+}
+
+/// Frames the boot frame allocator has handed out, including those since
+/// returned to the free list.
+fn boot_frames_allocated() -> u64 {
+    KERNEL_MEMORY
+        .lock()
+        .as_ref()
+        .map_or(0, |memory| memory.frames.boot.next as u64)
+}
@@ -0,0 +1,244 @@
+//! IPv4 address conflict detection (RFC 5227).
+//!
+//! Before an address from DHCP or the link-local fallback is used, a
+//! `Probe` sends ARP probes for it (sender address 0.0.0.0) and watches
+//! incoming ARP traffic for another host claiming it. If none does, the
+//! address is announced with gratuitous ARP so neighbours update their
+//! caches, and the watch continues for as long as the address is held.
+//!
+//! smoltcp has no hook for ARP, so frames are inspected in
+//! `NetworkRxToken::consume` before smoltcp sees them, and probes are sent
+//! with `NetworkStack::send_raw`. Intervals are shorter than the RFC's to
+//! keep boot quick on a virtual network.
+
+use super::stack::NetworkStack;
+use crate::sync::TrackedMutex;
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{
+    ArpOperation, ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol,
+    EthernetRepr, Ipv4Address, ETHERNET_HEADER_LEN,
+};
+
+/// Probes sent before an address is considered free.
+pub const PROBE_NUM: u8 = 3;
+
+/// Time between probes, and after the last one before the address is used.
+pub const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Gratuitous ARP announcements sent once the address is in use.
+pub const ANNOUNCE_NUM: u8 = 2;
+
+/// Time between announcements.
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Length of an ARP frame for IPv4 over Ethernet.
+pub const FRAME_LEN: usize = ETHERNET_HEADER_LEN + 28;
+
+/// The address being watched, and the first conflicting host seen.
+struct Watch {
+    ip: Ipv4Address,
+    mac: EthernetAddress,
+    probing: bool,
+    conflict: Option<EthernetAddress>,
+}
+
+static WATCH: TrackedMutex<Option<Watch>> = TrackedMutex::new("arp_watch", None);
+
+/// Outcome of a probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeEvent {
+    /// No other host answered the probes; the address may be used.
+    Clear,
+    /// The host with this MAC address uses the address.
+    Conflict(EthernetAddress),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Probing,
+    Announcing,
+    Watching,
+}
+
+/// Conflict detection for one address.
+pub struct Probe {
+    ip: Ipv4Address,
+    mac: EthernetAddress,
+    phase: Phase,
+    sent: u8,
+    next_at: Instant,
+}
+
+impl Probe {
+    /// Start probing for `ip` on behalf of the interface with address `mac`.
+    /// Replaces any earlier watch.
+    pub fn start(ip: Ipv4Address, mac: EthernetAddress, timestamp: Instant) -> Self {
+        *WATCH.lock() = Some(Watch {
+            ip,
+            mac,
+            probing: true,
+            conflict: None,
+        });
+        Self {
+            ip,
+            mac,
+            phase: Phase::Probing,
+            sent: 0,
+            next_at: timestamp,
+        }
+    }
+
+    /// The address probed for.
+    pub fn ip(&self) -> Ipv4Address {
+        self.ip
+    }
+
+    /// Send the probes and announcements that are due and report the
+    /// outcome: `Clear` once, after the last probe went unanswered, and
+    /// `Conflict` whenever another host claims the address.
+    pub fn poll(&mut self, stack: &mut NetworkStack, timestamp: Instant) -> Option<ProbeEvent> {
+        if let Some(mac) = WATCH
+            .lock()
+            .as_mut()
+            .and_then(|watch| watch.conflict.take())
+        {
+            return Some(ProbeEvent::Conflict(mac));
+        }
+        if timestamp < self.next_at {
+            return None;
+        }
+        match self.phase {
+            Phase::Probing if self.sent < PROBE_NUM => {
+                stack.send_raw(&probe_frame(self.mac, self.ip), timestamp);
+                self.sent += 1;
+                self.next_at = timestamp + PROBE_INTERVAL;
+                None
+            }
+            Phase::Probing => {
+                self.phase = Phase::Announcing;
+                self.sent = 0;
+                if let Some(watch) = WATCH.lock().as_mut() {
+                    watch.probing = false;
+                }
+                Some(ProbeEvent::Clear)
+            }
+            Phase::Announcing if self.sent < ANNOUNCE_NUM => {
+                stack.send_raw(&announce_frame(self.mac, self.ip), timestamp);
+                self.sent += 1;
+                self.next_at = timestamp + ANNOUNCE_INTERVAL;
+                None
+            }
+            Phase::Announcing => {
+                self.phase = Phase::Watching;
+                None
+            }
+            Phase::Watching => None,
+        }
+    }
+}
+
+/// Stop watching for conflicts, e.g. when the address is given up.
+pub fn stop() {
+    *WATCH.lock() = None;
+}
+
+/// Look at a received frame for a conflict with the watched address.
+pub fn observe(frame: &[u8]) {
+    let mut watch = WATCH.lock();
+    let Some(watch) = watch.as_mut() else {
+        return;
+    };
+    let Some((sender_mac, sender_ip, target_ip)) = parse(frame) else {
+        return;
+    };
+    if let Some(mac) = conflict(
+        watch.ip,
+        watch.mac,
+        watch.probing,
+        sender_mac,
+        sender_ip,
+        target_ip,
+    ) {
+        watch.conflict.get_or_insert(mac);
+    }
+}
+
+/// The host that conflicts with our use of `ip`, given an ARP packet's
+/// sender and target.
+///
+/// Any host claiming `ip` as its sender address conflicts. While probing,
+/// so does another host probing for `ip` at the same time.
+pub fn conflict(
+    ip: Ipv4Address,
+    mac: EthernetAddress,
+    probing: bool,
+    sender_mac: EthernetAddress,
+    sender_ip: Ipv4Address,
+    target_ip: Ipv4Address,
+) -> Option<EthernetAddress> {
+    if sender_mac == mac {
+        return None;
+    }
+    let claims = sender_ip == ip;
+    let competes = probing && sender_ip.is_unspecified() && target_ip == ip;
+    (claims || competes).then_some(sender_mac)
+}
+
+/// Sender hardware address, sender and target protocol addresses of an ARP
+/// frame.
+pub fn parse(frame: &[u8]) -> Option<(EthernetAddress, Ipv4Address, Ipv4Address)> {
+    let frame = EthernetFrame::new_checked(frame).ok()?;
+    if frame.ethertype() != EthernetProtocol::Arp {
+        return None;
+    }
+    let packet = ArpPacket::new_checked(frame.payload()).ok()?;
+    match ArpRepr::parse(&packet).ok()? {
+        ArpRepr::EthernetIpv4 {
+            source_hardware_addr,
+            source_protocol_addr,
+            target_protocol_addr,
+            ..
+        } => Some((
+            source_hardware_addr,
+            source_protocol_addr,
+            target_protocol_addr,
+        )),
+        _ => None,
+    }
+}
+
+/// An ARP probe for `ip`: a request with sender address 0.0.0.0.
+pub fn probe_frame(mac: EthernetAddress, ip: Ipv4Address) -> [u8; FRAME_LEN] {
+    request_frame(mac, Ipv4Address::UNSPECIFIED, ip)
+}
+
+/// A gratuitous ARP announcement of `ip`: a request with `ip` as both
+/// sender and target address.
+pub fn announce_frame(mac: EthernetAddress, ip: Ipv4Address) -> [u8; FRAME_LEN] {
+    request_frame(mac, ip, ip)
+}
+
+/// A broadcast ARP request.
+fn request_frame(
+    mac: EthernetAddress,
+    sender_ip: Ipv4Address,
+    target_ip: Ipv4Address,
+) -> [u8; FRAME_LEN] {
+    let mut buffer = [0u8; FRAME_LEN];
+    let mut frame = EthernetFrame::new_unchecked(&mut buffer[..]);
+    EthernetRepr {
+        src_addr: mac,
+        dst_addr: EthernetAddress::BROADCAST,
+        ethertype: EthernetProtocol::Arp,
+    }
+    .emit(&mut frame);
+    ArpRepr::EthernetIpv4 {
+        operation: ArpOperation::Request,
+        source_hardware_addr: mac,
+        source_protocol_addr: sender_ip,
+        target_hardware_addr: EthernetAddress([0; 6]),
+        target_protocol_addr: target_ip,
+    }
+    .emit(&mut ArpPacket::new_unchecked(frame.payload_mut()));
+    buffer
+}
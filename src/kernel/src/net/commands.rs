@@ -0,0 +1,956 @@
+//! Network shell commands.
+
+use super::dns::parse_ipv4;
+use super::{
+    httpd, poller, syslog, ConnectionSink, DhcpClient, DnsFailure, DnsResolver, Httpd,
+    NetworkDevice, NetworkStack, Syslog, TftpDirection,
+};
+use crate::terminal::commands::{parse_args, Opt, Spec};
+use crate::terminal::json::Json;
+use crate::terminal::registry::{self, Builtin};
+use crate::terminal::theme::{self, Role};
+use crate::terminal::{print_error, CommandContext};
+use crate::{print, println};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use smoltcp::socket::Socket;
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetAddress, IpAddress};
+
+/// Commands registered by the network subsystem.
+const COMMANDS: [Builtin; 11] = [
+    Builtin {
+        name: "ifconfig",
+        aliases: &["ip"],
+        usage: "",
+        help: "Show network configuration",
+        host_arg: Builtin::no_host,
+        run: |ctx, _| cmd_ifconfig(ctx.stack, ctx.dhcp),
+        json: |ctx, _| Some(json_ifconfig(ctx.stack, ctx.dhcp)),
+    },
+    Builtin {
+        name: "netstat",
+        aliases: &[],
+        usage: "[--cleanup]",
+        help: "List sockets and the stack's poll schedule",
+        host_arg: Builtin::no_host,
+        run: |ctx, args| cmd_netstat(ctx.stack, args, ctx.timestamp),
+        json: |ctx, args| Some(json_netstat(ctx.stack, args, ctx.timestamp)),
+    },
+    Builtin {
+        name: "nic",
+        aliases: &[],
+        usage: "[promisc on|off | filter add|del <mac>]",
+        help: "Show or change the NIC's receive filters",
+        host_arg: Builtin::no_host,
+        run: cmd_nic,
+        json: |ctx, args| args.is_empty().then(|| json_nic(ctx.stack)),
+    },
+    Builtin {
+        name: "dhcp",
+        aliases: &[],
+        usage: "[renew|release]",
+        help: "Show DHCP status or request new lease",
+        host_arg: Builtin::no_host,
+        run: cmd_dhcp,
+        json: |ctx, args| args.is_empty().then(|| json_dhcp(ctx.dhcp)),
+    },
+    Builtin {
+        name: "dns",
+        aliases: &["nslookup", "resolve"],
+        usage: "<host> | cache | flush",
+        help: "Resolve a hostname, show or clear the cache",
+        host_arg: Builtin::no_host,
+        run: cmd_dns,
+        json: |ctx, args| (args == ["cache"]).then(|| json_dns_cache(ctx.dns, ctx.timestamp)),
+    },
+    Builtin {
+        name: "connect",
+        aliases: &["nc"],
+        usage: "<host> <port>",
+        help: "Open TCP connection",
+        host_arg: Builtin::first_arg_host,
+        run: cmd_connect,
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "ping",
+        aliases: &[],
+        usage: "<host>",
+        help: "Send ICMP Echo Request",
+        host_arg: Builtin::first_arg_host,
+        run: cmd_ping,
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "traceroute",
+        aliases: &["tracert"],
+        usage: "<host>",
+        help: "Trace route with per-hop RTTs",
+        host_arg: Builtin::first_arg_host,
+        run: cmd_traceroute,
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "httpd",
+        aliases: &[],
+        usage: "start [dir] [port] | stop | status",
+        help: "Serve files over HTTP",
+        host_arg: Builtin::no_host,
+        run: cmd_httpd,
+        json: |ctx, args| matches!(args, [] | ["status"]).then(|| json_httpd(ctx.httpd)),
+    },
+    Builtin {
+        name: "tftp",
+        aliases: &[],
+        usage: "get|put <host> <file>",
+        help: "Transfer a file over TFTP",
+        host_arg: |args| (args.len() >= 3).then_some(1),
+        run: cmd_tftp,
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "log",
+        aliases: &[],
+        usage: "[status] | remote <host>|off",
+        help: "Stream kernel log to a syslog collector",
+        host_arg: |args| match args {
+            ["remote", host, ..] if *host != "off" => Some(1),
+            _ => None,
+        },
+        run: cmd_log,
+        json: |ctx, args| matches!(args, [] | ["status"]).then(|| json_log(ctx.syslog)),
+    },
+];
+
+/// Register the network commands with the shell.
+pub fn register() {
+    registry::register_builtins(&COMMANDS);
+}
+
+/// Network configuration as JSON.
+fn json_ifconfig(stack: &NetworkStack, dhcp: &DhcpClient) -> Json {
+    let mac = stack.device().mac_address().map(|mac| {
+        alloc::format!(
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0],
+            mac[1],
+            mac[2],
+            mac[3],
+            mac[4],
+            mac[5]
+        )
+    });
+    let gateway = dhcp
+        .config()
+        .and_then(|config| config.gateway)
+        .map(|gw| gw.to_string());
+    let dns: Vec<String> = stack.dns_servers.iter().map(|s| s.to_string()).collect();
+    Json::object()
+        .with("mac", mac)
+        .with("link", stack.link_up())
+        .with("checksum_offload", checksum_offload(stack))
+        .with("ip", stack.ip_address().map(|ip| ip.to_string()))
+        .with("gateway", gateway)
+        .with("dns", dns)
+        .with("dhcp", alloc::format!("{:?}", dhcp.state()))
+}
+
+/// Whether the NIC offloads checksums; `None` without an e1000.
+fn checksum_offload(stack: &NetworkStack) -> Option<bool> {
+    match stack.device() {
+        NetworkDevice::E1000(nic) => Some(nic.checksum_offload()),
+        _ => None,
+    }
+}
+
+/// One socket as protocol, local and remote endpoint, and state.
+fn socket_rows(stack: &mut NetworkStack) -> Vec<[String; 4]> {
+    let endpoint = |endpoint: Option<smoltcp::wire::IpEndpoint>| {
+        endpoint.map_or_else(|| "*".to_string(), |endpoint| endpoint.to_string())
+    };
+    stack
+        .sockets()
+        .iter()
+        .map(|(_, socket)| match socket {
+            Socket::Tcp(tcp) => [
+                "tcp".to_string(),
+                endpoint(tcp.local_endpoint()),
+                endpoint(tcp.remote_endpoint()),
+                tcp.state().to_string(),
+            ],
+            Socket::Udp(udp) => [
+                "udp".to_string(),
+                udp.endpoint().to_string(),
+                "*".to_string(),
+                open_state(udp.is_open()),
+            ],
+            Socket::Icmp(icmp) => [
+                "icmp".to_string(),
+                "*".to_string(),
+                "*".to_string(),
+                open_state(icmp.is_open()),
+            ],
+            Socket::Dhcpv4(_) => [
+                "dhcp".to_string(),
+                "*:68".to_string(),
+                "*:67".to_string(),
+                String::new(),
+            ],
+            Socket::Dns(_) => [
+                "dns".to_string(),
+                "*".to_string(),
+                "*:53".to_string(),
+                String::new(),
+            ],
+        })
+        .collect()
+}
+
+/// State shown for connectionless sockets.
+fn open_state(open: bool) -> String {
+    if open { "BOUND" } else { "UNBOUND" }.to_string()
+}
+
+/// Arguments of `netstat`; `--cleanup` removes finished detached sockets
+/// before listing.
+const NETSTAT_ARGS: Spec = Spec {
+    name: "netstat",
+    options: &[Opt::flag("cleanup")],
+    positional: &[],
+};
+
+/// Arguments of `connect`.
+const CONNECT_ARGS: Spec = Spec {
+    name: "connect",
+    options: &[],
+    positional: &["<host>", "<port>"],
+};
+
+/// Arguments of `ping`.
+const PING_ARGS: Spec = Spec {
+    name: "ping",
+    options: &[],
+    positional: &["<host>"],
+};
+
+/// Arguments of `traceroute`.
+const TRACEROUTE_ARGS: Spec = Spec {
+    name: "traceroute",
+    options: &[],
+    positional: &["<host>"],
+};
+
+/// Sockets and poll schedule as JSON.
+fn json_netstat(stack: &mut NetworkStack, args: &[&str], timestamp: Instant) -> Json {
+    let removed = NETSTAT_ARGS
+        .parse(args)
+        .is_ok_and(|args| args.flag("cleanup"))
+        .then(|| stack.collect_garbage(timestamp));
+    let sockets: Vec<Json> = socket_rows(stack)
+        .into_iter()
+        .map(|[proto, local, remote, state]| {
+            Json::object()
+                .with("proto", proto)
+                .with("local", local)
+                .with("remote", remote)
+                .with("state", state)
+        })
+        .collect();
+    let stats = poller::stats();
+    Json::object()
+        .with("sockets", sockets)
+        .with("detached", stack.detached_count())
+        .with("removed", removed)
+        .with("poll_delay_ms", stats.delay_ms)
+        .with("polls", stats.polls)
+        .with("wakeups", stats.wakeups)
+}
+
+/// Receive filter settings as JSON.
+fn json_nic(stack: &NetworkStack) -> Json {
+    let device = stack.device();
+    let filters: Vec<String> = device.filters().iter().map(format_mac).collect();
+    Json::object()
+        .with("promiscuous", device.promiscuous())
+        .with("filters", filters)
+}
+
+/// DHCP state and lease as JSON.
+fn json_dhcp(dhcp: &DhcpClient) -> Json {
+    let lease = dhcp.config().map(|config| {
+        let dns: Vec<String> = config.dns_servers.iter().map(|s| s.to_string()).collect();
+        Json::object()
+            .with("ip", config.ip.to_string())
+            .with("prefix_len", config.prefix_len)
+            .with("gateway", config.gateway.map(|gw| gw.to_string()))
+            .with("dns", dns)
+    });
+    Json::object()
+        .with("state", alloc::format!("{:?}", dhcp.state()))
+        .with("lease", lease.unwrap_or(Json::Null))
+}
+
+/// DNS cache entries as JSON.
+fn json_dns_cache(dns: &DnsResolver, timestamp: Instant) -> Json {
+    let entries: Vec<Json> = dns
+        .cache()
+        .iter(timestamp)
+        .map(|(hostname, entry)| {
+            let addresses: Vec<String> = entry.addresses.iter().map(|a| a.to_string()).collect();
+            Json::object()
+                .with("hostname", hostname)
+                .with("addresses", addresses)
+                .with("negative", entry.is_negative())
+                .with("failure", entry.failure.map(DnsFailure::label))
+                .with("ttl", (entry.expires - timestamp).secs())
+        })
+        .collect();
+    Json::Array(entries)
+}
+
+/// HTTP server status as JSON.
+fn json_httpd(server: &Httpd) -> Json {
+    match server.status() {
+        Some(status) => Json::object()
+            .with("running", true)
+            .with("root", status.root)
+            .with("port", status.port)
+            .with("connections", status.connections)
+            .with("requests", status.requests),
+        None => Json::object().with("running", false),
+    }
+}
+
+/// Log level and remote sink as JSON.
+fn json_log(syslog: &Syslog) -> Json {
+    let remote = syslog.status().map(|status| {
+        Json::object()
+            .with("address", status.remote.to_string())
+            .with("queued", status.queued)
+            .with("sent", status.sent)
+            .with("dropped", status.dropped)
+    });
+    Json::object()
+        .with("level", log::max_level().as_str())
+        .with("remote", remote.unwrap_or(Json::Null))
+}
+
+/// MAC address in the usual colon-separated form.
+fn format_mac(mac: &[u8; 6]) -> String {
+    alloc::format!(
+        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0],
+        mac[1],
+        mac[2],
+        mac[3],
+        mac[4],
+        mac[5]
+    )
+}
+
+/// Show or change promiscuous mode and the extra address filters.
+fn cmd_nic(ctx: &mut CommandContext, args: &[&str]) {
+    let device = ctx.stack.device_mut();
+    let result = match args {
+        [] => {
+            println!(
+                "Promiscuous: {}",
+                if device.promiscuous() { "on" } else { "off" }
+            );
+            let filters = device.filters();
+            if filters.is_empty() {
+                println!("No extra address filters");
+            }
+            for mac in &filters {
+                let kind = if mac[0] & 1 != 0 {
+                    "multicast"
+                } else {
+                    "unicast"
+                };
+                println!("  {}  {}", format_mac(mac), kind);
+            }
+            return;
+        }
+        ["promisc", "on"] => device.set_promiscuous(true),
+        ["promisc", "off"] => device.set_promiscuous(false),
+        ["filter", action @ ("add" | "del"), mac] => {
+            let Ok(mac) = mac.parse::<EthernetAddress>() else {
+                println!("Invalid MAC address: {}", mac);
+                return;
+            };
+            if *action == "add" {
+                device.add_filter(mac.0)
+            } else {
+                match device.remove_filter(mac.0) {
+                    Ok(false) => {
+                        println!("{} is not filtered for", format_mac(&mac.0));
+                        return;
+                    }
+                    result => result.map(|_| ()),
+                }
+            }
+        }
+        _ => {
+            println!("Usage: nic [promisc on|off | filter add|del <mac>]");
+            return;
+        }
+    };
+    if let Err(e) = result {
+        theme::set(Role::Error);
+        println!("nic: {}", e);
+        theme::reset();
+    }
+}
+
+/// List sockets and the stack's poll schedule.
+fn cmd_netstat(stack: &mut NetworkStack, args: &[&str], timestamp: Instant) {
+    let Some(args) = parse_args(&NETSTAT_ARGS, args) else {
+        return;
+    };
+    if args.flag("cleanup") {
+        let removed = stack.collect_garbage(timestamp);
+        println!("Removed {} finished sockets", removed);
+    }
+    theme::set(Role::Accent);
+    println!("{:<6} {:<21} {:<21} STATE", "PROTO", "LOCAL", "REMOTE");
+    theme::reset();
+    for [proto, local, remote, state] in socket_rows(stack) {
+        println!("{:<6} {:<21} {:<21} {}", proto, local, remote, state);
+    }
+
+    let stats = poller::stats();
+    println!();
+    match stats.delay_ms {
+        Some(ms) => print!("Next poll due in {} ms", ms),
+        None => print!("No socket timers pending"),
+    }
+    println!(
+        " (polling at least every {} ms; {} polls, {} early wakeups)",
+        poller::MAX_POLL_DELAY_MS,
+        stats.polls,
+        stats.wakeups
+    );
+    let detached = stack.detached_count();
+    if detached > 0 {
+        println!(
+            "{} detached sockets are removed once finished (netstat --cleanup)",
+            detached
+        );
+    }
+}
+
+fn cmd_ifconfig(stack: &NetworkStack, dhcp: &DhcpClient) {
+    println!();
+    theme::set(Role::Accent);
+    println!("Network Configuration");
+    println!("---------------------");
+    theme::reset();
+
+    // MAC address
+    print!("  MAC:     ");
+    if let Some(mac) = stack.device().mac_address() {
+        theme::set(Role::Warning);
+        println!(
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        );
+        theme::reset();
+    } else {
+        println!("None (point-to-point link)");
+    }
+
+    // Link state
+    print!("  Link:    ");
+    if stack.link_up() {
+        theme::set(Role::Success);
+        println!("up");
+    } else {
+        theme::set(Role::Error);
+        println!("down");
+    }
+    theme::reset();
+
+    // Checksum offload
+    if let NetworkDevice::E1000(nic) = stack.device() {
+        print!("  Offload: ");
+        if nic.checksum_offload() {
+            println!("checksums ({} bad frames dropped)", nic.checksum_errors());
+        } else {
+            println!("off");
+        }
+    }
+
+    // IP address
+    print!("  IP:      ");
+    if let Some(ip) = stack.ip_address() {
+        theme::set(Role::Success);
+        println!("{}", ip);
+    } else {
+        theme::set(Role::Error);
+        println!("Not configured");
+    }
+    theme::reset();
+
+    // Gateway
+    print!("  Gateway: ");
+    if let Some(config) = dhcp.config() {
+        if let Some(gw) = config.gateway {
+            theme::set(Role::Warning);
+            println!("{}", gw);
+        } else {
+            println!("None");
+        }
+    } else {
+        println!("None");
+    }
+    theme::reset();
+
+    // DNS servers
+    print!("  DNS:     ");
+    if !stack.dns_servers.is_empty() {
+        theme::set(Role::Warning);
+        for (i, server) in stack.dns_servers.iter().enumerate() {
+            if i > 0 {
+                print!(", ");
+            }
+            print!("{}", server);
+        }
+        println!();
+    } else {
+        println!("None");
+    }
+    theme::reset();
+
+    // DHCP state
+    print!("  DHCP:    ");
+    theme::set(Role::Warning);
+    println!("{:?}", dhcp.state());
+    theme::reset();
+    println!();
+}
+
+/// Handle DHCP commands.
+fn cmd_dhcp(ctx: &mut CommandContext, args: &[&str]) {
+    let dhcp = &mut *ctx.dhcp;
+    match args.first().map(|s| s.to_lowercase()).as_deref() {
+        Some("renew") => {
+            println!("Requesting DHCP renewal...");
+            dhcp.renew(ctx.stack);
+        }
+        Some("release") => {
+            println!("DHCP release not yet implemented");
+        }
+        _ => {
+            println!("DHCP State: {:?}", dhcp.state());
+            if let Some(config) = dhcp.config() {
+                println!("  IP: {}/{}", config.ip, config.prefix_len);
+                if let Some(gw) = config.gateway {
+                    println!("  Gateway: {}", gw);
+                }
+                if !config.dns_servers.is_empty() {
+                    print!("  DNS: ");
+                    for (i, dns) in config.dns_servers.iter().enumerate() {
+                        if i > 0 {
+                            print!(", ");
+                        }
+                        print!("{}", dns);
+                    }
+                    println!();
+                }
+            }
+        }
+    }
+}
+
+/// Handle DNS commands.
+fn cmd_dns(ctx: &mut CommandContext, args: &[&str]) {
+    let dns = &mut *ctx.dns;
+    let timestamp = ctx.timestamp;
+    match args.first().copied() {
+        Some("cache") => {
+            // Stale entries are not listed, so they must not count either
+            dns.purge_cache(timestamp);
+            if dns.cache().is_empty() {
+                println!("DNS cache is empty");
+                return;
+            }
+            for (hostname, entry) in dns.cache().iter(timestamp) {
+                let ttl = (entry.expires - timestamp).secs();
+                print!("  {:<32} ", hostname);
+                if let Some(failure) = entry.failure {
+                    theme::set(Role::Error);
+                    print!("{}", failure.label());
+                } else {
+                    theme::set(Role::Success);
+                    for (i, addr) in entry.addresses.iter().enumerate() {
+                        if i > 0 {
+                            print!(", ");
+                        }
+                        print!("{}", addr);
+                    }
+                }
+                theme::reset();
+                println!("  (ttl {}s)", ttl);
+            }
+        }
+        Some("flush") => {
+            let count = dns.cache().len();
+            dns.flush_cache();
+            println!("Flushed {} DNS cache entries", count);
+        }
+        Some(hostname) => cmd_dns_lookup(hostname, ctx.stack, dns, timestamp),
+        None => println!("Usage: dns <hostname> | dns cache | dns flush"),
+    }
+}
+
+/// Handle DNS lookup.
+fn cmd_dns_lookup(
+    hostname: &str,
+    stack: &mut NetworkStack,
+    dns: &mut DnsResolver,
+    timestamp: Instant,
+) {
+    // Check if it's already an IP address
+    if let Some(ip) = parse_ipv4(hostname) {
+        println!("{} -> {}", hostname, ip);
+        return;
+    }
+
+    match dns.cached(hostname, timestamp) {
+        Some(Ok(result)) => {
+            print!("{} -> ", hostname);
+            theme::set(Role::Success);
+            for (i, addr) in result.addresses.iter().enumerate() {
+                if i > 0 {
+                    print!(", ");
+                }
+                print!("{}", addr);
+            }
+            theme::reset();
+            println!(" (cached)");
+            return;
+        }
+        Some(Err(e)) => {
+            theme::set(Role::Error);
+            println!("{}: {} (cached)", hostname, e);
+            theme::reset();
+            return;
+        }
+        None => {}
+    }
+
+    // Initialize DNS resolver if needed
+    if !dns.is_ready() {
+        if let Err(e) = dns.init(stack) {
+            theme::set(Role::Error);
+            println!("DNS resolver: {}", e);
+            theme::reset();
+            return;
+        }
+    }
+
+    if !dns.is_ready() {
+        theme::set(Role::Error);
+        println!("DNS resolver not ready (no DNS servers configured)");
+        theme::reset();
+        return;
+    }
+
+    print!("Resolving {}... ", hostname);
+
+    match dns.resolve(stack, hostname) {
+        Ok(_handle) => {
+            // The DNS task polls the resolver and prints the answer.
+            println!("(query started)");
+        }
+        Err(e) => {
+            theme::set(Role::Error);
+            println!("Failed: {}", e);
+            theme::reset();
+        }
+    }
+}
+
+/// Handle TCP connect.
+fn cmd_connect(ctx: &mut CommandContext, args: &[&str]) {
+    let Some(args) = parse_args(&CONNECT_ARGS, args) else {
+        return;
+    };
+    let [host, port] = args.positional() else {
+        return;
+    };
+    let Ok(port) = port.parse::<u16>() else {
+        println!("Invalid port number");
+        return;
+    };
+
+    // Parse or resolve the host
+    let ip = if let Some(ip) = parse_ipv4(host) {
+        ip
+    } else {
+        // Hostnames are resolved by the shell before execution
+        theme::set(Role::Error);
+        println!("Invalid address: {}", host);
+        theme::reset();
+        return;
+    };
+
+    println!("Connecting to {}:{}...", ip, port);
+
+    let stack = &mut *ctx.stack;
+    let handle = match stack.tcp_socket() {
+        Ok(handle) => handle,
+        Err(e) => {
+            theme::set(Role::Error);
+            println!("Connection failed: {}", e);
+            theme::reset();
+            return;
+        }
+    };
+    let remote = smoltcp::wire::IpEndpoint::new(IpAddress::Ipv4(ip), port);
+    let local_port = 49152 + (ip.0[3] as u16 % 1000); // Simple ephemeral port
+
+    match stack.tcp_connect(handle, remote, local_port) {
+        Ok(()) => {
+            // Nothing keeps the handle; the stack removes it once closed,
+            // and the stack poller reports how the connection goes
+            stack.watch_connection(handle, ConnectionSink::Kernel, ctx.timestamp);
+            stack.detach(handle, ctx.timestamp);
+        }
+        Err(e) => {
+            stack.release_socket(handle);
+            print_error("connect", &e);
+        }
+    }
+}
+
+/// Handle HTTP server commands.
+fn cmd_httpd(ctx: &mut CommandContext, args: &[&str]) {
+    let stack = &mut *ctx.stack;
+    let server = &mut *ctx.httpd;
+    match args.first().copied() {
+        Some("start") => {
+            let root = args.get(1).unwrap_or(&"/www");
+            let port = match args.get(2).map(|p| p.parse::<u16>()) {
+                None => httpd::DEFAULT_PORT,
+                Some(Ok(port)) => port,
+                Some(Err(_)) => {
+                    println!("Invalid port number");
+                    return;
+                }
+            };
+            match server.start(stack, root, port) {
+                Ok(()) => {
+                    theme::set(Role::Success);
+                    println!("Serving {} on port {}", root, port);
+                    theme::reset();
+                }
+                Err(e) => {
+                    theme::set(Role::Error);
+                    println!("httpd: {}", e);
+                    theme::reset();
+                }
+            }
+        }
+        Some("stop") => {
+            if server.stop(stack) {
+                println!("HTTP server stopped");
+            } else {
+                println!("HTTP server not running");
+            }
+        }
+        Some("status") | None => match server.status() {
+            Some(status) => {
+                println!("Serving {} on port {}", status.root, status.port);
+                println!("  Connections: {}", status.connections);
+                println!("  Requests:    {}", status.requests);
+            }
+            None => println!("HTTP server not running"),
+        },
+        Some(_) => println!("Usage: httpd start [dir] [port] | httpd stop | httpd status"),
+    }
+}
+
+/// Handle TFTP transfers.
+fn cmd_tftp(ctx: &mut CommandContext, args: &[&str]) {
+    let direction = match args.first().copied() {
+        Some("get") => Some(TftpDirection::Get),
+        Some("put") => Some(TftpDirection::Put),
+        _ => None,
+    };
+    let (Some(direction), Some(host), Some(&file)) = (direction, args.get(1), args.get(2)) else {
+        println!("Usage: tftp get|put <host> <file>");
+        return;
+    };
+
+    let Some(server) = parse_ipv4(host) else {
+        println!("Invalid address: {}", host);
+        return;
+    };
+
+    let tftp = &mut *ctx.tftp;
+    if tftp.is_running() {
+        theme::set(Role::Error);
+        println!("A TFTP transfer is already in progress");
+        theme::reset();
+        return;
+    }
+
+    let result = match direction {
+        TftpDirection::Get => tftp.get(ctx.stack, server, file, ctx.timestamp),
+        TftpDirection::Put => tftp.put(ctx.stack, server, file, ctx.timestamp),
+    };
+    match result {
+        Ok(()) => println!("tftp: transferring {} with {}...", file, server),
+        Err(e) => {
+            theme::set(Role::Error);
+            println!("tftp: {}: {}", file, e);
+            theme::reset();
+        }
+    }
+}
+
+/// Handle logging commands.
+fn cmd_log(ctx: &mut CommandContext, args: &[&str]) {
+    let stack = &mut *ctx.stack;
+    let sink = &mut *ctx.syslog;
+    match (args.first().copied(), args.get(1).copied()) {
+        (Some("remote"), Some("off")) => {
+            if sink.stop(stack) {
+                println!("Remote logging stopped");
+            } else {
+                println!("Remote logging not enabled");
+            }
+        }
+        (Some("remote"), Some(host)) => {
+            let Some(server) = parse_ipv4(host) else {
+                println!("Invalid address: {}", host);
+                return;
+            };
+            match sink.start(stack, server) {
+                Ok(()) => {
+                    theme::set(Role::Success);
+                    println!("Logging to {}:{}", server, syslog::SYSLOG_PORT);
+                    theme::reset();
+                }
+                Err(e) => {
+                    theme::set(Role::Error);
+                    println!("log: {}", e);
+                    theme::reset();
+                }
+            }
+        }
+        (Some("status") | None, _) => {
+            println!("Log level: {}", log::max_level());
+            match sink.status() {
+                Some(status) => {
+                    println!("Remote:    {}", status.remote);
+                    println!("  Queued:  {}", status.queued);
+                    println!("  Sent:    {}", status.sent);
+                    println!("  Dropped: {}", status.dropped);
+                }
+                None => println!("Remote:    off"),
+            }
+        }
+        _ => println!("Usage: log [status] | log remote <host> | log remote off"),
+    }
+}
+
+/// Handle Ping command.
+fn cmd_ping(ctx: &mut CommandContext, args: &[&str]) {
+    let Some(args) = parse_args(&PING_ARGS, args) else {
+        return;
+    };
+    let [host] = args.positional() else {
+        return;
+    };
+    let ip = if let Some(ip) = parse_ipv4(host) {
+        ip
+    } else {
+        println!("Invalid address: {}", host);
+        return;
+    };
+
+    println!("Pinging {}...", ip);
+
+    let stack = &mut *ctx.stack;
+    let handle = match stack.icmp_socket() {
+        Ok(handle) => handle,
+        Err(e) => {
+            theme::set(Role::Error);
+            println!("  Failed to send: {}", e);
+            theme::reset();
+            return;
+        }
+    };
+    let ident = 0x1234;
+    let seq_no = 1;
+
+    let echo_payload = [0xffu8; 8];
+    let mut echo_repr = smoltcp::wire::Icmpv4Repr::EchoRequest {
+        ident,
+        seq_no,
+        data: &echo_payload,
+    };
+
+    let mut buffer = [0u8; 16]; // 8 bytes header + 8 bytes payload
+    let mut icmp_packet = smoltcp::wire::Icmpv4Packet::new_unchecked(&mut buffer);
+    echo_repr.emit(&mut icmp_packet, &Default::default());
+
+    let mut socket = stack
+        .sockets()
+        .get_mut::<smoltcp::socket::icmp::Socket>(handle);
+
+    match socket.send_slice(&buffer, smoltcp::wire::IpAddress::Ipv4(ip)) {
+        Ok(_) => {
+            // Replies are printed by `check_icmp` until the socket lingers out
+            stack.detach(handle, ctx.timestamp);
+            println!("  Echo request sent. Waiting for reply...");
+        }
+        Err(e) => {
+            stack.release_socket(handle);
+            theme::set(Role::Error);
+            println!("  Failed to send: {:?}", e);
+            theme::reset();
+        }
+    }
+}
+
+/// Handle Traceroute command.
+fn cmd_traceroute(ctx: &mut CommandContext, args: &[&str]) {
+    let Some(args) = parse_args(&TRACEROUTE_ARGS, args) else {
+        return;
+    };
+    let [host] = args.positional() else {
+        return;
+    };
+    let ip = if let Some(ip) = parse_ipv4(host) {
+        ip
+    } else {
+        println!("Invalid address: {}", host);
+        return;
+    };
+
+    let stack = &mut *ctx.stack;
+    if !stack.has_ip() {
+        theme::set(Role::Error);
+        println!("No IP address configured");
+        theme::reset();
+        return;
+    }
+
+    match ctx.traceroute.start(stack, ip) {
+        Ok(()) => println!(
+            "traceroute to {}, {} hops max, {} probes per hop",
+            ip,
+            super::traceroute::MAX_HOPS,
+            super::traceroute::PROBES_PER_HOP
+        ),
+        Err(e) => {
+            theme::set(Role::Error);
+            println!("Failed to start traceroute: {}", e);
+            theme::reset();
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! TCP connection state changes as events.
+//!
+//! A `Connection` follows one TCP socket through its life. On every stack
+//! poll, `NetworkStack::poll_connections` compares each watched socket's
+//! state with the last one seen and turns the transitions that matter into
+//! a `ConnectionEvent`: the handshake completing, the connection closing,
+//! or it failing before it was ever established (reset by the peer, or no
+//! answer within `CONNECT_TIMEOUT`).
+//!
+//! Each watch names where its events go. Kernel watchers (the `connect`
+//! command) get them back from `poll_connections`, and the stack poller
+//! reports them; a WASM process gets an `Event::Connection` on its event
+//! queue. A watch ends with its first closing or failure event, or when its
+//! socket is released.
+
+#[cfg(feature = "wasm")]
+use crate::wasm::event::{Event, SharedEventQueue};
+use core::fmt;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::tcp::State;
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::IpEndpoint;
+
+/// How long a connection may stay in SYN-SENT before it is abandoned.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A change in a connection's state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The handshake completed.
+    Connected,
+    /// The established connection was closed.
+    Closed,
+    /// The connection was reset before it was established.
+    Refused,
+    /// No answer within `CONNECT_TIMEOUT`.
+    TimedOut,
+}
+
+impl ConnectionEvent {
+    /// Event code as seen by WASM code (the data of `Event::Connection`).
+    pub fn code(self) -> u64 {
+        match self {
+            ConnectionEvent::Connected => 1,
+            ConnectionEvent::Closed => 2,
+            ConnectionEvent::Refused => 3,
+            ConnectionEvent::TimedOut => 4,
+        }
+    }
+
+    /// Whether this is the last event of the connection.
+    pub fn is_final(self) -> bool {
+        self != ConnectionEvent::Connected
+    }
+}
+
+impl fmt::Display for ConnectionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionEvent::Connected => write!(f, "connected"),
+            ConnectionEvent::Closed => write!(f, "closed"),
+            ConnectionEvent::Refused => write!(f, "connection refused"),
+            ConnectionEvent::TimedOut => write!(f, "connection timed out"),
+        }
+    }
+}
+
+/// Where a connection's events go.
+pub enum ConnectionSink {
+    /// Returned by `NetworkStack::poll_connections`.
+    Kernel,
+    /// Posted to a process's event queue.
+    #[cfg(feature = "wasm")]
+    Process {
+        /// The process's queue.
+        events: SharedEventQueue,
+        /// Handle of the Socket capability the process knows it by.
+        socket: u64,
+    },
+}
+
+/// One watched TCP socket.
+pub struct Connection {
+    handle: SocketHandle,
+    sink: ConnectionSink,
+    /// Peer, once known.
+    remote: Option<IpEndpoint>,
+    /// When the watch started, for `CONNECT_TIMEOUT`.
+    started: Instant,
+    established: bool,
+}
+
+impl Connection {
+    /// Watch the socket `handle`, starting at `started`.
+    pub fn new(handle: SocketHandle, sink: ConnectionSink, started: Instant) -> Self {
+        Self {
+            handle,
+            sink,
+            remote: None,
+            started,
+            established: false,
+        }
+    }
+
+    /// The watched socket.
+    pub fn handle(&self) -> SocketHandle {
+        self.handle
+    }
+
+    /// The peer, once known.
+    pub fn remote(&self) -> Option<IpEndpoint> {
+        self.remote
+    }
+
+    /// Record the socket's current state and peer, returning the event the
+    /// change amounts to, if any.
+    pub fn update(
+        &mut self,
+        state: State,
+        remote: Option<IpEndpoint>,
+        now: Instant,
+    ) -> Option<ConnectionEvent> {
+        if remote.is_some() {
+            self.remote = remote;
+        }
+        match state {
+            State::SynSent if now >= self.started + CONNECT_TIMEOUT => {
+                Some(ConnectionEvent::TimedOut)
+            }
+            State::Listen | State::SynSent | State::SynReceived => None,
+            State::Established | State::FinWait1 | State::FinWait2 | State::CloseWait => {
+                if self.established {
+                    None
+                } else {
+                    self.established = true;
+                    Some(ConnectionEvent::Connected)
+                }
+            }
+            State::Closed | State::Closing | State::LastAck | State::TimeWait => {
+                Some(if self.established {
+                    ConnectionEvent::Closed
+                } else {
+                    ConnectionEvent::Refused
+                })
+            }
+        }
+    }
+
+    /// Deliver `event`. Returns it if it is for the kernel.
+    pub fn deliver(&self, event: ConnectionEvent) -> Option<ConnectionEvent> {
+        match &self.sink {
+            ConnectionSink::Kernel => Some(event),
+            #[cfg(feature = "wasm")]
+            ConnectionSink::Process { events, socket } => {
+                // A full queue drops the event, as for any other source
+                events.lock().push(Event::Connection {
+                    socket: *socket,
+                    state: event.code(),
+                });
+                None
+            }
+        }
+    }
+}
@@ -3,6 +3,7 @@
 //! Provides a smoltcp-compatible Device implementation for network I/O.
 //! Currently implements a loopback device; real e1000 driver requires PCI enumeration.
 
+use super::pool::{PacketBuf, PACKET_POOL};
 use alloc::collections::VecDeque;
 use alloc::vec::Vec;
 use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
@@ -21,8 +22,8 @@ const QUEUE_CAPACITY: usize = 16;
 /// Currently implements a loopback device for testing.
 /// TODO: Implement actual e1000 MMIO driver with PCI enumeration.
 pub struct QemuE1000 {
-    rx_queue: Mutex<VecDeque<Vec<u8>>>,
-    tx_queue: Mutex<VecDeque<Vec<u8>>>,
+    rx_queue: Mutex<VecDeque<PacketBuf>>,
+    tx_queue: Mutex<VecDeque<PacketBuf>>,
     mac_address: [u8; 6],
 }
 
@@ -48,14 +49,14 @@ impl QemuE1000 {
     pub fn inject_rx(&self, data: &[u8]) {
         let mut queue = self.rx_queue.lock();
         if queue.len() < QUEUE_CAPACITY {
-            queue.push_back(data.to_vec());
+            queue.push_back(PACKET_POOL.packet_from(data));
         }
     }
 
     /// Drain transmitted packets (for testing/inspection).
     pub fn drain_tx(&self) -> Vec<Vec<u8>> {
         let mut queue = self.tx_queue.lock();
-        queue.drain(..).collect()
+        queue.drain(..).map(|packet| packet.to_vec()).collect()
     }
 }
 
@@ -67,7 +68,7 @@ impl Default for QemuE1000 {
 
 /// Receive token for QemuE1000.
 pub struct E1000RxToken {
-    buffer: Vec<u8>,
+    buffer: PacketBuf,
 }
 
 impl RxToken for E1000RxToken {
@@ -89,7 +90,7 @@ impl<'a> TxToken for E1000TxToken<'a> {
     where
         F: FnOnce(&mut [u8]) -> R,
     {
-        let mut buffer = alloc::vec![0u8; len];
+        let mut buffer = PACKET_POOL.packet(len);
         let result = f(&mut buffer);
 
         // Queue packet for transmission
@@ -1,14 +1,26 @@
 //! DHCP client for automatic IP configuration.
 //!
-//! Uses smoltcp's DHCP socket to acquire network configuration.
+//! Uses smoltcp's DHCP socket to acquire network configuration. A leased
+//! or link-local address is probed for with ARP before it is used (see
+//! `arp`): a lease another host already uses is declined, and a taken
+//! link-local address is replaced with another.
 
+use super::arp::{self, Probe, ProbeEvent};
 use super::stack::NetworkStack;
 use crate::serial_println;
 use alloc::vec::Vec;
 use smoltcp::iface::SocketHandle;
+use smoltcp::phy::ChecksumCapabilities;
 use smoltcp::socket::dhcpv4::{self, Event as DhcpSocketEvent};
 use smoltcp::time::{Duration, Instant};
-use smoltcp::wire::{IpCidr, Ipv4Address, Ipv4Cidr};
+use smoltcp::wire::{
+    DhcpMessageType, DhcpPacket, DhcpRepr, EthernetAddress, EthernetFrame, EthernetProtocol,
+    EthernetRepr, IpAddress, IpCidr, IpProtocol, Ipv4Address, Ipv4Cidr, Ipv4Packet, Ipv4Repr,
+    UdpPacket, UdpRepr, DHCP_CLIENT_PORT, DHCP_SERVER_PORT, ETHERNET_HEADER_LEN, UDP_HEADER_LEN,
+};
+
+/// Hop limit of the DHCPDECLINE datagram.
+const DECLINE_HOP_LIMIT: u8 = 64;
 
 /// DHCP client state.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -19,6 +31,8 @@ pub enum DhcpState {
     Discovering,
     /// Requesting IP address.
     Requesting,
+    /// Checking that no other host uses the address.
+    Probing,
     /// IP address acquired.
     Configured,
     /// Using link-local address (DHCP failed).
@@ -56,6 +70,52 @@ pub enum DhcpEvent {
     Deconfigured,
     /// DHCP failed, using link-local address.
     LinkLocalFallback(Ipv4Address),
+    /// Another host uses an address that was probed for or is held.
+    Conflict(AddressConflict),
+}
+
+/// What the client did about an address conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictAction {
+    /// The lease was declined and discovery restarted.
+    Declined,
+    /// Another link-local address is being probed for.
+    Reassigned,
+    /// The address was already in use and is kept.
+    Kept,
+}
+
+/// An address another host answered ARP for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressConflict {
+    /// The contested address.
+    pub ip: Ipv4Address,
+    /// The other host's MAC address.
+    pub mac: EthernetAddress,
+    /// What the client did about it.
+    pub action: ConflictAction,
+}
+
+/// An address being probed for before use.
+enum Candidate {
+    /// Offered by a DHCP server.
+    Lease {
+        config: DhcpConfig,
+        /// Server identifier, for declining.
+        server: Ipv4Address,
+        transaction_id: u32,
+    },
+    /// Picked after DHCP timed out.
+    LinkLocal(Ipv4Address),
+}
+
+impl Candidate {
+    fn ip(&self) -> Ipv4Address {
+        match self {
+            Candidate::Lease { config, .. } => config.ip,
+            Candidate::LinkLocal(ip) => *ip,
+        }
+    }
 }
 
 /// DHCP client for automatic network configuration.
@@ -65,6 +125,11 @@ pub struct DhcpClient {
     config: Option<DhcpConfig>,
     start_time: Option<Instant>,
     link_local_timeout: Duration,
+    probe: Option<Probe>,
+    candidate: Option<Candidate>,
+    link_local_attempts: u8,
+    /// The link went down; discovery restarts when it returns.
+    paused: bool,
 }
 
 impl DhcpClient {
@@ -77,6 +142,10 @@ impl DhcpClient {
             start_time: None,
             // Fall back to link-local after 10 seconds
             link_local_timeout: Duration::from_secs(10),
+            probe: None,
+            candidate: None,
+            link_local_attempts: 0,
+            paused: false,
         }
     }
 
@@ -105,11 +174,23 @@ impl DhcpClient {
     pub fn poll(&mut self, stack: &mut NetworkStack, timestamp: Instant) -> Option<DhcpEvent> {
         let handle = self.socket?;
 
+        if !stack.link_up() {
+            self.paused = true;
+            return None;
+        }
+        if self.paused {
+            self.resume(stack, timestamp);
+        }
+
+        if let Some(event) = self.poll_probe(stack, timestamp) {
+            return Some(event);
+        }
+
         // Check for link-local fallback timeout
         if self.state == DhcpState::Discovering || self.state == DhcpState::Requesting {
             if let Some(start) = self.start_time {
                 if timestamp - start > self.link_local_timeout {
-                    return Some(self.fallback_to_link_local(stack));
+                    return self.fallback_to_link_local(stack, timestamp);
                 }
             }
         }
@@ -119,8 +200,6 @@ impl DhcpClient {
         match socket.poll() {
             None => None,
             Some(DhcpSocketEvent::Configured(config)) => {
-                self.state = DhcpState::Configured;
-
                 // Extract DNS servers (filter out None values if present)
                 let dns_servers: Vec<Ipv4Address> = config.dns_servers.iter().copied().collect();
 
@@ -128,40 +207,136 @@ impl DhcpClient {
                     ip: config.address.address(),
                     prefix_len: config.address.prefix_len(),
                     gateway: config.router,
-                    dns_servers: dns_servers.clone(),
+                    dns_servers,
                     lease_duration: None, // smoltcp handles renewal internally
                 };
+                let candidate = Candidate::Lease {
+                    config: dhcp_config,
+                    server: config.server.identifier,
+                    transaction_id: config.packet.map_or(0, |packet| packet.transaction_id()),
+                };
 
-                // Apply configuration to network stack
-                stack.set_ip_config(dhcp_config.cidr(), dhcp_config.gateway);
-                stack.set_dns_servers(dns_servers);
-
-                self.config = Some(dhcp_config.clone());
-                Some(DhcpEvent::Configured(dhcp_config))
+                self.probe(stack, candidate, timestamp)
             }
             Some(DhcpSocketEvent::Deconfigured) => {
                 self.state = DhcpState::Discovering;
                 self.config = None;
                 self.start_time = Some(timestamp);
+                self.probe = None;
+                self.candidate = None;
+                arp::stop();
                 Some(DhcpEvent::Deconfigured)
             }
         }
     }
 
-    /// Fall back to a link-local address when DHCP fails.
-    fn fallback_to_link_local(&mut self, stack: &mut NetworkStack) -> DhcpEvent {
-        serial_println!("[DHCP] Timeout - falling back to link-local");
-        self.state = DhcpState::LinkLocal;
+    /// Probe for `candidate`'s address, or use it right away on links
+    /// without neighbours to conflict with.
+    fn probe(
+        &mut self,
+        stack: &mut NetworkStack,
+        candidate: Candidate,
+        timestamp: Instant,
+    ) -> Option<DhcpEvent> {
+        let Some(mac) = stack.device().mac_address() else {
+            return Some(self.apply(stack, candidate));
+        };
+        self.probe = Some(Probe::start(
+            candidate.ip(),
+            EthernetAddress(mac),
+            timestamp,
+        ));
+        self.candidate = Some(candidate);
+        self.state = DhcpState::Probing;
+        None
+    }
 
-        // Generate link-local address (169.254.x.x)
-        // Use MAC address bytes for uniqueness
-        let mac = stack.device().mac_address();
-        let ip = Ipv4Address::new(169, 254, mac[4], mac[5]);
+    /// Advance the address probe and act on its outcome.
+    fn poll_probe(&mut self, stack: &mut NetworkStack, timestamp: Instant) -> Option<DhcpEvent> {
+        let probe = self.probe.as_mut()?;
+        let ip = probe.ip();
+        let event = probe.poll(stack, timestamp)?;
+        let ours = EthernetAddress(stack.device().mac_address()?);
+        let mac = match event {
+            ProbeEvent::Clear => {
+                let candidate = self.candidate.take()?;
+                return Some(self.apply(stack, candidate));
+            }
+            ProbeEvent::Conflict(mac) => mac,
+        };
+        let action = match self.candidate.take() {
+            Some(Candidate::Lease {
+                config,
+                server,
+                transaction_id,
+            }) => {
+                serial_println!("[DHCP] {} is in use by {}, declining", config.ip, mac);
+                stack.send_raw(
+                    &decline_frame(ours, config.ip, server, transaction_id),
+                    timestamp,
+                );
+                self.probe = None;
+                arp::stop();
+                if let Some(handle) = self.socket {
+                    stack.sockets().get_mut::<dhcpv4::Socket>(handle).reset();
+                }
+                self.state = DhcpState::Discovering;
+                self.start_time = Some(timestamp);
+                ConflictAction::Declined
+            }
+            Some(Candidate::LinkLocal(_)) => {
+                self.link_local_attempts = self.link_local_attempts.wrapping_add(1);
+                let next = link_local_address(Some(ours.0), self.link_local_attempts);
+                self.probe = Some(Probe::start(next, ours, timestamp));
+                self.candidate = Some(Candidate::LinkLocal(next));
+                ConflictAction::Reassigned
+            }
+            None => ConflictAction::Kept,
+        };
+        Some(DhcpEvent::Conflict(AddressConflict { ip, mac, action }))
+    }
+
+    /// Configure the stack with an address found free.
+    fn apply(&mut self, stack: &mut NetworkStack, candidate: Candidate) -> DhcpEvent {
+        match candidate {
+            Candidate::Lease { config, .. } => {
+                self.state = DhcpState::Configured;
+                // Apply configuration to network stack
+                stack.set_ip_config(config.cidr(), config.gateway);
+                stack.set_dns_servers(config.dns_servers.clone());
+                self.config = Some(config.clone());
+                DhcpEvent::Configured(config)
+            }
+            Candidate::LinkLocal(ip) => {
+                self.state = DhcpState::LinkLocal;
+                let cidr = IpCidr::Ipv4(Ipv4Cidr::new(ip, 16));
+                stack.set_ip_config(cidr, None);
+                DhcpEvent::LinkLocalFallback(ip)
+            }
+        }
+    }
 
-        let cidr = IpCidr::Ipv4(Ipv4Cidr::new(ip, 16));
-        stack.set_ip_config(cidr, None);
+    /// Fall back to a link-local address when DHCP fails.
+    fn fallback_to_link_local(
+        &mut self,
+        stack: &mut NetworkStack,
+        timestamp: Instant,
+    ) -> Option<DhcpEvent> {
+        serial_println!("[DHCP] Timeout - falling back to link-local");
+        self.link_local_attempts = 0;
+        let ip = link_local_address(stack.device().mac_address(), 0);
+        self.probe(stack, Candidate::LinkLocal(ip), timestamp)
+    }
 
-        DhcpEvent::LinkLocalFallback(ip)
+    /// Start discovery afresh after the link returns, so a server that only
+    /// became reachable now is found before the link-local timeout.
+    fn resume(&mut self, stack: &mut NetworkStack, timestamp: Instant) {
+        self.paused = false;
+        self.probe = None;
+        self.candidate = None;
+        arp::stop();
+        self.renew(stack);
+        self.start_time = Some(timestamp);
     }
 
     /// Request a renewal of the current lease.
@@ -174,6 +349,87 @@ impl DhcpClient {
     }
 }
 
+/// The link-local address (169.254.x.x) to try after `attempt` conflicts.
+///
+/// The first is derived from the MAC address for uniqueness; later ones
+/// spread over 169.254.1.0 to 169.254.254.255 (RFC 3927).
+pub fn link_local_address(mac: Option<[u8; 6]>, attempt: u8) -> Ipv4Address {
+    let (hi, lo) = mac.map_or((0, 1), |mac| (mac[4], mac[5]));
+    if attempt == 0 {
+        return Ipv4Address::new(169, 254, hi, lo);
+    }
+    let seed = u16::from_be_bytes([hi, lo]).wrapping_add(u16::from(attempt).wrapping_mul(0x9E37));
+    let [hi, lo] = seed.to_be_bytes();
+    Ipv4Address::new(169, 254, 1 + hi % 254, lo)
+}
+
+/// A DHCPDECLINE for `ip`, broadcast from 0.0.0.0 (RFC 2131 section
+/// 4.4.4). smoltcp's DHCP socket cannot send one.
+fn decline_frame(
+    mac: EthernetAddress,
+    ip: Ipv4Address,
+    server: Ipv4Address,
+    transaction_id: u32,
+) -> Vec<u8> {
+    let dhcp = DhcpRepr {
+        message_type: DhcpMessageType::Decline,
+        transaction_id,
+        secs: 0,
+        client_hardware_address: mac,
+        client_ip: Ipv4Address::UNSPECIFIED,
+        your_ip: Ipv4Address::UNSPECIFIED,
+        server_ip: Ipv4Address::UNSPECIFIED,
+        router: None,
+        subnet_mask: None,
+        relay_agent_ip: Ipv4Address::UNSPECIFIED,
+        broadcast: false,
+        requested_ip: Some(ip),
+        client_identifier: Some(mac),
+        server_identifier: Some(server),
+        parameter_request_list: None,
+        dns_servers: None,
+        max_size: None,
+        lease_duration: None,
+        renew_duration: None,
+        rebind_duration: None,
+        additional_options: &[],
+    };
+    let udp = UdpRepr {
+        src_port: DHCP_CLIENT_PORT,
+        dst_port: DHCP_SERVER_PORT,
+    };
+    let ipv4 = Ipv4Repr {
+        src_addr: Ipv4Address::UNSPECIFIED,
+        dst_addr: Ipv4Address::BROADCAST,
+        next_header: IpProtocol::Udp,
+        payload_len: UDP_HEADER_LEN + dhcp.buffer_len(),
+        hop_limit: DECLINE_HOP_LIMIT,
+    };
+    let checksums = ChecksumCapabilities::default();
+
+    let mut buffer = alloc::vec![0u8; ETHERNET_HEADER_LEN + ipv4.buffer_len() + ipv4.payload_len];
+    let mut frame = EthernetFrame::new_unchecked(&mut buffer[..]);
+    EthernetRepr {
+        src_addr: mac,
+        dst_addr: EthernetAddress::BROADCAST,
+        ethertype: EthernetProtocol::Ipv4,
+    }
+    .emit(&mut frame);
+    let mut packet = Ipv4Packet::new_unchecked(frame.payload_mut());
+    ipv4.emit(&mut packet, &checksums);
+    udp.emit(
+        &mut UdpPacket::new_unchecked(packet.payload_mut()),
+        &IpAddress::Ipv4(ipv4.src_addr),
+        &IpAddress::Ipv4(ipv4.dst_addr),
+        dhcp.buffer_len(),
+        |payload| {
+            let _ = dhcp.emit(&mut DhcpPacket::new_unchecked(payload));
+        },
+        &checksums,
+    );
+    buffer
+}
+
 impl Default for DhcpClient {
     fn default() -> Self {
         Self::new()
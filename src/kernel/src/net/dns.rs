@@ -1,32 +1,66 @@
 //! DNS resolver for hostname lookup.
 //!
 //! Provides asynchronous DNS resolution using smoltcp's DNS socket.
+//!
+//! Answers are kept in a per-resolver cache, including negative entries for
+//! names that failed to resolve. Failed queries are retried with exponential
+//! backoff, rotating the server list so each attempt starts with a different
+//! configured server. Names listed in `/etc/hosts` override both.
+//!
+//! A task awaiting a `DnsFuture` donates its priority to the DNS task and
+//! the stack poller, which produce the answer (see `task::donate`).
 
+use super::hosts::{HostsFile, HOSTS_PATH};
 use super::stack::NetworkStack;
 use super::NetError;
+use crate::task::donate::{self, Donation, Producer};
+use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
 use smoltcp::iface::SocketHandle;
 use smoltcp::socket::dns::{self, GetQueryResultError, StartQueryError};
+use smoltcp::time::{Duration, Instant};
 use smoltcp::wire::{IpAddress, Ipv4Address};
 
+/// The task that polls the resolver, completing `DnsFuture`s.
+pub static PRODUCER: Producer = Producer::new();
+
+/// Lifetime of a successful answer.
+///
+/// smoltcp's DNS socket does not expose record TTLs, so every positive
+/// entry uses this conservative value.
+const POSITIVE_TTL: Duration = Duration::from_secs(300);
+
+/// Lifetime of a negative (failed lookup) entry.
+const NEGATIVE_TTL: Duration = Duration::from_secs(30);
+
+/// Maximum number of cached hostnames.
+const MAX_CACHE_ENTRIES: usize = 32;
+
+/// smoltcp's per-server retransmit timeout.
+///
+/// The DNS socket reports an NXDOMAIN answer and a query no server
+/// answered alike, so an attempt that fails only after this long is
+/// taken to have timed out.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of times a failed query is restarted before giving up.
+const MAX_RETRIES: u8 = 2;
+
+/// Delay before the first retry; doubled on each subsequent retry.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
 /// Handle for tracking a pending DNS query.
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy)]
 pub struct DnsQueryHandle {
-    /// Internal socket handle for the query.
-    pub handle: dns::QueryHandle,
     /// Query ID for tracking.
     pub id: u16,
 }
 
-impl core::fmt::Debug for DnsQueryHandle {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        f.debug_struct("DnsQueryHandle")
-            .field("id", &self.id)
-            .finish()
-    }
-}
-
 /// Result of a DNS resolution.
 #[derive(Debug, Clone)]
 pub struct DnsResult {
@@ -36,11 +70,253 @@ pub struct DnsResult {
     pub addresses: Vec<IpAddress>,
 }
 
+/// Why a lookup failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsFailure {
+    /// A server answered that the name has no addresses.
+    NxDomain,
+    /// No server answered.
+    Timeout,
+}
+
+impl DnsFailure {
+    /// Short label for listings.
+    pub fn label(self) -> &'static str {
+        match self {
+            DnsFailure::NxDomain => "NXDOMAIN",
+            DnsFailure::Timeout => "timeout",
+        }
+    }
+}
+
+/// A cached DNS answer.
+#[derive(Debug, Clone)]
+pub struct DnsCacheEntry {
+    /// Resolved addresses (empty for a negative entry).
+    pub addresses: Vec<IpAddress>,
+    /// Why the lookup failed, for a negative entry.
+    pub failure: Option<DnsFailure>,
+    /// Time after which the entry is stale.
+    pub expires: Instant,
+}
+
+impl DnsCacheEntry {
+    /// Check whether this entry records a failed lookup.
+    pub fn is_negative(&self) -> bool {
+        self.failure.is_some()
+    }
+}
+
+/// Hostname-keyed cache of positive and negative DNS answers.
+pub struct DnsCache {
+    entries: BTreeMap<String, DnsCacheEntry>,
+}
+
+impl DnsCache {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Look up a hostname, dropping the entry if it has expired.
+    pub fn lookup(&mut self, hostname: &str, now: Instant) -> Option<&DnsCacheEntry> {
+        let key = hostname.to_lowercase();
+        if self.entries.get(&key).is_some_and(|e| e.expires <= now) {
+            self.entries.remove(&key);
+        }
+        self.entries.get(&key)
+    }
+
+    /// Record an answer for `hostname`; an empty address list is cached as
+    /// an NXDOMAIN entry.
+    pub fn insert(&mut self, hostname: &str, addresses: Vec<IpAddress>, now: Instant) {
+        let failure = addresses.is_empty().then_some(DnsFailure::NxDomain);
+        self.store(hostname, addresses, failure, now);
+    }
+
+    /// Record a failed lookup of `hostname`.
+    pub fn insert_failure(&mut self, hostname: &str, failure: DnsFailure, now: Instant) {
+        self.store(hostname, Vec::new(), Some(failure), now);
+    }
+
+    fn store(
+        &mut self,
+        hostname: &str,
+        addresses: Vec<IpAddress>,
+        failure: Option<DnsFailure>,
+        now: Instant,
+    ) {
+        let ttl = if failure.is_some() {
+            NEGATIVE_TTL
+        } else {
+            POSITIVE_TTL
+        };
+        let key = hostname.to_lowercase();
+
+        if !self.entries.contains_key(&key) && self.entries.len() >= MAX_CACHE_ENTRIES {
+            self.evict_oldest();
+        }
+        self.entries.insert(
+            key,
+            DnsCacheEntry {
+                addresses,
+                failure,
+                expires: now + ttl,
+            },
+        );
+    }
+
+    /// Remove all entries.
+    pub fn flush(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Drop the entries that have expired by `now`.
+    pub fn purge(&mut self, now: Instant) {
+        self.entries.retain(|_, e| e.expires > now);
+    }
+
+    /// Get the number of cached entries (including expired ones not yet dropped).
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over unexpired entries.
+    pub fn iter(&self, now: Instant) -> impl Iterator<Item = (&str, &DnsCacheEntry)> {
+        self.entries
+            .iter()
+            .filter(move |(_, e)| e.expires > now)
+            .map(|(k, e)| (k.as_str(), e))
+    }
+
+    /// Drop the entry that expires soonest.
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, e)| e.expires)
+            .map(|(k, _)| k.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Progress of a pending query.
+enum QueryState {
+    /// Waiting on the smoltcp DNS socket.
+    InFlight(dns::QueryHandle),
+    /// Backing off before a restart.
+    Backoff(Instant),
+    /// Answered locally (hosts file); reported on the next poll.
+    Answered(Vec<IpAddress>),
+}
+
+/// A query in flight (or waiting to be retried).
+struct PendingQuery {
+    id: u16,
+    state: QueryState,
+    hostname: String,
+    retries: u8,
+    /// When the current attempt was first seen in flight.
+    sent: Option<Instant>,
+    /// Set for queries started by `resolve_async`.
+    completion: Option<Arc<spin::Mutex<Completion>>>,
+}
+
+/// Result slot shared between a pending query and its `DnsFuture`.
+#[derive(Default)]
+struct Completion {
+    result: Option<Result<IpAddress, NetError>>,
+    waker: Option<Waker>,
+}
+
+impl Completion {
+    /// Store the final result and wake the awaiting task.
+    fn complete(&mut self, result: Result<DnsResult, NetError>) {
+        self.result =
+            Some(result.and_then(|r| r.addresses.first().copied().ok_or(NetError::DnsError)));
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Future returned by `DnsResolver::resolve_async`.
+///
+/// Completes once the resolver's `poll` (driven by the network tasks)
+/// finishes the query, yielding the first resolved address.
+pub struct DnsFuture {
+    slot: Arc<spin::Mutex<Completion>>,
+    /// Priority lent to the tasks producing the answer while waiting.
+    donations: Vec<Donation>,
+}
+
+impl DnsFuture {
+    /// Create a future completed through `slot`.
+    fn new(slot: Arc<spin::Mutex<Completion>>) -> Self {
+        Self {
+            slot,
+            donations: Vec::new(),
+        }
+    }
+
+    /// Create a future that is already resolved.
+    fn ready(result: Result<IpAddress, NetError>) -> Self {
+        Self::new(Arc::new(spin::Mutex::new(Completion {
+            result: Some(result),
+            waker: None,
+        })))
+    }
+}
+
+impl Future for DnsFuture {
+    type Output = Result<IpAddress, NetError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut slot = this.slot.lock();
+        match slot.result.take() {
+            Some(result) => {
+                this.donations.clear();
+                Poll::Ready(result)
+            }
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                if this.donations.is_empty() {
+                    this.donations = [&PRODUCER, &super::poller::PRODUCER]
+                        .into_iter()
+                        .filter_map(donate::donate)
+                        .collect();
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
 /// DNS resolver for hostname lookup.
 pub struct DnsResolver {
     socket: Option<SocketHandle>,
-    pending: Vec<(u16, dns::QueryHandle, String)>,
+    pending: Vec<PendingQuery>,
     next_id: u16,
+    cache: DnsCache,
+    hosts: HostsFile,
+    /// Server list in the order currently handed to the socket.
+    servers: Vec<IpAddress>,
 }
 
 impl DnsResolver {
@@ -50,28 +326,36 @@ impl DnsResolver {
             socket: None,
             pending: Vec::new(),
             next_id: 1,
+            cache: DnsCache::new(),
+            hosts: HostsFile::new(HOSTS_PATH),
+            servers: Vec::new(),
         }
     }
 
     /// Initialize the DNS resolver with the network stack.
     ///
     /// Must be called after DHCP completes or DNS servers are configured.
-    pub fn init(&mut self, stack: &mut NetworkStack) {
-        if self.socket.is_some() {
-            return; // Already initialized
-        }
-
+    /// Calling it again picks up a changed server list. Fails with
+    /// `OutOfMemory` if the stack has no room for the DNS socket.
+    pub fn init(&mut self, stack: &mut NetworkStack) -> Result<(), NetError> {
         let servers = &stack.dns_servers;
         if servers.is_empty() {
-            return; // No DNS servers configured
+            return Ok(()); // No DNS servers configured
         }
 
         // Convert to smoltcp format
-        let server_addrs: Vec<IpAddress> = servers.iter().map(|s| IpAddress::Ipv4(*s)).collect();
+        self.servers = servers.iter().map(|s| IpAddress::Ipv4(*s)).collect();
 
-        let socket = dns::Socket::new(&server_addrs, Vec::new());
-        let handle = stack.sockets().add(socket);
-        self.socket = Some(handle);
+        if let Some(handle) = self.socket {
+            stack
+                .sockets()
+                .get_mut::<dns::Socket>(handle)
+                .update_servers(&self.servers);
+            return Ok(());
+        }
+
+        self.socket = Some(stack.dns_socket(&self.servers)?);
+        Ok(())
     }
 
     /// Check if the resolver is initialized and ready.
@@ -79,65 +363,143 @@ impl DnsResolver {
         self.socket.is_some()
     }
 
+    /// Look up a hostname in the hosts file, then the cache.
+    ///
+    /// Returns `Some(Err(NetError::DnsError))` for a cached negative answer.
+    pub fn cached(&mut self, hostname: &str, now: Instant) -> Option<Result<DnsResult, NetError>> {
+        if let Some(addresses) = self.hosts.lookup(hostname) {
+            return Some(Ok(DnsResult {
+                hostname: hostname.to_string(),
+                addresses,
+            }));
+        }
+
+        let entry = self.cache.lookup(hostname, now)?;
+        if entry.is_negative() {
+            Some(Err(NetError::DnsError))
+        } else {
+            Some(Ok(DnsResult {
+                hostname: hostname.to_string(),
+                addresses: entry.addresses.clone(),
+            }))
+        }
+    }
+
+    /// Get the answer cache.
+    pub fn cache(&self) -> &DnsCache {
+        &self.cache
+    }
+
+    /// Drop all cached answers.
+    pub fn flush_cache(&mut self) {
+        self.cache.flush();
+    }
+
+    /// Drop the cached answers that have expired by `now`.
+    pub fn purge_cache(&mut self, now: Instant) {
+        self.cache.purge(now);
+    }
+
     /// Start a DNS query for a hostname.
     ///
-    /// Returns a handle that can be used to check for results.
+    /// Names pinned in the hosts file are answered without touching the
+    /// network (the result is still delivered through `poll`). Callers
+    /// should consult `cached` first to avoid repeating cached lookups.
     pub fn resolve(
         &mut self,
         stack: &mut NetworkStack,
         hostname: &str,
     ) -> Result<DnsQueryHandle, NetError> {
-        let socket_handle = self.socket.ok_or(NetError::DeviceNotReady)?;
-
-        match stack.start_dns_query(socket_handle, hostname) {
-            Ok(query_handle) => {
-                let id = self.next_id;
-                self.next_id = self.next_id.wrapping_add(1);
-                self.pending.push((id, query_handle, hostname.to_string()));
-                Ok(DnsQueryHandle {
-                    handle: query_handle,
-                    id,
-                })
+        let state = match self.hosts.lookup(hostname) {
+            Some(addresses) => QueryState::Answered(addresses),
+            None => {
+                let socket_handle = self.socket.ok_or(NetError::DeviceNotReady)?;
+                QueryState::InFlight(start_query(stack, socket_handle, hostname)?)
+            }
+        };
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.pending.push(PendingQuery {
+            id,
+            state,
+            hostname: hostname.to_string(),
+            retries: 0,
+            sent: None,
+            completion: None,
+        });
+        Ok(DnsQueryHandle { id })
+    }
+
+    /// Resolve a hostname, returning a future for its first address.
+    ///
+    /// Literal IPv4 addresses, hosts file entries and cached answers
+    /// complete immediately. Otherwise a query is started and the future
+    /// completes when `poll` sees the answer, so some task must keep
+    /// polling the resolver.
+    pub fn resolve_async(
+        &mut self,
+        stack: &mut NetworkStack,
+        hostname: &str,
+        timestamp: Instant,
+    ) -> DnsFuture {
+        if let Some(ip) = parse_ipv4(hostname) {
+            return DnsFuture::ready(Ok(IpAddress::Ipv4(ip)));
+        }
+
+        if let Some(result) = self.cached(hostname, timestamp) {
+            let mut completion = Completion::default();
+            completion.complete(result);
+            return DnsFuture::new(Arc::new(spin::Mutex::new(completion)));
+        }
+
+        if !self.is_ready() {
+            if let Err(e) = self.init(stack) {
+                return DnsFuture::ready(Err(e));
+            }
+        }
+
+        match self.resolve(stack, hostname) {
+            Ok(query) => {
+                let slot = Arc::new(spin::Mutex::new(Completion::default()));
+                if let Some(pending) = self.pending.iter_mut().find(|q| q.id == query.id) {
+                    pending.completion = Some(slot.clone());
+                }
+                DnsFuture::new(slot)
             }
-            Err(StartQueryError::NoFreeSlot) => Err(NetError::BufferFull),
-            Err(StartQueryError::InvalidName) => Err(NetError::DnsError),
-            Err(StartQueryError::NameTooLong) => Err(NetError::DnsError),
+            Err(e) => DnsFuture::ready(Err(e)),
         }
     }
 
     /// Poll for completed DNS queries.
     ///
-    /// Returns results for any completed queries.
-    pub fn poll(&mut self, stack: &mut NetworkStack) -> Vec<Result<DnsResult, NetError>> {
+    /// Returns results for any completed queries. Answers are cached;
+    /// failed queries are retried until `MAX_RETRIES` is exhausted.
+    /// Queries started by `resolve_async` complete their future instead
+    /// of being returned here.
+    pub fn poll(
+        &mut self,
+        stack: &mut NetworkStack,
+        timestamp: Instant,
+    ) -> Vec<Result<DnsResult, NetError>> {
         let mut results = Vec::new();
 
-        let socket_handle = match self.socket {
-            Some(h) => h,
-            None => return results,
-        };
-
-        let socket = stack.sockets().get_mut::<dns::Socket>(socket_handle);
-
         // Check each pending query
         let mut i = 0;
         while i < self.pending.len() {
-            let (_, query_handle, _) = &self.pending[i];
-            match socket.get_query_result(*query_handle) {
-                Ok(addrs) => {
-                    let (_, _, hostname) = self.pending.remove(i);
-                    results.push(Ok(DnsResult {
-                        hostname,
-                        addresses: addrs.to_vec(),
-                    }));
+            let id = self.pending[i].id;
+            match self.check(stack, i, timestamp) {
+                Some(result) => {
+                    if let Some(pos) = self.pending.iter().position(|q| q.id == id) {
+                        let query = self.pending.remove(pos);
+                        match query.completion {
+                            Some(slot) => slot.lock().complete(result),
+                            None => results.push(result),
+                        }
+                    }
                     // Don't increment i since we removed an element
                 }
-                Err(GetQueryResultError::Pending) => {
-                    i += 1; // Still waiting, check next
-                }
-                Err(GetQueryResultError::Failed) => {
-                    let _ = self.pending.remove(i);
-                    results.push(Err(NetError::DnsError));
-                }
+                None => i += 1, // Still waiting, check next
             }
         }
 
@@ -149,39 +511,96 @@ impl DnsResolver {
         &mut self,
         stack: &mut NetworkStack,
         query: DnsQueryHandle,
+        timestamp: Instant,
+    ) -> Option<Result<DnsResult, NetError>> {
+        let pos = self.pending.iter().position(|q| q.id == query.id)?;
+        let result = self.check(stack, pos, timestamp)?;
+        self.pending.remove(pos);
+        Some(result)
+    }
+
+    /// Advance the pending query at `index`, returning its final result.
+    ///
+    /// Restarts the query after backoff if it failed and retries remain.
+    fn check(
+        &mut self,
+        stack: &mut NetworkStack,
+        index: usize,
+        timestamp: Instant,
     ) -> Option<Result<DnsResult, NetError>> {
+        let query = &mut self.pending[index];
+
+        let query_handle = match &mut query.state {
+            QueryState::Answered(addresses) => {
+                return Some(Ok(DnsResult {
+                    hostname: query.hostname.clone(),
+                    addresses: core::mem::take(addresses),
+                }));
+            }
+            QueryState::Backoff(retry_at) => {
+                if timestamp < *retry_at {
+                    return None;
+                }
+                let socket_handle = self.socket?;
+                rotate_servers(stack, socket_handle, &mut self.servers);
+                let query = &mut self.pending[index];
+                return match start_query(stack, socket_handle, &query.hostname) {
+                    Ok(handle) => {
+                        query.state = QueryState::InFlight(handle);
+                        query.sent = None;
+                        None
+                    }
+                    Err(e) => Some(Err(e)),
+                };
+            }
+            QueryState::InFlight(handle) => *handle,
+        };
+
         let socket_handle = self.socket?;
+        let sent = *query.sent.get_or_insert(timestamp);
         let socket = stack.sockets().get_mut::<dns::Socket>(socket_handle);
-
-        match socket.get_query_result(query.handle) {
+        match socket.get_query_result(query_handle) {
             Ok(addrs) => {
-                // Find and remove from pending
-                if let Some(pos) = self.pending.iter().position(|(id, _, _)| *id == query.id) {
-                    let (_, _, hostname) = self.pending.remove(pos);
-                    Some(Ok(DnsResult {
-                        hostname,
-                        addresses: addrs.to_vec(),
-                    }))
-                } else {
-                    None
-                }
+                let addresses = addrs.to_vec();
+                self.cache
+                    .insert(&query.hostname, addresses.clone(), timestamp);
+                Some(Ok(DnsResult {
+                    hostname: query.hostname.clone(),
+                    addresses,
+                }))
             }
             Err(GetQueryResultError::Pending) => None,
+            Err(GetQueryResultError::Failed) if query.retries < MAX_RETRIES => {
+                let delay = RETRY_BASE_DELAY * (1u32 << query.retries);
+                query.retries += 1;
+                query.state = QueryState::Backoff(timestamp + delay);
+                None
+            }
             Err(GetQueryResultError::Failed) => {
-                if let Some(pos) = self.pending.iter().position(|(id, _, _)| *id == query.id) {
-                    let _ = self.pending.remove(pos);
-                    Some(Err(NetError::DnsError))
+                let failure = if timestamp - sent >= QUERY_TIMEOUT {
+                    DnsFailure::Timeout
                 } else {
-                    None
-                }
+                    DnsFailure::NxDomain
+                };
+                self.cache
+                    .insert_failure(&query.hostname, failure, timestamp);
+                Some(Err(NetError::DnsError))
             }
         }
     }
 
     /// Cancel a pending DNS query.
-    pub fn cancel(&mut self, query: DnsQueryHandle) {
-        if let Some(pos) = self.pending.iter().position(|(id, _, _)| *id == query.id) {
-            self.pending.remove(pos);
+    pub fn cancel(&mut self, stack: &mut NetworkStack, query: DnsQueryHandle) {
+        if let Some(pos) = self.pending.iter().position(|q| q.id == query.id) {
+            let pending = self.pending.remove(pos);
+            if let (Some(socket_handle), QueryState::InFlight(handle)) =
+                (self.socket, pending.state)
+            {
+                stack
+                    .sockets()
+                    .get_mut::<dns::Socket>(socket_handle)
+                    .cancel_query(handle);
+            }
         }
     }
 
@@ -197,6 +616,36 @@ impl Default for DnsResolver {
     }
 }
 
+/// Start a query on the resolver socket, mapping smoltcp errors.
+fn start_query(
+    stack: &mut NetworkStack,
+    socket_handle: SocketHandle,
+    hostname: &str,
+) -> Result<dns::QueryHandle, NetError> {
+    match stack.start_dns_query(socket_handle, hostname) {
+        Ok(query_handle) => Ok(query_handle),
+        Err(StartQueryError::NoFreeSlot) => Err(NetError::BufferFull),
+        Err(StartQueryError::InvalidName) => Err(NetError::DnsError),
+        Err(StartQueryError::NameTooLong) => Err(NetError::DnsError),
+    }
+}
+
+/// Move the first server to the back so the next query starts elsewhere.
+fn rotate_servers(
+    stack: &mut NetworkStack,
+    socket_handle: SocketHandle,
+    servers: &mut [IpAddress],
+) {
+    if servers.len() < 2 {
+        return;
+    }
+    servers.rotate_left(1);
+    stack
+        .sockets()
+        .get_mut::<dns::Socket>(socket_handle)
+        .update_servers(servers);
+}
+
 /// Parse an IPv4 address from a string.
 ///
 /// Returns None if the string is not a valid IPv4 address.
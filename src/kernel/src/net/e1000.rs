@@ -2,24 +2,39 @@
 //!
 //! This module implements a real e1000 network driver using MMIO.
 //! Designed for the SovelmaOS kernel.
+//!
+//! The NIC verifies IPv4, TCP and UDP checksums of received frames and
+//! inserts TCP checksums into sent ones, so smoltcp only computes the IPv4
+//! and UDP checksums it sends. `csum_offload=off` on the kernel command line
+//! leaves all checksums to smoltcp, for comparing the two. TCP segmentation
+//! offload is not used: smoltcp never hands the driver more than one segment.
 
 use alloc::boxed::Box;
 use alloc::vec::Vec;
-use core::ptr::{read_volatile, write_volatile};
-use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
+use smoltcp::phy::{
+    Checksum, ChecksumCapabilities, Device, DeviceCapabilities, Medium, RxToken, TxToken,
+};
 use smoltcp::time::Instant;
 
+use super::pool::{PacketBuf, PACKET_POOL};
+use super::FilterError;
 use crate::arch::x86_64::pci::{self, PciDevice};
+use crate::arch::x86_64::pic;
+use crate::memory::mmio::MmioRegion;
+use spin::Once;
 
 const MTU: usize = 1500;
 const PACKET_BUFFER_SIZE: usize = 2048;
 const TX_DESC_COUNT: usize = 64;
 const RX_DESC_COUNT: usize = 64;
+/// Size of the register space BAR0 decodes.
+const REGISTER_SPACE: u64 = 0x2_0000;
 
 // Registers
 const REG_CTRL: u32 = 0x0000;
 const REG_STATUS: u32 = 0x0008;
 const REG_ICR: u32 = 0x00C0;
+const REG_IMS: u32 = 0x00D0;
 const REG_IMC: u32 = 0x00D8;
 const REG_RCTL: u32 = 0x0100;
 const REG_RDBAL: u32 = 0x2800;
@@ -36,19 +51,71 @@ const REG_TDH: u32 = 0x3810;
 const REG_TDT: u32 = 0x3818;
 const REG_RAL0: u32 = 0x5400;
 const REG_RAH0: u32 = 0x5404;
+const REG_RXCSUM: u32 = 0x5000;
+const REG_MTA: u32 = 0x5200;
+
+/// Multicast table array: 4096 hash bits in 32-bit registers.
+const MTA_ENTRIES: u32 = 128;
+/// Receive address registers; the first holds the NIC's own address.
+const RAR_ENTRIES: usize = 16;
+const RAH_AV: u32 = 1 << 31;
 
 // Bits
 const CTRL_RST: u32 = 1 << 26;
 const CTRL_SLU: u32 = 1 << 6;
+const STATUS_LU: u32 = 1 << 1;
 const RCTL_EN: u32 = 1 << 1;
+const RCTL_UPE: u32 = 1 << 3;
+const RCTL_MPE: u32 = 1 << 4;
 const RCTL_BAM: u32 = 1 << 15;
 const TCTL_EN: u32 = 1 << 1;
 const TCTL_PSP: u32 = 1 << 3;
+const RXCSUM_IPOFL: u32 = 1 << 8;
+const RXCSUM_TUOFL: u32 = 1 << 9;
+
+// Interrupt causes
+const INT_LSC: u32 = 1 << 2;
+const INT_RXDMT0: u32 = 1 << 4;
+const INT_RXT0: u32 = 1 << 7;
 
 // Descriptor Status Bits
 const TX_DD: u8 = 1 << 0;
 const RX_DD: u8 = 1 << 0;
 const RX_EOP: u8 = 1 << 1;
+const RX_TCPCS: u8 = 1 << 5;
+const RX_IPCS: u8 = 1 << 6;
+
+// Receive errors
+const RX_ERR_TCPE: u8 = 1 << 5;
+const RX_ERR_IPE: u8 = 1 << 6;
+
+// Transmit command bits
+const TX_CMD_EOP: u8 = 1 << 0;
+const TX_CMD_IFCS: u8 = 1 << 1;
+const TX_CMD_RS: u8 = 1 << 3;
+const TX_CMD_DEXT: u8 = 1 << 5;
+/// Context descriptor TUCMD: the segment is TCP.
+const TX_TUCMD_TCP: u8 = 1 << 0;
+/// Descriptor type of an extended data descriptor, in its length byte 2.
+const TX_DTYP_DATA: u8 = 1 << 4;
+/// Data descriptor POPTS: insert the TCP/UDP checksum.
+const TX_POPTS_TXSM: u8 = 1 << 1;
+
+// Frame layout
+const ETH_HEADER_LEN: usize = 14;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const IPV4_MORE_FRAGMENTS: u16 = 0x2000;
+const IPV4_FRAGMENT_OFFSET: u16 = 0x1FFF;
+const IP_PROTO_TCP: u8 = 6;
+const IP_PROTO_UDP: u8 = 17;
+const TCP_HEADER_LEN: usize = 20;
+const TCP_CHECKSUM_OFFSET: usize = 16;
+const UDP_CHECKSUM_OFFSET: usize = 6;
+
+/// Registers as seen by the interrupt handler, which cannot take the
+/// stack lock to reach the driver.
+static IRQ_REGS: Once<MmioRegion> = Once::new();
 
 #[repr(C, align(16))]
 #[derive(Clone, Copy, Default)]
@@ -74,7 +141,7 @@ struct RxDesc {
 }
 
 pub struct E1000 {
-    mmio_base: *mut u32,
+    regs: MmioRegion,
     phys_mem_offset: u64,
     mac_address: [u8; 6],
     tx_descs: Box<[TxDesc; TX_DESC_COUNT]>,
@@ -83,6 +150,16 @@ pub struct E1000 {
     rx_descs: Box<[RxDesc; RX_DESC_COUNT]>,
     rx_buffers: Box<[[u8; PACKET_BUFFER_SIZE]; RX_DESC_COUNT]>,
     rx_cur: usize,
+    irq: u8,
+    checksum_offload: bool,
+    /// Checksum start of the TCP offload context last loaded into the NIC.
+    tx_context: Option<u8>,
+    checksum_errors: u64,
+    promiscuous: bool,
+    /// Extra unicast addresses accepted, in receive address registers 1..
+    unicast_filters: Vec<[u8; 6]>,
+    /// Multicast addresses accepted through the hash table.
+    multicast_filters: Vec<[u8; 6]>,
 }
 
 unsafe impl Send for E1000 {}
@@ -90,9 +167,17 @@ unsafe impl Sync for E1000 {}
 
 impl E1000 {
     pub fn new(pci_dev: PciDevice, phys_mem_offset: u64) -> Option<Self> {
+        let regs = match MmioRegion::from_bar(&pci_dev, phys_mem_offset, REGISTER_SPACE) {
+            Ok(regs) => regs,
+            Err(e) => {
+                crate::serial_println!("[e1000] Cannot map registers: {}", e);
+                return None;
+            }
+        };
         pci_dev.enable();
-        let mmio_phys = pci_dev.mmio_base()?;
-        let mmio_base = (mmio_phys + phys_mem_offset) as *mut u32;
+        if let Ok(irq_regs) = MmioRegion::from_bar(&pci_dev, phys_mem_offset, REGISTER_SPACE) {
+            IRQ_REGS.call_once(|| irq_regs);
+        }
 
         let tx_descs = Box::new([TxDesc::default(); TX_DESC_COUNT]);
         let tx_buffers = Box::new([[0u8; PACKET_BUFFER_SIZE]; TX_DESC_COUNT]);
@@ -100,7 +185,7 @@ impl E1000 {
         let rx_buffers = Box::new([[0u8; PACKET_BUFFER_SIZE]; RX_DESC_COUNT]);
 
         let mut dev = Self {
-            mmio_base,
+            regs,
             phys_mem_offset,
             mac_address: [0; 6],
             tx_descs,
@@ -109,6 +194,13 @@ impl E1000 {
             rx_descs,
             rx_buffers,
             rx_cur: 0,
+            irq: pci_dev.irq,
+            checksum_offload: crate::boot::cmdline::get("csum_offload") != Some("off"),
+            tx_context: None,
+            checksum_errors: 0,
+            promiscuous: false,
+            unicast_filters: Vec::new(),
+            multicast_filters: Vec::new(),
         };
 
         dev.reset();
@@ -129,12 +221,18 @@ impl E1000 {
         pci::find_e1000().and_then(|pci| Self::new(pci, phys_mem_offset))
     }
 
+    // Every REG_* offset lies within REGISTER_SPACE, which `new` checked
+    // the BAR covers; a bad offset reads as all ones, like absent hardware.
     fn read_reg(&self, offset: u32) -> u32 {
-        unsafe { read_volatile(self.mmio_base.byte_add(offset as usize)) }
+        self.regs
+            .register::<u32>(offset as usize)
+            .map_or(u32::MAX, |reg| reg.read())
     }
 
     fn write_reg(&self, offset: u32, value: u32) {
-        unsafe { write_volatile(self.mmio_base.byte_add(offset as usize), value) }
+        if let Ok(reg) = self.regs.register::<u32>(offset as usize) {
+            reg.write(value);
+        }
     }
 
     fn virt_to_phys(&self, virt_addr: u64) -> u64 {
@@ -200,25 +298,76 @@ impl E1000 {
         // RDT should be one less than RDH initially to show all descriptors are available
         self.write_reg(REG_RDT, (RX_DESC_COUNT - 1) as u32);
         
-        // Enable with Broadcast Accept, Multicast Promisc, Unicast Promisc
-        self.write_reg(REG_RCTL, RCTL_EN | RCTL_BAM | (1 << 3) | (1 << 4));
+        if self.checksum_offload {
+            self.write_reg(REG_RXCSUM, RXCSUM_IPOFL | RXCSUM_TUOFL);
+        }
+
+        self.write_filters();
+
+        // Accept broadcasts and frames that pass the address filters
+        self.write_reg(REG_RCTL, self.rctl());
         
         // Force link status up
         let ctrl = self.read_reg(REG_CTRL);
         self.write_reg(REG_CTRL, ctrl | CTRL_SLU);
     }
 
-    pub fn transmit_raw(&mut self, data: &[u8]) -> bool {
-        if data.len() > PACKET_BUFFER_SIZE { return false; }
+    /// Descriptors a frame may take: a checksum context and the data.
+    fn tx_slots(&self) -> usize {
+        if self.checksum_offload { 2 } else { 1 }
+    }
+
+    fn tx_free(&self, slots: usize) -> bool {
+        (0..slots).all(|i| self.tx_descs[(self.tx_cur + i) % TX_DESC_COUNT].status & TX_DD != 0)
+    }
+
+    /// Load a TCP checksum context starting at `css`, so following data
+    /// descriptors with TXSM get their checksum inserted at `css + 16`.
+    fn load_tx_context(&mut self, css: u8) {
         let idx = self.tx_cur;
+        let cso = css + TCP_CHECKSUM_OFFSET as u8;
         let desc = &mut self.tx_descs[idx];
-        
-        if (desc.status & TX_DD) == 0 { return false; }
-        
+        // TUCSS, TUCSO and TUCSE (0: to the end of the frame)
+        desc.addr = (u64::from(css) << 32) | (u64::from(cso) << 40);
+        desc.length = 0;
+        desc.cso = 0;
+        desc.cmd = TX_CMD_DEXT | TX_CMD_RS | TX_TUCMD_TCP;
+        desc.status = 0;
+        desc.css = 0;
+        desc.special = 0;
+        self.tx_cur = (self.tx_cur + 1) % TX_DESC_COUNT;
+        self.tx_context = Some(css);
+    }
+
+    pub fn transmit_raw(&mut self, data: &mut [u8]) -> bool {
+        if data.len() > PACKET_BUFFER_SIZE { return false; }
+        if !self.tx_free(self.tx_slots()) { return false; }
+
+        let offload = if self.checksum_offload { prepare_tx_checksum(data) } else { None };
+        if let Some(css) = offload {
+            if self.tx_context != Some(css) {
+                self.load_tx_context(css);
+            }
+        }
+
+        let idx = self.tx_cur;
         self.tx_buffers[idx][..data.len()].copy_from_slice(data);
+        // A context descriptor may have used the slot's address field
+        let addr = self.virt_to_phys(self.tx_buffers[idx].as_ptr() as u64);
+        let desc = &mut self.tx_descs[idx];
+        desc.addr = addr;
         desc.length = data.len() as u16;
-        desc.cmd = (1 << 0) | (1 << 1) | (1 << 3); // EOP | IFCS | RS
         desc.status = 0;
+        desc.special = 0;
+        if offload.is_some() {
+            desc.cso = TX_DTYP_DATA;
+            desc.cmd = TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS | TX_CMD_DEXT;
+            desc.css = TX_POPTS_TXSM;
+        } else {
+            desc.cso = 0;
+            desc.cmd = TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS;
+            desc.css = 0;
+        }
         
         self.tx_cur = (self.tx_cur + 1) % TX_DESC_COUNT;
         self.write_reg(REG_TDT, self.tx_cur as u32);
@@ -227,33 +376,302 @@ impl E1000 {
         true
     }
 
-    pub fn receive_raw(&mut self) -> Option<Vec<u8>> {
-        let idx = self.rx_cur;
-        let desc = &mut self.rx_descs[idx];
-        
-        if (desc.status & RX_DD) == 0 {
-             // Debug: check if head has moved
-             let rdh = self.read_reg(REG_RDH);
-             if rdh != (self.rx_cur as u32) {
-                  // This is normal if we are lagging, but if it stays different and DD=0, something is wrong.
-             }
-             return None;
+    pub fn receive_raw(&mut self) -> Option<PacketBuf> {
+        loop {
+            let idx = self.rx_cur;
+            let desc = &mut self.rx_descs[idx];
+
+            if (desc.status & RX_DD) == 0 {
+                 // Debug: check if head has moved
+                 let rdh = self.read_reg(REG_RDH);
+                 if rdh != (self.rx_cur as u32) {
+                      // This is normal if we are lagging, but if it stays different and DD=0, something is wrong.
+                 }
+                 return None;
+            }
+
+            let len = desc.length as usize;
+            let frame = &self.rx_buffers[idx][..len];
+            let valid = !self.checksum_offload || rx_checksum_ok(frame, desc.status, desc.errors);
+            let data = valid.then(|| PACKET_POOL.packet_from(frame));
+
+            crate::serial_println!("[e1000] Received {} bytes (status={:#x})", len, desc.status);
+
+            desc.status = 0;
+            self.rx_cur = (self.rx_cur + 1) % RX_DESC_COUNT;
+            // Hardware uses up to RDT. So we move RDT to the one we just processed.
+            self.write_reg(REG_RDT, idx as u32);
+
+            match data {
+                Some(data) => return Some(data),
+                None => self.checksum_errors += 1,
+            }
         }
-        
-        let len = desc.length as usize;
-        let data = self.rx_buffers[idx][..len].to_vec();
-        
-        crate::serial_println!("[e1000] Received {} bytes (status={:#x})", len, desc.status);
-        
-        desc.status = 0;
-        self.rx_cur = (self.rx_cur + 1) % RX_DESC_COUNT;
-        // Hardware uses up to RDT. So we move RDT to the one we just processed.
-        self.write_reg(REG_RDT, idx as u32);
-        
-        Some(data)
     }
 
     pub fn mac_address(&self) -> [u8; 6] { self.mac_address }
+
+    /// Whether checksums are offloaded to the NIC.
+    pub fn checksum_offload(&self) -> bool {
+        self.checksum_offload
+    }
+
+    /// Received frames dropped for a bad checksum since boot.
+    pub fn checksum_errors(&self) -> u64 {
+        self.checksum_errors
+    }
+
+    fn rctl(&self) -> u32 {
+        if self.promiscuous {
+            RCTL_EN | RCTL_BAM | RCTL_UPE | RCTL_MPE
+        } else {
+            RCTL_EN | RCTL_BAM
+        }
+    }
+
+    /// Whether the NIC accepts every frame on the wire.
+    pub fn promiscuous(&self) -> bool {
+        self.promiscuous
+    }
+
+    /// Accept every frame on the wire, or only those the filters pass.
+    pub fn set_promiscuous(&mut self, on: bool) {
+        self.promiscuous = on;
+        self.write_reg(REG_RCTL, self.rctl());
+    }
+
+    /// Unicast addresses accepted besides the NIC's own.
+    pub fn unicast_filters(&self) -> &[[u8; 6]] {
+        &self.unicast_filters
+    }
+
+    /// Multicast addresses accepted.
+    pub fn multicast_filters(&self) -> &[[u8; 6]] {
+        &self.multicast_filters
+    }
+
+    /// Accept frames sent to `mac`, unicast or multicast.
+    pub fn add_filter(&mut self, mac: [u8; 6]) -> Result<(), FilterError> {
+        let multicast = mac[0] & 1 != 0;
+        let filters = if multicast {
+            &mut self.multicast_filters
+        } else {
+            &mut self.unicast_filters
+        };
+        if mac == self.mac_address || filters.contains(&mac) {
+            return Ok(());
+        }
+        // Multicast hashes may collide, but the table never fills
+        if !multicast && filters.len() + 1 >= RAR_ENTRIES {
+            return Err(FilterError::TableFull);
+        }
+        filters.push(mac);
+        self.write_filters();
+        Ok(())
+    }
+
+    /// Stop accepting frames sent to `mac`. Returns `false` if it was not
+    /// filtered for.
+    pub fn remove_filter(&mut self, mac: [u8; 6]) -> bool {
+        let before = self.unicast_filters.len() + self.multicast_filters.len();
+        self.unicast_filters.retain(|m| *m != mac);
+        self.multicast_filters.retain(|m| *m != mac);
+        let removed = self.unicast_filters.len() + self.multicast_filters.len() < before;
+        if removed {
+            self.write_filters();
+        }
+        removed
+    }
+
+    /// Program the receive address registers and multicast table from the
+    /// filter lists.
+    fn write_filters(&self) {
+        for i in 1..RAR_ENTRIES {
+            let (low, high) = match self.unicast_filters.get(i - 1) {
+                Some(mac) => (
+                    u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]),
+                    u32::from_le_bytes([mac[4], mac[5], 0, 0]) | RAH_AV,
+                ),
+                None => (0, 0),
+            };
+            let offset = (i * 8) as u32;
+            self.write_reg(REG_RAL0 + offset, low);
+            self.write_reg(REG_RAH0 + offset, high);
+        }
+        let mut table = [0u32; MTA_ENTRIES as usize];
+        for mac in &self.multicast_filters {
+            let (index, bit) = multicast_hash(*mac);
+            table[index] |= bit;
+        }
+        for (i, bits) in (0..MTA_ENTRIES).zip(table) {
+            self.write_reg(REG_MTA + i * 4, bits);
+        }
+    }
+
+    /// Check whether the link is up (cable plugged in, or the QEMU netdev
+    /// connected).
+    pub fn link_up(&self) -> bool {
+        self.read_reg(REG_STATUS) & STATUS_LU != 0
+    }
+
+    /// Raise an interrupt when frames arrive or the link changes, waking
+    /// the stack poller. Returns the IRQ line, or `None` if the firmware
+    /// routed the NIC to a line without a handler; the stack is then only
+    /// polled on its timer.
+    pub fn enable_interrupts(&self) -> Option<u8> {
+        if IRQ_REGS.get().is_none() || !pic::PCI_IRQS.contains(&self.irq) {
+            return None;
+        }
+        // Drop causes latched before now
+        self.read_reg(REG_ICR);
+        self.write_reg(REG_IMS, INT_RXT0 | INT_RXDMT0 | INT_LSC);
+        pic::unmask_pci(self.irq).then_some(self.irq)
+    }
+}
+
+/// Acknowledge an e1000 interrupt and wake the stack poller.
+///
+/// Called from the handler of every PCI IRQ line, which the NIC may share.
+pub fn handle_interrupt() {
+    if let Some(regs) = IRQ_REGS.get() {
+        // Reading ICR clears the causes and deasserts the line
+        let causes = regs
+            .register::<u32>(REG_ICR as usize)
+            .map_or(0, |reg| reg.read());
+        if causes != 0 {
+            super::poller::wake();
+        }
+    }
+}
+
+/// Multicast table register and bit for `mac`: bits 47:36 of the address
+/// (the default filter type), as the NIC hashes them.
+pub fn multicast_hash(mac: [u8; 6]) -> (usize, u32) {
+    let hash = (usize::from(mac[4]) >> 4 | usize::from(mac[5]) << 4) & 0xFFF;
+    (hash >> 5, 1 << (hash & 0x1F))
+}
+
+/// What smoltcp still checks and computes itself with offload enabled.
+///
+/// Received IPv4, TCP and UDP checksums are verified by the NIC, or by
+/// `rx_checksum_ok` when it did not look at a frame. Of the sent ones, the
+/// NIC only fills in TCP's: UDP would need a checksum of 0 rewritten to
+/// 0xFFFF, which it does not do, and the IPv4 header is cheap.
+pub fn offload_checksums() -> ChecksumCapabilities {
+    let mut checksum = ChecksumCapabilities::default();
+    checksum.ipv4 = Checksum::Tx;
+    checksum.udp = Checksum::Tx;
+    checksum.tcp = Checksum::None;
+    checksum
+}
+
+/// Offsets of the IPv4 payload in an Ethernet frame, if the frame holds an
+/// unfragmented IPv4 packet: header start, payload start, payload length
+/// and protocol.
+fn ipv4_payload(frame: &[u8]) -> Option<(usize, usize, usize, u8)> {
+    let ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+    if ethertype != ETHERTYPE_IPV4 {
+        return None;
+    }
+    let ip = frame.get(ETH_HEADER_LEN..)?;
+    let header_len = usize::from(*ip.first()? & 0x0F) * 4;
+    if ip[0] >> 4 != 4 || header_len < IPV4_MIN_HEADER_LEN || ip.len() < header_len {
+        return None;
+    }
+    let total_len = usize::from(u16::from_be_bytes([ip[2], ip[3]]));
+    let fragment = u16::from_be_bytes([ip[6], ip[7]]);
+    if fragment & (IPV4_MORE_FRAGMENTS | IPV4_FRAGMENT_OFFSET) != 0
+        || total_len < header_len
+        || ip.len() < total_len
+    {
+        return None;
+    }
+    Some((ETH_HEADER_LEN, ETH_HEADER_LEN + header_len, total_len - header_len, ip[9]))
+}
+
+/// Ones' complement sum of `data` as big-endian 16-bit words, not yet
+/// folded or inverted.
+fn sum_words(data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    let mut sum: u32 = chunks
+        .by_ref()
+        .map(|word| u32::from(u16::from_be_bytes([word[0], word[1]])))
+        .sum();
+    if let [last] = chunks.remainder() {
+        sum += u32::from(*last) << 8;
+    }
+    sum
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// Sum of the TCP/UDP pseudo-header of the payload found by `ipv4_payload`.
+fn pseudo_header_sum(frame: &[u8], ip: usize, len: usize, protocol: u8) -> u32 {
+    sum_words(&frame[ip + 12..ip + 20]) + u32::from(protocol) + len as u32
+}
+
+/// Prepare a frame for TCP checksum insertion by the NIC.
+///
+/// Returns where the checksum starts if the NIC can insert it, having
+/// seeded the checksum field with the pseudo-header sum (the NIC sums from
+/// there to the end of the frame). A TCP segment followed by padding gets
+/// its checksum computed here instead, since smoltcp left it zero. Other
+/// frames are left alone.
+pub fn prepare_tx_checksum(frame: &mut [u8]) -> Option<u8> {
+    let (ip, start, len, protocol) = ipv4_payload(frame)?;
+    if protocol != IP_PROTO_TCP || len < TCP_HEADER_LEN {
+        return None;
+    }
+    let pseudo = pseudo_header_sum(frame, ip, len, protocol);
+    let field = start + TCP_CHECKSUM_OFFSET;
+    if start + len == frame.len() {
+        frame[field..field + 2].copy_from_slice(&fold(pseudo).to_be_bytes());
+        return u8::try_from(start).ok();
+    }
+    frame[field..field + 2].fill(0);
+    let checksum = !fold(pseudo + sum_words(&frame[start..start + len]));
+    frame[field..field + 2].copy_from_slice(&checksum.to_be_bytes());
+    None
+}
+
+/// Whether a received frame's checksums are good, given the descriptor's
+/// status and errors. Checksums the NIC did not verify are checked here.
+pub fn rx_checksum_ok(frame: &[u8], status: u8, errors: u8) -> bool {
+    if errors & (RX_ERR_IPE | RX_ERR_TCPE) != 0 {
+        return false;
+    }
+    let Some((ip, start, len, protocol)) = ipv4_payload(frame) else {
+        return true;
+    };
+    if status & RX_IPCS == 0 && fold(sum_words(&frame[ip..start])) != 0xFFFF {
+        return false;
+    }
+    let checksum_offset = match protocol {
+        IP_PROTO_TCP => TCP_CHECKSUM_OFFSET,
+        IP_PROTO_UDP => UDP_CHECKSUM_OFFSET,
+        _ => return true,
+    };
+    if status & RX_TCPCS != 0 {
+        return true;
+    }
+    let Some(payload) = frame.get(start..start + len) else {
+        return false;
+    };
+    // A UDP checksum of zero means the sender did not compute one
+    if protocol == IP_PROTO_UDP
+        && payload.get(checksum_offset..checksum_offset + 2) == Some(&[0, 0])
+    {
+        return true;
+    }
+    if payload.len() < checksum_offset + 2 {
+        return false;
+    }
+    fold(pseudo_header_sum(frame, ip, len, protocol) + sum_words(payload)) == 0xFFFF
 }
 
 impl Device for E1000 {
@@ -265,7 +683,7 @@ impl Device for E1000 {
     }
 
     fn transmit(&mut self, _: Instant) -> Option<Self::TxToken<'_>> {
-        if (self.tx_descs[self.tx_cur].status & TX_DD) != 0 {
+        if self.tx_free(self.tx_slots()) {
             Some(E1000TxToken { dev: self })
         } else {
             None
@@ -277,11 +695,14 @@ impl Device for E1000 {
         caps.medium = Medium::Ethernet;
         caps.max_transmission_unit = MTU;
         caps.max_burst_size = Some(1);
+        if self.checksum_offload {
+            caps.checksum = offload_checksums();
+        }
         caps
     }
 }
 
-pub struct E1000RxToken { buffer: Vec<u8> }
+pub struct E1000RxToken { buffer: PacketBuf }
 impl RxToken for E1000RxToken {
     fn consume<R, F>(mut self, f: F) -> R where F: FnOnce(&mut [u8]) -> R { f(&mut self.buffer) }
 }
@@ -289,9 +710,9 @@ impl RxToken for E1000RxToken {
 pub struct E1000TxToken<'a> { dev: &'a mut E1000 }
 impl<'a> TxToken for E1000TxToken<'a> {
     fn consume<R, F>(self, len: usize, f: F) -> R where F: FnOnce(&mut [u8]) -> R {
-        let mut b = alloc::vec![0u8; len];
+        let mut b = PACKET_POOL.packet(len);
         let r = f(&mut b);
-        self.dev.transmit_raw(&b);
+        self.dev.transmit_raw(&mut b);
         r
     }
 }
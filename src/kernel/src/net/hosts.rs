@@ -0,0 +1,94 @@
+//! Static hostname overrides loaded from `/etc/hosts`.
+//!
+//! Uses the familiar `address name [alias...]` line format with `#`
+//! comments. The file is read from the root filesystem and re-read
+//! whenever an `FsWatch` on its path reports a change.
+
+use super::dns::parse_ipv4;
+use crate::fs::{FileSystem, FsWatch, ROOT_FS};
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use smoltcp::wire::IpAddress;
+
+/// Default location of the hosts file.
+pub const HOSTS_PATH: &str = "/etc/hosts";
+
+/// Hostname overrides backed by a file in RamFs.
+pub struct HostsFile {
+    watch: FsWatch,
+    entries: BTreeMap<String, Vec<IpAddress>>,
+    loaded: bool,
+}
+
+impl HostsFile {
+    /// Create an override table for the file at `path`.
+    ///
+    /// The file is read lazily on first lookup.
+    pub fn new(path: &str) -> Self {
+        Self {
+            watch: ROOT_FS.watch(path),
+            entries: BTreeMap::new(),
+            loaded: false,
+        }
+    }
+
+    /// Look up the addresses pinned for `hostname`, reloading the file if it changed.
+    pub fn lookup(&mut self, hostname: &str) -> Option<Vec<IpAddress>> {
+        self.refresh();
+        self.entries.get(&hostname.to_lowercase()).cloned()
+    }
+
+    /// Number of hostnames currently pinned.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether no hostnames are pinned.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Reload the file if it has not been read yet or was modified.
+    fn refresh(&mut self) {
+        if ROOT_FS.changed(&mut self.watch) || !self.loaded {
+            self.entries =
+                read_file(self.watch.path()).map_or_else(BTreeMap::new, |text| parse(&text));
+            self.loaded = true;
+        }
+    }
+}
+
+/// Read a whole file as (lossy) UTF-8 text.
+fn read_file(path: &str) -> Option<String> {
+    let handle = ROOT_FS.open(path).ok()?;
+    let size = ROOT_FS.size(handle).unwrap_or(0);
+    let mut buffer = vec![0u8; size];
+    let result = ROOT_FS.read(handle, &mut buffer, 0);
+    ROOT_FS.close(handle);
+    let len = result.ok()?;
+    Some(String::from_utf8_lossy(&buffer[..len]).to_string())
+}
+
+/// Parse hosts file text into a hostname → addresses map.
+///
+/// Malformed lines are skipped. Names are case-insensitive; a name listed
+/// on several lines collects all of their addresses in order.
+pub fn parse(text: &str) -> BTreeMap<String, Vec<IpAddress>> {
+    let mut entries: BTreeMap<String, Vec<IpAddress>> = BTreeMap::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        let Some(addr) = fields.next().and_then(parse_ipv4) else {
+            continue;
+        };
+        for name in fields {
+            let addrs = entries.entry(name.to_lowercase()).or_default();
+            if !addrs.contains(&IpAddress::Ipv4(addr)) {
+                addrs.push(IpAddress::Ipv4(addr));
+            }
+        }
+    }
+    entries
+}
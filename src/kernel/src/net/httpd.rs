@@ -0,0 +1,373 @@
+//! Static-file HTTP/1.1 server.
+//!
+//! Serves files from one RamFs directory. The server only holds a handle to
+//! that directory and resolves every request with `open_at` relative to it,
+//! rejecting `..` segments, so nothing outside the served tree is reachable.
+//! Each response closes its connection (`Connection: close`).
+
+use super::socket::TcpListener;
+use super::stack::NetworkStack;
+use super::NetError;
+use crate::fs::{FileHandle, FileSystem, FsError, ROOT_FS};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use smoltcp::iface::SocketHandle;
+use smoltcp::time::{Duration, Instant};
+
+/// Default port for `httpd start`.
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// Number of sockets kept listening for new connections.
+const LISTEN_BACKLOG: usize = 2;
+
+/// Maximum number of connections served at once.
+const MAX_CONNECTIONS: usize = 4;
+
+/// Maximum size of a request header block.
+const MAX_REQUEST_SIZE: usize = 2048;
+
+/// Time a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time after which a connection is aborted regardless of progress.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// File served for a directory request.
+const INDEX_FILE: &str = "index.html";
+
+/// Errors starting the HTTP server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpdError {
+    /// A server is already running.
+    AlreadyRunning,
+    /// The document root could not be opened or is not a directory.
+    Root(FsError),
+    /// The listening socket could not be set up.
+    Net(NetError),
+}
+
+impl core::fmt::Display for HttpdError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            HttpdError::AlreadyRunning => write!(f, "server already running"),
+            HttpdError::Root(e) => write!(f, "cannot serve directory: {:?}", e),
+            HttpdError::Net(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Snapshot of a running server.
+#[derive(Debug, Clone)]
+pub struct HttpdStatus {
+    /// Served directory.
+    pub root: String,
+    /// Listening port.
+    pub port: u16,
+    /// Connections currently open.
+    pub connections: usize,
+    /// Requests answered since start.
+    pub requests: u64,
+}
+
+/// Progress of one connection.
+enum ConnectionState {
+    /// Collecting the request header block.
+    Reading(Vec<u8>),
+    /// Sending the response; `usize` is the number of bytes already sent.
+    Writing(Vec<u8>, usize),
+    /// Response sent, waiting for the close handshake.
+    Closing,
+}
+
+/// One accepted client connection.
+struct Connection {
+    handle: SocketHandle,
+    accepted_at: Instant,
+    state: ConnectionState,
+}
+
+/// A running server.
+struct Server {
+    root: String,
+    dir: FileHandle,
+    listener: TcpListener,
+    connections: Vec<Connection>,
+    requests: u64,
+}
+
+/// HTTP server controller, polled by its task.
+pub struct Httpd {
+    server: Option<Server>,
+}
+
+impl Httpd {
+    /// Create a stopped server.
+    pub fn new() -> Self {
+        Self { server: None }
+    }
+
+    /// Start serving `root` on `port`.
+    pub fn start(
+        &mut self,
+        stack: &mut NetworkStack,
+        root: &str,
+        port: u16,
+    ) -> Result<(), HttpdError> {
+        if self.server.is_some() {
+            return Err(HttpdError::AlreadyRunning);
+        }
+
+        let dir = ROOT_FS.open(root).map_err(HttpdError::Root)?;
+        if !ROOT_FS.is_dir(dir) {
+            ROOT_FS.close(dir);
+            return Err(HttpdError::Root(FsError::NotFound));
+        }
+
+        let listener = match TcpListener::bind(stack, port, LISTEN_BACKLOG) {
+            Ok(listener) => listener,
+            Err(e) => {
+                ROOT_FS.close(dir);
+                return Err(HttpdError::Net(e));
+            }
+        };
+
+        self.server = Some(Server {
+            root: root.to_string(),
+            dir,
+            listener,
+            connections: Vec::new(),
+            requests: 0,
+        });
+        Ok(())
+    }
+
+    /// Stop the server, dropping open connections.
+    ///
+    /// Returns `false` if no server was running.
+    pub fn stop(&mut self, stack: &mut NetworkStack) -> bool {
+        let Some(server) = self.server.take() else {
+            return false;
+        };
+        for conn in server.connections {
+            stack.get_tcp_socket(conn.handle).abort();
+            stack.release_socket(conn.handle);
+        }
+        server.listener.close(stack);
+        ROOT_FS.close(server.dir);
+        true
+    }
+
+    /// Get the server status, if running.
+    pub fn status(&self) -> Option<HttpdStatus> {
+        self.server.as_ref().map(|server| HttpdStatus {
+            root: server.root.clone(),
+            port: server.listener.port(),
+            connections: server.connections.len(),
+            requests: server.requests,
+        })
+    }
+
+    /// Accept new connections and advance existing ones.
+    pub fn poll(&mut self, stack: &mut NetworkStack, timestamp: Instant) {
+        let Some(server) = self.server.as_mut() else {
+            return;
+        };
+
+        while server.connections.len() < MAX_CONNECTIONS {
+            let Some(handle) = server.listener.accept(stack) else {
+                break;
+            };
+            server.connections.push(Connection {
+                handle,
+                accepted_at: timestamp,
+                state: ConnectionState::Reading(Vec::new()),
+            });
+        }
+
+        let mut i = 0;
+        while i < server.connections.len() {
+            let done = server.advance(stack, i, timestamp);
+            if done {
+                let conn = server.connections.swap_remove(i);
+                stack.release_socket(conn.handle);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+impl Default for Httpd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    /// Drive connection `index`. Returns `true` once its socket can be released.
+    fn advance(&mut self, stack: &mut NetworkStack, index: usize, timestamp: Instant) -> bool {
+        let dir = self.dir;
+        let conn = &mut self.connections[index];
+        let socket = stack.get_tcp_socket(conn.handle);
+
+        if !socket.is_active() {
+            return true;
+        }
+        if timestamp - conn.accepted_at > CONNECTION_TIMEOUT {
+            // Stalled peer; the socket is released once the abort goes out
+            socket.abort();
+            return false;
+        }
+
+        match &mut conn.state {
+            ConnectionState::Reading(request) => {
+                let mut buffer = [0u8; 512];
+                while let Ok(len) = socket.recv_slice(&mut buffer) {
+                    if len == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..len]);
+                }
+
+                let response = if let Some(end) = find_header_end(request) {
+                    Some(respond(dir, &request[..end]))
+                } else if request.len() > MAX_REQUEST_SIZE {
+                    Some(error_response(431, "Request Header Fields Too Large"))
+                } else if timestamp - conn.accepted_at > REQUEST_TIMEOUT || !socket.may_recv() {
+                    socket.close();
+                    conn.state = ConnectionState::Closing;
+                    return false;
+                } else {
+                    None
+                };
+
+                if let Some(response) = response {
+                    self.requests += 1;
+                    conn.state = ConnectionState::Writing(response, 0);
+                }
+                false
+            }
+            ConnectionState::Writing(response, sent) => {
+                if let Ok(n) = socket.send_slice(&response[*sent..]) {
+                    *sent += n;
+                }
+                if *sent >= response.len() {
+                    socket.close();
+                    conn.state = ConnectionState::Closing;
+                }
+                false
+            }
+            ConnectionState::Closing => false,
+        }
+    }
+}
+
+/// Find the end of the header block (`\r\n\r\n`), returning its length.
+fn find_header_end(request: &[u8]) -> Option<usize> {
+    request
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+/// Build the response for a complete request header block.
+pub fn respond(dir: FileHandle, request: &[u8]) -> Vec<u8> {
+    let head = String::from_utf8_lossy(request);
+    let mut parts = head.lines().next().unwrap_or("").split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return error_response(400, "Bad Request");
+    };
+
+    let include_body = match method {
+        "GET" => true,
+        "HEAD" => false,
+        _ => return error_response(405, "Method Not Allowed"),
+    };
+
+    let Some(path) = sanitize_path(target) else {
+        return error_response(403, "Forbidden");
+    };
+
+    match read_file(dir, &path) {
+        Ok((name, body)) => {
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                content_type(&name),
+                body.len()
+            )
+            .into_bytes();
+            if include_body {
+                response.extend_from_slice(&body);
+            }
+            response
+        }
+        Err(FsError::NotFound) => error_response(404, "Not Found"),
+        Err(_) => error_response(500, "Internal Server Error"),
+    }
+}
+
+/// Turn a request target into a path relative to the served directory.
+///
+/// Drops the query string and rejects any `..` segment. Percent escapes
+/// are not decoded, so `%2e%2e` is a name like any other.
+pub fn sanitize_path(target: &str) -> Option<String> {
+    let path = target.split(['?', '#']).next().unwrap_or("");
+    let mut segments = Vec::new();
+    for segment in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+        if segment == ".." {
+            return None;
+        }
+        segments.push(segment);
+    }
+    Some(segments.join("/"))
+}
+
+/// Read a file relative to the served directory, falling back to
+/// `index.html` for directories. Returns the served file name and contents.
+fn read_file(dir: FileHandle, path: &str) -> Result<(String, Vec<u8>), FsError> {
+    let mut name = path.to_string();
+    let mut handle = ROOT_FS.open_at(dir, path)?;
+    if ROOT_FS.is_dir(handle) {
+        let index = ROOT_FS.open_at(handle, INDEX_FILE);
+        ROOT_FS.close(handle);
+        handle = index?;
+        name = INDEX_FILE.to_string();
+    }
+
+    let size = ROOT_FS.size(handle).unwrap_or(0);
+    let mut body = vec![0u8; size];
+    let result = ROOT_FS.read(handle, &mut body, 0);
+    ROOT_FS.close(handle);
+    body.truncate(result?);
+    Ok((name, body))
+}
+
+/// Guess a MIME type from the file extension.
+fn content_type(name: &str) -> &'static str {
+    match name.rsplit('.').next().unwrap_or("") {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "wasm" => "application/wasm",
+        "png" => "image/png",
+        "svg" => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Build a small plain-text error response.
+fn error_response(code: u16, reason: &str) -> Vec<u8> {
+    let body = format!("{} {}\n", code, reason);
+    format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        body.len(),
+        body
+    )
+    .into_bytes()
+}
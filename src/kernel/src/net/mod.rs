@@ -6,41 +6,95 @@
 //!
 //! - `e1000`: Real Intel e1000 NIC driver (PCI/MMIO)
 //! - `device`: Loopback/fallback device for testing
+//! - `slip`: SLIP link over COM2 for setups without a NIC
 //! - `stack`: smoltcp Interface wrapper
+//! - `arp`: Address conflict detection and gratuitous ARP
+//! - `selftest`: Boot-time UDP and TCP round trips over loopback
+//! - `poller`: Sleeps the stack poller until smoltcp or the NIC has work
+//! - `pool`: Recycled 2 KiB packet and 4 KiB socket buffers
 //! - `socket`: Socket abstraction layer
+//! - `connection`: TCP state changes delivered as events
+//! - `commands`: Network shell commands
 //! - `dhcp`: DHCP client for automatic IP configuration
 //! - `dns`: DNS resolver for hostname lookup
+//! - `hosts`: `/etc/hosts` overrides consulted before DNS
+//! - `httpd`: Static-file HTTP server
+//! - `telnetd`: Telnet server for remote shell sessions
+//! - `tftp`: TFTP client for moving files to and from the dev host
+//! - `traceroute`: TTL-limited UDP probes with ICMP error parsing
 
+pub mod arp;
+#[cfg(feature = "terminal")]
+pub mod commands;
+pub mod connection;
 pub mod device;
 pub mod dhcp;
 pub mod dns;
 pub mod e1000;
+pub mod hosts;
+pub mod httpd;
+pub mod poller;
+pub mod pool;
+pub mod selftest;
+pub mod slip;
 pub mod socket;
 pub mod stack;
+pub mod syslog;
+pub mod telnetd;
+pub mod tftp;
+pub mod traceroute;
 
+pub use connection::{ConnectionEvent, ConnectionSink};
 pub use device::QemuE1000;
-pub use dhcp::{DhcpClient, DhcpConfig, DhcpEvent};
-pub use dns::{DnsResolver, DnsResult};
+pub use dhcp::{AddressConflict, ConflictAction, DhcpClient, DhcpConfig, DhcpEvent};
+pub use dns::{DnsCache, DnsCacheEntry, DnsFailure, DnsFuture, DnsResolver, DnsResult};
 pub use e1000::E1000;
-pub use socket::{TcpSocket, UdpSocket};
-pub use stack::{NetConfig, NetworkStack};
+pub use httpd::{Httpd, HttpdError, HttpdStatus};
+pub use slip::SlipDevice;
+pub use socket::{TcpListener, TcpSocket, UdpSocket};
+pub use stack::{LinkEvent, NetConfig, NetworkStack};
+pub use syslog::{Syslog, SyslogStatus};
+pub use telnetd::{TelnetEvent, Telnetd};
+pub use tftp::{Tftp, TftpDirection, TftpError, TftpEvent};
+pub use traceroute::{Traceroute, TracerouteEvent, TracerouteHop};
 
 pub use sovelma_common::net::NetError;
 
+use alloc::vec::Vec;
 use smoltcp::phy::{Device, DeviceCapabilities, RxToken, TxToken};
 use smoltcp::time::Instant;
 
 /// Unified network device enum supporting multiple backends.
 ///
-/// This allows the network stack to work with either a real e1000 driver
-/// or the loopback device for testing.
+/// This allows the network stack to work with a real e1000 driver, a
+/// SLIP link over the serial port, or the loopback device for testing.
 pub enum NetworkDevice {
     /// Real Intel e1000 NIC driver.
     E1000(E1000),
+    /// SLIP over COM2.
+    Slip(SlipDevice),
     /// Loopback device for testing.
     Loopback(QemuE1000),
 }
 
+/// Errors changing a device's receive filters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterError {
+    /// The device receives everything anyway (SLIP, loopback).
+    Unsupported,
+    /// No free unicast address register is left.
+    TableFull,
+}
+
+impl core::fmt::Display for FilterError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            FilterError::Unsupported => write!(f, "device has no receive filters"),
+            FilterError::TableFull => write!(f, "unicast filter table full"),
+        }
+    }
+}
+
 impl NetworkDevice {
     /// Probe for a real e1000 device, falling back to loopback.
     ///
@@ -54,17 +108,98 @@ impl NetworkDevice {
         }
     }
 
+    /// Select the device named by `net=` on the kernel command line.
+    ///
+    /// `net=slip` uses the serial link; anything else probes for a NIC.
+    /// While replaying a recording (see `replay`) the loopback device is
+    /// used, so the recorded frames are the only traffic, and so it is in
+    /// safe mode (see `boot::crashloop`).
+    pub fn from_cmdline(phys_mem_offset: u64) -> Self {
+        if crate::replay::replaying() || crate::boot::crashloop::safe_mode() {
+            return NetworkDevice::Loopback(QemuE1000::new());
+        }
+        match crate::boot::cmdline::get("net") {
+            Some("slip") => NetworkDevice::Slip(SlipDevice::new()),
+            _ => Self::probe(phys_mem_offset),
+        }
+    }
+
     /// Get the MAC address of the device.
-    pub fn mac_address(&self) -> [u8; 6] {
+    ///
+    /// Returns `None` for point-to-point links, which have no link layer.
+    pub fn mac_address(&self) -> Option<[u8; 6]> {
+        match self {
+            NetworkDevice::E1000(dev) => Some(dev.mac_address()),
+            NetworkDevice::Slip(_) => None,
+            NetworkDevice::Loopback(dev) => Some(dev.mac_address()),
+        }
+    }
+
+    /// Check whether the device can currently reach the network.
+    ///
+    /// Only the e1000 reports link state; other devices are always up.
+    pub fn link_up(&self) -> bool {
+        match self {
+            NetworkDevice::E1000(dev) => dev.link_up(),
+            NetworkDevice::Slip(_) | NetworkDevice::Loopback(_) => true,
+        }
+    }
+
+    /// Whether the device accepts every frame on the wire. Devices without
+    /// receive filters always do.
+    pub fn promiscuous(&self) -> bool {
+        match self {
+            NetworkDevice::E1000(dev) => dev.promiscuous(),
+            NetworkDevice::Slip(_) | NetworkDevice::Loopback(_) => true,
+        }
+    }
+
+    /// Turn promiscuous mode on or off, e.g. for packet capture.
+    pub fn set_promiscuous(&mut self, on: bool) -> Result<(), FilterError> {
+        match self {
+            NetworkDevice::E1000(dev) => {
+                dev.set_promiscuous(on);
+                Ok(())
+            }
+            NetworkDevice::Slip(_) | NetworkDevice::Loopback(_) => Err(FilterError::Unsupported),
+        }
+    }
+
+    /// Addresses accepted besides the device's own and broadcast, unicast
+    /// first.
+    pub fn filters(&self) -> Vec<[u8; 6]> {
         match self {
-            NetworkDevice::E1000(dev) => dev.mac_address(),
-            NetworkDevice::Loopback(dev) => dev.mac_address(),
+            NetworkDevice::E1000(dev) => dev
+                .unicast_filters()
+                .iter()
+                .chain(dev.multicast_filters())
+                .copied()
+                .collect(),
+            NetworkDevice::Slip(_) | NetworkDevice::Loopback(_) => Vec::new(),
+        }
+    }
+
+    /// Also accept frames sent to `mac`, such as a multicast group
+    /// (01:00:5e:00:00:fb for mDNS).
+    pub fn add_filter(&mut self, mac: [u8; 6]) -> Result<(), FilterError> {
+        match self {
+            NetworkDevice::E1000(dev) => dev.add_filter(mac),
+            NetworkDevice::Slip(_) | NetworkDevice::Loopback(_) => Err(FilterError::Unsupported),
+        }
+    }
+
+    /// Stop accepting frames sent to `mac`. Returns `false` if it was not
+    /// filtered for.
+    pub fn remove_filter(&mut self, mac: [u8; 6]) -> Result<bool, FilterError> {
+        match self {
+            NetworkDevice::E1000(dev) => Ok(dev.remove_filter(mac)),
+            NetworkDevice::Slip(_) | NetworkDevice::Loopback(_) => Err(FilterError::Unsupported),
         }
     }
 
     /// Check if this is a real hardware device.
     pub fn is_real(&self) -> bool {
-        matches!(self, NetworkDevice::E1000(_))
+        matches!(self, NetworkDevice::E1000(_) | NetworkDevice::Slip(_))
     }
 }
 
@@ -72,6 +207,8 @@ impl NetworkDevice {
 pub enum NetworkRxToken {
     /// E1000 receive token.
     E1000(e1000::E1000RxToken),
+    /// SLIP receive token.
+    Slip(slip::SlipRxToken),
     /// Loopback receive token.
     Loopback(device::E1000RxToken),
 }
@@ -80,6 +217,8 @@ pub enum NetworkRxToken {
 pub enum NetworkTxToken<'a> {
     /// E1000 transmit token.
     E1000(e1000::E1000TxToken<'a>),
+    /// SLIP transmit token.
+    Slip(slip::SlipTxToken<'a>),
     /// Loopback transmit token.
     Loopback(device::E1000TxToken<'a>),
 }
@@ -89,8 +228,14 @@ impl RxToken for NetworkRxToken {
     where
         F: FnOnce(&mut [u8]) -> R,
     {
+        let f = |frame: &mut [u8]| {
+            crate::replay::record_frame(frame);
+            arp::observe(frame);
+            f(frame)
+        };
         match self {
             NetworkRxToken::E1000(token) => token.consume(f),
+            NetworkRxToken::Slip(token) => token.consume(f),
             NetworkRxToken::Loopback(token) => token.consume(f),
         }
     }
@@ -103,6 +248,7 @@ impl<'a> TxToken for NetworkTxToken<'a> {
     {
         match self {
             NetworkTxToken::E1000(token) => token.consume(len, f),
+            NetworkTxToken::Slip(token) => token.consume(len, f),
             NetworkTxToken::Loopback(token) => token.consume(len, f),
         }
     }
@@ -117,6 +263,9 @@ impl Device for NetworkDevice {
             NetworkDevice::E1000(dev) => dev
                 .receive(timestamp)
                 .map(|(rx, tx)| (NetworkRxToken::E1000(rx), NetworkTxToken::E1000(tx))),
+            NetworkDevice::Slip(dev) => dev
+                .receive(timestamp)
+                .map(|(rx, tx)| (NetworkRxToken::Slip(rx), NetworkTxToken::Slip(tx))),
             NetworkDevice::Loopback(dev) => dev
                 .receive(timestamp)
                 .map(|(rx, tx)| (NetworkRxToken::Loopback(rx), NetworkTxToken::Loopback(tx))),
@@ -126,6 +275,7 @@ impl Device for NetworkDevice {
     fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
         match self {
             NetworkDevice::E1000(dev) => dev.transmit(timestamp).map(NetworkTxToken::E1000),
+            NetworkDevice::Slip(dev) => dev.transmit(timestamp).map(NetworkTxToken::Slip),
             NetworkDevice::Loopback(dev) => dev.transmit(timestamp).map(NetworkTxToken::Loopback),
         }
     }
@@ -133,6 +283,7 @@ impl Device for NetworkDevice {
     fn capabilities(&self) -> DeviceCapabilities {
         match self {
             NetworkDevice::E1000(dev) => dev.capabilities(),
+            NetworkDevice::Slip(dev) => dev.capabilities(),
             NetworkDevice::Loopback(dev) => dev.capabilities(),
         }
     }
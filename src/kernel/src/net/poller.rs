@@ -0,0 +1,101 @@
+//! Network poll scheduling.
+//!
+//! The stack poller runs `NetworkStack::poll` only when there is work.
+//! smoltcp's `poll_delay` says when the next socket timer (retransmission,
+//! DHCP renewal, ARP retry) is due, and the poller sleeps on the timer wheel
+//! until then. `wake` cuts the sleep short: the e1000 interrupt handler
+//! calls it when frames arrive, and the socket wrappers when they queue
+//! data to send.
+//!
+//! SLIP and the loopback device have no receive interrupt, and some code
+//! writes to smoltcp sockets directly, so the poller never sleeps longer
+//! than `MAX_POLL_DELAY_MS`.
+//!
+//! The poller registers itself as `PRODUCER`, so tasks waiting on network
+//! I/O can lend it their priority (see `task::donate`).
+
+use crate::task::donate::Producer;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+use smoltcp::time::Duration;
+
+/// Longest the poller sleeps, whatever `poll_delay` says.
+pub const MAX_POLL_DELAY_MS: u64 = 10;
+
+/// `LAST_DELAY_MS` value when no socket has a timer pending.
+const IDLE: u64 = u64::MAX;
+
+/// The stack poller task.
+pub static PRODUCER: Producer = Producer::new();
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+static LAST_DELAY_MS: AtomicU64 = AtomicU64::new(IDLE);
+static POLLS: AtomicU64 = AtomicU64::new(0);
+static WAKEUPS: AtomicU64 = AtomicU64::new(0);
+
+/// Poll scheduling counters, for `netstat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollStats {
+    /// What `poll_delay` returned after the last poll, in milliseconds;
+    /// `None` if no socket had a timer pending.
+    pub delay_ms: Option<u64>,
+    /// Stack polls since boot.
+    pub polls: u64,
+    /// Sleeps cut short by `wake`.
+    pub wakeups: u64,
+}
+
+/// Current counters.
+pub fn stats() -> PollStats {
+    let delay = LAST_DELAY_MS.load(Ordering::Relaxed);
+    PollStats {
+        delay_ms: (delay != IDLE).then_some(delay),
+        polls: POLLS.load(Ordering::Relaxed),
+        wakeups: WAKEUPS.load(Ordering::Relaxed),
+    }
+}
+
+/// Wake the poller now. Safe in interrupt context.
+pub fn wake() {
+    WOKEN.store(true, Ordering::Release);
+    WAKER.wake();
+}
+
+/// How long to sleep after a poll, given `poll_delay`'s answer. Records
+/// the answer for `stats`.
+pub fn schedule(delay: Option<Duration>) -> u64 {
+    POLLS.fetch_add(1, Ordering::Relaxed);
+    let delay_ms = delay.map(|delay| delay.total_millis());
+    LAST_DELAY_MS.store(delay_ms.unwrap_or(IDLE), Ordering::Relaxed);
+    delay_ms.map_or(MAX_POLL_DELAY_MS, |ms| ms.min(MAX_POLL_DELAY_MS))
+}
+
+/// Wait until `ms` milliseconds have passed or `wake` is called.
+pub fn wait(ms: u64) -> Wait {
+    Wait {
+        sleep: crate::time::sleep_ms(ms),
+    }
+}
+
+/// Future returned by `wait`.
+pub struct Wait {
+    sleep: crate::time::Sleep,
+}
+
+impl Future for Wait {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        WAKER.register(cx.waker());
+        if WOKEN.swap(false, Ordering::AcqRel) {
+            WAKEUPS.fetch_add(1, Ordering::Relaxed);
+            return Poll::Ready(());
+        }
+        Pin::new(&mut self.sleep).poll(cx)
+    }
+}
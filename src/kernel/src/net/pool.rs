@@ -0,0 +1,235 @@
+//! Size-class buffer pools for the network path.
+//!
+//! Frames and socket buffers are allocated and freed constantly, which
+//! fragments the linked-list kernel heap and makes every allocation walk a
+//! longer free list. The pools below hand out fixed-size blocks that are
+//! allocated from the heap once and then recycled:
+//!
+//! - `PACKET_POOL`: 2 KiB blocks for frames moving to and from a device
+//!   (`PacketBuf`, returned to the pool on drop) and for UDP/ICMP socket
+//!   payload storage.
+//! - `SOCKET_POOL`: 4 KiB blocks backing TCP socket buffers.
+//!
+//! Socket storage is leased: smoltcp keeps the block for the lifetime of
+//! the socket, and `NetworkStack` reclaims it when the socket is released.
+//! A pool grows to the peak number of blocks in use and keeps them. When
+//! the heap has no room for another block, `lease` fails rather than
+//! aborting, so socket creation can report `NetError::OutOfMemory`.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Block size of the packet pool; holds any Ethernet frame.
+pub const PACKET_BUFFER_SIZE: usize = 2048;
+
+/// Block size of the socket pool.
+pub const SOCKET_BUFFER_SIZE: usize = 4096;
+
+/// 2 KiB blocks for frames and datagram socket storage.
+pub static PACKET_POOL: BufferPool = BufferPool::new(PACKET_BUFFER_SIZE);
+
+/// 4 KiB blocks for TCP socket buffers.
+pub static SOCKET_POOL: BufferPool = BufferPool::new(SOCKET_BUFFER_SIZE);
+
+/// Pool usage counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Size of each block.
+    pub block_size: usize,
+    /// Blocks allocated from the heap so far.
+    pub blocks: usize,
+    /// Blocks currently handed out.
+    pub in_use: usize,
+    /// Requests served by a recycled block.
+    pub reused: usize,
+    /// Requests too large for a block, served by the heap.
+    pub oversized: usize,
+    /// Requests that found no free block and no heap room for one.
+    pub exhausted: usize,
+}
+
+/// A pool of fixed-size, recycled buffer blocks.
+pub struct BufferPool {
+    block_size: usize,
+    free: Mutex<Vec<&'static mut [u8]>>,
+    blocks: AtomicUsize,
+    in_use: AtomicUsize,
+    reused: AtomicUsize,
+    oversized: AtomicUsize,
+    exhausted: AtomicUsize,
+}
+
+impl BufferPool {
+    /// Create an empty pool of `block_size`-byte blocks.
+    pub const fn new(block_size: usize) -> Self {
+        Self {
+            block_size,
+            free: Mutex::new(Vec::new()),
+            blocks: AtomicUsize::new(0),
+            in_use: AtomicUsize::new(0),
+            reused: AtomicUsize::new(0),
+            oversized: AtomicUsize::new(0),
+            exhausted: AtomicUsize::new(0),
+        }
+    }
+
+    /// Size of each block.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Usage counters.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            block_size: self.block_size,
+            blocks: self.blocks.load(Ordering::Relaxed),
+            in_use: self.in_use.load(Ordering::Relaxed),
+            reused: self.reused.load(Ordering::Relaxed),
+            oversized: self.oversized.load(Ordering::Relaxed),
+            exhausted: self.exhausted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Take a zeroed block, recycling a free one if possible.
+    ///
+    /// Returns `None` if no block is free and the heap cannot fit another.
+    fn take(&self) -> Option<&'static mut [u8]> {
+        let recycled = self.free.lock().pop();
+        let block = match recycled {
+            Some(block) => {
+                self.reused.fetch_add(1, Ordering::Relaxed);
+                block.fill(0);
+                block
+            }
+            None => {
+                let mut storage = Vec::new();
+                if storage.try_reserve_exact(self.block_size).is_err() {
+                    self.exhausted.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                storage.resize(self.block_size, 0);
+                self.blocks.fetch_add(1, Ordering::Relaxed);
+                Box::leak(storage.into_boxed_slice())
+            }
+        };
+        self.in_use.fetch_add(1, Ordering::Relaxed);
+        Some(block)
+    }
+
+    /// Put a block back on the free list.
+    fn give(&self, block: &'static mut [u8]) {
+        self.in_use.fetch_sub(1, Ordering::Relaxed);
+        self.free.lock().push(block);
+    }
+
+    /// A zeroed `len`-byte buffer for one packet, returned to the pool when
+    /// dropped. Lengths over the block size, and packets the pool has no
+    /// block for, fall back to the heap.
+    pub fn packet(&'static self, len: usize) -> PacketBuf {
+        if len > self.block_size {
+            self.oversized.fetch_add(1, Ordering::Relaxed);
+        } else if let Some(block) = self.take() {
+            return PacketBuf {
+                storage: Storage::Pooled(self, block),
+                len,
+            };
+        }
+        PacketBuf {
+            storage: Storage::Heap(vec![0u8; len]),
+            len,
+        }
+    }
+
+    /// A `PacketBuf` holding a copy of `data`.
+    pub fn packet_from(&'static self, data: &[u8]) -> PacketBuf {
+        let mut packet = self.packet(data.len());
+        packet.copy_from_slice(data);
+        packet
+    }
+
+    /// Lease a block as storage for something that needs `&'static mut`,
+    /// such as a smoltcp socket buffer.
+    ///
+    /// The block stays out of the pool until given back with `reclaim`.
+    /// Returns `None` if the heap has no room for another block.
+    pub fn lease(&'static self) -> Option<(&'static mut [u8], Lease)> {
+        let block = self.take()?;
+        let lease = Lease {
+            pool: self,
+            ptr: NonNull::from(&mut *block).cast(),
+        };
+        Some((block, lease))
+    }
+
+    /// Return a leased block to the pool.
+    ///
+    /// # Safety
+    ///
+    /// Whatever was given the block by `lease` (e.g. the socket it backs)
+    /// must have been dropped; no reference to the block may remain.
+    pub unsafe fn reclaim(lease: Lease) {
+        // SAFETY: The lease points at a whole block of this pool that was
+        // leaked from the heap and so lives forever; the caller guarantees
+        // the previous borrow of it has ended.
+        let block =
+            unsafe { core::slice::from_raw_parts_mut(lease.ptr.as_ptr(), lease.pool.block_size) };
+        lease.pool.give(block);
+    }
+}
+
+/// A block lent out by `BufferPool::lease`.
+#[must_use = "a lease must be reclaimed or the block is lost to the pool"]
+pub struct Lease {
+    pool: &'static BufferPool,
+    ptr: NonNull<u8>,
+}
+
+// SAFETY: A Lease is only an address used to rebuild the block reference in
+// `reclaim`; it grants no access to the block itself.
+unsafe impl Send for Lease {}
+
+enum Storage {
+    Pooled(&'static BufferPool, &'static mut [u8]),
+    Heap(Vec<u8>),
+}
+
+/// A packet buffer that returns its block to the pool when dropped.
+pub struct PacketBuf {
+    storage: Storage,
+    len: usize,
+}
+
+impl Deref for PacketBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.storage {
+            Storage::Pooled(_, block) => &block[..self.len],
+            Storage::Heap(buffer) => &buffer[..self.len],
+        }
+    }
+}
+
+impl DerefMut for PacketBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match &mut self.storage {
+            Storage::Pooled(_, block) => &mut block[..self.len],
+            Storage::Heap(buffer) => &mut buffer[..self.len],
+        }
+    }
+}
+
+impl Drop for PacketBuf {
+    fn drop(&mut self) {
+        if let Storage::Pooled(pool, block) =
+            core::mem::replace(&mut self.storage, Storage::Heap(Vec::new()))
+        {
+            pool.give(block);
+        }
+    }
+}
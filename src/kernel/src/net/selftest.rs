@@ -0,0 +1,198 @@
+//! Loopback self-test of the network stack, run at boot with `selftest`
+//! on the kernel command line.
+//!
+//! A stack of its own, on a loopback device at 127.0.0.1, sends a UDP
+//! datagram to itself and makes a TCP connection to itself, so a stack
+//! regression shows up without QEMU's user network. The loopback device
+//! only queues what the stack transmits; the test carries each frame back
+//! to the receive queue between polls. Time is counted in polls, so the
+//! result does not depend on the timer.
+
+use super::{NetConfig, NetError, NetworkDevice, NetworkStack, QemuE1000};
+use crate::boot::cmdline;
+use alloc::vec::Vec;
+use core::fmt;
+use smoltcp::iface::SocketHandle;
+use smoltcp::time::Instant;
+use smoltcp::wire::{IpCidr, IpEndpoint, Ipv4Address};
+
+/// Address of the test stack.
+const ADDRESS: Ipv4Address = Ipv4Address([127, 0, 0, 1]);
+
+/// UDP port the test socket binds and sends to.
+const UDP_PORT: u16 = 7;
+
+/// TCP port the listener accepts on.
+const TCP_PORT: u16 = 7;
+
+/// Local port of the TCP client.
+const CLIENT_PORT: u16 = 49152;
+
+/// What is sent over each protocol.
+const PAYLOAD: &[u8] = b"SovelmaOS loopback self-test";
+
+/// Polls a step may take before it counts as stuck.
+const MAX_POLLS: usize = 64;
+
+/// Simulated time between polls.
+const POLL_INTERVAL_MS: i64 = 10;
+
+/// A self-test step that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestError {
+    /// A socket could not be opened, bound or connected.
+    Socket(&'static str, NetError),
+    /// The step did not complete within `MAX_POLLS` polls.
+    Timeout(&'static str),
+    /// What arrived is not what was sent.
+    Corrupted(&'static str),
+}
+
+impl fmt::Display for SelfTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelfTestError::Socket(step, e) => write!(f, "loopback self-test: {}: {}", step, e),
+            SelfTestError::Timeout(step) => {
+                write!(f, "loopback self-test: {} timed out", step)
+            }
+            SelfTestError::Corrupted(step) => {
+                write!(f, "loopback self-test: {} received wrong data", step)
+            }
+        }
+    }
+}
+
+/// Whether the kernel was booted to run the self-test.
+pub fn enabled() -> bool {
+    cmdline::get("selftest").is_some()
+}
+
+/// A stack on loopback with simulated time.
+struct TestStack {
+    stack: NetworkStack,
+    now: i64,
+    /// Sockets to release when the test ends.
+    sockets: Vec<SocketHandle>,
+}
+
+impl TestStack {
+    fn new() -> Self {
+        let config = NetConfig::static_ip(IpCidr::new(ADDRESS.into(), 8), None, Vec::new());
+        Self {
+            stack: NetworkStack::new(NetworkDevice::Loopback(QemuE1000::new()), config),
+            now: 0,
+            sockets: Vec::new(),
+        }
+    }
+
+    /// Keep `handle` to release at the end.
+    fn track(
+        &mut self,
+        handle: Result<SocketHandle, NetError>,
+        step: &'static str,
+    ) -> Result<SocketHandle, SelfTestError> {
+        let handle = handle.map_err(|e| SelfTestError::Socket(step, e))?;
+        self.sockets.push(handle);
+        Ok(handle)
+    }
+
+    /// Poll once, then feed what the stack transmitted back to it.
+    fn poll(&mut self) {
+        self.now += POLL_INTERVAL_MS;
+        self.stack.poll(Instant::from_millis(self.now));
+        if let NetworkDevice::Loopback(device) = self.stack.device() {
+            for frame in device.drain_tx() {
+                device.inject_rx(&frame);
+            }
+        }
+    }
+
+    /// Poll until `done` holds, for at most `MAX_POLLS` polls.
+    fn poll_until(
+        &mut self,
+        step: &'static str,
+        mut done: impl FnMut(&mut NetworkStack) -> bool,
+    ) -> Result<(), SelfTestError> {
+        for _ in 0..MAX_POLLS {
+            if done(&mut self.stack) {
+                return Ok(());
+            }
+            self.poll();
+        }
+        Err(SelfTestError::Timeout(step))
+    }
+
+    /// Send a datagram from the UDP port to itself and read it back.
+    fn udp(&mut self) -> Result<(), SelfTestError> {
+        let udp_socket = self.stack.udp_socket();
+        let socket = self.track(udp_socket, "UDP socket")?;
+        self.stack
+            .udp_bind(socket, UDP_PORT)
+            .map_err(|e| SelfTestError::Socket("UDP bind", e))?;
+        let endpoint = IpEndpoint::new(ADDRESS.into(), UDP_PORT);
+        self.stack
+            .get_udp_socket(socket)
+            .send_slice(PAYLOAD, endpoint)
+            .map_err(|_| SelfTestError::Socket("UDP send", NetError::BufferFull))?;
+
+        self.poll_until("UDP receive", |stack| {
+            stack.get_udp_socket(socket).can_recv()
+        })?;
+        let mut buffer = [0u8; PAYLOAD.len()];
+        match self.stack.get_udp_socket(socket).recv_slice(&mut buffer) {
+            Ok((len, meta)) if buffer[..len] == *PAYLOAD && meta.endpoint == endpoint => Ok(()),
+            _ => Err(SelfTestError::Corrupted("UDP receive")),
+        }
+    }
+
+    /// Connect to a listener on the TCP port and send it the payload.
+    fn tcp(&mut self) -> Result<(), SelfTestError> {
+        let listener_socket = self.stack.tcp_socket();
+        let listener = self.track(listener_socket, "TCP socket")?;
+        self.stack
+            .tcp_listen(listener, TCP_PORT)
+            .map_err(|e| SelfTestError::Socket("TCP listen", e))?;
+        let client_socket = self.stack.tcp_socket();
+        let client = self.track(client_socket, "TCP socket")?;
+        self.stack
+            .tcp_connect(
+                client,
+                IpEndpoint::new(ADDRESS.into(), TCP_PORT),
+                CLIENT_PORT,
+            )
+            .map_err(|_| SelfTestError::Socket("TCP connect", NetError::ConnectionRefused))?;
+
+        self.poll_until("TCP accept", |stack| {
+            stack.get_tcp_socket(client).may_send() && stack.get_tcp_socket(listener).may_recv()
+        })?;
+        self.stack
+            .get_tcp_socket(client)
+            .send_slice(PAYLOAD)
+            .map_err(|_| SelfTestError::Socket("TCP send", NetError::BufferFull))?;
+
+        self.poll_until("TCP receive", |stack| {
+            stack.get_tcp_socket(listener).recv_queue() >= PAYLOAD.len()
+        })?;
+        let mut buffer = [0u8; PAYLOAD.len()];
+        match self.stack.get_tcp_socket(listener).recv_slice(&mut buffer) {
+            Ok(len) if buffer[..len] == *PAYLOAD => Ok(()),
+            _ => Err(SelfTestError::Corrupted("TCP receive")),
+        }
+    }
+}
+
+impl Drop for TestStack {
+    /// Return the sockets' buffers to the pools.
+    fn drop(&mut self) {
+        for handle in core::mem::take(&mut self.sockets) {
+            self.stack.release_socket(handle);
+        }
+    }
+}
+
+/// Run the UDP and then the TCP round trip over loopback.
+pub fn run() -> Result<(), SelfTestError> {
+    let mut stack = TestStack::new();
+    stack.udp()?;
+    stack.tcp()
+}
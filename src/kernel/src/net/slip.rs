@@ -0,0 +1,274 @@
+//! SLIP (RFC 1055) network device over a serial port.
+//!
+//! Carries raw IPv4 packets over COM2 so the TCP/IP stack works in
+//! environments without an emulated NIC. COM1 stays reserved for the
+//! kernel log. Selected with `net=slip` on the kernel command line.
+//!
+//! The UART is polled rather than interrupt driven, and transmission busy-
+//! waits on the line, so throughput is bounded by the baud rate.
+
+use super::dns::parse_ipv4;
+use super::pool::PACKET_POOL;
+use super::stack::NetConfig;
+use crate::arch::x86_64::serial::{self, COM2_PORT};
+use crate::boot::cmdline;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
+use smoltcp::time::Instant;
+use smoltcp::wire::{IpCidr, Ipv4Address, Ipv4Cidr};
+use uart_16550::SerialPort;
+
+/// Frame delimiter.
+const END: u8 = 0xC0;
+/// Escape byte.
+const ESC: u8 = 0xDB;
+/// Escaped `END` (after `ESC`).
+const ESC_END: u8 = 0xDC;
+/// Escaped `ESC` (after `ESC`).
+const ESC_ESC: u8 = 0xDD;
+
+/// Traditional SLIP MTU.
+pub const MTU: usize = 1006;
+
+/// Received frames buffered between stack polls.
+const QUEUE_CAPACITY: usize = 8;
+
+/// Local address used when `ip=` is not given.
+const DEFAULT_ADDRESS: Ipv4Address = Ipv4Address::new(10, 0, 3, 2);
+
+/// Prefix length used when `ip=` has none.
+const DEFAULT_PREFIX_LEN: u8 = 24;
+
+/// Peer (host) address used as gateway when `gw=` is not given.
+const DEFAULT_GATEWAY: Ipv4Address = Ipv4Address::new(10, 0, 3, 1);
+
+/// Build the static address configuration for a SLIP link.
+///
+/// SLIP has no DHCP, so the address comes from the command line:
+/// `ip=<addr>[/<prefix>]`, `gw=<addr>` and `dns=<addr>[,<addr>...]`.
+/// Unparseable values fall back to 10.0.3.2/24 via 10.0.3.1.
+pub fn cmdline_config() -> NetConfig {
+    let (address, prefix_len) = cmdline::get("ip")
+        .and_then(|value| {
+            let (addr, prefix) = value.split_once('/').unwrap_or((value, ""));
+            let prefix = if prefix.is_empty() {
+                DEFAULT_PREFIX_LEN
+            } else {
+                prefix.parse().ok().filter(|p| *p <= 32)?
+            };
+            Some((parse_ipv4(addr)?, prefix))
+        })
+        .unwrap_or((DEFAULT_ADDRESS, DEFAULT_PREFIX_LEN));
+    let gateway = cmdline::get("gw")
+        .and_then(parse_ipv4)
+        .unwrap_or(DEFAULT_GATEWAY);
+    let dns = cmdline::get("dns")
+        .map(|list| list.split(',').filter_map(parse_ipv4).collect())
+        .unwrap_or_default();
+
+    NetConfig::static_ip(
+        IpCidr::Ipv4(Ipv4Cidr::new(address, prefix_len)),
+        Some(gateway),
+        dns,
+    )
+}
+
+/// Incremental SLIP frame decoder.
+pub struct SlipDecoder {
+    frame: Vec<u8>,
+    escaped: bool,
+    overflow: bool,
+}
+
+impl SlipDecoder {
+    /// Create a decoder waiting for the first frame.
+    pub fn new() -> Self {
+        Self {
+            frame: Vec::new(),
+            escaped: false,
+            overflow: false,
+        }
+    }
+
+    /// Feed one byte from the line.
+    ///
+    /// Returns a complete packet when `byte` ends a frame. Empty frames
+    /// and frames longer than `MTU` are dropped.
+    pub fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        match byte {
+            END => {
+                self.escaped = false;
+                if core::mem::take(&mut self.overflow) {
+                    self.frame.clear();
+                    return None;
+                }
+                if self.frame.is_empty() {
+                    return None;
+                }
+                Some(core::mem::take(&mut self.frame))
+            }
+            ESC => {
+                self.escaped = true;
+                None
+            }
+            _ => {
+                let byte = if core::mem::take(&mut self.escaped) {
+                    match byte {
+                        ESC_END => END,
+                        ESC_ESC => ESC,
+                        // Protocol violation; RFC 1055 keeps the byte as is
+                        other => other,
+                    }
+                } else {
+                    byte
+                };
+                if self.frame.len() >= MTU {
+                    self.overflow = true;
+                } else {
+                    self.frame.push(byte);
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Default for SlipDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Append `packet` to `out` as a SLIP frame.
+///
+/// The frame starts with `END` as well, flushing any line noise the peer
+/// may have collected.
+pub fn encode(packet: &[u8], out: &mut Vec<u8>) {
+    out.push(END);
+    for &byte in packet {
+        match byte {
+            END => out.extend_from_slice(&[ESC, ESC_END]),
+            ESC => out.extend_from_slice(&[ESC, ESC_ESC]),
+            _ => out.push(byte),
+        }
+    }
+    out.push(END);
+}
+
+/// SLIP link on COM2.
+pub struct SlipDevice {
+    port: SerialPort,
+    decoder: SlipDecoder,
+    rx_queue: VecDeque<Vec<u8>>,
+}
+
+impl SlipDevice {
+    /// Initialize COM2 (8N1, 38400 baud unless `serial=2:<baud>`) for SLIP.
+    pub fn new() -> Self {
+        // SAFETY: COM2_PORT (0x2F8) is the standard second serial port; the
+        // GDB stub, its only other user, is disabled when SLIP is selected.
+        let port = unsafe { serial::init_polled(COM2_PORT) };
+        if let Some(baud) = serial::configured_baud(2) {
+            // SAFETY: As above, COM2 belongs to this device.
+            if let Err(e) = unsafe { serial::set_baud(COM2_PORT, baud) } {
+                log::warn!(target: "slip", "{}", e);
+            }
+        }
+
+        Self {
+            port,
+            decoder: SlipDecoder::new(),
+            rx_queue: VecDeque::with_capacity(QUEUE_CAPACITY),
+        }
+    }
+
+    /// Drain the UART, queueing completed frames.
+    fn pump(&mut self) {
+        while let Ok(byte) = self.port.try_receive() {
+            if let Some(frame) = self.decoder.push(byte) {
+                if self.rx_queue.len() < QUEUE_CAPACITY {
+                    self.rx_queue.push_back(frame);
+                }
+            }
+        }
+    }
+}
+
+impl Default for SlipDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Receive token for SlipDevice.
+pub struct SlipRxToken {
+    buffer: Vec<u8>,
+}
+
+impl RxToken for SlipRxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.buffer)
+    }
+}
+
+/// Transmit token for SlipDevice.
+pub struct SlipTxToken<'a> {
+    port: &'a mut SerialPort,
+}
+
+impl<'a> TxToken for SlipTxToken<'a> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut buffer = PACKET_POOL.packet(len);
+        let result = f(&mut buffer);
+
+        let mut frame = Vec::with_capacity(len + len / 8 + 2);
+        encode(&buffer, &mut frame);
+        for byte in frame {
+            self.port.send_raw(byte);
+        }
+        result
+    }
+}
+
+impl Device for SlipDevice {
+    type RxToken<'a>
+        = SlipRxToken
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = SlipTxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        self.pump();
+        let buffer = self.rx_queue.pop_front()?;
+        Some((
+            SlipRxToken { buffer },
+            SlipTxToken {
+                port: &mut self.port,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        Some(SlipTxToken {
+            port: &mut self.port,
+        })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ip;
+        caps.max_transmission_unit = MTU;
+        caps.max_burst_size = Some(1);
+        caps
+    }
+}
@@ -4,8 +4,10 @@
 
 use super::stack::NetworkStack;
 use super::NetError;
+use alloc::vec::Vec;
 use smoltcp::iface::SocketHandle;
 use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+use sovelma_common::error::Error;
 
 /// High-level TCP socket wrapper.
 pub struct TcpSocket {
@@ -15,12 +17,14 @@ pub struct TcpSocket {
 
 impl TcpSocket {
     /// Create a new TCP socket.
-    pub fn new(stack: &mut NetworkStack) -> Self {
-        let handle = stack.tcp_socket();
-        Self {
+    ///
+    /// Fails with `OutOfMemory` if the stack has no room for it.
+    pub fn new(stack: &mut NetworkStack) -> Result<Self, NetError> {
+        let handle = stack.tcp_socket()?;
+        Ok(Self {
             handle,
             local_port: 0,
-        }
+        })
     }
 
     /// Get the socket handle.
@@ -28,13 +32,13 @@ impl TcpSocket {
         self.handle
     }
 
-    /// Connect to a remote endpoint.
+    /// Connect to a remote endpoint. The error names the endpoint.
     pub fn connect(
         &mut self,
         stack: &mut NetworkStack,
         addr: Ipv4Address,
         port: u16,
-    ) -> Result<(), NetError> {
+    ) -> Result<(), Error<NetError>> {
         // Use ephemeral port for local binding
         self.local_port = ephemeral_port();
         let remote = IpEndpoint::new(IpAddress::Ipv4(addr), port);
@@ -68,7 +72,9 @@ impl TcpSocket {
     /// Send data through the socket.
     pub fn send(&self, stack: &mut NetworkStack, data: &[u8]) -> Result<usize, NetError> {
         let socket = stack.get_tcp_socket(self.handle);
-        socket.send_slice(data).map_err(|_| NetError::BufferFull)
+        let sent = socket.send_slice(data).map_err(|_| NetError::BufferFull)?;
+        super::poller::wake();
+        Ok(sent)
     }
 
     /// Receive data from the socket.
@@ -96,12 +102,14 @@ pub struct UdpSocket {
 
 impl UdpSocket {
     /// Create a new UDP socket.
-    pub fn new(stack: &mut NetworkStack) -> Self {
-        let handle = stack.udp_socket();
-        Self {
+    ///
+    /// Fails with `OutOfMemory` if the stack has no room for it.
+    pub fn new(stack: &mut NetworkStack) -> Result<Self, NetError> {
+        let handle = stack.udp_socket()?;
+        Ok(Self {
             handle,
             local_port: 0,
-        }
+        })
     }
 
     /// Get the socket handle.
@@ -125,7 +133,9 @@ impl UdpSocket {
         let socket = stack.get_udp_socket(self.handle);
         socket
             .send_slice(data, remote)
-            .map_err(|_| NetError::BufferFull)
+            .map_err(|_| NetError::BufferFull)?;
+        super::poller::wake();
+        Ok(())
     }
 
     /// Receive a datagram and get the sender's endpoint.
@@ -159,11 +169,76 @@ impl UdpSocket {
     }
 }
 
+/// A listening TCP port with a backlog of armed sockets.
+///
+/// smoltcp sockets turn into the connection when a peer connects, so the
+/// listener keeps `backlog` sockets in the Listen state and replaces each
+/// one as it is accepted.
+pub struct TcpListener {
+    port: u16,
+    sockets: Vec<SocketHandle>,
+}
+
+impl TcpListener {
+    /// Start listening on `port` with room for `backlog` simultaneous handshakes.
+    pub fn bind(stack: &mut NetworkStack, port: u16, backlog: usize) -> Result<Self, NetError> {
+        let mut listener = Self {
+            port,
+            sockets: Vec::with_capacity(backlog),
+        };
+        for _ in 0..backlog.max(1) {
+            if let Err(e) = listener.arm(stack) {
+                listener.close(stack);
+                return Err(e);
+            }
+        }
+        Ok(listener)
+    }
+
+    /// Get the listening port.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Take an established connection, if one is waiting.
+    ///
+    /// Never blocks. The caller owns the returned socket and must release
+    /// it with `NetworkStack::release_socket` once it is done.
+    pub fn accept(&mut self, stack: &mut NetworkStack) -> Option<SocketHandle> {
+        let pos = self
+            .sockets
+            .iter()
+            .position(|h| stack.get_tcp_socket(*h).may_send())?;
+        let handle = self.sockets.swap_remove(pos);
+        // If re-arming fails the backlog shrinks by one; the connection is still valid
+        let _ = self.arm(stack);
+        Some(handle)
+    }
+
+    /// Stop listening and release the backlog sockets.
+    pub fn close(self, stack: &mut NetworkStack) {
+        for handle in self.sockets {
+            stack.release_socket(handle);
+        }
+    }
+
+    /// Add one listening socket to the backlog.
+    fn arm(&mut self, stack: &mut NetworkStack) -> Result<(), NetError> {
+        let handle = stack.tcp_socket()?;
+        if let Err(e) = stack.tcp_listen(handle, self.port) {
+            stack.release_socket(handle);
+            return Err(e);
+        }
+        self.sockets.push(handle);
+        Ok(())
+    }
+}
+
 /// Counter for generating ephemeral ports.
 static EPHEMERAL_PORT_COUNTER: spin::Mutex<u16> = spin::Mutex::new(49152);
 
 /// Get the next ephemeral port number (49152-65535).
-fn ephemeral_port() -> u16 {
+pub(crate) fn ephemeral_port() -> u16 {
     let mut counter = EPHEMERAL_PORT_COUNTER.lock();
     let port = *counter;
     *counter = if *counter == 65535 {
@@ -1,24 +1,43 @@
 //! Network stack wrapper around smoltcp.
 //!
 //! Provides a high-level interface for TCP/IP networking.
+//!
+//! Socket creation fails with `NetError::OutOfMemory` when the socket
+//! table is full or the buffer pools cannot get memory for the socket's
+//! buffers, instead of aborting on a failed allocation.
+//!
+//! Sockets belong to whoever created them and stay in the table until
+//! released. Fire-and-forget users (the `ping` and `connect` commands)
+//! `detach` theirs instead, and `collect_garbage`, run by the poll task,
+//! removes a detached socket once it is finished: a TCP socket when it is
+//! fully closed, a UDP socket when it is unbound, an ICMP socket after
+//! `DETACHED_ICMP_LINGER`.
+//!
+//! TCP sockets can be watched for state changes (see `connection`).
 
+use super::connection::{Connection, ConnectionEvent, ConnectionSink};
+use super::pool::{BufferPool, Lease, PACKET_POOL, SOCKET_POOL};
 use super::{NetError, NetworkDevice};
 use alloc::vec::Vec;
 use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
+use smoltcp::phy::{Device, TxToken};
+use smoltcp::socket::dns;
+use smoltcp::socket::Socket;
 use smoltcp::socket::tcp;
 use smoltcp::socket::udp;
 use smoltcp::socket::icmp;
-use smoltcp::time::Instant;
-use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{
+    EthernetAddress, HardwareAddress, IpAddress, IpCidr, IpEndpoint, IpListenEndpoint, Ipv4Address,
+};
+use sovelma_common::error::{Context, Error};
 
-/// Maximum number of sockets in the socket set.
-const MAX_SOCKETS: usize = 16;
+/// Maximum number of sockets in the socket set. Its storage is allocated
+/// up front, so adding a socket never allocates.
+pub const MAX_SOCKETS: usize = 32;
 
-/// TCP socket receive buffer size.
-const TCP_RX_BUFFER_SIZE: usize = 4096;
-
-/// TCP socket transmit buffer size.
-const TCP_TX_BUFFER_SIZE: usize = 4096;
+/// How long a detached ICMP socket is kept for replies to arrive.
+pub const DETACHED_ICMP_LINGER: Duration = Duration::from_secs(10);
 
 /// UDP socket receive buffer metadata slots.
 const UDP_RX_META_SIZE: usize = 8;
@@ -26,8 +45,11 @@ const UDP_RX_META_SIZE: usize = 8;
 /// UDP socket transmit metadata slots.
 const UDP_TX_META_SIZE: usize = 8;
 
-/// UDP socket buffer size.
-const UDP_BUFFER_SIZE: usize = 2048;
+/// ICMP socket metadata slots, per direction.
+const ICMP_META_SIZE: usize = 4;
+
+/// ICMP socket payload buffer size, per direction.
+const ICMP_BUFFER_SIZE: usize = 1024;
 
 /// Network configuration options.
 #[derive(Clone)]
@@ -61,6 +83,15 @@ impl NetConfig {
     }
 }
 
+/// A change in the device's link state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The link came up.
+    Up,
+    /// The link went down.
+    Down,
+}
+
 /// Network stack managing smoltcp interface and sockets.
 pub struct NetworkStack {
     device: NetworkDevice,
@@ -69,20 +100,32 @@ pub struct NetworkStack {
     config: NetConfig,
     /// DNS server addresses for resolver.
     pub dns_servers: Vec<Ipv4Address>,
+    /// ICMP sockets owned by a subsystem (e.g. traceroute) that
+    /// `check_icmp` must leave alone.
+    reserved_icmp: Vec<SocketHandle>,
+    /// Pool blocks backing each socket's buffers.
+    leases: Vec<(SocketHandle, Lease)>,
+    /// Sockets handed over to the stack, with when they were detached.
+    detached: Vec<(SocketHandle, Instant)>,
+    /// TCP sockets whose state changes are reported.
+    connections: Vec<Connection>,
+    /// Link state at the last `poll_link`.
+    link_up: bool,
 }
 
 impl NetworkStack {
     /// Create a new network stack with the given device and configuration.
-    pub fn new(device: NetworkDevice, config: NetConfig) -> Self {
-        let mac = device.mac_address();
-        let hardware_addr = HardwareAddress::Ethernet(EthernetAddress(mac));
-
-        let iface_config = Config::new(hardware_addr);
+    pub fn new(mut device: NetworkDevice, config: NetConfig) -> Self {
+        // Point-to-point links (SLIP) carry bare IP packets
+        let hardware_addr = match device.mac_address() {
+            Some(mac) => HardwareAddress::Ethernet(EthernetAddress(mac)),
+            None => HardwareAddress::Ip,
+        };
 
-        // Create a dummy device for interface initialization
-        // The real device will be used during poll()
-        let mut dummy = super::QemuE1000::new();
-        let interface = Interface::new(iface_config, &mut dummy, Instant::from_millis(0));
+        let mut iface_config = Config::new(hardware_addr);
+        // Seeds DHCP transaction IDs, DNS query IDs and TCP initial sequence numbers
+        iface_config.random_seed = crate::arch::x86_64::random_seed();
+        let interface = Interface::new(iface_config, &mut device, Instant::from_millis(0));
 
         // Pre-allocate socket storage
         let sockets = SocketSet::new(Vec::with_capacity(MAX_SOCKETS));
@@ -92,12 +135,18 @@ impl NetworkStack {
             NetConfig::Static { dns_servers, .. } => dns_servers.clone(),
         };
 
+        let link_up = device.link_up();
         let mut stack = Self {
             device,
             interface,
             sockets,
             config,
             dns_servers,
+            reserved_icmp: Vec::new(),
+            leases: Vec::new(),
+            detached: Vec::new(),
+            connections: Vec::new(),
+            link_up,
         };
 
         // Apply static configuration if provided
@@ -125,6 +174,39 @@ impl NetworkStack {
             .poll(timestamp, &mut self.device, &mut self.sockets);
     }
 
+    /// Check the device's link, returning the change since the last check.
+    pub fn poll_link(&mut self) -> Option<LinkEvent> {
+        let up = self.device.link_up();
+        if up == self.link_up {
+            return None;
+        }
+        self.link_up = up;
+        Some(if up { LinkEvent::Up } else { LinkEvent::Down })
+    }
+
+    /// Link state as of the last `poll_link`.
+    pub fn link_up(&self) -> bool {
+        self.link_up
+    }
+
+    /// Time until the stack next needs polling, or `None` if only incoming
+    /// packets can give it work. Zero means it should be polled now.
+    pub fn poll_delay(&mut self, timestamp: Instant) -> Option<Duration> {
+        self.interface.poll_delay(timestamp, &self.sockets)
+    }
+
+    /// Transmit a complete link-layer frame, bypassing smoltcp. Returns
+    /// `false` if the device had no free transmit slot.
+    pub fn send_raw(&mut self, frame: &[u8], timestamp: Instant) -> bool {
+        match self.device.transmit(timestamp) {
+            Some(token) => {
+                token.consume(frame.len(), |buffer| buffer.copy_from_slice(frame));
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Get the current IP address, if configured.
     pub fn ip_address(&self) -> Option<IpAddress> {
         self.interface.ip_addrs().first().map(|cidr| cidr.address())
@@ -159,40 +241,170 @@ impl NetworkStack {
         self.dns_servers = servers;
     }
 
+    /// Number of sockets in the socket set.
+    pub fn socket_count(&self) -> usize {
+        self.sockets.iter().count()
+    }
+
+    /// Fail with `OutOfMemory` if the socket set is full.
+    fn check_socket_slot(&self) -> Result<(), NetError> {
+        if self.socket_count() >= MAX_SOCKETS {
+            return Err(NetError::OutOfMemory);
+        }
+        Ok(())
+    }
+
     /// Create a new TCP socket and return its handle.
-    pub fn tcp_socket(&mut self) -> SocketHandle {
-        let rx_buffer = tcp::SocketBuffer::new(alloc::vec![0; TCP_RX_BUFFER_SIZE]);
-        let tx_buffer = tcp::SocketBuffer::new(alloc::vec![0; TCP_TX_BUFFER_SIZE]);
+    ///
+    /// Both buffers are 4 KiB blocks from the socket pool.
+    pub fn tcp_socket(&mut self) -> Result<SocketHandle, NetError> {
+        self.check_socket_slot()?;
+        let ((rx_storage, rx_lease), (tx_storage, tx_lease)) =
+            lease_pair(&SOCKET_POOL, SOCKET_POOL.block_size())?;
+        let rx_buffer = tcp::SocketBuffer::new(rx_storage);
+        let tx_buffer = tcp::SocketBuffer::new(tx_storage);
         let socket = tcp::Socket::new(rx_buffer, tx_buffer);
-        self.sockets.add(socket)
+        let handle = self.sockets.add(socket);
+        self.leases.push((handle, rx_lease));
+        self.leases.push((handle, tx_lease));
+        Ok(handle)
     }
 
     /// Create a new UDP socket and return its handle.
-    pub fn udp_socket(&mut self) -> SocketHandle {
+    ///
+    /// Payload storage is a 2 KiB block from the packet pool per direction.
+    pub fn udp_socket(&mut self) -> Result<SocketHandle, NetError> {
+        self.check_socket_slot()?;
+        let ((rx_storage, rx_lease), (tx_storage, tx_lease)) =
+            lease_pair(&PACKET_POOL, PACKET_POOL.block_size())?;
         let rx_buffer = udp::PacketBuffer::new(
             alloc::vec![udp::PacketMetadata::EMPTY; UDP_RX_META_SIZE],
-            alloc::vec![0; UDP_BUFFER_SIZE],
+            rx_storage,
         );
         let tx_buffer = udp::PacketBuffer::new(
             alloc::vec![udp::PacketMetadata::EMPTY; UDP_TX_META_SIZE],
-            alloc::vec![0; UDP_BUFFER_SIZE],
+            tx_storage,
         );
         let socket = udp::Socket::new(rx_buffer, tx_buffer);
-        self.sockets.add(socket)
+        let handle = self.sockets.add(socket);
+        self.leases.push((handle, rx_lease));
+        self.leases.push((handle, tx_lease));
+        Ok(handle)
     }
 
     /// Create a new ICMP socket and return its handle.
-    pub fn icmp_socket(&mut self) -> SocketHandle {
+    ///
+    /// Payload storage comes from the packet pool, like UDP.
+    pub fn icmp_socket(&mut self) -> Result<SocketHandle, NetError> {
+        self.check_socket_slot()?;
+        let ((rx_storage, rx_lease), (tx_storage, tx_lease)) =
+            lease_pair(&PACKET_POOL, ICMP_BUFFER_SIZE)?;
         let rx_buffer = icmp::PacketBuffer::new(
-            alloc::vec![icmp::PacketMetadata::EMPTY; 4],
-            alloc::vec![0; 1024],
+            alloc::vec![icmp::PacketMetadata::EMPTY; ICMP_META_SIZE],
+            rx_storage,
         );
         let tx_buffer = icmp::PacketBuffer::new(
-            alloc::vec![icmp::PacketMetadata::EMPTY; 4],
-            alloc::vec![0; 1024],
+            alloc::vec![icmp::PacketMetadata::EMPTY; ICMP_META_SIZE],
+            tx_storage,
         );
         let socket = icmp::Socket::new(rx_buffer, tx_buffer);
-        self.sockets.add(socket)
+        let handle = self.sockets.add(socket);
+        self.leases.push((handle, rx_lease));
+        self.leases.push((handle, tx_lease));
+        Ok(handle)
+    }
+
+    /// Create a DNS socket querying `servers` and return its handle.
+    ///
+    /// Query slots grow on the heap as queries are started.
+    pub fn dns_socket(&mut self, servers: &[IpAddress]) -> Result<SocketHandle, NetError> {
+        self.check_socket_slot()?;
+        Ok(self.sockets.add(dns::Socket::new(servers, Vec::new())))
+    }
+
+    /// Remove a socket and return its buffer blocks to their pools.
+    fn remove_socket(&mut self, handle: SocketHandle) {
+        drop(self.sockets.remove(handle));
+        let mut i = 0;
+        while i < self.leases.len() {
+            if self.leases[i].0 == handle {
+                let (_, lease) = self.leases.swap_remove(i);
+                // SAFETY: The socket that borrowed the block was dropped above.
+                unsafe { BufferPool::reclaim(lease) };
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Create an ICMP socket that receives errors for a local UDP port.
+    ///
+    /// The socket accepts ICMP Destination Unreachable and Time Exceeded
+    /// messages whose embedded UDP header originates from `port`. It is
+    /// excluded from `check_icmp` until released with `release_socket`.
+    pub fn icmp_error_socket(&mut self, port: u16) -> Result<SocketHandle, NetError> {
+        let handle = self.icmp_socket()?;
+        let endpoint = icmp::Endpoint::Udp(IpListenEndpoint { addr: None, port });
+        if self
+            .sockets
+            .get_mut::<icmp::Socket>(handle)
+            .bind(endpoint)
+            .is_err()
+        {
+            self.remove_socket(handle);
+            return Err(NetError::IoError);
+        }
+        self.reserved_icmp.push(handle);
+        Ok(handle)
+    }
+
+    /// Remove a socket from the socket set.
+    pub fn release_socket(&mut self, handle: SocketHandle) {
+        self.reserved_icmp.retain(|h| *h != handle);
+        self.detached.retain(|(h, _)| *h != handle);
+        self.connections
+            .retain(|connection| connection.handle() != handle);
+        self.remove_socket(handle);
+    }
+
+    /// Hand a socket over to the stack, which removes it once it is
+    /// finished (see `collect_garbage`). The caller must not use the
+    /// handle again.
+    pub fn detach(&mut self, handle: SocketHandle, timestamp: Instant) {
+        if !self.detached.iter().any(|(h, _)| *h == handle) {
+            self.detached.push((handle, timestamp));
+        }
+    }
+
+    /// Number of detached sockets not yet removed.
+    pub fn detached_count(&self) -> usize {
+        self.detached.len()
+    }
+
+    /// Remove the detached sockets that are finished, returning how many
+    /// were removed. Sockets still owned are never touched.
+    pub fn collect_garbage(&mut self, timestamp: Instant) -> usize {
+        if self.detached.is_empty() {
+            return 0;
+        }
+        let finished: Vec<SocketHandle> = self
+            .sockets
+            .iter()
+            .filter_map(|(handle, socket)| {
+                let (_, since) = self.detached.iter().find(|(h, _)| *h == handle)?;
+                let done = match socket {
+                    Socket::Tcp(tcp) => tcp.state() == tcp::State::Closed,
+                    Socket::Udp(udp) => !udp.is_open(),
+                    Socket::Icmp(_) => *since + DETACHED_ICMP_LINGER <= timestamp,
+                    _ => false,
+                };
+                done.then_some(handle)
+            })
+            .collect();
+        for handle in &finished {
+            self.release_socket(*handle);
+        }
+        finished.len()
     }
 
     /// Get a TCP socket by handle.
@@ -205,18 +417,22 @@ impl NetworkStack {
         self.sockets.get_mut::<udp::Socket>(handle)
     }
 
-    /// Connect a TCP socket to a remote endpoint.
+    /// Connect a TCP socket to a remote endpoint. The error names the
+    /// endpoint.
     pub fn tcp_connect(
         &mut self,
         handle: SocketHandle,
         remote: IpEndpoint,
         local_port: u16,
-    ) -> Result<(), NetError> {
+    ) -> Result<(), Error<NetError>> {
         let socket = self.sockets.get_mut::<tcp::Socket>(handle);
         let cx = self.interface.context();
         socket
             .connect(cx, remote, local_port)
             .map_err(|_| NetError::ConnectionRefused)
+            .context("connecting to", remote)?;
+        super::poller::wake();
+        Ok(())
     }
 
     /// Bind a TCP socket to listen on a local port.
@@ -225,10 +441,52 @@ impl NetworkStack {
         socket.listen(port).map_err(|_| NetError::IoError)
     }
 
+    /// Report the state changes of the TCP socket `handle` to `sink`
+    /// (see `poll_connections`).
+    pub fn watch_connection(
+        &mut self,
+        handle: SocketHandle,
+        sink: ConnectionSink,
+        timestamp: Instant,
+    ) {
+        self.connections
+            .push(Connection::new(handle, sink, timestamp));
+    }
+
+    /// Turn watched TCP sockets' state changes into events, posting them
+    /// to their sinks. A socket that got no answer within
+    /// `connection::CONNECT_TIMEOUT` is aborted.
+    ///
+    /// Returns the events for kernel watchers, with the peer.
+    pub fn poll_connections(
+        &mut self,
+        timestamp: Instant,
+    ) -> Vec<(Option<IpEndpoint>, ConnectionEvent)> {
+        let mut kernel = Vec::new();
+        let sockets = &mut self.sockets;
+        self.connections.retain_mut(|connection| {
+            let socket = sockets.get_mut::<tcp::Socket>(connection.handle());
+            let Some(event) =
+                connection.update(socket.state(), socket.remote_endpoint(), timestamp)
+            else {
+                return true;
+            };
+            if event == ConnectionEvent::TimedOut {
+                socket.abort();
+            }
+            if let Some(event) = connection.deliver(event) {
+                kernel.push((connection.remote(), event));
+            }
+            !event.is_final()
+        });
+        kernel
+    }
+
     /// Close a TCP socket.
     pub fn tcp_close(&mut self, handle: SocketHandle) {
         let socket = self.sockets.get_mut::<tcp::Socket>(handle);
         socket.close();
+        super::poller::wake();
     }
 
     /// Bind a UDP socket to a local port.
@@ -279,7 +537,10 @@ impl NetworkStack {
     /// Check for received ICMP packets and print replies.
     pub fn check_icmp(&mut self) {
         let mut buffer = [0u8; 1024];
-        for (_handle, socket) in self.sockets.iter_mut() {
+        for (handle, socket) in self.sockets.iter_mut() {
+            if self.reserved_icmp.contains(&handle) {
+                continue;
+            }
             if let smoltcp::socket::Socket::Icmp(socket) = socket {
                 if socket.can_recv() {
                     match socket.recv_slice(&mut buffer) {
@@ -298,3 +559,40 @@ impl NetworkStack {
         }
     }
 }
+
+impl Drop for NetworkStack {
+    fn drop(&mut self) {
+        // Drop the sockets first so their pool blocks can be reclaimed
+        let sockets = core::mem::replace(&mut self.sockets, SocketSet::new(Vec::new()));
+        drop(sockets);
+        for (_, lease) in self.leases.drain(..) {
+            // SAFETY: Every socket, and with it every borrow of a leased
+            // block, was dropped with the old socket set.
+            unsafe { BufferPool::reclaim(lease) };
+        }
+    }
+}
+
+/// A leased block, cut to the length used.
+type Leased = (&'static mut [u8], Lease);
+
+/// Lease a block from `pool` and use only its first `len` bytes.
+fn lease_prefix(pool: &'static BufferPool, len: usize) -> Option<Leased> {
+    let (block, lease) = pool.lease()?;
+    let len = len.min(block.len());
+    Some((&mut block[..len], lease))
+}
+
+/// Lease one `len`-byte buffer per direction of a socket, or neither.
+fn lease_pair(pool: &'static BufferPool, len: usize) -> Result<(Leased, Leased), NetError> {
+    let (rx_storage, rx_lease) = lease_prefix(pool, len).ok_or(NetError::OutOfMemory)?;
+    match lease_prefix(pool, len) {
+        Some(tx) => Ok(((rx_storage, rx_lease), tx)),
+        None => {
+            // SAFETY: The block was not handed to a socket and `rx_storage`
+            // is not used again.
+            unsafe { BufferPool::reclaim(rx_lease) };
+            Err(NetError::OutOfMemory)
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Remote syslog sink (RFC 5424 over UDP, RFC 5426).
+//!
+//! Ships kernel log records queued by `klog` to a collector on the dev
+//! host, e.g. `nc -ulk 514` or rsyslog with a UDP input. Records stay
+//! queued while the interface has no address or the socket cannot take
+//! them, and sending is retried once per `RETRY_INTERVAL`.
+
+use super::socket::{ephemeral_port, UdpSocket};
+use super::stack::NetworkStack;
+use super::NetError;
+use crate::klog::{self, LogRecord};
+use crate::time::WallTime;
+use alloc::string::String;
+use log::Level;
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+
+/// Well-known syslog port.
+pub const SYSLOG_PORT: u16 = 514;
+
+/// Delay before retrying after the link or socket refused a record.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Records sent per poll, so a backlog does not starve other tasks.
+const MAX_RECORDS_PER_POLL: usize = 8;
+
+/// Syslog facility for kernel messages.
+const FACILITY_KERN: u8 = 0;
+
+/// HOSTNAME field of outgoing messages.
+const HOSTNAME: &str = "sovelma";
+
+/// APP-NAME field of outgoing messages.
+const APP_NAME: &str = "kernel";
+
+/// Snapshot of the sink for `log` status output.
+#[derive(Debug, Clone)]
+pub struct SyslogStatus {
+    /// Collector endpoint.
+    pub remote: IpEndpoint,
+    /// Records waiting to be sent.
+    pub queued: usize,
+    /// Records sent so far.
+    pub sent: u64,
+    /// Records dropped because the queue was full.
+    pub dropped: u64,
+}
+
+struct Remote {
+    endpoint: IpEndpoint,
+    socket: UdpSocket,
+    sent: u64,
+    retry_at: Option<Instant>,
+}
+
+/// Syslog client, idle until `start` is called.
+pub struct Syslog {
+    remote: Option<Remote>,
+}
+
+impl Syslog {
+    /// Create an idle client.
+    pub fn new() -> Self {
+        Self { remote: None }
+    }
+
+    /// Start shipping log records to `server`.
+    ///
+    /// Replaces any previous collector; records already queued go to the
+    /// new one.
+    pub fn start(&mut self, stack: &mut NetworkStack, server: Ipv4Address) -> Result<(), NetError> {
+        self.close(stack);
+
+        let mut socket = UdpSocket::new(stack)?;
+        if let Err(e) = socket.bind(stack, ephemeral_port()) {
+            stack.release_socket(socket.handle());
+            return Err(e);
+        }
+        self.remote = Some(Remote {
+            endpoint: IpEndpoint::new(IpAddress::Ipv4(server), SYSLOG_PORT),
+            socket,
+            sent: 0,
+            retry_at: None,
+        });
+        klog::set_remote(true);
+        Ok(())
+    }
+
+    /// Stop remote logging. Returns `false` if it was not running.
+    pub fn stop(&mut self, stack: &mut NetworkStack) -> bool {
+        klog::set_remote(false);
+        self.close(stack)
+    }
+
+    fn close(&mut self, stack: &mut NetworkStack) -> bool {
+        match self.remote.take() {
+            Some(remote) => {
+                stack.release_socket(remote.socket.handle());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Current collector and counters, if remote logging is on.
+    pub fn status(&self) -> Option<SyslogStatus> {
+        let remote = self.remote.as_ref()?;
+        Some(SyslogStatus {
+            remote: remote.endpoint,
+            queued: klog::with_remote_queue(|queue| queue.len()),
+            sent: remote.sent,
+            dropped: klog::dropped(),
+        })
+    }
+
+    /// Send queued records to the collector.
+    pub fn poll(&mut self, stack: &mut NetworkStack, timestamp: Instant) {
+        let Some(remote) = self.remote.as_mut() else {
+            return;
+        };
+        if remote.retry_at.is_some_and(|at| timestamp < at) {
+            return;
+        }
+        remote.retry_at = None;
+        if !stack.has_ip() {
+            remote.retry_at = Some(timestamp + RETRY_INTERVAL);
+            return;
+        }
+
+        for _ in 0..MAX_RECORDS_PER_POLL {
+            let Some(record) = klog::with_remote_queue(|queue| queue.front().cloned()) else {
+                return;
+            };
+            let message = format_message(&record);
+            if remote
+                .socket
+                .send_to(stack, message.as_bytes(), remote.endpoint)
+                .is_err()
+            {
+                remote.retry_at = Some(timestamp + RETRY_INTERVAL);
+                return;
+            }
+            klog::with_remote_queue(|queue| queue.pop_front());
+            remote.sent += 1;
+        }
+    }
+}
+
+impl Default for Syslog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// RFC 5424 severity for a log level.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Render a record as an RFC 5424 message.
+///
+/// TIMESTAMP is the record's wall-clock time; while the wall clock is not
+/// set it is the nil value and the collector stamps the message on arrival.
+pub fn format_message(record: &LogRecord) -> String {
+    let priority = FACILITY_KERN * 8 + severity(record.level);
+    let timestamp = match record.wall_ns {
+        Some(ns) => alloc::format!("{}", WallTime(ns)),
+        None => String::from("-"),
+    };
+    alloc::format!(
+        "<{}>1 {} {} {} - {} - {}",
+        priority,
+        timestamp,
+        HOSTNAME,
+        APP_NAME,
+        record.target,
+        record.message
+    )
+}
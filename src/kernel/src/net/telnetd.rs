@@ -0,0 +1,384 @@
+//! Telnet server for remote shell access.
+//!
+//! Listens on a TCP port and attaches one connected peer at a time to a
+//! shell session. There is no login, so the server only starts when the
+//! kernel is booted with `telnet` on the command line. The session task feeds decoded keys into its own
+//! `Terminal` and attaches a `SessionOutput` sink so command output is
+//! sent back over the connection as ANSI text.
+
+use super::stack::NetworkStack;
+use super::NetError;
+use crate::arch::x86_64::vga::Color;
+use crate::boot::cmdline;
+use crate::terminal::io::{ansi_foreground, TerminalIo};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use pc_keyboard::{DecodedKey, KeyCode};
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::tcp;
+
+/// Default listening port (unprivileged, easy to forward from the QEMU host).
+pub const DEFAULT_PORT: u16 = 2323;
+
+/// Maximum output buffered while the peer is not reading.
+const MAX_PENDING_OUTPUT: usize = 8192;
+
+/// Telnet "interpret as command" escape.
+const IAC: u8 = 255;
+/// Telnet WILL option code.
+const WILL: u8 = 251;
+/// Telnet DONT option code.
+const DONT: u8 = 254;
+/// Telnet subnegotiation begin.
+const SB: u8 = 250;
+/// Telnet subnegotiation end.
+const SE: u8 = 240;
+/// Telnet ECHO option.
+const OPT_ECHO: u8 = 1;
+/// Telnet SUPPRESS-GO-AHEAD option.
+const OPT_SGA: u8 = 3;
+
+/// Negotiation sent on connect: the server echoes input and runs in
+/// character-at-a-time mode.
+const NEGOTIATION: [u8; 6] = [IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SGA];
+
+/// Whether the kernel was booted to serve the telnet shell.
+pub fn enabled() -> bool {
+    cmdline::get("telnet").is_some()
+}
+
+/// Connection events reported by `Telnetd::poll`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TelnetEvent {
+    /// A peer connected and a new session should start.
+    Connected,
+    /// The peer disconnected.
+    Disconnected,
+}
+
+/// Output sink that buffers terminal output for the telnet peer.
+#[derive(Clone)]
+pub struct SessionOutput {
+    buffer: Arc<spin::Mutex<Vec<u8>>>,
+}
+
+impl SessionOutput {
+    fn new() -> Self {
+        Self {
+            buffer: Arc::new(spin::Mutex::new(Vec::new())),
+        }
+    }
+
+    fn push(&self, bytes: &[u8]) {
+        let mut buffer = self.buffer.lock();
+        let room = MAX_PENDING_OUTPUT.saturating_sub(buffer.len());
+        buffer.extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+}
+
+impl TerminalIo for SessionOutput {
+    fn write_str(&mut self, s: &str) {
+        // Telnet NVT line endings are CR LF
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.push(b"\r\n");
+            }
+            self.push(line.as_bytes());
+        }
+    }
+
+    fn set_color(&mut self, foreground: Color, _background: Color) {
+        let code = alloc::format!("\x1b[{}m", ansi_foreground(foreground));
+        self.push(code.as_bytes());
+    }
+
+    fn clear(&mut self) {
+        self.push(b"\x1b[2J\x1b[H");
+    }
+}
+
+/// Telnet input decoder state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputState {
+    /// Plain data.
+    Data,
+    /// Saw IAC.
+    Iac,
+    /// Saw IAC WILL/WONT/DO/DONT; next byte is the option.
+    Option,
+    /// Inside IAC SB ... IAC SE.
+    Subnegotiation,
+    /// Saw IAC inside a subnegotiation.
+    SubnegotiationIac,
+    /// Saw CR; a following LF or NUL is part of the same newline.
+    CarriageReturn,
+    /// Saw ESC.
+    Escape,
+    /// Saw ESC [ (CSI); collecting a numeric parameter.
+    Csi(u8),
+    /// Inside a UTF-8 sequence of `len` bytes with `remaining` still to come.
+    Utf8 { code: u32, remaining: u8, len: u8 },
+}
+
+/// Telnet server state.
+pub struct Telnetd {
+    port: u16,
+    socket: Option<SocketHandle>,
+    connected: bool,
+    state: InputState,
+    output: SessionOutput,
+}
+
+impl Telnetd {
+    /// Create a telnet server that will listen on `port`.
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            socket: None,
+            connected: false,
+            state: InputState::Data,
+            output: SessionOutput::new(),
+        }
+    }
+
+    /// Get the listening port.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Check whether a peer is attached.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Get a handle to the session output sink.
+    ///
+    /// The session task attaches this with `terminal::io::attach`.
+    pub fn output(&self) -> SessionOutput {
+        self.output.clone()
+    }
+
+    /// Start listening for connections.
+    pub fn start(&mut self, stack: &mut NetworkStack) -> Result<(), NetError> {
+        if self.socket.is_some() {
+            return Ok(());
+        }
+        let handle = stack.tcp_socket()?;
+        if let Err(e) = stack.tcp_listen(handle, self.port) {
+            stack.release_socket(handle);
+            return Err(e);
+        }
+        self.socket = Some(handle);
+        Ok(())
+    }
+
+    /// Stop the server, dropping any connected peer.
+    pub fn stop(&mut self, stack: &mut NetworkStack) {
+        if let Some(handle) = self.socket.take() {
+            stack.get_tcp_socket(handle).abort();
+            stack.release_socket(handle);
+        }
+        self.connected = false;
+    }
+
+    /// Track connection state, re-listening after a peer leaves.
+    pub fn poll(&mut self, stack: &mut NetworkStack) -> Option<TelnetEvent> {
+        let handle = self.socket?;
+        let socket = stack.get_tcp_socket(handle);
+
+        if self.connected {
+            if !socket.is_active() {
+                self.connected = false;
+                self.output.buffer.lock().clear();
+                return Some(TelnetEvent::Disconnected);
+            }
+            if !socket.may_recv() && socket.may_send() {
+                // Peer half-closed; finish our side too
+                socket.close();
+            }
+            return None;
+        }
+
+        // A finished connection ends up Closed; listen for the next peer
+        if socket.state() == tcp::State::Closed {
+            let _ = socket.listen(self.port);
+        }
+
+        if socket.may_send() {
+            self.connected = true;
+            self.state = InputState::Data;
+            self.output.buffer.lock().clear();
+            self.output.push(&NEGOTIATION);
+            return Some(TelnetEvent::Connected);
+        }
+        None
+    }
+
+    /// Read available input, decoding it into keys.
+    pub fn read_keys(&mut self, stack: &mut NetworkStack, keys: &mut Vec<DecodedKey>) {
+        let Some(handle) = self.socket else {
+            return;
+        };
+        if !self.connected {
+            return;
+        }
+
+        let mut buffer = [0u8; 128];
+        loop {
+            let socket = stack.get_tcp_socket(handle);
+            let len = match socket.recv_slice(&mut buffer) {
+                Ok(len) if len > 0 => len,
+                _ => break,
+            };
+            for &byte in &buffer[..len] {
+                if let Some(key) = self.decode(byte) {
+                    keys.push(key);
+                }
+            }
+        }
+    }
+
+    /// Send buffered output to the peer.
+    pub fn flush(&mut self, stack: &mut NetworkStack) {
+        let Some(handle) = self.socket else {
+            return;
+        };
+        let mut buffer = self.output.buffer.lock();
+        if !self.connected {
+            buffer.clear();
+            return;
+        }
+        let socket = stack.get_tcp_socket(handle);
+        if let Ok(sent) = socket.send_slice(&buffer) {
+            buffer.drain(..sent);
+        }
+    }
+
+    /// Feed one received byte through the telnet/ANSI decoder.
+    fn decode(&mut self, byte: u8) -> Option<DecodedKey> {
+        match self.state {
+            InputState::Data => match byte {
+                IAC => {
+                    self.state = InputState::Iac;
+                    None
+                }
+                b'\r' => {
+                    self.state = InputState::CarriageReturn;
+                    Some(DecodedKey::Unicode('\n'))
+                }
+                0x1b => {
+                    self.state = InputState::Escape;
+                    None
+                }
+                0 => None,
+                0x01..=0x7f => Some(DecodedKey::Unicode(byte as char)),
+                // Lead bytes of 2-, 3- and 4-byte sequences; stray
+                // continuation bytes and invalid leads are dropped
+                0xc2..=0xf4 => {
+                    let (code, len) = match byte {
+                        0xc2..=0xdf => (byte & 0x1f, 2),
+                        0xe0..=0xef => (byte & 0x0f, 3),
+                        _ => (byte & 0x07, 4),
+                    };
+                    self.state = InputState::Utf8 {
+                        code: u32::from(code),
+                        remaining: len - 1,
+                        len,
+                    };
+                    None
+                }
+                _ => None,
+            },
+            InputState::Utf8 {
+                code,
+                remaining,
+                len,
+            } => {
+                if byte & 0xc0 != 0x80 {
+                    // Truncated sequence: drop it and decode this byte afresh
+                    self.state = InputState::Data;
+                    return self.decode(byte);
+                }
+                let code = (code << 6) | u32::from(byte & 0x3f);
+                if remaining > 1 {
+                    self.state = InputState::Utf8 {
+                        code,
+                        remaining: remaining - 1,
+                        len,
+                    };
+                    return None;
+                }
+                self.state = InputState::Data;
+                // Rejects surrogates, values past U+10FFFF and overlong forms
+                char::from_u32(code)
+                    .filter(|c| c.len_utf8() == usize::from(len))
+                    .map(DecodedKey::Unicode)
+            }
+            InputState::CarriageReturn => {
+                self.state = InputState::Data;
+                match byte {
+                    b'\n' | 0 => None,
+                    _ => self.decode(byte),
+                }
+            }
+            InputState::Iac => {
+                // An escaped 0xFF data byte is not valid shell input; drop it
+                self.state = match byte {
+                    WILL..=DONT => InputState::Option,
+                    SB => InputState::Subnegotiation,
+                    _ => InputState::Data,
+                };
+                None
+            }
+            InputState::Option => {
+                self.state = InputState::Data;
+                None
+            }
+            InputState::Subnegotiation => {
+                if byte == IAC {
+                    self.state = InputState::SubnegotiationIac;
+                }
+                None
+            }
+            InputState::SubnegotiationIac => {
+                self.state = if byte == SE {
+                    InputState::Data
+                } else {
+                    InputState::Subnegotiation
+                };
+                None
+            }
+            InputState::Escape => {
+                self.state = if byte == b'[' {
+                    InputState::Csi(0)
+                } else {
+                    InputState::Data
+                };
+                None
+            }
+            InputState::Csi(param) => {
+                if byte.is_ascii_digit() {
+                    self.state =
+                        InputState::Csi(param.saturating_mul(10).saturating_add(byte - b'0'));
+                    return None;
+                }
+                // Other parameter bytes keep the sequence open; a final byte ends it
+                if !(0x40..=0x7e).contains(&byte) {
+                    return None;
+                }
+                self.state = InputState::Data;
+                let key = match (byte, param) {
+                    (b'A', _) => KeyCode::ArrowUp,
+                    (b'B', _) => KeyCode::ArrowDown,
+                    (b'C', _) => KeyCode::ArrowRight,
+                    (b'D', _) => KeyCode::ArrowLeft,
+                    (b'H', _) | (b'~', 1) | (b'~', 7) => KeyCode::Home,
+                    (b'F', _) | (b'~', 4) | (b'~', 8) => KeyCode::End,
+                    (b'~', 3) => KeyCode::Delete,
+                    _ => return None,
+                };
+                Some(DecodedKey::RawKey(key))
+            }
+        }
+    }
+}
@@ -0,0 +1,552 @@
+//! TFTP client (RFC 1350).
+//!
+//! Moves files between the RamFs and a TFTP server on the dev host using
+//! octet mode and 512-byte blocks. One transfer runs at a time; the client
+//! is driven by periodic polling and retransmits its last packet when the
+//! peer goes quiet.
+
+use super::socket::{ephemeral_port, UdpSocket};
+use super::stack::NetworkStack;
+use super::NetError;
+use crate::fs::{FileSystem, ROOT_FS};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+
+/// Well-known TFTP server port.
+pub const TFTP_PORT: u16 = 69;
+
+/// Payload size of a full DATA block.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Largest file transferred in either direction.
+///
+/// Keeps block numbers from wrapping and bounds heap use.
+pub const MAX_FILE_SIZE: usize = 256 * 1024;
+
+/// Time to wait for the peer before retransmitting.
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Retransmissions before a transfer is abandoned.
+const MAX_RETRIES: u8 = 5;
+
+/// Transfer mode sent in requests.
+const MODE_OCTET: &str = "octet";
+
+/// Size of the opcode and block/error-code header.
+const HEADER_LEN: usize = 4;
+
+const OP_RRQ: u16 = 1;
+const OP_WRQ: u16 = 2;
+const OP_DATA: u16 = 3;
+const OP_ACK: u16 = 4;
+const OP_ERROR: u16 = 5;
+
+/// A decoded TFTP packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet<'a> {
+    /// Read request.
+    ReadRequest {
+        /// Requested file.
+        filename: &'a str,
+        /// Transfer mode.
+        mode: &'a str,
+    },
+    /// Write request.
+    WriteRequest {
+        /// File to create.
+        filename: &'a str,
+        /// Transfer mode.
+        mode: &'a str,
+    },
+    /// File data; a block shorter than `BLOCK_SIZE` ends the transfer.
+    Data {
+        /// Block number, starting at 1.
+        block: u16,
+        /// Block contents.
+        data: &'a [u8],
+    },
+    /// Acknowledgement of a DATA block (or block 0 for a write request).
+    Ack {
+        /// Acknowledged block.
+        block: u16,
+    },
+    /// Error report; terminates the transfer.
+    Error {
+        /// TFTP error code.
+        code: u16,
+        /// Human-readable message.
+        message: &'a str,
+    },
+}
+
+impl<'a> Packet<'a> {
+    /// Decode a packet. Returns `None` for malformed input.
+    pub fn parse(buffer: &'a [u8]) -> Option<Self> {
+        let opcode = u16::from_be_bytes([*buffer.first()?, *buffer.get(1)?]);
+        let body = &buffer[2..];
+        match opcode {
+            OP_RRQ | OP_WRQ => {
+                let mut fields = body.split(|b| *b == 0);
+                let filename = core::str::from_utf8(fields.next()?).ok()?;
+                let mode = core::str::from_utf8(fields.next()?).ok()?;
+                if filename.is_empty() {
+                    return None;
+                }
+                Some(if opcode == OP_RRQ {
+                    Packet::ReadRequest { filename, mode }
+                } else {
+                    Packet::WriteRequest { filename, mode }
+                })
+            }
+            OP_DATA => Some(Packet::Data {
+                block: u16::from_be_bytes([*body.first()?, *body.get(1)?]),
+                data: body.get(2..)?,
+            }),
+            OP_ACK => Some(Packet::Ack {
+                block: u16::from_be_bytes([*body.first()?, *body.get(1)?]),
+            }),
+            OP_ERROR => {
+                let code = u16::from_be_bytes([*body.first()?, *body.get(1)?]);
+                let text = body.get(2..)?;
+                let end = text.iter().position(|b| *b == 0).unwrap_or(text.len());
+                let message = core::str::from_utf8(&text[..end]).ok()?;
+                Some(Packet::Error { code, message })
+            }
+            _ => None,
+        }
+    }
+
+    /// Encode the packet into a new buffer.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + BLOCK_SIZE);
+        match self {
+            Packet::ReadRequest { filename, mode } | Packet::WriteRequest { filename, mode } => {
+                let opcode = if matches!(self, Packet::ReadRequest { .. }) {
+                    OP_RRQ
+                } else {
+                    OP_WRQ
+                };
+                out.extend_from_slice(&opcode.to_be_bytes());
+                out.extend_from_slice(filename.as_bytes());
+                out.push(0);
+                out.extend_from_slice(mode.as_bytes());
+                out.push(0);
+            }
+            Packet::Data { block, data } => {
+                out.extend_from_slice(&OP_DATA.to_be_bytes());
+                out.extend_from_slice(&block.to_be_bytes());
+                out.extend_from_slice(data);
+            }
+            Packet::Ack { block } => {
+                out.extend_from_slice(&OP_ACK.to_be_bytes());
+                out.extend_from_slice(&block.to_be_bytes());
+            }
+            Packet::Error { code, message } => {
+                out.extend_from_slice(&OP_ERROR.to_be_bytes());
+                out.extend_from_slice(&code.to_be_bytes());
+                out.extend_from_slice(message.as_bytes());
+                out.push(0);
+            }
+        }
+        out
+    }
+}
+
+/// Transfer direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TftpDirection {
+    /// Download a file from the server into the RamFs.
+    Get,
+    /// Upload a RamFs file to the server.
+    Put,
+}
+
+/// Reasons a transfer failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TftpError {
+    /// The peer stopped answering.
+    Timeout,
+    /// The peer sent an ERROR packet.
+    Remote {
+        /// TFTP error code.
+        code: u16,
+        /// Message from the peer.
+        message: String,
+    },
+    /// The file exceeds `MAX_FILE_SIZE`.
+    TooLarge,
+    /// The local file could not be read.
+    LocalFile,
+    /// Socket setup or send failed.
+    Net(NetError),
+}
+
+impl core::fmt::Display for TftpError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TftpError::Timeout => write!(f, "transfer timed out"),
+            TftpError::Remote { code, message } => {
+                write!(f, "server error {}: {}", code, message)
+            }
+            TftpError::TooLarge => write!(f, "file larger than {} bytes", MAX_FILE_SIZE),
+            TftpError::LocalFile => write!(f, "cannot read local file"),
+            TftpError::Net(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Events emitted by the TFTP client.
+#[derive(Debug, Clone)]
+pub enum TftpEvent {
+    /// The transfer finished.
+    Complete {
+        /// Direction of the transfer.
+        direction: TftpDirection,
+        /// Transferred file.
+        file: String,
+        /// Number of bytes moved.
+        bytes: usize,
+    },
+    /// The transfer was abandoned.
+    Failed {
+        /// File being transferred.
+        file: String,
+        /// Why it failed.
+        error: TftpError,
+    },
+}
+
+/// State of the running transfer.
+struct Transfer {
+    direction: TftpDirection,
+    file: String,
+    socket: UdpSocket,
+    server: Ipv4Address,
+    /// Transfer ID (port) chosen by the server, learned from its first reply.
+    peer: Option<IpEndpoint>,
+    /// Last block received (get) or sent (put).
+    block: u16,
+    data: Vec<u8>,
+    last_packet: Vec<u8>,
+    sent_at: Instant,
+    retries: u8,
+    /// Set once the final packet is queued; reported on the next poll so
+    /// the stack gets a chance to transmit it before the socket goes away.
+    outcome: Option<TftpEvent>,
+}
+
+/// TFTP client driven by periodic polling.
+pub struct Tftp {
+    transfer: Option<Transfer>,
+}
+
+impl Tftp {
+    /// Create an idle client.
+    pub fn new() -> Self {
+        Self { transfer: None }
+    }
+
+    /// Check whether a transfer is in progress.
+    pub fn is_running(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    /// Download `file` from `server` into the RamFs under the same name.
+    pub fn get(
+        &mut self,
+        stack: &mut NetworkStack,
+        server: Ipv4Address,
+        file: &str,
+        timestamp: Instant,
+    ) -> Result<(), TftpError> {
+        self.begin(
+            stack,
+            TftpDirection::Get,
+            server,
+            file,
+            Vec::new(),
+            timestamp,
+        )
+    }
+
+    /// Upload the RamFs file `file` to `server`.
+    pub fn put(
+        &mut self,
+        stack: &mut NetworkStack,
+        server: Ipv4Address,
+        file: &str,
+        timestamp: Instant,
+    ) -> Result<(), TftpError> {
+        let data = read_local(file)?;
+        self.begin(stack, TftpDirection::Put, server, file, data, timestamp)
+    }
+
+    /// Abort the current transfer and free its socket.
+    pub fn cancel(&mut self, stack: &mut NetworkStack) {
+        if let Some(transfer) = self.transfer.take() {
+            stack.release_socket(transfer.socket.handle());
+        }
+    }
+
+    /// Poll the transfer, handling replies and retransmissions.
+    ///
+    /// Returns an event when the transfer completes or fails.
+    pub fn poll(&mut self, stack: &mut NetworkStack, timestamp: Instant) -> Option<TftpEvent> {
+        let transfer = self.transfer.as_mut()?;
+
+        if let Some(event) = transfer.outcome.take() {
+            self.cancel(stack);
+            return Some(event);
+        }
+
+        let mut buffer = [0u8; HEADER_LEN + BLOCK_SIZE];
+        while let Ok((len, from)) = transfer.socket.recv_from(stack, &mut buffer) {
+            if let Some(event) = transfer.receive(stack, &buffer[..len], from, timestamp) {
+                transfer.outcome = Some(event);
+                return None;
+            }
+        }
+
+        if timestamp - transfer.sent_at > RETRANSMIT_TIMEOUT {
+            if transfer.retries >= MAX_RETRIES {
+                let file = transfer.file.clone();
+                self.cancel(stack);
+                return Some(TftpEvent::Failed {
+                    file,
+                    error: TftpError::Timeout,
+                });
+            }
+            transfer.retries += 1;
+            transfer.resend(stack, timestamp);
+        }
+        None
+    }
+
+    /// Set up the socket and send the initial request.
+    fn begin(
+        &mut self,
+        stack: &mut NetworkStack,
+        direction: TftpDirection,
+        server: Ipv4Address,
+        file: &str,
+        data: Vec<u8>,
+        timestamp: Instant,
+    ) -> Result<(), TftpError> {
+        self.cancel(stack);
+
+        let request = match direction {
+            TftpDirection::Get => Packet::ReadRequest {
+                filename: file,
+                mode: MODE_OCTET,
+            },
+            TftpDirection::Put => Packet::WriteRequest {
+                filename: file,
+                mode: MODE_OCTET,
+            },
+        };
+
+        let mut socket = UdpSocket::new(stack).map_err(TftpError::Net)?;
+        if let Err(e) = socket.bind(stack, ephemeral_port()) {
+            stack.release_socket(socket.handle());
+            return Err(TftpError::Net(e));
+        }
+
+        let mut transfer = Transfer {
+            direction,
+            file: file.to_string(),
+            socket,
+            server,
+            peer: None,
+            block: 0,
+            data,
+            last_packet: request.to_bytes(),
+            sent_at: timestamp,
+            retries: 0,
+            outcome: None,
+        };
+        if let Err(e) = transfer.send_last(stack, timestamp) {
+            stack.release_socket(transfer.socket.handle());
+            return Err(TftpError::Net(e));
+        }
+        self.transfer = Some(transfer);
+        Ok(())
+    }
+}
+
+impl Default for Tftp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transfer {
+    /// Handle one datagram. Returns the final event once the transfer ends.
+    fn receive(
+        &mut self,
+        stack: &mut NetworkStack,
+        packet: &[u8],
+        from: IpEndpoint,
+        timestamp: Instant,
+    ) -> Option<TftpEvent> {
+        if from.addr != IpAddress::Ipv4(self.server) {
+            return None;
+        }
+        // The server answers from a fresh port; stick to it once seen
+        match self.peer {
+            Some(peer) if peer != from => return None,
+            Some(_) => {}
+            None => self.peer = Some(from),
+        }
+
+        let packet = Packet::parse(packet)?;
+        if let Packet::Error { code, message } = packet {
+            return Some(self.fail(TftpError::Remote {
+                code,
+                message: message.to_string(),
+            }));
+        }
+
+        match self.direction {
+            TftpDirection::Get => self.receive_data(stack, packet, timestamp),
+            TftpDirection::Put => self.receive_ack(stack, packet, timestamp),
+        }
+    }
+
+    /// Download side: store the next DATA block and acknowledge it.
+    fn receive_data(
+        &mut self,
+        stack: &mut NetworkStack,
+        packet: Packet,
+        timestamp: Instant,
+    ) -> Option<TftpEvent> {
+        let Packet::Data { block, data } = packet else {
+            return None;
+        };
+        if block == self.block {
+            // Our ACK was lost; repeat it
+            self.resend(stack, timestamp);
+            return None;
+        }
+        if block != self.block.wrapping_add(1) {
+            return None;
+        }
+        if self.data.len() + data.len() > MAX_FILE_SIZE {
+            self.send_error(stack, "file too large");
+            return Some(self.fail(TftpError::TooLarge));
+        }
+
+        self.data.extend_from_slice(data);
+        self.block = block;
+        self.retries = 0;
+        self.last_packet = Packet::Ack { block }.to_bytes();
+        if let Err(e) = self.send_last(stack, timestamp) {
+            return Some(self.fail(TftpError::Net(e)));
+        }
+
+        if data.len() < BLOCK_SIZE {
+            ROOT_FS.add_file(&self.file, &self.data);
+            return Some(TftpEvent::Complete {
+                direction: TftpDirection::Get,
+                file: self.file.clone(),
+                bytes: self.data.len(),
+            });
+        }
+        None
+    }
+
+    /// Upload side: on the ACK for the current block, send the next one.
+    fn receive_ack(
+        &mut self,
+        stack: &mut NetworkStack,
+        packet: Packet,
+        timestamp: Instant,
+    ) -> Option<TftpEvent> {
+        let Packet::Ack { block } = packet else {
+            return None;
+        };
+        if block != self.block {
+            return None;
+        }
+
+        // Block n carries bytes [(n-1)*512, n*512); a short block is the last
+        let sent_end = usize::from(self.block) * BLOCK_SIZE;
+        if self.block > 0 && sent_end > self.data.len() {
+            return Some(TftpEvent::Complete {
+                direction: TftpDirection::Put,
+                file: self.file.clone(),
+                bytes: self.data.len(),
+            });
+        }
+
+        let start = sent_end;
+        let end = (start + BLOCK_SIZE).min(self.data.len());
+        self.block = self.block.wrapping_add(1);
+        self.retries = 0;
+        self.last_packet = Packet::Data {
+            block: self.block,
+            data: &self.data[start..end],
+        }
+        .to_bytes();
+        if let Err(e) = self.send_last(stack, timestamp) {
+            return Some(self.fail(TftpError::Net(e)));
+        }
+        None
+    }
+
+    /// Build a failure event for this transfer.
+    fn fail(&self, error: TftpError) -> TftpEvent {
+        TftpEvent::Failed {
+            file: self.file.clone(),
+            error,
+        }
+    }
+
+    /// Tell the peer the transfer is being abandoned.
+    fn send_error(&self, stack: &mut NetworkStack, message: &str) {
+        let packet = Packet::Error { code: 0, message }.to_bytes();
+        let _ = self.socket.send_to(stack, &packet, self.destination());
+    }
+
+    /// Where packets go: the server's transfer port once known, else port 69.
+    fn destination(&self) -> IpEndpoint {
+        self.peer
+            .unwrap_or(IpEndpoint::new(IpAddress::Ipv4(self.server), TFTP_PORT))
+    }
+
+    /// Send the last packet again after a timeout or duplicate.
+    ///
+    /// A failed send is not reported; the next timeout retries it.
+    fn resend(&mut self, stack: &mut NetworkStack, timestamp: Instant) {
+        let _ = self.send_last(stack, timestamp);
+    }
+
+    /// Send `last_packet` and restart the retransmit timer.
+    fn send_last(&mut self, stack: &mut NetworkStack, timestamp: Instant) -> Result<(), NetError> {
+        self.sent_at = timestamp;
+        self.socket
+            .send_to(stack, &self.last_packet, self.destination())
+    }
+}
+
+/// Read a whole RamFs file for upload.
+fn read_local(file: &str) -> Result<Vec<u8>, TftpError> {
+    let handle = ROOT_FS.open(file).map_err(|_| TftpError::LocalFile)?;
+    if ROOT_FS.is_dir(handle) {
+        ROOT_FS.close(handle);
+        return Err(TftpError::LocalFile);
+    }
+    let size = ROOT_FS.size(handle).unwrap_or(0);
+    if size > MAX_FILE_SIZE {
+        ROOT_FS.close(handle);
+        return Err(TftpError::TooLarge);
+    }
+    let mut data = vec![0u8; size];
+    let result = ROOT_FS.read(handle, &mut data, 0);
+    ROOT_FS.close(handle);
+    let len = result.map_err(|_| TftpError::LocalFile)?;
+    data.truncate(len);
+    Ok(data)
+}
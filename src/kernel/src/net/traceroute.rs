@@ -0,0 +1,300 @@
+//! ICMP-based route tracing.
+//!
+//! Sends UDP probes with an increasing IP TTL and listens for the ICMP
+//! Time Exceeded messages returned by each router on the path. The trace
+//! ends when the destination answers with Port Unreachable (or any other
+//! Destination Unreachable), or after `MAX_HOPS`.
+
+use super::socket::ephemeral_port;
+use super::stack::NetworkStack;
+use alloc::vec::Vec;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::{icmp, udp};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{
+    Icmpv4DstUnreachable, Icmpv4Packet, Icmpv4Repr, IpAddress, IpEndpoint, IpProtocol, Ipv4Address,
+    UdpPacket,
+};
+
+/// Maximum TTL probed before giving up.
+pub const MAX_HOPS: u8 = 30;
+
+/// Number of probes sent per hop.
+pub const PROBES_PER_HOP: u8 = 3;
+
+/// Time to wait for a reply before a probe counts as lost.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// First destination port used for probes (the traditional traceroute base).
+const BASE_PORT: u16 = 33434;
+
+/// Size of the UDP probe payload.
+const PROBE_PAYLOAD_LEN: usize = 32;
+
+/// Receive buffer size for ICMP error messages.
+const ICMP_RX_BUFFER_SIZE: usize = 1024;
+
+/// Result of all probes sent with a single TTL.
+#[derive(Debug, Clone)]
+pub struct TracerouteHop {
+    /// TTL the probes were sent with.
+    pub ttl: u8,
+    /// Address that answered, if any probe was answered.
+    pub address: Option<Ipv4Address>,
+    /// Round-trip time per probe (`None` for lost probes).
+    pub rtts: Vec<Option<Duration>>,
+}
+
+/// Events emitted by the traceroute client.
+#[derive(Debug, Clone)]
+pub enum TracerouteEvent {
+    /// All probes for one hop completed.
+    Hop(TracerouteHop),
+    /// The trace ended.
+    Finished {
+        /// Traced destination.
+        target: Ipv4Address,
+        /// Whether the destination itself answered.
+        reached: bool,
+    },
+}
+
+/// A reply matched against the outstanding probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeReply {
+    /// Router or host that sent the reply.
+    pub from: Ipv4Address,
+    /// The reply terminates the trace (Destination Unreachable).
+    pub terminal: bool,
+    /// The destination itself answered (Port Unreachable).
+    pub reached: bool,
+}
+
+/// State of a running trace.
+struct Session {
+    target: Ipv4Address,
+    udp: SocketHandle,
+    icmp: SocketHandle,
+    ttl: u8,
+    probe: u8,
+    sent_at: Option<Instant>,
+    address: Option<Ipv4Address>,
+    rtts: Vec<Option<Duration>>,
+    terminal: bool,
+    reached: bool,
+    done: bool,
+}
+
+/// Traceroute client driven by periodic polling.
+pub struct Traceroute {
+    session: Option<Session>,
+}
+
+impl Traceroute {
+    /// Create an idle traceroute client.
+    pub fn new() -> Self {
+        Self { session: None }
+    }
+
+    /// Check whether a trace is in progress.
+    pub fn is_running(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Start tracing the route to `target`.
+    ///
+    /// Any trace already in progress is abandoned.
+    pub fn start(
+        &mut self,
+        stack: &mut NetworkStack,
+        target: Ipv4Address,
+    ) -> Result<(), super::NetError> {
+        self.cancel(stack);
+
+        let port = ephemeral_port();
+        let udp = stack.udp_socket()?;
+        stack.udp_bind(udp, port)?;
+        let icmp = match stack.icmp_error_socket(port) {
+            Ok(handle) => handle,
+            Err(e) => {
+                stack.release_socket(udp);
+                return Err(e);
+            }
+        };
+
+        self.session = Some(Session {
+            target,
+            udp,
+            icmp,
+            ttl: 1,
+            probe: 0,
+            sent_at: None,
+            address: None,
+            rtts: Vec::with_capacity(PROBES_PER_HOP as usize),
+            terminal: false,
+            reached: false,
+            done: false,
+        });
+        Ok(())
+    }
+
+    /// Abort the current trace and free its sockets.
+    pub fn cancel(&mut self, stack: &mut NetworkStack) {
+        if let Some(session) = self.session.take() {
+            stack.release_socket(session.udp);
+            stack.release_socket(session.icmp);
+        }
+    }
+
+    /// Poll the trace, sending probes and collecting replies.
+    ///
+    /// Returns an event when a hop completes or the trace ends.
+    pub fn poll(
+        &mut self,
+        stack: &mut NetworkStack,
+        timestamp: Instant,
+    ) -> Option<TracerouteEvent> {
+        let session = self.session.as_mut()?;
+
+        if session.done {
+            let event = TracerouteEvent::Finished {
+                target: session.target,
+                reached: session.reached,
+            };
+            self.cancel(stack);
+            return Some(event);
+        }
+
+        let Some(sent_at) = session.sent_at else {
+            session.send_probe(stack);
+            session.sent_at = Some(timestamp);
+            return None;
+        };
+
+        if let Some(reply) = session.receive(stack) {
+            session.address.get_or_insert(reply.from);
+            session.rtts.push(Some(timestamp - sent_at));
+            if reply.terminal {
+                session.terminal = true;
+                session.reached = reply.reached;
+            }
+        } else if timestamp - sent_at > PROBE_TIMEOUT {
+            session.rtts.push(None);
+        } else {
+            return None;
+        }
+
+        session.sent_at = None;
+        session.probe += 1;
+        if session.probe < PROBES_PER_HOP {
+            return None;
+        }
+
+        let hop = TracerouteHop {
+            ttl: session.ttl,
+            address: session.address.take(),
+            rtts: core::mem::take(&mut session.rtts),
+        };
+        if session.terminal || session.ttl >= MAX_HOPS {
+            session.done = true;
+        } else {
+            session.ttl += 1;
+            session.probe = 0;
+        }
+        Some(TracerouteEvent::Hop(hop))
+    }
+}
+
+impl Default for Traceroute {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Destination port of the `probe`th probe sent with `ttl`, which tells
+/// the reply to each probe apart.
+pub fn probe_port(ttl: u8, probe: u8) -> u16 {
+    BASE_PORT + u16::from(ttl - 1) * u16::from(PROBES_PER_HOP) + u16::from(probe)
+}
+
+/// Read the ICMP message `packet`, received from `from`, as the reply to
+/// the probe sent to `target` on `port`.
+///
+/// Returns `None` for anything else: other ICMP messages (an echo reply,
+/// say), errors about other datagrams, and malformed packets.
+pub fn parse_reply(
+    packet: &[u8],
+    from: Ipv4Address,
+    target: Ipv4Address,
+    port: u16,
+) -> Option<ProbeReply> {
+    let packet = Icmpv4Packet::new_checked(packet).ok()?;
+    let repr = Icmpv4Repr::parse(&packet, &Default::default()).ok()?;
+
+    // Any unreachable report ends the trace, but only Port Unreachable
+    // from the target means the destination itself answered.
+    let (header, data, terminal, reached) = match repr {
+        Icmpv4Repr::TimeExceeded { header, data, .. } => (header, data, false, false),
+        Icmpv4Repr::DstUnreachable {
+            reason,
+            header,
+            data,
+        } => {
+            let reached = reason == Icmpv4DstUnreachable::PortUnreachable && from == target;
+            (header, data, true, reached)
+        }
+        _ => return None,
+    };
+
+    if header.next_header != IpProtocol::Udp || header.dst_addr != target {
+        return None;
+    }
+    // The embedded datagram carries at least the 8-byte UDP header.
+    let udp = UdpPacket::new_unchecked(data);
+    (udp.dst_port() == port).then_some(ProbeReply {
+        from,
+        terminal,
+        reached,
+    })
+}
+
+impl Session {
+    /// Destination port identifying the current probe.
+    fn probe_port(&self) -> u16 {
+        probe_port(self.ttl, self.probe)
+    }
+
+    /// Send the current probe with the current TTL.
+    ///
+    /// A failed send is not reported; the probe simply times out.
+    fn send_probe(&self, stack: &mut NetworkStack) {
+        let remote = IpEndpoint::new(IpAddress::Ipv4(self.target), self.probe_port());
+        let socket = stack.sockets().get_mut::<udp::Socket>(self.udp);
+        socket.set_hop_limit(Some(self.ttl));
+        let _ = socket.send_slice(&[0u8; PROBE_PAYLOAD_LEN], remote);
+    }
+
+    /// Drain queued ICMP errors, returning the one answering the current probe.
+    ///
+    /// Late replies to earlier probes are discarded.
+    fn receive(&self, stack: &mut NetworkStack) -> Option<ProbeReply> {
+        let expected_port = self.probe_port();
+        let socket = stack.sockets().get_mut::<icmp::Socket>(self.icmp);
+        let mut buffer = [0u8; ICMP_RX_BUFFER_SIZE];
+
+        while socket.can_recv() {
+            let Ok((len, source)) = socket.recv_slice(&mut buffer) else {
+                break;
+            };
+            let from = match source {
+                IpAddress::Ipv4(addr) => addr,
+                #[allow(unreachable_patterns)]
+                _ => continue,
+            };
+            if let Some(reply) = parse_reply(&buffer[..len], from, self.target, expected_port) {
+                return Some(reply);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,295 @@
+//! Deterministic record and replay of keyboard and network input.
+//!
+//! With `record` on the kernel command line, every scancode the terminal
+//! decodes and every frame the network device receives is logged with its
+//! time since boot. The log is saved to `REPLAY_FILE` every
+//! `SAVE_INTERVAL_MS`, so it survives a hang, and can be fetched with
+//! `tftp put`.
+//!
+//! With `replay`, the kernel feeds a recording back at the times it was
+//! taken: scancodes through `add_scancode`, frames through the loopback
+//! device's `inject_rx`. The network uses the loopback device while
+//! replaying, so live traffic cannot interfere. The recording comes from
+//! `REPLAY_FILE` in the root filesystem or, if there is none, from the
+//! file named by `SOVELMA_REPLAY` at build time (see `build.rs`).
+//!
+//! # Format
+//!
+//! `MAGIC`, a version byte, then one record per input: kind (`u8`), time
+//! in milliseconds since boot (`u64`), payload length (`u16`) and the
+//! payload, little-endian.
+
+use crate::fs::{FileSystem, ROOT_FS};
+use crate::sync::TrackedMutex;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Where recordings are saved and replayed from.
+pub const REPLAY_FILE: &str = "boot/replay.rec";
+
+/// How often a recording in progress is saved.
+pub const SAVE_INTERVAL_MS: u64 = 1000;
+
+/// Largest recording kept; recording stops when it is reached.
+pub const MAX_RECORDING: usize = 128 * 1024;
+
+/// First bytes of a recording.
+pub const MAGIC: [u8; 4] = *b"SVRR";
+
+/// Format version written after `MAGIC`.
+pub const VERSION: u8 = 1;
+
+const KIND_SCANCODE: u8 = 1;
+const KIND_FRAME: u8 = 2;
+
+/// Bytes of a record before its payload.
+const RECORD_HEADER: usize = 11;
+
+/// Recording embedded by the build script (empty without `SOVELMA_REPLAY`).
+static EMBEDDED: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/replay.rec"));
+
+/// A recorded input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    /// A keyboard scancode.
+    Scancode(u8),
+    /// A received Ethernet frame.
+    Frame(Vec<u8>),
+}
+
+/// An input and when it arrived.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Milliseconds since boot.
+    pub time_ms: u64,
+    /// The input.
+    pub input: Input,
+}
+
+/// Why a recording could not be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The data does not start with `MAGIC`.
+    BadMagic,
+    /// Written by an unknown format version.
+    UnsupportedVersion(u8),
+    /// A record is cut short.
+    Truncated,
+    /// A record has an unknown kind.
+    UnknownKind(u8),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a recording"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported recording version {}", version)
+            }
+            Self::Truncated => write!(f, "recording is truncated"),
+            Self::UnknownKind(kind) => write!(f, "unknown record kind {}", kind),
+        }
+    }
+}
+
+/// Start a recording in `out`.
+pub fn encode_header(out: &mut Vec<u8>) {
+    out.extend_from_slice(&MAGIC);
+    out.push(VERSION);
+}
+
+/// Append a record to `out`. Payloads are cut to `u16::MAX` bytes.
+pub fn encode_record(out: &mut Vec<u8>, time_ms: u64, input: &Input) {
+    let (kind, payload) = match input {
+        Input::Scancode(scancode) => (KIND_SCANCODE, core::slice::from_ref(scancode)),
+        Input::Frame(frame) => (KIND_FRAME, frame.as_slice()),
+    };
+    push(out, kind, time_ms, payload);
+}
+
+fn push(out: &mut Vec<u8>, kind: u8, time_ms: u64, payload: &[u8]) {
+    let payload = &payload[..payload.len().min(u16::MAX as usize)];
+    out.push(kind);
+    out.extend_from_slice(&time_ms.to_le_bytes());
+    out.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Decode a recording.
+pub fn parse(data: &[u8]) -> Result<Vec<Record>, ReplayError> {
+    let rest = data.strip_prefix(&MAGIC).ok_or(ReplayError::BadMagic)?;
+    let (&version, mut rest) = rest.split_first().ok_or(ReplayError::Truncated)?;
+    if version != VERSION {
+        return Err(ReplayError::UnsupportedVersion(version));
+    }
+
+    let mut records = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < RECORD_HEADER {
+            return Err(ReplayError::Truncated);
+        }
+        let (header, tail) = rest.split_at(RECORD_HEADER);
+        let mut time = [0u8; 8];
+        time.copy_from_slice(&header[1..9]);
+        let len = u16::from_le_bytes([header[9], header[10]]) as usize;
+        if tail.len() < len {
+            return Err(ReplayError::Truncated);
+        }
+        let (payload, tail) = tail.split_at(len);
+        let input = match (header[0], payload) {
+            (KIND_SCANCODE, &[scancode]) => Input::Scancode(scancode),
+            (KIND_SCANCODE, _) => return Err(ReplayError::Truncated),
+            (KIND_FRAME, frame) => Input::Frame(frame.to_vec()),
+            (kind, _) => return Err(ReplayError::UnknownKind(kind)),
+        };
+        records.push(Record {
+            time_ms: u64::from_le_bytes(time),
+            input,
+        });
+        rest = tail;
+    }
+    Ok(records)
+}
+
+/// Whether the kernel was booted to replay a recording.
+pub fn replaying() -> bool {
+    crate::boot::cmdline::get("replay").is_some()
+}
+
+/// Set while inputs are being recorded.
+static RECORDING: AtomicBool = AtomicBool::new(false);
+
+/// Inputs fed back so far.
+static REPLAYED: AtomicU64 = AtomicU64::new(0);
+
+struct Recorder {
+    buffer: Vec<u8>,
+    inputs: u64,
+    /// Length of `buffer` when it was last saved.
+    saved: usize,
+}
+
+static RECORDER: TrackedMutex<Recorder> = TrackedMutex::new(
+    "replay",
+    Recorder {
+        buffer: Vec::new(),
+        inputs: 0,
+        saved: 0,
+    },
+);
+
+/// Start recording if the command line asks for it.
+///
+/// `replay` takes precedence over `record`. Returns whether recording.
+pub fn init() -> bool {
+    if replaying() || crate::boot::cmdline::get("record").is_none() {
+        return false;
+    }
+    let mut recorder = RECORDER.lock();
+    encode_header(&mut recorder.buffer);
+    RECORDING.store(true, Ordering::Release);
+    true
+}
+
+/// Whether inputs are being recorded.
+pub fn recording() -> bool {
+    RECORDING.load(Ordering::Acquire)
+}
+
+/// Record a scancode, if recording.
+pub fn record_scancode(scancode: u8) {
+    if recording() {
+        record(KIND_SCANCODE, &[scancode]);
+    }
+}
+
+/// Record a received frame, if recording.
+pub fn record_frame(frame: &[u8]) {
+    if recording() {
+        record(KIND_FRAME, frame);
+    }
+}
+
+fn record(kind: u8, payload: &[u8]) {
+    let mut recorder = RECORDER.lock();
+    if recorder.buffer.len() + RECORD_HEADER + payload.len() <= MAX_RECORDING {
+        push(&mut recorder.buffer, kind, crate::time::now_ms(), payload);
+        recorder.inputs += 1;
+        return;
+    }
+    RECORDING.store(false, Ordering::Release);
+    let inputs = recorder.inputs;
+    drop(recorder);
+    log::warn!(target: "replay", "Recording full, stopped after {} inputs", inputs);
+}
+
+/// Inputs recorded so far.
+pub fn recorded() -> u64 {
+    RECORDER.lock().inputs
+}
+
+/// Inputs replayed so far.
+pub fn replayed() -> u64 {
+    REPLAYED.load(Ordering::Relaxed)
+}
+
+/// Write the recording to `REPLAY_FILE` if it grew since the last save.
+pub fn save() {
+    let mut recorder = RECORDER.lock();
+    if recorder.buffer.len() != recorder.saved {
+        ROOT_FS.add_file(REPLAY_FILE, &recorder.buffer);
+        recorder.saved = recorder.buffer.len();
+    }
+}
+
+/// Load the recording to replay: `REPLAY_FILE`, else the embedded one.
+///
+/// Returns `Ok(None)` if there is neither.
+pub fn load() -> Result<Option<Vec<Record>>, ReplayError> {
+    match read_replay_file() {
+        Some(data) => parse(&data).map(Some),
+        None if EMBEDDED.is_empty() => Ok(None),
+        None => parse(EMBEDDED).map(Some),
+    }
+}
+
+fn read_replay_file() -> Option<Vec<u8>> {
+    let handle = ROOT_FS.open(REPLAY_FILE).ok()?;
+    let size = ROOT_FS.size(handle).unwrap_or(0);
+    let mut buffer = alloc::vec![0u8; size];
+    let result = ROOT_FS.read(handle, &mut buffer, 0);
+    ROOT_FS.close(handle);
+    buffer.truncate(result.ok()?);
+    Some(buffer)
+}
+
+/// Feed `records` back at their recorded times. Frames are dropped in a
+/// kernel built without `net`.
+pub async fn run(records: Vec<Record>) {
+    for record in records {
+        let now = crate::time::now_ms();
+        if record.time_ms > now {
+            crate::time::sleep_ms(record.time_ms - now).await;
+        }
+        match record.input {
+            Input::Scancode(scancode) => crate::task::keyboard::add_scancode(scancode),
+            #[cfg(feature = "net")]
+            Input::Frame(frame) => {
+                use crate::net::NetworkDevice;
+
+                if let Some(net_stack) = crate::services::net_stack() {
+                    if let NetworkDevice::Loopback(device) = net_stack.lock().device() {
+                        device.inject_rx(&frame);
+                    }
+                }
+            }
+            #[cfg(not(feature = "net"))]
+            Input::Frame(_) => {}
+        }
+        REPLAYED.fetch_add(1, Ordering::Relaxed);
+        // Let the consumer see each input before the next
+        crate::task::yield_now().await;
+    }
+    log::info!(target: "replay", "Replay finished after {} inputs", replayed());
+}
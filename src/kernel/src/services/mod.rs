@@ -0,0 +1,508 @@
+//! Kernel services.
+//!
+//! Brings the kernel up after the bootloader hands over: core hardware,
+//! memory, the filesystem and self-tests, then the long-running subsystems
+//! (network stack and protocol clients, remote shell, WASM processes).
+//! The subsystems are owned by [`Services`], which registers their executor
+//! tasks and hands out typed handles to the shells that run commands.
+//!
+//! - `net`: Network bring-up, protocol tasks and event reporting
+//! - `shell`: Command execution, keyboard and telnet sessions
+//!
+//! Each is built only with its cargo feature (`net`, `terminal`), as are
+//! the WASM processes (`wasm`); a kernel built without them boots to the
+//! services it has.
+
+#[cfg(feature = "net")]
+mod net;
+#[cfg(feature = "terminal")]
+mod shell;
+
+#[cfg(feature = "terminal")]
+pub use shell::Shell;
+
+use crate::arch::x86_64;
+use crate::boot::health::{self, Subsystem};
+use crate::boot::{self, Status};
+#[cfg(feature = "net")]
+use crate::net::{DhcpClient, DnsResolver, Httpd, NetworkStack, Syslog, Telnetd, Tftp, Traceroute};
+use crate::fs::FsError;
+use crate::println;
+use crate::sync::TrackedMutex;
+use crate::task::{executor::Executor, Task};
+use crate::terminal::theme::{self, Role};
+#[cfg(feature = "wasm")]
+use crate::wasm::process::ProcessManager;
+use ::x86_64::VirtAddr;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use bootloader::BootInfo;
+use sovelma_common::error::{Context, Error};
+#[cfg(feature = "net")]
+use smoltcp::time::Instant;
+#[cfg(feature = "net")]
+use spin::Once;
+
+/// A subsystem shared between tasks.
+///
+/// The lock is tracked (`sync::lockdep`) under the subsystem's name.
+pub type Shared<T> = Arc<TrackedMutex<T>>;
+
+/// Share `value` between tasks under lock class `name`.
+#[cfg(any(feature = "net", feature = "wasm"))]
+fn shared<T>(name: &'static str, value: T) -> Shared<T> {
+    Arc::new(TrackedMutex::new(name, value))
+}
+
+/// The stack `Services` was created with.
+#[cfg(feature = "net")]
+static NET_STACK: Once<Shared<NetworkStack>> = Once::new();
+
+/// The network stack, for code that runs outside the service tasks, such
+/// as WASM host functions. `None` before the stack is up.
+#[cfg(feature = "net")]
+pub fn net_stack() -> Option<Shared<NetworkStack>> {
+    NET_STACK.get().cloned()
+}
+
+/// Current timestamp for smoltcp.
+#[cfg(feature = "net")]
+pub fn now() -> Instant {
+    Instant::from_millis(crate::time::now_ms() as i64)
+}
+
+/// Handles to the kernel's long-running subsystems.
+///
+/// Cloning is cheap and shares the subsystems.
+#[derive(Clone)]
+pub struct Services {
+    /// The network stack every protocol task polls.
+    #[cfg(feature = "net")]
+    pub net_stack: Shared<NetworkStack>,
+    /// DHCP client driven by the `dhcp` command.
+    #[cfg(feature = "net")]
+    pub dhcp: Shared<DhcpClient>,
+    /// DNS resolver.
+    #[cfg(feature = "net")]
+    pub dns: Shared<DnsResolver>,
+    /// Traceroute prober.
+    #[cfg(feature = "net")]
+    pub traceroute: Shared<Traceroute>,
+    /// HTTP server (idle until `httpd start`).
+    #[cfg(feature = "net")]
+    pub httpd: Shared<Httpd>,
+    /// TFTP client.
+    #[cfg(feature = "net")]
+    pub tftp: Shared<Tftp>,
+    /// Remote syslog sink (idle until `log remote`).
+    #[cfg(feature = "net")]
+    pub syslog: Shared<Syslog>,
+    /// Telnet server for remote shell sessions.
+    #[cfg(feature = "net")]
+    pub telnetd: Shared<Telnetd>,
+    /// WASM processes started with `wasm run`.
+    #[cfg(feature = "wasm")]
+    pub processes: Shared<ProcessManager>,
+}
+
+impl Services {
+    /// Create the subsystems around an initialized network stack.
+    ///
+    /// Nothing runs until `spawn` registers the tasks.
+    #[cfg_attr(not(feature = "net"), allow(clippy::new_without_default))]
+    pub fn new(
+        #[cfg(feature = "net")] net_stack: NetworkStack,
+        #[cfg(feature = "net")] dhcp: DhcpClient,
+        #[cfg(feature = "net")] telnetd: Telnetd,
+    ) -> Self {
+        #[cfg(feature = "net")]
+        let net_stack = NET_STACK.call_once(|| shared("net_stack", net_stack));
+        Self {
+            #[cfg(feature = "net")]
+            net_stack: net_stack.clone(),
+            #[cfg(feature = "net")]
+            dhcp: shared("dhcp", dhcp),
+            #[cfg(feature = "net")]
+            dns: shared("dns", DnsResolver::new()),
+            #[cfg(feature = "net")]
+            traceroute: shared("traceroute", Traceroute::new()),
+            #[cfg(feature = "net")]
+            httpd: shared("httpd", Httpd::new()),
+            #[cfg(feature = "net")]
+            tftp: shared("tftp", Tftp::new()),
+            #[cfg(feature = "net")]
+            syslog: shared("syslog", Syslog::new()),
+            #[cfg(feature = "net")]
+            telnetd: shared("telnetd", telnetd),
+            #[cfg(feature = "wasm")]
+            processes: shared("processes", ProcessManager::new()),
+        }
+    }
+
+    /// Handles a shell needs to execute commands.
+    #[cfg(feature = "terminal")]
+    pub fn shell(&self) -> Shell {
+        Shell::new(self.clone())
+    }
+
+    /// Register every subsystem's task with `executor`.
+    pub fn spawn(&self, executor: &mut Executor) {
+        #[cfg(feature = "net")]
+        net::spawn(self, executor);
+        #[cfg(feature = "terminal")]
+        shell::spawn(self, executor);
+
+        // Console: draw screen changes at most once per tick
+        executor.spawn(Task::new(async {
+            x86_64::vga::set_deferred(true);
+            let mut drawn = crate::time::now_ms();
+            loop {
+                let now = crate::time::now_ms();
+                if now != drawn {
+                    x86_64::vga::flush();
+                    drawn = now;
+                }
+                crate::task::yield_now().await;
+            }
+        }));
+
+        // Records logged by interrupt handlers
+        executor.spawn(Task::new(async {
+            loop {
+                crate::klog::irq::drain();
+                crate::task::yield_now().await;
+            }
+        }));
+
+        if cfg!(feature = "heap-poison") {
+            // Catch writes to freed heap blocks soon after they happen
+            executor.spawn(Task::new(async {
+                loop {
+                    crate::time::sleep_ms(crate::allocator::poison::SCRUB_INTERVAL_MS).await;
+                    crate::allocator::poison::scrub();
+                }
+            }));
+        }
+
+        if crate::replay::recording() {
+            // Keep the recording on disk in case the kernel hangs
+            executor.spawn(Task::new(async {
+                loop {
+                    crate::time::sleep_ms(crate::replay::SAVE_INTERVAL_MS).await;
+                    crate::replay::save();
+                }
+            }));
+        }
+        if crate::replay::replaying() {
+            match crate::replay::load() {
+                Ok(Some(records)) => executor.spawn(Task::new(crate::replay::run(records))),
+                Ok(None) => log::warn!(target: "replay", "Nothing to replay"),
+                Err(e) => log::warn!(target: "replay", "Cannot replay: {}", e),
+            }
+        }
+
+        #[cfg(feature = "terminal")]
+        match crate::ctl::channel() {
+            Ok(Some(_)) if boot::crashloop::safe_mode() => {
+                log::warn!(target: "ctl", "Control protocol not started in safe mode")
+            }
+            Ok(Some((com, cap))) => {
+                log::info!(
+                    target: "ctl",
+                    "Control protocol on COM{} ({})",
+                    com,
+                    crate::ctl::rights_letters(cap.rights)
+                );
+                executor.spawn(Task::new(crate::ctl::serve(com, cap, self.shell())));
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!(target: "ctl", "Control protocol disabled: {}", e),
+        }
+
+        #[cfg(feature = "wasm")]
+        {
+            let processes = self.processes.clone();
+            executor.spawn(Task::new(async move {
+                loop {
+                    processes.lock().poll();
+                    crate::task::yield_now().await;
+                }
+            }));
+        }
+    }
+}
+
+/// Bring the kernel up and run it.
+///
+/// Called from the entry point with the bootloader's information; never
+/// returns.
+pub fn start(boot_info: &'static BootInfo) -> ! {
+    boot::crashloop::begin(boot_info);
+    init_core(boot_info);
+
+    #[cfg(feature = "net")]
+    let services = {
+        boot::log_section("Network");
+        let (net_stack, dhcp, telnetd) = net::init(boot_info.physical_memory_offset);
+        Services::new(net_stack, dhcp, telnetd)
+    };
+    #[cfg(not(feature = "net"))]
+    let services = Services::new();
+
+    boot::log_section("Services");
+    if boot::crashloop::safe_mode() {
+        boot::log(
+            Status::Warn,
+            &alloc::format!(
+                "Safe mode ({} failed boots in a row)",
+                boot::crashloop::failed_boots()
+            ),
+        );
+    }
+    #[cfg(feature = "terminal")]
+    {
+        register_commands();
+        boot::log(Status::Ok, "Terminal initialized");
+    }
+    if cfg!(feature = "wasm") {
+        boot::log(Status::Ok, "WASM engine ready");
+    }
+    log_omitted();
+
+    println!();
+    boot::log(Status::Ok, "Boot complete!");
+    println!();
+    if boot::crashloop::safe_mode() {
+        boot::crashloop::print_banner();
+    }
+    boot::crashloop::complete();
+    if cfg!(feature = "terminal") {
+        theme::set(Role::Accent);
+        println!("Type 'help' for available commands.");
+        theme::reset();
+        println!();
+    }
+
+    let mut executor = Executor::new();
+    services.spawn(&mut executor);
+    executor.run()
+}
+
+/// Register every subsystem's shell commands.
+#[cfg(feature = "terminal")]
+fn register_commands() {
+    crate::terminal::commands::register();
+    #[cfg(feature = "net")]
+    crate::net::commands::register();
+    #[cfg(feature = "wasm")]
+    crate::wasm::commands::register();
+}
+
+/// Note the subsystems this kernel was built without.
+fn log_omitted() {
+    let omitted: Vec<&str> = [
+        ("net", cfg!(feature = "net")),
+        ("wasm", cfg!(feature = "wasm")),
+        ("terminal", cfg!(feature = "terminal")),
+    ]
+    .into_iter()
+    .filter(|&(_, built)| !built)
+    .map(|(feature, _)| feature)
+    .collect();
+    if !omitted.is_empty() {
+        boot::log(
+            Status::Info,
+            &alloc::format!("Built without: {}", omitted.join(", ")),
+        );
+    }
+}
+
+/// Apply the serial options of the command line and add `/dev/serialN`
+/// nodes for the ports enabled.
+fn init_serial_ports() {
+    use x86_64::serial;
+
+    let ports = match serial::init_from_cmdline() {
+        Ok(ports) => ports,
+        Err(e) => {
+            boot::log(
+                Status::Warn,
+                &alloc::format!("Serial options ignored: {}", e),
+            );
+            return;
+        }
+    };
+    for (config, result) in ports {
+        match result.and_then(|()| serial::port_base(config.com)) {
+            Ok(port) => {
+                crate::fs::devfs::add_serial(config.com, port);
+                boot::log(
+                    Status::Ok,
+                    &alloc::format!(
+                        "COM{} at {} baud ({})",
+                        config.com,
+                        config.baud,
+                        crate::fs::devfs::serial_path(config.com)
+                    ),
+                );
+            }
+            Err(e) => boot::log(Status::Warn, &alloc::format!("Serial port: {}", e)),
+        }
+    }
+}
+
+/// Fill the RAM filesystem with the built-in files and apps, and read one
+/// back. Returns the number of apps installed.
+fn init_fs() -> Result<usize, Error<FsError>> {
+    const WASM_MAGIC: [u8; 8] = [0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00];
+    crate::fs::ROOT_FS.add_file("hello.wasm", &WASM_MAGIC);
+    crate::fs::ROOT_FS.add_file("etc/hosts", b"127.0.0.1 localhost\n");
+    crate::fs::ROOT_FS.add_file(
+        "www/index.html",
+        b"<!doctype html>\n<title>SovelmaOS</title>\n<h1>Hello from SovelmaOS</h1>\n",
+    );
+    boot::banner::install_motd();
+    crate::fs::read_file("etc/hosts").context("mounting", "/")?;
+    Ok(crate::fs::initrd::install())
+}
+
+/// Initialize hardware, memory and the filesystem, and run the self-tests.
+///
+/// A failed phase the kernel can run without is marked degraded (see
+/// `boot::health`) and the boot goes on.
+fn init_core(boot_info: &'static BootInfo) {
+    crate::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    // SAFETY: the bootloader maps all physical memory at this offset.
+    let mut mapper = unsafe { crate::memory::init_mapper(phys_mem_offset) };
+    // SAFETY: the memory map comes from the bootloader and marks used
+    // regions correctly.
+    let mut frame_allocator =
+        unsafe { crate::memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    health::require(
+        "heap initialization",
+        crate::allocator::init_heap(&mut mapper, &mut frame_allocator),
+    );
+
+    x86_64::vga::clear_screen();
+    crate::klog::mark_ready();
+    boot::banner::print_banner();
+
+    boot::log(Status::Ok, "Serial port initialized");
+    boot::log(Status::Ok, "GDT loaded");
+    boot::log(Status::Ok, "IDT configured");
+    boot::log(Status::Ok, "Memory manager initialized");
+    boot::log(Status::Ok, "Kernel heap ready (1 MiB)");
+    let cpu = x86_64::cpuid::info();
+    boot::log(
+        Status::Info,
+        &alloc::format!("CPU: {} {}", cpu.vendor(), cpu.brand()),
+    );
+    let features: Vec<&str> = cpu.features.present().collect();
+    boot::log_detail(&features.join(" "));
+    boot::log(
+        Status::Ok,
+        &alloc::format!("FPU/SSE state switching enabled ({})", x86_64::fpu::mode()),
+    );
+    if health::check(Subsystem::Keyboard, x86_64::ps2::init()).is_some() {
+        boot::log(Status::Ok, "PS/2 keyboard controller initialized");
+    }
+    let usermode = x86_64::usermode::init(&mut mapper, &mut frame_allocator, phys_mem_offset);
+    if health::check(Subsystem::Usermode, usermode).is_some() {
+        boot::log(Status::Ok, "Ring 3 support ready (syscall/sysret)");
+    }
+
+    if x86_64::gdbstub::requested() {
+        boot::log(Status::Info, "GDB stub on COM2, waiting for debugger");
+        x86_64::gdbstub::init(phys_mem_offset);
+        boot::log(Status::Ok, "Debugger attached");
+    } else if boot::cmdline::get("gdb").is_some() {
+        boot::log(Status::Warn, "GDB stub disabled: COM2 is used by SLIP");
+    }
+
+    crate::memory::install(mapper, frame_allocator);
+    boot::log(Status::Ok, "Process memory arenas ready");
+    for (name, result) in crate::driver::init() {
+        let result = result.map_err(|e| alloc::format!("{}: {}", name, e));
+        if health::check(Subsystem::Drivers, result).is_some() {
+            boot::log(Status::Ok, &alloc::format!("Driver {} started", name));
+        }
+    }
+
+    // Without a filesystem there are no saved settings to load
+    if let Some(apps) = health::check(Subsystem::Filesystem, init_fs()) {
+        boot::log(Status::Ok, "RAM filesystem mounted");
+        if apps > 0 {
+            boot::log(
+                Status::Ok,
+                &alloc::format!(
+                    "{} apps installed in /{}",
+                    apps,
+                    crate::fs::initrd::APPS_DIR
+                ),
+            );
+        }
+        if theme::load() {
+            boot::log(Status::Ok, "Shell theme loaded from /etc/shellrc");
+        }
+        match crate::config::load() {
+            Some(Ok(count)) => boot::log(
+                Status::Ok,
+                &alloc::format!(
+                    "Configuration loaded from {} ({} settings)",
+                    crate::config::CONFIG_PATH,
+                    count
+                ),
+            ),
+            Some(Err(e)) => boot::log(
+                Status::Warn,
+                &alloc::format!(
+                    "Configuration in {} not loaded: {}",
+                    crate::config::CONFIG_PATH,
+                    e
+                ),
+            ),
+            None => {}
+        }
+    }
+    init_serial_ports();
+
+    if crate::replay::replaying() {
+        boot::log(
+            Status::Info,
+            "Replaying recorded input (network on loopback)",
+        );
+    } else if crate::replay::init() {
+        boot::log(
+            Status::Info,
+            &alloc::format!("Recording input to {}", crate::replay::REPLAY_FILE),
+        );
+    }
+    if crate::trace::init() {
+        boot::log(
+            Status::Info,
+            "Tracing tasks, host calls and interrupts (trace dump)",
+        );
+    }
+
+    match crate::ksym::init() {
+        0 => boot::log(Status::Warn, "No kernel symbol map (see scripts/ksyms.sh)"),
+        count => boot::log(
+            Status::Ok,
+            &alloc::format!("Kernel symbols loaded ({})", count),
+        ),
+    }
+
+    // Verify heap allocation
+    let x = Box::new(42);
+    let v: Vec<i32> = (0..10).collect();
+    boot::log(
+        Status::Ok,
+        &alloc::format!("Heap verified (boxed={}, vec_len={})", *x, v.len()),
+    );
+
+    // Output to serial only
+    crate::tests::run_all();
+    boot::log(Status::Ok, "Kernel self-tests passed");
+}
@@ -0,0 +1,416 @@
+//! Network bring-up and protocol tasks.
+
+use super::{now, Services};
+use crate::boot::health::{self, Subsystem};
+use crate::boot::{self, Status};
+use crate::net::{
+    self, dns, poller, telnetd, ConflictAction, ConnectionEvent, DhcpClient, DhcpEvent,
+    DnsResolver, DnsResult, LinkEvent, NetConfig, NetError, NetworkDevice, NetworkStack, Telnetd,
+    TftpDirection, TftpEvent, TracerouteEvent,
+};
+use crate::println;
+use crate::task::{executor::Executor, yield_now, Task};
+use crate::terminal::theme::{self, Role};
+use alloc::string::String;
+use smoltcp::wire::IpEndpoint;
+
+/// Probe the network device and create the stack, DHCP client and telnet
+/// server.
+///
+/// Without a NIC the stack runs on loopback and the network is marked
+/// degraded, as is the telnet shell if it cannot listen. The telnet shell
+/// only starts with `telnet` on the command line. In safe mode it runs on
+/// loopback without DHCP or the telnet shell.
+pub(super) fn init(phys_mem_offset: u64) -> (NetworkStack, DhcpClient, Telnetd) {
+    let device = NetworkDevice::from_cmdline(phys_mem_offset);
+    let is_slip = matches!(device, NetworkDevice::Slip(_));
+    let safe_mode = boot::crashloop::safe_mode();
+
+    match &device {
+        NetworkDevice::Loopback(_) if safe_mode => {
+            boot::log(Status::Info, "Safe mode: loopback only, no DHCP or telnet")
+        }
+        NetworkDevice::E1000(_) => boot::log(Status::Ok, "Intel e1000 PCI NIC detected"),
+        NetworkDevice::Slip(_) => boot::log(Status::Ok, "SLIP link on COM2"),
+        NetworkDevice::Loopback(_) if crate::replay::replaying() => {
+            boot::log(Status::Info, "Loopback device for replay")
+        }
+        NetworkDevice::Loopback(_) => {
+            health::degrade(Subsystem::Network, &"no NIC found, using loopback")
+        }
+    }
+    if let Some(mac) = device.mac_address() {
+        boot::log_detail(&alloc::format!(
+            "MAC: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0],
+            mac[1],
+            mac[2],
+            mac[3],
+            mac[4],
+            mac[5]
+        ));
+    }
+    if let NetworkDevice::E1000(nic) = &device {
+        match nic.enable_interrupts() {
+            Some(irq) => boot::log_detail(&alloc::format!("Receive interrupts on IRQ {}", irq)),
+            None => boot::log_detail("No usable IRQ line, polling on a timer"),
+        }
+    }
+
+    // SLIP has no broadcast medium for DHCP; its address comes from the cmdline
+    let config = if is_slip {
+        net::slip::cmdline_config()
+    } else {
+        NetConfig::dhcp()
+    };
+    let mut net_stack = NetworkStack::new(device, config);
+    boot::log(Status::Ok, "Network stack initialized");
+    if net::selftest::enabled() && health::check(Subsystem::Network, net::selftest::run()).is_some()
+    {
+        boot::log(Status::Ok, "Loopback UDP and TCP self-test passed");
+    }
+
+    let mut dhcp = DhcpClient::new();
+    if safe_mode {
+        return (net_stack, dhcp, Telnetd::new(telnetd::DEFAULT_PORT));
+    }
+    if is_slip {
+        if let Some(ip) = net_stack.ip_address() {
+            boot::log_detail(&alloc::format!("IP: {}", ip));
+        }
+    } else {
+        dhcp.start(&mut net_stack, now());
+        boot::log(Status::Info, "DHCP discovery started...");
+    }
+
+    // Still create DHCP client but don't start it automatically
+    let dhcp = DhcpClient::new();
+
+    // Remote shell (forward host port 2323 to reach it); it has no login, so
+    // it is opt-in, and without the terminal there is no shell to serve
+    let mut telnetd = Telnetd::new(telnetd::DEFAULT_PORT);
+    if cfg!(feature = "terminal") && telnetd::enabled() {
+        let started = telnetd.start(&mut net_stack);
+        if health::check(Subsystem::RemoteShell, started).is_some() {
+            boot::log(
+                Status::Ok,
+                &alloc::format!("Telnet shell listening on port {}", telnetd.port()),
+            );
+        }
+    }
+
+    (net_stack, dhcp, telnetd)
+}
+
+/// Register the stack poller and one task per protocol client.
+pub(super) fn spawn(services: &Services, executor: &mut Executor) {
+    // Network stack poller
+    {
+        let net_stack = services.net_stack.clone();
+        executor.spawn(Task::new(async move {
+            poller::PRODUCER.register();
+            loop {
+                let (event, connections, delay) = {
+                    let mut stack = net_stack.lock();
+                    let event = stack.poll_link();
+                    // Sockets keep their queues while the link is down
+                    let (connections, delay) = if stack.link_up() {
+                        stack.poll(now());
+                        stack.check_icmp();
+                        let connections = stack.poll_connections(now());
+                        stack.collect_garbage(now());
+                        (connections, stack.poll_delay(now()))
+                    } else {
+                        (alloc::vec::Vec::new(), None)
+                    };
+                    (event, connections, delay)
+                };
+                if let Some(event) = event {
+                    handle_link_event(event);
+                }
+                for (remote, event) in connections {
+                    handle_connection_event(remote, event);
+                }
+                match poller::schedule(delay) {
+                    0 => yield_now().await,
+                    ms => poller::wait(ms).await,
+                }
+            }
+        }));
+    }
+
+    // DHCP
+    {
+        let net_stack = services.net_stack.clone();
+        let dhcp = services.dhcp.clone();
+        let dns = services.dns.clone();
+        executor.spawn(Task::new(async move {
+            loop {
+                let event = {
+                    let mut stack = net_stack.lock();
+                    let mut d = dhcp.lock();
+                    d.poll(&mut stack, now())
+                };
+
+                if let Some(e) = event {
+                    let mut d_res = dns.lock();
+                    let mut stack = net_stack.lock();
+                    handle_dhcp_event(&e, &mut d_res, &mut stack);
+                }
+                yield_now().await;
+            }
+        }));
+    }
+
+    // DNS
+    {
+        let net_stack = services.net_stack.clone();
+        let dns = services.dns.clone();
+        executor.spawn(Task::new(async move {
+            dns::PRODUCER.register();
+            loop {
+                let results = {
+                    let mut stack = net_stack.lock();
+                    let mut d_res = dns.lock();
+                    d_res.poll(&mut stack, now())
+                };
+
+                for result in results {
+                    handle_dns_result(result);
+                }
+                yield_now().await;
+            }
+        }));
+    }
+
+    // Traceroute
+    {
+        let net_stack = services.net_stack.clone();
+        let traceroute = services.traceroute.clone();
+        executor.spawn(Task::new(async move {
+            loop {
+                let event = {
+                    let mut stack = net_stack.lock();
+                    let mut trace = traceroute.lock();
+                    trace.poll(&mut stack, now())
+                };
+
+                if let Some(e) = event {
+                    handle_traceroute_event(&e);
+                }
+                yield_now().await;
+            }
+        }));
+    }
+
+    // HTTP server (idle until `httpd start`)
+    {
+        let net_stack = services.net_stack.clone();
+        let httpd = services.httpd.clone();
+        executor.spawn(Task::new(async move {
+            loop {
+                {
+                    let mut stack = net_stack.lock();
+                    httpd.lock().poll(&mut stack, now());
+                }
+                yield_now().await;
+            }
+        }));
+    }
+
+    // TFTP
+    {
+        let net_stack = services.net_stack.clone();
+        let tftp = services.tftp.clone();
+        executor.spawn(Task::new(async move {
+            loop {
+                let event = {
+                    let mut stack = net_stack.lock();
+                    let mut client = tftp.lock();
+                    client.poll(&mut stack, now())
+                };
+
+                if let Some(e) = event {
+                    handle_tftp_event(&e);
+                }
+                yield_now().await;
+            }
+        }));
+    }
+
+    // Syslog (idle until `log remote`)
+    {
+        let net_stack = services.net_stack.clone();
+        let syslog = services.syslog.clone();
+        executor.spawn(Task::new(async move {
+            loop {
+                {
+                    let mut stack = net_stack.lock();
+                    syslog.lock().poll(&mut stack, now());
+                }
+                yield_now().await;
+            }
+        }));
+    }
+}
+
+/// Report the link going down or coming back.
+fn handle_link_event(event: LinkEvent) {
+    println!();
+    match event {
+        LinkEvent::Up => {
+            boot::log(Status::Ok, "Link up");
+            log::info!(target: "net", "Link up");
+        }
+        LinkEvent::Down => {
+            boot::log(Status::Warn, "Link down");
+            log::warn!(target: "net", "Link down");
+        }
+    }
+}
+
+/// Report how a connection started by the shell went.
+fn handle_connection_event(remote: Option<IpEndpoint>, event: ConnectionEvent) {
+    let peer: String = remote.map_or_else(|| "peer".into(), |remote| alloc::format!("{}", remote));
+    println!();
+    match event {
+        ConnectionEvent::Connected => {
+            boot::log(Status::Ok, &alloc::format!("Connected to {}", peer));
+            log::info!(target: "net", "Connected to {}", peer);
+        }
+        ConnectionEvent::Closed => {
+            boot::log(
+                Status::Info,
+                &alloc::format!("Connection to {} closed", peer),
+            );
+            log::info!(target: "net", "Connection to {} closed", peer);
+        }
+        ConnectionEvent::Refused | ConnectionEvent::TimedOut => {
+            boot::log(Status::Fail, &alloc::format!("{}: {}", peer, event));
+            log::warn!(target: "net", "{}: {}", peer, event);
+        }
+    }
+}
+
+/// Handle DHCP events with consistent logging.
+fn handle_dhcp_event(event: &DhcpEvent, dns: &mut DnsResolver, stack: &mut NetworkStack) {
+    match event {
+        DhcpEvent::Configured(config) => {
+            println!();
+            boot::log(
+                Status::Ok,
+                &alloc::format!("DHCP: Acquired {}/{}", config.ip, config.prefix_len),
+            );
+            if let Some(gw) = config.gateway {
+                boot::log_detail(&alloc::format!("Gateway: {}", gw));
+            }
+            if !config.dns_servers.is_empty() {
+                let dns_list: alloc::vec::Vec<_> = config
+                    .dns_servers
+                    .iter()
+                    .map(|s| alloc::format!("{}", s))
+                    .collect();
+                boot::log_detail(&alloc::format!("DNS: {}", dns_list.join(", ")));
+            }
+            if let Err(e) = dns.init(stack) {
+                log::warn!(target: "dns", "Resolver not started: {}", e);
+            }
+            log::info!(target: "dhcp", "Configured: {}", config.ip);
+        }
+        DhcpEvent::Deconfigured => {
+            log::warn!(target: "dhcp", "Deconfigured");
+        }
+        DhcpEvent::Conflict(conflict) => {
+            let action = match conflict.action {
+                ConflictAction::Declined => "lease declined",
+                ConflictAction::Reassigned => "trying another link-local address",
+                ConflictAction::Kept => "keeping it",
+            };
+            println!();
+            boot::log(
+                Status::Warn,
+                &alloc::format!(
+                    "Address conflict: {} is used by {}, {}",
+                    conflict.ip,
+                    conflict.mac,
+                    action
+                ),
+            );
+            log::warn!(target: "dhcp", "{} in use by {}; {}", conflict.ip, conflict.mac, action);
+        }
+        DhcpEvent::LinkLocalFallback(ip) => {
+            println!();
+            boot::log(
+                Status::Warn,
+                &alloc::format!("DHCP: No server, using link-local {}", ip),
+            );
+            log::warn!(target: "dhcp", "Link-local fallback: {}", ip);
+        }
+    }
+}
+
+/// Print the outcome of a DNS query started from the shell.
+fn handle_dns_result(result: Result<DnsResult, NetError>) {
+    match result {
+        Ok(res) => {
+            let addrs: alloc::vec::Vec<_> = res
+                .addresses
+                .iter()
+                .map(|a| alloc::format!("{}", a))
+                .collect();
+            println!("{} -> {}", res.hostname, addrs.join(", "));
+        }
+        Err(e) => println!("DNS lookup failed: {}", e),
+    }
+}
+
+/// Report the outcome of a TFTP transfer.
+fn handle_tftp_event(event: &TftpEvent) {
+    match event {
+        TftpEvent::Complete {
+            direction,
+            file,
+            bytes,
+        } => {
+            let verb = match direction {
+                TftpDirection::Get => "received",
+                TftpDirection::Put => "sent",
+            };
+            println!("tftp: {} {} ({} bytes)", verb, file, bytes);
+            log::info!(target: "tftp", "{} {} ({} bytes)", verb, file, bytes);
+        }
+        TftpEvent::Failed { file, error } => {
+            theme::set(Role::Error);
+            println!("tftp: {}: {}", file, error);
+            theme::reset();
+            log::warn!(target: "tftp", "{}: {}", file, error);
+        }
+    }
+}
+
+/// Print traceroute progress, one line per hop.
+fn handle_traceroute_event(event: &TracerouteEvent) {
+    match event {
+        TracerouteEvent::Hop(hop) => {
+            let mut line = alloc::format!("{:>3}  ", hop.ttl);
+            match hop.address {
+                Some(addr) => line.push_str(&alloc::format!("{:<15}", addr)),
+                None => line.push_str(&alloc::format!("{:<15}", "*")),
+            }
+            for rtt in &hop.rtts {
+                match rtt {
+                    Some(rtt) => line.push_str(&alloc::format!("  {} ms", rtt.total_millis())),
+                    None => line.push_str("  *"),
+                }
+            }
+            println!("{}", line);
+        }
+        TracerouteEvent::Finished { target, reached } => {
+            if *reached {
+                println!("traceroute: reached {}", target);
+            } else {
+                println!("traceroute: {} not reached", target);
+            }
+        }
+    }
+}
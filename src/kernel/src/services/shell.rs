@@ -0,0 +1,239 @@
+//! Shell sessions: command execution, the local keyboard and telnet.
+
+#[cfg(feature = "net")]
+use super::now;
+use super::Services;
+use crate::boot;
+#[cfg(feature = "net")]
+use crate::net::TelnetEvent;
+#[cfg(feature = "net")]
+use crate::println;
+use crate::sync::TrackedMutex;
+use crate::task::keyboard::ScancodeStream;
+#[cfg(feature = "net")]
+use crate::task::yield_now;
+use crate::task::{executor::Executor, Priority, Task};
+#[cfg(feature = "net")]
+use crate::terminal::io;
+#[cfg(feature = "net")]
+use crate::terminal::theme::{self, Role};
+use crate::terminal::{self, decode_scancode, Command, CommandContext, Output, Terminal};
+use crate::time;
+#[cfg(feature = "net")]
+use alloc::{boxed::Box, string::String, vec::Vec};
+use futures_util::future::{self, Either};
+use futures_util::StreamExt;
+
+/// How often the local shell looks for new log records while following
+/// the log (`dmesg --follow`) and no key is pressed.
+const FOLLOW_POLL_MS: u64 = 50;
+
+/// Executes shell commands against the kernel services.
+///
+/// Cloned into every task that runs a shell (local keyboard, telnet).
+#[derive(Clone)]
+pub struct Shell {
+    // A kernel with neither net nor WASM gives commands nothing from it
+    #[cfg_attr(not(any(feature = "net", feature = "wasm")), allow(dead_code))]
+    services: Services,
+}
+
+impl Shell {
+    /// Create a shell over `services`.
+    pub fn new(services: Services) -> Self {
+        Self { services }
+    }
+
+    /// Resolve the command's host argument (if any) and execute it.
+    ///
+    /// The output is captured and shown through the terminal's pager.
+    pub async fn run(&self, command: Command, terminal: &TrackedMutex<Terminal>) {
+        if let Some(output) = self.execute(command, terminal).await {
+            terminal.lock().page(output);
+        }
+    }
+
+    /// Resolve the command's host argument (if any) and execute it,
+    /// returning its output.
+    ///
+    /// Returns `None` if the host could not be resolved; that error, like
+    /// the resolved address, is printed rather than captured.
+    pub async fn execute(
+        &self,
+        command: Command,
+        terminal: &TrackedMutex<Terminal>,
+    ) -> Option<Output> {
+        #[cfg(feature = "net")]
+        let command = self.resolve_host(command).await?;
+        #[cfg(any(feature = "net", feature = "wasm"))]
+        let s = &self.services;
+        let mut t = terminal.lock();
+        #[cfg(feature = "net")]
+        let (mut stack, mut d, mut d_res, mut trace, mut server, mut client, mut sink) = (
+            s.net_stack.lock(),
+            s.dhcp.lock(),
+            s.dns.lock(),
+            s.traceroute.lock(),
+            s.httpd.lock(),
+            s.tftp.lock(),
+            s.syslog.lock(),
+        );
+        #[cfg(feature = "wasm")]
+        let mut processes = s.processes.lock();
+        let output = terminal::capture(|| {
+            command.execute(&mut CommandContext {
+                #[cfg(feature = "net")]
+                stack: &mut stack,
+                #[cfg(feature = "net")]
+                dhcp: &mut d,
+                #[cfg(feature = "net")]
+                dns: &mut d_res,
+                #[cfg(feature = "net")]
+                traceroute: &mut trace,
+                #[cfg(feature = "net")]
+                httpd: &mut server,
+                #[cfg(feature = "net")]
+                tftp: &mut client,
+                #[cfg(feature = "net")]
+                syslog: &mut sink,
+                #[cfg(feature = "wasm")]
+                processes: &mut processes,
+                terminal: &mut t,
+                #[cfg(feature = "net")]
+                timestamp: now(),
+            })
+        });
+        Some(output)
+    }
+
+    /// Resolve a command's hostname argument, if it has one.
+    ///
+    /// Returns `None` (after reporting the error) if resolution failed. The
+    /// locks are released while waiting so the DNS task can make progress.
+    #[cfg(feature = "net")]
+    async fn resolve_host(&self, mut command: Command) -> Option<Command> {
+        let Some(host) = command.host_to_resolve().map(String::from) else {
+            return Some(command);
+        };
+
+        let lookup = {
+            let mut stack = self.services.net_stack.lock();
+            let mut d_res = self.services.dns.lock();
+            d_res.resolve_async(&mut stack, &host, now())
+        };
+
+        match lookup.await {
+            Ok(addr) => {
+                println!("{} is {}", host, addr);
+                command.set_resolved_host(addr);
+                Some(command)
+            }
+            Err(e) => {
+                theme::set(Role::Error);
+                println!("{}: {}", host, e);
+                theme::reset();
+                None
+            }
+        }
+    }
+}
+
+/// Register the keyboard and telnet session tasks, and the shell tests if
+/// the kernel was booted to run them.
+pub(super) fn spawn(services: &Services, executor: &mut Executor) {
+    // Local keyboard, ahead of network churn; what it awaits is lent its
+    // priority (see `task::donate`)
+    {
+        let shell = services.shell();
+        // Created here so keys pressed before the task first runs are kept
+        let mut scancodes = ScancodeStream::new();
+        let session = async move {
+            // The boot banner came before the filesystem was mounted
+            boot::banner::print_motd();
+            let terminal = TrackedMutex::new("terminal", Terminal::new());
+            terminal.lock().prompt();
+
+            loop {
+                // Sleeps until a key is pressed, or while the log is being
+                // followed, until it is time to look for new records
+                let following = terminal.lock().is_following();
+                let scancode = if following {
+                    match future::select(scancodes.next(), time::sleep_ms(FOLLOW_POLL_MS)).await {
+                        Either::Left((scancode, _)) => scancode,
+                        Either::Right(_) => None,
+                    }
+                } else {
+                    scancodes.next().await
+                };
+                if let Some(key) = scancode.and_then(decode_scancode) {
+                    let command = terminal.lock().handle_key(key);
+                    if let Some(command) = command {
+                        shell.run(command, &terminal).await;
+                        terminal.lock().prompt();
+                    }
+                }
+                terminal.lock().poll_follow();
+            }
+        };
+        executor.spawn(Task::with_priority(session, Priority::High));
+    }
+
+    // Telnet session
+    #[cfg(feature = "net")]
+    {
+        let telnetd = services.telnetd.clone();
+        let net_stack = services.net_stack.clone();
+        let shell = services.shell();
+
+        executor.spawn(Task::new(async move {
+            // Everything this task prints goes to the remote peer
+            let output = telnetd.lock().output();
+            io::attach(Box::new(output));
+
+            let terminal = TrackedMutex::new("terminal", Terminal::new());
+            let mut keys = Vec::new();
+
+            loop {
+                let event = {
+                    let mut stack = net_stack.lock();
+                    let mut td = telnetd.lock();
+                    let event = td.poll(&mut stack);
+                    td.read_keys(&mut stack, &mut keys);
+                    event
+                };
+
+                match event {
+                    Some(TelnetEvent::Connected) => {
+                        log::info!(target: "telnetd", "Session opened");
+                        *terminal.lock() = Terminal::new();
+                        boot::banner::print_banner();
+                        terminal.lock().prompt();
+                    }
+                    Some(TelnetEvent::Disconnected) => {
+                        log::info!(target: "telnetd", "Session closed");
+                    }
+                    None => {}
+                }
+
+                for key in keys.drain(..) {
+                    let command = terminal.lock().handle_key(key);
+                    if let Some(command) = command {
+                        shell.run(command, &terminal).await;
+                        terminal.lock().prompt();
+                    }
+                }
+                terminal.lock().poll_follow();
+
+                {
+                    let mut stack = net_stack.lock();
+                    telnetd.lock().flush(&mut stack);
+                }
+                yield_now().await;
+            }
+        }));
+    }
+
+    if crate::testutil::shell::enabled() {
+        executor.spawn(Task::new(crate::testutil::shell::run(services.shell())));
+    }
+}
@@ -0,0 +1,106 @@
+//! Futex-style wait queues keyed by memory address.
+//!
+//! `sp_futex_wait` lets a WASM process sleep until someone calls
+//! `sp_futex_wake` on the same address, so userspace can build its own
+//! locks and channels on memory it shares: the uncontended path is a plain
+//! atomic in linear memory, and the kernel is only entered to wait or to
+//! wake a waiter.
+//!
+//! An address only means something within its memory, so a queue is keyed
+//! by a `FutexKey`: the memory's space and the offset in it. Each process's
+//! linear memory is a space of its own (see `new_space`); a shared memory
+//! region is to be one space for every process that maps it.
+//!
+//! A waiter is a `FutexWaiter` shared by the queue and the waiting task.
+//! Waking sets its flag, which the task sees the next time the executor
+//! looks at it. Waiters whose task has gone (it timed out, or the process
+//! ended) are dropped from the queue when it is next used.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::TrackedMutex;
+
+/// Wait queues by key. Host calls run to completion, so checking the word
+/// and queueing the waiter cannot race a wake.
+static QUEUES: TrackedMutex<BTreeMap<FutexKey, VecDeque<Arc<FutexWaiter>>>> =
+    TrackedMutex::new("futex_queues", BTreeMap::new());
+
+/// Next space to hand out.
+static NEXT_SPACE: AtomicU64 = AtomicU64::new(1);
+
+/// A word of memory that tasks wait on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FutexKey {
+    /// The memory the word is in.
+    pub space: u64,
+    /// Offset of the word in that memory.
+    pub addr: u32,
+}
+
+/// One task waiting on a key.
+#[derive(Debug, Default)]
+pub struct FutexWaiter {
+    woken: AtomicBool,
+}
+
+impl FutexWaiter {
+    /// Whether the waiter has been woken.
+    pub fn is_woken(&self) -> bool {
+        self.woken.load(Ordering::Acquire)
+    }
+}
+
+/// A space for a memory whose words are waited on independently of all
+/// others.
+pub fn new_space() -> u64 {
+    NEXT_SPACE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Queue a waiter on `key`. The caller has checked the word and keeps the
+/// waiter until it is woken or gives up.
+pub fn wait(key: FutexKey) -> Arc<FutexWaiter> {
+    let waiter = Arc::new(FutexWaiter::default());
+    let mut queues = QUEUES.lock();
+    let queue = queues.entry(key).or_default();
+    queue.retain(is_waiting);
+    queue.push_back(waiter.clone());
+    waiter
+}
+
+/// Wake up to `count` waiters on `key`, oldest first. Returns how many
+/// were woken.
+pub fn wake(key: FutexKey, count: usize) -> usize {
+    let mut queues = QUEUES.lock();
+    let Some(queue) = queues.get_mut(&key) else {
+        return 0;
+    };
+    let mut woken = 0;
+    while woken < count {
+        let Some(waiter) = queue.pop_front() else {
+            break;
+        };
+        if is_waiting(&waiter) {
+            waiter.woken.store(true, Ordering::Release);
+            woken += 1;
+        }
+    }
+    queue.retain(is_waiting);
+    if queue.is_empty() {
+        queues.remove(&key);
+    }
+    woken
+}
+
+/// Number of tasks waiting on `key`.
+pub fn waiters(key: FutexKey) -> usize {
+    QUEUES.lock().get(&key).map_or(0, |queue| {
+        queue.iter().filter(|waiter| is_waiting(waiter)).count()
+    })
+}
+
+/// Whether a queued waiter still has a task behind it.
+fn is_waiting(waiter: &Arc<FutexWaiter>) -> bool {
+    Arc::strong_count(waiter) > 1 && !waiter.is_woken()
+}
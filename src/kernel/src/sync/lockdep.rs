@@ -0,0 +1,380 @@
+//! Spinlock diagnostics ("lockdep-lite").
+//!
+//! `TrackedMutex` wraps `spin::Mutex` for the kernel's long-lived locks:
+//! the screen and serial port, the network services, the terminals and the
+//! registries. A spinlock deadlock just hangs the machine, so every
+//! tracked lock has a name, its class, and acquisitions are checked for:
+//!
+//! - recursion: a contended lock this context already holds;
+//! - spinning with interrupts disabled: on this single-CPU kernel nothing
+//!   can release the lock then;
+//! - order inversion: taking B while holding A after B was held while
+//!   taking A (pairs only; longer cycles are not detected);
+//! - long holds, over `LONG_HOLD_CYCLES` TSC cycles.
+//!
+//! Each problem is reported once per class (or pair) to the log, or
+//! straight to COM1 while the serial lock is held. Per-class counters are
+//! available through `stats` (shell: `locks`).
+
+use crate::arch::x86_64::{self, serial};
+use crate::boot::panic::FixedWriter;
+use ::x86_64::instructions::interrupts;
+use core::fmt::{self, Write};
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Maximum number of lock classes; later classes are not tracked.
+pub const MAX_CLASSES: usize = 32;
+
+/// Maximum locks held at once that are checked for ordering.
+const MAX_HELD: usize = 16;
+
+/// Hold time reported as too long (about 50 ms at 2 GHz).
+pub const LONG_HOLD_CYCLES: u64 = 100_000_000;
+
+/// Size of the buffer for reports written straight to COM1.
+const RAW_REPORT_LEN: usize = 160;
+
+/// Class index meaning "not tracked".
+const UNTRACKED: usize = usize::MAX;
+
+/// Per-class counters.
+struct ClassStats {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    max_hold: AtomicU64,
+}
+
+impl ClassStats {
+    const fn new() -> Self {
+        Self {
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            max_hold: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Names of the registered classes, by index.
+static CLASSES: Mutex<[&str; MAX_CLASSES]> = Mutex::new([""; MAX_CLASSES]);
+
+/// Number of registered classes.
+static CLASS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_STATS: ClassStats = ClassStats::new();
+
+/// Counters, by class index.
+static STATS: [ClassStats; MAX_CLASSES] = [NO_STATS; MAX_CLASSES];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_EDGES: AtomicU32 = AtomicU32::new(0);
+
+/// Bit `b` of `ORDER[a]`: class `b` was taken while `a` was held.
+static ORDER: [AtomicU32; MAX_CLASSES] = [NO_EDGES; MAX_CLASSES];
+
+/// Bit `b` of `INVERSIONS[a]`: the inversion of `a` and `b` was reported.
+static INVERSIONS: [AtomicU32; MAX_CLASSES] = [NO_EDGES; MAX_CLASSES];
+
+/// Classes reported for recursive locking.
+static REPORTED_RECURSION: AtomicU32 = AtomicU32::new(0);
+
+/// Classes reported for spinning with interrupts disabled.
+static REPORTED_IRQS_OFF: AtomicU32 = AtomicU32::new(0);
+
+/// Classes reported for a long hold.
+static REPORTED_LONG_HOLD: AtomicU32 = AtomicU32::new(0);
+
+/// Set while a report is written, so locks taken by the log are not checked.
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+/// Classes currently held, in acquisition order.
+struct Held {
+    classes: [usize; MAX_HELD],
+    len: usize,
+}
+
+static HELD: Mutex<Held> = Mutex::new(Held {
+    classes: [UNTRACKED; MAX_HELD],
+    len: 0,
+});
+
+/// Counters for one lock class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockStats {
+    /// Class name.
+    pub name: &'static str,
+    /// Times the lock was taken.
+    pub acquisitions: u64,
+    /// Times the lock was already held when requested.
+    pub contended: u64,
+    /// Longest hold, in TSC cycles.
+    pub max_hold_cycles: u64,
+}
+
+/// Counters of every registered class, in registration order.
+pub fn stats() -> alloc::vec::Vec<LockStats> {
+    let names = interrupts::without_interrupts(|| *CLASSES.lock());
+    let count = CLASS_COUNT.load(Ordering::Acquire).min(MAX_CLASSES);
+    (0..count)
+        .map(|class| LockStats {
+            name: names[class],
+            acquisitions: STATS[class].acquisitions.load(Ordering::Relaxed),
+            contended: STATS[class].contended.load(Ordering::Relaxed),
+            max_hold_cycles: STATS[class].max_hold.load(Ordering::Relaxed),
+        })
+        .collect()
+}
+
+/// Find or register the class called `name`.
+fn class_of(name: &'static str) -> usize {
+    interrupts::without_interrupts(|| {
+        let mut names = CLASSES.lock();
+        let count = CLASS_COUNT.load(Ordering::Acquire);
+        if let Some(class) = names[..count].iter().position(|n| *n == name) {
+            return class;
+        }
+        if count == MAX_CLASSES {
+            return UNTRACKED;
+        }
+        names[count] = name;
+        CLASS_COUNT.store(count + 1, Ordering::Release);
+        count
+    })
+}
+
+fn bit(class: usize) -> u32 {
+    1 << class
+}
+
+/// Report a problem once per `key` bit in `reported`.
+fn report_once(reported: &AtomicU32, key: usize, args: fmt::Arguments) {
+    if reported.fetch_or(bit(key), Ordering::Relaxed) & bit(key) == 0 {
+        report(args);
+    }
+}
+
+/// Write a report to the log, or straight to COM1 if the log could block.
+fn report(args: fmt::Arguments) {
+    if REPORTING.swap(true, Ordering::Acquire) {
+        return;
+    }
+    let serial_busy = serial::SERIAL.get().is_some_and(|port| port.is_locked());
+    if serial_busy || !interrupts::are_enabled() {
+        let mut buffer = [0u8; RAW_REPORT_LEN];
+        let mut writer = FixedWriter::new(&mut buffer);
+        let _ = writer.write_fmt(args);
+        serial::write_raw(b"[lockdep] ");
+        serial::write_raw(writer.as_bytes());
+        serial::write_raw(b"\n");
+    } else {
+        log::warn!(target: "lockdep", "{}", args);
+    }
+    REPORTING.store(false, Ordering::Release);
+}
+
+/// Checks before spinning on a contended lock.
+fn contended(class: usize, name: &str) {
+    STATS[class].contended.fetch_add(1, Ordering::Relaxed);
+    let held = interrupts::without_interrupts(|| {
+        let held = HELD.lock();
+        held.classes[..held.len].contains(&class)
+    });
+    if held {
+        report_once(
+            &REPORTED_RECURSION,
+            class,
+            format_args!("recursive locking of '{}' (deadlock)", name),
+        );
+    }
+    if !interrupts::are_enabled() {
+        report_once(
+            &REPORTED_IRQS_OFF,
+            class,
+            format_args!("spinning on '{}' with interrupts disabled", name),
+        );
+    }
+}
+
+/// Record that `class` was taken: check ordering and push it as held.
+fn acquired(class: usize) {
+    STATS[class].acquisitions.fetch_add(1, Ordering::Relaxed);
+    let mut inversion = None;
+    interrupts::without_interrupts(|| {
+        let mut held = HELD.lock();
+        for &outer in &held.classes[..held.len] {
+            if outer == class || outer == UNTRACKED {
+                continue;
+            }
+            ORDER[outer].fetch_or(bit(class), Ordering::Relaxed);
+            if ORDER[class].load(Ordering::Relaxed) & bit(outer) != 0 {
+                inversion = Some(outer);
+            }
+        }
+        if held.len < MAX_HELD {
+            let len = held.len;
+            held.classes[len] = class;
+            held.len += 1;
+        }
+    });
+    if let Some(outer) = inversion {
+        let (first, second) = (outer.min(class), outer.max(class));
+        let names = interrupts::without_interrupts(|| *CLASSES.lock());
+        report_once(
+            &INVERSIONS[first],
+            second,
+            format_args!(
+                "lock order inversion: '{}' taken while holding '{}', elsewhere the reverse",
+                names[class], names[outer]
+            ),
+        );
+    }
+}
+
+/// Record that `class` was released after `cycles`.
+fn released(class: usize, cycles: u64, name: &str) {
+    interrupts::without_interrupts(|| {
+        let mut held = HELD.lock();
+        let len = held.len;
+        if let Some(pos) = held.classes[..len].iter().rposition(|c| *c == class) {
+            held.classes.copy_within(pos + 1..len, pos);
+            held.len -= 1;
+        }
+    });
+    STATS[class].max_hold.fetch_max(cycles, Ordering::Relaxed);
+    if cycles > LONG_HOLD_CYCLES {
+        report_once(
+            &REPORTED_LONG_HOLD,
+            class,
+            format_args!("'{}' held for {} cycles", name, cycles),
+        );
+    }
+}
+
+/// A spin mutex with hold-time statistics and deadlock checks.
+pub struct TrackedMutex<T: ?Sized> {
+    name: &'static str,
+    class: AtomicUsize,
+    inner: Mutex<T>,
+}
+
+impl<T> TrackedMutex<T> {
+    /// Create a mutex in lock class `name`.
+    ///
+    /// Mutexes with the same name share a class, e.g. every terminal.
+    pub const fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            class: AtomicUsize::new(UNTRACKED),
+            inner: Mutex::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> TrackedMutex<T> {
+    /// Name of the lock class.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Whether the lock is held.
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Class index, registering it on first use.
+    fn class(&self) -> usize {
+        let class = self.class.load(Ordering::Relaxed);
+        if class != UNTRACKED {
+            return class;
+        }
+        let class = class_of(self.name);
+        self.class.store(class, Ordering::Relaxed);
+        class
+    }
+
+    /// Acquire the lock, spinning until it is free.
+    pub fn lock(&self) -> TrackedMutexGuard<'_, T> {
+        let class = self.class();
+        let tracked = class != UNTRACKED && !REPORTING.load(Ordering::Relaxed);
+        let inner = match self.inner.try_lock() {
+            Some(guard) => guard,
+            None => {
+                if tracked {
+                    contended(class, self.name);
+                }
+                self.inner.lock()
+            }
+        };
+        self.guard(inner, class, tracked)
+    }
+
+    /// Acquire the lock if it is free.
+    pub fn try_lock(&self) -> Option<TrackedMutexGuard<'_, T>> {
+        let class = self.class();
+        let tracked = class != UNTRACKED && !REPORTING.load(Ordering::Relaxed);
+        let inner = self.inner.try_lock()?;
+        Some(self.guard(inner, class, tracked))
+    }
+
+    fn guard<'a>(
+        &'a self,
+        inner: spin::MutexGuard<'a, T>,
+        class: usize,
+        tracked: bool,
+    ) -> TrackedMutexGuard<'a, T> {
+        if tracked {
+            acquired(class);
+        }
+        TrackedMutexGuard {
+            inner: ManuallyDrop::new(inner),
+            name: self.name,
+            class,
+            tracked,
+            start: x86_64::rdtsc(),
+        }
+    }
+}
+
+impl<T: Default> Default for TrackedMutex<T> {
+    fn default() -> Self {
+        Self::new("unnamed", T::default())
+    }
+}
+
+/// Guard of a `TrackedMutex`; releasing it records the hold time.
+pub struct TrackedMutexGuard<'a, T: ?Sized> {
+    inner: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    name: &'static str,
+    class: usize,
+    tracked: bool,
+    start: u64,
+}
+
+impl<T: ?Sized> Deref for TrackedMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> DerefMut for TrackedMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: ?Sized> Drop for TrackedMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let cycles = x86_64::rdtsc().saturating_sub(self.start);
+        // SAFETY: `inner` is dropped exactly once, here, and not used after.
+        // Releasing before reporting lets the report take this lock.
+        unsafe { ManuallyDrop::drop(&mut self.inner) };
+        if self.tracked {
+            released(self.class, cycles, self.name);
+        }
+    }
+}
@@ -8,6 +8,9 @@
 //!
 //! - [`AsyncMutex<T>`]: Exclusive lock that yields when contended
 //! - [`Semaphore`]: Counting semaphore for limiting concurrent access
+//! - [`futex`]: Wait queues keyed by an address in WASM memory
+//! - [`TrackedMutex<T>`]: Spinlock with hold-time statistics and deadlock
+//!   checks, for the kernel's own shared state (see [`lockdep`])
 //!
 //! # WASM Integration
 //!
@@ -34,9 +37,12 @@
 //! sem.release();
 //! ```
 
+pub mod futex;
+pub mod lockdep;
 mod mutex;
 pub mod registry;
 mod semaphore;
 
+pub use lockdep::{TrackedMutex, TrackedMutexGuard};
 pub use mutex::{AsyncMutex, AsyncMutexGuard, AsyncMutexLockFuture};
 pub use semaphore::{Semaphore, SemaphoreAcquireFuture, SemaphorePermit};
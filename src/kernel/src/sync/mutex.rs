@@ -1,8 +1,10 @@
 //! Async-aware mutex for cooperative multitasking.
 //!
 //! This module provides an async mutex that yields to the scheduler when
-//! contended, integrating with the kernel's async executor.
+//! contended, integrating with the kernel's async executor. A task waiting
+//! for the lock donates its priority to the holder (see `task::donate`).
 
+use crate::task::donate::{self, Donation, Producer};
 use alloc::sync::Arc;
 use core::{
     cell::UnsafeCell,
@@ -39,6 +41,8 @@ pub struct AsyncMutex<T> {
     locked: AtomicBool,
     /// FIFO queue of waiters to wake.
     waiters: ArrayQueue<Waker>,
+    /// Task holding the lock, which waiters donate their priority to.
+    holder: Producer,
 }
 
 // Safety: The mutex provides synchronized access to T.
@@ -54,6 +58,7 @@ impl<T> AsyncMutex<T> {
             data: UnsafeCell::new(data),
             locked: AtomicBool::new(false),
             waiters: ArrayQueue::new(MAX_WAITERS),
+            holder: Producer::new(),
         }
     }
 
@@ -72,7 +77,7 @@ impl<T> AsyncMutex<T> {
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
-            Some(AsyncMutexGuard { mutex: self })
+            Some(AsyncMutexGuard::new(self))
         } else {
             None
         }
@@ -87,9 +92,20 @@ impl<T> AsyncMutex<T> {
         AsyncMutexLockFuture {
             mutex: self,
             registered: false,
+            donation: None,
         }
     }
 
+    /// Whether the lock is held.
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Number of tasks queued waiting for the lock.
+    pub fn waiters(&self) -> usize {
+        self.waiters.len()
+    }
+
     /// Wake the next waiter in the queue, if any.
     fn wake_next(&self) {
         if let Some(waker) = self.waiters.pop() {
@@ -103,6 +119,14 @@ pub struct AsyncMutexGuard<'a, T> {
     mutex: &'a AsyncMutex<T>,
 }
 
+impl<'a, T> AsyncMutexGuard<'a, T> {
+    /// Guard a just-acquired lock, recording the current task as holder.
+    fn new(mutex: &'a AsyncMutex<T>) -> Self {
+        mutex.holder.register();
+        Self { mutex }
+    }
+}
+
 impl<T> Deref for AsyncMutexGuard<'_, T> {
     type Target = T;
 
@@ -122,6 +146,7 @@ impl<T> DerefMut for AsyncMutexGuard<'_, T> {
 impl<T> Drop for AsyncMutexGuard<'_, T> {
     fn drop(&mut self) {
         // Release the lock
+        self.mutex.holder.set(None);
         self.mutex.locked.store(false, Ordering::Release);
         // Wake the next waiter
         self.mutex.wake_next();
@@ -132,6 +157,8 @@ impl<T> Drop for AsyncMutexGuard<'_, T> {
 pub struct AsyncMutexLockFuture<'a, T> {
     mutex: &'a AsyncMutex<T>,
     registered: bool,
+    /// Priority lent to the holder while waiting.
+    donation: Option<Donation>,
 }
 
 impl<'a, T> Future for AsyncMutexLockFuture<'a, T> {
@@ -147,7 +174,8 @@ impl<'a, T> Future for AsyncMutexLockFuture<'a, T> {
             .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
-            return Poll::Ready(AsyncMutexGuard { mutex: this.mutex });
+            this.donation = None;
+            return Poll::Ready(AsyncMutexGuard::new(this.mutex));
         }
 
         // Slow path: register waker and retry
@@ -164,9 +192,12 @@ impl<'a, T> Future for AsyncMutexLockFuture<'a, T> {
             .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
-            return Poll::Ready(AsyncMutexGuard { mutex: this.mutex });
+            this.donation = None;
+            return Poll::Ready(AsyncMutexGuard::new(this.mutex));
         }
 
+        // The holder may have changed since the last poll
+        this.donation = donate::donate(&this.mutex.holder);
         Poll::Pending
     }
 }
@@ -2,19 +2,47 @@
 //!
 //! This module provides thread-safe registries for mutexes and semaphores
 //! that are exposed to WASM modules via host functions.
+//!
+//! Each object records the process that created it. When that process
+//! ends, its objects are destroyed (see `release`) unless they were marked
+//! shared with `share`, in which case they outlive it without an owner.
+//! `list` reports the live objects for the `sync` command.
 
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU64, Ordering};
-use spin::{Mutex, Once};
+use spin::Once;
+
+use super::{AsyncMutex, Semaphore, TrackedMutex};
+
+/// A registered object.
+struct Entry<T> {
+    object: Arc<T>,
+    /// Process that created it, while it lives.
+    owner: Option<u64>,
+    /// Whether a capability for it was shared, so it outlives its owner.
+    shared: bool,
+}
 
-use super::{AsyncMutex, Semaphore};
+impl<T> Entry<T> {
+    fn new(object: T, owner: Option<u64>) -> Self {
+        Self {
+            object: Arc::new(object),
+            owner,
+            shared: false,
+        }
+    }
+}
+
+/// Registered objects by handle.
+type Registry<T> = TrackedMutex<BTreeMap<u64, Entry<T>>>;
 
 /// Global registry for mutexes accessible from WASM.
-static MUTEX_REGISTRY: Once<Mutex<BTreeMap<u64, Arc<AsyncMutex<()>>>>> = Once::new();
+static MUTEX_REGISTRY: Once<Registry<AsyncMutex<()>>> = Once::new();
 
 /// Global registry for semaphores accessible from WASM.
-static SEM_REGISTRY: Once<Mutex<BTreeMap<u64, Arc<Semaphore>>>> = Once::new();
+static SEM_REGISTRY: Once<Registry<Semaphore>> = Once::new();
 
 /// Next handle ID for mutexes.
 static NEXT_MUTEX_ID: AtomicU64 = AtomicU64::new(1);
@@ -22,35 +50,78 @@ static NEXT_MUTEX_ID: AtomicU64 = AtomicU64::new(1);
 /// Next handle ID for semaphores.
 static NEXT_SEM_ID: AtomicU64 = AtomicU64::new(1);
 
+/// Kind of a registered object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncKind {
+    /// An `AsyncMutex`.
+    Mutex,
+    /// A `Semaphore`.
+    Semaphore,
+}
+
+impl SyncKind {
+    /// Name as shown by `sync list`.
+    pub fn name(self) -> &'static str {
+        match self {
+            SyncKind::Mutex => "mutex",
+            SyncKind::Semaphore => "semaphore",
+        }
+    }
+}
+
+/// A live object, as reported by `list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncInfo {
+    /// Mutex or semaphore.
+    pub kind: SyncKind,
+    /// Registry handle.
+    pub handle: u64,
+    /// Process that created it, unless it has exited.
+    pub owner: Option<u64>,
+    /// Whether it outlives its owner.
+    pub shared: bool,
+    /// Whether the mutex is held; for a semaphore, whether no permits are
+    /// left.
+    pub busy: bool,
+    /// Available permits (0 or 1 for a mutex).
+    pub permits: usize,
+    /// Tasks queued waiting for it.
+    pub waiters: usize,
+}
+
 /// Initialize the sync registries.
 fn init_registries() {
-    MUTEX_REGISTRY.call_once(|| Mutex::new(BTreeMap::new()));
-    SEM_REGISTRY.call_once(|| Mutex::new(BTreeMap::new()));
+    MUTEX_REGISTRY.call_once(|| TrackedMutex::new("mutex_registry", BTreeMap::new()));
+    SEM_REGISTRY.call_once(|| TrackedMutex::new("semaphore_registry", BTreeMap::new()));
 }
 
 /// Get the mutex registry, initializing if needed.
-fn mutex_registry() -> &'static Mutex<BTreeMap<u64, Arc<AsyncMutex<()>>>> {
+fn mutex_registry() -> &'static Registry<AsyncMutex<()>> {
     init_registries();
     MUTEX_REGISTRY.get().expect("mutex registry initialized")
 }
 
 /// Get the semaphore registry, initializing if needed.
-fn sem_registry() -> &'static Mutex<BTreeMap<u64, Arc<Semaphore>>> {
+fn sem_registry() -> &'static Registry<Semaphore> {
     init_registries();
     SEM_REGISTRY.get().expect("sem registry initialized")
 }
 
-/// Create a new mutex and return its handle.
-pub fn create_mutex() -> u64 {
+/// Create a new mutex owned by process `owner` and return its handle.
+pub fn create_mutex(owner: Option<u64>) -> u64 {
     let handle = NEXT_MUTEX_ID.fetch_add(1, Ordering::Relaxed);
-    let mutex = Arc::new(AsyncMutex::new(()));
-    mutex_registry().lock().insert(handle, mutex);
+    mutex_registry()
+        .lock()
+        .insert(handle, Entry::new(AsyncMutex::new(()), owner));
     handle
 }
 
 /// Get a mutex by handle.
 pub fn get_mutex(handle: u64) -> Option<Arc<AsyncMutex<()>>> {
-    mutex_registry().lock().get(&handle).cloned()
+    mutex_registry()
+        .lock()
+        .get(&handle)
+        .map(|entry| entry.object.clone())
 }
 
 /// Destroy a mutex by handle.
@@ -58,17 +129,21 @@ pub fn destroy_mutex(handle: u64) -> bool {
     mutex_registry().lock().remove(&handle).is_some()
 }
 
-/// Create a new semaphore and return its handle.
-pub fn create_semaphore(permits: usize) -> u64 {
+/// Create a new semaphore owned by process `owner` and return its handle.
+pub fn create_semaphore(permits: usize, owner: Option<u64>) -> u64 {
     let handle = NEXT_SEM_ID.fetch_add(1, Ordering::Relaxed);
-    let sem = Arc::new(Semaphore::new(permits));
-    sem_registry().lock().insert(handle, sem);
+    sem_registry()
+        .lock()
+        .insert(handle, Entry::new(Semaphore::new(permits), owner));
     handle
 }
 
 /// Get a semaphore by handle.
 pub fn get_semaphore(handle: u64) -> Option<Arc<Semaphore>> {
-    sem_registry().lock().get(&handle).cloned()
+    sem_registry()
+        .lock()
+        .get(&handle)
+        .map(|entry| entry.object.clone())
 }
 
 /// Destroy a semaphore by handle.
@@ -76,14 +151,86 @@ pub fn destroy_semaphore(handle: u64) -> bool {
     sem_registry().lock().remove(&handle).is_some()
 }
 
+/// Mark an object as shared, so it outlives its owner. Returns `false` if
+/// there is no such object.
+pub fn share(kind: SyncKind, handle: u64) -> bool {
+    fn mark<T>(registry: &Registry<T>, handle: u64) -> bool {
+        match registry.lock().get_mut(&handle) {
+            Some(entry) => {
+                entry.shared = true;
+                true
+            }
+            None => false,
+        }
+    }
+    match kind {
+        SyncKind::Mutex => mark(mutex_registry(), handle),
+        SyncKind::Semaphore => mark(sem_registry(), handle),
+    }
+}
+
+/// The creator of an object has ended: destroy the object unless it was
+/// shared, in which case it is kept without an owner. Returns whether it
+/// was destroyed.
+pub fn release(kind: SyncKind, handle: u64) -> bool {
+    fn release_in<T>(registry: &Registry<T>, handle: u64) -> bool {
+        let mut objects = registry.lock();
+        match objects.get_mut(&handle) {
+            Some(entry) if entry.shared => {
+                entry.owner = None;
+                false
+            }
+            Some(_) => objects.remove(&handle).is_some(),
+            None => false,
+        }
+    }
+    match kind {
+        SyncKind::Mutex => release_in(mutex_registry(), handle),
+        SyncKind::Semaphore => release_in(sem_registry(), handle),
+    }
+}
+
+/// Live objects: mutexes, then semaphores, by handle.
+pub fn list() -> Vec<SyncInfo> {
+    let mut list: Vec<SyncInfo> = mutex_registry()
+        .lock()
+        .iter()
+        .map(|(&handle, entry)| {
+            let locked = entry.object.is_locked();
+            SyncInfo {
+                kind: SyncKind::Mutex,
+                handle,
+                owner: entry.owner,
+                shared: entry.shared,
+                busy: locked,
+                permits: usize::from(!locked),
+                waiters: entry.object.waiters(),
+            }
+        })
+        .collect();
+    list.extend(sem_registry().lock().iter().map(|(&handle, entry)| {
+        let permits = entry.object.available_permits();
+        SyncInfo {
+            kind: SyncKind::Semaphore,
+            handle,
+            owner: entry.owner,
+            shared: entry.shared,
+            busy: permits == 0,
+            permits,
+            waiters: entry.object.waiters(),
+        }
+    }));
+    list
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_mutex_registry() {
-        let h1 = create_mutex();
-        let h2 = create_mutex();
+        let h1 = create_mutex(None);
+        let h2 = create_mutex(None);
 
         assert_ne!(h1, h2);
         assert!(get_mutex(h1).is_some());
@@ -97,8 +244,8 @@ mod tests {
 
     #[test]
     fn test_semaphore_registry() {
-        let h1 = create_semaphore(3);
-        let h2 = create_semaphore(1);
+        let h1 = create_semaphore(3, None);
+        let h2 = create_semaphore(1, None);
 
         assert_ne!(h1, h2);
 
@@ -111,4 +258,25 @@ mod tests {
         assert!(destroy_semaphore(h1));
         assert!(get_semaphore(h1).is_none());
     }
+
+    #[test]
+    fn test_release_unless_shared() {
+        let owned = create_mutex(Some(7));
+        let shared = create_semaphore(2, Some(7));
+        assert!(share(SyncKind::Semaphore, shared));
+
+        assert!(release(SyncKind::Mutex, owned));
+        assert!(get_mutex(owned).is_none());
+        assert!(!release(SyncKind::Semaphore, shared));
+        assert!(get_semaphore(shared).is_some());
+
+        let info = list()
+            .into_iter()
+            .find(|info| info.kind == SyncKind::Semaphore && info.handle == shared)
+            .unwrap();
+        assert_eq!(info.owner, None);
+        assert!(info.shared);
+        assert_eq!(info.permits, 2);
+        assert!(destroy_semaphore(shared));
+    }
 }
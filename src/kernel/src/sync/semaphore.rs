@@ -72,6 +72,11 @@ impl Semaphore {
         self.permits.load(Ordering::Relaxed)
     }
 
+    /// Number of tasks queued waiting for a permit.
+    pub fn waiters(&self) -> usize {
+        self.waiters.len()
+    }
+
     /// Attempt to acquire a permit without blocking.
     ///
     /// Returns `true` if a permit was acquired, `false` if none available.
@@ -0,0 +1,135 @@
+//! Priority donation.
+//!
+//! A High-priority task that awaits work done by a Normal-priority task
+//! (the terminal waiting for a DNS answer, say) would otherwise wait behind
+//! every other Normal task. While it waits it donates its priority to the
+//! task that will produce the result: the producer's wakeups are queued at
+//! the donated priority until the donation is dropped.
+//!
+//! Producers are named by a `Producer`, which the producing task registers
+//! itself with when it starts; `AsyncMutex` uses one for its holder, so
+//! waiting for a lock donates to the task holding it. A boosted task that
+//! waits in turn donates its boosted priority.
+
+use super::{current_priority, current_task, Priority, TaskId};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// `Producer` value while no task is registered.
+const NO_TASK: u64 = u64::MAX;
+
+/// A task that produces something other tasks await.
+pub struct Producer {
+    task: AtomicU64,
+}
+
+impl Default for Producer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Producer {
+    /// A producer with no task registered.
+    pub const fn new() -> Self {
+        Self {
+            task: AtomicU64::new(NO_TASK),
+        }
+    }
+
+    /// Register the current task as the producer.
+    pub fn register(&self) {
+        self.set(current_task());
+    }
+
+    /// Register `task` as the producer, or none.
+    pub fn set(&self, task: Option<TaskId>) {
+        self.task
+            .store(task.map_or(NO_TASK, |task| task.0), Ordering::Relaxed);
+    }
+
+    /// The registered task, if any.
+    pub fn task(&self) -> Option<TaskId> {
+        match self.task.load(Ordering::Relaxed) {
+            NO_TASK => None,
+            id => Some(TaskId(id)),
+        }
+    }
+}
+
+/// One task's priority lent to another.
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    id: u64,
+    to: TaskId,
+    priority: Priority,
+}
+
+/// Active donations. Only locked with interrupts disabled, as wakers run
+/// in interrupt handlers.
+static DONATIONS: Mutex<Vec<Record>> = Mutex::new(Vec::new());
+
+/// Tasks that received a donation since the executor last looked; it
+/// requeues them at their new priority.
+static BOOSTED: Mutex<Vec<TaskId>> = Mutex::new(Vec::new());
+
+/// A donation in effect; withdrawn when dropped.
+#[must_use = "the donation is withdrawn when dropped"]
+pub struct Donation {
+    id: u64,
+}
+
+impl Drop for Donation {
+    fn drop(&mut self) {
+        interrupts::without_interrupts(|| {
+            DONATIONS.lock().retain(|record| record.id != self.id);
+        });
+    }
+}
+
+/// Donate the current task's priority to `producer` until the returned
+/// donation is dropped.
+///
+/// Returns `None` if no producer is registered, outside task context, or
+/// if the current task would donate to itself.
+pub fn donate(producer: &Producer) -> Option<Donation> {
+    let to = producer.task()?;
+    let priority = current_priority()?;
+    if current_task() == Some(to) {
+        return None;
+    }
+    Some(donate_to(to, priority))
+}
+
+/// Lend `priority` to `to` until the returned donation is dropped.
+pub fn donate_to(to: TaskId, priority: Priority) -> Donation {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    interrupts::without_interrupts(|| {
+        DONATIONS.lock().push(Record { id, to, priority });
+        let mut boosted = BOOSTED.lock();
+        if !boosted.contains(&to) {
+            boosted.push(to);
+        }
+    });
+    Donation { id }
+}
+
+/// Highest priority donated to `task`, if any.
+pub fn boost(task: TaskId) -> Option<Priority> {
+    interrupts::without_interrupts(|| {
+        DONATIONS
+            .lock()
+            .iter()
+            .filter(|record| record.to == task)
+            .map(|record| record.priority)
+            .max()
+    })
+}
+
+/// Tasks boosted since the last call.
+pub(super) fn take_boosted() -> Vec<TaskId> {
+    interrupts::without_interrupts(|| core::mem::take(&mut *BOOSTED.lock()))
+}
@@ -2,17 +2,39 @@
 //!
 //! This module provides a priority-based cooperative task executor for the kernel.
 //! Tasks are organized into 4 priority levels and executed in order from highest
-//! to lowest priority.
-
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc};
+//! to lowest priority. An idle task (see `idle`) at the lowest level halts
+//! the CPU when nothing else is ready. A task that was donated a higher
+//! priority (see `donate`) is queued at that priority when woken.
+//!
+//! Code running inside a task has no access to the executor; it starts new
+//! tasks with `spawn`, which the executor takes on at its next round.
+
+use super::donate;
+use super::idle::IdleTask;
+use super::{Priority, Task, TaskId};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::future::Future;
+use core::pin::Pin;
 use core::task::{Context, Poll, Waker};
 use crossbeam_queue::ArrayQueue;
 use futures_util::task::ArcWake;
+use spin::Mutex;
 
 /// Maximum number of tasks per priority queue.
 const QUEUE_CAPACITY: usize = 100;
 
+/// A future started with `spawn`.
+type Spawned = (Pin<Box<dyn Future<Output = ()> + Send>>, Priority);
+
+/// Futures started with `spawn` since the executor last looked.
+static SPAWNED: Mutex<Vec<Spawned>> = Mutex::new(Vec::new());
+
+/// Start `future` as a new task at `priority`, from inside a running task
+/// (a shell command, say). It is first polled at the executor's next round.
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static, priority: Priority) {
+    SPAWNED.lock().push((Box::pin(future), priority));
+}
+
 /// A simple executor that runs tasks to completion.
 ///
 /// The executor maintains separate queues for each priority level and processes
@@ -77,26 +99,55 @@ impl Executor {
         }
     }
 
+    /// Take on the tasks started with `spawn`.
+    ///
+    /// Only the main loop does, so that an executor run from inside a task
+    /// (see `run_until_idle`) leaves them to the kernel's executor.
+    fn spawn_pending(&mut self) {
+        let spawned = core::mem::take(&mut *SPAWNED.lock());
+        for (future, priority) in spawned {
+            self.spawn(Task::with_priority(future, priority));
+        }
+    }
+
+    /// Requeue tasks that were donated a priority, so they run at it now
+    /// rather than at their next wakeup.
+    ///
+    /// A task may then sit in two queues; the extra poll is spurious and
+    /// harmless.
+    fn requeue_boosted(&mut self) {
+        for task_id in donate::take_boosted() {
+            if let Some(waker) = self.waker_cache.get(&task_id) {
+                waker.wake_by_ref();
+            }
+        }
+    }
+
     /// Run all ready tasks.
     ///
     /// Iterates through priority queues from Critical (3) down to Idle (0),
     /// polling each task until it either completes or yields.
     fn run_ready_tasks(&mut self) {
+        self.requeue_boosted();
         // Iterate queues from Critical (3) down to Idle (0)
         for priority in (0..4).rev() {
             let queue = &self.task_queues[priority];
 
-            // Process all tasks in this priority level before moving lower
-            while let Some(task_id) = queue.pop() {
+            // Process the tasks that were ready when this level started.
+            // Tasks that wake themselves run again next round, so a level
+            // of yielding tasks cannot starve the levels below it.
+            for _ in 0..queue.len() {
+                let Some(task_id) = queue.pop() else {
+                    break;
+                };
                 let task = match self.tasks.get_mut(&task_id) {
                     Some(task) => task,
                     None => continue, // task no longer exists
                 };
 
-                let waker = self
-                    .waker_cache
-                    .entry(task_id)
-                    .or_insert_with(|| TaskWaker::new(task_id, self.task_queues[priority].clone()));
+                let waker = self.waker_cache.entry(task_id).or_insert_with(|| {
+                    TaskWaker::new(task_id, task.priority, self.task_queues.clone())
+                });
 
                 let mut context = Context::from_waker(waker);
                 match task.poll(&mut context) {
@@ -111,50 +162,55 @@ impl Executor {
         }
     }
 
-    /// Run the executor until all tasks are finished.
+    /// Run tasks until none is ready.
     ///
-    /// This function never returns under normal operation (diverging `-> !`).
-    pub fn run(&mut self) -> ! {
-        loop {
+    /// For short-lived sets of tasks, such as the `bench` task switch
+    /// measurements; may be called from inside a task. Tasks waiting for a
+    /// wakeup are left in place.
+    pub fn run_until_idle(&mut self) {
+        while self.task_queues.iter().any(|queue| !queue.is_empty()) {
             self.run_ready_tasks();
-            self.sleep_if_idle();
         }
     }
 
-    /// Sleep the CPU if no tasks are ready.
+    /// Run the executor until all tasks are finished.
     ///
-    /// Uses x86_64 HLT instruction to reduce power consumption while waiting
-    /// for interrupts to wake the processor.
-    fn sleep_if_idle(&self) {
-        use x86_64::instructions::interrupts;
-
-        interrupts::disable();
-        // Check all queues
-        let is_empty = self.task_queues.iter().all(|q| q.is_empty());
-        if is_empty {
-            interrupts::enable_and_hlt();
-        } else {
-            interrupts::enable();
+    /// Spawns the idle task first, which halts the CPU whenever no other
+    /// task is ready. This function never returns under normal operation
+    /// (diverging `-> !`).
+    pub fn run(&mut self) -> ! {
+        let idle = IdleTask::new(self.task_queues.clone());
+        self.spawn(Task::with_priority(idle, Priority::Idle));
+        loop {
+            self.spawn_pending();
+            self.run_ready_tasks();
         }
     }
 }
 
 /// Internal waker implementation for tasks.
 ///
-/// When a task is woken, its ID is pushed back onto its priority queue
-/// so it will be polled again.
+/// When a task is woken, its ID is pushed back onto the queue of its
+/// priority, or of a higher priority donated to it, so it will be polled
+/// again.
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    priority: Priority,
+    task_queues: [Arc<ArrayQueue<TaskId>>; 4],
 }
 
 impl TaskWaker {
     /// Create a new `Waker` for the given task.
     #[allow(clippy::new_ret_no_self)]
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+    fn new(
+        task_id: TaskId,
+        priority: Priority,
+        task_queues: [Arc<ArrayQueue<TaskId>>; 4],
+    ) -> Waker {
         futures_util::task::waker(Arc::new(TaskWaker {
             task_id,
-            task_queue,
+            priority,
+            task_queues,
         }))
     }
 }
@@ -165,7 +221,9 @@ impl ArcWake for TaskWaker {
     /// If the queue is full, the wake is silently dropped. This can happen
     /// under extreme load but is safe—the task will be woken again later.
     fn wake_by_ref(arc_self: &Arc<Self>) {
+        let priority = donate::boost(arc_self.task_id)
+            .map_or(arc_self.priority, |boost| boost.max(arc_self.priority));
         // Silently drop if queue is full to avoid kernel panic
-        let _ = arc_self.task_queue.push(arc_self.task_id);
+        let _ = arc_self.task_queues[priority as usize].push(arc_self.task_id);
     }
 }
@@ -0,0 +1,107 @@
+//! The idle task and CPU utilization statistics.
+//!
+//! The executor always runs an idle task at `Priority::Idle`. When it is
+//! polled and no other task is ready, it halts the CPU until the next
+//! interrupt (C1; deeper C-states via MWAIT are not used) and records how
+//! long it was halted. Time is measured with the TSC, so the statistics do
+//! not depend on the kernel clock.
+
+use super::TaskId;
+use crate::arch::x86_64::rdtsc;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use crossbeam_queue::ArrayQueue;
+
+/// TSC value when the idle task first ran; 0 before that.
+static START_TSC: AtomicU64 = AtomicU64::new(0);
+
+/// TSC cycles spent halted.
+static IDLE_CYCLES: AtomicU64 = AtomicU64::new(0);
+
+/// Number of times the CPU was halted.
+static HALTS: AtomicU64 = AtomicU64::new(0);
+
+/// CPU utilization since the idle task started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdleStats {
+    /// TSC cycles elapsed.
+    pub total_cycles: u64,
+    /// TSC cycles spent halted.
+    pub idle_cycles: u64,
+    /// Number of halts.
+    pub halts: u64,
+}
+
+impl IdleStats {
+    /// Percentage of time the CPU was busy (0–100).
+    pub fn busy_percent(&self) -> u64 {
+        let busy = self.total_cycles.saturating_sub(self.idle_cycles);
+        (busy.saturating_mul(100))
+            .checked_div(self.total_cycles)
+            .unwrap_or(0)
+    }
+
+    /// Percentage of time the CPU was halted (0–100).
+    pub fn idle_percent(&self) -> u64 {
+        100 - self.busy_percent()
+    }
+}
+
+/// Current utilization statistics.
+pub fn stats() -> IdleStats {
+    let start = START_TSC.load(Ordering::Relaxed);
+    let total_cycles = if start == 0 {
+        0
+    } else {
+        rdtsc().saturating_sub(start)
+    };
+    IdleStats {
+        total_cycles,
+        idle_cycles: IDLE_CYCLES.load(Ordering::Relaxed),
+        halts: HALTS.load(Ordering::Relaxed),
+    }
+}
+
+/// The idle task: halts while the executor has nothing else to run.
+pub struct IdleTask {
+    queues: [Arc<ArrayQueue<TaskId>>; 4],
+}
+
+impl IdleTask {
+    /// Create an idle task watching the executor's ready queues.
+    pub(super) fn new(queues: [Arc<ArrayQueue<TaskId>>; 4]) -> Self {
+        Self { queues }
+    }
+
+    /// Halt until the next interrupt if no task is ready.
+    ///
+    /// Interrupts are disabled while checking so that a wake-up from an
+    /// interrupt handler cannot slip in between the check and the halt.
+    fn halt_if_idle(&self) {
+        use x86_64::instructions::interrupts;
+
+        interrupts::disable();
+        if self.queues.iter().all(|queue| queue.is_empty()) {
+            let before = rdtsc();
+            interrupts::enable_and_hlt();
+            IDLE_CYCLES.fetch_add(rdtsc().saturating_sub(before), Ordering::Relaxed);
+            HALTS.fetch_add(1, Ordering::Relaxed);
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+impl Future for IdleTask {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let _ = START_TSC.compare_exchange(0, rdtsc(), Ordering::Relaxed, Ordering::Relaxed);
+        self.halt_if_idle();
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
@@ -12,8 +12,8 @@ use futures_util::{
 };
 use spin::Once;
 
-/// Publicly accessible scancode queue for the kernel.
-pub static SCANCODE_QUEUE: Once<ArrayQueue<u8>> = Once::new();
+/// Scancodes from the keyboard interrupt, read through `ScancodeStream`.
+static SCANCODE_QUEUE: Once<ArrayQueue<u8>> = Once::new();
 static WAKER: AtomicWaker = AtomicWaker::new();
 
 /// Called by the keyboard interrupt handler to add a scancode to the queue.
@@ -22,7 +22,11 @@ static WAKER: AtomicWaker = AtomicWaker::new();
 pub fn add_scancode(scancode: u8) {
     if let Some(queue) = SCANCODE_QUEUE.get() {
         if queue.push(scancode).is_err() {
-            // print!("WARNING: scancode queue full; dropping keyboard input");
+            crate::irq_log!(
+                log::Level::Warn,
+                "keyboard",
+                "scancode queue full; dropping keyboard input"
+            );
         } else {
             WAKER.wake();
         }
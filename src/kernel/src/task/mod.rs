@@ -1,14 +1,17 @@
 //! Asynchronous task management.
 
+use crate::arch::x86_64::fpu::{self, FpuState};
 use alloc::boxed::Box;
 use core::{
     future::Future,
     pin::Pin,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
     task::{Context, Poll},
 };
 
+pub mod donate;
 pub mod executor;
+pub mod idle;
 pub mod keyboard;
 
 /// Yields execution to allow other tasks to run.
@@ -36,6 +39,40 @@ impl Future for YieldNow {
     }
 }
 
+/// Sentinel stored in `CURRENT_TASK` while no task is being polled.
+const NO_TASK: u64 = u64::MAX;
+
+/// ID of the task currently being polled by the executor.
+static CURRENT_TASK: AtomicU64 = AtomicU64::new(NO_TASK);
+
+/// Get the ID of the task currently running, if any.
+///
+/// Returns `None` outside of task context (early boot, interrupt-free
+/// kernel code before the executor starts).
+pub fn current_task() -> Option<TaskId> {
+    match CURRENT_TASK.load(Ordering::Relaxed) {
+        NO_TASK => None,
+        id => Some(TaskId(id)),
+    }
+}
+
+/// Record which task the executor is about to poll.
+fn set_current_task(id: Option<TaskId>) {
+    CURRENT_TASK.store(id.map_or(NO_TASK, |id| id.0), Ordering::Relaxed);
+}
+
+/// Priority of the task being polled, including donations.
+static CURRENT_PRIORITY: AtomicU8 = AtomicU8::new(Priority::Normal as u8);
+
+/// Get the priority the current task runs at, including any priority
+/// donated to it (see `donate`).
+///
+/// Returns `None` outside of task context.
+pub fn current_priority() -> Option<Priority> {
+    current_task()?;
+    Some(Priority::from_level(CURRENT_PRIORITY.load(Ordering::Relaxed).into()))
+}
+
 /// A unique identifier for a task.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TaskId(u64);
@@ -61,11 +98,26 @@ pub enum Priority {
     Critical = 3,
 }
 
+impl Priority {
+    /// The priority at executor queue `level`; levels above Critical are
+    /// Critical.
+    pub fn from_level(level: usize) -> Self {
+        match level {
+            0 => Priority::Idle,
+            1 => Priority::Normal,
+            2 => Priority::High,
+            _ => Priority::Critical,
+        }
+    }
+}
+
 /// A wrapper around a future that represents a task.
 pub struct Task {
     id: TaskId,
     priority: Priority,
     future: Pin<Box<dyn Future<Output = ()>>>,
+    /// FPU/SSE registers, saved while another task uses them.
+    fpu: Box<FpuState>,
 }
 
 impl Task {
@@ -80,11 +132,30 @@ impl Task {
             id: TaskId::new(),
             priority,
             future: Box::pin(future),
+            fpu: Box::default(),
         }
     }
 
+    /// The task's priority raised by any donation to it.
+    fn effective_priority(&self) -> Priority {
+        donate::boost(self.id).map_or(self.priority, |boost| boost.max(self.priority))
+    }
+
     /// Poll the task's future.
+    ///
+    /// The current task is restored afterwards, so a task may run a nested
+    /// executor (see `Executor::run_until_idle`).
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
+        let _span = crate::trace::span(crate::trace::Category::Task, "task", self.id.0);
+        let outer = current_task();
+        let outer_priority = CURRENT_PRIORITY.load(Ordering::Relaxed);
+        set_current_task(Some(self.id));
+        CURRENT_PRIORITY.store(self.effective_priority() as u8, Ordering::Relaxed);
+        fpu::switch_to(Some(&mut self.fpu));
+        let result = self.future.as_mut().poll(context);
+        fpu::switch_to(None);
+        set_current_task(outer);
+        CURRENT_PRIORITY.store(outer_priority, Ordering::Relaxed);
+        result
     }
 }
@@ -0,0 +1,276 @@
+//! Parsing command options and positional arguments.
+//!
+//! A command describes what it accepts with a `Spec`: options such as
+//! `--follow`/`-f` or `--since <seconds>`, and positional arguments such
+//! as `<file>`, `[port]` or `[path...]`. `Spec::parse` checks a command's
+//! words against it and `Spec::usage` generates the usage line, so every
+//! command accepts the same syntax: options may come before, between or
+//! after positional arguments, `--name=value` is `--name value`, and `--`
+//! ends the options. A word starting with `-` and a digit is positional,
+//! so negative numbers need no `--`.
+//!
+//! Like `line`, this needs nothing from the rest of the kernel.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+/// An option a command accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opt {
+    /// Name after `--`.
+    pub long: &'static str,
+    /// Letter after `-`, if the option has a short form.
+    pub short: Option<char>,
+    /// Name of the value the option takes, shown as `<value>`; `None` for
+    /// a flag.
+    pub value: Option<&'static str>,
+}
+
+impl Opt {
+    /// An option without a value, `--long`.
+    pub const fn flag(long: &'static str) -> Self {
+        Self {
+            long,
+            short: None,
+            value: None,
+        }
+    }
+
+    /// An option taking a value, `--long <value>`.
+    pub const fn value(long: &'static str, value: &'static str) -> Self {
+        Self {
+            long,
+            short: None,
+            value: Some(value),
+        }
+    }
+
+    /// The same option, also accepted as `-letter`.
+    pub const fn short(self, letter: char) -> Self {
+        Self {
+            short: Some(letter),
+            ..self
+        }
+    }
+}
+
+/// What a command accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spec {
+    /// Command name, as the usage line starts (e.g. `wasm run`).
+    pub name: &'static str,
+    /// Options, in the order the usage line lists them.
+    pub options: &'static [Opt],
+    /// Positional arguments in order: `<name>` is required, `[name]`
+    /// optional, and a last one ending in `...` takes any number of
+    /// words. Required ones come first.
+    pub positional: &'static [&'static str],
+}
+
+/// Why a command's words do not fit its `Spec`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgError<'a> {
+    /// An option the command does not have.
+    UnknownOption(&'a str),
+    /// An option that takes a value came last.
+    MissingValue(&'static str),
+    /// A flag was given a value with `=`.
+    UnexpectedValue(&'static str),
+    /// A required positional argument is missing.
+    Missing(&'static str),
+    /// More positional arguments than the command takes.
+    Unexpected(&'a str),
+    /// The value of the option named first does not parse.
+    Invalid(&'static str, &'a str),
+}
+
+impl fmt::Display for ArgError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::UnknownOption(option) => write!(f, "unknown option {}", option),
+            ArgError::MissingValue(long) => write!(f, "--{} needs a value", long),
+            ArgError::UnexpectedValue(long) => write!(f, "--{} takes no value", long),
+            ArgError::Missing(name) => write!(f, "missing {}", name),
+            ArgError::Unexpected(word) => write!(f, "unexpected argument {}", word),
+            ArgError::Invalid(long, value) => write!(f, "invalid --{}: {}", long, value),
+        }
+    }
+}
+
+/// Whether `word` is an option rather than a positional argument.
+fn is_option(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next() == Some('-') && chars.next().is_some_and(|c| !c.is_ascii_digit())
+}
+
+impl Spec {
+    /// Number of positional arguments that are required.
+    fn required(&self) -> usize {
+        self.positional
+            .iter()
+            .take_while(|name| name.starts_with('<'))
+            .count()
+    }
+
+    /// Most positional arguments taken; `None` if unlimited.
+    fn max_positional(&self) -> Option<usize> {
+        match self.positional.last() {
+            Some(name) if name.trim_end_matches(']').ends_with("...") => None,
+            _ => Some(self.positional.len()),
+        }
+    }
+
+    /// Find the option `word` names, with the value given after `=`.
+    fn option<'a>(
+        &'static self,
+        word: &'a str,
+    ) -> Result<(&'static Opt, Option<&'a str>), ArgError<'a>> {
+        let found = match word.strip_prefix("--") {
+            Some(long) => {
+                let (long, value) = match long.split_once('=') {
+                    Some((long, value)) => (long, Some(value)),
+                    None => (long, None),
+                };
+                self.options
+                    .iter()
+                    .find(|opt| opt.long == long)
+                    .map(|opt| (opt, value))
+            }
+            None => {
+                let mut letters = word[1..].chars();
+                match (letters.next(), letters.next()) {
+                    (Some(letter), None) => self
+                        .options
+                        .iter()
+                        .find(|opt| opt.short == Some(letter))
+                        .map(|opt| (opt, None)),
+                    _ => None,
+                }
+            }
+        };
+        found.ok_or(ArgError::UnknownOption(word))
+    }
+
+    /// Check `words` against the spec.
+    pub fn parse<'a>(&'static self, words: &[&'a str]) -> Result<Args<'a>, ArgError<'a>> {
+        let mut args = Args {
+            options: Vec::new(),
+            positional: Vec::new(),
+        };
+        let mut words = words.iter().copied();
+        let mut options_ended = false;
+        while let Some(word) = words.next() {
+            if options_ended || !is_option(word) {
+                args.positional.push(word);
+                continue;
+            }
+            if word == "--" {
+                options_ended = true;
+                continue;
+            }
+            let (opt, value) = self.option(word)?;
+            let value = match (opt.value, value) {
+                (Some(_), Some(value)) => Some(value),
+                (Some(_), None) => Some(words.next().ok_or(ArgError::MissingValue(opt.long))?),
+                (None, Some(_)) => return Err(ArgError::UnexpectedValue(opt.long)),
+                (None, None) => None,
+            };
+            args.options.push((opt.long, value));
+        }
+
+        if let Some(name) = self.positional.get(args.positional.len()) {
+            if args.positional.len() < self.required() {
+                return Err(ArgError::Missing(name));
+            }
+        }
+        if let Some(max) = self.max_positional() {
+            if let Some(extra) = args.positional.get(max) {
+                return Err(ArgError::Unexpected(extra));
+            }
+        }
+        Ok(args)
+    }
+
+    /// The arguments after the command name, e.g.
+    /// `[-f|--follow] [--since <seconds>] <file>`.
+    pub fn synopsis(&self) -> String {
+        let mut parts: Vec<String> = self
+            .options
+            .iter()
+            .map(|opt| {
+                let name = match opt.short {
+                    Some(letter) => alloc::format!("-{}|--{}", letter, opt.long),
+                    None => alloc::format!("--{}", opt.long),
+                };
+                match opt.value {
+                    Some(value) => alloc::format!("[{} <{}>]", name, value),
+                    None => alloc::format!("[{}]", name),
+                }
+            })
+            .collect();
+        parts.extend(self.positional.iter().map(|name| String::from(*name)));
+        parts.join(" ")
+    }
+
+    /// The usage line: the command name and its synopsis.
+    pub fn usage(&self) -> String {
+        let synopsis = self.synopsis();
+        if synopsis.is_empty() {
+            return String::from(self.name);
+        }
+        alloc::format!("{} {}", self.name, synopsis)
+    }
+}
+
+/// A command's words, checked against its `Spec`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Args<'a> {
+    /// Options given, in order, by long name with their values.
+    options: Vec<(&'static str, Option<&'a str>)>,
+    positional: Vec<&'a str>,
+}
+
+impl<'a> Args<'a> {
+    /// Whether the option `long` was given.
+    pub fn flag(&self, long: &str) -> bool {
+        self.options.iter().any(|(name, _)| *name == long)
+    }
+
+    /// The value of the option `long`, the last one if it was given more
+    /// than once.
+    pub fn value(&self, long: &str) -> Option<&'a str> {
+        self.values(long).last()
+    }
+
+    /// Every value given for the option `long`, in order.
+    pub fn values<'s>(&'s self, long: &'s str) -> impl Iterator<Item = &'a str> + 's {
+        self.options
+            .iter()
+            .filter(move |(name, _)| *name == long)
+            .filter_map(|(_, value)| *value)
+    }
+
+    /// The value of the option `long` parsed as a `T`.
+    pub fn parse_value<T: FromStr>(&self, long: &'static str) -> Result<Option<T>, ArgError<'a>> {
+        self.value(long)
+            .map(|value| value.parse().map_err(|_| ArgError::Invalid(long, value)))
+            .transpose()
+    }
+
+    /// Options given, in order, by long name with their values.
+    pub fn options(&self) -> impl Iterator<Item = (&'static str, Option<&'a str>)> + '_ {
+        self.options.iter().copied()
+    }
+
+    /// Positional arguments, in order.
+    pub fn positional(&self) -> &[&'a str] {
+        &self.positional
+    }
+
+    /// The positional argument at `index`.
+    pub fn get(&self, index: usize) -> Option<&'a str> {
+        self.positional.get(index).copied()
+    }
+}
@@ -0,0 +1,129 @@
+//! Capturing terminal output.
+//!
+//! `capture` runs a closure with everything it prints through `print!`,
+//! `vga::set_color` and `vga::clear_screen` recorded as an `Output`
+//! instead of shown. The shell captures each command's output for the
+//! pager; tests capture it to check what a command printed.
+
+use super::io::{self, TerminalIo};
+use crate::arch::x86_64::vga::Color;
+use alloc::boxed::Box;
+#[cfg(feature = "terminal")]
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// A piece of captured output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chunk {
+    /// Text, possibly spanning lines.
+    Text(String),
+    /// A color change.
+    Color(Color, Color),
+    /// A screen clear.
+    Clear,
+}
+
+/// Output captured from a command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Output {
+    chunks: Vec<Chunk>,
+}
+
+impl Output {
+    /// Create empty output.
+    pub fn new() -> Self {
+        Self { chunks: Vec::new() }
+    }
+
+    /// Append text.
+    pub fn push_str(&mut self, s: &str) {
+        match self.chunks.last_mut() {
+            Some(Chunk::Text(text)) => text.push_str(s),
+            _ => self.chunks.push(Chunk::Text(String::from(s))),
+        }
+    }
+
+    /// Append a color change.
+    pub fn push_color(&mut self, foreground: Color, background: Color) {
+        self.chunks.push(Chunk::Color(foreground, background));
+    }
+
+    /// Append a screen clear.
+    pub fn push_clear(&mut self) {
+        self.chunks.push(Chunk::Clear);
+    }
+
+    /// Check whether nothing was captured.
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// The captured text without colors.
+    pub fn text(&self) -> String {
+        self.chunks
+            .iter()
+            .filter_map(|chunk| match chunk {
+                Chunk::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Split into lines. A line keeps its trailing newline, if any.
+    #[cfg(feature = "terminal")]
+    pub(super) fn lines(self) -> VecDeque<Vec<Chunk>> {
+        let mut lines = VecDeque::new();
+        let mut line = Vec::new();
+        for chunk in self.chunks {
+            let Chunk::Text(text) = chunk else {
+                line.push(chunk);
+                continue;
+            };
+            for piece in text.split_inclusive('\n') {
+                line.push(Chunk::Text(String::from(piece)));
+                if piece.ends_with('\n') {
+                    lines.push_back(core::mem::take(&mut line));
+                }
+            }
+        }
+        if !line.is_empty() {
+            lines.push_back(line);
+        }
+        lines
+    }
+}
+
+/// Sink that records output instead of displaying it.
+struct Capture(Arc<Mutex<Output>>);
+
+impl TerminalIo for Capture {
+    fn write_str(&mut self, s: &str) {
+        self.0.lock().push_str(s);
+    }
+
+    fn set_color(&mut self, foreground: Color, background: Color) {
+        self.0.lock().push_color(foreground, background);
+    }
+
+    fn clear(&mut self) {
+        self.0.lock().push_clear();
+    }
+}
+
+/// Run `f` with what the current task prints recorded instead of shown,
+/// and return the recording.
+///
+/// Outside of task context the output of the boot code is captured, so
+/// the kernel self-tests can check what a command prints. Captures nest:
+/// the inner one gets the output while it runs.
+pub fn capture(f: impl FnOnce()) -> Output {
+    let output = Arc::new(Mutex::new(Output::new()));
+    let previous = io::replace(Some(Box::new(Capture(output.clone()))));
+    f();
+    io::replace(previous);
+    let captured = core::mem::take(&mut *output.lock());
+    captured
+}
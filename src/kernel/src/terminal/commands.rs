@@ -1,481 +1,1167 @@
-//! Built-in shell commands.
+//! Command lines and the shell's own commands.
 //!
-//! Provides commands for network operations, system info, and more.
+//! A `Command` is a parsed command line bound to the registered
+//! `ShellCommand` it names. This module also provides the commands that
+//! belong to the shell itself (help, clear, echo, ksym, sysinfo, theme,
+//! config, locks, sync, dmesg, trace, devices); network and WASM commands
+//! are registered by their subsystems.
 
-use crate::arch::x86_64::vga::{self, Color};
+use super::json::Json;
+use super::line;
+use super::registry::{self, Builtin, ShellCommand};
+use super::theme::{self, Role};
+use crate::allocator::{self, arena, poison};
+use crate::arch::x86_64::{cpuid, ps2, usermode, vga};
+use crate::boot::{crashloop, health};
+#[cfg(feature = "net")]
 use crate::net::dns::parse_ipv4;
-use crate::net::{DhcpClient, DnsResolver, NetworkStack};
-use crate::{print, println};
+#[cfg(feature = "net")]
+use crate::net::{DhcpClient, DnsResolver, Httpd, NetworkStack, Syslog, Tftp, Traceroute};
+use crate::sync::{lockdep, registry as sync_registry};
+#[cfg(feature = "wasm")]
+use crate::wasm::process::ProcessManager;
+use crate::{bench, buildinfo, config, driver, klog, ksym, memory, time, trace};
+use crate::{print, println, serial_println};
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::bootinfo::MemoryRegionType;
+#[cfg(feature = "net")]
 use smoltcp::time::Instant;
+#[cfg(feature = "net")]
 use smoltcp::wire::IpAddress;
 
-/// Shell command types.
-#[derive(Debug, Clone)]
-pub enum Command {
-    /// Display help information.
-    Help,
-    /// Clear the screen.
-    Clear,
-    /// Show network configuration.
-    Ifconfig,
-    /// DHCP operations.
-    Dhcp(DhcpAction),
-    /// DNS lookup.
-    Dns {
-        /// The hostname to resolve.
-        hostname: String,
-    },
-    /// Establish TCP connection.
-    Connect {
-        /// The hostname or IP address to connect to.
-        host: String,
-        /// The port number to connect to.
-        port: u16,
-    },
-    /// Echo text.
-    Echo {
-        /// The text to echo.
-        text: String,
-    },
-    /// Ping a host.
-    Ping {
-        /// The host to ping.
-        host: String,
-    },
-    /// Show system info.
-    Sysinfo,
-    /// Run a test WASM module.
-    WasmTest {
-        /// The file to run.
-        file: String,
-    },
-    /// Unknown command.
-    Unknown(String),
+pub use super::args::{ArgError, Args, Opt, Spec};
+pub use super::line::JSON_FLAG;
+
+/// Column at which `help` starts the descriptions.
+const HELP_COLUMN: usize = 30;
+
+/// A parsed command line.
+pub struct Command {
+    /// Name as typed.
+    name: String,
+    /// The command it names; `None` if no such command is registered.
+    handler: Option<Arc<dyn ShellCommand>>,
+    /// Arguments, without `--json`.
+    args: Vec<String>,
+    /// Whether `--json` was given.
+    json: bool,
 }
 
-/// DHCP sub-commands.
-#[derive(Debug, Clone)]
-pub enum DhcpAction {
-    /// Show DHCP status.
-    Status,
-    /// Request new lease.
-    Renew,
-    /// Release current lease.
-    Release,
+/// Kernel services available to a command while it executes.
+///
+/// Only the services the kernel was built with are here.
+pub struct CommandContext<'a> {
+    /// Network stack.
+    #[cfg(feature = "net")]
+    pub stack: &'a mut NetworkStack,
+    /// DHCP client.
+    #[cfg(feature = "net")]
+    pub dhcp: &'a mut DhcpClient,
+    /// DNS resolver.
+    #[cfg(feature = "net")]
+    pub dns: &'a mut DnsResolver,
+    /// Traceroute client.
+    #[cfg(feature = "net")]
+    pub traceroute: &'a mut Traceroute,
+    /// HTTP server.
+    #[cfg(feature = "net")]
+    pub httpd: &'a mut Httpd,
+    /// TFTP client.
+    #[cfg(feature = "net")]
+    pub tftp: &'a mut Tftp,
+    /// Remote syslog sink.
+    #[cfg(feature = "net")]
+    pub syslog: &'a mut Syslog,
+    /// Background WASM processes.
+    #[cfg(feature = "wasm")]
+    pub processes: &'a mut ProcessManager,
+    /// Terminal the command was entered on.
+    pub terminal: &'a mut super::Terminal,
+    /// Current time.
+    #[cfg(feature = "net")]
+    pub timestamp: Instant,
 }
 
 impl Command {
     /// Parse a command from input.
+    ///
+    /// Returns `None` for an empty command name.
     pub fn parse(cmd: &str, args: &[&str]) -> Option<Command> {
-        match cmd.to_lowercase().as_str() {
-            "help" | "?" => Some(Command::Help),
-            "clear" | "cls" => Some(Command::Clear),
-            "ifconfig" | "ip" => Some(Command::Ifconfig),
-            "dhcp" => {
-                let action = args.first().map(|s| s.to_lowercase());
-                let action = match action.as_deref() {
-                    Some("renew") => DhcpAction::Renew,
-                    Some("release") => DhcpAction::Release,
-                    _ => DhcpAction::Status,
-                };
-                Some(Command::Dhcp(action))
-            }
-            "dns" | "nslookup" | "resolve" => {
-                if let Some(hostname) = args.first() {
-                    Some(Command::Dns {
-                        hostname: hostname.to_string(),
-                    })
-                } else {
-                    println!("Usage: dns <hostname>");
-                    None
-                }
-            }
-            "connect" | "nc" => {
-                if args.len() >= 2 {
-                    if let Ok(port) = args[1].parse::<u16>() {
-                        Some(Command::Connect {
-                            host: args[0].to_string(),
-                            port,
-                        })
-                    } else {
-                        println!("Invalid port number");
-                        None
-                    }
-                } else {
-                    println!("Usage: connect <host> <port>");
-                    None
-                }
-            }
-            "echo" => {
-                let text = args.join(" ");
-                Some(Command::Echo { text })
-            }
-            "ping" => {
-                if let Some(host) = args.first() {
-                    Some(Command::Ping { host: host.to_string() })
-                } else {
-                    println!("Usage: ping <host>");
-                    None
-                }
-            }
-            "sysinfo" | "info" => Some(Command::Sysinfo),
-            "wasm-test" | "wasm" => {
-                let file = args.first().unwrap_or(&"hello.wasm").to_string();
-                Some(Command::WasmTest { file })
-            }
-            "" => None,
-            _ => Some(Command::Unknown(cmd.to_string())),
+        let parsed = line::parse(cmd, args)?;
+        Some(Command {
+            handler: registry::lookup(&parsed.name),
+            name: parsed.name,
+            args: parsed.args,
+            json: parsed.json,
+        })
+    }
+
+    /// Parse a line as typed, with quoting (see `line::words`).
+    ///
+    /// Returns `None` for a blank line or one that continues on the next.
+    pub fn parse_line(input: &str) -> Option<Command> {
+        let (cmd, args) = line::split(input)?;
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        Self::parse(&cmd, &args)
+    }
+
+    /// Name of the command as typed.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Arguments, without `--json`.
+    pub fn args(&self) -> Vec<&str> {
+        self.args.iter().map(String::as_str).collect()
+    }
+
+    /// Position of the host argument that must be resolved first.
+    #[cfg(feature = "net")]
+    fn host_index(&self) -> Option<usize> {
+        self.handler.as_ref()?.host_arg(&self.args())
+    }
+
+    /// Hostname argument that must be resolved before the command runs.
+    ///
+    /// Returns `None` for commands without a host argument or whose host
+    /// is already a literal IPv4 address.
+    #[cfg(feature = "net")]
+    pub fn host_to_resolve(&self) -> Option<&str> {
+        let host = self.args.get(self.host_index()?)?;
+        if parse_ipv4(host).is_some() {
+            None
+        } else {
+            Some(host)
+        }
+    }
+
+    /// Replace the host argument with a resolved address.
+    #[cfg(feature = "net")]
+    pub fn set_resolved_host(&mut self, addr: IpAddress) {
+        if let Some(index) = self.host_index() {
+            self.args[index] = addr.to_string();
         }
     }
 
     /// Execute a command.
-    pub fn execute(
-        self,
-        stack: &mut NetworkStack,
-        dhcp: &mut DhcpClient,
-        dns: &mut DnsResolver,
-        terminal: &super::Terminal,
-        timestamp: Instant,
-    ) {
-        match self {
-            Command::Help => cmd_help(),
-            Command::Clear => terminal.clear(),
-            Command::Ifconfig => cmd_ifconfig(stack, dhcp),
-            Command::Dhcp(action) => cmd_dhcp(action, stack, dhcp, timestamp),
-            Command::Dns { hostname } => cmd_dns(&hostname, stack, dns),
-            Command::Connect { host, port } => cmd_connect(&host, port, stack, dns),
-            Command::Echo { text } => println!("{}", text),
-            Command::Ping { host } => cmd_ping(&host, stack),
-            Command::Sysinfo => cmd_sysinfo(),
-            Command::WasmTest { file } => cmd_wasm_test(&file),
-            Command::Unknown(cmd) => {
-                vga::set_color(Color::LightRed, Color::Black);
-                println!("Unknown command: {}", cmd);
-                vga::set_color(Color::White, Color::Black);
-                println!("Type 'help' for available commands.");
-            }
+    pub fn execute(self, ctx: &mut CommandContext) {
+        let Some(handler) = &self.handler else {
+            theme::set(Role::Error);
+            println!("Unknown command: {}", self.name);
+            theme::reset();
+            println!("Type 'help' for available commands.");
+            return;
+        };
+        let args = self.args();
+        if self.json {
+            let json = handler.json(ctx, &args).unwrap_or_else(|| {
+                Json::object()
+                    .with("error", "unsupported")
+                    .with("message", "command has no JSON output")
+            });
+            // Serial is the machine-readable channel; echo for the user
+            serial_println!("{}", json);
+            println!("{}", json);
+        } else {
+            handler.run(ctx, &args);
         }
     }
 }
 
-/// Display help information.
-fn cmd_help() {
+/// The shell's own commands.
+const BUILTINS: [Builtin; 18] = [
+    Builtin {
+        name: "help",
+        aliases: &["?"],
+        usage: "",
+        help: "Show this help message",
+        host_arg: Builtin::no_host,
+        run: cmd_help,
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "clear",
+        aliases: &["cls"],
+        usage: "",
+        help: "Clear the screen",
+        host_arg: Builtin::no_host,
+        run: |ctx, _| ctx.terminal.clear(),
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "motd",
+        aliases: &[],
+        usage: "",
+        help: "Show the banner and message of the day",
+        host_arg: Builtin::no_host,
+        run: |_, _| crate::boot::banner::print_banner(),
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "echo",
+        aliases: &[],
+        usage: "<text>",
+        help: "Echo text to console",
+        host_arg: Builtin::no_host,
+        run: |_, args| println!("{}", args.join(" ")),
+        json: |_, args| Some(Json::object().with("text", args.join(" "))),
+    },
+    Builtin {
+        name: "ksym",
+        aliases: &[],
+        usage: "<addr|name>",
+        help: "Resolve a kernel address or symbol",
+        host_arg: Builtin::no_host,
+        run: cmd_ksym,
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "version",
+        aliases: &[],
+        usage: "",
+        help: "Show the version and how the kernel was built",
+        host_arg: Builtin::no_host,
+        run: |_, _| cmd_version(),
+        json: |_, _| Some(buildinfo::json()),
+    },
+    Builtin {
+        name: "sysinfo",
+        aliases: &["info"],
+        usage: "",
+        help: "Show system information",
+        host_arg: Builtin::no_host,
+        run: |_, _| cmd_sysinfo(),
+        json: |_, _| Some(json_sysinfo()),
+    },
+    Builtin {
+        name: "theme",
+        aliases: &[],
+        usage: "[list|set|color|prompt]",
+        help: "Show or change colors and prompt",
+        host_arg: Builtin::no_host,
+        run: cmd_theme,
+        json: |_, _| Some(json_theme()),
+    },
+    Builtin {
+        name: "config",
+        aliases: &[],
+        usage: "[list|get|set|unset|save|reset]",
+        help: "Show or change system settings",
+        host_arg: Builtin::no_host,
+        run: |_, args| cmd_config(args),
+        json: |_, args| matches!(args, [] | ["list"]).then(json_config),
+    },
+    Builtin {
+        name: "locks",
+        aliases: &[],
+        usage: "",
+        help: "Show spinlock statistics",
+        host_arg: Builtin::no_host,
+        run: |_, _| cmd_locks(),
+        json: |_, _| Some(json_locks()),
+    },
+    Builtin {
+        name: "sync",
+        aliases: &[],
+        usage: "[list]",
+        help: "Show WASM mutexes and semaphores",
+        host_arg: Builtin::no_host,
+        run: |_, args| cmd_sync(args),
+        json: |_, args| matches!(args, [] | ["list"]).then(json_sync),
+    },
+    Builtin {
+        name: "dmesg",
+        aliases: &[],
+        usage: "[--since <seconds>] [-f|--follow]",
+        help: "Show kernel log records",
+        host_arg: Builtin::no_host,
+        run: cmd_dmesg,
+        json: |_, args| json_dmesg(args),
+    },
+    Builtin {
+        name: "kbd",
+        aliases: &[],
+        usage: "[rate <cps> [delay_ms]]",
+        help: "Show keyboard LEDs or set the repeat rate",
+        host_arg: Builtin::no_host,
+        run: cmd_kbd,
+        json: |_, _| Some(json_kbd()),
+    },
+    Builtin {
+        name: "ring3",
+        aliases: &[],
+        usage: "",
+        help: "Run the native ring 3 demo program",
+        host_arg: Builtin::no_host,
+        run: |_, _| cmd_ring3(),
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "memmap",
+        aliases: &[],
+        usage: "",
+        help: "Show the physical memory map and kernel usage",
+        host_arg: Builtin::no_host,
+        run: |_, _| cmd_memmap(),
+        json: |_, _| Some(json_memmap()),
+    },
+    Builtin {
+        name: "devices",
+        aliases: &["lsdev"],
+        usage: "",
+        help: "List the devices drivers registered",
+        host_arg: Builtin::no_host,
+        run: |_, _| cmd_devices(),
+        json: |_, _| Some(json_devices()),
+    },
+    Builtin {
+        name: "bench",
+        aliases: &[],
+        usage: "[name [ops]]",
+        help: "Run kernel micro-benchmarks",
+        host_arg: Builtin::no_host,
+        run: cmd_bench,
+        json: json_bench,
+    },
+    Builtin {
+        name: "trace",
+        aliases: &[],
+        usage: "[on|off|dump]",
+        help: "Trace tasks, host calls and interrupts",
+        host_arg: Builtin::no_host,
+        run: |_, args| cmd_trace(args),
+        json: |_, _| Some(json_trace()),
+    },
+];
+
+/// Register the shell's own commands.
+pub fn register() {
+    registry::register_builtins(&BUILTINS);
+}
+
+/// Print `command`'s error in the error color. The context of a
+/// `sovelma_common::error::Error` goes on indented lines below it.
+pub fn print_error(command: &str, error: &dyn core::fmt::Display) {
+    theme::set(Role::Error);
+    println!("{}: {:#}", command, error);
+    theme::reset();
+}
+
+/// Check `args` against `spec`; if they do not fit, print why and the
+/// usage line.
+pub fn parse_args<'a>(spec: &'static Spec, args: &[&'a str]) -> Option<Args<'a>> {
+    spec.parse(args).map_err(|e| usage_error(spec, &e)).ok()
+}
+
+/// Print `error` in arguments checked against `spec`, and the usage line.
+pub fn usage_error(spec: &Spec, error: &ArgError) {
+    print_error(spec.name, error);
+    println!("Usage: {}", spec.usage());
+}
+
+/// System information as JSON.
+fn json_sysinfo() -> Json {
+    let cpu = crate::task::idle::stats();
+    let info = cpuid::info();
+    let features: Vec<&str> = info.features.present().collect();
+    let degraded: Vec<Json> = health::degraded()
+        .into_iter()
+        .map(|degradation| {
+            Json::object()
+                .with("subsystem", degradation.subsystem.name())
+                .with("reason", degradation.reason)
+        })
+        .collect();
+    Json::object()
+        .with("version", buildinfo::VERSION)
+        .with("build", buildinfo::json())
+        .with("arch", "x86_64")
+        .with("platform", "QEMU")
+        .with("cpu_vendor", info.vendor())
+        .with("cpu_brand", info.brand())
+        .with("cpu_family", u64::from(info.family))
+        .with("cpu_model", u64::from(info.model))
+        .with("cpu_stepping", u64::from(info.stepping))
+        .with("cpu_features", features)
+        .with("cpu_busy_percent", cpu.busy_percent())
+        .with("cpu_idle_percent", cpu.idle_percent())
+        .with("halts", cpu.halts)
+        .with("degraded", degraded)
+        .with("safe_mode", crashloop::safe_mode())
+}
+
+/// Show the version and build information.
+fn cmd_version() {
+    println!("SovelmaOS {}", buildinfo::VERSION);
+    println!("  Commit:     {}", buildinfo::GIT_HASH);
+    println!("  Profile:    {}", buildinfo::PROFILE);
+    println!("  Features:   {}", buildinfo::FEATURES);
+    println!("  Compiler:   {}", buildinfo::RUSTC_VERSION);
+}
+
+/// Display help information, generated from the registered commands.
+fn cmd_help(_ctx: &mut CommandContext, _args: &[&str]) {
     println!();
-    vga::set_color(Color::Cyan, Color::Black);
+    theme::set(Role::Accent);
     println!("SovelmaOS Shell Commands");
     println!("========================");
-    vga::set_color(Color::White, Color::Black);
-    println!();
-    println!("  help          Show this help message");
-    println!("  clear         Clear the screen");
-    println!("  ifconfig      Show network configuration");
-    println!("  dhcp [renew]  Show DHCP status or request new lease");
-    println!("  dns <host>    Resolve hostname to IP address");
-    println!("  connect <host> <port>  Open TCP connection");
-    println!("  ping <host>   Send ICMP Echo Request");
-    println!("  echo <text>   Echo text to console");
-    println!("  sysinfo       Show system information");
-    println!("  wasm-test     Run a simple WASM module test");
+    theme::reset();
     println!();
-}
-
-/// Show network configuration.
-fn cmd_ifconfig(stack: &NetworkStack, dhcp: &DhcpClient) {
-    println!();
-    vga::set_color(Color::Cyan, Color::Black);
-    println!("Network Configuration");
-    println!("---------------------");
-    vga::set_color(Color::White, Color::Black);
-
-    // MAC address
-    let mac = stack.device().mac_address();
-    print!("  MAC:     ");
-    vga::set_color(Color::Yellow, Color::Black);
+    for command in registry::commands() {
+        let synopsis = if command.usage().is_empty() {
+            String::from(command.name())
+        } else {
+            alloc::format!("{} {}", command.name(), command.usage())
+        };
+        if synopsis.len() < HELP_COLUMN {
+            println!(
+                "  {:<width$}{}",
+                synopsis,
+                command.help(),
+                width = HELP_COLUMN
+            );
+        } else {
+            println!("  {}", synopsis);
+            println!("  {:<width$}{}", "", command.help(), width = HELP_COLUMN);
+        }
+    }
     println!(
-        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        "  {:<width$}{}",
+        "<cmd> --json",
+        "Machine-readable output (ifconfig, dhcp, dns cache, ...)",
+        width = HELP_COLUMN
     );
-    vga::set_color(Color::White, Color::Black);
-
-    // IP address
-    print!("  IP:      ");
-    if let Some(ip) = stack.ip_address() {
-        vga::set_color(Color::LightGreen, Color::Black);
-        println!("{}", ip);
-    } else {
-        vga::set_color(Color::LightRed, Color::Black);
-        println!("Not configured");
-    }
-    vga::set_color(Color::White, Color::Black);
+    println!();
+}
 
-    // Gateway
-    print!("  Gateway: ");
-    if let Some(config) = dhcp.config() {
-        if let Some(gw) = config.gateway {
-            vga::set_color(Color::Yellow, Color::Black);
-            println!("{}", gw);
-        } else {
-            println!("None");
+/// The active theme as JSON.
+fn json_theme() -> Json {
+    let current = theme::current();
+    let mut json = Json::object()
+        .with("base", current.base)
+        .with("modified", current.is_modified())
+        .with("prompt", current.prompt.as_ref());
+    for slot in theme::SLOTS {
+        if let Some(color) = current.palette.slot(slot) {
+            json = json.with(slot, theme::color_name(color));
         }
-    } else {
-        println!("None");
     }
-    vga::set_color(Color::White, Color::Black);
+    json
+}
 
-    // DNS servers
-    print!("  DNS:     ");
-    if !stack.dns_servers.is_empty() {
-        vga::set_color(Color::Yellow, Color::Black);
-        for (i, server) in stack.dns_servers.iter().enumerate() {
-            if i > 0 {
-                print!(", ");
+/// Show, list or change the theme.
+fn cmd_theme(_ctx: &mut CommandContext, args: &[&str]) {
+    match args {
+        [] | ["show"] => show_theme(),
+        ["list"] => {
+            for (name, palette) in theme::PRESETS {
+                let (foreground, background) = palette.colors(Role::Text);
+                vga::set_color(foreground, background);
+                print!(" {:<10}", name);
+                let samples = [
+                    (Role::Accent, "accent"),
+                    (Role::Success, "ok"),
+                    (Role::Warning, "warning"),
+                    (Role::Error, "error"),
+                ];
+                for (role, sample) in samples {
+                    let (foreground, background) = palette.colors(role);
+                    vga::set_color(foreground, background);
+                    print!(" {}", sample);
+                }
+                theme::reset();
+                println!();
             }
-            print!("{}", server);
         }
-        println!();
+        _ => match theme::update(args) {
+            Ok(()) => {
+                theme::reset();
+                println!("Theme saved to {}", theme::SHELLRC_PATH);
+            }
+            Err(e) => {
+                theme::set(Role::Error);
+                println!("theme: {}", e);
+                theme::reset();
+            }
+        },
+    }
+}
+
+/// Show the active theme.
+fn show_theme() {
+    let current = theme::current();
+    let modified = if current.is_modified() {
+        " (modified)"
     } else {
-        println!("None");
+        ""
+    };
+    println!("Theme:  {}{}", current.base, modified);
+    println!(
+        "Prompt: {} -> {}",
+        current.prompt,
+        theme::expand_prompt(&current.prompt, theme::hostname(), theme::ROOT_DIR)
+    );
+    for slot in theme::SLOTS {
+        if let Some(color) = current.palette.slot(slot) {
+            println!("  {:<12}{}", slot, theme::color_name(color));
+        }
     }
-    vga::set_color(Color::White, Color::Black);
+}
 
-    // DHCP state
-    print!("  DHCP:    ");
-    vga::set_color(Color::Yellow, Color::Black);
-    println!("{:?}", dhcp.state());
-    vga::set_color(Color::White, Color::Black);
-    println!();
+/// The system configuration as JSON.
+fn json_config() -> Json {
+    let mut json = Json::object();
+    for (key, value) in config::current().iter() {
+        json = json.with(key, value);
+    }
+    json
 }
 
-/// Handle DHCP commands.
-fn cmd_dhcp(
-    action: DhcpAction,
-    stack: &mut NetworkStack,
-    dhcp: &mut DhcpClient,
-    _timestamp: Instant,
-) {
-    match action {
-        DhcpAction::Status => {
-            println!("DHCP State: {:?}", dhcp.state());
-            if let Some(config) = dhcp.config() {
-                println!("  IP: {}/{}", config.ip, config.prefix_len);
-                if let Some(gw) = config.gateway {
-                    println!("  Gateway: {}", gw);
-                }
-                if !config.dns_servers.is_empty() {
-                    print!("  DNS: ");
-                    for (i, dns) in config.dns_servers.iter().enumerate() {
-                        if i > 0 {
-                            print!(", ");
-                        }
-                        print!("{}", dns);
-                    }
-                    println!();
-                }
+/// Show or change the system configuration.
+fn cmd_config(args: &[&str]) {
+    let result = match args {
+        [] | ["list"] => {
+            let current = config::current();
+            if current.is_empty() {
+                println!("No settings");
+            }
+            for (key, value) in current.iter() {
+                println!("  {} = {}", key, value);
+            }
+            return;
+        }
+        ["get", key] => match config::get(key) {
+            Some(value) => {
+                println!("{}", value);
+                return;
             }
+            None => Err(alloc::format!("{} is not set", key)),
+        },
+        ["set", key, value @ ..] if !value.is_empty() => {
+            config::set(key, &value.join(" ")).map_err(|e| alloc::format!("{}: {}", key, e))
         }
-        DhcpAction::Renew => {
-            println!("Requesting DHCP renewal...");
-            dhcp.renew(stack);
+        ["unset", key] => {
+            if config::unset(key) {
+                Ok(())
+            } else {
+                Err(alloc::format!("{} is not set", key))
+            }
+        }
+        ["save"] => {
+            let count = config::save();
+            println!("Saved {} settings to {}", count, config::CONFIG_PATH);
+            return;
+        }
+        ["reset"] => {
+            let count = config::reset();
+            println!("Removed {} settings", count);
+            return;
+        }
+        _ => {
+            println!(
+                "Usage: config [list] | get <key> | set <key> <value> | unset <key> | save | reset"
+            );
+            return;
         }
-        DhcpAction::Release => {
-            println!("DHCP release not yet implemented");
+    };
+    if let Err(e) = result {
+        theme::set(Role::Error);
+        println!("config: {}", e);
+        theme::reset();
+    }
+}
+
+/// Lock statistics as JSON.
+fn json_locks() -> Json {
+    let locks: Vec<Json> = lockdep::stats()
+        .into_iter()
+        .map(|lock| {
+            Json::object()
+                .with("name", lock.name)
+                .with("acquisitions", lock.acquisitions)
+                .with("contended", lock.contended)
+                .with("max_hold_cycles", lock.max_hold_cycles)
+        })
+        .collect();
+    Json::object().with("locks", locks)
+}
+
+/// Show the tracked spinlocks and their counters.
+fn cmd_locks() {
+    println!(
+        "{:<20} {:>12} {:>10} {:>14}",
+        "LOCK", "ACQUIRED", "CONTENDED", "MAX HOLD (cyc)"
+    );
+    for lock in lockdep::stats() {
+        if lock.max_hold_cycles > lockdep::LONG_HOLD_CYCLES {
+            theme::set(Role::Warning);
         }
+        println!(
+            "{:<20} {:>12} {:>10} {:>14}",
+            lock.name, lock.acquisitions, lock.contended, lock.max_hold_cycles
+        );
+        theme::reset();
     }
 }
 
-/// Handle DNS lookup.
-fn cmd_dns(hostname: &str, stack: &mut NetworkStack, dns: &mut DnsResolver) {
-    // Check if it's already an IP address
-    if let Some(ip) = parse_ipv4(hostname) {
-        println!("{} -> {}", hostname, ip);
+/// Live sync objects as JSON.
+fn json_sync() -> Json {
+    let objects: Vec<Json> = sync_registry::list()
+        .into_iter()
+        .map(|object| {
+            Json::object()
+                .with("kind", object.kind.name())
+                .with("handle", object.handle)
+                .with("owner", object.owner)
+                .with("shared", object.shared)
+                .with("permits", object.permits)
+                .with("waiters", object.waiters)
+        })
+        .collect();
+    Json::object().with("objects", objects)
+}
+
+/// Show the mutexes and semaphores WASM processes created.
+fn cmd_sync(args: &[&str]) {
+    if !matches!(args, [] | ["list"]) {
+        println!("Usage: sync [list]");
+        return;
+    }
+    let objects = sync_registry::list();
+    if objects.is_empty() {
+        println!("No sync objects");
         return;
     }
+    println!(
+        "{:<10} {:>6} {:>6} {:>7} {:>7} {:>7}",
+        "KIND", "HANDLE", "OWNER", "SHARED", "PERMITS", "WAITERS"
+    );
+    for object in objects {
+        let owner = object
+            .owner
+            .map_or_else(|| String::from("-"), |pid| pid.to_string());
+        // Waiting on a taken object is normal; waiting on a free one is not
+        if object.waiters > 0 && !object.busy {
+            theme::set(Role::Warning);
+        }
+        println!(
+            "{:<10} {:>6} {:>6} {:>7} {:>7} {:>7}",
+            object.kind.name(),
+            object.handle,
+            owner,
+            if object.shared { "yes" } else { "no" },
+            object.permits,
+            object.waiters
+        );
+        theme::reset();
+    }
+}
 
-    // Initialize DNS resolver if needed
-    if !dns.is_ready() {
-        dns.init(stack);
+/// Arguments of `dmesg`.
+pub const DMESG_ARGS: Spec = Spec {
+    name: "dmesg",
+    options: &[
+        Opt::value("since", "seconds"),
+        Opt::flag("follow").short('f'),
+    ],
+    positional: &[],
+};
+
+/// Options of `dmesg`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmesgArgs {
+    /// Oldest record shown, in ns since boot.
+    pub since_ns: u64,
+    /// Keep printing records as they are logged.
+    pub follow: bool,
+}
+
+impl DmesgArgs {
+    /// Parse arguments fitting `DMESG_ARGS`.
+    pub fn parse(args: &[&str]) -> Option<Self> {
+        Self::from_args(&DMESG_ARGS.parse(args).ok()?).ok()
     }
 
-    if !dns.is_ready() {
-        vga::set_color(Color::LightRed, Color::Black);
-        println!("DNS resolver not ready (no DNS servers configured)");
-        vga::set_color(Color::White, Color::Black);
+    /// Take the options from arguments checked against `DMESG_ARGS`.
+    fn from_args<'a>(args: &Args<'a>) -> Result<Self, ArgError<'a>> {
+        let since_ns = match args.value("since") {
+            Some(seconds) => parse_seconds(seconds).ok_or(ArgError::Invalid("since", seconds))?,
+            None => 0,
+        };
+        Ok(Self {
+            since_ns,
+            follow: args.flag("follow"),
+        })
+    }
+}
+
+/// Parse seconds with up to nine decimals, e.g. `12.5`, as nanoseconds.
+pub fn parse_seconds(text: &str) -> Option<u64> {
+    let (secs, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if secs.is_empty() || fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut nanos = 0;
+    for (index, digit) in fraction.bytes().enumerate() {
+        nanos += u64::from(digit - b'0') * 10u64.pow(8 - index as u32);
+    }
+    secs.parse::<u64>()
+        .ok()?
+        .checked_mul(time::NS_PER_SEC)?
+        .checked_add(nanos)
+}
+
+/// Log records as JSON.
+fn json_dmesg(args: &[&str]) -> Option<Json> {
+    let DmesgArgs { since_ns, .. } = DmesgArgs::parse(args)?;
+    let records: Vec<Json> = klog::history(0)
+        .into_iter()
+        .filter(|(_, record)| record.mono_ns >= since_ns)
+        .map(|(seq, record)| {
+            Json::object()
+                .with("seq", seq)
+                .with("mono_ns", record.mono_ns)
+                .with("wall_ns", record.wall_ns)
+                .with("level", record.level.as_str())
+                .with("target", record.target)
+                .with("message", record.message)
+        })
+        .collect();
+    Some(Json::object().with("records", records))
+}
+
+/// Show the kept log records, then follow the log if asked to.
+fn cmd_dmesg(ctx: &mut CommandContext, args: &[&str]) {
+    let Some(args) = parse_args(&DMESG_ARGS, args) else {
         return;
+    };
+    let DmesgArgs { since_ns, follow } = match DmesgArgs::from_args(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => return usage_error(&DMESG_ARGS, &e),
+    };
+    let records = klog::history(0);
+    let next = records
+        .last()
+        .map_or_else(klog::next_seq, |(seq, _)| seq + 1);
+    for (_, record) in records {
+        if record.mono_ns >= since_ns {
+            println!("{}", record);
+        }
+    }
+    if follow {
+        println!("-- following the log; press any key to stop --");
+        ctx.terminal.follow_log(next);
     }
+}
 
-    print!("Resolving {}... ", hostname);
+/// Keyboard LEDs and repeat settings as JSON.
+fn json_kbd() -> Json {
+    let leds = ps2::leds();
+    let typematic = ps2::typematic();
+    Json::object()
+        .with("caps_lock", leds.caps_lock)
+        .with("num_lock", leds.num_lock)
+        .with("scroll_lock", leds.scroll_lock)
+        .with(
+            "rate_centihertz",
+            u64::from(ps2::typematic_centihertz(typematic)),
+        )
+        .with("delay_ms", u64::from(ps2::typematic_delay_ms(typematic)))
+}
 
-    match dns.resolve(stack, hostname) {
-        Ok(_handle) => {
-            // In a real implementation, we'd poll for the result
-            // For now, just indicate the query was started
-            println!("(query started)");
-            println!("Use the main loop to poll for DNS results.");
+/// Show the keyboard state or change the repeat rate.
+fn cmd_kbd(_ctx: &mut CommandContext, args: &[&str]) {
+    match args {
+        [] => {
+            let leds = ps2::leds();
+            let on_off = |on: bool| if on { "on" } else { "off" };
+            println!(
+                "Caps Lock {}, Num Lock {}, Scroll Lock {}",
+                on_off(leds.caps_lock),
+                on_off(leds.num_lock),
+                on_off(leds.scroll_lock)
+            );
+            let typematic = ps2::typematic();
+            let centihertz = ps2::typematic_centihertz(typematic);
+            println!(
+                "Repeat {}.{:02} cps after {} ms",
+                centihertz / 100,
+                centihertz % 100,
+                ps2::typematic_delay_ms(typematic)
+            );
         }
-        Err(e) => {
-            vga::set_color(Color::LightRed, Color::Black);
-            println!("Failed: {}", e);
-            vga::set_color(Color::White, Color::Black);
+        ["rate", rate, rest @ ..] if rest.len() <= 1 => {
+            let delay = match rest.first() {
+                Some(delay) => delay.parse().ok(),
+                None => Some(ps2::typematic_delay_ms(ps2::typematic())),
+            };
+            let (Ok(rate), Some(delay)) = (rate.parse(), delay) else {
+                println!("Usage: kbd rate <cps> [delay_ms]");
+                return;
+            };
+            match ps2::set_typematic(rate, delay) {
+                Some(typematic) => {
+                    let centihertz = ps2::typematic_centihertz(typematic);
+                    println!(
+                        "Repeat {}.{:02} cps after {} ms",
+                        centihertz / 100,
+                        centihertz % 100,
+                        ps2::typematic_delay_ms(typematic)
+                    );
+                }
+                None => println!("Keyboard busy, try again"),
+            }
         }
+        _ => println!("Usage: kbd [rate <cps> [delay_ms]]"),
     }
 }
 
-/// Handle TCP connect.
-fn cmd_connect(host: &str, port: u16, stack: &mut NetworkStack, _dns: &mut DnsResolver) {
-    // Parse or resolve the host
-    let ip = if let Some(ip) = parse_ipv4(host) {
-        ip
-    } else {
-        // Would need async DNS resolution here
-        vga::set_color(Color::LightRed, Color::Black);
-        println!("DNS resolution for connect not yet implemented.");
-        println!("Please use an IP address directly.");
-        vga::set_color(Color::White, Color::Black);
-        return;
-    };
+/// Run the ring 3 demo and report how it exited.
+fn cmd_ring3() {
+    match usermode::run_demo() {
+        Ok(status) => println!("ring 3 program exited with status {}", status),
+        Err(e) => {
+            theme::set(Role::Error);
+            println!("ring3: {}", e);
+            theme::reset();
+        }
+    }
+}
 
-    println!("Connecting to {}:{}...", ip, port);
+/// Memory map and usage as JSON.
+fn json_memmap() -> Json {
+    let regions: Vec<Json> = memory::regions()
+        .iter()
+        .map(|region| {
+            Json::object()
+                .with("start", region.start)
+                .with("end", region.end)
+                .with("type", region.kind_name())
+                .with("allocated", region.allocated)
+        })
+        .collect();
+    Json::object()
+        .with("regions", regions)
+        .with("recycled_frames", memory::recycled_frames())
+        .with("heap_used", allocator::heap_used())
+        .with("heap_size", allocator::HEAP_SIZE)
+        .with("arenas", arena::in_use())
+        .with("arena_mapped", arena::mapped_total())
+}
 
-    let handle = stack.tcp_socket();
-    let remote = smoltcp::wire::IpEndpoint::new(IpAddress::Ipv4(ip), port);
-    let local_port = 49152 + (ip.0[3] as u16 % 1000); // Simple ephemeral port
+/// Registered devices and linked drivers as JSON.
+fn json_devices() -> Json {
+    let devices: Vec<Json> = driver::devices()
+        .iter()
+        .map(|device| {
+            Json::object()
+                .with("id", u64::from(device.id.0))
+                .with("name", device.name.as_str())
+                .with("kind", device.kind.name())
+        })
+        .collect();
+    let drivers: Vec<&str> = driver::linked().collect();
+    Json::object()
+        .with("devices", devices)
+        .with("drivers", drivers)
+}
 
-    match stack.tcp_connect(handle, remote, local_port) {
-        Ok(()) => {
-            vga::set_color(Color::LightGreen, Color::Black);
-            println!("Connection initiated to {}:{}", ip, port);
-            vga::set_color(Color::White, Color::Black);
-            println!("Use the main loop to check connection state.");
+/// List the registered devices and the drivers linked in.
+fn cmd_devices() {
+    let devices = driver::devices();
+    if devices.is_empty() {
+        println!("No devices registered");
+    } else {
+        println!("{:<4} {:<6} NAME", "ID", "KIND");
+        for device in &devices {
+            println!(
+                "{:<4} {:<6} {}",
+                device.id.0,
+                device.kind.name(),
+                device.name
+            );
         }
-        Err(e) => {
-            vga::set_color(Color::LightRed, Color::Black);
-            println!("Connection failed: {}", e);
-            vga::set_color(Color::White, Color::Black);
+    }
+    let drivers: Vec<&str> = driver::linked().collect();
+    println!();
+    if drivers.is_empty() {
+        println!("Drivers linked: none");
+    } else {
+        println!("Drivers linked: {}", drivers.join(", "));
+    }
+}
+
+/// Print the physical memory map with what the kernel uses of it.
+fn cmd_memmap() {
+    const KIB: u64 = 1024;
+
+    let regions = memory::regions();
+    if regions.is_empty() {
+        println!("Memory map not available");
+        return;
+    }
+    for region in &regions {
+        print!(
+            "{:#012x}-{:#012x} {:>9} KiB  {:<16}",
+            region.start,
+            region.end,
+            region.size() / KIB,
+            region.kind_name()
+        );
+        if region.allocated > 0 {
+            print!(" {} KiB allocated", region.allocated / KIB);
         }
+        println!();
+    }
+
+    let total = |kind| -> u64 {
+        regions
+            .iter()
+            .filter(|region| region.kind == kind)
+            .map(|region| region.size())
+            .sum()
+    };
+    let allocated: u64 = regions.iter().map(|region| region.allocated).sum();
+    println!();
+    println!(
+        "Usable:  {} KiB, {} KiB allocated, {} frames recycled",
+        total(MemoryRegionType::Usable) / KIB,
+        allocated / KIB,
+        memory::recycled_frames()
+    );
+    println!(
+        "ACPI:    {} KiB reclaimable, {} KiB NVS",
+        total(MemoryRegionType::AcpiReclaimable) / KIB,
+        total(MemoryRegionType::AcpiNvs) / KIB
+    );
+    println!(
+        "Heap:    {} of {} KiB used at {:#x}",
+        allocator::heap_used() as u64 / KIB,
+        allocator::HEAP_SIZE as u64 / KIB,
+        allocator::HEAP_START
+    );
+    println!(
+        "Arenas:  {} in use, {} KiB mapped at {:#x}",
+        arena::in_use(),
+        arena::mapped_total() / KIB,
+        arena::ARENA_BASE
+    );
+    if cfg!(feature = "heap-poison") {
+        println!(
+            "Poison:  {} violations, {} blocks quarantined, {} scrubs",
+            poison::violations(),
+            poison::quarantined(),
+            poison::scrubs()
+        );
+    }
+}
+
+/// Resolve an address to a symbol, or a symbol name to its address.
+fn cmd_ksym(_ctx: &mut CommandContext, args: &[&str]) {
+    let Some(&query) = args.first() else {
+        println!("Usage: ksym <addr> | ksym <name>");
+        return;
+    };
+    let hex = query.trim_start_matches("0x");
+    match u64::from_str_radix(hex, 16) {
+        Ok(addr) => match ksym::symbolize(addr) {
+            Some(symbol) => println!("{:#018x} {}", addr, symbol),
+            None => println!("{:#018x} not found in the symbol map", addr),
+        },
+        Err(_) => match ksym::lookup_name(query) {
+            Some(addr) => println!("{:#018x} {}", addr, query),
+            None => println!("Unknown symbol: {}", query),
+        },
     }
 }
 
 /// Show system information.
 fn cmd_sysinfo() {
     println!();
-    vga::set_color(Color::Cyan, Color::Black);
+    theme::set(Role::Accent);
     println!("SovelmaOS System Information");
     println!("============================");
-    vga::set_color(Color::White, Color::Black);
-    println!("  Version:    0.1.0");
+    theme::reset();
+    println!(
+        "  Version:    {} ({})",
+        buildinfo::VERSION,
+        buildinfo::GIT_HASH
+    );
     println!("  Arch:       x86_64");
     println!("  Platform:   QEMU");
 
+    let info = cpuid::info();
+    println!("  Processor:  {} {}", info.vendor(), info.brand());
+    println!(
+        "  Signature:  family {:#x}, model {:#x}, stepping {}",
+        info.family, info.model, info.stepping
+    );
+    let features: Vec<&str> = info.features.present().collect();
+    println!("  Features:   {}", features.join(" "));
+
+    let cpu = crate::task::idle::stats();
+    println!(
+        "  CPU:        {}% busy, {}% idle ({} halts)",
+        cpu.busy_percent(),
+        cpu.idle_percent(),
+        cpu.halts
+    );
+
+    let degraded = health::degraded();
+    if degraded.is_empty() {
+        println!("  Health:     all subsystems up");
+    } else {
+        theme::set(Role::Warning);
+        println!("  Health:     {} subsystem failure(s)", degraded.len());
+        theme::reset();
+        for degradation in degraded {
+            println!(
+                "              {}: {}",
+                degradation.subsystem, degradation.reason
+            );
+        }
+    }
+    if crashloop::safe_mode() {
+        theme::set(Role::Warning);
+        println!(
+            "  Boot:       safe mode ({} failed boots before)",
+            crashloop::failed_boots()
+        );
+        theme::reset();
+    }
+
     // Could add more system info here:
     // - Memory usage
     // - Uptime
-    // - CPU info
     // - Interrupt counts
     println!();
 }
-/// Run a simple WASM module test.
-fn cmd_wasm_test(filename: &str) {
-    use crate::fs::{FileSystem, ROOT_FS};
-    use crate::wasm::WasmEngine;
-    use alloc::vec;
 
-    println!();
-    vga::set_color(Color::Cyan, Color::Black);
-    println!("WASM Runtime Test executing '{}'", filename);
-    println!("-----------------");
-    vga::set_color(Color::White, Color::Black);
-
-    // Open file
-    let handle = match ROOT_FS.open(filename) {
-        Ok(h) => h,
-        Err(e) => {
-            vga::set_color(Color::LightRed, Color::Black);
-            println!("Failed to open file: {:?}", e);
-            vga::set_color(Color::White, Color::Black);
-            return;
+/// Benchmarks and operation counts selected by `bench` arguments.
+fn bench_selection(args: &[&str]) -> Option<Vec<(&'static bench::Benchmark, u64)>> {
+    match args {
+        [] => Some(
+            bench::BENCHMARKS
+                .iter()
+                .map(|benchmark| (benchmark, benchmark.default_ops))
+                .collect(),
+        ),
+        [name] => {
+            bench::find(name).map(|benchmark| alloc::vec![(benchmark, benchmark.default_ops)])
         }
-    };
-
-    // Read file
-    let size = ROOT_FS.size(handle).unwrap_or(0);
-    let mut buffer = vec![0u8; size];
-    if let Err(e) = ROOT_FS.read(handle, &mut buffer, 0) {
-        vga::set_color(Color::LightRed, Color::Black);
-        println!("Failed to read file: {:?}", e);
-        vga::set_color(Color::White, Color::Black);
-        return;
-    }
-
-    ROOT_FS.close(handle);
-
-    let engine = WasmEngine::new();
-
-    // Use spawn_process_with_caps to be safe/compliant, even if caps are empty for now.
-    // In a real test, we might want to grant some caps.
-    match engine.spawn_process_with_caps(&buffer, vec![]) {
-        Ok(mut process) => {
-            vga::set_color(Color::LightGreen, Color::Black);
-            println!("WASM process spawned successfully!");
-            vga::set_color(Color::White, Color::Black);
-
-            println!("Executing _start...");
-            match process.call("_start", &[]) {
-                Ok(_) => {
-                    vga::set_color(Color::LightGreen, Color::Black);
-                    println!("_start completed successfully!");
-                }
-                Err(e) => {
-                    vga::set_color(Color::LightRed, Color::Black);
-                    println!("Execution failed: {:?}", e);
-                }
-            }
-        }
-        Err(e) => {
-            vga::set_color(Color::LightRed, Color::Black);
-            println!("WASM test failed: {:?}", e);
+        [name, ops] => {
+            let ops = ops.parse().ok().filter(|&ops| ops > 0)?;
+            bench::find(name).map(|benchmark| alloc::vec![(benchmark, ops)])
         }
+        _ => None,
     }
-    vga::set_color(Color::White, Color::Black);
-    println!();
 }
 
-/// Handle Ping command.
-fn cmd_ping(host: &str, stack: &mut NetworkStack) {
-    let ip = if let Some(ip) = parse_ipv4(host) {
-        ip
-    } else {
-        println!("Invalid IP address. Hostname resolution in ping not yet implemented.");
+/// Print the benchmark names after a bad `bench` command line.
+fn bench_usage() {
+    println!("Usage: bench [name [ops]]");
+    let names: Vec<&str> = bench::BENCHMARKS
+        .iter()
+        .map(|benchmark| benchmark.name)
+        .collect();
+    println!("Benchmarks: {}", names.join(", "));
+}
+
+/// Run benchmarks and print one row each.
+fn cmd_bench(_ctx: &mut CommandContext, args: &[&str]) {
+    let Some(selection) = bench_selection(args) else {
+        bench_usage();
         return;
     };
+    let rate = |rate: Option<u64>, scale: u64| {
+        rate.map_or_else(|| String::from("-"), |rate| (rate / scale).to_string())
+    };
+    println!(
+        "{:<9} {:>9} {:>7} {:>9} {:>11} {:>7}  {}",
+        "BENCH", "OPS", "MS", "CYC/OP", "OPS/S", "MB/S", "OP"
+    );
+    for (benchmark, ops) in selection {
+        let sample = benchmark.run(ops);
+        println!(
+            "{:<9} {:>9} {:>7} {:>9} {:>11} {:>7}  {}",
+            sample.name,
+            sample.ops,
+            sample.ms,
+            sample.cycles_per_op(),
+            rate(sample.ops_per_sec(), 1),
+            rate(sample.bytes_per_sec(), 1_000_000),
+            benchmark.op
+        );
+    }
+}
 
-    println!("Pinging {}...", ip);
-
-    let handle = stack.icmp_socket();
-    let ident = 0x1234;
-    let seq_no = 1;
-
-    let echo_payload = [0xffu8; 8];
-    let mut echo_repr = smoltcp::wire::Icmpv4Repr::EchoRequest {
-        ident,
-        seq_no,
-        data: &echo_payload,
+/// Benchmark results as JSON.
+fn json_bench(_ctx: &mut CommandContext, args: &[&str]) -> Option<Json> {
+    let Some(selection) = bench_selection(args) else {
+        return Some(
+            Json::object()
+                .with("error", "usage")
+                .with("message", "bench [name [ops]]"),
+        );
     };
+    let results: Vec<Json> = selection
+        .into_iter()
+        .map(|(benchmark, ops)| {
+            let sample = benchmark.run(ops);
+            Json::object()
+                .with("name", sample.name)
+                .with("op", benchmark.op)
+                .with("ops", sample.ops)
+                .with("bytes", sample.bytes)
+                .with("cycles", sample.cycles)
+                .with("ms", sample.ms)
+                .with("cycles_per_op", sample.cycles_per_op())
+                .with("ops_per_sec", sample.ops_per_sec())
+                .with("bytes_per_sec", sample.bytes_per_sec())
+        })
+        .collect();
+    Some(Json::object().with("benchmarks", results))
+}
 
-    let mut buffer = [0u8; 16]; // 8 bytes header + 8 bytes payload
-    let mut icmp_packet = smoltcp::wire::Icmpv4Packet::new_unchecked(&mut buffer);
-    echo_repr.emit(&mut icmp_packet, &Default::default());
+/// Tracing state as JSON.
+fn json_trace() -> Json {
+    Json::object()
+        .with("enabled", trace::enabled())
+        .with("spans", trace::spans().len())
+        .with("capacity", trace::TRACE_CAPACITY)
+        .with("overwritten", trace::overwritten())
+}
 
-    let mut socket = stack.sockets().get_mut::<smoltcp::socket::icmp::Socket>(handle);
-    
-    match socket.send_slice(&buffer, smoltcp::wire::IpAddress::Ipv4(ip)) {
-        Ok(_) => {
-            println!("  Echo request sent. Waiting for reply...");
+/// Turn span tracing on or off, or dump the spans to serial.
+fn cmd_trace(args: &[&str]) {
+    match args {
+        [] => println!(
+            "Tracing {}: {} spans recorded ({} overwritten, {} kept)",
+            if trace::enabled() { "on" } else { "off" },
+            trace::spans().len(),
+            trace::overwritten(),
+            trace::TRACE_CAPACITY
+        ),
+        ["on"] => {
+            trace::enable();
+            println!("Tracing on");
         }
-        Err(e) => {
-            vga::set_color(Color::LightRed, Color::Black);
-            println!("  Failed to send: {:?}", e);
-            vga::set_color(Color::White, Color::Black);
+        ["off"] => {
+            trace::disable();
+            println!("Tracing off; 'trace dump' writes the spans to serial");
+        }
+        ["dump"] => {
+            // Dumping would record itself
+            let was_enabled = trace::enabled();
+            trace::disable();
+            let count = trace::dump_chrome();
+            if was_enabled {
+                trace::enable();
+            }
+            println!("Wrote {} spans to serial as Chrome trace JSON", count);
         }
+        _ => println!("Usage: trace [on|off|dump]"),
     }
 }
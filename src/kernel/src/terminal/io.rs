@@ -0,0 +1,126 @@
+//! Terminal output routing.
+//!
+//! Shell output is written with `print!`/`println!` and colored with
+//! `vga::set_color`. By default that goes to the VGA screen; a task can
+//! attach a `TerminalIo` sink so everything it prints (including command
+//! output) goes elsewhere, e.g. to a remote telnet session. Code running
+//! outside of task context (boot, the kernel self-tests) has a sink slot
+//! of its own, so `terminal::capture` works there too. Interrupt
+//! handlers print too, so the sinks are only locked with interrupts off.
+
+use crate::arch::x86_64::vga::Color;
+use crate::sync::TrackedMutex;
+use crate::task::{current_task, TaskId};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::fmt;
+use x86_64::instructions::interrupts;
+
+/// A destination for terminal output.
+pub trait TerminalIo: Send {
+    /// Write a string.
+    fn write_str(&mut self, s: &str);
+    /// Change the color of subsequent output.
+    fn set_color(&mut self, foreground: Color, background: Color);
+    /// Clear the display.
+    fn clear(&mut self);
+}
+
+/// Output sinks attached to tasks, and under `None` the one for code
+/// outside of task context; without one output goes to the screen.
+static TASK_OUTPUT: TrackedMutex<BTreeMap<Option<TaskId>, Box<dyn TerminalIo>>> =
+    TrackedMutex::new("terminal_io", BTreeMap::new());
+
+/// Route the current task's output to `sink`.
+pub fn attach(sink: Box<dyn TerminalIo>) {
+    let task = current_task();
+    // Dropped outside the lock, in case the old sink prints on the way out
+    let _old = interrupts::without_interrupts(|| TASK_OUTPUT.lock().insert(task, sink));
+}
+
+/// Restore screen output for the current task.
+pub fn detach() {
+    let task = current_task();
+    let _old = interrupts::without_interrupts(|| TASK_OUTPUT.lock().remove(&task));
+}
+
+/// Swap the current task's sink for `sink` (`None` restores the screen),
+/// returning the previous one.
+pub fn replace(sink: Option<Box<dyn TerminalIo>>) -> Option<Box<dyn TerminalIo>> {
+    let task = current_task();
+    interrupts::without_interrupts(|| {
+        let mut sinks = TASK_OUTPUT.lock();
+        match sink {
+            Some(sink) => sinks.insert(task, sink),
+            None => sinks.remove(&task),
+        }
+    })
+}
+
+/// Run `f` on the current task's sink, if it has one.
+///
+/// Returns `false` when output should go to the screen instead.
+fn with_sink(f: impl FnOnce(&mut dyn TerminalIo)) -> bool {
+    let task = current_task();
+    // An interrupt handler printing while a task holds the lock would
+    // spin forever
+    interrupts::without_interrupts(|| {
+        let mut sinks = TASK_OUTPUT.lock();
+        match sinks.get_mut(&task) {
+            Some(sink) => {
+                f(sink.as_mut());
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Adapter so `write_fmt` can target a `TerminalIo`.
+struct FmtAdapter<'a>(&'a mut dyn TerminalIo);
+
+impl fmt::Write for FmtAdapter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s);
+        Ok(())
+    }
+}
+
+/// Print through the current task's sink. Returns `false` if it has none.
+pub(crate) fn route_print(args: fmt::Arguments) -> bool {
+    with_sink(|sink| {
+        let _ = fmt::Write::write_fmt(&mut FmtAdapter(sink), args);
+    })
+}
+
+/// Set color on the current task's sink. Returns `false` if it has none.
+pub(crate) fn route_set_color(foreground: Color, background: Color) -> bool {
+    with_sink(|sink| sink.set_color(foreground, background))
+}
+
+/// Clear the current task's sink. Returns `false` if it has none.
+pub(crate) fn route_clear() -> bool {
+    with_sink(|sink| sink.clear())
+}
+
+/// Map a VGA color to its ANSI SGR foreground code.
+pub fn ansi_foreground(color: Color) -> u8 {
+    match color {
+        Color::Black => 30,
+        Color::Red => 31,
+        Color::Green => 32,
+        Color::Brown => 33,
+        Color::Blue => 34,
+        Color::Magenta => 35,
+        Color::Cyan => 36,
+        Color::LightGray => 37,
+        Color::DarkGray => 90,
+        Color::LightRed => 91,
+        Color::LightGreen => 92,
+        Color::Yellow => 93,
+        Color::LightBlue => 94,
+        Color::Pink => 95,
+        Color::LightCyan => 96,
+        Color::White => 97,
+    }
+}
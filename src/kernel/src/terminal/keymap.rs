@@ -0,0 +1,53 @@
+//! Decoding PS/2 scancodes into keys.
+//!
+//! `KeyDecoder` is the keyboard state machine behind `decode_scancode`,
+//! kept apart from the hardware (LEDs, recording) so `fuzz/` can build it
+//! on the host and feed it arbitrary scancode sequences.
+
+use pc_keyboard::{
+    layouts, DecodedKey, HandleControl, KeyEvent, Keyboard, Modifiers, ScancodeSet1,
+};
+
+/// Decoder for scan code set 1 on a US 104-key layout.
+///
+/// Ctrl with a letter types the control character (Ctrl-C is `'\x03'`),
+/// as a terminal sends it over telnet, so the shell handles both alike.
+pub struct KeyDecoder {
+    keyboard: Keyboard<layouts::Us104Key, ScancodeSet1>,
+}
+
+impl KeyDecoder {
+    /// A decoder with no keys held.
+    pub const fn new() -> Self {
+        Self {
+            keyboard: Keyboard::new(
+                ScancodeSet1::new(),
+                layouts::Us104Key,
+                HandleControl::MapLettersToUnicode,
+            ),
+        }
+    }
+
+    /// Feed one scancode. Returns the key event it completes, if any;
+    /// invalid sequences are absorbed.
+    pub fn add_byte(&mut self, scancode: u8) -> Option<KeyEvent> {
+        self.keyboard.add_byte(scancode).ok().flatten()
+    }
+
+    /// Apply a key event to the modifier state and return the key it
+    /// types, if any (Shift alone types nothing).
+    pub fn process(&mut self, event: KeyEvent) -> Option<DecodedKey> {
+        self.keyboard.process_keyevent(event)
+    }
+
+    /// Modifier and lock keys currently in effect.
+    pub fn modifiers(&self) -> &Modifiers {
+        self.keyboard.get_modifiers()
+    }
+}
+
+impl Default for KeyDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,130 @@
+//! Splitting a command line into a command name and arguments, with
+//! quoting, escapes and continuation lines.
+//!
+//! This is the part of command parsing that does not need the rest of the
+//! kernel, so `fuzz/` can build it on the host and feed it garbage: lines
+//! arrive from the keyboard, serial, telnet and the control channel, and
+//! none of them may panic the shell.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Flag selecting JSON output, accepted anywhere in the arguments.
+pub const JSON_FLAG: &str = "--json";
+
+/// Word separating the stages of a pipeline.
+pub const PIPE: &str = "|";
+
+/// Why a line cannot be split yet: it continues on the next line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Incomplete {
+    /// A quote is still open; the line break belongs to the quoted word.
+    Quote,
+    /// The line ends with a backslash, which joins it to the next.
+    Continued,
+}
+
+/// Split `input` into words.
+///
+/// Unquoted whitespace separates words. Within double quotes whitespace
+/// is kept; within single quotes every character stands for itself. A
+/// backslash outside single quotes escapes the next character: `\n` and
+/// `\t` are a line break and a tab, a backslash before a line break
+/// removes both, and any other character stands for itself (`\"`, `\\`,
+/// `\ `). `""` is an empty word.
+///
+/// Quoting only groups characters: a quoted `|` or `--json` is still
+/// taken as one by `stages` and `parse`.
+pub fn words(input: &str) -> Result<Vec<String>, Incomplete> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // Whether a word has started, which an empty quoted word does too
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), c) => word.push(c),
+            (_, '\\') => {
+                let Some(escaped) = chars.next() else {
+                    return Err(Incomplete::Continued);
+                };
+                match escaped {
+                    '\n' => continue,
+                    'n' => word.push('\n'),
+                    't' => word.push('\t'),
+                    c => word.push(c),
+                }
+                in_word = true;
+            }
+            (Some(_), '"') => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(core::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(Incomplete::Quote);
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Split a complete line into the command name and its arguments (see
+/// `words`).
+///
+/// Returns `None` for a blank line or one that continues on the next.
+pub fn split(input: &str) -> Option<(String, Vec<String>)> {
+    let mut words = words(input).ok()?.into_iter();
+    let name = words.next()?;
+    Some((name, words.collect()))
+}
+
+/// The stages of a pipeline: `args` split at each `|`.
+pub fn stages<T: AsRef<str>>(args: &[T]) -> impl Iterator<Item = &[T]> {
+    args.split(|arg| arg.as_ref() == PIPE)
+}
+
+/// A command line with the name normalized and `--json` taken out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parsed {
+    /// Name as typed, in lowercase.
+    pub name: String,
+    /// Arguments, without `--json`.
+    pub args: Vec<String>,
+    /// Whether `--json` was given.
+    pub json: bool,
+}
+
+/// Parse a command name and its arguments.
+///
+/// Returns `None` for an empty command name.
+pub fn parse(cmd: &str, args: &[&str]) -> Option<Parsed> {
+    if cmd.is_empty() {
+        return None;
+    }
+    Some(Parsed {
+        name: cmd.to_lowercase(),
+        args: args
+            .iter()
+            .filter(|arg| **arg != JSON_FLAG)
+            .map(|arg| arg.to_string())
+            .collect(),
+        json: args.contains(&JSON_FLAG),
+    })
+}
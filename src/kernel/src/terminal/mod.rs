@@ -5,32 +5,67 @@
 //! # Architecture
 //!
 //! - `shell`: Command-line shell with input handling
-//! - `commands`: Built-in shell commands
+//! - `commands`: Command lines and the shell's own commands
+//! - `line`: Splitting command lines into words
+//! - `args`: Options and positional arguments, with generated usage
+//! - `keymap`: Scancode decoding
+//! - `registry`: Registered `ShellCommand`s, looked up by name
+//! - `io`: Per-task output routing (screen or remote session)
+//! - `capture`: Recording what a closure prints
+//! - `pager`: `--More--` paging of captured output
+//! - `theme`: Color themes and the prompt (`/etc/shellrc`)
+//! - `json`: Machine-readable command output (`--json`)
+//!
+//! Without the `terminal` feature only `io`, `capture`, `theme`, `json`
+//! and the parsing modules are built: the kernel still prints and colors
+//! its output, but has no shell.
 
+pub mod args;
+pub mod capture;
+#[cfg(feature = "terminal")]
 pub mod commands;
+pub mod io;
+pub mod json;
+pub mod keymap;
+pub mod line;
+#[cfg(feature = "terminal")]
+pub mod pager;
+#[cfg(feature = "terminal")]
+pub mod registry;
+#[cfg(feature = "terminal")]
 pub mod shell;
+pub mod theme;
 
-pub use commands::Command;
+pub use capture::{capture, Output};
+#[cfg(feature = "terminal")]
+pub use commands::{print_error, Command, CommandContext};
+#[cfg(feature = "terminal")]
+pub use registry::ShellCommand;
+#[cfg(feature = "terminal")]
 pub use shell::Terminal;
 
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use crate::arch::x86_64::ps2;
+use keymap::KeyDecoder;
+use pc_keyboard::DecodedKey;
 
 /// Global keyboard decoder instance.
-static KEYBOARD: spin::Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-    spin::Mutex::new(Keyboard::new(
-        ScancodeSet1::new(),
-        layouts::Us104Key,
-        HandleControl::Ignore,
-    ));
+static KEYBOARD: spin::Mutex<KeyDecoder> = spin::Mutex::new(KeyDecoder::new());
 
 /// Decode a PS/2 scancode to a key event.
 ///
-/// Returns the decoded key if a complete key event was received.
+/// Returns the decoded key if a complete key event was received. The
+/// keyboard LEDs follow Caps Lock and Num Lock. The scancode is recorded
+/// when booted with `record` (see `replay`).
 pub fn decode_scancode(scancode: u8) -> Option<DecodedKey> {
+    crate::replay::record_scancode(scancode);
     let mut keyboard = KEYBOARD.lock();
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        keyboard.process_keyevent(key_event)
-    } else {
-        None
-    }
+    let event = keyboard.add_byte(scancode)?;
+    let key = keyboard.process(event);
+    let modifiers = keyboard.modifiers();
+    ps2::set_leds(ps2::Leds {
+        caps_lock: modifiers.capslock,
+        num_lock: modifiers.numlock,
+        ..ps2::leds()
+    });
+    key
 }
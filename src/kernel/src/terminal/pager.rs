@@ -0,0 +1,137 @@
+//! Paging of long command output.
+//!
+//! The shell runs each command with its output captured (see
+//! `terminal::capture`). Output that fits on the screen is printed at
+//! once; longer output is shown a page at a time behind a `--More--`
+//! prompt: space shows the next page, enter the next line and `q`
+//! discards the rest.
+
+use super::capture::{Chunk, Output};
+use super::theme::{self, Role};
+use crate::arch::x86_64::vga::{self, Color, BUFFER_HEIGHT, BUFFER_WIDTH};
+use crate::print;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// Rows of output per page; the last screen row holds the prompt.
+pub const PAGE_ROWS: usize = BUFFER_HEIGHT - 1;
+
+/// Screen rows a line takes up.
+fn rows(line: &[Chunk]) -> usize {
+    let width: usize = line
+        .iter()
+        .map(|chunk| match chunk {
+            Chunk::Text(text) => text.trim_end_matches('\n').chars().count(),
+            _ => 0,
+        })
+        .sum();
+    width.div_ceil(BUFFER_WIDTH).max(1)
+}
+
+/// Output being shown a page at a time.
+pub struct Pager {
+    /// Lines not shown yet.
+    lines: VecDeque<Vec<Chunk>>,
+    /// Rows per page.
+    page_rows: usize,
+    /// Colors in effect after the last line shown.
+    color: (Color, Color),
+    /// Length of the prompt on screen; 0 if none.
+    prompt_len: usize,
+}
+
+impl Pager {
+    /// Create a pager over `output`; nothing is shown yet.
+    pub fn new(output: Output, page_rows: usize) -> Self {
+        Self {
+            lines: output.lines(),
+            page_rows: page_rows.max(1),
+            color: theme::colors(Role::Text),
+            prompt_len: 0,
+        }
+    }
+
+    /// Show `output`, paging it if it does not fit on one screen.
+    ///
+    /// Returns the pager if output remains; keys must then go to
+    /// `handle_key` until it finishes.
+    pub fn start(output: Output) -> Option<Self> {
+        let mut pager = Self::new(output, PAGE_ROWS);
+        pager.show(PAGE_ROWS).then_some(pager)
+    }
+
+    /// Number of lines not shown yet.
+    pub fn remaining(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Show at least one line and up to `rows` rows, then the prompt if
+    /// output remains. Returns whether output remains.
+    pub fn show(&mut self, rows: usize) -> bool {
+        self.erase_prompt();
+        let mut used = 0;
+        while let Some(line) = self.lines.front() {
+            let needed = self::rows(line);
+            if used > 0 && used + needed > rows {
+                break;
+            }
+            used += needed;
+            if let Some(line) = self.lines.pop_front() {
+                self.print_line(&line);
+            }
+        }
+        if self.lines.is_empty() {
+            return false;
+        }
+        self.show_prompt();
+        true
+    }
+
+    /// Handle a key while paging. Returns whether output remains.
+    pub fn handle_key(&mut self, c: char) -> bool {
+        match c {
+            ' ' => self.show(self.page_rows),
+            '\n' | '\r' => self.show(1),
+            'q' | 'Q' => {
+                self.erase_prompt();
+                self.lines.clear();
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Print one line, tracking the colors it sets.
+    fn print_line(&mut self, line: &[Chunk]) {
+        for chunk in line {
+            match chunk {
+                Chunk::Text(text) => print!("{}", text),
+                Chunk::Color(foreground, background) => {
+                    vga::set_color(*foreground, *background);
+                    self.color = (*foreground, *background);
+                }
+                Chunk::Clear => vga::clear_screen(),
+            }
+        }
+    }
+
+    /// Show the `--More--` prompt. Only the last line can lack a newline,
+    /// so the prompt always starts on a fresh line.
+    fn show_prompt(&mut self) {
+        let prompt = alloc::format!("--More-- ({} lines left)", self.lines.len());
+        theme::set(Role::Status);
+        print!("{}", prompt);
+        vga::set_color(self.color.0, self.color.1);
+        self.prompt_len = prompt.len();
+    }
+
+    /// Remove the prompt from the screen, if shown.
+    fn erase_prompt(&mut self) {
+        if self.prompt_len > 0 {
+            theme::reset();
+            print!("\r{:width$}\r", "", width = self.prompt_len);
+            vga::set_color(self.color.0, self.color.1);
+            self.prompt_len = 0;
+        }
+    }
+}
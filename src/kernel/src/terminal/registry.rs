@@ -0,0 +1,223 @@
+//! Shell command registry.
+//!
+//! Every shell command is a `ShellCommand` registered by the subsystem that
+//! implements it, usually while the kernel starts. The shell looks commands
+//! up by name or alias, and `help` lists whatever is registered.
+//!
+//! Most commands are plain functions described by a `Builtin`; subsystems
+//! that need state can implement the trait themselves.
+
+use super::commands::CommandContext;
+use super::json::Json;
+use crate::sync::TrackedMutex;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Commands available to every shell.
+static COMMANDS: TrackedMutex<Registry> = TrackedMutex::new("shell_commands", Registry::new());
+
+/// A command the shell can run.
+pub trait ShellCommand: Send + Sync {
+    /// Name typed at the prompt.
+    fn name(&self) -> &'static str;
+
+    /// Other names the command answers to.
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Argument synopsis shown by `help`, e.g. `<host> <port>`.
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    /// One-line description shown by `help`.
+    fn help(&self) -> &'static str;
+
+    /// Index of the argument naming a host the shell must resolve before
+    /// the command runs, if any.
+    fn host_arg(&self, _args: &[&str]) -> Option<usize> {
+        None
+    }
+
+    /// Run the command.
+    fn run(&self, ctx: &mut CommandContext, args: &[&str]);
+
+    /// Run the command for `--json`; `None` if it has no JSON output.
+    fn json(&self, _ctx: &mut CommandContext, _args: &[&str]) -> Option<Json> {
+        None
+    }
+}
+
+/// A command implemented by plain functions.
+#[derive(Clone, Copy)]
+pub struct Builtin {
+    /// Name typed at the prompt.
+    pub name: &'static str,
+    /// Other names the command answers to.
+    pub aliases: &'static [&'static str],
+    /// Argument synopsis.
+    pub usage: &'static str,
+    /// One-line description.
+    pub help: &'static str,
+    /// Finds the host argument to resolve.
+    pub host_arg: fn(&[&str]) -> Option<usize>,
+    /// Runs the command.
+    pub run: fn(&mut CommandContext, &[&str]),
+    /// Produces the `--json` output.
+    pub json: fn(&mut CommandContext, &[&str]) -> Option<Json>,
+}
+
+impl Builtin {
+    /// `host_arg` for commands without a host argument.
+    pub fn no_host(_args: &[&str]) -> Option<usize> {
+        None
+    }
+
+    /// `host_arg` for commands whose first argument is a host.
+    pub fn first_arg_host(args: &[&str]) -> Option<usize> {
+        (!args.is_empty()).then_some(0)
+    }
+
+    /// `json` for commands without JSON output.
+    pub fn no_json(_ctx: &mut CommandContext, _args: &[&str]) -> Option<Json> {
+        None
+    }
+}
+
+impl ShellCommand for Builtin {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        self.aliases
+    }
+
+    fn usage(&self) -> &'static str {
+        self.usage
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn host_arg(&self, args: &[&str]) -> Option<usize> {
+        (self.host_arg)(args)
+    }
+
+    fn run(&self, ctx: &mut CommandContext, args: &[&str]) {
+        (self.run)(ctx, args)
+    }
+
+    fn json(&self, ctx: &mut CommandContext, args: &[&str]) -> Option<Json> {
+        (self.json)(ctx, args)
+    }
+}
+
+/// Errors from registering a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryError {
+    /// The name or an alias is already taken.
+    Duplicate(&'static str),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::Duplicate(name) => write!(f, "command {} already registered", name),
+        }
+    }
+}
+
+/// Commands by name, with their aliases.
+pub struct Registry {
+    commands: BTreeMap<&'static str, Arc<dyn ShellCommand>>,
+    /// Alias to command name.
+    aliases: BTreeMap<&'static str, &'static str>,
+}
+
+impl Registry {
+    /// Create an empty registry.
+    pub const fn new() -> Self {
+        Self {
+            commands: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+        }
+    }
+
+    /// Add a command. Fails without changes if its name or an alias is
+    /// already in use.
+    pub fn register(&mut self, command: Arc<dyn ShellCommand>) -> Result<(), RegistryError> {
+        let name = command.name();
+        if let Some(taken) = core::iter::once(&name)
+            .chain(command.aliases())
+            .find(|name| self.contains(name))
+        {
+            return Err(RegistryError::Duplicate(taken));
+        }
+        for alias in command.aliases() {
+            self.aliases.insert(alias, name);
+        }
+        self.commands.insert(name, command);
+        Ok(())
+    }
+
+    /// Find a command by name or alias.
+    pub fn lookup(&self, name: &str) -> Option<Arc<dyn ShellCommand>> {
+        let name = self.aliases.get(name).copied().unwrap_or(name);
+        self.commands.get(name).cloned()
+    }
+
+    /// Check whether a name or alias is in use.
+    pub fn contains(&self, name: &str) -> bool {
+        self.commands.contains_key(name) || self.aliases.contains_key(name)
+    }
+
+    /// All commands, sorted by name.
+    pub fn commands(&self) -> Vec<Arc<dyn ShellCommand>> {
+        self.commands.values().cloned().collect()
+    }
+
+    /// Number of commands.
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Check whether no commands are registered.
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Make a command available to every shell.
+pub fn register(command: Arc<dyn ShellCommand>) -> Result<(), RegistryError> {
+    COMMANDS.lock().register(command)
+}
+
+/// Register a subsystem's builtins, logging any that clash.
+pub fn register_builtins(builtins: &[Builtin]) {
+    for builtin in builtins {
+        if let Err(e) = register(Arc::new(*builtin)) {
+            log::warn!(target: "shell", "{}", e);
+        }
+    }
+}
+
+/// Find a registered command by name or alias.
+pub fn lookup(name: &str) -> Option<Arc<dyn ShellCommand>> {
+    COMMANDS.lock().lookup(name)
+}
+
+/// All registered commands, sorted by name.
+pub fn commands() -> Vec<Arc<dyn ShellCommand>> {
+    COMMANDS.lock().commands()
+}
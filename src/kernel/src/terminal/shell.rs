@@ -1,9 +1,28 @@
 //! Command-line shell with input handling.
 //!
-//! Provides line editing and command history.
+//! Provides line editing, command history, paging of command output and
+//! following the kernel log (`dmesg --follow`). A line ending in a
+//! backslash or with a quote left open continues on the next, after a
+//! `>` prompt (see `line::words`).
+//!
+//! Control chords arrive as control characters, from the keyboard (see
+//! `keymap`) and from telnet alike: Ctrl-C abandons the line or stops
+//! paging, Ctrl-L clears the screen and keeps the line, Ctrl-U deletes up
+//! to the cursor, Ctrl-W the word before it and Ctrl-D the character
+//! under it.
+//!
+//! A line longer than the terminal is wide wraps onto the rows below. The
+//! editor keeps count of the columns it has printed since the prompt, so
+//! it can move the cursor between rows with ANSI sequences (which the VGA
+//! writer understands too) and erase what a shorter line leaves behind.
 
+use super::capture::Output;
 use super::commands::Command;
-use crate::arch::x86_64::vga::{self, Color};
+use super::line::{self, Incomplete};
+use super::pager::Pager;
+use super::theme::{self, Role};
+use crate::arch::x86_64::vga;
+use crate::klog;
 use crate::{print, println};
 use alloc::string::String;
 use alloc::vec::Vec;
@@ -15,18 +34,48 @@ const MAX_LINE_LENGTH: usize = 256;
 /// Maximum command history size.
 const MAX_HISTORY: usize = 16;
 
+/// Width of the terminal; telnet clients are taken to be as wide as the
+/// screen.
+const COLUMNS: usize = vga::BUFFER_WIDTH;
+
+/// The control character Ctrl plus `letter` types.
+const fn ctrl(letter: u8) -> char {
+    (letter & 0x1f) as char
+}
+
+const CTRL_C: char = ctrl(b'c');
+const CTRL_D: char = ctrl(b'd');
+const CTRL_L: char = ctrl(b'l');
+const CTRL_U: char = ctrl(b'u');
+const CTRL_W: char = ctrl(b'w');
+
 /// Terminal shell with line editing and history.
 pub struct Terminal {
     /// Current input buffer.
     input_buffer: String,
     /// Cursor position in input buffer.
     cursor: usize,
+    /// Columns the prompt takes.
+    prompt_width: usize,
+    /// Characters of the input on screen, more than there are after a
+    /// deletion until the line is redrawn.
+    drawn: usize,
+    /// Where the terminal's cursor is, in columns from the start of the
+    /// prompt.
+    position: usize,
     /// Command history.
     history: Vec<String>,
     /// Current position in history (for up/down navigation).
     history_index: Option<usize>,
     /// Saved input when navigating history.
     saved_input: String,
+    /// Earlier lines of a command that continues on the current one.
+    continued: Option<String>,
+    /// Command output still being paged; takes all keys while set.
+    pager: Option<Pager>,
+    /// Sequence number of the next log record `dmesg --follow` prints;
+    /// any key stops following.
+    follow: Option<u64>,
 }
 
 impl Terminal {
@@ -35,24 +84,64 @@ impl Terminal {
         Self {
             input_buffer: String::with_capacity(MAX_LINE_LENGTH),
             cursor: 0,
+            prompt_width: 0,
+            drawn: 0,
+            position: 0,
             history: Vec::with_capacity(MAX_HISTORY),
             history_index: None,
             saved_input: String::new(),
+            continued: None,
+            pager: None,
+            follow: None,
         }
     }
 
     /// Display the shell prompt.
-    pub fn prompt(&self) {
-        vga::set_color(Color::LightGreen, Color::Black);
-        print!("sovelma");
-        vga::set_color(Color::White, Color::Black);
-        print!("> ");
+    ///
+    /// Deferred while output is being paged or the log followed; it is
+    /// shown when they are done.
+    pub fn prompt(&mut self) {
+        if self.pager.is_some() || self.follow.is_some() {
+            return;
+        }
+        self.print_prompt();
+    }
+
+    /// Print the shell prompt, or `>` on a continuation line, at the start
+    /// of a row.
+    fn print_prompt(&mut self) {
+        self.position = 0;
+        self.drawn = 0;
+        self.prompt_width = if self.continued.is_none() {
+            theme::print_prompt(theme::ROOT_DIR)
+        } else {
+            theme::set(Role::Prompt);
+            print!(">");
+            theme::reset();
+            print!(" ");
+            2
+        };
+        self.advance(self.prompt_width);
     }
 
     /// Handle a decoded key input.
     ///
     /// Returns a command if the user pressed Enter with a valid command.
     pub fn handle_key(&mut self, key: DecodedKey) -> Option<Command> {
+        if let Some(pager) = &mut self.pager {
+            if let DecodedKey::Unicode(c) = key {
+                let c = if c == CTRL_C { 'q' } else { c };
+                if !pager.handle_key(c) {
+                    self.pager = None;
+                    self.prompt();
+                }
+            }
+            return None;
+        }
+        if self.follow.take().is_some() {
+            self.prompt();
+            return None;
+        }
         match key {
             DecodedKey::Unicode(c) => self.handle_char(c),
             DecodedKey::RawKey(raw) => {
@@ -66,18 +155,41 @@ impl Terminal {
     fn handle_char(&mut self, c: char) -> Option<Command> {
         match c {
             '\n' | '\r' => {
-                println!(); // Move to next line
-                let command = self.parse_command();
-
-                // Add to history if not empty
-                if !self.input_buffer.is_empty() {
-                    self.add_to_history(self.input_buffer.clone());
+                // Leave the cursor below the whole line
+                self.move_to(self.prompt_width + self.input_buffer.len());
+                if self.column() != 0 {
+                    println!();
                 }
-
+                let mut input = self.continued.take().unwrap_or_default();
+                input.push_str(&self.input_buffer);
                 self.input_buffer.clear();
                 self.cursor = 0;
                 self.history_index = None;
 
+                match line::words(&input) {
+                    // The backslash joins the lines; an open quote keeps
+                    // the line break
+                    Err(Incomplete::Continued) => {
+                        input.pop();
+                        self.continued = Some(input);
+                        self.prompt();
+                        return None;
+                    }
+                    Err(Incomplete::Quote) => {
+                        input.push('\n');
+                        self.continued = Some(input);
+                        self.prompt();
+                        return None;
+                    }
+                    Ok(_) => {}
+                }
+                let command = Command::parse_line(&input);
+
+                // Add to history if not empty
+                if !input.is_empty() {
+                    self.add_to_history(input);
+                }
+
                 if command.is_some() {
                     return command;
                 }
@@ -91,7 +203,7 @@ impl Terminal {
                 if self.cursor > 0 {
                     self.input_buffer.remove(self.cursor - 1);
                     self.cursor -= 1;
-                    self.redraw_line();
+                    self.redraw_from(self.cursor);
                 }
                 None
             }
@@ -99,17 +211,47 @@ impl Terminal {
                 // Tab - could implement auto-completion here
                 None
             }
+            CTRL_C => {
+                self.move_to(self.prompt_width + self.input_buffer.len());
+                println!("^C");
+                self.continued = None;
+                self.input_buffer.clear();
+                self.cursor = 0;
+                self.history_index = None;
+                self.prompt();
+                None
+            }
+            CTRL_D => {
+                if self.cursor < self.input_buffer.len() {
+                    self.input_buffer.remove(self.cursor);
+                    self.redraw_from(self.cursor);
+                }
+                None
+            }
+            CTRL_L => {
+                self.clear();
+                self.print_prompt();
+                self.redraw_from(0);
+                None
+            }
+            CTRL_U => {
+                self.input_buffer.drain(..self.cursor);
+                self.cursor = 0;
+                self.redraw_from(0);
+                None
+            }
+            CTRL_W => {
+                let start = word_start(&self.input_buffer[..self.cursor]);
+                self.input_buffer.drain(start..self.cursor);
+                self.cursor = start;
+                self.redraw_from(start);
+                None
+            }
             c if c.is_ascii() && !c.is_control() => {
                 if self.input_buffer.len() < MAX_LINE_LENGTH {
                     self.input_buffer.insert(self.cursor, c);
                     self.cursor += 1;
-
-                    // If cursor is at end, just print the char
-                    if self.cursor == self.input_buffer.len() {
-                        print!("{}", c);
-                    } else {
-                        self.redraw_line();
-                    }
+                    self.redraw_from(self.cursor - 1);
                 }
                 None
             }
@@ -131,27 +273,27 @@ impl Terminal {
             KeyCode::ArrowLeft => {
                 if self.cursor > 0 {
                     self.cursor -= 1;
-                    print!("\x1b[D"); // Move cursor left
+                    self.move_to(self.prompt_width + self.cursor);
                 }
             }
             KeyCode::ArrowRight => {
                 if self.cursor < self.input_buffer.len() {
                     self.cursor += 1;
-                    print!("\x1b[C"); // Move cursor right
+                    self.move_to(self.prompt_width + self.cursor);
                 }
             }
             KeyCode::Home => {
                 self.cursor = 0;
-                self.redraw_line();
+                self.move_to(self.prompt_width);
             }
             KeyCode::End => {
                 self.cursor = self.input_buffer.len();
-                self.redraw_line();
+                self.move_to(self.prompt_width + self.cursor);
             }
             KeyCode::Delete => {
                 if self.cursor < self.input_buffer.len() {
                     self.input_buffer.remove(self.cursor);
-                    self.redraw_line();
+                    self.redraw_from(self.cursor);
                 }
             }
             _ => {}
@@ -182,7 +324,7 @@ impl Terminal {
         if let Some(idx) = self.history_index {
             self.input_buffer = self.history[idx].clone();
             self.cursor = self.input_buffer.len();
-            self.redraw_line();
+            self.redraw_from(0);
         }
     }
 
@@ -197,13 +339,13 @@ impl Terminal {
                 self.history_index = None;
                 self.input_buffer = self.saved_input.clone();
                 self.cursor = self.input_buffer.len();
-                self.redraw_line();
+                self.redraw_from(0);
             }
             Some(idx) => {
                 self.history_index = Some(idx + 1);
                 self.input_buffer = self.history[idx + 1].clone();
                 self.cursor = self.input_buffer.len();
-                self.redraw_line();
+                self.redraw_from(0);
             }
         }
     }
@@ -221,37 +363,86 @@ impl Terminal {
         self.history.push(cmd);
     }
 
-    /// Redraw the current input line.
-    fn redraw_line(&self) {
-        // Move to start of line, clear it, print prompt and input
-        print!("\r");
-        vga::set_color(Color::LightGreen, Color::Black);
-        print!("sovelma");
-        vga::set_color(Color::White, Color::Black);
-        print!("> {}", self.input_buffer);
-
-        // Clear any remaining characters from previous line
-        print!("  \r");
-
-        // Reprint and position cursor
-        vga::set_color(Color::LightGreen, Color::Black);
-        print!("sovelma");
-        vga::set_color(Color::White, Color::Black);
-        print!("> {}", self.input_buffer);
+    /// Count `width` columns just printed. Ending at the right edge, start
+    /// the next row: terminals differ in where they leave the cursor there.
+    fn advance(&mut self, width: usize) {
+        self.position += width;
+        if width > 0 && self.column() == 0 {
+            print!("\r\n");
+        }
+    }
+
+    /// Column the cursor is in.
+    fn column(&self) -> usize {
+        self.position % COLUMNS
     }
 
-    /// Parse the current input buffer into a command.
-    fn parse_command(&self) -> Option<Command> {
-        let input = self.input_buffer.trim();
-        if input.is_empty() {
-            return None;
+    /// Move the cursor to `position` columns from the start of the prompt.
+    fn move_to(&mut self, position: usize) {
+        let (row, column) = (self.position / COLUMNS, self.column());
+        let (to_row, to_column) = (position / COLUMNS, position % COLUMNS);
+        if to_row < row {
+            print!("\x1b[{}A", row - to_row);
+        } else if to_row > row {
+            print!("\x1b[{}B", to_row - row);
+        }
+        if to_column < column {
+            print!("\x1b[{}D", column - to_column);
+        } else if to_column > column {
+            print!("\x1b[{}C", to_column - column);
         }
+        self.position = position;
+    }
 
-        let mut parts = input.split_whitespace();
-        let cmd = parts.next()?;
-        let args: Vec<&str> = parts.collect();
+    /// Reprint the input from `index` on, erase what was shown past its
+    /// end and put the cursor back.
+    fn redraw_from(&mut self, index: usize) {
+        self.move_to(self.prompt_width + index);
+        let rest = &self.input_buffer[index..];
+        let width = rest.len();
+        print!("{}", rest);
+        self.advance(width);
+        if self.input_buffer.len() < self.drawn {
+            print!("\x1b[J");
+        }
+        self.drawn = self.input_buffer.len();
+        self.move_to(self.prompt_width + self.cursor);
+    }
+
+    /// Show a command's output, paging it if it does not fit on screen.
+    pub fn page(&mut self, output: Output) {
+        self.pager = Pager::start(output);
+    }
 
-        Command::parse(cmd, &args)
+    /// Print log records from `next` on as they arrive (see `poll_follow`),
+    /// until a key is pressed.
+    pub fn follow_log(&mut self, next: u64) {
+        self.follow = Some(next);
+    }
+
+    /// Print the log records that arrived since the last call, if following
+    /// the log. Records wait while earlier output is being paged.
+    pub fn poll_follow(&mut self) {
+        let Some(next) = self.follow else {
+            return;
+        };
+        if self.pager.is_some() {
+            return;
+        }
+        for (seq, record) in klog::history(next) {
+            println!("{}", record);
+            self.follow = Some(seq + 1);
+        }
+    }
+
+    /// Check whether output is being paged.
+    pub fn is_paging(&self) -> bool {
+        self.pager.is_some()
+    }
+
+    /// Check whether the log is being followed (see `follow_log`).
+    pub fn is_following(&self) -> bool {
+        self.follow.is_some()
     }
 
     /// Get the current input buffer.
@@ -265,6 +456,13 @@ impl Terminal {
     }
 }
 
+/// Where the word ending `text` starts, skipping trailing spaces first,
+/// as Ctrl-W deletes it.
+fn word_start(text: &str) -> usize {
+    let trimmed = text.trim_end_matches(' ');
+    trimmed.rfind(' ').map_or(0, |space| space + 1)
+}
+
 impl Default for Terminal {
     fn default() -> Self {
         Self::new()
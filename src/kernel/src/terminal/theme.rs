@@ -0,0 +1,458 @@
+//! Color themes and the shell prompt.
+//!
+//! Output is colored by role (`Role::Error`, `Role::Accent`, ...) rather
+//! than with fixed VGA colors; the active `Theme` maps each role to a
+//! color. The `theme` command changes it at runtime and saves it to
+//! `/etc/shellrc`, which is read again at boot.
+//!
+//! The prompt is a format string: `%h` expands to the hostname, `%w` to
+//! the working directory and `%%` to a literal `%`. A space is printed
+//! after it.
+
+use crate::arch::x86_64::vga::{self, Color};
+use crate::boot::cmdline;
+use crate::fs::{FileSystem, ROOT_FS};
+use crate::print;
+use alloc::borrow::Cow;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+/// Where the theme is saved.
+pub const SHELLRC_PATH: &str = "/etc/shellrc";
+
+/// Hostname used when the command line sets none (`hostname=`).
+pub const DEFAULT_HOSTNAME: &str = "sovelma";
+
+/// Prompt format of the default theme.
+pub const DEFAULT_PROMPT: &str = "%h>";
+
+/// Working directory shown by `%w`; the shell has no `cd` yet.
+pub const ROOT_DIR: &str = "/";
+
+/// What a piece of output is, which decides its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Ordinary text.
+    Text,
+    /// Headings and hints.
+    Accent,
+    /// Informational status.
+    Info,
+    /// Success status.
+    Success,
+    /// Warnings.
+    Warning,
+    /// Errors.
+    Error,
+    /// Fields of the prompt (hostname, directory).
+    Prompt,
+    /// Status bars such as the pager's `--More--`; text colors inverted.
+    Status,
+}
+
+/// Colors of a theme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// Text color.
+    pub foreground: Color,
+    /// Background color.
+    pub background: Color,
+    /// Headings and hints.
+    pub accent: Color,
+    /// Informational status.
+    pub info: Color,
+    /// Success status and the prompt.
+    pub success: Color,
+    /// Warnings.
+    pub warning: Color,
+    /// Errors.
+    pub error: Color,
+}
+
+impl Palette {
+    /// The original SovelmaOS colors.
+    pub const DEFAULT: Palette = Palette {
+        foreground: Color::White,
+        background: Color::Black,
+        accent: Color::Cyan,
+        info: Color::LightCyan,
+        success: Color::LightGreen,
+        warning: Color::Yellow,
+        error: Color::LightRed,
+    };
+
+    /// Softer colors on black.
+    pub const DARK: Palette = Palette {
+        foreground: Color::LightGray,
+        background: Color::Black,
+        accent: Color::LightBlue,
+        info: Color::Cyan,
+        success: Color::Green,
+        warning: Color::Brown,
+        error: Color::Red,
+    };
+
+    /// Dark text on a light background.
+    pub const LIGHT: Palette = Palette {
+        foreground: Color::Black,
+        background: Color::LightGray,
+        accent: Color::Blue,
+        info: Color::Cyan,
+        success: Color::Green,
+        warning: Color::Brown,
+        error: Color::Red,
+    };
+
+    /// Foreground and background colors for `role`.
+    pub fn colors(&self, role: Role) -> (Color, Color) {
+        let foreground = match role {
+            Role::Text => self.foreground,
+            Role::Accent => self.accent,
+            Role::Info => self.info,
+            Role::Success | Role::Prompt => self.success,
+            Role::Warning => self.warning,
+            Role::Error => self.error,
+            Role::Status => return (self.background, self.foreground),
+        };
+        (foreground, self.background)
+    }
+
+    /// The color in slot `name` (`foreground`, `accent`, ...).
+    pub fn slot(&self, name: &str) -> Option<Color> {
+        let mut palette = *self;
+        palette.slot_mut(name).map(|color| *color)
+    }
+
+    fn slot_mut(&mut self, name: &str) -> Option<&mut Color> {
+        match name {
+            "foreground" | "fg" => Some(&mut self.foreground),
+            "background" | "bg" => Some(&mut self.background),
+            "accent" => Some(&mut self.accent),
+            "info" => Some(&mut self.info),
+            "success" => Some(&mut self.success),
+            "warning" => Some(&mut self.warning),
+            "error" => Some(&mut self.error),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Built-in themes, by name.
+pub const PRESETS: [(&str, Palette); 3] = [
+    ("default", Palette::DEFAULT),
+    ("dark", Palette::DARK),
+    ("light", Palette::LIGHT),
+];
+
+/// Palette slots, in the order they are listed and saved.
+pub const SLOTS: [&str; 7] = [
+    "foreground",
+    "background",
+    "accent",
+    "info",
+    "success",
+    "warning",
+    "error",
+];
+
+/// VGA color names accepted by `theme color`.
+const COLOR_NAMES: [(&str, Color); 16] = [
+    ("black", Color::Black),
+    ("blue", Color::Blue),
+    ("green", Color::Green),
+    ("cyan", Color::Cyan),
+    ("red", Color::Red),
+    ("magenta", Color::Magenta),
+    ("brown", Color::Brown),
+    ("lightgray", Color::LightGray),
+    ("darkgray", Color::DarkGray),
+    ("lightblue", Color::LightBlue),
+    ("lightgreen", Color::LightGreen),
+    ("lightcyan", Color::LightCyan),
+    ("lightred", Color::LightRed),
+    ("pink", Color::Pink),
+    ("yellow", Color::Yellow),
+    ("white", Color::White),
+];
+
+/// Look up a built-in theme.
+pub fn preset(name: &str) -> Option<(&'static str, Palette)> {
+    PRESETS
+        .iter()
+        .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+        .copied()
+}
+
+/// Parse a color name (`lightblue`, `light-blue`, `LightBlue`).
+pub fn parse_color(name: &str) -> Option<Color> {
+    let name: String = name
+        .chars()
+        .filter(|c| *c != '-' && *c != '_')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    COLOR_NAMES
+        .iter()
+        .find(|(color, _)| *color == name)
+        .map(|(_, color)| *color)
+}
+
+/// Name of a color, as accepted by `parse_color`.
+pub fn color_name(color: Color) -> &'static str {
+    COLOR_NAMES
+        .iter()
+        .find(|(_, c)| *c == color)
+        .map_or("?", |(name, _)| name)
+}
+
+/// Errors from changing a theme.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThemeError {
+    /// No built-in theme has this name.
+    UnknownTheme(String),
+    /// No palette slot has this name.
+    UnknownSlot(String),
+    /// Not a color name.
+    UnknownColor(String),
+    /// Missing or unexpected arguments.
+    Usage,
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThemeError::UnknownTheme(name) => write!(f, "unknown theme '{}'", name),
+            ThemeError::UnknownSlot(name) => write!(f, "unknown color slot '{}'", name),
+            ThemeError::UnknownColor(name) => write!(f, "unknown color '{}'", name),
+            ThemeError::Usage => write!(
+                f,
+                "usage: theme set <name> | theme color <slot> <color> | theme prompt <format>"
+            ),
+        }
+    }
+}
+
+/// A palette plus prompt format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    /// Built-in theme the palette started from.
+    pub base: &'static str,
+    /// Colors.
+    pub palette: Palette,
+    /// Prompt format string.
+    pub prompt: Cow<'static, str>,
+}
+
+impl Theme {
+    /// The default theme.
+    pub const fn new() -> Self {
+        Self {
+            base: "default",
+            palette: Palette::DEFAULT,
+            prompt: Cow::Borrowed(DEFAULT_PROMPT),
+        }
+    }
+
+    /// Apply a `theme` subcommand: `set <name>`, `color <slot> <color>` or
+    /// `prompt <format...>`.
+    pub fn apply(&mut self, args: &[&str]) -> Result<(), ThemeError> {
+        match args {
+            ["set", name] => {
+                let (base, palette) =
+                    preset(name).ok_or_else(|| ThemeError::UnknownTheme(name.to_string()))?;
+                self.base = base;
+                self.palette = palette;
+            }
+            ["color", slot, color] => {
+                let color = parse_color(color)
+                    .ok_or_else(|| ThemeError::UnknownColor(color.to_string()))?;
+                let slot = self
+                    .palette
+                    .slot_mut(slot)
+                    .ok_or_else(|| ThemeError::UnknownSlot(slot.to_string()))?;
+                *slot = color;
+            }
+            ["prompt", format @ ..] if !format.is_empty() => {
+                self.prompt = Cow::Owned(format.join(" "));
+            }
+            _ => return Err(ThemeError::Usage),
+        }
+        Ok(())
+    }
+
+    /// Whether the palette differs from its base theme.
+    pub fn is_modified(&self) -> bool {
+        !matches!(preset(self.base), Some((_, palette)) if palette == self.palette)
+    }
+
+    /// Serialize as `theme` commands for `/etc/shellrc`.
+    pub fn to_shellrc(&self) -> String {
+        let mut text = String::from("# Shell settings, written by the `theme` command\n");
+        text.push_str(&alloc::format!("theme set {}\n", self.base));
+        let base = preset(self.base).map_or(Palette::DEFAULT, |(_, palette)| palette);
+        for slot in SLOTS {
+            let color = self.palette.slot(slot);
+            if color != base.slot(slot) {
+                if let Some(color) = color {
+                    text.push_str(&alloc::format!(
+                        "theme color {} {}\n",
+                        slot,
+                        color_name(color)
+                    ));
+                }
+            }
+        }
+        text.push_str(&alloc::format!("theme prompt {}\n", self.prompt));
+        text
+    }
+
+    /// Parse `/etc/shellrc` text, starting from the default theme.
+    ///
+    /// Lines other than `theme` commands, and invalid ones, are skipped.
+    pub fn from_shellrc(text: &str) -> Self {
+        let mut theme = Self::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let args: Vec<&str> = line.split_whitespace().collect();
+            if let ["theme", rest @ ..] = args.as_slice() {
+                let _ = theme.apply(rest);
+            }
+        }
+        theme
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The active theme.
+static THEME: Mutex<Theme> = Mutex::new(Theme::new());
+
+/// Colors the active theme uses for `role`.
+pub fn colors(role: Role) -> (Color, Color) {
+    THEME.lock().palette.colors(role)
+}
+
+/// Color subsequent output as `role`.
+pub fn set(role: Role) {
+    let (foreground, background) = colors(role);
+    vga::set_color(foreground, background);
+}
+
+/// Return to the text colors.
+pub fn reset() {
+    set(Role::Text);
+}
+
+/// A copy of the active theme.
+pub fn current() -> Theme {
+    THEME.lock().clone()
+}
+
+/// Apply a `theme` subcommand to the active theme and save it.
+pub fn update(args: &[&str]) -> Result<(), ThemeError> {
+    let mut theme = current();
+    theme.apply(args)?;
+    ROOT_FS.add_file(SHELLRC_PATH, theme.to_shellrc().as_bytes());
+    *THEME.lock() = theme;
+    Ok(())
+}
+
+/// Load the theme saved in `/etc/shellrc`. Returns `false` if there is none.
+pub fn load() -> bool {
+    let Some(text) = read_shellrc() else {
+        return false;
+    };
+    *THEME.lock() = Theme::from_shellrc(&text);
+    true
+}
+
+/// Read `/etc/shellrc` as (lossy) UTF-8 text.
+fn read_shellrc() -> Option<String> {
+    let handle = ROOT_FS.open(SHELLRC_PATH).ok()?;
+    let size = ROOT_FS.size(handle).unwrap_or(0);
+    let mut buffer = vec![0u8; size];
+    let result = ROOT_FS.read(handle, &mut buffer, 0);
+    ROOT_FS.close(handle);
+    let len = result.ok()?;
+    Some(String::from_utf8_lossy(&buffer[..len]).to_string())
+}
+
+/// The system hostname, from the `hostname=` command-line option.
+pub fn hostname() -> &'static str {
+    cmdline::get("hostname")
+        .filter(|name| !name.is_empty())
+        .unwrap_or(DEFAULT_HOSTNAME)
+}
+
+/// Split a prompt format into `(is_field, text)` pieces.
+fn segments(format: &str, hostname: &str, cwd: &str) -> Vec<(bool, String)> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+        let field = match chars.next() {
+            Some('h') => hostname,
+            Some('w') => cwd,
+            Some('%') => {
+                literal.push('%');
+                continue;
+            }
+            Some(other) => {
+                literal.push('%');
+                literal.push(other);
+                continue;
+            }
+            None => {
+                literal.push('%');
+                continue;
+            }
+        };
+        if !literal.is_empty() {
+            segments.push((false, core::mem::take(&mut literal)));
+        }
+        segments.push((true, String::from(field)));
+    }
+    if !literal.is_empty() {
+        segments.push((false, literal));
+    }
+    segments
+}
+
+/// Expand a prompt format to plain text.
+pub fn expand_prompt(format: &str, hostname: &str, cwd: &str) -> String {
+    segments(format, hostname, cwd)
+        .into_iter()
+        .map(|(_, text)| text)
+        .collect()
+}
+
+/// Print the prompt for working directory `cwd`, returning how many
+/// columns it takes.
+pub fn print_prompt(cwd: &str) -> usize {
+    let format = THEME.lock().prompt.clone();
+    let mut width = 1;
+    for (field, text) in segments(&format, hostname(), cwd) {
+        set(if field { Role::Prompt } else { Role::Text });
+        print!("{}", text);
+        width += text.chars().count();
+    }
+    reset();
+    print!(" ");
+    width
+}
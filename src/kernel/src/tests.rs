@@ -5,6 +5,7 @@
 use crate::capability::{CapabilityTable, CapabilityType};
 use crate::serial_println;
 use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::vec::Vec;
 
 /// Runs all kernel tests.
@@ -16,7 +17,138 @@ pub fn run_all() {
     test_allocation();
     test_capabilities();
     test_task_id();
+    #[cfg(feature = "wasm")]
     test_capability_generation_revocation();
+    #[cfg(feature = "net")]
+    test_dns_cache();
+    #[cfg(feature = "net")]
+    test_hosts_file();
+    #[cfg(feature = "net")]
+    test_tftp_packets();
+    #[cfg(feature = "net")]
+    test_slip_framing();
+    test_cmdline();
+    test_ksym();
+    #[cfg(feature = "net")]
+    test_syslog_format();
+    test_json_output();
+    #[cfg(feature = "wasm")]
+    test_process_timers();
+    #[cfg(feature = "wasm")]
+    test_event_queue();
+    #[cfg(feature = "wasm")]
+    test_signals();
+    #[cfg(feature = "wasm")]
+    test_snapshot_format();
+    #[cfg(feature = "wasm")]
+    test_wasm_libraries();
+    #[cfg(feature = "wasm")]
+    test_runtime_slices();
+    #[cfg(feature = "wasm")]
+    test_manifest();
+    #[cfg(feature = "wasm")]
+    test_fuel_calibration();
+    test_idle_stats();
+    test_timer_wheel();
+    #[cfg(feature = "net")]
+    test_services();
+    #[cfg(feature = "terminal")]
+    test_shell_registry();
+    #[cfg(feature = "terminal")]
+    test_pager();
+    test_theme();
+    test_early_panic_writer();
+    #[cfg(feature = "net")]
+    test_net_pools();
+    test_lockdep();
+    test_irq_log();
+    test_vga_deferred();
+    test_serial_config();
+    test_ps2();
+    test_cpuid();
+    test_fpu();
+    test_usermode();
+    test_process_memory();
+    test_mmio();
+    test_memmap();
+    test_replay();
+    test_bench();
+    test_fs_write();
+    #[cfg(feature = "wasm")]
+    test_pipes();
+    test_config();
+    test_cp437();
+    #[cfg(feature = "terminal")]
+    test_ctl();
+    #[cfg(feature = "net")]
+    test_poll_schedule();
+    #[cfg(feature = "net")]
+    test_arp_conflict();
+    #[cfg(feature = "net")]
+    test_link_state();
+    #[cfg(feature = "net")]
+    test_checksum_offload();
+    #[cfg(feature = "net")]
+    test_nic_filters();
+    #[cfg(all(feature = "wasm", feature = "terminal"))]
+    test_net_rights();
+    #[cfg(feature = "wasm")]
+    test_batch_records();
+    test_trace_spans();
+    test_priority_donation();
+    #[cfg(feature = "net")]
+    test_socket_gc();
+    #[cfg(all(feature = "net", feature = "wasm"))]
+    test_connection_events();
+    #[cfg(feature = "wasm")]
+    test_capability_handles();
+    #[cfg(feature = "wasm")]
+    test_process_teardown();
+    test_sync_registry();
+    test_futex();
+    #[cfg(feature = "terminal")]
+    test_log_timestamps();
+    test_serial_capture();
+    test_driver_api();
+    test_error_context();
+    test_boot_health();
+    test_command_line();
+    test_ramfs_stress();
+    test_capture();
+    #[cfg(feature = "terminal")]
+    test_shell_shortcuts();
+    #[cfg(feature = "terminal")]
+    test_shell_continuation();
+    test_command_args();
+    #[cfg(feature = "terminal")]
+    test_shell_line_wrap();
+    test_buildinfo();
+    test_fs_namespaces();
+    test_tmpfs();
+    test_fs_locks();
+    test_capability_descriptors();
+    #[cfg(feature = "wasm")]
+    test_wasm_grant();
+    #[cfg(feature = "wasm")]
+    test_rate_limits();
+    #[cfg(feature = "wasm")]
+    test_dbg_break();
+    test_fs_readdir();
+    #[cfg(feature = "wasm")]
+    test_dbg_step();
+    test_fs_unlink_rename();
+    test_crash_loop();
+    #[cfg(feature = "wasm")]
+    test_opendir_restricted();
+    #[cfg(feature = "net")]
+    test_traceroute_replies();
+    #[cfg(feature = "net")]
+    test_httpd_paths();
+    test_fs_watch_notify();
+    #[cfg(feature = "wasm")]
+    test_snapshot_derive();
+    #[cfg(feature = "heap-poison")]
+    test_heap_poison();
 
     serial_println!("[test] All kernel tests passed!");
 }
@@ -61,6 +193,7 @@ fn test_task_id() {
 /// This tests the core security mechanism: when a capability is revoked,
 /// any subsequent access attempts using the old CapId should fail due to
 /// generation mismatch.
+#[cfg(feature = "wasm")]
 fn test_capability_generation_revocation() {
     use crate::wasm::HostState;
     use sovelma_common::capability::{CapId, Capability, CapabilityRights};
@@ -120,3 +253,3818 @@ fn test_capability_generation_revocation() {
 
     serial_println!("[test] test_capability_generation_revocation... ok");
 }
+
+/// Test DNS cache expiry, negative entries and case-insensitive keys.
+#[cfg(feature = "net")]
+fn test_dns_cache() {
+    use crate::net::{DnsCache, DnsFailure};
+    use smoltcp::time::{Duration, Instant};
+    use smoltcp::wire::{IpAddress, Ipv4Address};
+
+    serial_println!("[test] test_dns_cache... ");
+
+    let mut cache = DnsCache::new();
+    let t0 = Instant::from_millis(0);
+    let addr = IpAddress::Ipv4(Ipv4Address::new(10, 0, 2, 3));
+
+    cache.insert("Example.COM", alloc::vec![addr], t0);
+    cache.insert("missing.invalid", Vec::new(), t0);
+
+    let hit = cache.lookup("example.com", t0).expect("positive entry missing");
+    assert!(!hit.is_negative());
+    assert_eq!(hit.addresses[0], addr);
+    assert!(cache
+        .lookup("missing.invalid", t0)
+        .expect("negative entry missing")
+        .is_negative());
+
+    // Negative entries expire well before positive ones
+    let later = t0 + Duration::from_secs(60);
+    assert!(cache.lookup("missing.invalid", later).is_none());
+    assert!(cache.lookup("example.com", later).is_some());
+
+    // A cache holding only stale entries is empty once purged
+    cache.insert("stale.invalid", Vec::new(), t0);
+    assert_eq!(cache.len(), 2);
+    cache.purge(later);
+    assert_eq!(cache.len(), 1);
+    cache.purge(t0 + Duration::from_secs(24 * 60 * 60));
+    assert!(cache.is_empty());
+
+    cache.insert("example.com", alloc::vec![addr], t0);
+    cache.flush();
+    assert!(cache.is_empty());
+
+    // A lookup no server answered keeps its reason
+    cache.insert("missing.invalid", Vec::new(), t0);
+    cache.insert_failure("slow.invalid", DnsFailure::Timeout, t0);
+    let miss = cache
+        .lookup("slow.invalid", t0)
+        .expect("timeout entry missing");
+    assert!(miss.is_negative());
+    assert_eq!(miss.failure, Some(DnsFailure::Timeout));
+    assert_eq!(
+        cache.lookup("missing.invalid", t0).unwrap().failure,
+        Some(DnsFailure::NxDomain)
+    );
+    serial_println!("[test] test_dns_cache... ok");
+}
+
+/// Test hosts file parsing and the filesystem watch used to reload it.
+#[cfg(feature = "net")]
+fn test_hosts_file() {
+    use crate::fs::ramfs::RamFs;
+    use crate::net::hosts::parse;
+
+    serial_println!("[test] test_hosts_file... ");
+
+    let entries = parse("# comment\n10.0.0.1 Dev dev.local # pinned\nbogus line\n10.0.0.2 dev\n");
+    let dev = entries.get("dev").expect("dev not parsed");
+    assert_eq!(dev.len(), 2);
+    assert!(entries.contains_key("dev.local"));
+    assert!(!entries.contains_key("bogus"));
+
+    let fs = RamFs::new();
+    let mut watch = fs.watch("/etc/hosts");
+    assert!(!fs.changed(&mut watch));
+    fs.add_file("etc/hosts", b"192.168.1.7 box\n");
+    assert!(fs.changed(&mut watch));
+    assert!(!fs.changed(&mut watch));
+    fs.add_file("etc/other", b"");
+    assert!(!fs.changed(&mut watch));
+    serial_println!("[test] test_hosts_file... ok");
+}
+
+#[cfg(feature = "net")]
+fn test_tftp_packets() {
+    use crate::net::tftp::Packet;
+
+    serial_println!("[test] test_tftp_packets... ");
+
+    let rrq = Packet::ReadRequest {
+        filename: "app.wasm",
+        mode: "octet",
+    };
+    let bytes = rrq.to_bytes();
+    assert_eq!(&bytes[..2], &[0, 1]);
+    assert_eq!(Packet::parse(&bytes), Some(rrq));
+
+    let payload = [7u8; 3];
+    let data = Packet::Data {
+        block: 258,
+        data: &payload,
+    };
+    let bytes = data.to_bytes();
+    assert_eq!(&bytes[..4], &[0, 3, 1, 2]);
+    assert_eq!(Packet::parse(&bytes), Some(data));
+
+    let error = Packet::parse(b"\x00\x05\x00\x01File not found\x00");
+    assert_eq!(
+        error,
+        Some(Packet::Error {
+            code: 1,
+            message: "File not found"
+        })
+    );
+    assert_eq!(Packet::parse(&[0, 4, 0]), None);
+    assert_eq!(Packet::parse(&[0, 9, 0, 0]), None);
+    serial_println!("[test] test_tftp_packets... ok");
+}
+
+#[cfg(feature = "net")]
+fn test_slip_framing() {
+    use crate::net::slip::{encode, SlipDecoder};
+
+    serial_println!("[test] test_slip_framing... ");
+
+    let packet = [0x45, 0xC0, 0x01, 0xDB, 0x02];
+    let mut frame = Vec::new();
+    encode(&packet, &mut frame);
+    assert_eq!(
+        frame,
+        [0xC0, 0x45, 0xDB, 0xDC, 0x01, 0xDB, 0xDD, 0x02, 0xC0]
+    );
+
+    let mut decoder = SlipDecoder::new();
+    let decoded: Vec<Vec<u8>> = frame.iter().filter_map(|b| decoder.push(*b)).collect();
+    assert_eq!(decoded.len(), 1);
+    assert_eq!(decoded[0], packet);
+
+    // Oversized frames are dropped without disturbing the next one
+    for _ in 0..crate::net::slip::MTU + 1 {
+        assert!(decoder.push(0x11).is_none());
+    }
+    assert!(decoder.push(0xC0).is_none());
+    assert_eq!(decoder.push(0x22), None);
+    assert_eq!(decoder.push(0xC0), Some(alloc::vec![0x22]));
+    serial_println!("[test] test_slip_framing... ok");
+}
+
+fn test_cmdline() {
+    use crate::boot::cmdline::lookup;
+
+    serial_println!("[test] test_cmdline... ");
+
+    let line = "net=slip ip=10.0.3.2/24 quiet net=e1000";
+    assert_eq!(lookup(line, "net"), Some("e1000"));
+    assert_eq!(lookup(line, "ip"), Some("10.0.3.2/24"));
+    assert_eq!(lookup(line, "quiet"), Some(""));
+    assert_eq!(lookup(line, "gw"), None);
+    assert_eq!(lookup("", "net"), None);
+    serial_println!("[test] test_cmdline... ok");
+}
+
+fn test_ksym() {
+    use crate::ksym::{Symbol, SymbolTable};
+
+    serial_println!("[test] test_ksym... ");
+
+    let listing = "\
+0000000000401000 T _start
+0000000000400000 r .rodata_start
+0000000000401200 t sovelma_kernel::init::h0123456789abcdef
+0000000000401100 T kernel_main
+";
+    let table = SymbolTable::parse(listing);
+    assert_eq!(table.len(), 3);
+    assert_eq!(table.lookup(0x400fff), None);
+    assert_eq!(
+        table.lookup(0x401000),
+        Some(Symbol {
+            name: "_start",
+            offset: 0
+        })
+    );
+    assert_eq!(
+        table.lookup(0x401234),
+        Some(Symbol {
+            name: "sovelma_kernel::init",
+            offset: 0x34
+        })
+    );
+    assert_eq!(table.address_of("kernel_main"), Some(0x401100));
+    serial_println!("[test] test_ksym... ok");
+}
+
+#[cfg(feature = "net")]
+fn test_syslog_format() {
+    use crate::klog::LogRecord;
+    use crate::net::syslog::format_message;
+
+    serial_println!("[test] test_syslog_format... ");
+
+    let record = LogRecord {
+        level: log::Level::Warn,
+        target: "dhcp".into(),
+        message: "Deconfigured".into(),
+        mono_ns: 1_500_000_000,
+        wall_ns: None,
+    };
+    assert_eq!(
+        format_message(&record),
+        "<4>1 - sovelma kernel - dhcp - Deconfigured"
+    );
+    let stamped = LogRecord {
+        wall_ns: Some(1_700_000_000_123_456_789),
+        ..record.clone()
+    };
+    assert_eq!(
+        format_message(&stamped),
+        "<4>1 2023-11-14T22:13:20.123456Z sovelma kernel - dhcp - Deconfigured"
+    );
+
+    let record = LogRecord {
+        level: log::Level::Debug,
+        ..record
+    };
+    assert!(format_message(&record).starts_with("<7>1 "));
+    serial_println!("[test] test_syslog_format... ok");
+}
+
+fn test_json_output() {
+    use crate::terminal::json::Json;
+
+    serial_println!("[test] test_json_output... ");
+
+    let value = Json::object()
+        .with("name", "say \"hi\"\n")
+        .with("port", 8080u16)
+        .with("gateway", None::<&str>)
+        .with("dns", alloc::vec!["10.0.2.3", "1.1.1.1"])
+        .with("up", true);
+    assert_eq!(
+        alloc::format!("{}", value),
+        r#"{"name":"say \"hi\"\n","port":8080,"gateway":null,"dns":["10.0.2.3","1.1.1.1"],"up":true}"#
+    );
+    assert_eq!(alloc::format!("{}", Json::Array(Vec::new())), "[]");
+    serial_println!("[test] test_json_output... ok");
+}
+
+#[cfg(feature = "wasm")]
+fn test_process_timers() {
+    use crate::wasm::event::{Event, EventQueue};
+    use crate::wasm::timer::ProcessTimers;
+
+    serial_println!("[test] test_process_timers... ");
+
+    let mut timers = ProcessTimers::new();
+    let mut events = EventQueue::new();
+    let periodic = timers.create().expect("create periodic timer");
+    let oneshot = timers.create().expect("create one-shot timer");
+    assert!(timers.arm(periodic, 0, 10, 10));
+    assert!(timers.arm(oneshot, 0, 5, 0));
+    assert!(!timers.arm(999, 0, 5, 0));
+
+    timers.fire(4, &mut events);
+    assert!(events.is_empty());
+
+    // The one-shot fires once; the periodic timer missed three periods.
+    timers.fire(35, &mut events);
+    assert_eq!(
+        events.pop(),
+        Some(Event::Timer {
+            timer: periodic,
+            expirations: 3
+        })
+    );
+    assert_eq!(
+        events.pop(),
+        Some(Event::Timer {
+            timer: oneshot,
+            expirations: 1
+        })
+    );
+    assert_eq!(timers.next_deadline(), Some(40));
+
+    // Unconsumed expirations coalesce into one event.
+    timers.fire(40, &mut events);
+    timers.fire(50, &mut events);
+    assert_eq!(events.len(), 1);
+    assert_eq!(
+        events.pop(),
+        Some(Event::Timer {
+            timer: periodic,
+            expirations: 2
+        })
+    );
+
+    assert!(timers.cancel(periodic));
+    assert!(!timers.cancel(periodic));
+    timers.fire(100, &mut events);
+    assert!(events.is_empty());
+    serial_println!("[test] test_process_timers... ok");
+}
+
+#[cfg(feature = "wasm")]
+fn test_event_queue() {
+    use crate::wasm::event::{kind, Event, EventQueue, EVENT_QUEUE_CAPACITY};
+
+    serial_println!("[test] test_event_queue... ");
+
+    let exit = Event::ChildExit { pid: 7, status: -1 };
+    let record = exit.encode();
+    assert_eq!(record[0..4], kind::CHILD_EXIT.to_le_bytes());
+    assert_eq!(record[8..16], 7u64.to_le_bytes());
+    assert_eq!(record[16..24], (-1i64).to_le_bytes());
+
+    // Only timer events coalesce.
+    let mut queue = EventQueue::new();
+    let message = Event::Message { sender: 3, len: 12 };
+    assert!(queue.push(message));
+    assert!(queue.push(message));
+    assert_eq!(queue.len(), 2);
+
+    for i in queue.len()..EVENT_QUEUE_CAPACITY {
+        assert!(queue.push(Event::Message {
+            sender: i as u64,
+            len: 0
+        }));
+    }
+    assert!(!queue.push(exit));
+    assert_eq!(queue.dropped(), 1);
+    assert_eq!(queue.pop(), Some(message));
+    serial_println!("[test] test_event_queue... ok");
+}
+
+#[cfg(feature = "wasm")]
+fn test_signals() {
+    use crate::wasm::event::{kind, Event};
+    use crate::wasm::process::{self, Signal, SignalError};
+
+    serial_println!("[test] test_signals... ");
+
+    assert_eq!("TERM".parse::<Signal>(), Ok(Signal::Term));
+    assert_eq!("sigusr1".parse::<Signal>(), Ok(Signal::Usr1));
+    assert_eq!("9".parse::<Signal>(), Ok(Signal::Kill));
+    assert_eq!("INT".parse::<Signal>(), Err(SignalError::UnknownSignal));
+    assert_eq!(Signal::from_number(1), Some(Signal::Hup));
+    assert_eq!(alloc::format!("{}", Signal::Term), "SIGTERM");
+
+    let record = Event::Signal {
+        signal: Signal::Hup.number(),
+    }
+    .encode();
+    assert_eq!(record[0..4], kind::SIGNAL.to_le_bytes());
+    assert_eq!(record[8..16], 1u64.to_le_bytes());
+
+    assert_eq!(
+        process::signal(u64::MAX, Signal::Term),
+        Err(SignalError::NoSuchProcess)
+    );
+    serial_println!("[test] test_signals... ok");
+}
+
+#[cfg(feature = "wasm")]
+fn test_snapshot_format() {
+    use crate::wasm::handles::Handle;
+    use crate::wasm::snapshot::{
+        GlobalValue, SavedCapability, SavedObject, Snapshot, SnapshotError,
+    };
+    use sovelma_common::capability::{CapabilityRights, Label};
+    use sovelma_common::rate::RateLimit;
+
+    serial_println!("[test] test_snapshot_format... ");
+
+    let saved = |handle: u32, object: SavedObject, rights: CapabilityRights| SavedCapability {
+        handle: Handle::new(handle).expect("handle is valid"),
+        object,
+        rights,
+        label: None,
+        rate_limit: None,
+    };
+    let snapshot = Snapshot {
+        module: "apps/counter.wasm".into(),
+        entry: "_start".into(),
+        memory: alloc::vec![0xAB; 300],
+        globals: alloc::vec![
+            ("counter".into(), GlobalValue::I64(-42)),
+            ("ratio".into(), GlobalValue::F32(0x3fc0_0000)),
+        ],
+        capabilities: alloc::vec![
+            SavedCapability {
+                label: Some(Label::new("clock").expect("clock is a valid label")),
+                ..saved(1, SavedObject::Timer, CapabilityRights::READ)
+            },
+            SavedCapability {
+                rate_limit: Some(RateLimit::new(100, 20).expect("limit is nonzero")),
+                ..saved(
+                    3,
+                    SavedObject::Network {
+                        first_port: 7,
+                        last_port: 7,
+                    },
+                    CapabilityRights::LISTEN,
+                )
+            },
+            saved(
+                4,
+                SavedObject::Directory("/srv/data".into()),
+                CapabilityRights::READ | CapabilityRights::WRITE,
+            ),
+        ],
+    };
+    let encoded = snapshot.encode();
+    assert_eq!(Snapshot::decode(&encoded).ok(), Some(snapshot));
+
+    assert!(matches!(
+        Snapshot::decode(b"NOTASNAPSHOT"),
+        Err(SnapshotError::BadMagic)
+    ));
+    assert!(matches!(
+        Snapshot::decode(&encoded[..encoded.len() - 1]),
+        Err(SnapshotError::Truncated)
+    ));
+
+    // Kinds that are never saved, such as Memory (tag 0), do not decode
+    let timer_only = Snapshot {
+        module: "a.wasm".into(),
+        entry: "_start".into(),
+        memory: Vec::new(),
+        globals: Vec::new(),
+        capabilities: alloc::vec![saved(1, SavedObject::Timer, CapabilityRights::READ)],
+    };
+    let mut forged = timer_only.encode();
+    // The tag is followed by two words, the path, the label and the rate limit
+    let tag = forged.len() - (4 + 8 + 8 + 4 + 4 + 4 + 4);
+    forged[tag..tag + 4].copy_from_slice(&0u32.to_le_bytes());
+    assert!(matches!(
+        Snapshot::decode(&forged),
+        Err(SnapshotError::UnknownType(0))
+    ));
+    serial_println!("[test] test_snapshot_format... ok");
+}
+
+#[cfg(feature = "wasm")]
+fn test_wasm_libraries() {
+    use crate::wasm::library::LibraryError;
+    use crate::wasm::WasmEngine;
+
+    serial_println!("[test] test_wasm_libraries... ");
+
+    const HEADER: [u8; 8] = *b"\0asm\x01\0\0\0";
+    // One type, `() -> ()`
+    const TYPES: [u8; 6] = [0x01, 0x04, 0x01, 0x60, 0x00, 0x00];
+
+    // Library exporting an empty function `f`
+    let mut lib = HEADER.to_vec();
+    lib.extend_from_slice(&TYPES);
+    lib.extend_from_slice(&[0x03, 0x02, 0x01, 0x00]);
+    lib.extend_from_slice(&[0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00]);
+    lib.extend_from_slice(&[0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b]);
+
+    // Module importing `dep.f`
+    let mut app = HEADER.to_vec();
+    app.extend_from_slice(&TYPES);
+    app.extend_from_slice(&[
+        0x02, 0x09, 0x01, 0x03, b'd', b'e', b'p', 0x01, b'f', 0x00, 0x00,
+    ]);
+
+    let engine = WasmEngine::new();
+    assert!(matches!(
+        engine.load_library("env", &lib),
+        Err(LibraryError::InvalidName)
+    ));
+    assert!(matches!(
+        engine.load_library("app", &app),
+        Err(LibraryError::MissingDependency(name)) if name == "dep"
+    ));
+    assert!(engine.spawn_process_with_caps(&app, Vec::new()).is_err());
+
+    assert!(engine.load_library("dep", &lib).is_ok());
+    assert!(matches!(
+        engine.load_library("dep", &lib),
+        Err(LibraryError::AlreadyLoaded)
+    ));
+    assert!(engine.spawn_process_with_caps(&app, Vec::new()).is_ok());
+
+    assert!(engine.load_library("app", &app).is_ok());
+    let libraries = engine.libraries();
+    assert_eq!(libraries.len(), 2);
+    assert_eq!(libraries[0].dependencies, ["dep"]);
+    assert_eq!(libraries[1].exports, 1);
+
+    assert!(matches!(
+        engine.unload_library("dep"),
+        Err(LibraryError::InUse(name)) if name == "app"
+    ));
+    assert!(engine.unload_library("app").is_ok());
+    assert!(engine.unload_library("dep").is_ok());
+    assert!(matches!(
+        engine.unload_library("dep"),
+        Err(LibraryError::NotLoaded)
+    ));
+    serial_println!("[test] test_wasm_libraries... ok");
+}
+
+#[cfg(feature = "wasm")]
+fn test_runtime_slices() {
+    use crate::wasm::runtime::{poll_slice, Process, Slice};
+    use core::task::{Context, Poll};
+
+    serial_println!("[test] test_runtime_slices... ");
+
+    /// Suspends `remaining` times, skipping slices while `blocked`.
+    struct Countdown {
+        remaining: u32,
+        blocked: bool,
+        slices: u32,
+    }
+
+    impl Process for Countdown {
+        type Suspended = ();
+        type Error = ();
+
+        fn begin_slice(&mut self, suspended: Option<&()>) -> bool {
+            !(suspended.is_some() && self.blocked)
+        }
+
+        fn start(&mut self, entry: &str) -> Result<Slice<()>, ()> {
+            if entry != "_start" {
+                return Err(());
+            }
+            self.resume(())
+        }
+
+        fn resume(&mut self, _: ()) -> Result<Slice<()>, ()> {
+            self.slices += 1;
+            if self.remaining == 0 {
+                return Ok(Slice::Finished);
+            }
+            self.remaining -= 1;
+            Ok(Slice::Suspended(()))
+        }
+
+        fn fuel_consumed(&self) -> u64 {
+            u64::from(self.slices)
+        }
+    }
+
+    let waker = futures_util::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut process = Countdown {
+        remaining: 2,
+        blocked: false,
+        slices: 0,
+    };
+    let mut suspended = None;
+
+    assert!(poll_slice(&mut process, "_start", &mut suspended, &mut cx).is_pending());
+    process.blocked = true;
+    assert!(poll_slice(&mut process, "_start", &mut suspended, &mut cx).is_pending());
+    assert_eq!(process.slices, 1);
+    process.blocked = false;
+    assert!(poll_slice(&mut process, "_start", &mut suspended, &mut cx).is_pending());
+    assert_eq!(
+        poll_slice(&mut process, "_start", &mut suspended, &mut cx),
+        Poll::Ready(Ok(()))
+    );
+    assert_eq!(process.slices, 3);
+
+    assert_eq!(
+        poll_slice(&mut process, "main", &mut None, &mut cx),
+        Poll::Ready(Err(()))
+    );
+    serial_println!("[test] test_runtime_slices... ok");
+}
+
+#[cfg(feature = "wasm")]
+fn test_manifest() {
+    use crate::wasm::manifest::{Manifest, ManifestError, MANIFEST_SECTION};
+    use sovelma_common::capability::{Capability, CapabilityRights};
+
+    serial_println!("[test] test_manifest... ");
+
+    let text = b"# demo\nname = clock\nversion = 1.2.0\ncapabilities = timer, file\n";
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+    module.push(0);
+    module.push((1 + MANIFEST_SECTION.len() + text.len()) as u8);
+    module.push(MANIFEST_SECTION.len() as u8);
+    module.extend_from_slice(MANIFEST_SECTION.as_bytes());
+    module.extend_from_slice(text);
+
+    let manifest = Manifest::from_module(&module)
+        .ok()
+        .flatten()
+        .expect("manifest not found");
+    assert_eq!(manifest.name, "clock");
+    assert_eq!(manifest.version, "1.2.0");
+    assert_eq!(manifest.entry, "_start");
+    assert_eq!(manifest.capabilities, ["timer", "file"]);
+
+    let timer = Capability::new(CapabilityType::Timer, CapabilityRights::READ);
+    assert_eq!(manifest.missing_capabilities(&[timer]), ["file"]);
+
+    assert_eq!(Manifest::from_module(&module[..8]), Ok(None));
+    assert_eq!(
+        Manifest::from_module(&module[..module.len() - 1]),
+        Err(ManifestError::Truncated)
+    );
+    assert_eq!(Manifest::from_module(b"MZ"), Err(ManifestError::NotWasm));
+    assert_eq!(
+        Manifest::parse(b"name = x\nversion = 1\ncapabilities = gpu"),
+        Err(ManifestError::UnknownCapability("gpu".into()))
+    );
+    assert_eq!(
+        Manifest::parse(b"version = 1"),
+        Err(ManifestError::MissingField("name"))
+    );
+    serial_println!("[test] test_manifest... ok");
+}
+
+#[cfg(feature = "wasm")]
+fn test_fuel_calibration() {
+    use crate::wasm::cpu::{FuelCalibration, DEFAULT_FUEL_PER_MS};
+
+    serial_println!("[test] test_fuel_calibration... ");
+
+    let mut calibration = FuelCalibration::new();
+    assert_eq!(calibration.fuel_per_ms(), DEFAULT_FUEL_PER_MS);
+    assert_eq!(calibration.to_ms(DEFAULT_FUEL_PER_MS * 3), 3);
+
+    // No clock tick passed: no information
+    calibration.sample(50_000, 0);
+    assert_eq!(calibration.samples(), 0);
+
+    calibration.sample(80_000, 10);
+    assert_eq!(calibration.fuel_per_ms(), 8_000);
+    calibration.sample(160_000, 10);
+    assert_eq!(calibration.fuel_per_ms(), 9_000);
+    assert_eq!(calibration.samples(), 2);
+    assert_eq!(calibration.to_ms(90_000), 10);
+    serial_println!("[test] test_fuel_calibration... ok");
+}
+
+fn test_idle_stats() {
+    use crate::task::idle::IdleStats;
+
+    serial_println!("[test] test_idle_stats... ");
+
+    let stats = IdleStats {
+        total_cycles: 1_000,
+        idle_cycles: 250,
+        halts: 3,
+    };
+    assert_eq!(stats.busy_percent(), 75);
+    assert_eq!(stats.idle_percent(), 25);
+
+    // Before the idle task has run
+    let stats = IdleStats {
+        total_cycles: 0,
+        idle_cycles: 0,
+        halts: 0,
+    };
+    assert_eq!(stats.busy_percent(), 0);
+    assert_eq!(stats.idle_percent(), 100);
+    serial_println!("[test] test_idle_stats... ok");
+}
+
+fn test_timer_wheel() {
+    use crate::time::{TimerWheel, WHEEL_SLOTS};
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use futures_util::task::ArcWake;
+
+    serial_println!("[test] test_timer_wheel... ");
+
+    struct Counter(AtomicUsize);
+
+    impl ArcWake for Counter {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let counter = Arc::new(Counter(AtomicUsize::new(0)));
+    let waker = futures_util::task::waker(counter.clone());
+    let far = WHEEL_SLOTS as u64 + 44;
+
+    let mut wheel = TimerWheel::new();
+    wheel.insert(5, waker.clone());
+    wheel.insert(far, waker.clone());
+    wheel.insert(2, waker.clone());
+    assert_eq!(wheel.len(), 3);
+
+    assert_eq!(wheel.expire(1), 0);
+    assert_eq!(wheel.expire(5), 2);
+    // Same slot as 44, but a revolution later
+    assert_eq!(wheel.expire(44), 0);
+    assert_eq!(wheel.len(), 1);
+
+    // A deadline already passed fires on the next expiry
+    wheel.insert(3, waker.clone());
+    assert_eq!(wheel.expire(45), 1);
+
+    // Gaps longer than a revolution still find every entry
+    assert_eq!(wheel.expire(far * 4), 1);
+    assert!(wheel.is_empty());
+    assert_eq!(counter.0.load(Ordering::Relaxed), 4);
+    serial_println!("[test] test_timer_wheel... ok");
+}
+
+#[cfg(feature = "net")]
+fn test_services() {
+    use crate::net::dhcp::DhcpState;
+    use crate::net::{
+        telnetd, DhcpClient, NetConfig, NetworkDevice, NetworkStack, QemuE1000, Telnetd,
+    };
+    use crate::services::Services;
+    use alloc::sync::Arc;
+
+    serial_println!("[test] test_services... ");
+
+    let stack = NetworkStack::new(NetworkDevice::Loopback(QemuE1000::new()), NetConfig::dhcp());
+    let services = Services::new(
+        stack,
+        DhcpClient::new(),
+        Telnetd::new(telnetd::DEFAULT_PORT),
+    );
+
+    // Shells get handles to the same subsystems
+    let copy = services.clone();
+    assert!(Arc::ptr_eq(&services.net_stack, &copy.net_stack));
+    #[cfg(feature = "wasm")]
+    assert!(Arc::ptr_eq(&services.processes, &copy.processes));
+
+    assert!(services.net_stack.lock().ip_address().is_none());
+    assert_eq!(services.dhcp.lock().state(), DhcpState::Idle);
+    #[cfg(feature = "wasm")]
+    assert!(services.processes.lock().list().is_empty());
+    serial_println!("[test] test_services... ok");
+}
+
+#[cfg(feature = "terminal")]
+fn test_shell_registry() {
+    use crate::terminal::registry::{Builtin, Registry, RegistryError};
+    use crate::terminal::Command;
+    use alloc::sync::Arc;
+
+    serial_println!("[test] test_shell_registry... ");
+
+    const PING: Builtin = Builtin {
+        name: "ping",
+        aliases: &["p"],
+        usage: "<host>",
+        help: "Send ICMP Echo Request",
+        host_arg: Builtin::first_arg_host,
+        run: |_, _| {},
+        json: Builtin::no_json,
+    };
+    const ECHO: Builtin = Builtin {
+        name: "echo",
+        aliases: &[],
+        usage: "<text>",
+        help: "Echo text",
+        host_arg: Builtin::no_host,
+        run: |_, _| {},
+        json: Builtin::no_json,
+    };
+
+    let mut registry = Registry::new();
+    assert_eq!(registry.register(Arc::new(PING)), Ok(()));
+    assert_eq!(registry.register(Arc::new(ECHO)), Ok(()));
+    assert_eq!(registry.len(), 2);
+
+    // Names and aliases share one namespace
+    let clash = Builtin { name: "p", ..ECHO };
+    assert_eq!(
+        registry.register(Arc::new(clash)),
+        Err(RegistryError::Duplicate("p"))
+    );
+    assert_eq!(registry.len(), 2);
+
+    assert_eq!(registry.lookup("p").map(|c| c.name()), Some("ping"));
+    assert!(registry.lookup("pong").is_none());
+    let ping = registry.lookup("ping").expect("registered");
+    assert_eq!(ping.host_arg(&["example.org"]), Some(0));
+
+    // Sorted by name, for `help`
+    let names: alloc::vec::Vec<_> = registry.commands().iter().map(|c| c.name()).collect();
+    assert_eq!(names, ["echo", "ping"]);
+
+    // Unregistered commands still parse, with --json stripped
+    let command = Command::parse("NoSuchCommand", &["a", "--json", "b"]).expect("parse");
+    assert_eq!(command.name(), "nosuchcommand");
+    assert_eq!(command.args(), ["a", "b"]);
+    #[cfg(feature = "net")]
+    assert!(command.host_to_resolve().is_none());
+    assert!(Command::parse("", &[]).is_none());
+    serial_println!("[test] test_shell_registry... ok");
+}
+
+#[cfg(feature = "terminal")]
+fn test_pager() {
+    use crate::arch::x86_64::vga::BUFFER_WIDTH;
+    use crate::terminal::pager::Pager;
+    use crate::terminal::Output;
+
+    serial_println!("[test] test_pager... ");
+
+    let mut output = Output::new();
+    for i in 0..30 {
+        output.push_str(&alloc::format!("line {}\n", i));
+    }
+    assert!(output.text().starts_with("line 0\nline 1\n"));
+
+    let mut pager = Pager::new(output, 10);
+    assert_eq!(pager.remaining(), 30);
+    assert!(pager.show(10));
+    assert_eq!(pager.remaining(), 20);
+    assert!(pager.handle_key(' '));
+    assert_eq!(pager.remaining(), 10);
+    assert!(pager.handle_key('\n'));
+    assert_eq!(pager.remaining(), 9);
+    assert!(pager.handle_key('x'));
+    assert_eq!(pager.remaining(), 9);
+    assert!(!pager.handle_key('q'));
+    assert_eq!(pager.remaining(), 0);
+
+    // A line wider than the screen wraps and counts for several rows
+    let mut output = Output::new();
+    output.push_str(&"x".repeat(BUFFER_WIDTH * 2 + 1));
+    output.push_str("\nshort\n");
+    let mut pager = Pager::new(output, 3);
+    assert!(pager.show(3));
+    assert_eq!(pager.remaining(), 1);
+    assert!(!pager.handle_key(' '));
+
+    serial_println!("[test] test_pager... ok");
+}
+
+fn test_theme() {
+    use crate::arch::x86_64::vga::Color;
+    use crate::terminal::theme::{self, Palette, Role, Theme, ThemeError};
+
+    serial_println!("[test] test_theme... ");
+
+    let mut t = Theme::new();
+    assert_eq!(
+        t.palette.colors(Role::Error),
+        (Color::LightRed, Color::Black)
+    );
+    assert_eq!(t.palette.colors(Role::Status), (Color::Black, Color::White));
+
+    t.apply(&["set", "light"]).expect("preset");
+    assert_eq!(t.palette, Palette::LIGHT);
+    assert!(!t.is_modified());
+    t.apply(&["color", "accent", "light-blue"]).expect("color");
+    assert_eq!(t.palette.accent, Color::LightBlue);
+    assert!(t.is_modified());
+    t.apply(&["prompt", "%h:%w", "$"]).expect("prompt");
+    assert_eq!(
+        t.apply(&["set", "neon"]),
+        Err(ThemeError::UnknownTheme("neon".into()))
+    );
+    assert_eq!(t.apply(&["color", "accent"]), Err(ThemeError::Usage));
+
+    // Round trip through /etc/shellrc
+    let rc = t.to_shellrc();
+    assert!(rc.contains("theme set light\n"));
+    assert!(rc.contains("theme color accent lightblue\n"));
+    assert!(!rc.contains("theme color error"));
+    assert_eq!(Theme::from_shellrc(&rc), t);
+    assert_eq!(Theme::from_shellrc("echo hi\ntheme bogus\n"), Theme::new());
+
+    assert_eq!(theme::expand_prompt(&t.prompt, "box", "/www"), "box:/www $");
+    assert_eq!(theme::expand_prompt("100%% %x%", "h", "/"), "100% %x%");
+
+    serial_println!("[test] test_theme... ok");
+}
+
+fn test_early_panic_writer() {
+    use crate::boot::panic::FixedWriter;
+    use core::fmt::Write;
+
+    serial_println!("[test] test_early_panic_writer... ");
+
+    let mut buffer = [0u8; 8];
+    let mut writer = FixedWriter::new(&mut buffer);
+    write!(writer, "{}", 1234).expect("write");
+    assert_eq!(writer.as_bytes(), b"1234");
+    assert!(!writer.is_truncated());
+    writer
+        .write_str("-overflow")
+        .expect("truncation is not an error");
+    assert_eq!(writer.as_bytes(), b"1234-ove");
+    assert!(writer.is_truncated());
+    assert!(crate::klog::is_ready());
+
+    serial_println!("[test] test_early_panic_writer... ok");
+}
+
+#[cfg(feature = "net")]
+fn test_net_pools() {
+    use crate::net::pool::{BufferPool, SOCKET_POOL};
+    use crate::net::stack::{NetConfig, NetworkStack, MAX_SOCKETS};
+    use crate::net::{NetError, NetworkDevice, QemuE1000};
+
+    serial_println!("[test] test_net_pools... ");
+
+    static POOL: BufferPool = BufferPool::new(64);
+
+    let mut packet = POOL.packet(10);
+    assert_eq!(packet.len(), 10);
+    packet[9] = 0xAA;
+    drop(packet);
+    let packet = POOL.packet_from(&[1, 2, 3]);
+    assert_eq!(&packet[..], &[1, 2, 3]);
+    let stats = POOL.stats();
+    assert_eq!((stats.blocks, stats.in_use, stats.reused), (1, 1, 1));
+    drop(packet);
+
+    // Recycled blocks come back zeroed
+    assert!(POOL.packet(64).iter().all(|byte| *byte == 0));
+    let big = POOL.packet(100);
+    assert_eq!(big.len(), 100);
+    assert_eq!(POOL.stats().oversized, 1);
+    drop(big);
+    assert_eq!(POOL.stats().in_use, 0);
+
+    let (block, lease) = POOL.lease().expect("heap exhausted");
+    assert_eq!(block.len(), 64);
+    assert_eq!(POOL.stats().in_use, 1);
+    // SAFETY: `block` is not used after this point.
+    unsafe { BufferPool::reclaim(lease) };
+    assert_eq!(POOL.stats().in_use, 0);
+
+    // TCP sockets lease two socket blocks and give them back on release
+    let before = SOCKET_POOL.stats().in_use;
+    let mut stack = NetworkStack::new(NetworkDevice::Loopback(QemuE1000::new()), NetConfig::dhcp());
+    let handle = stack.tcp_socket().expect("no room for a TCP socket");
+    assert_eq!(SOCKET_POOL.stats().in_use, before + 2);
+    stack.release_socket(handle);
+    assert_eq!(SOCKET_POOL.stats().in_use, before);
+    assert!(stack.tcp_socket().is_ok());
+
+    // A full socket table is reported, without leasing any buffers
+    while stack.dns_socket(&[]).is_ok() {}
+    assert_eq!(stack.socket_count(), MAX_SOCKETS);
+    assert_eq!(stack.tcp_socket(), Err(NetError::OutOfMemory));
+    assert_eq!(stack.udp_socket(), Err(NetError::OutOfMemory));
+    assert_eq!(SOCKET_POOL.stats().in_use, before + 2);
+    drop(stack);
+    assert_eq!(SOCKET_POOL.stats().in_use, before);
+
+    serial_println!("[test] test_net_pools... ok");
+}
+
+fn test_lockdep() {
+    use crate::sync::lockdep;
+    use crate::sync::TrackedMutex;
+
+    serial_println!("[test] test_lockdep... ");
+
+    let outer = TrackedMutex::new("test_outer", 1);
+    let inner = TrackedMutex::new("test_inner", 2);
+    {
+        let mut a = outer.lock();
+        assert!(outer.is_locked());
+        assert!(outer.try_lock().is_none());
+        let b = inner.lock();
+        *a += *b;
+    }
+    assert!(!outer.is_locked());
+    assert_eq!(*outer.try_lock().expect("free lock"), 3);
+
+    let stats = lockdep::stats();
+    let outer_stats = stats
+        .iter()
+        .find(|lock| lock.name == "test_outer")
+        .expect("outer class registered");
+    assert_eq!(outer_stats.acquisitions, 2);
+    assert_eq!(outer_stats.contended, 0);
+    assert!(stats.iter().any(|lock| lock.name == "test_inner"));
+    assert!(stats.iter().any(|lock| lock.name == "serial"));
+
+    serial_println!("[test] test_lockdep... ok");
+}
+
+fn test_irq_log() {
+    use crate::klog::irq::{self, IRQ_LOG_SLOTS, IRQ_RECORD_LEN};
+    use log::Level;
+
+    serial_println!("[test] test_irq_log... ");
+
+    // Trace records are below the log level, so draining them prints nothing
+    irq::drain();
+    assert!(crate::irq_log!(Level::Trace, "test", "value {}", 42));
+    assert_eq!(irq::pending(), 1);
+    assert_eq!(irq::drain(), 1);
+    assert_eq!(irq::pending(), 0);
+
+    for i in 0..IRQ_LOG_SLOTS {
+        assert!(crate::irq_log!(Level::Trace, "test", "{}", i));
+    }
+    assert!(!crate::irq_log!(Level::Trace, "test", "full"));
+    assert_eq!(irq::drain(), IRQ_LOG_SLOTS);
+
+    // Long messages are cut to the slot size
+    let long = "x".repeat(IRQ_RECORD_LEN * 2);
+    assert!(crate::irq_log!(Level::Trace, "test", "{}", long));
+    assert_eq!(irq::pending(), 1);
+    assert_eq!(irq::drain(), 1);
+
+    serial_println!("[test] test_irq_log... ok");
+}
+
+fn test_vga_deferred() {
+    use crate::arch::x86_64::vga::{self, WRITER};
+    use core::fmt::Write;
+
+    serial_println!("[test] test_vga_deferred... ");
+
+    let writer = WRITER.get().expect("VGA writer initialized");
+    vga::set_deferred(true);
+    // A blank cell at the start of the current line, overwritten later
+    writer.lock().write_str(" \r").expect("vga write");
+    assert!(writer.lock().is_dirty());
+    vga::flush();
+    assert!(!writer.lock().is_dirty());
+
+    vga::set_deferred(false);
+    writer.lock().write_str(" \r").expect("vga write");
+    assert!(!writer.lock().is_dirty());
+
+    serial_println!("[test] test_vga_deferred... ok");
+}
+
+fn test_serial_config() {
+    use crate::arch::x86_64::serial::{self, PortConfig, SerialError, COM3_PORT, DEFAULT_BAUD};
+    use crate::fs::ramfs::RamFs;
+    use crate::fs::{devfs, Device, FileSystem};
+
+    serial_println!("[test] test_serial_config... ");
+
+    assert_eq!(serial::divisor(115_200), Ok(1));
+    assert_eq!(serial::divisor(9600), Ok(12));
+    assert_eq!(serial::divisor(1000), Err(SerialError::InvalidBaud(1000)));
+    assert_eq!(serial::port_base(3), Ok(COM3_PORT));
+    assert_eq!(serial::port_base(5), Err(SerialError::InvalidPort(5)));
+
+    let ports = serial::parse_ports("3:9600,4").expect("valid serial= value");
+    assert_eq!(
+        ports,
+        [
+            PortConfig { com: 3, baud: 9600 },
+            PortConfig {
+                com: 4,
+                baud: DEFAULT_BAUD
+            },
+        ]
+    );
+    assert_eq!(serial::parse_ports("x"), Err(SerialError::Syntax));
+    assert_eq!(serial::parse_ports("5"), Err(SerialError::InvalidPort(5)));
+
+    // COM1 is the log console
+    let console = PortConfig {
+        com: 1,
+        baud: DEFAULT_BAUD,
+    };
+    assert_eq!(serial::open(console), Err(SerialError::InUse(1)));
+    assert_eq!(serial::write_port(4, b"x"), Err(SerialError::NotOpen(4)));
+
+    // Device nodes open like files but name the hardware
+    let fs = RamFs::new();
+    let device = Device::Serial {
+        com: 3,
+        port: COM3_PORT,
+    };
+    fs.add_device(&devfs::serial_path(3), device);
+    assert_eq!(fs.files(), ["dev/serial3"]);
+    let handle = fs.open("dev/serial3").expect("device node exists");
+    assert_eq!(fs.device(handle), Some(device));
+    assert!(fs.read(handle, &mut [0u8; 4], 0).is_err());
+    fs.close(handle);
+
+    serial_println!("[test] test_serial_config... ok");
+}
+
+fn test_ps2() {
+    use crate::arch::x86_64::ps2::{self, Leds};
+
+    serial_println!("[test] test_ps2... ");
+
+    // Fastest rate, shortest delay; slowest rate, longest delay
+    assert_eq!(ps2::typematic_byte(30, 250), 0x00);
+    assert_eq!(ps2::typematic_byte(2, 1000), 0x7F);
+    assert_eq!(ps2::typematic_byte(100, 0), 0x00);
+    assert_eq!(ps2::typematic_centihertz(0x00), 2997);
+    assert_eq!(ps2::typematic_centihertz(0x1F), 199);
+
+    let byte = ps2::typematic_byte(ps2::DEFAULT_RATE, ps2::DEFAULT_DELAY_MS);
+    assert_eq!(ps2::typematic_delay_ms(byte), ps2::DEFAULT_DELAY_MS);
+    assert_eq!(ps2::typematic_centihertz(byte & 0x1F), 999);
+
+    let leds = Leds {
+        scroll_lock: true,
+        num_lock: false,
+        caps_lock: true,
+    };
+    assert_eq!(leds.bits(), 0b101);
+    assert_eq!(Leds::default().bits(), 0);
+
+    serial_println!("[test] test_ps2... ok");
+}
+
+fn test_cpuid() {
+    use crate::arch::x86_64::cpuid;
+
+    serial_println!("[test] test_cpuid... ");
+
+    // Family 6 folds in the extended model; family 0xF adds the extended family
+    assert_eq!(cpuid::decode_signature(0x0009_06EA), (0x6, 0x9E, 0xA));
+    assert_eq!(cpuid::decode_signature(0x00A2_0F10), (0x19, 0x21, 0x0));
+    assert_eq!(cpuid::decode_signature(0x0001_0F23), (0xF, 0x2, 0x3));
+
+    let info = cpuid::info();
+    assert_eq!(info.vendor().len(), 12);
+    // SSE2 is part of the x86_64 baseline
+    assert!(info.features.sse && info.features.sse2);
+    assert!(info.features.present().any(|name| name == "sse2"));
+    assert_eq!(
+        crate::arch::x86_64::rdrand().is_some(),
+        info.features.rdrand
+    );
+
+    serial_println!("[test] test_cpuid... ok");
+}
+
+fn test_fpu() {
+    use crate::arch::x86_64::fpu::{self, FpuState};
+
+    /// Write XMM0. The kernel is soft-float, so the compiler keeps nothing
+    /// in vector registers that this could clobber.
+    fn write_xmm0(value: u64) {
+        // SAFETY: See above; SSE is enabled by `fpu::init`.
+        unsafe { core::arch::asm!("movq xmm0, {}", in(reg) value, options(nomem, nostack)) };
+    }
+
+    fn read_xmm0() -> u64 {
+        let value;
+        // SAFETY: As for `write_xmm0`.
+        unsafe { core::arch::asm!("movq {}, xmm0", out(reg) value, options(nomem, nostack)) };
+        value
+    }
+
+    serial_println!("[test] test_fpu... ");
+
+    let mut a = Box::new(FpuState::new());
+    let mut b = Box::new(FpuState::new());
+    let before = fpu::switches();
+
+    // Each "task" sees its own XMM0 across switches
+    fpu::switch_to(Some(&mut a));
+    write_xmm0(0x1111);
+    fpu::switch_to(Some(&mut b));
+    write_xmm0(0x2222);
+    fpu::switch_to(Some(&mut a));
+    assert_eq!(read_xmm0(), 0x1111);
+    fpu::switch_to(Some(&mut b));
+    assert_eq!(read_xmm0(), 0x2222);
+    fpu::switch_to(None);
+
+    assert_eq!(fpu::switches() - before, 4);
+    assert!(a.is_saved() && b.is_saved());
+    // Dropping the owner's area leaves the registers unowned
+    drop(b);
+    drop(a);
+
+    serial_println!("[test] test_fpu... ok");
+}
+
+fn test_usermode() {
+    use crate::arch::x86_64::usermode::{self, USER_BASE, USER_END};
+
+    serial_println!("[test] test_usermode... ");
+
+    assert!(usermode::is_user_range(USER_BASE, 16));
+    assert!(!usermode::is_user_range(USER_END - 8, 16));
+    assert!(!usermode::is_user_range(0x1000, 1));
+    assert!(!usermode::is_user_range(u64::MAX, 2));
+    assert!(!usermode::demo_program().is_empty());
+
+    // mov edi, 7; mov eax, SYS_EXIT; syscall
+    let program = [
+        0xBF, 0x07, 0x00, 0x00, 0x00, 0xB8, 0x00, 0x00, 0x00, 0x00, 0x0F, 0x05,
+    ];
+    let before = usermode::syscalls();
+    usermode::load(&program).expect("user region mapped");
+    assert_eq!(usermode::run(), Ok(7));
+    assert_eq!(usermode::syscalls() - before, 1);
+
+    serial_println!("[test] test_usermode... ok");
+}
+
+fn test_process_memory() {
+    use crate::allocator::arena::{self, ProcessMemory};
+
+    const PAGE: usize = 64 * 1024;
+
+    serial_println!("[test] test_process_memory... ");
+
+    let arenas = arena::in_use();
+    let recycled = crate::memory::recycled_frames();
+
+    let memory = ProcessMemory::new().expect("free arena");
+    let (large, small, host) = {
+        let _arena = memory.enter();
+        let small = Vec::<u8>::with_capacity(64);
+        // Only the expected linear memory growth goes to the arena; a
+        // host call's buffer as large stays on the kernel heap
+        arena::expect_growth(2 * PAGE);
+        let large = Vec::<u8>::with_capacity(2 * PAGE);
+        let host = Vec::<u8>::with_capacity(2 * PAGE);
+        // Growth that fits the memory's allocation is expected nowhere
+        arena::expect_growth(PAGE);
+        let fits = Vec::<u8>::with_capacity(PAGE);
+        assert!(arena::slot_of(fits.as_ptr()).is_none());
+        (large, small, host)
+    };
+    assert!(arena::slot_of(large.as_ptr()).is_some());
+    assert!(arena::slot_of(small.as_ptr()).is_none());
+    assert!(arena::slot_of(host.as_ptr()).is_none());
+    assert!(memory.mapped_bytes() >= 2 * PAGE as u64);
+    assert!(memory.used_bytes() >= 2 * PAGE);
+    drop(host);
+
+    // Outside the process, nothing is expected
+    arena::expect_growth(4 * PAGE);
+    let outside = Vec::<u8>::with_capacity(4 * PAGE);
+    assert!(arena::slot_of(outside.as_ptr()).is_none());
+    drop(outside);
+
+    // A block outliving its process keeps the arena until it is freed
+    drop(memory);
+    assert_eq!(arena::in_use(), arenas + 1);
+    drop(large);
+    drop(small);
+    assert_eq!(arena::in_use(), arenas);
+    assert!(crate::memory::recycled_frames() > recycled);
+
+    serial_println!("[test] test_process_memory... ok");
+}
+
+fn test_mmio() {
+    use crate::memory::mmio::{MmioError, MmioRegion};
+    use core::ptr::NonNull;
+
+    serial_println!("[test] test_mmio... ");
+
+    // Plain memory stands in for a device
+    let mut backing = [0u64; 4];
+    let base = NonNull::from(&mut backing).cast::<u8>();
+    // SAFETY: `backing` outlives the region and is only accessed through it.
+    let region = unsafe { MmioRegion::from_raw(base, 32) };
+    assert_eq!(region.size(), 32);
+
+    let reg = region.register::<u32>(8).expect("in bounds");
+    reg.write(0xDEAD_BEEF);
+    assert_eq!(reg.read(), 0xDEAD_BEEF);
+    reg.modify(|value| value & 0xFFFF);
+    assert_eq!(region.register::<u16>(8).map(|reg| reg.read()), Ok(0xBEEF));
+    assert_eq!(region.register::<u8>(31).map(|reg| reg.read()), Ok(0));
+
+    assert_eq!(
+        region.register::<u32>(6).err(),
+        Some(MmioError::Misaligned { offset: 6 })
+    );
+    assert_eq!(
+        region.register::<u64>(32).err(),
+        Some(MmioError::OutOfBounds { offset: 32 })
+    );
+    assert_eq!(
+        region.register::<u32>(usize::MAX - 3).err(),
+        Some(MmioError::OutOfBounds {
+            offset: usize::MAX - 3
+        })
+    );
+
+    serial_println!("[test] test_mmio... ok");
+}
+
+fn test_memmap() {
+    use bootloader::bootinfo::MemoryRegionType;
+
+    serial_println!("[test] test_memmap... ");
+
+    let regions = crate::memory::regions();
+    assert!(regions
+        .iter()
+        .any(|region| region.kind == MemoryRegionType::Usable));
+    for pair in regions.windows(2) {
+        assert!(pair[0].end <= pair[1].start);
+    }
+    for region in &regions {
+        assert!(region.start < region.end);
+        assert!(region.allocated <= region.size());
+        if region.kind != MemoryRegionType::Usable {
+            assert_eq!(region.allocated, 0);
+        }
+    }
+    // At least the kernel heap came from the frame allocator
+    let allocated: u64 = regions.iter().map(|region| region.allocated).sum();
+    assert!(allocated >= crate::allocator::HEAP_SIZE as u64);
+
+    serial_println!("[test] test_memmap... ok");
+}
+
+fn test_replay() {
+    use crate::replay::{self, Input, Record, ReplayError};
+
+    serial_println!("[test] test_replay... ");
+
+    let records = [
+        Record {
+            time_ms: 5,
+            input: Input::Scancode(0x1E),
+        },
+        Record {
+            time_ms: 1 << 40,
+            input: Input::Frame(alloc::vec![0xFF; 60]),
+        },
+        Record {
+            time_ms: 1 << 40,
+            input: Input::Frame(Vec::new()),
+        },
+    ];
+    let mut data = Vec::new();
+    replay::encode_header(&mut data);
+    assert_eq!(replay::parse(&data), Ok(Vec::new()));
+    for record in &records {
+        replay::encode_record(&mut data, record.time_ms, &record.input);
+    }
+    assert_eq!(replay::parse(&data).as_deref(), Ok(&records[..]));
+
+    assert_eq!(replay::parse(b"NOPE\x01"), Err(ReplayError::BadMagic));
+    assert_eq!(
+        replay::parse(&data[..data.len() - 1]),
+        Err(ReplayError::Truncated)
+    );
+    let mut version = data.clone();
+    version[replay::MAGIC.len()] = replay::VERSION + 1;
+    assert_eq!(
+        replay::parse(&version),
+        Err(ReplayError::UnsupportedVersion(replay::VERSION + 1))
+    );
+    let mut kind = data.clone();
+    kind[replay::MAGIC.len() + 1] = 0x7F;
+    assert_eq!(replay::parse(&kind), Err(ReplayError::UnknownKind(0x7F)));
+
+    serial_println!("[test] test_replay... ok");
+}
+
+fn test_bench() {
+    use crate::bench::{self, BENCHMARKS};
+
+    serial_println!("[test] test_bench... ");
+
+    assert!(bench::find("heap").is_some());
+    assert!(bench::find("nope").is_none());
+    for benchmark in BENCHMARKS {
+        // A short run of each, so boot stays quick
+        let sample = benchmark.run(64);
+        assert_eq!(sample.name, benchmark.name);
+        assert!(sample.ops >= 64, "{} ran {} ops", sample.name, sample.ops);
+    }
+    #[cfg(feature = "net")]
+    {
+        let loopback = bench::find("loopback").map(|benchmark| benchmark.run(16));
+        assert!(loopback.is_some_and(|sample| sample.bytes == sample.ops * bench::FRAME_SIZE as u64));
+    }
+
+    serial_println!("[test] test_bench... ok");
+}
+
+fn test_fs_write() {
+    use crate::fs::ramfs::RamFs;
+    use crate::fs::{FileSystem, FsError};
+
+    serial_println!("[test] test_fs_write... ");
+
+    let fs = RamFs::new();
+    fs.add_file("log", b"hello");
+    let handle = fs.open("log").expect("file exists");
+    assert_eq!(fs.write(handle, b"J", 0), Ok(1));
+    // Writing past the end fills the gap with zeros
+    assert_eq!(fs.write(handle, b"!", 6), Ok(1));
+    let mut buffer = [0u8; 8];
+    assert_eq!(fs.read(handle, &mut buffer, 0), Ok(7));
+    assert_eq!(&buffer[..7], b"Jello\0!");
+    assert_eq!(fs.size(handle), Ok(7));
+    fs.close(handle);
+    assert_eq!(fs.write(handle, b"x", 0), Err(FsError::InvalidHandle));
+
+    fs.mkdir("dir").expect("mkdir");
+    let dir = fs.open("dir").expect("directory exists");
+    assert_eq!(fs.write(dir, b"x", 0), Err(FsError::InvalidHandle));
+
+    serial_println!("[test] test_fs_write... ok");
+}
+
+#[cfg(feature = "wasm")]
+fn test_pipes() {
+    use crate::wasm::pipe::{pipe, PipeError, PIPE_CAPACITY};
+
+    serial_println!("[test] test_pipes... ");
+
+    let (writer, reader) = pipe();
+    let mut buffer = [0u8; 8];
+    assert_eq!(reader.read(&mut buffer), Err(PipeError::WouldBlock));
+    assert_eq!(writer.write(b"abc"), Ok(3));
+    assert_eq!(reader.read(&mut buffer[..2]), Ok(2));
+    assert_eq!(&buffer[..2], b"ab");
+
+    // A full pipe takes only what fits
+    let fill = alloc::vec![0u8; PIPE_CAPACITY];
+    assert_eq!(writer.write(&fill), Ok(PIPE_CAPACITY - 1));
+    assert_eq!(writer.write(b"x"), Ok(0));
+
+    // The reader drains the rest after the writer is gone, then sees EOF
+    drop(writer);
+    let mut drained = 0;
+    let mut chunk = [0u8; 512];
+    while let Ok(count @ 1..) = reader.read(&mut chunk) {
+        drained += count;
+    }
+    assert_eq!(drained, PIPE_CAPACITY);
+    assert_eq!(reader.read(&mut chunk), Ok(0));
+
+    let (writer, reader) = pipe();
+    drop(reader);
+    assert_eq!(writer.write(b"x"), Err(PipeError::Broken));
+
+    serial_println!("[test] test_pipes... ok");
+}
+
+fn test_config() {
+    use crate::config::{Config, ConfigError, MAX_ENTRIES, MAX_VALUE_LEN, SCHEMA_VERSION};
+
+    serial_println!("[test] test_config... ");
+
+    let mut config = Config::new();
+    assert_eq!(config.set("httpd.port", " 8080 "), Ok(()));
+    assert_eq!(config.get("httpd.port"), Some("8080"));
+    assert_eq!(config.set("net.hostname", "sovelma box"), Ok(()));
+    assert_eq!(config.set("", "x"), Err(ConfigError::InvalidKey));
+    assert_eq!(config.set("a b", "x"), Err(ConfigError::InvalidKey));
+    assert_eq!(config.set("a", "x\ny"), Err(ConfigError::InvalidValue));
+    assert_eq!(config.set("a", "x # y"), Err(ConfigError::InvalidValue));
+    let long = "x".repeat(MAX_VALUE_LEN + 1);
+    assert_eq!(config.set("a", &long), Err(ConfigError::InvalidValue));
+
+    // Saved text reads back the same; junk lines are skipped
+    let text = config.encode() + "not a setting\nbad key = 1\n";
+    assert_eq!(Config::parse(&text), config);
+
+    // Files from before the schema line are schema 1; later ones are refused
+    assert_eq!(Config::decode(&config.encode()), Ok(config.clone()));
+    assert_eq!(Config::decode("httpd.port = 8080\n"), Ok(Config::parse("httpd.port = 8080")));
+    let later = alloc::format!("# schema {}\nhttpd.port = 1\n", SCHEMA_VERSION + 1);
+    assert_eq!(Config::decode(&later), Err(ConfigError::Schema(SCHEMA_VERSION + 1)));
+
+    assert!(config.unset("httpd.port"));
+    assert!(!config.unset("httpd.port"));
+    assert_eq!(config.get("httpd.port"), None);
+
+    for i in config.len()..MAX_ENTRIES {
+        assert_eq!(config.set(&alloc::format!("k{}", i), "v"), Ok(()));
+    }
+    assert_eq!(config.set("one.more", "v"), Err(ConfigError::Full));
+    // Replacing a value still works when full
+    assert_eq!(config.set("net.hostname", "other"), Ok(()));
+
+    serial_println!("[test] test_config... ok");
+}
+
+fn test_cp437() {
+    use crate::arch::x86_64::cp437::{decode, encode};
+
+    serial_println!("[test] test_cp437... ");
+
+    assert_eq!(encode('A'), Some(b'A'));
+    assert_eq!(encode('é'), Some(0x82));
+    assert_eq!(encode('─'), Some(0xC4));
+    assert_eq!(encode('╔'), Some(0xC9));
+    assert_eq!(encode('♥'), Some(0x03));
+    // Look-alikes and characters the font lacks
+    assert_eq!(encode('╭'), Some(0xDA));
+    assert_eq!(encode('”'), Some(b'"'));
+    assert_eq!(encode('\t'), None);
+    assert_eq!(encode('漢'), None);
+
+    // Every glyph maps back to itself
+    for byte in 0x01..=0xFF {
+        let c = decode(byte).expect("glyph has a character");
+        assert_eq!(encode(c), Some(byte));
+    }
+    assert_eq!(decode(0), None);
+
+    serial_println!("[test] test_cp437... ok");
+}
+
+#[cfg(feature = "terminal")]
+fn test_ctl() {
+    use crate::ctl::{
+        frame, hex_decode, hex_encode, parse_rights, rights_letters, unframe, CtlError, Op,
+        MAX_CHUNK,
+    };
+    use sovelma_common::capability::CapabilityRights;
+
+    serial_println!("[test] test_ctl... ");
+
+    // Framing round trip; a changed byte breaks the checksum
+    let line = frame("1 get /etc/config 0 64");
+    assert!(line.ends_with('\n'));
+    assert_eq!(unframe(line.trim_end()), Ok("1 get /etc/config 0 64"));
+    let damaged = line.trim_end().replace("64", "65");
+    assert_eq!(unframe(&damaged), Err(CtlError::BadChecksum));
+    assert_eq!(unframe("1 hello"), Err(CtlError::BadChecksum));
+
+    assert_eq!(hex_encode(&[0x00, 0xAB, 0x7F]), "00ab7f");
+    assert_eq!(hex_decode("00AB7f"), Some(alloc::vec![0x00, 0xAB, 0x7F]));
+    assert_eq!(hex_decode(""), Some(alloc::vec![]));
+    assert_eq!(hex_decode("abc"), None);
+    assert_eq!(hex_decode("zz"), None);
+
+    let rights = parse_rights("rx").expect("valid rights");
+    assert_eq!(rights, CapabilityRights::READ | CapabilityRights::EXECUTE);
+    assert_eq!(rights_letters(rights), "r-x");
+    assert_eq!(parse_rights("rq"), None);
+
+    assert_eq!(Op::parse("hello", ""), Ok(Op::Hello));
+    assert_eq!(
+        Op::parse("put", "/tmp/a 0 6869"),
+        Ok(Op::Put {
+            path: "/tmp/a",
+            offset: 0,
+            data: alloc::vec![b'h', b'i'],
+        })
+    );
+    // An empty chunk creates an empty file
+    assert!(matches!(Op::parse("put", "/tmp/a 0"), Ok(Op::Put { .. })));
+    let oversized = alloc::format!("/tmp/a 0 {}", "00".repeat(MAX_CHUNK + 1));
+    assert_eq!(Op::parse("put", &oversized), Err(CtlError::BadRequest));
+    assert_eq!(
+        Op::parse("get", "/tmp/a 4 16"),
+        Ok(Op::Get {
+            path: "/tmp/a",
+            offset: 4,
+            len: 16,
+        })
+    );
+    assert_eq!(Op::parse("get", "/tmp/a"), Err(CtlError::BadRequest));
+    assert_eq!(Op::parse("run", " ps "), Ok(Op::Run("ps")));
+    assert_eq!(Op::parse("run", ""), Err(CtlError::BadRequest));
+    assert_eq!(Op::parse("log", "12"), Ok(Op::Log { since: 12 }));
+    assert_eq!(
+        Op::parse("time", "1700000000000000000"),
+        Ok(Op::Time {
+            unix_ns: 1_700_000_000_000_000_000
+        })
+    );
+    assert_eq!(Op::parse("time", "soon"), Err(CtlError::BadRequest));
+    assert_eq!(
+        Op::parse("reboot", ""),
+        Err(CtlError::UnknownOp("reboot".into()))
+    );
+
+    // Each operation is scoped by one right
+    assert_eq!(Op::Hello.rights(), CapabilityRights::empty());
+    assert_eq!(Op::Log { since: 0 }.rights(), CapabilityRights::READ);
+    assert_eq!(Op::Run("ps").rights(), CapabilityRights::EXECUTE);
+    assert_eq!(Op::Time { unix_ns: 0 }.rights(), CapabilityRights::WRITE);
+
+    serial_println!("[test] test_ctl... ok");
+}
+
+#[cfg(feature = "net")]
+fn test_poll_schedule() {
+    use crate::net::poller::{schedule, stats, MAX_POLL_DELAY_MS};
+    use smoltcp::time::Duration;
+
+    serial_println!("[test] test_poll_schedule... ");
+
+    let polls = stats().polls;
+    assert_eq!(schedule(Some(Duration::from_millis(3))), 3);
+    assert_eq!(stats().delay_ms, Some(3));
+    assert_eq!(schedule(Some(Duration::ZERO)), 0);
+    // Long timers and idle sockets still leave the polled devices serviced
+    assert_eq!(schedule(Some(Duration::from_secs(60))), MAX_POLL_DELAY_MS);
+    assert_eq!(stats().delay_ms, Some(60_000));
+    assert_eq!(schedule(None), MAX_POLL_DELAY_MS);
+    assert_eq!(stats().delay_ms, None);
+    assert_eq!(stats().polls, polls + 4);
+
+    serial_println!("[test] test_poll_schedule... ok");
+}
+
+#[cfg(feature = "net")]
+fn test_arp_conflict() {
+    use crate::net::arp::{announce_frame, conflict, parse, probe_frame};
+    use crate::net::dhcp::link_local_address;
+    use smoltcp::wire::{EthernetAddress, Ipv4Address};
+
+    serial_println!("[test] test_arp_conflict... ");
+
+    let ours = EthernetAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    let other = EthernetAddress([0x52, 0x54, 0x00, 0xAB, 0xCD, 0xEF]);
+    let ip = Ipv4Address::new(10, 0, 2, 15);
+    let none = Ipv4Address::UNSPECIFIED;
+
+    assert_eq!(parse(&probe_frame(ours, ip)), Some((ours, none, ip)));
+    assert_eq!(parse(&announce_frame(ours, ip)), Some((ours, ip, ip)));
+    assert_eq!(parse(&[0u8; 20]), None);
+
+    // Our own frames never conflict
+    assert_eq!(conflict(ip, ours, true, ours, ip, ip), None);
+    // Another host claiming the address does, probing or not
+    assert_eq!(conflict(ip, ours, true, other, ip, ip), Some(other));
+    assert_eq!(conflict(ip, ours, false, other, ip, none), Some(other));
+    // Another host probing for it only while we probe too
+    assert_eq!(conflict(ip, ours, true, other, none, ip), Some(other));
+    assert_eq!(conflict(ip, ours, false, other, none, ip), None);
+    // Unrelated traffic
+    let elsewhere = Ipv4Address::new(10, 0, 2, 2);
+    assert_eq!(conflict(ip, ours, true, other, elsewhere, ip), None);
+
+    // Replacement link-local addresses stay in 169.254.1.0-169.254.254.255
+    let mac = Some(ours.0);
+    assert_eq!(link_local_address(mac, 0), Ipv4Address::new(169, 254, 0x34, 0x56));
+    for attempt in 1..=20 {
+        let addr = link_local_address(mac, attempt);
+        assert_ne!(addr, link_local_address(mac, attempt - 1));
+        let [a, b, c, _] = addr.0;
+        assert!(a == 169 && b == 254 && (1..=254).contains(&c));
+    }
+
+    serial_println!("[test] test_arp_conflict... ok");
+}
+
+#[cfg(feature = "heap-poison")]
+fn test_heap_poison() {
+    use crate::allocator::poison::{self, FREED_BYTE};
+    use alloc::alloc::{alloc, dealloc, Layout};
+
+    serial_println!("[test] test_heap_poison... ");
+
+    let layout = Layout::from_size_align(24, 8).expect("valid layout");
+    let before = poison::violations();
+
+    // SAFETY: The stray writes land in the allocator's red zone and in a
+    // quarantined block, which stay mapped and are never handed out while
+    // the test looks at them.
+    unsafe {
+        let ptr = alloc(layout);
+        assert!(!ptr.is_null());
+        ptr.add(layout.size()).write(0);
+        dealloc(ptr, layout);
+        assert_eq!(poison::violations(), before + 1);
+        assert_eq!(*ptr, FREED_BYTE);
+        assert!(poison::quarantined() > 0);
+
+        ptr.write(0);
+        poison::scrub();
+        assert_eq!(poison::violations(), before + 2);
+        // Poisoned again, so it is reported once
+        poison::scrub();
+        assert_eq!(poison::violations(), before + 2);
+    }
+
+    serial_println!("[test] test_heap_poison... ok");
+}
+
+#[cfg(feature = "net")]
+fn test_link_state() {
+    use crate::net::dhcp::DhcpState;
+    use crate::net::{DhcpClient, NetConfig, NetworkDevice, NetworkStack, QemuE1000};
+    use smoltcp::time::Instant;
+
+    serial_println!("[test] test_link_state... ");
+
+    // Devices without link detection are always up and never report a change
+    let mut stack = NetworkStack::new(NetworkDevice::Loopback(QemuE1000::new()), NetConfig::dhcp());
+    assert!(stack.link_up());
+    assert_eq!(stack.poll_link(), None);
+    assert!(stack.link_up());
+
+    // DHCP keeps discovering rather than pausing on such a link
+    let mut dhcp = DhcpClient::new();
+    let start = Instant::from_millis(0);
+    dhcp.start(&mut stack, start);
+    assert!(dhcp.poll(&mut stack, start).is_none());
+    assert_eq!(dhcp.state(), DhcpState::Discovering);
+
+    serial_println!("[test] test_link_state... ok");
+}
+
+#[cfg(feature = "net")]
+fn test_checksum_offload() {
+    use crate::net::e1000::{prepare_tx_checksum, rx_checksum_ok};
+    use smoltcp::phy::ChecksumCapabilities;
+    use smoltcp::wire::{
+        IpAddress, IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr, TcpControl, TcpPacket,
+        TcpRepr, TcpSeqNumber, ETHERNET_HEADER_LEN,
+    };
+
+    serial_println!("[test] test_checksum_offload... ");
+
+    // A TCP segment with every checksum computed by smoltcp
+    let src = Ipv4Address::new(10, 0, 2, 15);
+    let dst = Ipv4Address::new(10, 0, 2, 2);
+    let tcp = TcpRepr {
+        src_port: 49152,
+        dst_port: 80,
+        control: TcpControl::Psh,
+        seq_number: TcpSeqNumber(1000),
+        ack_number: Some(TcpSeqNumber(2000)),
+        window_len: 8192,
+        window_scale: None,
+        max_seg_size: None,
+        sack_permitted: false,
+        sack_ranges: [None; 3],
+        payload: b"GET / HTTP/1.0\r\n\r\n!",
+    };
+    let ip = Ipv4Repr {
+        src_addr: src,
+        dst_addr: dst,
+        next_header: IpProtocol::Tcp,
+        payload_len: tcp.buffer_len(),
+        hop_limit: 64,
+    };
+    let caps = ChecksumCapabilities::default();
+    let mut frame = alloc::vec![0u8; ETHERNET_HEADER_LEN + ip.buffer_len() + tcp.buffer_len()];
+    frame[12..14].copy_from_slice(&[0x08, 0x00]);
+    let mut packet = Ipv4Packet::new_unchecked(&mut frame[ETHERNET_HEADER_LEN..]);
+    ip.emit(&mut packet, &caps);
+    tcp.emit(
+        &mut TcpPacket::new_unchecked(packet.payload_mut()),
+        &IpAddress::Ipv4(src),
+        &IpAddress::Ipv4(dst),
+        &caps,
+    );
+    let start = ETHERNET_HEADER_LEN + ip.buffer_len();
+    let field = start + 16;
+    let expected = [frame[field], frame[field + 1]];
+
+    assert!(rx_checksum_ok(&frame, 0, 0));
+    // The NIC's error bits drop a frame even if it looks fine
+    assert!(!rx_checksum_ok(&frame, 0, 1 << 5));
+
+    // What the NIC does with the seeded field gives smoltcp's checksum
+    let mut offloaded = frame.clone();
+    offloaded[field..field + 2].fill(0);
+    assert_eq!(prepare_tx_checksum(&mut offloaded), Some(start as u8));
+    let mut sum: u32 = offloaded[start..]
+        .chunks(2)
+        .map(|w| u32::from(u16::from_be_bytes([w[0], *w.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    assert_eq!((!(sum as u16)).to_be_bytes(), expected);
+
+    // Trailing padding keeps the checksum in software
+    let mut padded = frame.clone();
+    padded[field..field + 2].fill(0);
+    padded.extend_from_slice(&[0; 6]);
+    assert_eq!(prepare_tx_checksum(&mut padded), None);
+    assert_eq!([padded[field], padded[field + 1]], expected);
+
+    // Corruption the NIC did not check for is caught in software
+    let mut corrupt = frame.clone();
+    corrupt[start + 20] ^= 0x01;
+    assert!(!rx_checksum_ok(&corrupt, 0, 0));
+    let mut corrupt = frame.clone();
+    corrupt[ETHERNET_HEADER_LEN + 8] ^= 0x01;
+    assert!(!rx_checksum_ok(&corrupt, 1 << 5, 0));
+
+    serial_println!("[test] test_checksum_offload... ok");
+}
+
+#[cfg(feature = "net")]
+fn test_nic_filters() {
+    use crate::net::e1000::multicast_hash;
+    use crate::net::{FilterError, NetworkDevice, QemuE1000};
+
+    serial_println!("[test] test_nic_filters... ");
+
+    // Bits 47:36 of the address pick the multicast table bit
+    let mdns = [0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb];
+    assert_eq!(multicast_hash(mdns), (0x7D, 1 << 16));
+    let all_hosts = [0x01, 0x00, 0x5e, 0x00, 0x00, 0x01];
+    assert_eq!(multicast_hash(all_hosts), (0, 1 << 16));
+    assert_eq!(multicast_hash([0xff; 6]), (0x7F, 1 << 31));
+
+    // Devices without filters see everything and refuse changes
+    let mut device = NetworkDevice::Loopback(QemuE1000::new());
+    assert!(device.promiscuous());
+    assert_eq!(device.set_promiscuous(false), Err(FilterError::Unsupported));
+    assert_eq!(device.add_filter(mdns), Err(FilterError::Unsupported));
+    assert_eq!(device.remove_filter(mdns), Err(FilterError::Unsupported));
+    assert!(device.filters().is_empty());
+
+    serial_println!("[test] test_nic_filters... ok");
+}
+
+#[cfg(all(feature = "wasm", feature = "terminal"))]
+fn test_net_rights() {
+    use crate::wasm::commands::parse_net_grant;
+    use sovelma_common::capability::CapabilityRights;
+
+    serial_println!("[test] test_net_rights... ");
+
+    // A client cannot listen or send raw frames
+    let client = parse_net_grant("connect").expect("connect");
+    assert_eq!(client.rights, CapabilityRights::CONNECT);
+    assert!(!client.permits_listen(80));
+
+    // A server listens on its own ports only
+    let server = parse_net_grant("listen=8000-8099").expect("listen range");
+    assert!(!server.rights.contains(CapabilityRights::CONNECT));
+    assert!(server.permits_listen(8000) && server.permits_listen(8099));
+    assert!(!server.permits_listen(7999) && !server.permits_listen(8100));
+    let single = parse_net_grant("raw,listen=8080").expect("single port");
+    assert!(single.rights.contains(CapabilityRights::RAW));
+    assert!(single.permits_listen(8080) && !single.permits_listen(8081));
+
+    for bad in ["", "listen", "listen=9000-8000", "listen=http", "connect,bind"] {
+        assert!(parse_net_grant(bad).is_none(), "{:?} accepted", bad);
+    }
+
+    serial_println!("[test] test_net_rights... ok");
+}
+
+#[cfg(feature = "wasm")]
+fn test_batch_records() {
+    use crate::wasm::batch::{self, BatchOp, BATCH_RECORD_SIZE};
+
+    serial_println!("[test] test_batch_records... ");
+
+    let read = BatchOp {
+        op: batch::op::FS_READ,
+        cap: 0x1_0000_0007,
+        ptr: 4096,
+        len: 64,
+        offset: 512,
+    };
+    let mut record = read.encode();
+    assert_eq!(BatchOp::decode(&record), Some(read));
+
+    // The kernel's result lands in the record without disturbing the op
+    batch::set_result(&mut record, -21);
+    assert_eq!(BatchOp::decode(&record), Some(read));
+    assert_eq!(&record[24..28], &(-21i32).to_le_bytes());
+
+    let unknown = BatchOp { op: 0, ..read };
+    assert_eq!(BatchOp::decode(&unknown.encode()), None);
+    assert_eq!(BatchOp::decode(&[0xFF; BATCH_RECORD_SIZE]), None);
+
+    // Buffers must lie within memory
+    assert_eq!(read.buffer(65536), Some(4096..4160));
+    assert_eq!(read.buffer(4160), Some(4096..4160));
+    assert_eq!(read.buffer(4159), None);
+    let past_end = BatchOp {
+        ptr: 65535,
+        len: 2,
+        ..read
+    };
+    assert_eq!(past_end.buffer(65536), None);
+
+    serial_println!("[test] test_batch_records... ok");
+}
+
+fn test_trace_spans() {
+    use crate::trace::{self, Category, Span};
+
+    serial_println!("[test] test_trace_spans... ");
+
+    // 1000 cycles per microsecond: 2000 cycles in, 500 long
+    let base = 1_000_000;
+    let task = Span {
+        category: Category::Task,
+        name: "task",
+        arg: 7,
+        start: base + 2000,
+        cycles: 500,
+    };
+    assert_eq!(
+        trace::chrome_event(&task, base, 1000),
+        "{\"name\":\"task 7\",\"cat\":\"task\",\"ph\":\"X\",\"pid\":1,\"tid\":1,\"ts\":2.000,\"dur\":0.500}"
+    );
+
+    let irq = Span {
+        category: Category::Interrupt,
+        name: "timer",
+        arg: 0,
+        start: base,
+        cycles: 1_500_250,
+    };
+    assert_eq!(
+        trace::chrome_event(&irq, base, 1000),
+        "{\"name\":\"timer\",\"cat\":\"irq\",\"ph\":\"X\",\"pid\":1,\"tid\":2,\"ts\":0.000,\"dur\":1500.250,\"args\":{\"irq\":0}}"
+    );
+
+    // With tracing off a span records nothing
+    if !trace::enabled() {
+        let before = trace::spans().len();
+        drop(trace::span(Category::HostCall, "sp_fs_read", 0));
+        assert_eq!(trace::spans().len(), before);
+    }
+
+    serial_println!("[test] test_trace_spans... ok");
+}
+
+fn test_priority_donation() {
+    use crate::sync::AsyncMutex;
+    use crate::task::donate::{self, Producer};
+    use crate::task::executor::Executor;
+    use crate::task::{current_priority, yield_now, Priority, Task};
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    static PRODUCER: Producer = Producer::new();
+
+    serial_println!("[test] test_priority_donation... ");
+
+    // Outside task context there is no priority to lend
+    assert!(donate::donate(&PRODUCER).is_none());
+
+    let lock = AsyncMutex::new_shared(());
+    let boosted = Arc::new(AtomicBool::new(false));
+    let mut executor = Executor::new();
+    {
+        let lock = lock.clone();
+        let boosted = boosted.clone();
+        executor.spawn(Task::new(async move {
+            PRODUCER.register();
+            let _guard = lock.lock().await;
+            yield_now().await;
+            yield_now().await;
+            // The High task is waiting for the lock by now
+            boosted.store(current_priority() == Some(Priority::High), Ordering::Relaxed);
+        }));
+    }
+    executor.spawn(Task::with_priority(
+        async move {
+            // Let the Normal task register and take the lock
+            yield_now().await;
+            let producer = PRODUCER.task().expect("producer not registered");
+            let donation = donate::donate(&PRODUCER);
+            assert!(donation.is_some());
+            assert_eq!(donate::boost(producer), Some(Priority::High));
+            drop(donation);
+            assert_eq!(donate::boost(producer), None);
+
+            // Waiting for the lock lends the holder our priority
+            drop(lock.lock().await);
+            assert_eq!(donate::boost(producer), None);
+        },
+        Priority::High,
+    ));
+    executor.run_until_idle();
+    assert!(boosted.load(Ordering::Relaxed));
+
+    serial_println!("[test] test_priority_donation... ok");
+}
+
+#[cfg(feature = "net")]
+fn test_socket_gc() {
+    use crate::net::stack::{NetConfig, NetworkStack, DETACHED_ICMP_LINGER};
+    use crate::net::{NetworkDevice, QemuE1000};
+    use smoltcp::time::Instant;
+
+    serial_println!("[test] test_socket_gc... ");
+
+    let mut stack = NetworkStack::new(NetworkDevice::Loopback(QemuE1000::new()), NetConfig::dhcp());
+    let t0 = Instant::from_millis(0);
+
+    // A closed socket someone still owns stays
+    let owned = stack.tcp_socket().expect("no room for a TCP socket");
+    assert_eq!(stack.collect_garbage(t0), 0);
+
+    // Detached sockets go once finished: closed TCP, unbound UDP...
+    let tcp = stack.tcp_socket().expect("no room for a TCP socket");
+    let udp = stack.udp_socket().expect("no room for a UDP socket");
+    let bound = stack.udp_socket().expect("no room for a UDP socket");
+    stack.udp_bind(bound, 40000).expect("bind failed");
+    let icmp = stack.icmp_socket().expect("no room for an ICMP socket");
+    for handle in [tcp, udp, bound, icmp] {
+        stack.detach(handle, t0);
+    }
+    assert_eq!(stack.collect_garbage(t0), 2);
+    assert_eq!(stack.socket_count(), 3);
+    assert_eq!(stack.detached_count(), 2);
+
+    // ...and ICMP once replies had time to arrive
+    assert_eq!(stack.collect_garbage(t0 + DETACHED_ICMP_LINGER), 1);
+    assert_eq!(stack.detached_count(), 1);
+
+    // Releasing a detached socket forgets it
+    stack.release_socket(bound);
+    assert_eq!(stack.detached_count(), 0);
+    stack.release_socket(owned);
+    assert_eq!(stack.socket_count(), 0);
+
+    serial_println!("[test] test_socket_gc... ok");
+}
+
+#[cfg(all(feature = "net", feature = "wasm"))]
+fn test_connection_events() {
+    use crate::net::connection::{Connection, CONNECT_TIMEOUT};
+    use crate::net::stack::{NetConfig, NetworkStack};
+    use crate::net::{ConnectionEvent, ConnectionSink, NetworkDevice, QemuE1000};
+    use crate::wasm::event::{Event, EventQueue};
+    use smoltcp::socket::tcp::State;
+    use smoltcp::time::Instant;
+    use smoltcp::wire::{IpAddress, IpEndpoint};
+
+    serial_println!("[test] test_connection_events... ");
+
+    let mut stack = NetworkStack::new(NetworkDevice::Loopback(QemuE1000::new()), NetConfig::dhcp());
+    let handle = stack.tcp_socket().expect("no room for a TCP socket");
+    let t0 = Instant::from_millis(0);
+    let remote = IpEndpoint::new(IpAddress::v4(10, 0, 2, 2), 80);
+
+    // Established once, then closed
+    let mut conn = Connection::new(handle, ConnectionSink::Kernel, t0);
+    assert_eq!(conn.update(State::SynSent, None, t0), None);
+    assert_eq!(
+        conn.update(State::Established, Some(remote), t0),
+        Some(ConnectionEvent::Connected)
+    );
+    assert_eq!(conn.update(State::CloseWait, None, t0), None);
+    assert_eq!(conn.remote(), Some(remote));
+    assert_eq!(conn.update(State::Closed, None, t0), Some(ConnectionEvent::Closed));
+
+    // Reset during the handshake
+    let mut conn = Connection::new(handle, ConnectionSink::Kernel, t0);
+    assert_eq!(conn.update(State::SynSent, None, t0), None);
+    assert_eq!(conn.update(State::Closed, None, t0), Some(ConnectionEvent::Refused));
+
+    // No answer
+    let mut conn = Connection::new(handle, ConnectionSink::Kernel, t0);
+    assert_eq!(
+        conn.update(State::SynSent, None, t0 + CONNECT_TIMEOUT),
+        Some(ConnectionEvent::TimedOut)
+    );
+    assert_eq!(conn.deliver(ConnectionEvent::TimedOut), Some(ConnectionEvent::TimedOut));
+
+    // Process watchers get queue events instead
+    let events = EventQueue::shared();
+    let conn = Connection::new(
+        handle,
+        ConnectionSink::Process {
+            events: events.clone(),
+            socket: 3,
+        },
+        t0,
+    );
+    assert_eq!(conn.deliver(ConnectionEvent::Connected), None);
+    assert_eq!(
+        events.lock().pop(),
+        Some(Event::Connection {
+            socket: 3,
+            state: ConnectionEvent::Connected.code(),
+        })
+    );
+
+    // Kernel watches report through the stack and end with a final event
+    stack.watch_connection(handle, ConnectionSink::Kernel, t0);
+    assert_eq!(stack.poll_connections(t0), [(None, ConnectionEvent::Refused)]);
+    assert!(stack.poll_connections(t0).is_empty());
+
+    // Releasing the socket ends its watch
+    stack.watch_connection(handle, ConnectionSink::Kernel, t0);
+    stack.release_socket(handle);
+    assert!(stack.poll_connections(t0).is_empty());
+
+    serial_println!("[test] test_connection_events... ok");
+}
+
+/// Processes name capabilities by small handles of their own.
+#[cfg(feature = "wasm")]
+fn test_capability_handles() {
+    use crate::wasm::handles::{Handle, HandleTable};
+    use crate::wasm::HostState;
+    use sovelma_common::capability::{Capability, CapabilityRights};
+
+    serial_println!("[test] test_capability_handles... ");
+
+    // Lowest free handle first, starting at 1
+    let mut table = HandleTable::new();
+    let ids: alloc::vec::Vec<_> = (0..3)
+        .map(|_| Capability::new(CapabilityType::Timer, CapabilityRights::READ).id)
+        .collect();
+    let handles: alloc::vec::Vec<_> = ids.iter().map(|&id| table.insert(id)).collect();
+    assert_eq!(
+        handles.iter().map(|handle| handle.value()).collect::<alloc::vec::Vec<_>>(),
+        [1, 2, 3]
+    );
+    assert_eq!(table.remove(handles[1]), Some(ids[1]));
+    assert_eq!(table.get(handles[1]), None);
+    assert_eq!(table.insert(ids[1]), handles[1]);
+    assert_eq!(table.handle_of(ids[2]), Some(handles[2]));
+    assert_eq!(table.len(), 3);
+
+    // Only small positive values are handles
+    assert_eq!(Handle::from_raw(0), None);
+    assert_eq!(Handle::from_raw(-1), None);
+    assert_eq!(Handle::from_raw(i64::from(u32::MAX)), None);
+    assert_eq!(Handle::from_raw(2), Handle::new(2));
+
+    // Initial capabilities are handles 1, 2, ...
+    let timer = Capability::new(CapabilityType::Timer, CapabilityRights::READ);
+    let file = Capability::new(CapabilityType::File(7), CapabilityRights::READ);
+    let file_id = file.id;
+    let mut state = HostState::with_capabilities([timer, file]);
+    assert_eq!(state.capability(2).map(|cap| cap.id), Some(file_id));
+
+    // Closing frees the handle for the next grant; revoking drops it too
+    assert_eq!(state.close(1).map(|cap| cap.object), Some(CapabilityType::Timer));
+    assert!(state.capability(1).is_none());
+    let sem = Capability::new(CapabilityType::Semaphore(1), CapabilityRights::CALL);
+    let sem_id = sem.id;
+    assert_eq!(state.grant(sem).value(), 1);
+    state.revoke(sem_id);
+    assert!(state.capability(1).is_none());
+    assert_eq!(state.held().len(), 1);
+
+    serial_println!("[test] test_capability_handles... ok");
+}
+
+/// An exited process's files are closed and its capabilities revoked.
+#[cfg(feature = "wasm")]
+fn test_process_teardown() {
+    use crate::fs::{FileSystem, ROOT_FS};
+    use crate::wasm::HostState;
+    use sovelma_common::capability::{Capability, CapabilityRights};
+
+    serial_println!("[test] test_process_teardown... ");
+
+    ROOT_FS.add_file("/tmp/teardown.txt", b"left open");
+    let handle = ROOT_FS.open("/tmp/teardown.txt").expect("open failed");
+    let mut state = HostState::with_capabilities([
+        Capability::new(CapabilityType::Timer, CapabilityRights::READ),
+        Capability::new(CapabilityType::File(u64::from(handle.0)), CapabilityRights::READ),
+    ]);
+
+    let released = state.teardown();
+    assert_eq!(released.capabilities, 2);
+    assert_eq!(released.files, 1);
+    assert_eq!(released.open_sockets(), 0);
+    assert!(ROOT_FS.size(handle).is_err(), "file should be closed");
+    assert!(state.capability(1).is_none());
+    assert!(state.held().is_empty());
+
+    // Nothing is left for a second teardown
+    assert!(state.teardown().is_empty());
+
+    serial_println!("[test] test_process_teardown... ok");
+}
+
+/// Sync objects record their owner and are destroyed with it unless shared.
+fn test_sync_registry() {
+    use crate::sync::registry::{self, SyncKind};
+
+    serial_println!("[test] test_sync_registry... ");
+
+    let owned = registry::create_mutex(Some(42));
+    let shared = registry::create_semaphore(1, Some(42));
+    assert!(registry::share(SyncKind::Semaphore, shared));
+    assert!(!registry::share(SyncKind::Mutex, u64::MAX));
+
+    let mine = |list: &[registry::SyncInfo]| {
+        list.iter()
+            .filter(|info| {
+                (info.kind, info.handle) == (SyncKind::Mutex, owned)
+                    || (info.kind, info.handle) == (SyncKind::Semaphore, shared)
+            })
+            .cloned()
+            .collect::<alloc::vec::Vec<_>>()
+    };
+    let before = mine(&registry::list());
+    assert_eq!(before.len(), 2);
+    assert!(before.iter().all(|info| info.owner == Some(42)));
+    assert!(before.iter().all(|info| info.waiters == 0));
+
+    // The owner exits
+    assert!(registry::release(SyncKind::Mutex, owned));
+    assert!(!registry::release(SyncKind::Semaphore, shared));
+    assert!(registry::get_mutex(owned).is_none());
+    let after = mine(&registry::list());
+    assert_eq!(after.len(), 1);
+    assert_eq!(after[0].owner, None);
+    assert!(after[0].shared);
+
+    assert!(registry::destroy_semaphore(shared));
+
+    serial_println!("[test] test_sync_registry... ok");
+}
+
+/// Futex waiters are woken oldest first, and only those still waiting count.
+fn test_futex() {
+    use crate::sync::futex::{self, FutexKey};
+
+    serial_println!("[test] test_futex... ");
+
+    let key = FutexKey {
+        space: futex::new_space(),
+        addr: 64,
+    };
+    let other = FutexKey { addr: 68, ..key };
+    let first = futex::wait(key);
+    let second = futex::wait(key);
+    let gone = futex::wait(key);
+    drop(gone);
+    assert_eq!(futex::waiters(key), 2);
+
+    assert_eq!(futex::wake(other, 1), 0);
+    assert_eq!(futex::wake(key, 1), 1);
+    assert!(first.is_woken());
+    assert!(!second.is_woken());
+    assert_eq!(futex::wake(key, usize::MAX), 1);
+    assert!(second.is_woken());
+    assert_eq!(futex::waiters(key), 0);
+    assert_eq!(futex::wake(key, 1), 0);
+
+    serial_println!("[test] test_futex... ok");
+}
+
+/// Log records carry their time; it is shown dmesg and RFC 3339 style.
+#[cfg(feature = "terminal")]
+fn test_log_timestamps() {
+    use crate::klog::{self, LogRecord};
+    use crate::terminal::commands::{parse_seconds, DmesgArgs, DMESG_ARGS};
+    use crate::time::{Uptime, WallTime};
+
+    serial_println!("[test] test_log_timestamps... ");
+
+    assert_eq!(
+        alloc::format!("{}", WallTime(0)),
+        "1970-01-01T00:00:00.000000Z"
+    );
+    // Leap day, and the last microsecond of a year
+    assert_eq!(
+        alloc::format!("{}", WallTime(951_782_400_000_000_000)),
+        "2000-02-29T00:00:00.000000Z"
+    );
+    assert_eq!(
+        alloc::format!("{}", WallTime(1_767_225_599_999_999_000)),
+        "2025-12-31T23:59:59.999999Z"
+    );
+    assert_eq!(alloc::format!("{}", Uptime(12_345_678_901)), "   12.345678");
+
+    let record = LogRecord {
+        level: log::Level::Info,
+        target: "dhcp".into(),
+        message: "Bound".into(),
+        mono_ns: 2_000_000,
+        wall_ns: None,
+    };
+    assert_eq!(
+        alloc::format!("{}", record),
+        "[    0.002000] [INFO  dhcp] Bound"
+    );
+
+    // A new record is stamped with the clock (unless `loglevel=` hides it)
+    if log::log_enabled!(target: "test", log::Level::Info) {
+        let before = crate::time::now_ns();
+        log::info!(target: "test", "timestamp probe");
+        let (_, logged) = klog::history(klog::next_seq() - 1)
+            .pop()
+            .expect("record kept");
+        assert_eq!(logged.message, "timestamp probe");
+        assert!(logged.mono_ns >= before && logged.mono_ns <= crate::time::now_ns());
+        assert_eq!(logged.wall_ns, crate::time::wall_ns_at(logged.mono_ns));
+    }
+
+    assert_eq!(parse_seconds("12"), Some(12_000_000_000));
+    assert_eq!(parse_seconds("1.5"), Some(1_500_000_000));
+    assert_eq!(parse_seconds("0.000000001"), Some(1));
+    assert_eq!(parse_seconds(".5"), None);
+    assert_eq!(parse_seconds("1.x"), None);
+    assert_eq!(
+        DmesgArgs::parse(&["--since", "2.5", "--follow"]),
+        Some(DmesgArgs {
+            since_ns: 2_500_000_000,
+            follow: true,
+        })
+    );
+    assert_eq!(DmesgArgs::parse(&["--since"]), None);
+    assert_eq!(DmesgArgs::parse(&["--tail"]), None);
+    assert_eq!(DmesgArgs::parse(&["--since", "x"]), None);
+    assert_eq!(
+        DmesgArgs::parse(&["-f", "--since=1"]),
+        Some(DmesgArgs {
+            since_ns: 1_000_000_000,
+            follow: true,
+        })
+    );
+    assert_eq!(DMESG_ARGS.usage(), "dmesg [--since <seconds>] [-f|--follow]");
+
+    serial_println!("[test] test_log_timestamps... ok");
+}
+
+/// `testutil::Capture` records serial output for `assert_serial_contains`.
+fn test_serial_capture() {
+    use crate::testutil::{assert_serial_contains, Capture};
+
+    serial_println!("[test] test_serial_capture... ");
+
+    let capture = Capture::start();
+    serial_println!("captured {}", 42);
+    crate::serial_print!("no newline");
+    let serial = capture.finish();
+    assert_eq!(serial, "captured 42\nno newline");
+    assert_serial_contains(&serial, "captured 42");
+    serial_println!();
+
+    serial_println!("[test] test_serial_capture... ok");
+}
+
+/// Drivers register devices, share interrupt lines and get DMA memory.
+fn test_driver_api() {
+    use crate::driver::{self, prelude::*};
+    use crate::memory::{self, PAGE_SIZE};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    serial_println!("[test] test_driver_api... ");
+
+    /// Echoes back what was written.
+    struct Loopback(Vec<u8>);
+
+    impl CharDevice for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, DeviceError> {
+            if self.0.is_empty() {
+                return Err(DeviceError::WouldBlock);
+            }
+            let len = buf.len().min(self.0.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0.drain(..len);
+            Ok(len)
+        }
+
+        fn write(&mut self, buf: &[u8]) -> Result<usize, DeviceError> {
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    let id = register_char("test-loop0", Box::new(Loopback(Vec::new()))).expect("registered");
+    assert_eq!(
+        register_char("test-loop0", Box::new(Loopback(Vec::new()))),
+        Err(DriverError::NameTaken)
+    );
+    assert!(driver::devices()
+        .iter()
+        .any(|device| device.id == id && device.kind == DeviceKind::Char));
+    let mut buf = [0u8; 8];
+    let read = driver::with_char("test-loop0", |device| {
+        device.write(b"ping").and_then(|_| device.read(&mut buf))
+    });
+    assert_eq!(read, Some(Ok(4)));
+    assert_eq!(&buf[..4], b"ping");
+    assert!(driver::with_block("test-loop0", |_| ()).is_none());
+    assert!(driver::unregister(id));
+    assert!(!driver::unregister(id));
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    fn handler(irq: u8) {
+        assert_eq!(irq, 11);
+        CALLS.fetch_add(1, Ordering::Relaxed);
+    }
+    assert_eq!(request_irq(1, handler), Err(DriverError::IrqUnavailable(1)));
+    assert_eq!(request_irq(11, handler), Ok(()));
+    assert_eq!(request_irq(11, handler), Ok(()));
+    driver::dispatch_irq(11);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    free_irq(11, handler);
+    driver::dispatch_irq(11);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+
+    let recycled = memory::recycled_frames();
+    let mut region = dma_alloc(PAGE_SIZE as usize + 1).expect("DMA memory");
+    assert_eq!(region.len(), 2 * PAGE_SIZE as usize);
+    assert_eq!(region.phys() % PAGE_SIZE, 0);
+    assert!(region.as_slice().iter().all(|&byte| byte == 0));
+    region.as_mut_slice()[PAGE_SIZE as usize] = 0xAA;
+    // SAFETY: No device was given the region.
+    unsafe { dma_free(region) };
+    assert_eq!(memory::recycled_frames(), recycled + 2);
+
+    serial_println!("[test] test_driver_api... ok");
+}
+
+/// Errors from `fs::read_file` say which path failed.
+fn test_error_context() {
+    use crate::fs::{self, FsError};
+
+    serial_println!("[test] test_error_context... ");
+
+    let error = fs::read_file("no/such/file.txt").expect_err("file is missing");
+    assert_eq!(*error.kind(), FsError::NotFound);
+    assert_eq!(
+        alloc::format!("{}", error),
+        "not found, while opening no/such/file.txt"
+    );
+    let hosts = fs::read_file("etc/hosts").expect("hosts file");
+    assert_eq!(hosts, b"127.0.0.1 localhost\n");
+
+    serial_println!("[test] test_error_context... ok");
+}
+
+/// Failed boot phases are recorded per subsystem; good ones pass their
+/// value through.
+fn test_boot_health() {
+    use crate::boot::health::{self, Health, Subsystem};
+
+    serial_println!("[test] test_boot_health... ");
+
+    let mut record = Health::new();
+    assert!(record.is_healthy());
+    record.record(Subsystem::Network, &"no NIC found");
+    record.record(Subsystem::Drivers, &"ramdisk: out of memory");
+    assert!(!record.is_healthy());
+    assert!(record.is_degraded(Subsystem::Network));
+    assert!(!record.is_degraded(Subsystem::Filesystem));
+    let degraded = record.degraded();
+    assert_eq!(degraded.len(), 2);
+    assert_eq!(degraded[1].subsystem, Subsystem::Drivers);
+    assert_eq!(degraded[1].reason, "ramdisk: out of memory");
+
+    let before = health::degraded().len();
+    assert_eq!(
+        health::check(Subsystem::Filesystem, Ok::<_, &str>(3)),
+        Some(3)
+    );
+    assert_eq!(health::require("test phase", Ok::<_, ()>(4)), 4);
+    assert_eq!(health::degraded().len(), before);
+    // The filesystem phase ran before the tests and the tests use it
+    assert!(!health::is_degraded(Subsystem::Filesystem));
+
+    serial_println!("[test] test_boot_health... ok");
+}
+
+fn test_command_line() {
+    use crate::terminal::keymap::KeyDecoder;
+    use crate::terminal::line;
+
+    serial_println!("[test] test_command_line... ");
+
+    assert_eq!(line::split("  \t "), None);
+    let Some((cmd, args)) = line::split(" wasm  run a.wasm | wasm run b.wasm ") else {
+        panic!("line did not split");
+    };
+    assert_eq!(cmd, "wasm");
+    let stages: Vec<&[String]> = line::stages(&args).collect();
+    assert_eq!(stages.len(), 2);
+    assert_eq!(stages[0], ["run", "a.wasm"]);
+    assert_eq!(stages[1], ["wasm", "run", "b.wasm"]);
+
+    // Quotes group words, escapes stand for characters
+    let Ok(words) = line::words(r#"echo "hello  world" 'a "b"' c\ d "" "x\"y\n""#) else {
+        panic!("line did not split");
+    };
+    assert_eq!(words, ["echo", "hello  world", r#"a "b""#, "c d", "", "x\"y\n"]);
+    assert_eq!(line::words(r"'\n'"), Ok(alloc::vec![String::from(r"\n")]));
+    assert_eq!(line::words("a\\\nb"), Ok(alloc::vec![String::from("ab")]));
+    assert_eq!(line::words("echo 'open"), Err(line::Incomplete::Quote));
+    assert_eq!(line::words("echo \"a\\"), Err(line::Incomplete::Continued));
+    assert_eq!(line::split("echo \\"), None);
+
+    let Some(parsed) = line::parse("PS", &["--json", "-a"]) else {
+        panic!("command did not parse");
+    };
+    assert_eq!(parsed.name, "ps");
+    assert_eq!(parsed.args, ["-a"]);
+    assert!(parsed.json);
+    assert!(line::parse("", &["x"]).is_none());
+
+    // Garbage and truncated extended sequences decode to nothing harmful
+    let mut decoder = KeyDecoder::new();
+    for scancode in [0xe0, 0xe0, 0xe1, 0x1d, 0xff, 0x00, 0xaa] {
+        if let Some(event) = decoder.add_byte(scancode) {
+            let _ = decoder.process(event);
+        }
+    }
+
+    serial_println!("[test] test_command_line... ok");
+}
+
+fn test_ramfs_stress() {
+    use crate::fs::{FileSystem, ROOT_FS};
+    use crate::task::executor::Executor;
+    use crate::task::{yield_now, Task};
+    use alloc::collections::BTreeSet;
+    use alloc::format;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use spin::Mutex;
+
+    const TASKS: usize = 16;
+    const ROUNDS: usize = 64;
+    // Handles each task holds across a yield
+    const OPENS_PER_ROUND: usize = 10;
+    const FILE_LEN: usize = 64;
+
+    serial_println!("[test] test_ramfs_stress... ");
+
+    let open_before = ROOT_FS.open_count();
+    ROOT_FS.add_file("stress/shared/.keep", b"");
+    // Handles currently open, to catch the same handle given out twice
+    let open = Arc::new(Mutex::new(BTreeSet::new()));
+    let dirs_created = Arc::new(AtomicUsize::new(0));
+
+    let mut executor = Executor::new();
+    for task in 0..TASKS {
+        let open = open.clone();
+        let dirs_created = dirs_created.clone();
+        executor.spawn(Task::new(async move {
+            let own = format!("stress/t{}", task);
+            let content = [task as u8; FILE_LEN];
+            for round in 0..ROUNDS {
+                // Every task writes the same shared files and races to
+                // create the same directories
+                ROOT_FS.add_file(&format!("stress/shared/f{}", round), &content);
+                if ROOT_FS.mkdir(&format!("stress/shared/d{}", round)).is_ok() {
+                    dirs_created.fetch_add(1, Ordering::Relaxed);
+                }
+                ROOT_FS.add_file(&format!("{}/d{}/file", own, round), &content[..round]);
+                yield_now().await;
+
+                let Ok(dir) = ROOT_FS.open(&format!("{}/d{}", own, round)) else {
+                    panic!("task {} round {}: directory missing", task, round);
+                };
+                let mut handles = alloc::vec![dir];
+                for _ in 0..OPENS_PER_ROUND {
+                    let Ok(file) = ROOT_FS.open_at(dir, "file") else {
+                        panic!("task {} round {}: file missing", task, round);
+                    };
+                    handles.push(file);
+                }
+                for handle in &handles {
+                    assert!(open.lock().insert(handle.0), "handle {} given twice", handle.0);
+                }
+                yield_now().await;
+
+                let file = handles[1];
+                assert_eq!(ROOT_FS.write(file, &content, round), Ok(FILE_LEN));
+                yield_now().await;
+                let mut buffer = [0u8; 2 * FILE_LEN];
+                let Ok(read) = ROOT_FS.read(handles[OPENS_PER_ROUND], &mut buffer, 0) else {
+                    panic!("task {} round {}: read failed", task, round);
+                };
+                assert_eq!(read, round + FILE_LEN);
+                assert!(buffer[..read].iter().all(|&byte| byte == task as u8));
+
+                for handle in handles {
+                    ROOT_FS.close(handle);
+                    open.lock().remove(&handle.0);
+                }
+            }
+        }));
+    }
+    executor.run_until_idle();
+
+    // Nothing leaked, every race for a directory had one winner, and no
+    // shared file is a mix of two writers
+    assert_eq!(ROOT_FS.open_count(), open_before);
+    assert!(open.lock().is_empty());
+    assert_eq!(dirs_created.load(Ordering::Relaxed), ROUNDS);
+    let files = ROOT_FS.files();
+    for round in 0..ROUNDS {
+        let Ok(content) = crate::fs::read_file(&format!("stress/shared/f{}", round)) else {
+            panic!("shared file {} missing", round);
+        };
+        assert_eq!(content.len(), FILE_LEN);
+        assert!(content.iter().all(|&byte| byte == content[0]));
+        for task in 0..TASKS {
+            let path = format!("stress/t{}/d{}/file", task, round);
+            assert!(files.contains(&path), "{} missing", path);
+        }
+    }
+
+    serial_println!("[test] test_ramfs_stress... ok");
+}
+
+fn test_capture() {
+    use crate::arch::x86_64::vga::{self, Color};
+    use crate::terminal;
+
+    serial_println!("[test] test_capture... ");
+
+    // The self-tests run outside of task context, where output is captured too
+    let mut inner = terminal::Output::new();
+    let output = terminal::capture(|| {
+        crate::print!("outer ");
+        vga::set_color(Color::Red, Color::Black);
+        inner = terminal::capture(|| crate::println!("inner"));
+        crate::println!("again");
+    });
+    assert_eq!(output.text(), "outer again\n");
+    assert_eq!(inner.text(), "inner\n");
+
+    // Color changes are recorded in order rather than applied
+    let mut expected = terminal::Output::new();
+    expected.push_str("outer ");
+    expected.push_color(Color::Red, Color::Black);
+    expected.push_str("again\n");
+    assert_eq!(output, expected);
+    assert!(terminal::capture(|| {}).is_empty());
+
+    serial_println!("[test] test_capture... ok");
+}
+
+#[cfg(feature = "terminal")]
+fn test_shell_shortcuts() {
+    use crate::terminal::{self, Terminal};
+    use pc_keyboard::{DecodedKey, KeyCode};
+
+    serial_println!("[test] test_shell_shortcuts... ");
+
+    let ctrl = |letter: u8| DecodedKey::Unicode((letter & 0x1f) as char);
+    let mut t = Terminal::new();
+    let output = terminal::capture(|| {
+        for c in "cat a  bb  ".chars() {
+            assert!(t.handle_key(DecodedKey::Unicode(c)).is_none());
+        }
+        // Ctrl-W takes the spaces before the cursor with the word
+        t.handle_key(ctrl(b'w'));
+        assert_eq!(t.input(), "cat a  ");
+        t.handle_key(ctrl(b'w'));
+        assert_eq!(t.input(), "cat ");
+
+        // Ctrl-U deletes up to the cursor only
+        t.handle_key(DecodedKey::Unicode('x'));
+        t.handle_key(DecodedKey::RawKey(KeyCode::ArrowLeft));
+        t.handle_key(ctrl(b'u'));
+        assert_eq!(t.input(), "x");
+
+        // Ctrl-D deletes under the cursor, and nothing at the end
+        t.handle_key(ctrl(b'd'));
+        assert_eq!(t.input(), "");
+        t.handle_key(DecodedKey::Unicode('y'));
+        t.handle_key(ctrl(b'd'));
+        assert_eq!(t.input(), "y");
+
+        // Ctrl-L keeps the line; Ctrl-C abandons it without running it
+        t.handle_key(ctrl(b'l'));
+        assert_eq!(t.input(), "y");
+        assert!(t.handle_key(ctrl(b'c')).is_none());
+        assert_eq!(t.input(), "");
+    });
+    assert!(output.text().contains("y^C\n"));
+
+    serial_println!("[test] test_shell_shortcuts... ok");
+}
+
+#[cfg(feature = "terminal")]
+fn test_shell_continuation() {
+    use crate::terminal::{self, Terminal};
+    use pc_keyboard::DecodedKey;
+
+    serial_println!("[test] test_shell_continuation... ");
+
+    let mut t = Terminal::new();
+    let enter = |t: &mut Terminal, text: &str| {
+        for c in text.chars() {
+            t.handle_key(DecodedKey::Unicode(c));
+        }
+        t.handle_key(DecodedKey::Unicode('\n'))
+    };
+    let mut command = None;
+    let output = terminal::capture(|| {
+        // A backslash joins lines; an open quote keeps the line break
+        assert!(enter(&mut t, "echo one\\").is_none());
+        assert!(enter(&mut t, "two 'three").is_none());
+        command = enter(&mut t, "four'");
+    });
+    let Some(command) = command else {
+        panic!("continued line did not complete");
+    };
+    assert_eq!(command.name(), "echo");
+    assert_eq!(command.args(), ["onetwo", "three\nfour"]);
+    assert_eq!(output.text().matches("> ").count(), 2);
+
+    serial_println!("[test] test_shell_continuation... ok");
+}
+
+/// `args::Spec` checks options and positional arguments and generates the
+/// usage line.
+fn test_command_args() {
+    use crate::terminal::args::{ArgError, Opt, Spec};
+
+    serial_println!("[test] test_command_args... ");
+
+    static SPEC: Spec = Spec {
+        name: "cp",
+        options: &[
+            Opt::flag("force").short('f'),
+            Opt::value("mode", "octal").short('m'),
+        ],
+        positional: &["<from>", "<to>", "[more...]"],
+    };
+    assert_eq!(
+        SPEC.usage(),
+        "cp [-f|--force] [-m|--mode <octal>] <from> <to> [more...]"
+    );
+
+    let Ok(args) = SPEC.parse(&["a", "-f", "b", "--mode=644", "-m", "600", "c", "d"]) else {
+        panic!("cp arguments not accepted");
+    };
+    assert!(args.flag("force"));
+    assert_eq!(args.value("mode"), Some("600"));
+    assert_eq!(args.values("mode").collect::<Vec<_>>(), ["644", "600"]);
+    assert_eq!(args.parse_value::<u32>("mode"), Ok(Some(600)));
+    assert_eq!(args.positional(), ["a", "b", "c", "d"]);
+    assert_eq!(
+        args.options().map(|(long, _)| long).collect::<Vec<_>>(),
+        ["force", "mode", "mode"]
+    );
+
+    // `--` ends the options, and negative numbers are positional
+    let Ok(args) = SPEC.parse(&["-1", "--", "--force"]) else {
+        panic!("positional arguments not accepted");
+    };
+    assert!(!args.flag("force"));
+    assert_eq!(args.positional(), ["-1", "--force"]);
+    assert_eq!(args.parse_value::<u32>("mode"), Ok(None));
+
+    assert_eq!(SPEC.parse(&["a"]), Err(ArgError::Missing("<to>")));
+    assert_eq!(SPEC.parse(&["a", "b", "-x"]), Err(ArgError::UnknownOption("-x")));
+    assert_eq!(SPEC.parse(&["a", "b", "-fm"]), Err(ArgError::UnknownOption("-fm")));
+    assert_eq!(SPEC.parse(&["a", "b", "--mode"]), Err(ArgError::MissingValue("mode")));
+    assert_eq!(
+        SPEC.parse(&["a", "b", "--force=yes"]),
+        Err(ArgError::UnexpectedValue("force"))
+    );
+    let Ok(args) = SPEC.parse(&["a", "b", "-m", "rw"]) else {
+        panic!("mode not accepted");
+    };
+    assert_eq!(
+        args.parse_value::<u32>("mode"),
+        Err(ArgError::Invalid("mode", "rw"))
+    );
+    assert_eq!(
+        alloc::format!("{}", ArgError::Invalid("mode", "rw")),
+        "invalid --mode: rw"
+    );
+
+    static BARE: Spec = Spec {
+        name: "sync",
+        options: &[],
+        positional: &["[path]"],
+    };
+    assert_eq!(BARE.usage(), "sync [path]");
+    assert!(BARE.parse(&[]).is_ok());
+    assert_eq!(BARE.parse(&["a", "b"]), Err(ArgError::Unexpected("b")));
+
+    serial_println!("[test] test_command_args... ok");
+}
+
+/// The line editor follows a line across the rows it wraps onto, and
+/// erases what a shorter one leaves behind.
+#[cfg(feature = "terminal")]
+fn test_shell_line_wrap() {
+    use crate::arch::x86_64::vga::BUFFER_WIDTH;
+    use crate::terminal::{self, Terminal};
+    use pc_keyboard::{DecodedKey, KeyCode};
+
+    serial_println!("[test] test_shell_line_wrap... ");
+
+    let mut t = Terminal::new();
+    let prompt = terminal::capture(|| t.prompt()).text().len();
+    let mut key = |key: DecodedKey| {
+        terminal::capture(|| {
+            t.handle_key(key);
+        })
+        .text()
+    };
+
+    // Filling the first row moves to the next one
+    let mut typed = String::new();
+    for _ in prompt..BUFFER_WIDTH {
+        typed.push_str(&key(DecodedKey::Unicode('a')));
+    }
+    assert!(typed.ends_with("a\r\n"));
+
+    // Home goes up to the prompt's row; inserting there pushes the rest
+    // of the line along and comes back
+    let home = alloc::format!("\x1b[1A\x1b[{}C", prompt);
+    assert_eq!(key(DecodedKey::RawKey(KeyCode::Home)), home);
+    assert_eq!(
+        key(DecodedKey::Unicode('b')),
+        alloc::format!("b{}{}", "a".repeat(BUFFER_WIDTH - prompt), home)
+    );
+
+    // End goes back down, and deleting the line erases both rows
+    assert_eq!(
+        key(DecodedKey::RawKey(KeyCode::End)),
+        alloc::format!("\x1b[1B\x1b[{}D", prompt)
+    );
+    let erased = key(DecodedKey::Unicode('\x15'));
+    assert!(erased.starts_with("\x1b[1A"));
+    assert!(erased.ends_with("\x1b[J"));
+    assert_eq!(t.input(), "");
+
+    serial_println!("[test] test_shell_line_wrap... ok");
+}
+
+/// The build script's feature list matches what the kernel was compiled
+/// with.
+fn test_buildinfo() {
+    use crate::buildinfo;
+
+    serial_println!("[test] test_buildinfo... ");
+
+    let enabled = |name: &str| buildinfo::features().any(|feature| feature == name);
+    assert_eq!(enabled("net"), cfg!(feature = "net"));
+    assert_eq!(enabled("wasm"), cfg!(feature = "wasm"));
+    assert_eq!(enabled("terminal"), cfg!(feature = "terminal"));
+    assert_eq!(enabled("heap-poison"), cfg!(feature = "heap-poison"));
+    assert!(!enabled("default"));
+    assert!(!buildinfo::GIT_HASH.is_empty());
+    let rustc = buildinfo::RUSTC_VERSION;
+    assert!(rustc.starts_with("rustc ") || rustc == "unknown");
+
+    let json = alloc::format!("{}", buildinfo::json());
+    assert!(json.contains(&alloc::format!("\"version\":\"{}\"", buildinfo::VERSION)));
+
+    serial_println!("[test] test_buildinfo... ok");
+}
+
+/// A namespace reaches only what is mounted in it, and read-only mounts
+/// stay read-only below them.
+fn test_fs_namespaces() {
+    use crate::fs::ramfs::RamFs;
+    use crate::fs::{FileSystem, FsError};
+
+    serial_println!("[test] test_fs_namespaces... ");
+
+    let fs = RamFs::new();
+    fs.add_file("apps/hello.wasm", b"\0asm");
+    fs.add_file("data/log", b"");
+    let apps = fs.open("apps").expect("apps exists");
+    let data = fs.open("data").expect("data exists");
+    let namespace = fs.namespace();
+    assert_eq!(fs.mount(namespace, "/apps", apps, true), Ok(()));
+    assert_eq!(fs.mount(namespace, "/srv/data", data, false), Ok(()));
+    assert_eq!(
+        fs.mount(namespace, "/apps", data, false),
+        Err(FsError::PermissionDenied)
+    );
+    // Mounting through a mount would change the directory it binds
+    assert_eq!(
+        fs.mount(namespace, "/apps/data", data, false),
+        Err(FsError::PermissionDenied)
+    );
+    fs.close(apps);
+    fs.close(data);
+
+    let app = fs.open_at(namespace, "apps/hello.wasm").expect("mounted");
+    assert!(fs.is_read_only(app));
+    assert_eq!(fs.size(app), Ok(4));
+    assert_eq!(fs.write(app, b"x", 0), Err(FsError::PermissionDenied));
+    assert_eq!(
+        fs.mkdir_at(namespace, "apps/new"),
+        Err(FsError::PermissionDenied)
+    );
+    let log = fs.open_at(namespace, "srv/data/log").expect("mounted");
+    assert!(!fs.is_read_only(log));
+    assert_eq!(fs.write(log, b"ok", 0), Ok(2));
+    assert_eq!(fs.open_at(namespace, "data").err(), Some(FsError::NotFound));
+
+    // Directories made in the namespace are its own
+    fs.mkdir_at(namespace, "tmp").expect("mkdir in namespace");
+    assert_eq!(fs.open("tmp").err(), Some(FsError::NotFound));
+    assert_eq!(fs.files(), ["apps/hello.wasm", "data/log"]);
+    let mut buffer = [0u8; 2];
+    let real_log = fs.open("data/log").expect("data/log exists");
+    assert_eq!(fs.read(real_log, &mut buffer, 0), Ok(2));
+    assert_eq!(&buffer, b"ok");
+
+    serial_println!("[test] test_fs_namespaces... ok");
+}
+
+/// Files in a tmpfs grow only to its quota, and nothing created in a
+/// namespace shows up in the tree.
+fn test_tmpfs() {
+    use crate::fs::ramfs::RamFs;
+    use crate::fs::{FileSystem, FsError};
+
+    serial_println!("[test] test_tmpfs... ");
+
+    let fs = RamFs::new();
+    let namespace = fs.namespace();
+    assert_eq!(fs.mount_tmpfs(namespace, "/tmp", 8), Ok(()));
+    assert_eq!(
+        fs.mount_tmpfs(namespace, "/tmp", 8),
+        Err(FsError::PermissionDenied)
+    );
+    let file = fs.create_at(namespace, "tmp/scratch").expect("create");
+    assert_eq!(fs.quota(file), Some((0, 8)));
+    assert_eq!(
+        fs.create_at(namespace, "tmp/scratch"),
+        Err(FsError::PermissionDenied)
+    );
+    assert_eq!(fs.write(file, b"12345", 0), Ok(5));
+    // Overwriting takes nothing; only growth counts
+    assert_eq!(fs.write(file, b"abc", 0), Ok(3));
+    fs.mkdir_at(namespace, "tmp/sub").expect("mkdir in tmpfs");
+    let other = fs.create_at(namespace, "tmp/sub/other").expect("create");
+    assert_eq!(fs.write(other, b"1234", 0), Err(FsError::NoSpace));
+    assert_eq!(fs.write(other, b"123", 0), Ok(3));
+    assert_eq!(fs.quota(file), Some((8, 8)));
+    // Outside the tmpfs there is no quota
+    let outside = fs.create_at(namespace, "notes").expect("create");
+    assert_eq!(fs.quota(outside), None);
+    assert_eq!(fs.write(outside, b"0123456789", 0), Ok(10));
+
+    let before = fs.open_count();
+    for handle in [file, other, outside, namespace] {
+        fs.close(handle);
+    }
+    assert_eq!(fs.open_count(), before - 4);
+    assert!(fs.files().is_empty());
+
+    serial_println!("[test] test_tmpfs... ok");
+}
+
+/// Advisory locks: shared locks coexist, an exclusive one excludes all
+/// others, and closing a handle releases its lock.
+fn test_fs_locks() {
+    use crate::fs::ramfs::RamFs;
+    use crate::fs::{FileSystem, FsError};
+
+    serial_println!("[test] test_fs_locks... ");
+
+    let fs = RamFs::new();
+    fs.add_file("data/db", b"");
+    fs.add_file("data/other", b"");
+    let a = fs.open("data/db").expect("file exists");
+    let b = fs.open("data/db").expect("file exists");
+    let other = fs.open("data/other").expect("file exists");
+
+    assert_eq!(fs.try_lock(a, false), Ok(true));
+    assert_eq!(fs.try_lock(b, false), Ok(true));
+    assert!(!fs.can_lock(b, true));
+    assert_eq!(fs.try_lock(b, true), Ok(false));
+    // Locks on another file do not interfere
+    assert_eq!(fs.try_lock(other, true), Ok(true));
+    assert_eq!(fs.unlock(a), Ok(true));
+    assert_eq!(fs.unlock(a), Ok(false));
+    // The only shared holder may upgrade
+    assert_eq!(fs.try_lock(b, true), Ok(true));
+    assert_eq!(fs.try_lock(a, false), Ok(false));
+
+    fs.close(b);
+    assert!(fs.can_lock(a, true));
+    assert_eq!(fs.try_lock(a, true), Ok(true));
+    // A handle opened from a locked one does not inherit its lock
+    let dir = fs.open("data").expect("directory exists");
+    let c = fs.open_at(dir, "db").expect("file exists");
+    assert_eq!(fs.unlock(c), Ok(false));
+    assert_eq!(fs.try_lock(c, false), Ok(false));
+    fs.close(a);
+    assert_eq!(fs.try_lock(c, false), Ok(true));
+    assert_eq!(fs.unlock(b), Err(FsError::InvalidHandle));
+
+    serial_println!("[test] test_fs_locks... ok");
+}
+
+/// Capability descriptors: labels from grant time, a directory's path
+/// from the root (none in a namespace) and a Network capability's ports.
+fn test_capability_descriptors() {
+    use crate::fs::ramfs::RamFs;
+    use crate::fs::FileSystem;
+    use sovelma_common::capability::{
+        Capability, CapabilityDescriptor, CapabilityDetail, CapabilityRights, Label,
+    };
+
+    serial_println!("[test] test_capability_descriptors... ");
+
+    let fs = RamFs::new();
+    fs.add_file("srv/data/db", b"");
+    let root = fs.open("/").expect("root exists");
+    let data = fs.open("srv/data").expect("directory exists");
+    let db = fs.open_at(data, "db").expect("file exists");
+    let namespace = fs.namespace();
+    assert_eq!(fs.path(root).as_deref(), Some("/"));
+    assert_eq!(fs.path(data).as_deref(), Some("/srv/data"));
+    assert_eq!(fs.path(db).as_deref(), Some("/srv/data/db"));
+    assert_eq!(fs.path(namespace), None);
+
+    let cap = Capability::new(
+        CapabilityType::Directory(u64::from(data.0)),
+        CapabilityRights::READ,
+    )
+    .with_label(Label::new("data").expect("data is a valid label"));
+    let descriptor = CapabilityDescriptor::new(2, &cap, Some("/srv/data"));
+    assert_eq!(descriptor.record.kind, "directory");
+    assert_eq!(descriptor.label, Some("data"));
+    assert_eq!(descriptor.detail, CapabilityDetail::Path("/srv/data"));
+
+    let net = Capability::new(
+        CapabilityType::Network {
+            first_port: 80,
+            last_port: 80,
+        },
+        CapabilityRights::LISTEN,
+    );
+    assert_eq!(
+        CapabilityDescriptor::new(3, &net, None).detail,
+        CapabilityDetail::Ports {
+            first: 80,
+            last: 80
+        }
+    );
+    assert!(Label::new("").is_none());
+    assert!(Label::new("no spaces").is_none());
+    assert!(Label::new("seventeen-bytes-x").is_none());
+
+    for handle in [root, data, db, namespace] {
+        fs.close(handle);
+    }
+    serial_println!("[test] test_capability_descriptors... ok");
+}
+
+/// A capability granted to a running process takes the next handle and is
+/// announced on its event queue.
+#[cfg(feature = "wasm")]
+fn test_wasm_grant() {
+    use crate::wasm::event::Event;
+    use crate::wasm::WasmEngine;
+    use sovelma_common::capability::{Capability, CapabilityRights};
+
+    serial_println!("[test] test_wasm_grant... ");
+
+    let engine = WasmEngine::new();
+    let timer = Capability::new(CapabilityType::Timer, CapabilityRights::READ);
+    let mut process = engine
+        .spawn_process_with_caps(b"\0asm\x01\0\0\0", alloc::vec![timer])
+        .expect("empty module loads");
+    let config = Capability::new(
+        CapabilityType::Config,
+        CapabilityRights::READ | CapabilityRights::WRITE,
+    );
+    let handle = process.grant(config);
+    assert_eq!(handle.as_raw(), 2);
+    assert_eq!(
+        process.events().lock().pop(),
+        Some(Event::Granted { handle: 2 })
+    );
+    serial_println!("[test] test_wasm_grant... ok");
+}
+
+/// Capabilities derived from a rate-limited one share its bucket, which
+/// outlives the parent.
+#[cfg(feature = "wasm")]
+fn test_rate_limits() {
+    use crate::wasm::HostState;
+    use sovelma_common::capability::{Capability, CapabilityRights};
+    use sovelma_common::rate::RateLimit;
+
+    serial_println!("[test] test_rate_limits... ");
+
+    let limit = RateLimit::new(1, 2).expect("limit is nonzero");
+    let net = Capability::new(
+        CapabilityType::Network {
+            first_port: 80,
+            last_port: 80,
+        },
+        CapabilityRights::LISTEN,
+    )
+    .with_rate_limit(limit);
+    let mut state = HostState::with_capabilities([net]);
+    let socket = Capability::new(CapabilityType::Socket(1), CapabilityRights::READ);
+    let socket = state.derive(1, socket).as_raw();
+    assert_eq!(
+        state.capability(socket).and_then(|cap| cap.rate_limit),
+        Some(limit)
+    );
+
+    assert_eq!(state.take_token(1, 0), None);
+    assert_eq!(state.take_token(socket, 0), None);
+    assert_eq!(state.take_token(1, 0), Some(1000));
+    assert!(state.close(1).is_some());
+    assert_eq!(state.take_token(socket, 1000), Some(2000));
+
+    let unlimited = Capability::new(CapabilityType::Config, CapabilityRights::READ);
+    let unlimited = state.grant(unlimited).as_raw();
+    assert_eq!(state.take_token(unlimited, 0), None);
+    serial_println!("[test] test_rate_limits... ok");
+}
+
+/// A process that calls `sp_dbg_break` stops until resumed, and its
+/// memory and globals can be read meanwhile.
+#[cfg(feature = "wasm")]
+fn test_dbg_break() {
+    use crate::wasm::process::{DebugError, ProcessManager};
+    use crate::wasm::snapshot::GlobalValue;
+
+    serial_println!("[test] test_dbg_break... ");
+
+    // `_start` calls `env.sp_dbg_break`; one page of memory holding "hi"
+    // at 16 and a mutable global `counter` of 7 are exported
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+    module.extend_from_slice(&[0x01, 0x08, 0x02, 0x60, 0x00, 0x01, 0x7f, 0x60, 0x00, 0x00]);
+    module.extend_from_slice(&[0x02, 0x14, 0x01, 0x03]);
+    module.extend_from_slice(b"env\x0csp_dbg_break\x00\x00");
+    module.extend_from_slice(&[0x03, 0x02, 0x01, 0x01]);
+    module.extend_from_slice(&[0x05, 0x03, 0x01, 0x00, 0x01]);
+    module.extend_from_slice(&[0x06, 0x06, 0x01, 0x7f, 0x01, 0x41, 0x07, 0x0b]);
+    module.extend_from_slice(&[0x07, 0x1d, 0x03]);
+    module.extend_from_slice(b"\x06memory\x02\x00\x07counter\x03\x00\x06_start\x00\x01");
+    module.extend_from_slice(&[0x0a, 0x07, 0x01, 0x05, 0x00, 0x10, 0x00, 0x1a, 0x0b]);
+    module.extend_from_slice(&[0x0b, 0x08, 0x01, 0x00, 0x41, 0x10, 0x0b, 0x02, b'h', b'i']);
+
+    let mut processes = ProcessManager::new();
+    let process = processes
+        .engine()
+        .spawn_process_with_caps(&module, Vec::new())
+        .expect("module loads");
+    let pid = processes.spawn("dbg.wasm", process, "_start");
+    assert_eq!(processes.resume(pid), Err(DebugError::NotStopped));
+
+    let stopped = |processes: &ProcessManager| processes.list().iter().any(|info| info.stopped);
+    for _ in 0..8 {
+        if stopped(&processes) {
+            break;
+        }
+        processes.poll();
+    }
+    assert!(stopped(&processes));
+    // Stays stopped however often it is polled
+    processes.poll();
+    assert!(stopped(&processes));
+
+    assert_eq!(processes.peek(pid, 16, 2).as_deref(), Ok(&b"hi"[..]));
+    assert_eq!(processes.peek(pid, 65535, 2), Err(DebugError::OutOfBounds));
+    assert_eq!(
+        processes.globals(pid),
+        Ok(alloc::vec![("counter".into(), GlobalValue::I32(7))])
+    );
+    assert_eq!(processes.capabilities(pid), Ok(Vec::new()));
+    assert_eq!(
+        processes.peek(pid + 1, 0, 1),
+        Err(DebugError::NoSuchProcess)
+    );
+
+    assert_eq!(processes.resume(pid), Ok(()));
+    for _ in 0..8 {
+        if processes.is_empty() {
+            break;
+        }
+        processes.poll();
+    }
+    assert!(processes.is_empty());
+    serial_println!("[test] test_dbg_break... ok");
+}
+
+/// Directory listings are sorted, report sizes, and list a mount as the
+/// directory it binds.
+fn test_fs_readdir() {
+    use crate::fs::ramfs::RamFs;
+    use crate::fs::{Device, DirEntry, FileSystem, FsError};
+    use sovelma_common::fs::EntryKind;
+
+    serial_println!("[test] test_fs_readdir... ");
+
+    let fs = RamFs::new();
+    fs.add_file("srv/notes.txt", b"hello");
+    fs.add_file("srv/logs/boot", b"");
+    fs.add_device(
+        "srv/ttyS0",
+        Device::Serial {
+            com: 1,
+            port: 0x3f8,
+        },
+    );
+    let srv = fs.open("srv").expect("directory exists");
+    let entry = |name: &str, kind, size| DirEntry {
+        name: name.into(),
+        kind,
+        size,
+    };
+    assert_eq!(
+        fs.readdir(srv),
+        Ok(alloc::vec![
+            entry("logs", EntryKind::Directory, 0),
+            entry("notes.txt", EntryKind::File, 5),
+            entry("ttyS0", EntryKind::Device, 0),
+        ])
+    );
+
+    let namespace = fs.namespace();
+    fs.mount(namespace, "/data", srv, true).expect("mounts");
+    assert_eq!(
+        fs.readdir(namespace),
+        Ok(alloc::vec![entry("data", EntryKind::Directory, 0)])
+    );
+    let data = fs.open_at(namespace, "data").expect("mount is reachable");
+    assert_eq!(fs.readdir(data).map(|entries| entries.len()), Ok(3));
+
+    let notes = fs.open("srv/notes.txt").expect("file exists");
+    assert_eq!(fs.readdir(notes), Err(FsError::InvalidHandle));
+    fs.close(srv);
+    assert_eq!(fs.readdir(srv), Err(FsError::InvalidHandle));
+    serial_println!("[test] test_fs_readdir... ok");
+}
+
+/// `step` runs a stopped process up to its next suspension after the fuel
+/// budget and stops it there again.
+#[cfg(feature = "wasm")]
+fn test_dbg_step() {
+    use crate::wasm::process::{DebugError, ProcessManager};
+    use crate::wasm::snapshot::GlobalValue;
+
+    serial_println!("[test] test_dbg_step... ");
+
+    // `_start` calls `env.sp_dbg_break`, then loops adding 1 to the
+    // exported global `counter` (initially 7) and calling
+    // `env.sp_sched_yield`
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+    module.extend_from_slice(&[0x01, 0x08, 0x02, 0x60, 0x00, 0x01, 0x7f, 0x60, 0x00, 0x00]);
+    module.extend_from_slice(&[0x02, 0x29, 0x02, 0x03]);
+    module.extend_from_slice(b"env\x0csp_dbg_break\x00\x00\x03env\x0esp_sched_yield\x00\x01");
+    module.extend_from_slice(&[0x03, 0x02, 0x01, 0x01]);
+    module.extend_from_slice(&[0x06, 0x06, 0x01, 0x7f, 0x01, 0x41, 0x07, 0x0b]);
+    module.extend_from_slice(&[0x07, 0x14, 0x02]);
+    module.extend_from_slice(b"\x07counter\x03\x00\x06_start\x00\x02");
+    module.extend_from_slice(&[0x0a, 0x15, 0x01, 0x13, 0x00, 0x10, 0x00, 0x1a, 0x03, 0x40]);
+    module.extend_from_slice(&[0x23, 0x00, 0x41, 0x01, 0x6a, 0x24, 0x00, 0x10, 0x01, 0x0c]);
+    module.extend_from_slice(&[0x00, 0x0b, 0x0b]);
+
+    let mut processes = ProcessManager::new();
+    let process = processes
+        .engine()
+        .spawn_process_with_caps(&module, Vec::new())
+        .expect("module loads");
+    let pid = processes.spawn("step.wasm", process, "_start");
+    assert_eq!(processes.step(pid, 1), Err(DebugError::NotStopped));
+
+    let stopped = |processes: &ProcessManager| processes.list().iter().any(|info| info.stopped);
+    for _ in 0..8 {
+        if stopped(&processes) {
+            break;
+        }
+        processes.poll();
+    }
+    assert!(stopped(&processes));
+
+    // Each step runs one pass of the loop, up to the yield, and stops
+    for counter in 8..11 {
+        assert_eq!(processes.step(pid, 1), Ok(()));
+        assert!(!stopped(&processes));
+        processes.poll();
+        assert!(stopped(&processes));
+        processes.poll();
+        assert_eq!(
+            processes.globals(pid),
+            Ok(alloc::vec![("counter".into(), GlobalValue::I32(counter))])
+        );
+    }
+    assert_eq!(processes.step(pid + 1, 1), Err(DebugError::NoSuchProcess));
+
+    // A resumed process is no longer stepped
+    assert_eq!(processes.resume(pid), Ok(()));
+    for _ in 0..4 {
+        processes.poll();
+    }
+    assert!(!stopped(&processes));
+    serial_println!("[test] test_dbg_step... ok");
+}
+
+/// Unlinking and renaming: open handles and their locks outlive the
+/// name, and an unlinked file's tmpfs space comes back with its last
+/// handle.
+fn test_fs_unlink_rename() {
+    use crate::fs::ramfs::RamFs;
+    use crate::fs::{FileSystem, FsError};
+
+    serial_println!("[test] test_fs_unlink_rename... ");
+
+    let fs = RamFs::new();
+    fs.add_file("srv/a.txt", b"abc");
+    fs.add_file("srv/sub/b.txt", b"");
+    let file = fs.open("srv/a.txt").expect("file exists");
+    assert_eq!(fs.try_lock(file, true), Ok(true));
+    assert_eq!(fs.rename("srv/a.txt", "srv/sub/c.txt"), Ok(()));
+    assert_eq!(fs.open("srv/a.txt"), Err(FsError::NotFound));
+    assert_eq!(fs.path(file).as_deref(), Some("/srv/sub/c.txt"));
+    assert_eq!(fs.try_lock(file, true), Ok(true));
+    assert_eq!(
+        fs.rename("srv/sub/c.txt", "srv/sub/b.txt"),
+        Err(FsError::PermissionDenied)
+    );
+    assert_eq!(
+        fs.rename("srv", "srv/sub/srv"),
+        Err(FsError::PermissionDenied)
+    );
+    assert_eq!(fs.unlink("srv/sub"), Err(FsError::PermissionDenied));
+    assert_eq!(fs.unlink("srv/sub/c.txt"), Ok(()));
+    assert_eq!(fs.unlink("srv/sub/c.txt"), Err(FsError::NotFound));
+    let mut buffer = [0u8; 3];
+    assert_eq!(fs.read(file, &mut buffer, 0), Ok(3));
+    assert_eq!(&buffer, b"abc");
+    fs.close(file);
+    assert_eq!(fs.unlink("srv/sub/b.txt"), Ok(()));
+    assert_eq!(fs.unlink("srv/sub"), Ok(()));
+
+    // In a tmpfs
+    let namespace = fs.namespace();
+    assert_eq!(fs.mount_tmpfs(namespace, "/tmp", 8), Ok(()));
+    let scratch = fs.create_at(namespace, "tmp/scratch").expect("create");
+    assert_eq!(fs.write(scratch, b"12345", 0), Ok(5));
+    assert_eq!(
+        fs.rename_at(namespace, "tmp/scratch", "outside"),
+        Err(FsError::PermissionDenied)
+    );
+    assert_eq!(
+        fs.unlink_at(namespace, "tmp"),
+        Err(FsError::PermissionDenied)
+    );
+    assert_eq!(fs.unlink_at(namespace, "tmp/scratch"), Ok(()));
+    // Still open, so still taking space
+    assert_eq!(fs.write(scratch, b"6789", 5), Err(FsError::NoSpace));
+    assert_eq!(fs.quota(scratch), Some((5, 8)));
+    let probe = fs.create_at(namespace, "tmp/probe").expect("create");
+    fs.close(scratch);
+    assert_eq!(fs.quota(probe), Some((0, 8)));
+    assert_eq!(fs.write(probe, b"12345678", 0), Ok(8));
+    assert_eq!(fs.unlink_at(namespace, "tmp/probe"), Ok(()));
+    fs.close(probe);
+
+    fs.close(namespace);
+    serial_println!("[test] test_fs_unlink_rename... ok");
+}
+
+fn test_crash_loop() {
+    use crate::boot::crashloop::{BootRecord, CRASH_LOOP_LIMIT, PANIC_MESSAGE_MAX};
+
+    serial_println!("[test] test_crash_loop... ");
+
+    // What a cold boot leaves in the frame is not a record
+    let mut record = BootRecord::new();
+    // SAFETY: every field of `BootRecord` is a plain integer
+    unsafe {
+        core::ptr::write_bytes(
+            &mut record as *mut BootRecord as *mut u8,
+            0xa5,
+            core::mem::size_of::<BootRecord>(),
+        );
+    }
+    assert_eq!(record.start(), 0);
+    assert_eq!(record.last_panic(), None);
+
+    // Boots that never complete add up
+    for failed in 1..CRASH_LOOP_LIMIT {
+        assert_eq!(record.start(), failed);
+    }
+    record.set_panic(format_args!("boom at {}", 42));
+    assert_eq!(record.last_panic(), Some("boom at 42"));
+    assert!(record.start() >= CRASH_LOOP_LIMIT);
+
+    // Long messages are cut, on a character boundary
+    let long = "\u{e9}".repeat(PANIC_MESSAGE_MAX);
+    record.set_panic(format_args!("x{}", long));
+    let kept = record.last_panic().expect("panic kept");
+    assert!(kept.len() <= PANIC_MESSAGE_MAX && kept.len() > PANIC_MESSAGE_MAX - 2);
+    assert!(kept.starts_with("x\u{e9}"));
+
+    // A completed boot forgets the failed ones
+    record.complete();
+    assert_eq!(record.last_panic(), None);
+    assert_eq!(record.start(), 0);
+
+    serial_println!("[test] test_crash_loop... ok");
+}
+
+/// `sp_fs_opendir_restricted` hands out a subdirectory with no more than
+/// both the parent's and the requested rights.
+#[cfg(feature = "wasm")]
+fn test_opendir_restricted() {
+    use crate::fs::{FileHandle, FileSystem, ROOT_FS};
+    use crate::wasm::handles::Handle;
+    use crate::wasm::HostState;
+    use sovelma_common::capability::{Capability, CapabilityRights};
+    use sovelma_common::host::error;
+
+    serial_println!("[test] test_opendir_restricted... ");
+
+    ROOT_FS.add_file("/tmp/restricted/sub/file.txt", b"");
+    ROOT_FS.add_file("/tmp/restricted/sub/inner/file.txt", b"");
+    let parent = ROOT_FS.open("/tmp/restricted").expect("directory exists");
+    let directory =
+        |rights| Capability::new(CapabilityType::Directory(u64::from(parent.0)), rights);
+    let mut state =
+        HostState::with_capabilities([directory(CapabilityRights::READ | CapabilityRights::WRITE)]);
+    let rights_of =
+        |state: &HostState, handle: Handle| state.capability(handle.as_raw()).map(|cap| cap.rights);
+
+    // The rights asked for, less any the parent lacks
+    let read_only = state
+        .open_dir_restricted(1, "sub", CapabilityRights::READ.bits())
+        .expect("subdirectory opens");
+    assert_eq!(rights_of(&state, read_only), Some(CapabilityRights::READ));
+    let all = CapabilityRights::READ
+        | CapabilityRights::WRITE
+        | CapabilityRights::EXECUTE
+        | CapabilityRights::GRANT;
+    let widest = state
+        .open_dir_restricted(1, "sub", all.bits())
+        .expect("subdirectory opens");
+    assert_eq!(
+        rights_of(&state, widest),
+        Some(CapabilityRights::READ | CapabilityRights::WRITE)
+    );
+    let Some(CapabilityType::Directory(sub)) =
+        state.capability(read_only.as_raw()).map(|cap| cap.object)
+    else {
+        panic!("not a directory capability");
+    };
+    let file = ROOT_FS
+        .open_at(FileHandle(sub as u32), "file.txt")
+        .expect("the capability names the subdirectory");
+    ROOT_FS.close(file);
+    // Below a restricted directory, asking for more gets no more
+    let inner = state
+        .open_dir_restricted(read_only.as_raw(), "inner", all.bits())
+        .expect("subdirectory opens");
+    assert_eq!(rights_of(&state, inner), Some(CapabilityRights::READ));
+
+    assert_eq!(
+        state.open_dir_restricted(1, "sub/file.txt", CapabilityRights::READ.bits()),
+        Err(error::NOT_A_DIRECTORY)
+    );
+    assert_eq!(
+        state.open_dir_restricted(1, "missing", CapabilityRights::READ.bits()),
+        Err(error::FS_ERROR)
+    );
+    assert_eq!(
+        state.open_dir_restricted(1, "sub", 1 << 31),
+        Err(error::INVALID_ARGUMENT)
+    );
+    assert_eq!(
+        state.open_dir_restricted(99, "sub", CapabilityRights::READ.bits()),
+        Err(error::CAP_NOT_FOUND)
+    );
+
+    // A parent without READ opens nothing
+    let write_only = state.grant(directory(CapabilityRights::WRITE));
+    assert_eq!(
+        state.open_dir_restricted(write_only.as_raw(), "sub", CapabilityRights::WRITE.bits()),
+        Err(error::PERMISSION_DENIED)
+    );
+
+    // Both parent capabilities name the same open directory; let one
+    // close it
+    state.close(write_only.as_raw());
+    let released = state.teardown();
+    assert_eq!(released.files, 4);
+    serial_println!("[test] test_opendir_restricted... ok");
+}
+
+/// Traceroute probes are told apart by port, and only ICMP errors about
+/// the current probe count as its reply.
+#[cfg(feature = "net")]
+fn test_traceroute_replies() {
+    use crate::net::traceroute::{parse_reply, probe_port, ProbeReply, PROBES_PER_HOP};
+    use smoltcp::phy::ChecksumCapabilities;
+    use smoltcp::wire::{
+        Icmpv4DstUnreachable, Icmpv4Packet, Icmpv4Repr, Icmpv4TimeExceeded, IpProtocol,
+        Ipv4Address, Ipv4Repr,
+    };
+
+    serial_println!("[test] test_traceroute_replies... ");
+
+    assert_eq!(probe_port(1, 0), 33434);
+    assert_eq!(probe_port(1, PROBES_PER_HOP - 1) + 1, probe_port(2, 0));
+
+    let local = Ipv4Address::new(10, 0, 2, 15);
+    let router = Ipv4Address::new(10, 0, 2, 2);
+    let target = Ipv4Address::new(192, 0, 2, 7);
+    let port = probe_port(2, 1);
+    // The start of the probe as the ICMP error quotes it: its UDP header
+    let mut quoted = [0u8; 8];
+    quoted[2..4].copy_from_slice(&port.to_be_bytes());
+    let header = Ipv4Repr {
+        src_addr: local,
+        dst_addr: target,
+        next_header: IpProtocol::Udp,
+        payload_len: quoted.len(),
+        hop_limit: 1,
+    };
+    let emit = |repr: Icmpv4Repr| {
+        let mut bytes = alloc::vec![0u8; repr.buffer_len()];
+        repr.emit(
+            &mut Icmpv4Packet::new_unchecked(&mut bytes[..]),
+            &ChecksumCapabilities::default(),
+        );
+        bytes
+    };
+
+    // A router on the way: the trace goes on
+    let exceeded = emit(Icmpv4Repr::TimeExceeded {
+        reason: Icmpv4TimeExceeded::TtlExpired,
+        header,
+        data: &quoted,
+    });
+    assert_eq!(
+        parse_reply(&exceeded, router, target, port),
+        Some(ProbeReply {
+            from: router,
+            terminal: false,
+            reached: false,
+        })
+    );
+    // A late reply to an earlier probe is not this one's
+    assert_eq!(parse_reply(&exceeded, router, target, port + 1), None);
+
+    // The target's Port Unreachable: reached
+    let unreachable = |reason| {
+        emit(Icmpv4Repr::DstUnreachable {
+            reason,
+            header,
+            data: &quoted,
+        })
+    };
+    let port_unreachable = unreachable(Icmpv4DstUnreachable::PortUnreachable);
+    assert_eq!(
+        parse_reply(&port_unreachable, target, target, port),
+        Some(ProbeReply {
+            from: target,
+            terminal: true,
+            reached: true,
+        })
+    );
+    // Unreachable from a router: the trace ends short of the target
+    let host_unreachable = unreachable(Icmpv4DstUnreachable::HostUnreachable);
+    assert_eq!(
+        parse_reply(&host_unreachable, router, target, port),
+        Some(ProbeReply {
+            from: router,
+            terminal: true,
+            reached: false,
+        })
+    );
+
+    // Not an error about a probe at all
+    let echo_reply = emit(Icmpv4Repr::EchoReply {
+        ident: 1,
+        seq_no: 1,
+        data: b"ping",
+    });
+    assert_eq!(parse_reply(&echo_reply, target, target, port), None);
+    assert_eq!(parse_reply(&exceeded[..12], router, target, port), None);
+
+    serial_println!("[test] test_traceroute_replies... ok");
+}
+
+/// Request targets never reach outside the served directory.
+#[cfg(feature = "net")]
+fn test_httpd_paths() {
+    use crate::fs::{FileSystem, ROOT_FS};
+    use crate::net::httpd::{respond, sanitize_path};
+
+    serial_println!("[test] test_httpd_paths... ");
+
+    assert_eq!(sanitize_path("/").as_deref(), Some(""));
+    assert_eq!(sanitize_path("").as_deref(), Some(""));
+    assert_eq!(sanitize_path("/?page=2").as_deref(), Some(""));
+    assert_eq!(sanitize_path("/docs/").as_deref(), Some("docs"));
+    assert_eq!(
+        sanitize_path("//etc//passwd").as_deref(),
+        Some("etc/passwd")
+    );
+    assert_eq!(sanitize_path("/./a/./b#top").as_deref(), Some("a/b"));
+    assert_eq!(sanitize_path("/x?y=/../").as_deref(), Some("x"));
+    for target in [
+        "/..",
+        "/../secret",
+        "/a/../b",
+        "/a/..",
+        "..",
+        "/a/b/../../..",
+    ] {
+        assert_eq!(sanitize_path(target), None, "{}", target);
+    }
+    // Not decoded, so these name files, not parents
+    assert_eq!(
+        sanitize_path("/%2e%2e/secret").as_deref(),
+        Some("%2e%2e/secret")
+    );
+    assert_eq!(sanitize_path("/a%2f..%2fb").as_deref(), Some("a%2f..%2fb"));
+
+    ROOT_FS.add_file("/tmp/www/index.html", b"home");
+    ROOT_FS.add_file("/tmp/www/docs/index.html", b"docs");
+    ROOT_FS.add_file("/tmp/secret.txt", b"secret");
+    let dir = ROOT_FS.open("/tmp/www").expect("served directory exists");
+    let get = |target: &str| {
+        let request = alloc::format!("GET {} HTTP/1.1\r\n\r\n", target);
+        String::from_utf8(respond(dir, request.as_bytes())).expect("responses are text")
+    };
+    let home = get("/");
+    assert!(home.starts_with("HTTP/1.1 200 OK") && home.ends_with("\r\n\r\nhome"));
+    assert!(get("/docs/").ends_with("\r\n\r\ndocs"));
+    assert!(get("/../secret.txt").starts_with("HTTP/1.1 403"));
+    assert!(get("/docs/../../secret.txt").starts_with("HTTP/1.1 403"));
+    assert!(get("//tmp/secret.txt").starts_with("HTTP/1.1 404"));
+    assert!(get("/%2e%2e/secret.txt").starts_with("HTTP/1.1 404"));
+    ROOT_FS.close(dir);
+
+    serial_println!("[test] test_httpd_paths... ok");
+}
+
+/// Test that writes and changes made below a directory handle reach the
+/// watches on their paths from the root.
+fn test_fs_watch_notify() {
+    use crate::fs::ramfs::RamFs;
+    use crate::fs::FileSystem;
+
+    serial_println!("[test] test_fs_watch_notify... ");
+
+    let fs = RamFs::new();
+    fs.add_file("var/log/keep", b"");
+    let log = fs.open("var/log").expect("directory exists");
+    let mut app = fs.watch("/var/log/app");
+    let mut old = fs.watch("/var/log/old");
+    let mut sub = fs.watch("/var/log/sub");
+
+    let file = fs.create_at(log, "app").expect("file created");
+    assert!(fs.changed(&mut app));
+    assert_eq!(fs.write(file, b"line\n", 0), Ok(5));
+    assert!(fs.changed(&mut app));
+    assert!(!fs.changed(&mut app));
+
+    assert_eq!(fs.rename_at(log, "app", "old"), Ok(()));
+    assert!(fs.changed(&mut app));
+    assert!(fs.changed(&mut old));
+    assert_eq!(fs.write(file, b"more\n", 5), Ok(5));
+    assert!(fs.changed(&mut old));
+
+    assert_eq!(fs.mkdir_at(log, "sub"), Ok(()));
+    assert!(fs.changed(&mut sub));
+    assert_eq!(fs.unlink_at(log, "sub"), Ok(()));
+    assert!(fs.changed(&mut sub));
+
+    // A file the root no longer reaches has no path to notify
+    assert_eq!(fs.unlink_at(log, "old"), Ok(()));
+    assert!(fs.changed(&mut old));
+    assert_eq!(fs.write(file, b"gone\n", 10), Ok(5));
+    assert!(!fs.changed(&mut old));
+
+    fs.close(file);
+    fs.close(log);
+    serial_println!("[test] test_fs_watch_notify... ok");
+}
+
+/// Test that a snapshot's capabilities are only restored as derived from
+/// the parents given, with new handles and IDs.
+#[cfg(feature = "wasm")]
+fn test_snapshot_derive() {
+    use crate::fs::{FileHandle, FileSystem, ROOT_FS};
+    use crate::wasm::handles::Handle;
+    use crate::wasm::snapshot::{self, SavedCapability, SavedObject, Snapshot, SnapshotError};
+    use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType};
+
+    serial_println!("[test] test_snapshot_derive... ");
+
+    ROOT_FS.add_file("tmp/snapdrv/data/a.txt", b"abc");
+    ROOT_FS.add_file("tmp/snapdrv/data/sub/b.txt", b"");
+    ROOT_FS.add_file("tmp/snapdrv/database/c.txt", b"");
+    let data = ROOT_FS.open("tmp/snapdrv/data").expect("directory exists");
+    let parents = [
+        Capability::new(
+            CapabilityType::Directory(u64::from(data.0)),
+            CapabilityRights::READ,
+        ),
+        Capability::new(
+            CapabilityType::Network {
+                first_port: 1,
+                last_port: 100,
+            },
+            CapabilityRights::LISTEN,
+        ),
+    ];
+    let saved = |object: SavedObject, rights: CapabilityRights| SavedCapability {
+        handle: Handle::new(2).expect("handle 2 is valid"),
+        object,
+        rights,
+        label: None,
+        rate_limit: None,
+    };
+    let open = ROOT_FS.open_count();
+
+    // A file below the parent is opened again, under a new handle and ID
+    let file = saved(
+        SavedObject::File("/tmp/snapdrv/data/a.txt".into()),
+        CapabilityRights::READ,
+    );
+    let derived = file.derive(&parents).expect("file is below the parent");
+    let CapabilityType::File(handle) = derived.object else {
+        panic!("derived {:?}", derived.object);
+    };
+    assert_ne!(handle, u64::from(data.0));
+    assert_ne!(derived.id, parents[0].id);
+    let mut buffer = [0u8; 3];
+    assert_eq!(
+        ROOT_FS.read(FileHandle(handle as u32), &mut buffer, 0),
+        Ok(3)
+    );
+    ROOT_FS.close(FileHandle(handle as u32));
+
+    let not_covered = |object: SavedObject, rights: CapabilityRights| {
+        matches!(
+            saved(object, rights).derive(&parents),
+            Err(SnapshotError::NotCovered(_))
+        )
+    };
+    // Nothing outside the parent, nor more rights than it has
+    assert!(not_covered(
+        SavedObject::Directory("/tmp/snapdrv/database".into()),
+        CapabilityRights::READ
+    ));
+    assert!(not_covered(
+        SavedObject::Directory("/tmp/snapdrv/data/../database".into()),
+        CapabilityRights::READ
+    ));
+    assert!(not_covered(
+        SavedObject::File("/tmp/snapdrv/data/a.txt".into()),
+        CapabilityRights::READ | CapabilityRights::WRITE
+    ));
+    assert!(not_covered(
+        SavedObject::Serial { port: 0x3f8 },
+        CapabilityRights::READ
+    ));
+    assert!(not_covered(
+        SavedObject::Network {
+            first_port: 100,
+            last_port: 101,
+        },
+        CapabilityRights::LISTEN
+    ));
+    assert!(matches!(
+        saved(
+            SavedObject::Directory("/tmp/snapdrv/data/a.txt".into()),
+            CapabilityRights::READ
+        )
+        .derive(&parents),
+        Err(SnapshotError::Reopen(..))
+    ));
+    let net = saved(
+        SavedObject::Network {
+            first_port: 7,
+            last_port: 7,
+        },
+        CapabilityRights::LISTEN,
+    );
+    assert!(net.derive(&parents).unwrap().permits_listen(7));
+
+    // Kernel objects and memory are left out of a snapshot
+    let memory = Capability::new(
+        CapabilityType::Memory {
+            start: 0xb8000,
+            size: 4000,
+        },
+        CapabilityRights::READ,
+    );
+    assert!(SavedCapability::save(Handle::new(5).unwrap(), &memory).is_none());
+    assert_eq!(
+        SavedCapability::save(Handle::new(5).unwrap(), &parents[0]).map(|saved| saved.object),
+        Some(SavedObject::Directory("/tmp/snapdrv/data".into()))
+    );
+
+    // One capability not covered fails them all and closes what was opened
+    let snapshot = Snapshot {
+        module: "a.wasm".into(),
+        entry: "_start".into(),
+        memory: Vec::new(),
+        globals: Vec::new(),
+        capabilities: alloc::vec![
+            saved(
+                SavedObject::Directory("/tmp/snapdrv/data/sub".into()),
+                CapabilityRights::READ
+            ),
+            saved(SavedObject::Config, CapabilityRights::READ),
+        ],
+    };
+    assert!(matches!(
+        snapshot.derive_capabilities(&parents),
+        Err(SnapshotError::NotCovered(SavedObject::Config))
+    ));
+    assert_eq!(ROOT_FS.open_count(), open);
+    let derived = snapshot.capabilities[..1]
+        .iter()
+        .map(|saved| {
+            (
+                saved.handle,
+                saved.derive(&parents).expect("sub is below data"),
+            )
+        })
+        .collect::<Vec<_>>();
+    snapshot::release(&derived);
+    assert_eq!(ROOT_FS.open_count(), open);
+
+    ROOT_FS.close(data);
+    serial_println!("[test] test_snapshot_derive... ok");
+}
@@ -9,40 +9,150 @@
 //! ```rust,ignore
 //! use sovelma_kernel::testutil::{QemuExitCode, exit_qemu, test_runner, Testable};
 //! ```
+//!
+//! # Markers
+//!
+//! Every test is framed on serial by a `TEST_BEGIN <name>` line and a
+//! `TEST_END <name> ok` (or `FAILED`) line, so a host-side harness can cut
+//! the log into one section per test and compare each with a golden file
+//! (see `src/testharness`).
+//!
+//! # Exit codes
+//!
+//! A panic during a test run exits QEMU with a code naming what failed:
+//! `QemuExitCode::Test` with the test's number if a test was in progress
+//! (ending it as `FAILED`), else `QemuExitCode::Subsystem` for the kernel
+//! module the panic happened in. The harness turns the code back into a
+//! test or module name.
+//!
+//! `Capture` records what is printed to serial while it is alive, for
+//! `assert_serial_contains`.
+
+#[cfg(feature = "terminal")]
+pub mod shell;
+
+use crate::{serial_print, serial_println};
+use alloc::string::String;
+use core::fmt::{self, Write};
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// First word of the line that starts a test.
+pub const TEST_BEGIN: &str = "TEST_BEGIN";
+
+/// First word of the line that ends a test.
+pub const TEST_END: &str = "TEST_END";
+
+/// The test in progress and its number in the run, for the panic handler.
+static CURRENT: Mutex<Option<(usize, &'static str)>> = Mutex::new(None);
+
+/// Number of tests begun so far.
+static BEGUN: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether a test run is in progress, so a panic must exit QEMU.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Serial output recorded by the live `Capture`, if any.
+static CAPTURED: Mutex<Option<String>> = Mutex::new(None);
+
+/// Whether a `Capture` is live; checked before taking `CAPTURED` so
+/// printing costs nothing extra outside tests.
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+/// Exit code base for a panic outside any test: `SUBSYSTEM_CODE_BASE + i`
+/// for a panic in `SUBSYSTEMS[i]`.
+pub const SUBSYSTEM_CODE_BASE: u32 = 0x20;
 
-use crate::serial_println;
+/// Exit code base for a failed test: `TEST_CODE_BASE + n` for the `n`th
+/// test of the run (from 0), saturating at the last code.
+pub const TEST_CODE_BASE: u32 = 0x40;
+
+/// Number of test exit codes; QEMU keeps seven bits of the value.
+pub const TEST_CODES: u32 = 0x40;
+
+/// Kernel modules a panic is attributed to, by the file it happened in.
+/// The host-side harness (`src/testharness`) keeps the same list.
+pub const SUBSYSTEMS: [&str; 22] = [
+    "allocator",
+    "arch",
+    "bench",
+    "boot",
+    "capability",
+    "config",
+    "ctl",
+    "fs",
+    "klog",
+    "ksym",
+    "memory",
+    "net",
+    "replay",
+    "services",
+    "sync",
+    "task",
+    "terminal",
+    "tests",
+    "testutil",
+    "time",
+    "trace",
+    "wasm",
+];
+
+const _: () = assert!(SUBSYSTEMS.len() as u32 <= TEST_CODE_BASE - SUBSYSTEM_CODE_BASE);
 
 /// QEMU exit codes for signaling test results.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u32)]
 pub enum QemuExitCode {
     /// All tests passed.
-    Success = 0x10,
+    Success,
     /// One or more tests failed.
-    Failed = 0x11,
+    Failed,
+    /// The `n`th test of the run (from 0) failed.
+    Test(usize),
+    /// A test run panicked outside any test, in `SUBSYSTEMS[i]`.
+    Subsystem(usize),
+}
+
+impl QemuExitCode {
+    /// Value written to the exit device.
+    ///
+    /// QEMU exits with status `(value << 1) | 1`, so `Success` (0x10) is
+    /// 33 and `Failed` (0x11) is 35.
+    pub fn code(self) -> u32 {
+        match self {
+            QemuExitCode::Success => 0x10,
+            QemuExitCode::Failed => 0x11,
+            QemuExitCode::Test(n) => {
+                TEST_CODE_BASE + u32::try_from(n).unwrap_or(u32::MAX).min(TEST_CODES - 1)
+            }
+            QemuExitCode::Subsystem(i) => SUBSYSTEM_CODE_BASE + i as u32,
+        }
+    }
+
+    /// Exit code for a panic outside any test in `file` (as in a panic's
+    /// `Location`); `Failed` if it is not in one of `SUBSYSTEMS`.
+    pub fn for_file(file: &str) -> Self {
+        let module = file
+            .rsplit_once("kernel/src/")
+            .and_then(|(_, path)| path.split(['/', '.']).next());
+        match SUBSYSTEMS.iter().position(|name| Some(*name) == module) {
+            Some(i) => QemuExitCode::Subsystem(i),
+            None => QemuExitCode::Failed,
+        }
+    }
 }
 
 /// Exit QEMU with the given exit code.
 ///
-/// Uses the isa-debug-exit device configured on port 0xf4.
-///
-/// # Note
-///
-/// QEMU must be started with `-device isa-debug-exit,iobase=0xf4,iosize=0x04`.
-/// The actual exit code will be `(value << 1) | 1`, so:
-/// - `Success` (0x10) → exit code 33
-/// - `Failed` (0x11) → exit code 35
+/// Goes through the platform's `DebugExit` device; on x86_64 QEMU must be
+/// started with `-device isa-debug-exit,iobase=0xf4,iosize=0x04`. Returns
+/// if there is no such device.
 pub fn exit_qemu(exit_code: QemuExitCode) {
     #[cfg(target_arch = "x86_64")]
     {
-        use x86_64::instructions::port::Port;
+        use sovelma_hal::DebugExit;
 
-        // SAFETY: Writing to the isa-debug-exit device port is safe when QEMU
-        // is configured with this device. It triggers a QEMU exit.
-        unsafe {
-            let mut port = Port::new(0xf4);
-            port.write(exit_code as u32);
-        }
+        crate::arch::debug_exit().exit(exit_code.code());
     }
 }
 
@@ -54,12 +164,124 @@ pub trait Testable {
 
 impl<T: Fn()> Testable for T {
     fn run(&self) {
-        serial_println!("test {} ... ", core::any::type_name::<T>());
+        let name = core::any::type_name::<T>();
+        begin(name);
         self();
-        serial_println!("[ok]");
+        end(name, true);
     }
 }
 
+/// Mark the start of a test run: from now on a panic exits QEMU.
+pub fn start_run() {
+    RUNNING.store(true, Ordering::Release);
+}
+
+/// Print the marker starting test `name`.
+pub fn begin(name: &'static str) {
+    start_run();
+    let number = BEGUN.fetch_add(1, Ordering::Relaxed);
+    *CURRENT.lock() = Some((number, name));
+    serial_println!("{} {}", TEST_BEGIN, name);
+}
+
+/// Print the marker ending test `name`, with its result.
+pub fn end(name: &'static str, passed: bool) {
+    CURRENT.lock().take();
+    serial_println!(
+        "{} {} {}",
+        TEST_END,
+        name,
+        if passed { "ok" } else { "FAILED" }
+    );
+}
+
+/// End the test in progress, if any, as failed, and pick the exit code for
+/// a panic at `location`: the test's if one was running, else that of the
+/// subsystem the panic is in. `None` outside test runs; called by panic
+/// handlers.
+pub fn fail_current(location: Option<&Location>) -> Option<QemuExitCode> {
+    CAPTURING.store(false, Ordering::Release);
+    // The panic may have interrupted a test holding the lock
+    let current = CURRENT.try_lock().and_then(|mut current| current.take());
+    if let Some((number, name)) = current {
+        serial_println!("{} {} FAILED", TEST_END, name);
+        return Some(QemuExitCode::Test(number));
+    }
+    if !RUNNING.load(Ordering::Acquire) {
+        return None;
+    }
+    Some(location.map_or(QemuExitCode::Failed, |location| {
+        QemuExitCode::for_file(location.file())
+    }))
+}
+
+/// Records serial output from creation until `finish` or drop.
+///
+/// Captures do not nest: starting one discards what an earlier, still
+/// live one recorded.
+#[must_use = "output is only recorded while the capture is alive"]
+pub struct Capture {
+    _private: (),
+}
+
+impl Capture {
+    /// Start recording serial output.
+    pub fn start() -> Self {
+        *CAPTURED.lock() = Some(String::new());
+        CAPTURING.store(true, Ordering::Release);
+        Self { _private: () }
+    }
+
+    /// Stop recording and return what was printed.
+    pub fn finish(self) -> String {
+        CAPTURING.store(false, Ordering::Release);
+        CAPTURED.lock().take().unwrap_or_default()
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        CAPTURING.store(false, Ordering::Release);
+        CAPTURED.lock().take();
+    }
+}
+
+/// Add `args` to the live capture, if any. Called by the serial driver
+/// for everything it prints.
+#[doc(hidden)]
+pub fn record(args: fmt::Arguments) {
+    if !CAPTURING.load(Ordering::Acquire) {
+        return;
+    }
+    // Formatting `args` may print in turn; skip rather than deadlock
+    if let Some(mut captured) = CAPTURED.try_lock() {
+        if let Some(text) = captured.as_mut() {
+            let _ = text.write_fmt(args);
+        }
+    }
+}
+
+/// Print `text` to serial, ending it with a newline if it lacks one, so
+/// the next marker starts a line of its own.
+pub fn print_section(text: &str) {
+    serial_print!("{}", text);
+    if !text.is_empty() && !text.ends_with('\n') {
+        serial_println!();
+    }
+}
+
+/// Panic unless `serial` (typically from `Capture::finish`) contains
+/// `needle`.
+#[track_caller]
+pub fn assert_serial_contains(serial: &str, needle: &str) {
+    assert!(
+        serial.contains(needle),
+        "serial output does not contain {:?}:\n{}",
+        needle,
+        serial
+    );
+}
+
 /// Custom test runner for bare-metal tests.
 ///
 /// Runs all tests and exits QEMU with success if all pass.
@@ -71,6 +293,7 @@ impl<T: Fn()> Testable for T {
 /// #![test_runner(sovelma_kernel::testutil::test_runner)]
 /// ```
 pub fn test_runner(tests: &[&dyn Testable]) {
+    start_run();
     serial_println!("Running {} tests", tests.len());
     for test in tests {
         test.run();
@@ -93,8 +316,8 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 /// }
 /// ```
 pub fn test_panic_handler(info: &core::panic::PanicInfo) -> ! {
-    serial_println!("[failed]");
+    let code = fail_current(info.location()).unwrap_or(QemuExitCode::Failed);
     serial_println!("Error: {}", info);
-    exit_qemu(QemuExitCode::Failed);
+    exit_qemu(code);
     crate::arch::x86_64::halt_loop()
 }
@@ -0,0 +1,158 @@
+//! End-to-end shell tests.
+//!
+//! Booting with `shelltest` on the kernel command line runs each of
+//! `SHELL_TESTS` through the shell once the kernel has settled, printing
+//! the command's output to serial between `TEST_BEGIN`/`TEST_END` markers,
+//! and then exits QEMU:
+//!
+//! ```text
+//! cd src/kernel && SOVELMA_CMDLINE="shelltest" cargo run -- \
+//!     -device isa-debug-exit,iobase=0xf4,iosize=0x04 -display none > serial.log
+//! cargo run -p sovelma-testharness -- serial.log
+//! ```
+//!
+//! The tests of the example apps are skipped unless the kernel was built
+//! with them (`scripts/apps.sh`).
+//!
+//! The kernel only checks that each output contains an expected string;
+//! the host-side harness compares the full output with the golden files in
+//! `src/testharness/golden`.
+
+use super::{
+    assert_serial_contains, begin, end, exit_qemu, print_section, start_run, Capture, QemuExitCode,
+};
+use crate::boot::cmdline;
+use crate::services::Shell;
+use crate::sync::TrackedMutex;
+use crate::terminal::{Command, Terminal};
+
+/// How long to let boot-time tasks (DHCP, the first stack polls) run
+/// before the first test, so their log lines come before it.
+const SETTLE_MS: u64 = 2000;
+
+/// How long the example apps get to run to completion; the slowest ticks
+/// ten times at 100 ms.
+const APP_WAIT_MS: u64 = 1500;
+
+/// One command run through the shell.
+#[derive(Debug, Clone, Copy)]
+pub struct ShellTest {
+    /// Test name in the markers; also the golden file's name.
+    pub name: &'static str,
+    /// Command line as typed at the prompt.
+    pub line: &'static str,
+    /// Text the output must contain.
+    pub expect: &'static str,
+    /// File the test needs; without it the test is skipped.
+    pub needs: Option<&'static str>,
+    /// How long to keep capturing after the command returns, for output
+    /// from the processes it started.
+    pub wait_ms: u64,
+}
+
+/// The shell tests, in the order they run.
+pub const SHELL_TESTS: &[ShellTest] = &[
+    ShellTest {
+        name: "shell.help",
+        line: "help",
+        expect: "SovelmaOS Shell Commands",
+        needs: None,
+        wait_ms: 0,
+    },
+    ShellTest {
+        name: "shell.echo",
+        line: "echo hello  world",
+        expect: "hello world\n",
+        needs: None,
+        wait_ms: 0,
+    },
+    ShellTest {
+        name: "shell.unknown",
+        line: "frobnicate --now",
+        expect: "Unknown command: frobnicate",
+        needs: None,
+        wait_ms: 0,
+    },
+    ShellTest {
+        name: "shell.ifconfig",
+        line: "ifconfig",
+        expect: "Network Configuration",
+        needs: None,
+        wait_ms: 0,
+    },
+    ShellTest {
+        name: "shell.ifconfig.json",
+        line: "ifconfig --json",
+        expect: "\"mac\"",
+        needs: None,
+        wait_ms: 0,
+    },
+    ShellTest {
+        name: "shell.app.counter",
+        line: "wasm run apps/counter.wasm",
+        expect: "counter: done",
+        needs: Some("apps/counter.wasm"),
+        wait_ms: APP_WAIT_MS,
+    },
+    ShellTest {
+        name: "shell.app.pingpong",
+        line: "wasm run apps/ping.wasm | wasm run apps/pong.wasm",
+        expect: "pong: done, 5 answered",
+        needs: Some("apps/pong.wasm"),
+        wait_ms: APP_WAIT_MS,
+    },
+    ShellTest {
+        name: "shell.app.cat",
+        line: "wasm run apps/ping.wasm | wasm run apps/cat.wasm",
+        expect: "ping 5",
+        needs: Some("apps/cat.wasm"),
+        wait_ms: APP_WAIT_MS,
+    },
+];
+
+/// Whether the kernel was booted to run the shell tests.
+pub fn enabled() -> bool {
+    cmdline::get("shelltest").is_some()
+}
+
+/// Run every shell test, then exit QEMU.
+///
+/// A failed check panics; the panic handler ends the test as failed and
+/// exits QEMU with the test's `QemuExitCode::Test` code.
+pub async fn run(shell: Shell) {
+    start_run();
+    crate::time::sleep_ms(SETTLE_MS).await;
+
+    let terminal = TrackedMutex::new("terminal", Terminal::new());
+    for test in SHELL_TESTS {
+        if test.needs.is_some_and(|path| !installed(path)) {
+            continue;
+        }
+        begin(test.name);
+        let capture = Capture::start();
+        if let Some(command) = Command::parse_line(test.line) {
+            if let Some(output) = shell.execute(command, &terminal).await {
+                print_section(&output.text());
+            }
+        }
+        if test.wait_ms > 0 {
+            crate::time::sleep_ms(test.wait_ms).await;
+        }
+        assert_serial_contains(&capture.finish(), test.expect);
+        end(test.name, true);
+    }
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// Whether `path` is in the root filesystem.
+fn installed(path: &str) -> bool {
+    use crate::fs::{FileSystem, ROOT_FS};
+
+    match ROOT_FS.open(path) {
+        Ok(handle) => {
+            ROOT_FS.close(handle);
+            true
+        }
+        Err(_) => false,
+    }
+}
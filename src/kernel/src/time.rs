@@ -0,0 +1,260 @@
+//! Kernel monotonic clock.
+//!
+//! A millisecond tick counter shared by the network stack, kernel timers
+//! and the WASM clock host functions. It starts at zero at boot and never
+//! goes backwards.
+//!
+//! The timer interrupt is the only tick source: each IRQ advances the clock
+//! by `TICK_MS` and wakes the tasks whose `sleep_ms` deadline has passed,
+//! which are kept in a hashed timer wheel.
+//!
+//! Wall-clock time is only known once something sets it (the `time`
+//! request of the control port, or an NTP client): `set_wall_clock` records
+//! the wall time at boot, and every later wall time is derived from the
+//! monotonic clock. A monotonic timestamp therefore maps to exactly one
+//! wall time, and log records carrying both stay consistent.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+
+/// Milliseconds represented by one tick.
+pub const TICK_MS: u64 = 1;
+
+/// Timer interrupt rate that gives one tick per `TICK_MS`.
+pub const TICK_HZ: u32 = (1000 / TICK_MS) as u32;
+
+/// Slots in the timer wheel (a power of two).
+pub const WHEEL_SLOTS: usize = 256;
+
+/// Nanoseconds per millisecond.
+const NS_PER_MS: u64 = 1_000_000;
+
+/// Nanoseconds per second.
+pub const NS_PER_SEC: u64 = 1_000_000_000;
+
+/// Ticks since boot.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Wall-clock time (ns since the Unix epoch) at boot; 0 until known.
+static BOOT_WALL_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Sleeping tasks, by deadline.
+static WHEEL: Mutex<TimerWheel> = Mutex::new(TimerWheel::new());
+
+/// Advance the clock by one tick and wake expired sleepers.
+///
+/// Called from the timer interrupt handler.
+pub fn tick() {
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    // A task is registering a sleeper; it will be woken on the next tick
+    if let Some(mut wheel) = WHEEL.try_lock() {
+        wheel.expire(ticks * TICK_MS);
+    }
+}
+
+/// Milliseconds since boot.
+pub fn now_ms() -> u64 {
+    TICKS.load(Ordering::Relaxed) * TICK_MS
+}
+
+/// Nanoseconds since boot, at the clock's `TICK_MS` resolution.
+pub fn now_ns() -> u64 {
+    now_ms() * NS_PER_MS
+}
+
+/// Set the wall clock: it is `unix_ns` nanoseconds since the Unix epoch
+/// now. Later corrections step it.
+pub fn set_wall_clock(unix_ns: u64) {
+    // 0 means unknown; nobody sets the clock to the first tick of 1970
+    BOOT_WALL_NS.store(unix_ns.saturating_sub(now_ns()).max(1), Ordering::Relaxed);
+}
+
+/// Wall-clock time at the monotonic time `mono_ns`, if the clock is set.
+pub fn wall_ns_at(mono_ns: u64) -> Option<u64> {
+    match BOOT_WALL_NS.load(Ordering::Relaxed) {
+        0 => None,
+        boot => Some(boot.saturating_add(mono_ns)),
+    }
+}
+
+/// Wall-clock time now, if the clock is set.
+pub fn wall_ns() -> Option<u64> {
+    wall_ns_at(now_ns())
+}
+
+/// A monotonic time (ns since boot), displayed dmesg style as seconds with
+/// microseconds, e.g. `   12.345000`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uptime(pub u64);
+
+impl fmt::Display for Uptime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>5}.{:06}",
+            self.0 / NS_PER_SEC,
+            self.0 % NS_PER_SEC / 1000
+        )
+    }
+}
+
+/// A wall-clock time (ns since the Unix epoch), displayed as an RFC 3339
+/// UTC timestamp with microseconds, e.g. `2026-10-16T12:00:00.000000Z`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WallTime(pub u64);
+
+impl fmt::Display for WallTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0 / NS_PER_SEC;
+        let micros = self.0 % NS_PER_SEC / 1000;
+        let (year, month, day) = civil_from_days(secs / 86_400);
+        let time = secs % 86_400;
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+            year,
+            month,
+            day,
+            time / 3600,
+            time % 3600 / 60,
+            time % 60,
+            micros
+        )
+    }
+}
+
+/// Year, month and day of the date `days` days after 1970-01-01.
+///
+/// Howard Hinnant's `civil_from_days`, for dates after the epoch.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Days since 0000-03-01, so leap days end each 400-year era
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Wait until `ms` milliseconds have passed.
+pub fn sleep_ms(ms: u64) -> Sleep {
+    Sleep {
+        deadline: now_ms().saturating_add(ms),
+        registered: false,
+    }
+}
+
+/// Future returned by `sleep_ms`.
+pub struct Sleep {
+    deadline: u64,
+    registered: bool,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if now_ms() >= self.deadline {
+            return Poll::Ready(());
+        }
+        if !self.registered {
+            let deadline = self.deadline;
+            // The timer interrupt takes the wheel lock too
+            x86_64::instructions::interrupts::without_interrupts(|| {
+                WHEEL.lock().insert(deadline, cx.waker().clone());
+            });
+            self.registered = true;
+        }
+        Poll::Pending
+    }
+}
+
+/// Hashed timer wheel of wakers.
+///
+/// Each deadline hashes to a slot by its low bits; a slot can hold entries
+/// for several revolutions, which stay until their own deadline passes.
+pub struct TimerWheel {
+    slots: [Vec<(u64, Waker)>; WHEEL_SLOTS],
+    /// Last time `expire` processed.
+    expired_until: u64,
+    len: usize,
+}
+
+impl TimerWheel {
+    /// Create an empty wheel.
+    pub const fn new() -> Self {
+        const EMPTY: Vec<(u64, Waker)> = Vec::new();
+        Self {
+            slots: [EMPTY; WHEEL_SLOTS],
+            expired_until: 0,
+            len: 0,
+        }
+    }
+
+    /// Wake `waker` once `expire` is called with a time at or after
+    /// `deadline`.
+    pub fn insert(&mut self, deadline: u64, waker: Waker) {
+        // Deadlines already processed go into the next slot to be checked
+        let deadline = deadline.max(self.expired_until + 1);
+        self.slots[slot(deadline)].push((deadline, waker));
+        self.len += 1;
+    }
+
+    /// Number of pending wakers.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check whether no wakers are pending.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Wake every entry whose deadline is at or before `now`. Returns the
+    /// number woken. Does not allocate, so it is safe in interrupt context.
+    pub fn expire(&mut self, now: u64) -> usize {
+        if now <= self.expired_until {
+            return 0;
+        }
+        // Beyond one revolution every slot is visited once
+        let steps = (now - self.expired_until).min(WHEEL_SLOTS as u64);
+        let mut woken = 0;
+        for step in 1..=steps {
+            let entries = &mut self.slots[slot(self.expired_until + step)];
+            let mut index = 0;
+            while index < entries.len() {
+                if entries[index].0 <= now {
+                    let (_, waker) = entries.swap_remove(index);
+                    waker.wake();
+                    woken += 1;
+                } else {
+                    index += 1;
+                }
+            }
+        }
+        self.expired_until = now;
+        self.len -= woken;
+        woken
+    }
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wheel slot of a deadline.
+fn slot(deadline: u64) -> usize {
+    deadline as usize & (WHEEL_SLOTS - 1)
+}
@@ -0,0 +1,245 @@
+//! Span tracing for latency analysis.
+//!
+//! While tracing is on, the executor records each task poll, the WASM host
+//! functions each host call and the interrupt handlers each interrupt as a
+//! span: what ran, when it started and for how many cycles. The most recent
+//! `TRACE_CAPACITY` spans are kept in a ring.
+//!
+//! `trace dump` writes the ring to COM1 as Chrome trace JSON, which
+//! `chrome://tracing` and Perfetto load directly. Task polls and the host
+//! calls inside them share a track; interrupts have their own. TSC cycles
+//! are converted with the rate measured against the kernel clock since
+//! tracing was turned on.
+//!
+//! Tracing starts at boot with `trace` on the kernel command line, or with
+//! `trace on`. While it is off a span costs one atomic load.
+
+use crate::arch::x86_64::rdtsc;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Spans kept; older ones are overwritten.
+pub const TRACE_CAPACITY: usize = 4096;
+
+/// Chrome trace track of task polls and host calls.
+const EXECUTOR_TRACK: u32 = 1;
+
+/// Chrome trace track of interrupts.
+const INTERRUPT_TRACK: u32 = 2;
+
+/// What a span covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    /// One poll of a task by the executor; the argument is the task ID.
+    Task,
+    /// One WASM host function call.
+    HostCall,
+    /// One interrupt handler run; the argument is the IRQ line.
+    Interrupt,
+}
+
+impl Category {
+    /// Category name in the trace.
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Task => "task",
+            Category::HostCall => "hostcall",
+            Category::Interrupt => "irq",
+        }
+    }
+
+    fn track(self) -> u32 {
+        match self {
+            Category::Task | Category::HostCall => EXECUTOR_TRACK,
+            Category::Interrupt => INTERRUPT_TRACK,
+        }
+    }
+}
+
+/// A finished span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// What the span covers.
+    pub category: Category,
+    /// Task, host function or interrupt name.
+    pub name: &'static str,
+    /// Task ID or IRQ line; unused for host calls.
+    pub arg: u64,
+    /// TSC when the span was entered.
+    pub start: u64,
+    /// Cycles until it was exited.
+    pub cycles: u64,
+}
+
+/// An entered span; recorded when dropped.
+#[must_use = "the span ends when the guard is dropped"]
+pub struct SpanGuard {
+    category: Category,
+    name: &'static str,
+    arg: u64,
+    /// Entry TSC; `None` if tracing was off.
+    start: Option<u64>,
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            record(Span {
+                category: self.category,
+                name: self.name,
+                arg: self.arg,
+                start,
+                cycles: rdtsc().saturating_sub(start),
+            });
+        }
+    }
+}
+
+/// Whether spans are recorded.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Recorded spans, oldest first. Only locked with interrupts disabled, as
+/// interrupt handlers record too.
+static RING: Mutex<VecDeque<Span>> = Mutex::new(VecDeque::new());
+
+/// Spans overwritten since tracing was turned on.
+static OVERWRITTEN: AtomicU64 = AtomicU64::new(0);
+
+/// TSC and kernel clock when tracing was turned on.
+static START_TSC: AtomicU64 = AtomicU64::new(0);
+static START_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Turn tracing on if the kernel command line has `trace`.
+///
+/// Returns whether tracing is on.
+pub fn init() -> bool {
+    let requested = crate::boot::cmdline::get("trace").is_some();
+    if requested {
+        enable();
+    }
+    requested
+}
+
+/// Enter a span, which ends when the returned guard is dropped.
+pub fn span(category: Category, name: &'static str, arg: u64) -> SpanGuard {
+    SpanGuard {
+        category,
+        name,
+        arg,
+        start: ENABLED.load(Ordering::Relaxed).then(rdtsc),
+    }
+}
+
+/// Start recording into an empty ring.
+pub fn enable() {
+    interrupts::without_interrupts(|| {
+        let mut ring = RING.lock();
+        ring.clear();
+        // Allocate now: interrupt handlers must not grow the ring
+        ring.reserve(TRACE_CAPACITY);
+    });
+    OVERWRITTEN.store(0, Ordering::Relaxed);
+    START_TSC.store(rdtsc(), Ordering::Relaxed);
+    START_MS.store(crate::time::now_ms(), Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Stop recording; the ring is kept for `dump`.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// Whether spans are being recorded.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Spans overwritten because the ring was full.
+pub fn overwritten() -> u64 {
+    OVERWRITTEN.load(Ordering::Relaxed)
+}
+
+/// Copy of the recorded spans, oldest first.
+pub fn spans() -> Vec<Span> {
+    interrupts::without_interrupts(|| RING.lock().iter().copied().collect())
+}
+
+fn record(span: Span) {
+    interrupts::without_interrupts(|| {
+        let mut ring = RING.lock();
+        // Spans ending after `disable` still fit: the capacity is kept
+        if ring.len() >= TRACE_CAPACITY.min(ring.capacity()) {
+            if ring.pop_front().is_none() {
+                return;
+            }
+            OVERWRITTEN.fetch_add(1, Ordering::Relaxed);
+        }
+        ring.push_back(span);
+    });
+}
+
+/// TSC cycles per microsecond since tracing was turned on; `None` until a
+/// clock tick has passed.
+pub fn cycles_per_us() -> Option<u64> {
+    let ms = crate::time::now_ms().saturating_sub(START_MS.load(Ordering::Relaxed));
+    let cycles = rdtsc().saturating_sub(START_TSC.load(Ordering::Relaxed));
+    (ms > 0).then(|| (cycles / (ms * 1000)).max(1))
+}
+
+/// Write `cycles` as microseconds with three decimals.
+fn write_us(out: &mut String, cycles: u64, cycles_per_us: u64) {
+    let ns = u128::from(cycles) * 1000 / u128::from(cycles_per_us.max(1));
+    let _ = write!(out, "{}.{:03}", ns / 1000, ns % 1000);
+}
+
+/// One span as a Chrome trace "complete" event, timed from `base` (TSC).
+pub fn chrome_event(span: &Span, base: u64, cycles_per_us: u64) -> String {
+    let mut out = String::from("{\"name\":\"");
+    let _ = match span.category {
+        Category::Task => write!(out, "task {}", span.arg),
+        _ => write!(out, "{}", span.name),
+    };
+    let _ = write!(
+        out,
+        "\",\"cat\":\"{}\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":",
+        span.category.name(),
+        span.category.track()
+    );
+    write_us(&mut out, span.start.saturating_sub(base), cycles_per_us);
+    out.push_str(",\"dur\":");
+    write_us(&mut out, span.cycles, cycles_per_us);
+    if span.category == Category::Interrupt {
+        let _ = write!(out, ",\"args\":{{\"irq\":{}}}", span.arg);
+    }
+    out.push('}');
+    out
+}
+
+/// Write the recorded spans to COM1 as a Chrome trace JSON document.
+///
+/// Returns the number of spans written.
+pub fn dump_chrome() -> usize {
+    let spans = spans();
+    let cycles_per_us = cycles_per_us().unwrap_or(1);
+    // Spans are kept in the order they ended; an outer one started first
+    let base = spans.iter().map(|span| span.start).min().unwrap_or(0);
+    crate::serial_println!("{{\"displayTimeUnit\":\"ns\",\"traceEvents\":[");
+    crate::serial_println!(
+        "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":\"executor\"}}}},",
+        EXECUTOR_TRACK
+    );
+    crate::serial_println!(
+        "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":\"interrupts\"}}}}",
+        INTERRUPT_TRACK
+    );
+    for span in &spans {
+        crate::serial_println!(",{}", chrome_event(span, base, cycles_per_us));
+    }
+    crate::serial_println!("]}}");
+    spans.len()
+}
@@ -0,0 +1,94 @@
+//! Batched host calls.
+//!
+//! Every host call crosses the wasmi boundary, checks fuel and looks up the
+//! process's memory export. `sp_batch` runs a vector of small FS and socket
+//! operations in one call instead: the records are read and every buffer is
+//! bounds-checked in one pass, fuel is charged once for the whole batch, and
+//! the results are written back into the records.
+//!
+//! A batch stops early when the slice's fuel would run out; the call
+//! returns how many operations ran and the caller submits the rest after
+//! yielding.
+
+/// Size of one operation record passed to `sp_batch`.
+///
+/// Layout (little endian): `op: u32`, `len: u32`, `cap: i64`, `ptr: u32`,
+/// `offset: u32`, `result: i32` (written by the kernel), reserved `u32`.
+pub const BATCH_RECORD_SIZE: usize = 32;
+
+/// Most operations one `sp_batch` call takes.
+pub const BATCH_MAX: usize = 64;
+
+/// Offset of the result field in a record.
+const RESULT_OFFSET: usize = 24;
+
+/// Operation codes as seen by WASM code.
+pub mod op {
+    /// `sp_fs_read`: read `len` bytes at `offset` into `ptr`.
+    pub const FS_READ: u32 = 1;
+    /// `sp_fs_write`: write `len` bytes from `ptr` at `offset`.
+    pub const FS_WRITE: u32 = 2;
+    /// `sp_net_send`: send `len` bytes from `ptr`.
+    pub const NET_SEND: u32 = 3;
+    /// `sp_net_recv`: receive up to `len` bytes into `ptr`.
+    pub const NET_RECV: u32 = 4;
+}
+
+/// One decoded operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchOp {
+    /// Operation code (see `op`).
+    pub op: u32,
+    /// File or Socket capability.
+    pub cap: i64,
+    /// Buffer address in WASM memory.
+    pub ptr: u32,
+    /// Buffer length.
+    pub len: u32,
+    /// File offset (FS operations only).
+    pub offset: u32,
+}
+
+impl BatchOp {
+    /// Decode a `BATCH_RECORD_SIZE` record; `None` for an unknown opcode.
+    pub fn decode(record: &[u8; BATCH_RECORD_SIZE]) -> Option<Self> {
+        let u32_at = |at: usize| {
+            u32::from_le_bytes([record[at], record[at + 1], record[at + 2], record[at + 3]])
+        };
+        let mut cap = [0u8; 8];
+        cap.copy_from_slice(&record[8..16]);
+        let batch_op = Self {
+            op: u32_at(0),
+            len: u32_at(4),
+            cap: i64::from_le_bytes(cap),
+            ptr: u32_at(16),
+            offset: u32_at(20),
+        };
+        (op::FS_READ..=op::NET_RECV)
+            .contains(&batch_op.op)
+            .then_some(batch_op)
+    }
+
+    /// Encode the operation as a record with a zero result.
+    pub fn encode(&self) -> [u8; BATCH_RECORD_SIZE] {
+        let mut record = [0u8; BATCH_RECORD_SIZE];
+        record[0..4].copy_from_slice(&self.op.to_le_bytes());
+        record[4..8].copy_from_slice(&self.len.to_le_bytes());
+        record[8..16].copy_from_slice(&self.cap.to_le_bytes());
+        record[16..20].copy_from_slice(&self.ptr.to_le_bytes());
+        record[20..24].copy_from_slice(&self.offset.to_le_bytes());
+        record
+    }
+
+    /// Buffer range in WASM memory, if it lies within `memory_size` bytes.
+    pub fn buffer(&self, memory_size: usize) -> Option<core::ops::Range<usize>> {
+        let start = self.ptr as usize;
+        let end = start.checked_add(self.len as usize)?;
+        (end <= memory_size).then_some(start..end)
+    }
+}
+
+/// Store `result` in the record at `record`.
+pub fn set_result(record: &mut [u8], result: i32) {
+    record[RESULT_OFFSET..RESULT_OFFSET + 4].copy_from_slice(&result.to_le_bytes());
+}
@@ -0,0 +1,1208 @@
+//! WASM shell commands.
+
+use super::process::{self, Pid, ProcessManager, Signal};
+use super::{LoadError, WasmProcess};
+use crate::terminal::commands::{parse_args, usage_error, ArgError, Opt, Spec};
+use crate::terminal::json::Json;
+use crate::terminal::line;
+use crate::terminal::registry::{self, Builtin};
+use crate::terminal::theme::{self, Role};
+use crate::terminal::{print_error, CommandContext};
+use crate::{print, println};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use sovelma_common::capability::Label;
+use sovelma_common::error::{Context, Error};
+use sovelma_common::rate::RateLimit;
+
+/// Export run by `wasm run`.
+const WASM_ENTRY: &str = "_start";
+
+/// Arguments of one `wasm run`.
+const RUN_ARGS: Spec = Spec {
+    name: "wasm run",
+    options: &[
+        Opt::value("cpu-ms", "ms"),
+        Opt::value("serial", "[label@]n"),
+        Opt::flag("config"),
+        Opt::value("net", "[label@]rights"),
+        Opt::value("dir", "[label@]path"),
+        Opt::value("mount", "path=dir[:ro]"),
+        Opt::value("tmp", "bytes"),
+        Opt::value("rate", "label=ops[/burst]"),
+    ],
+    positional: &["<file>"],
+};
+
+/// Arguments of `restore`.
+const RESTORE_ARGS: Spec = Spec {
+    name: "restore",
+    options: &[Opt::value("grant", "capability")],
+    positional: &["<file>"],
+};
+
+/// Arguments of `grant`.
+const GRANT_ARGS: Spec = Spec {
+    name: "grant",
+    options: &[
+        Opt::value("label", "name"),
+        Opt::value("rate", "ops[/burst]"),
+    ],
+    positional: &["<pid>", "<capability>"],
+};
+
+/// Arguments of `peek`.
+const PEEK_ARGS: Spec = Spec {
+    name: "peek",
+    options: &[],
+    positional: &["<pid>", "<addr>", "<len>"],
+};
+
+/// Most bytes `peek` shows at once.
+const PEEK_MAX: u32 = 4096;
+
+/// Arguments of `resume`.
+const RESUME_ARGS: Spec = Spec {
+    name: "resume",
+    options: &[],
+    positional: &["<pid>"],
+};
+
+/// Arguments of `step`.
+const STEP_ARGS: Spec = Spec {
+    name: "step",
+    options: &[],
+    positional: &["<pid>", "<fuel>"],
+};
+
+/// Arguments of `inspect`.
+const INSPECT_ARGS: Spec = Spec {
+    name: "inspect",
+    options: &[],
+    positional: &["<pid>"],
+};
+
+/// Commands registered by the WASM subsystem.
+const COMMANDS: [Builtin; 11] = [
+    Builtin {
+        name: "wasm",
+        aliases: &["wasm-test"],
+        usage: "[file] | run [--cpu-ms <ms>] [--serial <[label@]n>] [--config] [--net <[label@]rights>] [--dir <[label@]path>] [--mount <path=dir[:ro]>] [--tmp <bytes>] [--rate <label=ops[/burst]>] <file> [| wasm run ...] | lib ...",
+        help: "Test or start a module; manage shared libraries",
+        host_arg: Builtin::no_host,
+        run: cmd_wasm,
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "ps",
+        aliases: &[],
+        usage: "",
+        help: "List WASM processes and their CPU time",
+        host_arg: Builtin::no_host,
+        run: |ctx, _| cmd_ps(ctx.processes),
+        json: |ctx, _| Some(json_ps(ctx.processes)),
+    },
+    Builtin {
+        name: "apps",
+        aliases: &[],
+        usage: "",
+        help: "List installed WASM modules",
+        host_arg: Builtin::no_host,
+        run: |_, _| cmd_apps(),
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "kill",
+        aliases: &[],
+        usage: "<pid> [sig]",
+        help: "Signal a WASM process (default TERM)",
+        host_arg: Builtin::no_host,
+        run: |_, args| cmd_kill(args),
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "snapshot",
+        aliases: &[],
+        usage: "<pid> [file]",
+        help: "Save a WASM process's state",
+        host_arg: Builtin::no_host,
+        run: |ctx, args| cmd_snapshot(args, ctx.processes),
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "restore",
+        aliases: &[],
+        usage: "[--grant <capability>]... <file>",
+        help: "Start a WASM process from a snapshot",
+        host_arg: Builtin::no_host,
+        run: |ctx, args| cmd_restore(args, ctx.processes),
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "grant",
+        aliases: &[],
+        usage: "[--label <name>] [--rate <ops[/burst]>] <pid> <capability>",
+        help: "Give a running WASM process a capability",
+        host_arg: Builtin::no_host,
+        run: |ctx, args| cmd_grant(args, ctx.processes),
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "peek",
+        aliases: &[],
+        usage: "<pid> <addr> <len>",
+        help: "Dump a WASM process's memory",
+        host_arg: Builtin::no_host,
+        run: |ctx, args| cmd_peek(args, ctx.processes),
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "inspect",
+        aliases: &[],
+        usage: "<pid>",
+        help: "Show a WASM process's globals and capabilities",
+        host_arg: Builtin::no_host,
+        run: |ctx, args| cmd_inspect(args, ctx.processes),
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "resume",
+        aliases: &[],
+        usage: "<pid>",
+        help: "Continue a stopped WASM process",
+        host_arg: Builtin::no_host,
+        run: |ctx, args| cmd_resume(args, ctx.processes),
+        json: Builtin::no_json,
+    },
+    Builtin {
+        name: "step",
+        aliases: &[],
+        usage: "<pid> <fuel>",
+        help: "Run a stopped WASM process for about <fuel> fuel",
+        host_arg: Builtin::no_host,
+        run: |ctx, args| cmd_step(args, ctx.processes),
+        json: Builtin::no_json,
+    },
+];
+
+/// Register the WASM commands with the shell.
+pub fn register() {
+    registry::register_builtins(&COMMANDS);
+}
+
+/// Running WASM processes as JSON.
+fn json_ps(processes: &ProcessManager) -> Json {
+    let list = processes
+        .list()
+        .into_iter()
+        .map(|info| {
+            Json::object()
+                .with("pid", info.pid)
+                .with("name", info.name)
+                .with("cpu_ms", info.cpu_ms)
+                .with(
+                    "cpu_limit_ms",
+                    info.cpu_limit_ms.map_or(Json::Null, Json::from),
+                )
+                .with("terminating", info.terminating)
+                .with("stopped", info.stopped)
+        })
+        .collect();
+    Json::object()
+        .with("fuel_per_ms", processes.calibration().fuel_per_ms())
+        .with("processes", Json::Array(list))
+}
+
+/// Dispatch the `wasm` sub-commands.
+fn cmd_wasm(ctx: &mut CommandContext, args: &[&str]) {
+    match args.first().copied() {
+        Some("run") => cmd_wasm_run(&args[1..], ctx.processes),
+        Some("lib") => cmd_wasm_lib(&args[1..], ctx.processes),
+        file => cmd_wasm_test(file.unwrap_or("hello.wasm"), ctx.processes),
+    }
+}
+
+/// Read a whole file from the root filesystem, reporting failures as
+/// `command`'s.
+fn read_file(command: &str, filename: &str) -> Option<Vec<u8>> {
+    crate::fs::read_file(filename)
+        .map_err(|e| print_error(command, &e))
+        .ok()
+}
+
+/// Open `path` as a directory to grant to a process, reporting failures
+/// as `command`'s.
+fn open_dir(command: &str, path: &str) -> Option<crate::fs::FileHandle> {
+    use crate::fs::{FileSystem, ROOT_FS};
+
+    let error = |message: &dyn core::fmt::Display| {
+        theme::set(Role::Error);
+        println!("{}: {}: {}", command, path, message);
+        theme::reset();
+    };
+    let handle = match ROOT_FS.open(path) {
+        Ok(h) => h,
+        Err(e) => {
+            error(&e);
+            return None;
+        }
+    };
+    if !ROOT_FS.is_dir(handle) {
+        ROOT_FS.close(handle);
+        error(&"not a directory");
+        return None;
+    }
+    Some(handle)
+}
+
+/// A `--mount` of `wasm run`: `<path>=<dir>` binds the directory `dir` at
+/// `path` in the process's namespace, read-only with `:ro` after it.
+struct Mount<'a> {
+    path: &'a str,
+    dir: &'a str,
+    read_only: bool,
+}
+
+impl<'a> Mount<'a> {
+    fn parse(spec: &'a str) -> Option<Self> {
+        let (path, dir) = spec.split_once('=')?;
+        let (dir, read_only) = match dir.strip_suffix(":ro") {
+            Some(dir) => (dir, true),
+            None => (dir, false),
+        };
+        (!path.is_empty() && !dir.is_empty()).then_some(Self {
+            path,
+            dir,
+            read_only,
+        })
+    }
+}
+
+/// Where `wasm run --tmp` mounts the process's tmpfs.
+const TMP_PATH: &str = "/tmp";
+
+/// Build a namespace holding `mounts` and, given a quota, a tmpfs at
+/// `TMP_PATH`, to grant to a process instead of a single directory; see
+/// `RamFs::mount` and `RamFs::mount_tmpfs`.
+fn open_namespace(mounts: &[Mount], tmp: Option<usize>) -> Option<crate::fs::FileHandle> {
+    use crate::fs::{FileSystem, ROOT_FS};
+
+    let namespace = ROOT_FS.namespace();
+    if let Some(limit) = tmp {
+        // The namespace is empty, so this cannot fail
+        let _ = ROOT_FS.mount_tmpfs(namespace, TMP_PATH, limit);
+    }
+    for mount in mounts {
+        let Some(dir) = open_dir("wasm run", mount.dir) else {
+            ROOT_FS.close(namespace);
+            return None;
+        };
+        // The namespace keeps what is mounted, so `dir` is not needed after
+        let result = ROOT_FS.mount(namespace, mount.path, dir, mount.read_only);
+        ROOT_FS.close(dir);
+        if let Err(e) = result {
+            theme::set(Role::Error);
+            println!(
+                "wasm run: cannot mount {} at {}: {}",
+                mount.dir, mount.path, e
+            );
+            theme::reset();
+            ROOT_FS.close(namespace);
+            return None;
+        }
+    }
+    Some(namespace)
+}
+
+/// Run a simple WASM module test in the background.
+///
+/// The module gets a read-only capability for the root directory and runs
+/// as its own task, so the shell and the network keep running; its output
+/// is printed as it is written.
+fn cmd_wasm_test(filename: &str, processes: &ProcessManager) {
+    use crate::fs::{FileSystem, ROOT_FS, ROOT_PATH};
+    use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType};
+
+    println!();
+    theme::set(Role::Accent);
+    println!("WASM Runtime Test executing '{}'", filename);
+    println!("-----------------");
+    theme::reset();
+
+    let root = match ROOT_FS.open(ROOT_PATH) {
+        Ok(root) => root,
+        Err(e) => {
+            print_error("wasm", &format_args!("{}: {}", ROOT_PATH, e));
+            return;
+        }
+    };
+    let granted = alloc::vec![Capability::new(
+        CapabilityType::Directory(u64::from(root.0)),
+        CapabilityRights::READ,
+    )];
+
+    match processes.engine().spawn_file(filename, granted) {
+        Ok(process) => {
+            theme::set(Role::Success);
+            println!("WASM process spawned successfully!");
+            theme::reset();
+
+            println!("Executing {} in the background...", WASM_ENTRY);
+            process.spawn_task(WASM_ENTRY);
+        }
+        Err(e) => {
+            ROOT_FS.close(root);
+            print_error("wasm", &e);
+        }
+    }
+    println!();
+}
+
+/// A process `wasm run` has loaded but not started yet.
+struct Prepared {
+    name: String,
+    process: WasmProcess,
+    entry: String,
+    cpu_limit_ms: Option<u64>,
+}
+
+/// Start WASM modules as background processes:
+/// `wasm run <args> [| wasm run <args> ...]`.
+///
+/// With `|`, the stdout of each process is piped to the stdin of the next
+/// (see `ProcessManager::spawn_pipeline`). No process is started unless
+/// all of them load.
+fn cmd_wasm_run(args: &[&str], processes: &mut ProcessManager) {
+    let mut stages = Vec::new();
+    for (i, segment) in line::stages(args).enumerate() {
+        let segment = match segment {
+            _ if i == 0 => segment,
+            ["wasm", "run", rest @ ..] => rest,
+            _ => {
+                println!("Usage: {} | wasm run ...", RUN_ARGS.usage());
+                return;
+            }
+        };
+        let Some(prepared) = prepare_run(segment, processes) else {
+            return;
+        };
+        stages.push(prepared);
+    }
+
+    let limits: Vec<Option<u64>> = stages.iter().map(|stage| stage.cpu_limit_ms).collect();
+    let names: Vec<String> = stages.iter().map(|stage| stage.name.clone()).collect();
+    let pids = match stages.len() {
+        1 => stages
+            .into_iter()
+            .map(|stage| processes.spawn(&stage.name, stage.process, &stage.entry))
+            .collect(),
+        _ => processes.spawn_pipeline(
+            stages
+                .into_iter()
+                .map(|stage| (stage.name, stage.process, stage.entry))
+                .collect(),
+        ),
+    };
+    for ((pid, limit), name) in pids.into_iter().zip(limits).zip(names) {
+        processes.set_cpu_limit(pid, limit);
+        println!("[{}] {}", pid, name);
+    }
+}
+
+/// Parse the `--net` rights of `wasm run`: a comma-separated list of
+/// `connect`, `raw` and `listen=<port>[-<port>]`, e.g.
+/// `connect,listen=8000-8099`. Without `listen` the capability covers no
+/// ports.
+pub fn parse_net_grant(spec: &str) -> Option<sovelma_common::capability::Capability> {
+    use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType};
+
+    let mut rights = CapabilityRights::empty();
+    let (mut first_port, mut last_port) = (0, 0);
+    for item in spec.split(',') {
+        match item.split_once('=') {
+            None if item == "connect" => rights |= CapabilityRights::CONNECT,
+            None if item == "raw" => rights |= CapabilityRights::RAW,
+            Some(("listen", ports)) => {
+                let (first, last) = ports.split_once('-').unwrap_or((ports, ports));
+                first_port = first.parse().ok()?;
+                last_port = last.parse().ok()?;
+                if first_port > last_port {
+                    return None;
+                }
+                rights |= CapabilityRights::LISTEN;
+            }
+            _ => return None,
+        }
+    }
+    Some(Capability::new(
+        CapabilityType::Network {
+            first_port,
+            last_port,
+        },
+        rights,
+    ))
+}
+
+/// Read/write access to COM`com`, if it is enabled.
+fn serial_capability(
+    com: u8,
+) -> Result<sovelma_common::capability::Capability, crate::arch::x86_64::serial::SerialError> {
+    use crate::arch::x86_64::serial::{self, SerialError};
+    use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType};
+
+    let port = serial::port_base(com)?;
+    if !serial::is_open(com) {
+        return Err(SerialError::NotOpen(com));
+    }
+    Ok(Capability::new(
+        CapabilityType::Serial { port },
+        CapabilityRights::READ | CapabilityRights::WRITE,
+    ))
+}
+
+/// Split the label off a `wasm run` grant such as `web@listen=80`: the
+/// label, if there is one, and the rest.
+///
+/// Returns `None` if what comes before `@` is not a valid `Label`.
+fn split_label(value: &str) -> Option<(Option<Label>, &str)> {
+    match value.split_once('@') {
+        Some((label, rest)) => Some((Some(Label::new(label)?), rest)),
+        None => Some((None, value)),
+    }
+}
+
+/// Load one module for `wasm run [--cpu-ms <ms>] [--serial <[label@]n>]
+/// [--config] [--net <[label@]rights>] [--dir <[label@]path>]
+/// [--mount <path=dir[:ro]>]... [--tmp <bytes>] [--rate <label=ops[/burst]>]...
+/// <file>`, reporting failures.
+///
+/// The process is granted the Timer capability, so it can use the clock,
+/// timers and `sp_poll`, with `--serial` read/write access to an enabled
+/// port and with `--config` read/write access to the system configuration. A module whose manifest requires more is refused; one
+/// without a manifest is started at `_start`. `--net` grants a Network
+/// capability with the rights listed (see `parse_net_grant`), and `--dir`
+/// read-only access to a directory. `--mount` instead grants read/write
+/// access to a namespace of its own holding the directories mounted (see
+/// `Mount`), writable unless mounted `:ro`, and `--tmp` adds a private
+/// `/tmp` to it whose files may take that many bytes; it is freed when the
+/// process exits. The directory is granted last. `--serial`, `--net` and
+/// `--dir` may name what they grant with a `label@` prefix, which the
+/// process sees in `sp_describe_capabilities`, and `--rate` limits how
+/// often the capability so labelled may be used (see `RateLimit::parse`).
+fn prepare_run(args: &[&str], processes: &ProcessManager) -> Option<Prepared> {
+    use super::manifest::Manifest;
+    use crate::fs::{FileSystem, ROOT_FS};
+    use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType};
+
+    let args = parse_args(&RUN_ARGS, args)?;
+    let mut dir = None;
+    let mut dir_label = None;
+    let mut mounts = Vec::new();
+    let mut tmp = None;
+    let mut cpu_limit_ms = None;
+    let mut rates = Vec::new();
+    let mut granted = alloc::vec![Capability::new(
+        CapabilityType::Timer,
+        CapabilityRights::READ | CapabilityRights::CALL,
+    )];
+    for (option, value) in args.options() {
+        let value = value.unwrap_or_default();
+        let (label, value) = match option {
+            "serial" | "net" | "dir" => match split_label(value) {
+                Some(split) => split,
+                None => {
+                    usage_error(&RUN_ARGS, &ArgError::Invalid(option, value));
+                    return None;
+                }
+            },
+            _ => (None, value),
+        };
+        match option {
+            "cpu-ms" => match value.parse::<u64>() {
+                Ok(ms) => cpu_limit_ms = Some(ms),
+                Err(_) => {
+                    usage_error(&RUN_ARGS, &ArgError::Invalid(option, value));
+                    return None;
+                }
+            },
+            "serial" => {
+                let Ok(com) = value.parse::<u8>() else {
+                    usage_error(&RUN_ARGS, &ArgError::Invalid(option, value));
+                    return None;
+                };
+                match serial_capability(com) {
+                    Ok(cap) => granted.push(Capability { label, ..cap }),
+                    Err(e) => {
+                        theme::set(Role::Error);
+                        println!("wasm run: {}", e);
+                        theme::reset();
+                        return None;
+                    }
+                }
+            }
+            "net" => match parse_net_grant(value) {
+                Some(cap) => granted.push(Capability { label, ..cap }),
+                None => {
+                    usage_error(&RUN_ARGS, &ArgError::Invalid(option, value));
+                    return None;
+                }
+            },
+            "dir" => {
+                dir = Some(value);
+                dir_label = label;
+            }
+            "tmp" => match value.parse::<usize>() {
+                Ok(bytes) => tmp = Some(bytes),
+                Err(_) => {
+                    usage_error(&RUN_ARGS, &ArgError::Invalid(option, value));
+                    return None;
+                }
+            },
+            "mount" => match Mount::parse(value) {
+                Some(mount) => mounts.push(mount),
+                None => {
+                    usage_error(&RUN_ARGS, &ArgError::Invalid(option, value));
+                    return None;
+                }
+            },
+            "rate" => match value
+                .split_once('=')
+                .and_then(|(label, limit)| Some((label, RateLimit::parse(limit)?)))
+            {
+                Some(rate) => rates.push(rate),
+                None => {
+                    usage_error(&RUN_ARGS, &ArgError::Invalid(option, value));
+                    return None;
+                }
+            },
+            // `--config`, the only other option in `RUN_ARGS`
+            _ => granted.push(Capability::new(
+                CapabilityType::Config,
+                CapabilityRights::READ | CapabilityRights::WRITE,
+            )),
+        }
+    }
+    let filename = args.get(0)?;
+    let labels: Vec<&Label> = granted
+        .iter()
+        .filter_map(|cap| cap.label.as_ref())
+        .chain(dir_label.as_ref())
+        .collect();
+    if let Some((label, _)) = rates
+        .iter()
+        .find(|(label, _)| !labels.iter().any(|known| known.as_str() == *label))
+    {
+        print_error(
+            "wasm run",
+            &format_args!("--rate: no capability labelled {}", label),
+        );
+        return None;
+    }
+    let namespace = !mounts.is_empty() || tmp.is_some();
+    if dir.is_some() && namespace {
+        print_error(
+            "wasm run",
+            &"--dir cannot be combined with --mount or --tmp",
+        );
+        return None;
+    }
+
+    let buffer = read_file("wasm run", filename)?;
+
+    // The process owns the directory handle once it is spawned; if the
+    // module fails to load, it is closed again
+    let dir = match dir {
+        Some(path) => {
+            Some(open_dir("wasm run", path).map(|handle| (handle, CapabilityRights::READ)))
+        }
+        None if !namespace => None,
+        None => Some(
+            open_namespace(&mounts, tmp)
+                .map(|handle| (handle, CapabilityRights::READ | CapabilityRights::WRITE)),
+        ),
+    };
+    let dir = match dir {
+        Some(Some((handle, rights))) => {
+            granted.push(Capability {
+                label: dir_label,
+                ..Capability::new(CapabilityType::Directory(u64::from(handle.0)), rights)
+            });
+            Some(handle)
+        }
+        Some(None) => return None,
+        None => None,
+    };
+    let release_dir = || {
+        if let Some(handle) = dir {
+            ROOT_FS.close(handle);
+        }
+    };
+    for cap in &mut granted {
+        let label = cap.label.as_ref().map(Label::as_str);
+        if let Some((_, limit)) = rates.iter().rev().find(|(name, _)| Some(*name) == label) {
+            cap.rate_limit = Some(*limit);
+        }
+    }
+
+    let entry = match Manifest::from_module(&buffer) {
+        Ok(Some(manifest)) => {
+            let missing = manifest.missing_capabilities(&granted);
+            if !missing.is_empty() {
+                theme::set(Role::Error);
+                println!(
+                    "{} requires capabilities not granted: {}",
+                    manifest.name,
+                    missing.join(", ")
+                );
+                theme::reset();
+                release_dir();
+                return None;
+            }
+            manifest.entry
+        }
+        Ok(None) => String::from(WASM_ENTRY),
+        Err(e) => {
+            print_error(
+                "wasm run",
+                &Error::new(e).context("reading the manifest of", filename),
+            );
+            release_dir();
+            return None;
+        }
+    };
+
+    match processes
+        .engine()
+        .spawn_process_with_caps(&buffer, granted)
+        .map_err(LoadError::from)
+        .context("loading", filename)
+    {
+        Ok(process) => Some(Prepared {
+            name: String::from(filename),
+            process,
+            entry,
+            cpu_limit_ms,
+        }),
+        Err(e) => {
+            print_error("wasm run", &e);
+            release_dir();
+            None
+        }
+    }
+}
+
+/// List running WASM processes.
+fn cmd_ps(processes: &ProcessManager) {
+    let list = processes.list();
+    if list.is_empty() {
+        println!("No WASM processes");
+        return;
+    }
+    println!("  {:>5}  {:>8}  {:>8}  NAME", "PID", "CPU(ms)", "LIMIT");
+    for info in list {
+        let limit = info
+            .cpu_limit_ms
+            .map(|limit| limit.to_string())
+            .unwrap_or_else(|| String::from("-"));
+        let state = if info.terminating {
+            " (terminating)"
+        } else if info.stopped {
+            " (stopped)"
+        } else {
+            ""
+        };
+        println!(
+            "  {:>5}  {:>8}  {:>8}  {}{}",
+            info.pid, info.cpu_ms, limit, info.name, state
+        );
+    }
+}
+
+/// List the WASM modules in the filesystem with their manifests.
+fn cmd_apps() {
+    use super::manifest::Manifest;
+
+    println!();
+    theme::set(Role::Accent);
+    println!("Installed Modules");
+    println!("-----------------");
+    theme::reset();
+
+    let modules: Vec<String> = crate::fs::ROOT_FS
+        .files()
+        .into_iter()
+        .filter(|path| path.ends_with(".wasm"))
+        .collect();
+    if modules.is_empty() {
+        println!("  (none)");
+    }
+    for path in modules {
+        let Some(buffer) = read_file("apps", &path) else {
+            continue;
+        };
+        match Manifest::from_module(&buffer) {
+            Ok(Some(manifest)) => {
+                println!("  {:<24} {} {}", path, manifest.name, manifest.version);
+                if !manifest.capabilities.is_empty() {
+                    println!("  {:<24} needs {}", "", manifest.capabilities.join(", "));
+                }
+            }
+            Ok(None) => println!("  {:<24} (no manifest)", path),
+            Err(e) => println!("  {:<24} ({})", path, e),
+        }
+    }
+    println!();
+}
+
+/// Manage shared WASM libraries:
+/// `wasm lib [list] | load <file> [name] | unload <name>`.
+fn cmd_wasm_lib(args: &[&str], processes: &ProcessManager) {
+    let engine = processes.engine();
+    let result = match (args.first().copied(), args.get(1).copied(), args.get(2)) {
+        (Some("load"), Some(file), name) => {
+            let Some(buffer) = read_file("wasm lib", file) else {
+                return;
+            };
+            let name = name.map(|name| name.to_string()).unwrap_or_else(|| {
+                let base = file.rsplit('/').next().unwrap_or(file);
+                base.strip_suffix(".wasm").unwrap_or(base).to_string()
+            });
+            engine
+                .load_library(&name, &buffer)
+                .map(|()| println!("Loaded library {} from {}", name, file))
+        }
+        (Some("unload"), Some(name), _) => engine
+            .unload_library(name)
+            .map(|()| println!("Unloaded library {}", name)),
+        (Some("list") | None, _, _) => {
+            let libraries = engine.libraries();
+            if libraries.is_empty() {
+                println!("No libraries loaded");
+            }
+            for lib in libraries {
+                print!("  {:<16} {:>4} exports", lib.name, lib.exports);
+                if !lib.dependencies.is_empty() {
+                    print!("  needs {}", lib.dependencies.join(", "));
+                }
+                println!();
+            }
+            Ok(())
+        }
+        _ => {
+            println!("Usage: wasm lib [list] | load <file> [name] | unload <name>");
+            return;
+        }
+    };
+    if let Err(e) = result {
+        theme::set(Role::Error);
+        println!("wasm lib: {}", e);
+        theme::reset();
+    }
+}
+
+/// Save a process's state to a file.
+fn cmd_snapshot(args: &[&str], processes: &ProcessManager) {
+    use super::snapshot::SNAPSHOT_DIR;
+
+    let Some(pid) = args.first().and_then(|pid| pid.parse::<Pid>().ok()) else {
+        println!("Usage: snapshot <pid> [file]");
+        return;
+    };
+    let file = args
+        .get(1)
+        .map(|file| file.to_string())
+        .unwrap_or_else(|| alloc::format!("{}/{}.snap", SNAPSHOT_DIR, pid));
+    match processes.snapshot(pid) {
+        Ok(snapshot) => {
+            let data = snapshot.encode();
+            crate::fs::ROOT_FS.add_file(&file, &data);
+            println!("Saved {} to {} ({} bytes)", pid, file, data.len());
+        }
+        Err(e) => {
+            theme::set(Role::Error);
+            println!("snapshot {}: {}", pid, e);
+            theme::reset();
+        }
+    }
+}
+
+/// Start a process from a snapshot file, e.g. `restore --grant
+/// dir:/data:rw --grant net:listen=7 snapshots/3.snap`.
+///
+/// The capabilities the snapshot lists are derived from those given with
+/// `--grant`, in `grant`'s syntax, and from the Timer capability every
+/// process gets; a saved capability none of them covers fails the restore.
+fn cmd_restore(args: &[&str], processes: &mut ProcessManager) {
+    use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType};
+
+    let Some(args) = parse_args(&RESTORE_ARGS, args) else {
+        return;
+    };
+    let Some(filename) = args.get(0) else {
+        usage_error(&RESTORE_ARGS, &ArgError::Missing("<file>"));
+        return;
+    };
+    let mut parents = alloc::vec![Capability::new(
+        CapabilityType::Timer,
+        CapabilityRights::READ | CapabilityRights::CALL,
+    )];
+    for spec in args.values("grant") {
+        match grant_capability(spec) {
+            Some(cap) => parents.push(cap),
+            None => {
+                close_parents(&parents);
+                return;
+            }
+        }
+    }
+    restore_from(filename, &parents, processes);
+    close_parents(&parents);
+}
+
+/// Close the directories opened for `restore --grant`; what the restored
+/// process holds was opened again below them.
+fn close_parents(parents: &[sovelma_common::capability::Capability]) {
+    use crate::fs::{FileHandle, FileSystem, ROOT_FS};
+    use sovelma_common::capability::CapabilityType;
+
+    for parent in parents {
+        if let CapabilityType::Directory(dir) = parent.object {
+            ROOT_FS.close(FileHandle(dir as u32));
+        }
+    }
+}
+
+/// Restore the snapshot in `filename` with capabilities derived from
+/// `parents`, reporting the outcome.
+fn restore_from(
+    filename: &str,
+    parents: &[sovelma_common::capability::Capability],
+    processes: &mut ProcessManager,
+) {
+    use super::snapshot::Snapshot;
+
+    let Some(data) = read_file("restore", filename) else {
+        return;
+    };
+    let snapshot = match Snapshot::decode(&data) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            theme::set(Role::Error);
+            println!("restore {}: {}", filename, e);
+            theme::reset();
+            return;
+        }
+    };
+    // The module is loaded from where the process was started from
+    let Some(module) = read_file("restore", &snapshot.module) else {
+        return;
+    };
+    match processes.restore(&module, &snapshot, parents) {
+        Ok(pid) => println!("[{}] restored from {}", pid, filename),
+        Err(e) => {
+            theme::set(Role::Error);
+            println!("restore {}: {}", filename, e);
+            theme::reset();
+        }
+    }
+}
+
+/// Post a signal to a WASM process.
+fn cmd_kill(args: &[&str]) {
+    let pid = args.first().and_then(|pid| pid.parse::<Pid>().ok());
+    let signal = match args.get(1) {
+        None => Ok(Signal::Term),
+        Some(signal) => signal.trim_start_matches('-').parse::<Signal>(),
+    };
+    let (pid, signal) = match (pid, signal) {
+        (Some(pid), Ok(signal)) => (pid, signal),
+        (_, Err(e)) => {
+            println!("kill: {}", e);
+            return;
+        }
+        (None, _) => {
+            println!("Usage: kill <pid> [HUP|TERM|USR1|KILL]");
+            return;
+        }
+    };
+    match process::signal(pid, signal) {
+        Ok(()) => println!("Sent {} to {}", signal, pid),
+        Err(e) => {
+            theme::set(Role::Error);
+            println!("kill {}: {}", pid, e);
+            theme::reset();
+        }
+    }
+}
+
+/// Build the capability `grant` gives from its description, reporting
+/// failures: `dir:<path>[:ro|:rw]` (read-only unless `:rw`),
+/// `net:<rights>` (see `parse_net_grant`), `serial:<n>` or `config`.
+///
+/// A directory is opened here; the caller closes it if it is not granted.
+fn grant_capability(spec: &str) -> Option<sovelma_common::capability::Capability> {
+    use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType};
+
+    let invalid = || {
+        print_error("grant", &format_args!("invalid capability: {}", spec));
+        None
+    };
+    let (kind, value) = spec.split_once(':').unwrap_or((spec, ""));
+    match kind {
+        "dir" => {
+            let (path, rights) = match value.rsplit_once(':') {
+                Some((path, "rw")) => (path, CapabilityRights::READ | CapabilityRights::WRITE),
+                Some((path, "ro")) => (path, CapabilityRights::READ),
+                _ => (value, CapabilityRights::READ),
+            };
+            if path.is_empty() {
+                return invalid();
+            }
+            let handle = open_dir("grant", path)?;
+            Some(Capability::new(
+                CapabilityType::Directory(u64::from(handle.0)),
+                rights,
+            ))
+        }
+        "net" => parse_net_grant(value).or_else(invalid),
+        "serial" => {
+            let Ok(com) = value.parse::<u8>() else {
+                return invalid();
+            };
+            serial_capability(com)
+                .map_err(|e| print_error("grant", &e))
+                .ok()
+        }
+        "config" if value.is_empty() => Some(Capability::new(
+            CapabilityType::Config,
+            CapabilityRights::READ | CapabilityRights::WRITE,
+        )),
+        _ => invalid(),
+    }
+}
+
+/// Give a running process a capability, e.g. `grant 3 dir:/data:rw
+/// --label data --rate 100`.
+///
+/// The capability goes into the process's handle table as if it had been
+/// granted at start, and the process is told of it by an
+/// `Event::Granted` on its event queue; with `--rate` it is limited to that
+/// many operations a second. It is not recorded anywhere else:
+/// a process restarted or restored from a snapshot taken before the grant
+/// does not have it.
+fn cmd_grant(args: &[&str], processes: &mut ProcessManager) {
+    use crate::fs::{FileHandle, FileSystem, ROOT_FS};
+    use sovelma_common::capability::{Capability, CapabilityType};
+
+    let Some(args) = parse_args(&GRANT_ARGS, args) else {
+        return;
+    };
+    let Some(pid) = args.get(0).and_then(|pid| pid.parse::<Pid>().ok()) else {
+        usage_error(&GRANT_ARGS, &ArgError::Missing("<pid>"));
+        return;
+    };
+    let label = match args.value("label") {
+        Some(value) => match Label::new(value) {
+            Some(label) => Some(label),
+            None => {
+                usage_error(&GRANT_ARGS, &ArgError::Invalid("label", value));
+                return;
+            }
+        },
+        None => None,
+    };
+    let rate_limit = match args.value("rate") {
+        Some(value) => match RateLimit::parse(value) {
+            Some(limit) => Some(limit),
+            None => {
+                usage_error(&GRANT_ARGS, &ArgError::Invalid("rate", value));
+                return;
+            }
+        },
+        None => None,
+    };
+    let Some(cap) = args.get(1).and_then(grant_capability) else {
+        return;
+    };
+    let object = cap.object;
+    let cap = Capability {
+        label,
+        rate_limit,
+        ..cap
+    };
+    match processes.grant(pid, cap) {
+        Some(handle) => println!(
+            "Granted {} to {} as handle {}",
+            object.kind_name(),
+            pid,
+            handle.as_raw()
+        ),
+        None => {
+            if let CapabilityType::Directory(dir) = object {
+                ROOT_FS.close(FileHandle(dir as u32));
+            }
+            print_error("grant", &format_args!("{}: no such process", pid));
+        }
+    }
+}
+
+/// The pid argument of a debugging command, reporting a bad one.
+fn debug_pid(spec: &'static Spec, word: Option<&str>) -> Option<Pid> {
+    let pid = word.and_then(|pid| pid.parse::<Pid>().ok());
+    if pid.is_none() {
+        usage_error(spec, &ArgError::Missing("<pid>"));
+    }
+    pid
+}
+
+/// Parse an address or length, in hex with `0x` or else decimal.
+fn parse_u32(word: &str) -> Option<u32> {
+    match word.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+/// Dump part of a process's linear memory, e.g. `peek 3 0x1000 64`:
+/// 16 bytes a line, with their address and as ASCII.
+fn cmd_peek(args: &[&str], processes: &ProcessManager) {
+    let Some(args) = parse_args(&PEEK_ARGS, args) else {
+        return;
+    };
+    let Some(pid) = debug_pid(&PEEK_ARGS, args.get(0)) else {
+        return;
+    };
+    let mut numbers = [0u32; 2];
+    for (i, number) in numbers.iter_mut().enumerate() {
+        let word = args.get(i + 1).unwrap_or_default();
+        let Some(value) = parse_u32(word) else {
+            print_error("peek", &format_args!("invalid number: {}", word));
+            return;
+        };
+        *number = value;
+    }
+    let [addr, len] = numbers;
+    let bytes = match processes.peek(pid, addr, len.min(PEEK_MAX)) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            print_error("peek", &format_args!("{}: {}", pid, e));
+            return;
+        }
+    };
+    for (i, line) in bytes.chunks(16).enumerate() {
+        print!("{:08x} ", addr as usize + i * 16);
+        for byte in line {
+            print!(" {:02x}", byte);
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            })
+            .collect();
+        println!("{:width$}  |{}|", "", ascii, width = (16 - line.len()) * 3);
+    }
+    if len > PEEK_MAX {
+        println!("(first {} of {} bytes)", PEEK_MAX, len);
+    }
+}
+
+/// Show a process's exported globals and the capabilities it holds.
+fn cmd_inspect(args: &[&str], processes: &ProcessManager) {
+    let Some(args) = parse_args(&INSPECT_ARGS, args) else {
+        return;
+    };
+    let Some(pid) = debug_pid(&INSPECT_ARGS, args.get(0)) else {
+        return;
+    };
+    let found = processes
+        .globals(pid)
+        .and_then(|globals| Ok((globals, processes.capabilities(pid)?)));
+    let (globals, capabilities) = match found {
+        Ok(found) => found,
+        Err(e) => {
+            print_error("inspect", &format_args!("{}: {}", pid, e));
+            return;
+        }
+    };
+    let stopped = processes
+        .list()
+        .iter()
+        .any(|info| info.pid == pid && info.stopped);
+    println!(
+        "Process {} ({})",
+        pid,
+        if stopped { "stopped" } else { "running" }
+    );
+
+    theme::set(Role::Accent);
+    println!("Globals:");
+    theme::reset();
+    if globals.is_empty() {
+        println!("  (none)");
+    }
+    for (name, value) in &globals {
+        println!("  {:<24} {}", name, value);
+    }
+
+    theme::set(Role::Accent);
+    println!("Capabilities:");
+    theme::reset();
+    if capabilities.is_empty() {
+        println!("  (none)");
+    }
+    for (handle, cap) in &capabilities {
+        let rights: Vec<&str> = cap.rights.iter_names().map(|(name, _)| name).collect();
+        print!(
+            "  {:>3}  {:<10} {}",
+            handle.as_raw(),
+            cap.object.kind_name(),
+            rights.join("|")
+        );
+        if let Some(label) = &cap.label {
+            print!("  label {}", label);
+        }
+        if let Some(limit) = cap.rate_limit {
+            print!("  rate {}", limit);
+        }
+        println!();
+    }
+}
+
+/// Let a process stopped in `sp_dbg_break` continue.
+fn cmd_resume(args: &[&str], processes: &mut ProcessManager) {
+    let Some(args) = parse_args(&RESUME_ARGS, args) else {
+        return;
+    };
+    let Some(pid) = debug_pid(&RESUME_ARGS, args.get(0)) else {
+        return;
+    };
+    match processes.resume(pid) {
+        Ok(()) => println!("Resumed {}", pid),
+        Err(e) => print_error("resume", &format_args!("{}: {}", pid, e)),
+    }
+}
+
+/// Let a stopped process run for a fuel budget, e.g. `step 3 1000`; it
+/// stops again at its first suspension after using the budget up.
+fn cmd_step(args: &[&str], processes: &mut ProcessManager) {
+    let Some(args) = parse_args(&STEP_ARGS, args) else {
+        return;
+    };
+    let Some(pid) = debug_pid(&STEP_ARGS, args.get(0)) else {
+        return;
+    };
+    let word = args.get(1).unwrap_or_default();
+    let fuel = match word.parse::<u64>() {
+        Ok(fuel) if fuel > 0 => fuel,
+        _ => {
+            print_error("step", &format_args!("invalid fuel: {}", word));
+            return;
+        }
+    };
+    match processes.step(pid, fuel) {
+        Ok(()) => println!("Stepping {} by {} fuel", pid, fuel),
+        Err(e) => print_error("step", &format_args!("{}: {}", pid, e)),
+    }
+}
@@ -0,0 +1,71 @@
+//! CPU time accounting for WASM processes.
+//!
+//! Processes are charged in fuel, which counts interpreted instructions.
+//! Fuel is converted to milliseconds with a rate calibrated against the
+//! monotonic clock: whenever processes run across a clock tick, the fuel
+//! they used over the elapsed time is folded into a moving average. Until
+//! the first sample, `DEFAULT_FUEL_PER_MS` is assumed.
+
+/// Fuel per millisecond assumed before calibration.
+pub const DEFAULT_FUEL_PER_MS: u64 = 20_000;
+
+/// Weight of the current rate against a new sample (moving average of
+/// roughly the last eight samples).
+const CALIBRATION_WEIGHT: u64 = 7;
+
+/// Fuel-to-time conversion rate.
+#[derive(Debug, Clone, Copy)]
+pub struct FuelCalibration {
+    fuel_per_ms: u64,
+    samples: u64,
+}
+
+impl FuelCalibration {
+    /// Start with the default rate.
+    pub fn new() -> Self {
+        Self {
+            fuel_per_ms: DEFAULT_FUEL_PER_MS,
+            samples: 0,
+        }
+    }
+
+    /// Record that `fuel` was consumed over `elapsed_ms` of wall time.
+    ///
+    /// Intervals in which no clock tick passed carry no information and
+    /// are ignored.
+    pub fn sample(&mut self, fuel: u64, elapsed_ms: u64) {
+        let Some(rate) = fuel.checked_div(elapsed_ms) else {
+            return;
+        };
+        if rate == 0 {
+            return;
+        }
+        self.fuel_per_ms = if self.samples == 0 {
+            rate
+        } else {
+            (self.fuel_per_ms * CALIBRATION_WEIGHT + rate) / (CALIBRATION_WEIGHT + 1)
+        };
+        self.samples += 1;
+    }
+
+    /// Current rate.
+    pub fn fuel_per_ms(&self) -> u64 {
+        self.fuel_per_ms
+    }
+
+    /// Number of samples taken.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Convert fuel to milliseconds of CPU time.
+    pub fn to_ms(&self, fuel: u64) -> u64 {
+        fuel / self.fuel_per_ms.max(1)
+    }
+}
+
+impl Default for FuelCalibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,232 @@
+//! Per-process event queue.
+//!
+//! Everything a WASM process can wait for is reported as an `Event` on its
+//! queue: timer expiries, IPC messages, socket readiness, TCP connection
+//! state changes, filesystem watch notifications, child exits, signals and
+//! capabilities granted from the shell. The process drains the queue with
+//! `sp_poll`, which blocks until at least one event is pending or its
+//! timeout elapses.
+//!
+//! The queue is bounded. A timer that fires again before its previous
+//! event was consumed bumps the pending event's expiration count instead of
+//! taking another slot; other events are dropped (and counted) when the
+//! queue is full.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+/// Events buffered per process.
+pub const EVENT_QUEUE_CAPACITY: usize = 64;
+
+/// Size of one event record written by `sp_poll`.
+///
+/// Layout (little endian): `kind: u32`, reserved `u32`, `object: u64`,
+/// `data: u64`.
+pub const EVENT_RECORD_SIZE: usize = 24;
+
+/// Event kinds as seen by WASM code.
+pub mod kind {
+    /// A timer expired. Object: timer ID; data: expirations.
+    pub const TIMER: u32 = 1;
+    /// An IPC message arrived. Object: sender; data: message length.
+    pub const MESSAGE: u32 = 2;
+    /// A socket became ready. Object: socket; data: readiness bits.
+    pub const SOCKET: u32 = 3;
+    /// A watched path changed. Object: watch; data: change bits.
+    pub const FS_WATCH: u32 = 4;
+    /// A child process exited. Object: process ID; data: exit status.
+    pub const CHILD_EXIT: u32 = 5;
+    /// A signal was posted. Object: signal number; data: unused.
+    pub const SIGNAL: u32 = 6;
+    /// A TCP connection changed state. Object: socket; data: event code
+    /// (see `net::connection::ConnectionEvent::code`).
+    pub const CONNECTION: u32 = 7;
+    /// A capability was granted by the shell's `grant`. Object: its
+    /// handle; data: unused.
+    pub const GRANTED: u32 = 8;
+}
+
+/// An event delivered to a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A timer expired.
+    Timer {
+        /// Timer ID returned by `sp_timer_create`.
+        timer: u64,
+        /// Expirations since the event was queued (at least 1).
+        expirations: u64,
+    },
+    /// An IPC message is waiting to be received.
+    Message {
+        /// Sending process or channel.
+        sender: u64,
+        /// Message length in bytes.
+        len: u64,
+    },
+    /// A socket became readable or writable.
+    Socket {
+        /// Socket handle.
+        socket: u64,
+        /// Readiness bits (`SOCKET_READABLE`, `SOCKET_WRITABLE`).
+        readiness: u64,
+    },
+    /// A watched filesystem object changed.
+    FsWatch {
+        /// Watch handle.
+        watch: u64,
+        /// Change bits.
+        changes: u64,
+    },
+    /// A child process exited.
+    ChildExit {
+        /// Process ID of the child.
+        pid: u64,
+        /// Exit status.
+        status: i64,
+    },
+    /// A signal was posted to the process (see `process::Signal`).
+    Signal {
+        /// Signal number.
+        signal: u32,
+    },
+    /// A TCP connection was established, closed or failed.
+    Connection {
+        /// Handle of the Socket capability.
+        socket: u64,
+        /// Event code: connected 1, closed 2, refused 3, timed out 4.
+        state: u64,
+    },
+    /// A capability was granted while the process runs.
+    Granted {
+        /// The process's handle for it.
+        handle: u64,
+    },
+}
+
+/// `Event::Socket` readiness bit: data can be received.
+pub const SOCKET_READABLE: u64 = 1 << 0;
+
+/// `Event::Socket` readiness bit: data can be sent.
+pub const SOCKET_WRITABLE: u64 = 1 << 1;
+
+impl Event {
+    /// Event kind (see `kind`).
+    pub fn kind(&self) -> u32 {
+        match self {
+            Event::Timer { .. } => kind::TIMER,
+            Event::Message { .. } => kind::MESSAGE,
+            Event::Socket { .. } => kind::SOCKET,
+            Event::FsWatch { .. } => kind::FS_WATCH,
+            Event::ChildExit { .. } => kind::CHILD_EXIT,
+            Event::Signal { .. } => kind::SIGNAL,
+            Event::Connection { .. } => kind::CONNECTION,
+            Event::Granted { .. } => kind::GRANTED,
+        }
+    }
+
+    /// Encode the event as an `EVENT_RECORD_SIZE` record.
+    pub fn encode(&self) -> [u8; EVENT_RECORD_SIZE] {
+        let (object, data) = match *self {
+            Event::Timer { timer, expirations } => (timer, expirations),
+            Event::Message { sender, len } => (sender, len),
+            Event::Socket { socket, readiness } => (socket, readiness),
+            Event::FsWatch { watch, changes } => (watch, changes),
+            Event::ChildExit { pid, status } => (pid, status as u64),
+            Event::Signal { signal } => (u64::from(signal), 0),
+            Event::Connection { socket, state } => (socket, state),
+            Event::Granted { handle } => (handle, 0),
+        };
+        let mut record = [0u8; EVENT_RECORD_SIZE];
+        record[0..4].copy_from_slice(&self.kind().to_le_bytes());
+        record[8..16].copy_from_slice(&object.to_le_bytes());
+        record[16..24].copy_from_slice(&data.to_le_bytes());
+        record
+    }
+}
+
+/// Bounded FIFO of pending events.
+#[derive(Debug, Default)]
+pub struct EventQueue {
+    events: VecDeque<Event>,
+    dropped: u64,
+}
+
+/// An event queue shared between a process and the kernel objects that
+/// post to it.
+pub type SharedEventQueue = Arc<Mutex<EventQueue>>;
+
+impl EventQueue {
+    /// Create an empty queue.
+    pub fn new() -> Self {
+        Self {
+            events: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Create an empty queue that can be shared with event sources.
+    pub fn shared() -> SharedEventQueue {
+        Arc::new(Mutex::new(Self::new()))
+    }
+
+    /// Queue an event.
+    ///
+    /// Timer events coalesce with a pending event for the same timer. When
+    /// the queue is full the event is dropped and counted; returns `false`
+    /// in that case.
+    pub fn push(&mut self, event: Event) -> bool {
+        if let Event::Timer { timer, expirations } = event {
+            for pending in self.events.iter_mut() {
+                if let Event::Timer {
+                    timer: pending_timer,
+                    expirations: pending_expirations,
+                } = pending
+                {
+                    if *pending_timer == timer {
+                        *pending_expirations = pending_expirations.saturating_add(expirations);
+                        return true;
+                    }
+                }
+            }
+        }
+
+        if self.events.len() >= EVENT_QUEUE_CAPACITY {
+            self.dropped += 1;
+            return false;
+        }
+        self.events.push_back(event);
+        true
+    }
+
+    /// Take the oldest event.
+    pub fn pop(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    /// Iterate over pending events, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Event> {
+        self.events.iter()
+    }
+
+    /// Number of pending events.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Check whether no events are pending.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Events dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Drop all events of the given timer (after it was cancelled).
+    pub fn remove_timer(&mut self, id: u64) {
+        self.events
+            .retain(|event| !matches!(event, Event::Timer { timer, .. } if *timer == id));
+    }
+}
@@ -9,17 +9,38 @@
 //! - **No ambient authority**: Processes cannot access resources without explicit capabilities.
 //! - **Rights degradation**: Derived capabilities have equal or fewer rights than their parent.
 //! - **Generation-based revocation**: Stale capability references are rejected.
+//! - **Per-process handles**: WASM code names capabilities by small handles
+//!   private to the process (see `handles`), never by kernel `CapId`s.
 //!
 //! # Fuel Management
 //!
 //! Host functions track fuel consumption to enable cooperative preemption. When fuel
 //! runs low, functions yield control back to the scheduler via `HostTrap::Yield`.
 
+use super::batch::{self, BatchOp, BATCH_MAX, BATCH_RECORD_SIZE};
+use super::calls;
+use super::capabilities::ProcessCapabilities;
+use super::event::{EventQueue, SharedEventQueue, EVENT_RECORD_SIZE};
+use super::handles::Handle;
+use super::pipe::{PipeError, PipeReader, PipeWriter};
+use super::timer::ProcessTimers;
+#[cfg(feature = "net")]
+use crate::net::TcpSocket;
 use crate::println;
+use crate::sync::futex::{self, FutexKey, FutexWaiter};
+use crate::time;
+use crate::trace::{self, Category};
+#[cfg(feature = "net")]
 use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
 use sovelma_common::capability::{CapId, Capability, CapabilityRights, CapabilityType};
-use wasmi::{Caller, Linker};
+#[cfg(feature = "net")]
+use sovelma_common::host::MAX_PROCESS_SOCKETS;
+use sovelma_common::host::{DEFAULT_FS_QUOTA, FS_PATH_MAX, FS_WRITE_MAX};
+use wasmi::{AsContextMut, Caller, Linker, Memory};
 
 use core::fmt;
 
@@ -27,37 +48,7 @@ use core::fmt;
 // Error Codes
 // ============================================================================
 
-/// Host function error codes returned to WASM modules.
-///
-/// These are returned as negative i32/i64 values from host functions.
-pub mod error {
-    /// Capability not found or generation mismatch.
-    pub const CAP_NOT_FOUND: i64 = -1;
-    /// WASM module did not export a "memory" object.
-    pub const NO_MEMORY_EXPORT: i64 = -2;
-    /// Failed to read from WASM linear memory.
-    pub const MEMORY_READ_FAILED: i64 = -3;
-    /// Path string was not valid UTF-8.
-    pub const INVALID_UTF8: i64 = -4;
-    /// Capability lacks required rights for operation.
-    pub const PERMISSION_DENIED: i64 = -5;
-    /// Expected a directory capability, got something else.
-    pub const NOT_A_DIRECTORY: i64 = -6;
-    /// Filesystem operation failed.
-    pub const FS_ERROR: i64 = -7;
-    /// Buffer provided was too small.
-    pub const BUFFER_TOO_SMALL: i64 = -8;
-    /// Failed to write to WASM linear memory.
-    pub const MEMORY_WRITE_FAILED: i64 = -9;
-    /// Expected a file capability, got something else.
-    pub const NOT_A_FILE: i64 = -10;
-    /// Mutex is currently locked (for try_lock).
-    pub const MUTEX_LOCKED: i64 = -11;
-    /// Semaphore has no available permits (for try_acquire).
-    pub const SEM_NO_PERMITS: i64 = -12;
-    /// Invalid handle (mutex/semaphore not found).
-    pub const INVALID_HANDLE: i64 = -13;
-}
+pub use sovelma_common::host::error;
 
 // ============================================================================
 // Fuel Tracking
@@ -79,6 +70,24 @@ mod fuel_cost {
     pub const SYNC_CREATE: u64 = 50;
     /// Cost of a sync operation (lock/unlock/acquire/release).
     pub const SYNC_OPERATION: u64 = 20;
+    /// Cost of a clock read or timer operation.
+    pub const TIMER_OPERATION: u64 = 20;
+    /// Cost of checking the event queue.
+    pub const POLL: u64 = 20;
+    /// Cost of posting a signal.
+    pub const SIGNAL: u64 = 20;
+    /// Cost of a serial port read or write.
+    pub const SERIAL_IO: u64 = 50;
+    /// Cost of a stdin read or stdout write.
+    pub const PIPE_IO: u64 = 20;
+    /// Cost of a configuration lookup or change.
+    pub const CONFIG: u64 = 50;
+    /// Cost per KiB (or part) written to a file.
+    pub const FS_WRITE_KIB: u64 = 50;
+    /// Cost of a socket operation.
+    pub const NET_IO: u64 = 50;
+    /// Cost of an `sp_batch` call, besides its operations.
+    pub const BATCH_CALL: u64 = 20;
 }
 
 // ============================================================================
@@ -94,9 +103,22 @@ pub enum HostTrap {
     ///
     /// The task will be re-queued and resumed later with fresh fuel.
     Yield,
-    /// Sleep for the specified duration (future use).
-    #[allow(dead_code)]
+    /// Sleep until the given monotonic time (ms).
+    ///
+    /// The task is not resumed before the deadline has passed.
     Sleep(u64),
+    /// Waiting in `sp_poll` for events.
+    ///
+    /// The task is resumed once an event is queued or the deadline (if
+    /// any) has passed; pending events are then copied to `ptr`.
+    Poll {
+        /// Address of the event buffer in WASM memory.
+        ptr: u32,
+        /// Capacity of the buffer in events.
+        max: u32,
+        /// Monotonic time (ms) to give up at; `None` waits forever.
+        deadline: Option<u64>,
+    },
     /// Waiting on a mutex (handle).
     ///
     /// The task will be re-queued and resumed when the mutex is released.
@@ -105,15 +127,58 @@ pub enum HostTrap {
     ///
     /// The task will be re-queued and resumed when a permit is available.
     SemWait(u64),
+    /// Waiting in `sp_futex_wait`.
+    ///
+    /// The task is resumed once the waiter is woken or the deadline (if
+    /// any) has passed.
+    FutexWait {
+        /// The queued waiter.
+        waiter: Arc<FutexWaiter>,
+        /// Monotonic time (ms) to give up at; `None` waits forever.
+        deadline: Option<u64>,
+    },
+    /// Waiting in `sp_fs_lock` for an advisory lock on a file.
+    ///
+    /// The task is resumed once the lock can be taken, and takes it.
+    FileLock {
+        /// The file's handle in the root filesystem.
+        handle: crate::fs::FileHandle,
+        /// Whether the lock is exclusive.
+        exclusive: bool,
+    },
+    /// Stopped in `sp_dbg_break`.
+    ///
+    /// Like any stopped process, the task gets no slices before the shell's
+    /// `resume` or `step` clears `HostState::stopped`.
+    Break,
+    /// A call through a rate-limited capability found its bucket empty.
+    ///
+    /// The call has been made; its result is returned once the monotonic
+    /// time (ms) `until` has passed.
+    Throttled {
+        /// When the bucket is out of debt.
+        until: u64,
+        /// What the host function returned.
+        result: wasmi::Value,
+    },
 }
 
 impl fmt::Display for HostTrap {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             HostTrap::Yield => write!(f, "Yield"),
-            HostTrap::Sleep(ms) => write!(f, "Sleep({}ms)", ms),
+            HostTrap::Sleep(deadline) => write!(f, "Sleep(until {}ms)", deadline),
+            HostTrap::Poll {
+                deadline: Some(deadline),
+                ..
+            } => write!(f, "Poll(until {}ms)", deadline),
+            HostTrap::Poll { deadline: None, .. } => write!(f, "Poll"),
             HostTrap::MutexWait(h) => write!(f, "MutexWait({})", h),
             HostTrap::SemWait(h) => write!(f, "SemWait({})", h),
+            HostTrap::FutexWait { .. } => write!(f, "FutexWait"),
+            HostTrap::FileLock { handle, .. } => write!(f, "FileLock({})", handle.0),
+            HostTrap::Break => write!(f, "Break"),
+            HostTrap::Throttled { until, .. } => write!(f, "Throttled(until {}ms)", until),
         }
     }
 }
@@ -129,12 +194,77 @@ impl wasmi::core::HostError for HostTrap {}
 /// Each WASM process has its own `HostState` containing its granted capabilities
 /// and fuel tracking information.
 pub struct HostState {
-    /// Capabilities granted to this process.
-    pub capabilities: BTreeMap<CapId, Capability>,
+    /// Capabilities granted to this process, with the process's handles
+    /// for them; WASM code only sees the handles.
+    pub capabilities: ProcessCapabilities,
     /// Remaining fuel for this time slice.
     ///
     /// Host functions decrement this and yield when it drops below the threshold.
     pub fuel_remaining: u64,
+    /// Events waiting to be consumed by the process.
+    ///
+    /// Shared so kernel objects can post events while the process runs.
+    pub events: SharedEventQueue,
+    /// Timers created with `sp_timer_create`.
+    pub timers: ProcessTimers,
+    /// Bytes the process may still add to files with `sp_fs_write`.
+    pub fs_quota: u64,
+    /// Pipe `sp_stdin_read` reads from; without one stdin is empty.
+    pub stdin: Option<PipeReader>,
+    /// Pipe `sp_stdout_write` writes to; without one output goes to the
+    /// console.
+    pub stdout: Option<PipeWriter>,
+    /// Sockets opened with `sp_net_connect` and `sp_net_listen`, by the
+    /// handle in their Socket capability.
+    #[cfg(feature = "net")]
+    pub sockets: BTreeMap<u64, TcpSocket>,
+    /// Handle the next socket gets.
+    #[cfg(feature = "net")]
+    next_socket: u64,
+    /// Mutexes and semaphores the process created; released at teardown.
+    sync_objects: Vec<CapabilityType>,
+    /// PID of the process, once it has one; recorded as the owner of the
+    /// mutexes and semaphores it creates.
+    pub pid: Option<u64>,
+    /// Futex space of the process's linear memory.
+    futex_space: u64,
+    /// Whether the process is stopped, in `sp_dbg_break` or after a step.
+    pub stopped: bool,
+    /// Fuel consumed at which a process being stepped stops again.
+    pub step_until: Option<u64>,
+}
+
+/// What `HostState::teardown` released.
+#[derive(Default)]
+pub struct Teardown {
+    /// Files and directories closed.
+    pub files: usize,
+    /// Mutexes and semaphores destroyed; shared ones are kept.
+    pub sync_objects: usize,
+    /// Capabilities revoked.
+    pub capabilities: usize,
+    /// Sockets still to be closed (see `release_sockets`).
+    #[cfg(feature = "net")]
+    pub sockets: Vec<TcpSocket>,
+}
+
+impl Teardown {
+    /// Whether anything was left behind.
+    pub fn is_empty(&self) -> bool {
+        #[cfg(feature = "net")]
+        if !self.sockets.is_empty() {
+            return false;
+        }
+        self.capabilities == 0
+    }
+
+    /// Number of sockets still to be closed.
+    pub fn open_sockets(&self) -> usize {
+        #[cfg(feature = "net")]
+        return self.sockets.len();
+        #[cfg(not(feature = "net"))]
+        0
+    }
 }
 
 impl Default for HostState {
@@ -147,8 +277,22 @@ impl HostState {
     /// Create a new host state with no initial capabilities.
     pub fn new() -> Self {
         Self {
-            capabilities: BTreeMap::new(),
+            capabilities: ProcessCapabilities::new(),
             fuel_remaining: 0,
+            events: EventQueue::shared(),
+            timers: ProcessTimers::new(),
+            fs_quota: DEFAULT_FS_QUOTA,
+            stdin: None,
+            stdout: None,
+            #[cfg(feature = "net")]
+            sockets: BTreeMap::new(),
+            #[cfg(feature = "net")]
+            next_socket: 1,
+            sync_objects: Vec::new(),
+            pid: None,
+            futex_space: futex::new_space(),
+            stopped: false,
+            step_until: None,
         }
     }
 
@@ -157,10 +301,22 @@ impl HostState {
     /// This is the correct way to grant initial capabilities to a WASM process,
     /// enforcing the object-capability discipline where capabilities are only
     /// obtained through explicit grants, not ambient authority.
+    ///
+    /// The capabilities get handles 1, 2, ... in order.
     pub fn with_capabilities(initial_caps: impl IntoIterator<Item = Capability>) -> Self {
         let mut state = Self::new();
         for cap in initial_caps {
-            state.capabilities.insert(cap.id, cap);
+            state.grant(cap);
+        }
+        state
+    }
+
+    /// Create a new host state with capabilities at the given handles, as
+    /// saved in a snapshot.
+    pub fn with_handles(caps: impl IntoIterator<Item = (Handle, Capability)>) -> Self {
+        let mut state = Self::new();
+        for (handle, cap) in caps {
+            state.capabilities.grant_at(handle, cap);
         }
         state
     }
@@ -168,26 +324,123 @@ impl HostState {
     /// Add a capability and return its ID.
     pub fn add_capability(&mut self, cap: Capability) -> CapId {
         let id = cap.id;
-        self.capabilities.insert(id, cap);
+        self.grant(cap);
         id
     }
 
+    /// Add a capability and return the process's handle for it.
+    pub fn grant(&mut self, cap: Capability) -> Handle {
+        self.capabilities.grant(cap)
+    }
+
+    /// Add `cap`, derived from the capability `parent` names, and return
+    /// the process's handle for it. It is under the parent's rate limit,
+    /// drawing on the same bucket.
+    pub fn derive(&mut self, parent: i64, cap: Capability) -> Handle {
+        self.capabilities.derive(parent, cap)
+    }
+
+    /// Open the directory at `path` below the one `dir_cap` grants READ
+    /// on, as a capability with only the rights both `dir_cap` and
+    /// `rights` hold (`sp_fs_opendir_restricted`).
+    ///
+    /// Returns the process's handle for it, or an error code.
+    pub fn open_dir_restricted(
+        &mut self,
+        dir_cap: i64,
+        path: &str,
+        rights: u32,
+    ) -> Result<Handle, i64> {
+        calls::fs_opendir_restricted(&mut self.capabilities, dir_cap, path, rights)
+    }
+
+    /// Take a token for a call through `handle` at `now`, if the
+    /// capability is rate-limited. Returns when the caller may go on if
+    /// the bucket was empty.
+    pub fn take_token(&self, handle: i64, now: u64) -> Option<u64> {
+        self.capabilities.take_token(handle, now)
+    }
+
+    /// The capability a host function was passed as `handle`, if the
+    /// process holds it.
+    pub fn capability(&self, handle: i64) -> Option<&Capability> {
+        self.capabilities.capability(handle)
+    }
+
+    /// Release everything the process holds once it has exited or trapped:
+    /// open files and directories are closed, the mutexes and semaphores
+    /// it created are destroyed unless they were shared, and all its
+    /// capabilities revoked.
+    ///
+    /// Closing sockets needs the network stack, so they are handed back in
+    /// the result for `release_sockets`.
+    pub fn teardown(&mut self) -> Teardown {
+        use crate::fs::{FileHandle, FileSystem, ROOT_FS};
+        use crate::sync::registry::{self, SyncKind};
+
+        let mut released = Teardown {
+            capabilities: self.capabilities.len(),
+            ..Teardown::default()
+        };
+        for cap in self.capabilities.take_all() {
+            if let CapabilityType::File(handle) | CapabilityType::Directory(handle) = cap.object {
+                ROOT_FS.close(FileHandle(handle as u32));
+                released.files += 1;
+            }
+        }
+        for object in core::mem::take(&mut self.sync_objects) {
+            let destroyed = match object {
+                CapabilityType::Mutex(handle) => registry::release(SyncKind::Mutex, handle),
+                CapabilityType::Semaphore(handle) => registry::release(SyncKind::Semaphore, handle),
+                _ => false,
+            };
+            if destroyed {
+                released.sync_objects += 1;
+            }
+        }
+        #[cfg(feature = "net")]
+        {
+            released.sockets = core::mem::take(&mut self.sockets).into_values().collect();
+        }
+        self.stdin = None;
+        self.stdout = None;
+        self.timers = ProcessTimers::new();
+        released
+    }
+
+    /// Capabilities the process holds with their handles, lowest first.
+    pub fn held(&self) -> Vec<(Handle, Capability)> {
+        self.capabilities.held()
+    }
+
+    /// Give up the capability named by `handle`, returning it.
+    pub fn close(&mut self, handle: i64) -> Option<Capability> {
+        self.capabilities.close(handle)
+    }
+
     /// Get a capability if it exists and generation matches.
     ///
     /// Returns `None` if the capability doesn't exist or the generation
     /// has been invalidated (revoked).
     pub fn get_capability(&self, id: CapId) -> Option<&Capability> {
-        let cap = self.capabilities.get(&id)?;
-        if cap.generation as u32 == id.generation() {
-            Some(cap)
-        } else {
-            None
-        }
+        self.capabilities.get(id)
     }
 
     /// Revoke a capability by ID.
     pub fn revoke(&mut self, id: CapId) {
-        self.capabilities.remove(&id);
+        self.capabilities.revoke(id);
+    }
+
+    /// Check whether the process holds a Timer capability with `rights`.
+    ///
+    /// READ allows reading the clock; CALL allows sleeping and timers.
+    pub fn has_timer_rights(&self, rights: CapabilityRights) -> bool {
+        self.capabilities.has_timer_rights(rights)
+    }
+
+    /// Deliver expired timers to the event queue.
+    pub fn fire_timers(&mut self, now: u64) {
+        self.timers.fire(now, &mut self.events.lock());
     }
 
     /// Consume fuel for an operation.
@@ -203,6 +456,32 @@ impl HostState {
 // Helper Functions
 // ============================================================================
 
+/// Copy up to `max` pending events to WASM memory at `ptr`.
+///
+/// Events are only removed from the queue once written. Returns the number
+/// of events delivered, or an error code.
+pub(super) fn deliver_events(
+    mut ctx: impl AsContextMut<UserState = HostState>,
+    memory: Memory,
+    ptr: u32,
+    max: u32,
+) -> i32 {
+    let events = ctx.as_context_mut().data().events.clone();
+    let mut queue = events.lock();
+    let count = queue.len().min(max as usize);
+    let mut buffer = Vec::with_capacity(count * EVENT_RECORD_SIZE);
+    for event in queue.iter().take(count) {
+        buffer.extend_from_slice(&event.encode());
+    }
+    if memory.write(&mut ctx, ptr as usize, &buffer).is_err() {
+        return error::MEMORY_WRITE_FAILED as i32;
+    }
+    for _ in 0..count {
+        queue.pop();
+    }
+    count as i32
+}
+
 /// Check fuel and return a yield trap if exhausted.
 fn check_fuel(caller: &mut Caller<'_, HostState>, cost: u64) -> Result<(), wasmi::core::Trap> {
     if !caller.data_mut().consume_fuel(cost) {
@@ -212,6 +491,32 @@ fn check_fuel(caller: &mut Caller<'_, HostState>, cost: u64) -> Result<(), wasmi
     }
 }
 
+/// Return `result` from a host function that acted through the capability
+/// `handle`, taking a token if it is rate-limited; with the bucket empty,
+/// the result is held back by a `HostTrap::Throttled` until the bucket is
+/// out of debt.
+fn throttle<T: Into<wasmi::Value>>(
+    caller: &Caller<'_, HostState>,
+    handle: i64,
+    result: T,
+) -> Result<T, wasmi::core::Trap> {
+    hold_until(caller.data().take_token(handle, time::now_ms()), result)
+}
+
+/// Return `result`, or hold it back until the time `until` if one is given.
+fn hold_until<T: Into<wasmi::Value>>(
+    until: Option<u64>,
+    result: T,
+) -> Result<T, wasmi::core::Trap> {
+    match until {
+        Some(until) => Err(wasmi::core::Trap::from(HostTrap::Throttled {
+            until,
+            result: result.into(),
+        })),
+        None => Ok(result),
+    }
+}
+
 // ============================================================================
 // Host Function Registration
 // ============================================================================
@@ -229,6 +534,14 @@ pub fn register_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::E
     register_fs_functions(linker)?;
     register_scheduler_functions(linker)?;
     register_sync_functions(linker)?;
+    register_timer_functions(linker)?;
+    register_event_functions(linker)?;
+    register_process_functions(linker)?;
+    register_serial_functions(linker)?;
+    register_stdio_functions(linker)?;
+    register_config_functions(linker)?;
+    register_net_functions(linker)?;
+    register_batch_functions(linker)?;
     Ok(())
 }
 
@@ -240,70 +553,111 @@ fn register_debug_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi:
         println!("[WASM] Host function 'print' called");
     })?;
 
+    // sp_dbg_break() -> i32
+    // Returns: 0 once the process is resumed
+    // Stops the process via HostTrap::Break and tells the shell, where
+    // `peek` and `inspect` look at it and `resume` lets it go on
+    linker.func_wrap(
+        "env",
+        "sp_dbg_break",
+        |mut caller: Caller<'_, HostState>| -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_dbg_break", 0);
+            check_fuel(&mut caller, fuel_cost::CAP_LOOKUP)?;
+
+            let state = caller.data_mut();
+            state.stopped = true;
+            match state.pid {
+                Some(pid) => log::info!(target: "wasm", "[{}] stopped in sp_dbg_break", pid),
+                None => log::info!(target: "wasm", "process stopped in sp_dbg_break"),
+            }
+            Err(wasmi::core::Trap::from(HostTrap::Break))
+        },
+    )?;
+
     Ok(())
 }
 
 /// Register capability discovery and management functions.
 fn register_capability_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
     // sp_get_capabilities(ptr: i32, len: i32) -> i32
+    // Writes the held capabilities in version 1 of the capability list
+    // format, a `codec` sequence of `CapabilityRecord`s.
     // Returns: number of capabilities written, or negative error code
     linker.func_wrap(
         "env",
         "sp_get_capabilities",
         |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<i32, wasmi::core::Trap> {
-            check_fuel(&mut caller, fuel_cost::CAP_LOOKUP)?;
-
-            let memory = match caller.get_export("memory") {
-                Some(wasmi::Extern::Memory(m)) => m,
-                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
-            };
-
-            let caps: alloc::vec::Vec<_> = caller.data().capabilities.values().cloned().collect();
-            let count = caps.len();
-            let struct_size = 16; // 8 (id) + 4 (type) + 4 (rights)
-            let required_len = count * struct_size;
-
-            if (len as usize) < required_len {
-                return Ok(error::BUFFER_TOO_SMALL as i32);
-            }
-
-            check_fuel(&mut caller, fuel_cost::MEMORY_IO * count as u64)?;
-
-            let mut offset = ptr as usize;
-            for cap in caps {
-                let id_bytes = cap.id.as_u64().to_le_bytes();
-                let type_val: u32 = match cap.object {
-                    CapabilityType::File(_) => 0,
-                    CapabilityType::Directory(_) => 1,
-                    CapabilityType::Mutex(_) => 2,
-                    CapabilityType::Semaphore(_) => 3,
-                    _ => 255,
-                };
-                let type_bytes = type_val.to_le_bytes();
-                let rights_bits = cap.rights.bits();
-                let rights_bytes = rights_bits.to_le_bytes();
-
-                if memory.write(&mut caller, offset, &id_bytes).is_err() {
-                    return Ok(error::MEMORY_WRITE_FAILED as i32);
-                }
-                offset += 8;
-                if memory.write(&mut caller, offset, &type_bytes).is_err() {
-                    return Ok(error::MEMORY_WRITE_FAILED as i32);
-                }
-                offset += 4;
-                if memory.write(&mut caller, offset, &rights_bytes).is_err() {
-                    return Ok(error::MEMORY_WRITE_FAILED as i32);
-                }
-                offset += 4;
-            }
+            let _span = trace::span(Category::HostCall, "sp_get_capabilities", 0);
+            write_capability_list(&mut caller, 1, ptr, len)
+        },
+    )?;
 
-            Ok(count as i32)
+    // sp_describe_capabilities(version: i32, ptr: i32, len: i32) -> i32
+    // Writes the held capabilities in capability list format `version`
+    // (see `CAPABILITY_FORMATS`); version 2 adds labels and details.
+    // Returns: number of capabilities written, or negative error code
+    linker.func_wrap(
+        "env",
+        "sp_describe_capabilities",
+        |mut caller: Caller<'_, HostState>,
+         version: i32,
+         ptr: i32,
+         len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_describe_capabilities", 0);
+            write_capability_list(&mut caller, version as u32, ptr, len)
         },
     )?;
 
     Ok(())
 }
 
+/// Write the capability list for `sp_get_capabilities` and
+/// `sp_describe_capabilities` to `ptr`.
+fn write_capability_list(
+    caller: &mut Caller<'_, HostState>,
+    version: u32,
+    ptr: i32,
+    len: i32,
+) -> Result<i32, wasmi::core::Trap> {
+    check_fuel(caller, fuel_cost::CAP_LOOKUP)?;
+
+    let memory = match caller.get_export("memory") {
+        Some(wasmi::Extern::Memory(m)) => m,
+        _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+    };
+    let caps = &caller.data().capabilities;
+    let (bytes, count) = match calls::capability_list(caps, version, len.max(0) as usize) {
+        Ok(list) => list,
+        Err(code) => return Ok(code),
+    };
+
+    check_fuel(caller, fuel_cost::MEMORY_IO * count as u64)?;
+    if memory.write(&mut *caller, ptr as usize, &bytes).is_err() {
+        return Ok(error::MEMORY_WRITE_FAILED as i32);
+    }
+    Ok(count as i32)
+}
+
+/// Read a UTF-8 string of at most `max` bytes from WASM memory.
+fn read_str(
+    caller: &Caller<'_, HostState>,
+    memory: &Memory,
+    ptr: i32,
+    len: i32,
+    max: usize,
+) -> Result<String, i32> {
+    let len = usize::try_from(len).map_err(|_| error::INVALID_ARGUMENT as i32)?;
+    if len > max {
+        return Err(error::INVALID_ARGUMENT as i32);
+    }
+    let mut buffer = alloc::vec![0u8; len];
+    if memory.read(caller, ptr as usize, &mut buffer).is_err() {
+        return Err(error::MEMORY_READ_FAILED as i32);
+    }
+    String::from_utf8(buffer).map_err(|_| error::INVALID_UTF8 as i32)
+}
+
 /// Register filesystem host functions.
 fn register_fs_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
     // sp_fs_open(dir_cap: i64, path_ptr: i32, path_len: i32) -> i64
@@ -315,6 +669,7 @@ fn register_fs_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Er
          path_ptr: i32,
          path_len: i32|
          -> Result<i64, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_open", 0);
             check_fuel(&mut caller, fuel_cost::FS_OPERATION)?;
 
             let memory = match caller.get_export("memory") {
@@ -335,55 +690,90 @@ fn register_fs_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Er
                 Err(_) => return Ok(error::INVALID_UTF8),
             };
 
-            let cap_id = CapId::from_u64(dir_cap as u64);
+            match calls::fs_open(&mut caller.data_mut().capabilities, dir_cap, path) {
+                code if code < 0 => Ok(code),
+                handle => throttle(&caller, dir_cap, handle),
+            }
+        },
+    )?;
 
-            // Extract handle and parent rights for derivation
-            let (dir_handle, parent_rights) = {
-                let host_state = caller.data();
-                match host_state.get_capability(cap_id) {
-                    Some(cap) => match cap.object {
-                        CapabilityType::Directory(handle_val) => {
-                            if cap.rights.contains(CapabilityRights::READ) {
-                                (crate::fs::FileHandle(handle_val as u32), cap.rights)
-                            } else {
-                                return Ok(error::PERMISSION_DENIED);
-                            }
-                        }
-                        _ => return Ok(error::NOT_A_DIRECTORY),
-                    },
-                    None => return Ok(error::CAP_NOT_FOUND),
-                }
-            };
+    // sp_fs_opendir_restricted(dir_cap: i64, path_ptr: i32, path_len: i32, rights: i32) -> i64
+    // Returns: a capability for the directory at `path` below `dir_cap`,
+    // with the rights of both `dir_cap` and `rights`, or error code
+    linker.func_wrap(
+        "env",
+        "sp_fs_opendir_restricted",
+        |mut caller: Caller<'_, HostState>,
+         dir_cap: i64,
+         path_ptr: i32,
+         path_len: i32,
+         rights: i32|
+         -> Result<i64, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_opendir_restricted", 0);
+            check_fuel(&mut caller, fuel_cost::FS_OPERATION)?;
 
-            // Perform FS operation
-            use crate::fs::{FileSystem, ROOT_FS};
-            let new_handle = match ROOT_FS.open_at(dir_handle, path) {
-                Ok(h) => h,
-                Err(_) => return Ok(error::FS_ERROR),
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT),
             };
 
-            // Determine type of new capability
-            let is_dir = ROOT_FS.is_dir(new_handle);
-            let cap_type = if is_dir {
-                CapabilityType::Directory(new_handle.0 as u64)
-            } else {
-                CapabilityType::File(new_handle.0 as u64)
+            let mut buffer = alloc::vec![0u8; path_len.max(0) as usize];
+            if memory
+                .read(&caller, path_ptr as usize, &mut buffer)
+                .is_err()
+            {
+                return Ok(error::MEMORY_READ_FAILED);
+            }
+            let path = match core::str::from_utf8(&buffer) {
+                Ok(s) => s,
+                Err(_) => return Ok(error::INVALID_UTF8),
             };
 
-            // Rights degradation: derived capabilities inherit parent's rights
-            // but cannot exceed type-applicable rights
-            let applicable_rights = if is_dir {
-                CapabilityRights::READ
-                    | CapabilityRights::WRITE
-                    | CapabilityRights::EXECUTE
-                    | CapabilityRights::GRANT
-            } else {
-                CapabilityRights::READ | CapabilityRights::WRITE
+            match caller
+                .data_mut()
+                .open_dir_restricted(dir_cap, path, rights as u32)
+            {
+                Ok(handle) => throttle(&caller, dir_cap, handle.as_raw()),
+                Err(code) => Ok(code),
+            }
+        },
+    )?;
+
+    // sp_fs_readdir(dir_cap: i64, start: i32, buf_ptr: i32, buf_len: i32) -> i32
+    // Writes the entries of the directory from the `start`th on, as many
+    // as fit, as a sequence of `DirEntryRecord`s (see `sovelma_common::fs`)
+    // Returns: number of entries written (0 past the last), or error code
+    linker.func_wrap(
+        "env",
+        "sp_fs_readdir",
+        |mut caller: Caller<'_, HostState>,
+         dir_cap: i64,
+         start: i32,
+         buf_ptr: i32,
+         buf_len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_readdir", 0);
+            check_fuel(&mut caller, fuel_cost::FS_OPERATION)?;
+
+            if start < 0 || buf_len < 0 {
+                return Ok(error::INVALID_ARGUMENT as i32);
+            }
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
             };
-            let derived_rights = parent_rights & applicable_rights;
+            let caps = &caller.data().capabilities;
+            let (bytes, count) =
+                match calls::fs_readdir(caps, dir_cap, start as usize, buf_len as usize) {
+                    Ok(listing) => listing,
+                    Err(code) => return Ok(code),
+                };
 
-            let new_cap = Capability::new(cap_type, derived_rights);
-            Ok(caller.data_mut().add_capability(new_cap).as_u64() as i64)
+            check_fuel(&mut caller, fuel_cost::MEMORY_IO * count as u64)?;
+            if memory.write(&mut caller, buf_ptr as usize, &bytes).is_err() {
+                return Ok(error::MEMORY_WRITE_FAILED as i32);
+            }
+            throttle(&caller, dir_cap, count as i32)
         },
     )?;
 
@@ -397,6 +787,7 @@ fn register_fs_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Er
          buf_len: i32,
          offset: i32|
          -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_read", 0);
             check_fuel(&mut caller, fuel_cost::FS_OPERATION)?;
 
             let memory = match caller.get_export("memory") {
@@ -404,30 +795,11 @@ fn register_fs_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Er
                 _ => return Ok(error::NO_MEMORY_EXPORT as i32),
             };
 
-            let cap_id = CapId::from_u64(file_cap as u64);
-            let file_handle = {
-                let host_state = caller.data();
-                match host_state.get_capability(cap_id) {
-                    Some(cap) => match cap.object {
-                        CapabilityType::File(handle_val) => {
-                            if cap.rights.contains(CapabilityRights::READ) {
-                                crate::fs::FileHandle(handle_val as u32)
-                            } else {
-                                return Ok(error::PERMISSION_DENIED as i32);
-                            }
-                        }
-                        _ => return Ok(error::NOT_A_FILE as i32),
-                    },
-                    None => return Ok(error::CAP_NOT_FOUND as i32),
-                }
-            };
-
-            // Perform read
-            use crate::fs::{FileSystem, ROOT_FS};
             let mut buffer = alloc::vec![0u8; buf_len as usize];
-            let bytes_read = match ROOT_FS.read(file_handle, &mut buffer, offset as usize) {
-                Ok(n) => n,
-                Err(_) => return Ok(error::FS_ERROR as i32),
+            let caps = &caller.data().capabilities;
+            let bytes_read = match calls::fs_read(caps, file_cap, &mut buffer, offset as usize) {
+                n if n < 0 => return Ok(n),
+                n => n as usize,
             };
 
             check_fuel(&mut caller, fuel_cost::MEMORY_IO)?;
@@ -440,7 +812,48 @@ fn register_fs_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Er
                 return Ok(error::MEMORY_WRITE_FAILED as i32);
             }
 
-            Ok(bytes_read as i32)
+            throttle(&caller, file_cap, bytes_read as i32)
+        },
+    )?;
+
+    // sp_fs_write(file_cap: i64, buf_ptr: i32, buf_len: i32, offset: i32) -> i32
+    // Returns: bytes written (at most FS_WRITE_MAX), or error code
+    // Bytes that grow the file count against the process's quota
+    linker.func_wrap(
+        "env",
+        "sp_fs_write",
+        |mut caller: Caller<'_, HostState>,
+         file_cap: i64,
+         buf_ptr: i32,
+         buf_len: i32,
+         offset: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_write", 0);
+            if buf_len < 0 || offset < 0 {
+                return Ok(error::INVALID_ARGUMENT as i32);
+            }
+            let len = (buf_len as usize).min(FS_WRITE_MAX);
+            let kib = len.div_ceil(1024) as u64;
+            check_fuel(
+                &mut caller,
+                fuel_cost::FS_OPERATION + kib * fuel_cost::FS_WRITE_KIB,
+            )?;
+
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+
+            let mut buffer = alloc::vec![0u8; len];
+            if memory.read(&caller, buf_ptr as usize, &mut buffer).is_err() {
+                return Ok(error::MEMORY_READ_FAILED as i32);
+            }
+            let state = caller.data_mut();
+            let (caps, quota) = (&state.capabilities, &mut state.fs_quota);
+            match calls::fs_write(caps, quota, file_cap, &buffer, offset as usize) {
+                written if written < 0 => Ok(written),
+                written => throttle(&caller, file_cap, written),
+            }
         },
     )?;
 
@@ -449,26 +862,12 @@ fn register_fs_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Er
         "env",
         "sp_fs_size",
         |mut caller: Caller<'_, HostState>, file_cap: i64| -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_size", 0);
             check_fuel(&mut caller, fuel_cost::CAP_LOOKUP)?;
 
-            let cap_id = CapId::from_u64(file_cap as u64);
-            let handle = {
-                let host_state = caller.data();
-                match host_state.get_capability(cap_id) {
-                    Some(cap) => match cap.object {
-                        CapabilityType::File(val) | CapabilityType::Directory(val) => {
-                            crate::fs::FileHandle(val as u32)
-                        }
-                        _ => return Ok(error::NOT_A_FILE as i32),
-                    },
-                    None => return Ok(error::CAP_NOT_FOUND as i32),
-                }
-            };
-
-            use crate::fs::{FileSystem, ROOT_FS};
-            match ROOT_FS.size(handle) {
-                Ok(s) => Ok(s as i32),
-                Err(_) => Ok(error::FS_ERROR as i32),
+            match calls::fs_size(&caller.data().capabilities, file_cap) {
+                code if code < 0 => Ok(code),
+                size => throttle(&caller, file_cap, size),
             }
         },
     )?;
@@ -478,28 +877,10 @@ fn register_fs_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Er
         "env",
         "sp_fs_close",
         |mut caller: Caller<'_, HostState>, file_cap: i64| -> Result<(), wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_close", 0);
             check_fuel(&mut caller, fuel_cost::CAP_LOOKUP)?;
 
-            let cap_id = CapId::from_u64(file_cap as u64);
-
-            let handle_to_close = {
-                let host_state = caller.data_mut();
-                if let Some(cap) = host_state.capabilities.remove(&cap_id) {
-                    match cap.object {
-                        CapabilityType::File(val) | CapabilityType::Directory(val) => {
-                            Some(crate::fs::FileHandle(val as u32))
-                        }
-                        _ => None,
-                    }
-                } else {
-                    None
-                }
-            };
-
-            if let Some(handle) = handle_to_close {
-                use crate::fs::{FileSystem, ROOT_FS};
-                ROOT_FS.close(handle);
-            }
+            calls::fs_close(&mut caller.data_mut().capabilities, file_cap);
             Ok(())
         },
     )?;
@@ -513,6 +894,7 @@ fn register_fs_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Er
          path_ptr: i32,
          path_len: i32|
          -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_mkdir", 0);
             check_fuel(&mut caller, fuel_cost::FS_OPERATION)?;
 
             let memory = match caller.get_export("memory") {
@@ -532,65 +914,217 @@ fn register_fs_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Er
                 Err(_) => return Ok(error::INVALID_UTF8 as i32),
             };
 
-            let cap_id = CapId::from_u64(dir_cap as u64);
-            let dir_handle = {
-                let host_state = caller.data();
-                match host_state.get_capability(cap_id) {
-                    Some(cap) => match cap.object {
-                        CapabilityType::Directory(val) => {
-                            if cap.rights.contains(CapabilityRights::WRITE) {
-                                crate::fs::FileHandle(val as u32)
-                            } else {
-                                return Ok(error::PERMISSION_DENIED as i32);
-                            }
-                        }
-                        _ => return Ok(error::NOT_A_DIRECTORY as i32),
-                    },
-                    None => return Ok(error::CAP_NOT_FOUND as i32),
-                }
-            };
-
-            use crate::fs::{FileSystem, ROOT_FS};
-            match ROOT_FS.mkdir_at(dir_handle, path) {
-                Ok(_) => Ok(0),
-                Err(_) => Ok(error::FS_ERROR as i32),
+            match calls::fs_mkdir(&caller.data().capabilities, dir_cap, path) {
+                0 => throttle(&caller, dir_cap, 0),
+                code => Ok(code),
             }
         },
     )?;
 
-    Ok(())
-}
-
-/// Register scheduler-related host functions.
-fn register_scheduler_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
-    // sp_sched_yield(): Voluntarily yield control to the scheduler
+    // sp_fs_unlink(dir_cap: i64, path_ptr: i32, path_len: i32) -> i32
+    // Removes the file, device node or empty directory at `path` below
+    // `dir_cap`, which must grant WRITE; capabilities open on a file keep
+    // working until closed
+    // Returns: 0 on success, or error code
     linker.func_wrap(
         "env",
-        "sp_sched_yield",
-        |_caller: Caller<'_, HostState>| -> Result<(), wasmi::core::Trap> {
-            Err(wasmi::core::Trap::from(HostTrap::Yield))
-        },
-    )?;
+        "sp_fs_unlink",
+        |mut caller: Caller<'_, HostState>,
+         dir_cap: i64,
+         path_ptr: i32,
+         path_len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_unlink", 0);
+            check_fuel(&mut caller, fuel_cost::FS_OPERATION)?;
 
-    Ok(())
-}
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let path = match read_str(&caller, &memory, path_ptr, path_len, FS_PATH_MAX) {
+                Ok(path) => path,
+                Err(code) => return Ok(code),
+            };
 
-/// Register synchronization host functions.
-fn register_sync_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
-    use crate::sync::registry;
+            match calls::fs_unlink(&caller.data().capabilities, dir_cap, &path) {
+                0 => throttle(&caller, dir_cap, 0),
+                code => Ok(code),
+            }
+        },
+    )?;
 
-    // sp_mutex_create() -> i64
-    // Returns: mutex capability ID (positive) or error code (negative)
+    // sp_fs_rename(dir_cap: i64, from_ptr: i32, from_len: i32, to_ptr: i32, to_len: i32) -> i32
+    // Moves `from` to `to`, both below `dir_cap`, which must grant WRITE;
+    // `to` must not exist yet
+    // Returns: 0 on success, or error code
     linker.func_wrap(
         "env",
-        "sp_mutex_create",
-        |mut caller: Caller<'_, HostState>| -> Result<i64, wasmi::core::Trap> {
-            check_fuel(&mut caller, fuel_cost::SYNC_CREATE)?;
-
-            let handle = registry::create_mutex();
+        "sp_fs_rename",
+        |mut caller: Caller<'_, HostState>,
+         dir_cap: i64,
+         from_ptr: i32,
+         from_len: i32,
+         to_ptr: i32,
+         to_len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_rename", 0);
+            check_fuel(&mut caller, fuel_cost::FS_OPERATION)?;
+
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let from = match read_str(&caller, &memory, from_ptr, from_len, FS_PATH_MAX) {
+                Ok(from) => from,
+                Err(code) => return Ok(code),
+            };
+            let to = match read_str(&caller, &memory, to_ptr, to_len, FS_PATH_MAX) {
+                Ok(to) => to,
+                Err(code) => return Ok(code),
+            };
+
+            match calls::fs_rename(&caller.data().capabilities, dir_cap, &from, &to) {
+                0 => throttle(&caller, dir_cap, 0),
+                code => Ok(code),
+            }
+        },
+    )?;
+
+    // sp_fs_lock(file_cap: i64, exclusive: i32) -> i32
+    // Returns: 0 once the advisory lock is held, or error code
+    // A shared lock needs READ, an exclusive one WRITE; blocks via
+    // HostTrap::FileLock while another handle holds a conflicting lock
+    linker.func_wrap(
+        "env",
+        "sp_fs_lock",
+        |mut caller: Caller<'_, HostState>,
+         file_cap: i64,
+         exclusive: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_lock", 0);
+            check_fuel(&mut caller, fuel_cost::SYNC_OPERATION)?;
+
+            let exclusive = exclusive != 0;
+            let rights = if exclusive {
+                CapabilityRights::WRITE
+            } else {
+                CapabilityRights::READ
+            };
+            let handle = match calls::file(&caller.data().capabilities, file_cap, rights) {
+                Ok(handle) => handle,
+                Err(code) => return Ok(code),
+            };
+
+            use crate::fs::ROOT_FS;
+            match ROOT_FS.try_lock(handle, exclusive) {
+                Ok(true) => throttle(&caller, file_cap, 0),
+                Ok(false) => Err(wasmi::core::Trap::from(HostTrap::FileLock {
+                    handle,
+                    exclusive,
+                })),
+                Err(_) => Ok(error::FS_ERROR as i32),
+            }
+        },
+    )?;
+
+    // sp_fs_unlock(file_cap: i64) -> i32
+    // Returns: 0, NOT_LOCKED if the capability holds no lock, or error code
+    linker.func_wrap(
+        "env",
+        "sp_fs_unlock",
+        |mut caller: Caller<'_, HostState>, file_cap: i64| -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_unlock", 0);
+            check_fuel(&mut caller, fuel_cost::SYNC_OPERATION)?;
+
+            let caps = &caller.data().capabilities;
+            let handle = match calls::file(caps, file_cap, CapabilityRights::empty()) {
+                Ok(handle) => handle,
+                Err(code) => return Ok(code),
+            };
+
+            use crate::fs::ROOT_FS;
+            match ROOT_FS.unlock(handle) {
+                Ok(true) => Ok(0),
+                Ok(false) => Ok(error::NOT_LOCKED as i32),
+                Err(_) => Ok(error::FS_ERROR as i32),
+            }
+        },
+    )?;
+
+    // sp_fs_create(dir_cap: i64, path_ptr: i32, path_len: i32) -> i64
+    // Returns: a capability for a new empty file at `path` below `dir_cap`,
+    // which must grant WRITE, or error code
+    linker.func_wrap(
+        "env",
+        "sp_fs_create",
+        |mut caller: Caller<'_, HostState>,
+         dir_cap: i64,
+         path_ptr: i32,
+         path_len: i32|
+         -> Result<i64, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_fs_create", 0);
+            check_fuel(&mut caller, fuel_cost::FS_OPERATION)?;
+
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT),
+            };
+
+            let mut buffer = alloc::vec![0u8; path_len.max(0) as usize];
+            if memory
+                .read(&caller, path_ptr as usize, &mut buffer)
+                .is_err()
+            {
+                return Ok(error::MEMORY_READ_FAILED);
+            }
+            let path = match core::str::from_utf8(&buffer) {
+                Ok(s) => s,
+                Err(_) => return Ok(error::INVALID_UTF8),
+            };
+
+            match calls::fs_create(&mut caller.data_mut().capabilities, dir_cap, path) {
+                code if code < 0 => Ok(code),
+                handle => throttle(&caller, dir_cap, handle),
+            }
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Register scheduler-related host functions.
+fn register_scheduler_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    // sp_sched_yield(): Voluntarily yield control to the scheduler
+    linker.func_wrap(
+        "env",
+        "sp_sched_yield",
+        |_caller: Caller<'_, HostState>| -> Result<(), wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_sched_yield", 0);
+            Err(wasmi::core::Trap::from(HostTrap::Yield))
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Register synchronization host functions.
+fn register_sync_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    use crate::sync::registry;
+
+    // sp_mutex_create() -> i64
+    // Returns: mutex capability handle (positive) or error code (negative)
+    linker.func_wrap(
+        "env",
+        "sp_mutex_create",
+        |mut caller: Caller<'_, HostState>| -> Result<i64, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_mutex_create", 0);
+            check_fuel(&mut caller, fuel_cost::SYNC_CREATE)?;
+
+            let state = caller.data_mut();
+            let handle = registry::create_mutex(state.pid);
             let cap = Capability::new(CapabilityType::Mutex(handle), CapabilityRights::CALL);
-            let cap_id = caller.data_mut().add_capability(cap);
-            Ok(cap_id.as_u64() as i64)
+            state.sync_objects.push(cap.object);
+            Ok(state.grant(cap).as_raw())
         },
     )?;
 
@@ -601,12 +1135,12 @@ fn register_sync_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::
         "env",
         "sp_mutex_lock",
         |mut caller: Caller<'_, HostState>, cap: i64| -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_mutex_lock", 0);
             check_fuel(&mut caller, fuel_cost::SYNC_OPERATION)?;
 
-            let cap_id = CapId::from_u64(cap as u64);
             let handle = {
                 let host_state = caller.data();
-                match host_state.get_capability(cap_id) {
+                match host_state.capability(cap) {
                     Some(c) => match c.object {
                         CapabilityType::Mutex(h) => {
                             if c.rights.contains(CapabilityRights::CALL) {
@@ -644,12 +1178,12 @@ fn register_sync_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::
         "env",
         "sp_mutex_try_lock",
         |mut caller: Caller<'_, HostState>, cap: i64| -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_mutex_try_lock", 0);
             check_fuel(&mut caller, fuel_cost::SYNC_OPERATION)?;
 
-            let cap_id = CapId::from_u64(cap as u64);
             let handle = {
                 let host_state = caller.data();
-                match host_state.get_capability(cap_id) {
+                match host_state.capability(cap) {
                     Some(c) => match c.object {
                         CapabilityType::Mutex(h) => {
                             if c.rights.contains(CapabilityRights::CALL) {
@@ -682,12 +1216,12 @@ fn register_sync_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::
         "env",
         "sp_mutex_unlock",
         |mut caller: Caller<'_, HostState>, cap: i64| -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_mutex_unlock", 0);
             check_fuel(&mut caller, fuel_cost::SYNC_OPERATION)?;
 
-            let cap_id = CapId::from_u64(cap as u64);
             let handle = {
                 let host_state = caller.data();
-                match host_state.get_capability(cap_id) {
+                match host_state.capability(cap) {
                     Some(c) => match c.object {
                         CapabilityType::Mutex(h) => {
                             if c.rights.contains(CapabilityRights::CALL) {
@@ -717,21 +1251,23 @@ fn register_sync_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::
     )?;
 
     // sp_sem_create(permits: i32) -> i64
-    // Returns: semaphore capability ID (positive) or error code (negative)
+    // Returns: semaphore capability handle (positive) or error code (negative)
     linker.func_wrap(
         "env",
         "sp_sem_create",
         |mut caller: Caller<'_, HostState>, permits: i32| -> Result<i64, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_sem_create", 0);
             check_fuel(&mut caller, fuel_cost::SYNC_CREATE)?;
 
             if permits < 0 {
                 return Ok(error::PERMISSION_DENIED); // Invalid argument
             }
 
-            let handle = registry::create_semaphore(permits as usize);
+            let state = caller.data_mut();
+            let handle = registry::create_semaphore(permits as usize, state.pid);
             let cap = Capability::new(CapabilityType::Semaphore(handle), CapabilityRights::CALL);
-            let cap_id = caller.data_mut().add_capability(cap);
-            Ok(cap_id.as_u64() as i64)
+            state.sync_objects.push(cap.object);
+            Ok(state.grant(cap).as_raw())
         },
     )?;
 
@@ -742,12 +1278,12 @@ fn register_sync_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::
         "env",
         "sp_sem_acquire",
         |mut caller: Caller<'_, HostState>, cap: i64| -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_sem_acquire", 0);
             check_fuel(&mut caller, fuel_cost::SYNC_OPERATION)?;
 
-            let cap_id = CapId::from_u64(cap as u64);
             let handle = {
                 let host_state = caller.data();
-                match host_state.get_capability(cap_id) {
+                match host_state.capability(cap) {
                     Some(c) => match c.object {
                         CapabilityType::Semaphore(h) => {
                             if c.rights.contains(CapabilityRights::CALL) {
@@ -780,12 +1316,12 @@ fn register_sync_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::
         "env",
         "sp_sem_try_acquire",
         |mut caller: Caller<'_, HostState>, cap: i64| -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_sem_try_acquire", 0);
             check_fuel(&mut caller, fuel_cost::SYNC_OPERATION)?;
 
-            let cap_id = CapId::from_u64(cap as u64);
             let handle = {
                 let host_state = caller.data();
-                match host_state.get_capability(cap_id) {
+                match host_state.capability(cap) {
                     Some(c) => match c.object {
                         CapabilityType::Semaphore(h) => {
                             if c.rights.contains(CapabilityRights::CALL) {
@@ -818,12 +1354,12 @@ fn register_sync_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::
         "env",
         "sp_sem_release",
         |mut caller: Caller<'_, HostState>, cap: i64| -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_sem_release", 0);
             check_fuel(&mut caller, fuel_cost::SYNC_OPERATION)?;
 
-            let cap_id = CapId::from_u64(cap as u64);
             let handle = {
                 let host_state = caller.data();
-                match host_state.get_capability(cap_id) {
+                match host_state.capability(cap) {
                     Some(c) => match c.object {
                         CapabilityType::Semaphore(h) => {
                             if c.rights.contains(CapabilityRights::CALL) {
@@ -847,5 +1383,1150 @@ fn register_sync_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::
         },
     )?;
 
+    // sp_futex_wait(addr: i32, expected: i32, timeout_ms: i64) -> i32
+    // Returns: 0 once woken, VALUE_CHANGED if the word at addr does not
+    // hold expected, TIMED_OUT, or error code
+    // addr must be 4-byte aligned; timeout_ms < 0 waits forever. Blocks via
+    // HostTrap::FutexWait
+    linker.func_wrap(
+        "env",
+        "sp_futex_wait",
+        |mut caller: Caller<'_, HostState>,
+         addr: i32,
+         expected: i32,
+         timeout_ms: i64|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_futex_wait", 0);
+            check_fuel(&mut caller, fuel_cost::SYNC_OPERATION)?;
+
+            let key = match futex_key(&caller, addr) {
+                Ok(key) => key,
+                Err(e) => return Ok(e),
+            };
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let mut word = [0u8; 4];
+            if memory.read(&caller, key.addr as usize, &mut word).is_err() {
+                return Ok(error::MEMORY_READ_FAILED as i32);
+            }
+            if i32::from_le_bytes(word) != expected {
+                return Ok(error::VALUE_CHANGED as i32);
+            }
+            if timeout_ms == 0 {
+                return Ok(error::TIMED_OUT as i32);
+            }
+
+            let deadline = u64::try_from(timeout_ms)
+                .ok()
+                .map(|ms| time::now_ms().saturating_add(ms));
+            Err(wasmi::core::Trap::from(HostTrap::FutexWait {
+                waiter: futex::wait(key),
+                deadline,
+            }))
+        },
+    )?;
+
+    // sp_futex_wake(addr: i32, count: i32) -> i32
+    // Returns: number of waiters woken, or error code
+    linker.func_wrap(
+        "env",
+        "sp_futex_wake",
+        |mut caller: Caller<'_, HostState>,
+         addr: i32,
+         count: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_futex_wake", 0);
+            check_fuel(&mut caller, fuel_cost::SYNC_OPERATION)?;
+
+            let key = match futex_key(&caller, addr) {
+                Ok(key) => key,
+                Err(e) => return Ok(e),
+            };
+            let Ok(count) = usize::try_from(count) else {
+                return Ok(error::INVALID_ARGUMENT as i32);
+            };
+            Ok(futex::wake(key, count) as i32)
+        },
+    )?;
+
+    Ok(())
+}
+
+/// The futex `addr` names in the caller's memory, or the error for a
+/// misaligned address.
+fn futex_key(caller: &Caller<'_, HostState>, addr: i32) -> Result<FutexKey, i32> {
+    let addr = u32::try_from(addr).map_err(|_| error::INVALID_ARGUMENT as i32)?;
+    if addr % 4 != 0 {
+        return Err(error::INVALID_ARGUMENT as i32);
+    }
+    Ok(FutexKey {
+        space: caller.data().futex_space,
+        addr,
+    })
+}
+
+/// Register clock, sleep and timer host functions.
+///
+/// All of them require a Timer capability: READ for the clock, CALL for
+/// sleeping and timers. Timer expirations are delivered as events on the
+/// process's event queue.
+fn register_timer_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    // sp_clock_monotonic_ms() -> i64
+    // Returns: milliseconds since boot, or error code
+    linker.func_wrap(
+        "env",
+        "sp_clock_monotonic_ms",
+        |mut caller: Caller<'_, HostState>| -> Result<i64, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_clock_monotonic_ms", 0);
+            check_fuel(&mut caller, fuel_cost::TIMER_OPERATION)?;
+
+            if !caller.data().has_timer_rights(CapabilityRights::READ) {
+                return Ok(error::PERMISSION_DENIED);
+            }
+            Ok(time::now_ms() as i64)
+        },
+    )?;
+
+    // sp_clock_resolution_ms() -> i64
+    // Returns: clock resolution in milliseconds, or error code
+    linker.func_wrap(
+        "env",
+        "sp_clock_resolution_ms",
+        |mut caller: Caller<'_, HostState>| -> Result<i64, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_clock_resolution_ms", 0);
+            check_fuel(&mut caller, fuel_cost::TIMER_OPERATION)?;
+
+            if !caller.data().has_timer_rights(CapabilityRights::READ) {
+                return Ok(error::PERMISSION_DENIED);
+            }
+            Ok(time::TICK_MS as i64)
+        },
+    )?;
+
+    // sp_sleep_ms(ms: i64) -> i32
+    // Returns: 0 after sleeping, or error code
+    // Suspends via HostTrap::Sleep until the deadline has passed
+    linker.func_wrap(
+        "env",
+        "sp_sleep_ms",
+        |mut caller: Caller<'_, HostState>, ms: i64| -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_sleep_ms", 0);
+            check_fuel(&mut caller, fuel_cost::TIMER_OPERATION)?;
+
+            if !caller.data().has_timer_rights(CapabilityRights::CALL) {
+                return Ok(error::PERMISSION_DENIED as i32);
+            }
+            if ms < 0 {
+                return Ok(error::INVALID_ARGUMENT as i32);
+            }
+            let deadline = time::now_ms().saturating_add(ms as u64);
+            Err(wasmi::core::Trap::from(HostTrap::Sleep(deadline)))
+        },
+    )?;
+
+    // sp_timer_create() -> i64
+    // Returns: timer ID (positive) or error code (negative)
+    linker.func_wrap(
+        "env",
+        "sp_timer_create",
+        |mut caller: Caller<'_, HostState>| -> Result<i64, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_timer_create", 0);
+            check_fuel(&mut caller, fuel_cost::TIMER_OPERATION)?;
+
+            let host_state = caller.data_mut();
+            if !host_state.has_timer_rights(CapabilityRights::CALL) {
+                return Ok(error::PERMISSION_DENIED);
+            }
+            match host_state.timers.create() {
+                Some(id) => Ok(id as i64),
+                None => Ok(error::TOO_MANY_TIMERS),
+            }
+        },
+    )?;
+
+    // sp_timer_arm(timer: i64, initial_ms: i64, period_ms: i64) -> i32
+    // Returns: 0 on success, or error code
+    // A period of 0 makes a one-shot timer
+    linker.func_wrap(
+        "env",
+        "sp_timer_arm",
+        |mut caller: Caller<'_, HostState>,
+         timer: i64,
+         initial_ms: i64,
+         period_ms: i64|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_timer_arm", 0);
+            check_fuel(&mut caller, fuel_cost::TIMER_OPERATION)?;
+
+            let host_state = caller.data_mut();
+            if !host_state.has_timer_rights(CapabilityRights::CALL) {
+                return Ok(error::PERMISSION_DENIED as i32);
+            }
+            if timer <= 0 || initial_ms < 0 || period_ms < 0 {
+                return Ok(error::INVALID_ARGUMENT as i32);
+            }
+            let id = timer as u64;
+            // Drop expirations of the previous schedule.
+            host_state.events.lock().remove_timer(id);
+            if host_state
+                .timers
+                .arm(id, time::now_ms(), initial_ms as u64, period_ms as u64)
+            {
+                Ok(0)
+            } else {
+                Ok(error::INVALID_HANDLE as i32)
+            }
+        },
+    )?;
+
+    // sp_timer_cancel(timer: i64) -> i32
+    // Returns: 0 on success, or error code
+    // Deletes the timer and any of its undelivered events
+    linker.func_wrap(
+        "env",
+        "sp_timer_cancel",
+        |mut caller: Caller<'_, HostState>, timer: i64| -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_timer_cancel", 0);
+            check_fuel(&mut caller, fuel_cost::TIMER_OPERATION)?;
+
+            let host_state = caller.data_mut();
+            if !host_state.has_timer_rights(CapabilityRights::CALL) {
+                return Ok(error::PERMISSION_DENIED as i32);
+            }
+            let id = timer as u64;
+            if host_state.timers.cancel(id) {
+                host_state.events.lock().remove_timer(id);
+                Ok(0)
+            } else {
+                Ok(error::INVALID_HANDLE as i32)
+            }
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Register event queue host functions.
+fn register_event_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    // sp_poll(events_ptr: i32, max: i32, timeout_ms: i64) -> i32
+    // Returns: number of events written, 0 on timeout, or error code
+    // Each event is an EVENT_RECORD_SIZE record; timeout_ms < 0 waits forever
+    // and 0 returns immediately. Blocks via HostTrap::Poll.
+    linker.func_wrap(
+        "env",
+        "sp_poll",
+        |mut caller: Caller<'_, HostState>,
+         events_ptr: i32,
+         max: i32,
+         timeout_ms: i64|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_poll", 0);
+            check_fuel(&mut caller, fuel_cost::POLL)?;
+
+            if max <= 0 {
+                return Ok(error::INVALID_ARGUMENT as i32);
+            }
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+
+            if !caller.data().events.lock().is_empty() {
+                check_fuel(&mut caller, fuel_cost::MEMORY_IO)?;
+                return Ok(deliver_events(
+                    &mut caller,
+                    memory,
+                    events_ptr as u32,
+                    max as u32,
+                ));
+            }
+            if timeout_ms == 0 {
+                return Ok(0);
+            }
+
+            let deadline = u64::try_from(timeout_ms)
+                .ok()
+                .map(|ms| time::now_ms().saturating_add(ms));
+            Err(wasmi::core::Trap::from(HostTrap::Poll {
+                ptr: events_ptr as u32,
+                max: max as u32,
+                deadline,
+            }))
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Register process control host functions.
+fn register_process_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    use super::process::{self, Signal, SignalError};
+
+    // sp_kill(process_cap: i64, signal: i32) -> i32
+    // Returns: 0 on success, or error code
+    // Requires a Process capability with CALL rights
+    linker.func_wrap(
+        "env",
+        "sp_kill",
+        |mut caller: Caller<'_, HostState>,
+         process_cap: i64,
+         signal: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_kill", 0);
+            check_fuel(&mut caller, fuel_cost::SIGNAL)?;
+
+            let pid = {
+                let host_state = caller.data();
+                match host_state.capability(process_cap) {
+                    Some(c) => match c.object {
+                        CapabilityType::Process(pid) => {
+                            if c.rights.contains(CapabilityRights::CALL) {
+                                pid
+                            } else {
+                                return Ok(error::PERMISSION_DENIED as i32);
+                            }
+                        }
+                        _ => return Ok(error::INVALID_HANDLE as i32),
+                    },
+                    None => return Ok(error::CAP_NOT_FOUND as i32),
+                }
+            };
+
+            let Some(signal) = u32::try_from(signal).ok().and_then(Signal::from_number) else {
+                return Ok(error::INVALID_ARGUMENT as i32);
+            };
+            match process::signal(pid, signal) {
+                Ok(()) => Ok(0),
+                Err(SignalError::NoSuchProcess) => Ok(error::NO_SUCH_PROCESS as i32),
+                Err(SignalError::UnknownSignal) => Ok(error::INVALID_ARGUMENT as i32),
+                Err(SignalError::QueueFull) => Ok(error::QUEUE_FULL as i32),
+            }
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Register serial port host functions.
+///
+/// A Serial capability comes from `wasm run --serial <n>` or from opening
+/// `/dev/serial<n>` through a directory capability; READ allows
+/// `sp_serial_read`, WRITE `sp_serial_write`.
+fn register_serial_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    use crate::arch::x86_64::serial;
+
+    /// Port number of the serial port `serial_cap` grants with `rights`.
+    fn serial_port(
+        caller: &Caller<'_, HostState>,
+        serial_cap: i64,
+        rights: CapabilityRights,
+    ) -> Result<u8, i32> {
+        let cap = caller
+            .data()
+            .capability(serial_cap)
+            .ok_or(error::CAP_NOT_FOUND as i32)?;
+        let CapabilityType::Serial { port } = cap.object else {
+            return Err(error::NOT_A_SERIAL_PORT as i32);
+        };
+        if !cap.rights.contains(rights) {
+            return Err(error::PERMISSION_DENIED as i32);
+        }
+        serial::COM_PORTS
+            .iter()
+            .position(|base| *base == port)
+            .map(|index| index as u8 + 1)
+            .ok_or(error::DEVICE_UNAVAILABLE as i32)
+    }
+
+    // sp_serial_write(serial_cap: i64, buf_ptr: i32, buf_len: i32) -> i32
+    // Returns: bytes written, or error code
+    linker.func_wrap(
+        "env",
+        "sp_serial_write",
+        |mut caller: Caller<'_, HostState>,
+         serial_cap: i64,
+         buf_ptr: i32,
+         buf_len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_serial_write", 0);
+            check_fuel(&mut caller, fuel_cost::SERIAL_IO)?;
+
+            let com = match serial_port(&caller, serial_cap, CapabilityRights::WRITE) {
+                Ok(com) => com,
+                Err(code) => return Ok(code),
+            };
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let mut buffer = alloc::vec![0u8; buf_len.max(0) as usize];
+            if memory.read(&caller, buf_ptr as usize, &mut buffer).is_err() {
+                return Ok(error::MEMORY_READ_FAILED as i32);
+            }
+            match serial::write_port(com, &buffer) {
+                Ok(count) => throttle(&caller, serial_cap, count as i32),
+                Err(_) => Ok(error::DEVICE_UNAVAILABLE as i32),
+            }
+        },
+    )?;
+
+    // sp_serial_read(serial_cap: i64, buf_ptr: i32, buf_len: i32) -> i32
+    // Returns: bytes read (0 if none are waiting), or error code
+    linker.func_wrap(
+        "env",
+        "sp_serial_read",
+        |mut caller: Caller<'_, HostState>,
+         serial_cap: i64,
+         buf_ptr: i32,
+         buf_len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_serial_read", 0);
+            check_fuel(&mut caller, fuel_cost::SERIAL_IO)?;
+
+            let com = match serial_port(&caller, serial_cap, CapabilityRights::READ) {
+                Ok(com) => com,
+                Err(code) => return Ok(code),
+            };
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let mut buffer = alloc::vec![0u8; buf_len.max(0) as usize];
+            let count = match serial::read_port(com, &mut buffer) {
+                Ok(count) => count,
+                Err(_) => return Ok(error::DEVICE_UNAVAILABLE as i32),
+            };
+            if memory
+                .write(&mut caller, buf_ptr as usize, &buffer[..count])
+                .is_err()
+            {
+                return Ok(error::MEMORY_WRITE_FAILED as i32);
+            }
+            throttle(&caller, serial_cap, count as i32)
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Register the standard stream functions.
+///
+/// stdin and stdout are pipes when the process was started in a pipeline
+/// (see `pipe`); otherwise stdin is empty and stdout goes to the console.
+/// Neither blocks: the caller yields and retries.
+fn register_stdio_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    // sp_stdout_write(buf_ptr: i32, buf_len: i32) -> i32
+    // Returns: bytes written (0 if the pipe is full), or error code
+    linker.func_wrap(
+        "env",
+        "sp_stdout_write",
+        |mut caller: Caller<'_, HostState>,
+         buf_ptr: i32,
+         buf_len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_stdout_write", 0);
+            check_fuel(&mut caller, fuel_cost::PIPE_IO)?;
+
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let mut buffer = alloc::vec![0u8; buf_len.max(0) as usize];
+            if memory.read(&caller, buf_ptr as usize, &mut buffer).is_err() {
+                return Ok(error::MEMORY_READ_FAILED as i32);
+            }
+            let Some(stdout) = &caller.data().stdout else {
+                let text = String::from_utf8_lossy(&buffer);
+                crate::print!("{}", text);
+                // Shell tests read process output from serial
+                #[cfg(feature = "terminal")]
+                if crate::testutil::shell::enabled() {
+                    crate::serial_print!("{}", text);
+                }
+                return Ok(buffer.len() as i32);
+            };
+            match stdout.write(&buffer) {
+                Ok(count) => Ok(count as i32),
+                Err(_) => Ok(error::BROKEN_PIPE as i32),
+            }
+        },
+    )?;
+
+    // sp_stdin_read(buf_ptr: i32, buf_len: i32) -> i32
+    // Returns: bytes read (0 at end of input), or error code
+    linker.func_wrap(
+        "env",
+        "sp_stdin_read",
+        |mut caller: Caller<'_, HostState>,
+         buf_ptr: i32,
+         buf_len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_stdin_read", 0);
+            check_fuel(&mut caller, fuel_cost::PIPE_IO)?;
+
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let mut buffer = alloc::vec![0u8; buf_len.max(0) as usize];
+            let count = match &caller.data().stdin {
+                None => 0,
+                Some(stdin) => match stdin.read(&mut buffer) {
+                    Ok(count) => count,
+                    Err(PipeError::WouldBlock) => return Ok(error::WOULD_BLOCK as i32),
+                    Err(PipeError::Broken) => return Ok(error::BROKEN_PIPE as i32),
+                },
+            };
+            if memory
+                .write(&mut caller, buf_ptr as usize, &buffer[..count])
+                .is_err()
+            {
+                return Ok(error::MEMORY_WRITE_FAILED as i32);
+            }
+            Ok(count as i32)
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Register the configuration store functions (see `crate::config`).
+///
+/// A Config capability comes from `wasm run --config`; READ allows
+/// `sp_cfg_get`, WRITE `sp_cfg_set`.
+fn register_config_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    use crate::config::{self, ConfigError, MAX_KEY_LEN, MAX_VALUE_LEN};
+
+    /// Check that `cfg_cap` is a Config capability with `rights`.
+    fn check_config(
+        caller: &Caller<'_, HostState>,
+        cfg_cap: i64,
+        rights: CapabilityRights,
+    ) -> Result<(), i32> {
+        let cap = caller
+            .data()
+            .capability(cfg_cap)
+            .ok_or(error::CAP_NOT_FOUND as i32)?;
+        if cap.object != CapabilityType::Config || !cap.rights.contains(rights) {
+            return Err(error::PERMISSION_DENIED as i32);
+        }
+        Ok(())
+    }
+
+    // sp_cfg_get(cfg_cap: i64, key_ptr: i32, key_len: i32, buf_ptr: i32, buf_len: i32) -> i32
+    // Returns: length of the value (written to the buffer), or error code
+    linker.func_wrap(
+        "env",
+        "sp_cfg_get",
+        |mut caller: Caller<'_, HostState>,
+         cfg_cap: i64,
+         key_ptr: i32,
+         key_len: i32,
+         buf_ptr: i32,
+         buf_len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_cfg_get", 0);
+            check_fuel(&mut caller, fuel_cost::CONFIG)?;
+
+            if let Err(code) = check_config(&caller, cfg_cap, CapabilityRights::READ) {
+                return Ok(code);
+            }
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let key = match read_str(&caller, &memory, key_ptr, key_len, MAX_KEY_LEN) {
+                Ok(key) => key,
+                Err(code) => return Ok(code),
+            };
+            let Some(value) = config::get(&key) else {
+                return Ok(error::NO_SUCH_KEY as i32);
+            };
+            if value.len() > buf_len.max(0) as usize {
+                return Ok(error::BUFFER_TOO_SMALL as i32);
+            }
+            if memory
+                .write(&mut caller, buf_ptr as usize, value.as_bytes())
+                .is_err()
+            {
+                return Ok(error::MEMORY_WRITE_FAILED as i32);
+            }
+            throttle(&caller, cfg_cap, value.len() as i32)
+        },
+    )?;
+
+    // sp_cfg_set(cfg_cap: i64, key_ptr: i32, key_len: i32, val_ptr: i32, val_len: i32) -> i32
+    // Returns: 0 on success, or error code
+    linker.func_wrap(
+        "env",
+        "sp_cfg_set",
+        |mut caller: Caller<'_, HostState>,
+         cfg_cap: i64,
+         key_ptr: i32,
+         key_len: i32,
+         val_ptr: i32,
+         val_len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_cfg_set", 0);
+            check_fuel(&mut caller, fuel_cost::CONFIG)?;
+
+            if let Err(code) = check_config(&caller, cfg_cap, CapabilityRights::WRITE) {
+                return Ok(code);
+            }
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let key = match read_str(&caller, &memory, key_ptr, key_len, MAX_KEY_LEN) {
+                Ok(key) => key,
+                Err(code) => return Ok(code),
+            };
+            let value = match read_str(&caller, &memory, val_ptr, val_len, MAX_VALUE_LEN) {
+                Ok(value) => value,
+                Err(code) => return Ok(code),
+            };
+            match config::set(&key, &value) {
+                Ok(()) => throttle(&caller, cfg_cap, 0),
+                Err(ConfigError::Full) => Ok(error::QUOTA_EXCEEDED as i32),
+                Err(_) => Ok(error::INVALID_ARGUMENT as i32),
+            }
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Close the sockets of processes that have exited, handing them to the
+/// stack to remove once closed (see `NetworkStack::detach`).
+///
+/// Returns `false`, leaving `sockets` as they are, if the stack is busy;
+/// try again later.
+#[cfg(feature = "net")]
+pub fn release_sockets(sockets: &mut Vec<TcpSocket>) -> bool {
+    if sockets.is_empty() {
+        return true;
+    }
+    let released = with_stack(|stack| {
+        let now = crate::services::now();
+        for socket in sockets.drain(..) {
+            socket.close(stack);
+            stack.detach(socket.handle(), now);
+        }
+    });
+    released.is_ok()
+}
+
+/// Run `f` on the network stack. The shell may hold the stack while a
+/// process runs, so a busy stack is WOULD_BLOCK rather than a wait.
+#[cfg(feature = "net")]
+fn with_stack<R>(f: impl FnOnce(&mut crate::net::NetworkStack) -> R) -> Result<R, i32> {
+    let shared = crate::services::net_stack().ok_or(error::DEVICE_UNAVAILABLE as i32)?;
+    let mut stack = shared.try_lock().ok_or(error::WOULD_BLOCK as i32)?;
+    Ok(f(&mut stack))
+}
+
+/// Send `data` on the socket `sock_cap` grants WRITE on.
+///
+/// Returns bytes queued, WOULD_BLOCK while the socket cannot send yet, or
+/// an error code (NET_ERROR once the connection is closed).
+#[cfg(feature = "net")]
+fn net_send_from(state: &HostState, sock_cap: i64, data: &[u8]) -> i32 {
+    let handle = match calls::socket(&state.capabilities, sock_cap, CapabilityRights::WRITE) {
+        Ok(handle) => handle,
+        Err(code) => return code,
+    };
+    let Some(socket) = state.sockets.get(&handle) else {
+        return error::INVALID_HANDLE as i32;
+    };
+    let result = with_stack(|stack| {
+        if socket.can_send(stack) {
+            socket
+                .send(stack, data)
+                .map(|sent| sent as i32)
+                .unwrap_or(error::NET_ERROR as i32)
+        } else if socket.is_connected(stack) {
+            error::WOULD_BLOCK as i32
+        } else {
+            error::NET_ERROR as i32
+        }
+    });
+    result.unwrap_or_else(|code| code)
+}
+
+/// Receive into `buf` from the socket `sock_cap` grants READ on.
+///
+/// Returns bytes received, 0 once the peer has closed the connection,
+/// WOULD_BLOCK while nothing is waiting, or an error code.
+#[cfg(feature = "net")]
+fn net_recv_into(state: &HostState, sock_cap: i64, buf: &mut [u8]) -> i32 {
+    let handle = match calls::socket(&state.capabilities, sock_cap, CapabilityRights::READ) {
+        Ok(handle) => handle,
+        Err(code) => return code,
+    };
+    let Some(socket) = state.sockets.get(&handle) else {
+        return error::INVALID_HANDLE as i32;
+    };
+    let result = with_stack(|stack| {
+        if socket.can_recv(stack) {
+            socket
+                .recv(stack, buf)
+                .map(|count| count as i32)
+                .unwrap_or(error::NET_ERROR as i32)
+        } else if stack.get_tcp_socket(socket.handle()).may_recv() || socket.is_connected(stack) {
+            // Waiting for data, or still listening or connecting
+            error::WOULD_BLOCK as i32
+        } else {
+            0
+        }
+    });
+    result.unwrap_or_else(|code| code)
+}
+
+/// Register network host functions.
+///
+/// A Network capability comes from `wasm run --net`. CONNECT allows
+/// `sp_net_connect`, LISTEN `sp_net_listen` on the capability's ports and
+/// RAW `sp_net_raw_send`. The sockets they open are Socket capabilities:
+/// READ allows `sp_net_recv`, WRITE `sp_net_send`. Nothing blocks; calls
+/// that would return WOULD_BLOCK and the caller retries. A process holds at
+/// most `MAX_PROCESS_SOCKETS` sockets, and OUT_OF_MEMORY reports that the
+/// kernel has no room for another.
+#[cfg(feature = "net")]
+fn register_net_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    use crate::net::{ConnectionSink, NetError, NetworkStack};
+    use smoltcp::wire::Ipv4Address;
+
+    /// Longest frame `sp_net_raw_send` sends (Ethernet header and MTU).
+    const RAW_FRAME_MAX: usize = 1514;
+
+    /// The Network capability `net_cap` if it has `rights`.
+    fn network_cap(
+        caller: &Caller<'_, HostState>,
+        net_cap: i64,
+        rights: CapabilityRights,
+    ) -> Result<Capability, i32> {
+        calls::network(&caller.data().capabilities, net_cap, rights)
+    }
+
+    /// Fail with TOO_MANY_SOCKETS if the process holds its limit.
+    fn check_socket_limit(caller: &Caller<'_, HostState>) -> Result<(), i32> {
+        if caller.data().sockets.len() >= MAX_PROCESS_SOCKETS {
+            return Err(error::TOO_MANY_SOCKETS as i32);
+        }
+        Ok(())
+    }
+
+    /// Open a TCP socket for the process, set it up with `setup` and grant
+    /// the process a Socket capability for it, derived from `net_cap`. The
+    /// connection's state changes are posted to the process as
+    /// `Event::Connection`s.
+    ///
+    /// Returns the capability's handle, or an error code.
+    fn open_process_socket(
+        caller: &mut Caller<'_, HostState>,
+        net_cap: i64,
+        setup: impl FnOnce(&mut TcpSocket, &mut NetworkStack) -> Result<(), NetError>,
+    ) -> i64 {
+        let state = caller.data_mut();
+        let handle = state.next_socket;
+        // Granted first, as events name the socket by its capability
+        let cap_handle = state.derive(
+            net_cap,
+            Capability::new(
+                CapabilityType::Socket(handle),
+                CapabilityRights::READ | CapabilityRights::WRITE,
+            ),
+        );
+        let events = state.events.clone();
+        let opened = with_stack(|stack| {
+            let socket = open_socket(stack, setup)?;
+            let sink = ConnectionSink::Process {
+                events,
+                socket: u64::from(cap_handle.value()),
+            };
+            stack.watch_connection(socket.handle(), sink, crate::services::now());
+            Ok(socket)
+        });
+
+        let state = caller.data_mut();
+        match opened {
+            Ok(Ok(socket)) => {
+                state.next_socket += 1;
+                state.sockets.insert(handle, socket);
+                cap_handle.as_raw()
+            }
+            Ok(Err(code)) | Err(code) => {
+                state.close(cap_handle.as_raw());
+                i64::from(code)
+            }
+        }
+    }
+
+    /// Open a TCP socket and set it up with `setup`, releasing it on
+    /// failure.
+    fn open_socket(
+        stack: &mut NetworkStack,
+        setup: impl FnOnce(&mut TcpSocket, &mut NetworkStack) -> Result<(), NetError>,
+    ) -> Result<TcpSocket, i32> {
+        let mut socket = match TcpSocket::new(stack) {
+            Ok(socket) => socket,
+            Err(NetError::OutOfMemory) => return Err(error::OUT_OF_MEMORY as i32),
+            Err(_) => return Err(error::NET_ERROR as i32),
+        };
+        match setup(&mut socket, stack) {
+            Ok(()) => Ok(socket),
+            Err(_) => {
+                stack.release_socket(socket.handle());
+                Err(error::NET_ERROR as i32)
+            }
+        }
+    }
+
+    // sp_net_connect(net_cap: i64, addr: i32, port: i32) -> i64
+    // Returns: Socket capability handle, or error code
+    // The address is an IPv4 address in network byte order (10.0.2.2 is
+    // 0x0A000202). Requires CONNECT rights; the connection completes in the
+    // background, and sends return WOULD_BLOCK until it does. A CONNECTION
+    // event reports when it is established, closed, refused or timed out.
+    linker.func_wrap(
+        "env",
+        "sp_net_connect",
+        |mut caller: Caller<'_, HostState>,
+         net_cap: i64,
+         addr: i32,
+         port: i32|
+         -> Result<i64, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_net_connect", 0);
+            check_fuel(&mut caller, fuel_cost::NET_IO)?;
+
+            if let Err(code) = network_cap(&caller, net_cap, CapabilityRights::CONNECT)
+                .and_then(|_| check_socket_limit(&caller))
+            {
+                return Ok(i64::from(code));
+            }
+            let Ok(port) = u16::try_from(port) else {
+                return Ok(error::INVALID_ARGUMENT);
+            };
+            let addr = Ipv4Address::from_bytes(&(addr as u32).to_be_bytes());
+            match open_process_socket(&mut caller, net_cap, |socket, stack| {
+                socket
+                    .connect(stack, addr, port)
+                    .map_err(sovelma_common::error::Error::into_kind)
+            }) {
+                code if code < 0 => Ok(code),
+                socket => throttle(&caller, net_cap, socket),
+            }
+        },
+    )?;
+
+    // sp_net_listen(net_cap: i64, port: i32) -> i64
+    // Returns: Socket capability handle, or error code
+    // Requires LISTEN rights covering `port`. The socket takes one
+    // connection; receives return WOULD_BLOCK until a peer connects, which
+    // a CONNECTION event reports.
+    linker.func_wrap(
+        "env",
+        "sp_net_listen",
+        |mut caller: Caller<'_, HostState>,
+         net_cap: i64,
+         port: i32|
+         -> Result<i64, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_net_listen", 0);
+            check_fuel(&mut caller, fuel_cost::NET_IO)?;
+
+            let cap = match network_cap(&caller, net_cap, CapabilityRights::empty()) {
+                Ok(cap) => cap,
+                Err(code) => return Ok(i64::from(code)),
+            };
+            let Ok(port) = u16::try_from(port) else {
+                return Ok(error::INVALID_ARGUMENT);
+            };
+            if !cap.permits_listen(port) {
+                return Ok(error::PERMISSION_DENIED);
+            }
+            if let Err(code) = check_socket_limit(&caller) {
+                return Ok(i64::from(code));
+            }
+            match open_process_socket(&mut caller, net_cap, |socket, stack| {
+                socket.listen(stack, port)
+            }) {
+                code if code < 0 => Ok(code),
+                socket => throttle(&caller, net_cap, socket),
+            }
+        },
+    )?;
+
+    // sp_net_send(sock_cap: i64, buf_ptr: i32, buf_len: i32) -> i32
+    // Returns: bytes queued, WOULD_BLOCK while the socket cannot send yet,
+    // or error code (NET_ERROR once the connection is closed)
+    linker.func_wrap(
+        "env",
+        "sp_net_send",
+        |mut caller: Caller<'_, HostState>,
+         sock_cap: i64,
+         buf_ptr: i32,
+         buf_len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_net_send", 0);
+            check_fuel(&mut caller, fuel_cost::NET_IO)?;
+
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let mut buffer = alloc::vec![0u8; buf_len.max(0) as usize];
+            if memory.read(&caller, buf_ptr as usize, &mut buffer).is_err() {
+                return Ok(error::MEMORY_READ_FAILED as i32);
+            }
+            match net_send_from(caller.data(), sock_cap, &buffer) {
+                sent if sent < 0 => Ok(sent),
+                sent => throttle(&caller, sock_cap, sent),
+            }
+        },
+    )?;
+
+    // sp_net_recv(sock_cap: i64, buf_ptr: i32, buf_len: i32) -> i32
+    // Returns: bytes received, 0 once the peer has closed the connection,
+    // WOULD_BLOCK while nothing is waiting, or error code
+    linker.func_wrap(
+        "env",
+        "sp_net_recv",
+        |mut caller: Caller<'_, HostState>,
+         sock_cap: i64,
+         buf_ptr: i32,
+         buf_len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_net_recv", 0);
+            check_fuel(&mut caller, fuel_cost::NET_IO)?;
+
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let mut buffer = alloc::vec![0u8; buf_len.max(0) as usize];
+            let count = net_recv_into(caller.data(), sock_cap, &mut buffer);
+            if count <= 0 {
+                return Ok(count);
+            }
+            if memory
+                .write(&mut caller, buf_ptr as usize, &buffer[..count as usize])
+                .is_err()
+            {
+                return Ok(error::MEMORY_WRITE_FAILED as i32);
+            }
+            throttle(&caller, sock_cap, count)
+        },
+    )?;
+
+    // sp_net_close(sock_cap: i64) -> i32
+    // Returns: 0 on success, or error code
+    // Closes the connection (queued data is still sent) and revokes the
+    // capability.
+    linker.func_wrap(
+        "env",
+        "sp_net_close",
+        |mut caller: Caller<'_, HostState>, sock_cap: i64| -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_net_close", 0);
+            check_fuel(&mut caller, fuel_cost::NET_IO)?;
+
+            let caps = &caller.data().capabilities;
+            let handle = match calls::socket(caps, sock_cap, CapabilityRights::empty()) {
+                Ok(handle) => handle,
+                Err(code) => return Ok(code),
+            };
+            let Some(socket) = caller.data().sockets.get(&handle) else {
+                return Ok(error::INVALID_HANDLE as i32);
+            };
+            let closed = with_stack(|stack| {
+                socket.close(stack);
+                stack.detach(socket.handle(), crate::services::now());
+            });
+            if let Err(code) = closed {
+                return Ok(code);
+            }
+            let state = caller.data_mut();
+            state.sockets.remove(&handle);
+            state.close(sock_cap);
+            Ok(0)
+        },
+    )?;
+
+    // sp_net_raw_send(net_cap: i64, buf_ptr: i32, buf_len: i32) -> i32
+    // Returns: bytes sent, WOULD_BLOCK if the device is busy, or error code
+    // Sends a complete Ethernet frame as is. Requires RAW rights.
+    linker.func_wrap(
+        "env",
+        "sp_net_raw_send",
+        |mut caller: Caller<'_, HostState>,
+         net_cap: i64,
+         buf_ptr: i32,
+         buf_len: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_net_raw_send", 0);
+            check_fuel(&mut caller, fuel_cost::NET_IO)?;
+
+            if let Err(code) = network_cap(&caller, net_cap, CapabilityRights::RAW) {
+                return Ok(code);
+            }
+            let len = match usize::try_from(buf_len) {
+                Ok(len) if len <= RAW_FRAME_MAX => len,
+                _ => return Ok(error::INVALID_ARGUMENT as i32),
+            };
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let mut frame = alloc::vec![0u8; len];
+            if memory.read(&caller, buf_ptr as usize, &mut frame).is_err() {
+                return Ok(error::MEMORY_READ_FAILED as i32);
+            }
+            match with_stack(|stack| stack.send_raw(&frame, crate::services::now())) {
+                Ok(true) => throttle(&caller, net_cap, len as i32),
+                Ok(false) => Ok(error::WOULD_BLOCK as i32),
+                Err(code) => Ok(code),
+            }
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Fuel an `sp_batch` operation costs; the same as the single call.
+fn batch_op_cost(op: &BatchOp) -> u64 {
+    match op.op {
+        batch::op::FS_READ => fuel_cost::FS_OPERATION + fuel_cost::MEMORY_IO,
+        batch::op::FS_WRITE => {
+            let kib = (op.len as usize).min(FS_WRITE_MAX).div_ceil(1024) as u64;
+            fuel_cost::FS_OPERATION + kib * fuel_cost::FS_WRITE_KIB
+        }
+        _ => fuel_cost::NET_IO,
+    }
+}
+
+/// Register the network host functions of a kernel built without `net`.
+///
+/// The imports resolve, so modules that use them still load; every call
+/// fails with DEVICE_UNAVAILABLE.
+#[cfg(not(feature = "net"))]
+fn register_net_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    const UNAVAILABLE: i32 = error::DEVICE_UNAVAILABLE as i32;
+
+    linker.func_wrap("env", "sp_net_connect", |_: i64, _: i32, _: i32| {
+        error::DEVICE_UNAVAILABLE
+    })?;
+    linker.func_wrap("env", "sp_net_listen", |_: i64, _: i32| {
+        error::DEVICE_UNAVAILABLE
+    })?;
+    linker.func_wrap("env", "sp_net_send", |_: i64, _: i32, _: i32| UNAVAILABLE)?;
+    linker.func_wrap("env", "sp_net_recv", |_: i64, _: i32, _: i32| UNAVAILABLE)?;
+    linker.func_wrap("env", "sp_net_close", |_: i64| UNAVAILABLE)?;
+    linker.func_wrap("env", "sp_net_raw_send", |_: i64, _: i32, _: i32| {
+        UNAVAILABLE
+    })?;
+    Ok(())
+}
+
+/// Register the batch host function (see `batch`).
+fn register_batch_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+    // sp_batch(ops_ptr: i32, count: i32) -> i32
+    // Returns: operations run, or error code
+    // Runs up to BATCH_MAX operation records at `ops_ptr` in order and
+    // stores each one's result in its record. Nothing runs unless every
+    // record is valid. Fewer than `count` run when the slice's fuel would
+    // not cover them; the caller yields and submits the rest.
+    linker.func_wrap(
+        "env",
+        "sp_batch",
+        |mut caller: Caller<'_, HostState>,
+         ops_ptr: i32,
+         count: i32|
+         -> Result<i32, wasmi::core::Trap> {
+            let _span = trace::span(Category::HostCall, "sp_batch", 0);
+            let count = match usize::try_from(count) {
+                Ok(count) if count <= BATCH_MAX => count,
+                _ => return Ok(error::INVALID_ARGUMENT as i32),
+            };
+            let memory = match caller.get_export("memory") {
+                Some(wasmi::Extern::Memory(m)) => m,
+                _ => return Ok(error::NO_MEMORY_EXPORT as i32),
+            };
+            let mut records = alloc::vec![0u8; count * BATCH_RECORD_SIZE];
+            if memory
+                .read(&caller, ops_ptr as usize, &mut records)
+                .is_err()
+            {
+                return Ok(error::MEMORY_READ_FAILED as i32);
+            }
+
+            // Validate every operation and buffer before running any
+            let memory_size = memory.data(&caller).len();
+            let mut ops = Vec::with_capacity(count);
+            for record in records.chunks_exact(BATCH_RECORD_SIZE) {
+                let Some(op) = record.try_into().ok().and_then(BatchOp::decode) else {
+                    return Ok(error::INVALID_ARGUMENT as i32);
+                };
+                let Some(buffer) = op.buffer(memory_size) else {
+                    return Ok(error::MEMORY_READ_FAILED as i32);
+                };
+                ops.push((op, buffer));
+            }
+
+            // One fuel check, for as many operations as the slice has fuel for
+            let available = caller
+                .data()
+                .fuel_remaining
+                .saturating_sub(fuel_cost::YIELD_THRESHOLD);
+            let mut cost = fuel_cost::BATCH_CALL;
+            let mut runnable = 0;
+            for (op, _) in &ops {
+                let op_cost = batch_op_cost(op);
+                if runnable > 0 && cost + op_cost > available {
+                    break;
+                }
+                cost += op_cost;
+                runnable += 1;
+            }
+            check_fuel(&mut caller, cost)?;
+
+            // Operations through rate-limited capabilities take a token
+            // each; the batch returns once the emptiest bucket allows
+            let now = time::now_ms();
+            let mut until = None;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let results = records.chunks_exact_mut(BATCH_RECORD_SIZE);
+            for ((op, buffer), record) in ops.into_iter().take(runnable).zip(results) {
+                let buffer = &mut data[buffer];
+                let offset = op.offset as usize;
+                let result = match op.op {
+                    batch::op::FS_READ => {
+                        calls::fs_read(&state.capabilities, op.cap, buffer, offset)
+                    }
+                    batch::op::FS_WRITE => {
+                        let (caps, quota) = (&state.capabilities, &mut state.fs_quota);
+                        calls::fs_write(caps, quota, op.cap, buffer, offset)
+                    }
+                    #[cfg(feature = "net")]
+                    batch::op::NET_SEND => net_send_from(state, op.cap, buffer),
+                    #[cfg(feature = "net")]
+                    _ => net_recv_into(state, op.cap, buffer),
+                    #[cfg(not(feature = "net"))]
+                    _ => error::DEVICE_UNAVAILABLE as i32,
+                };
+                if result >= 0 {
+                    until = until.max(state.take_token(op.cap, now));
+                }
+                batch::set_result(record, result);
+            }
+
+            let ran = &records[..runnable * BATCH_RECORD_SIZE];
+            if memory.write(&mut caller, ops_ptr as usize, ran).is_err() {
+                return Ok(error::MEMORY_WRITE_FAILED as i32);
+            }
+            hold_until(until, runnable as i32)
+        },
+    )?;
+
     Ok(())
 }
@@ -0,0 +1,205 @@
+//! Shared WASM libraries.
+//!
+//! A library is a module loaded once with `wasm lib load` and linked into
+//! every process that imports from it, so common code (such as an SDK
+//! runtime) does not have to be compiled into each app. Import module names
+//! other than `env` name libraries: an app importing `sdk.alloc` is linked
+//! against the `alloc` export of the library registered as `sdk`.
+//!
+//! A library is compiled once but instantiated per process, inside that
+//! process's store. Its globals and memory are therefore private to the
+//! process, and its host calls run with the process's capabilities.
+//! Libraries may import from libraries loaded before them; loading in
+//! dependency order rules out cycles.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+use wasmi::{Extern, Linker, Module, Store};
+
+use super::HostState;
+
+/// Import module of the host functions; not available as a library name.
+pub const HOST_MODULE: &str = "env";
+
+/// Errors from managing libraries.
+#[derive(Debug)]
+pub enum LibraryError {
+    /// The name is empty or reserved.
+    InvalidName,
+    /// A library with that name is already loaded.
+    AlreadyLoaded,
+    /// No library with that name is loaded.
+    NotLoaded,
+    /// The library imports from one that is not loaded.
+    MissingDependency(String),
+    /// Another loaded library imports from this one.
+    InUse(String),
+    /// The module could not be compiled.
+    Wasm(wasmi::Error),
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LibraryError::InvalidName => write!(f, "invalid library name"),
+            LibraryError::AlreadyLoaded => write!(f, "library already loaded"),
+            LibraryError::NotLoaded => write!(f, "no such library"),
+            LibraryError::MissingDependency(name) => write!(f, "needs library {}", name),
+            LibraryError::InUse(name) => write!(f, "used by library {}", name),
+            LibraryError::Wasm(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<wasmi::Error> for LibraryError {
+    fn from(e: wasmi::Error) -> Self {
+        LibraryError::Wasm(e)
+    }
+}
+
+/// Summary of a loaded library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LibraryInfo {
+    /// Name imports refer to it by.
+    pub name: String,
+    /// Number of exports.
+    pub exports: usize,
+    /// Libraries it imports from.
+    pub dependencies: Vec<String>,
+}
+
+/// A compiled library.
+struct Library {
+    module: Module,
+    dependencies: Vec<String>,
+}
+
+/// Library namespace of a `WasmEngine`, shared by its clones.
+#[derive(Clone, Default)]
+pub(super) struct Libraries {
+    table: Arc<Mutex<BTreeMap<String, Library>>>,
+}
+
+/// Library names `module` imports from.
+fn dependencies(module: &Module) -> Vec<String> {
+    let mut names: Vec<String> = module
+        .imports()
+        .map(|import| import.module())
+        .filter(|name| *name != HOST_MODULE)
+        .map(ToString::to_string)
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+impl Libraries {
+    /// Register a compiled module under `name`.
+    pub(super) fn load(&self, name: &str, module: Module) -> Result<(), LibraryError> {
+        if name.is_empty() || name == HOST_MODULE {
+            return Err(LibraryError::InvalidName);
+        }
+        let mut table = self.table.lock();
+        if table.contains_key(name) {
+            return Err(LibraryError::AlreadyLoaded);
+        }
+        let dependencies = dependencies(&module);
+        if let Some(missing) = dependencies.iter().find(|dep| !table.contains_key(*dep)) {
+            return Err(LibraryError::MissingDependency(missing.clone()));
+        }
+        table.insert(
+            name.to_string(),
+            Library {
+                module,
+                dependencies,
+            },
+        );
+        Ok(())
+    }
+
+    /// Remove a library. Processes already linked against it keep their
+    /// instance.
+    pub(super) fn unload(&self, name: &str) -> Result<(), LibraryError> {
+        let mut table = self.table.lock();
+        if !table.contains_key(name) {
+            return Err(LibraryError::NotLoaded);
+        }
+        if let Some((user, _)) = table
+            .iter()
+            .find(|(_, lib)| lib.dependencies.iter().any(|dep| dep == name))
+        {
+            return Err(LibraryError::InUse(user.clone()));
+        }
+        table.remove(name);
+        Ok(())
+    }
+
+    /// Loaded libraries, by name.
+    pub(super) fn list(&self) -> Vec<LibraryInfo> {
+        self.table
+            .lock()
+            .iter()
+            .map(|(name, lib)| LibraryInfo {
+                name: name.clone(),
+                exports: lib.module.exports().count(),
+                dependencies: lib.dependencies.clone(),
+            })
+            .collect()
+    }
+
+    /// Instantiate the libraries `module` imports from in `store` and
+    /// define their exports in `linker`.
+    ///
+    /// Unknown import modules are left alone; instantiating `module`
+    /// reports them as missing imports.
+    pub(super) fn link(
+        &self,
+        linker: &mut Linker<HostState>,
+        store: &mut Store<HostState>,
+        module: &Module,
+    ) -> Result<(), wasmi::Error> {
+        let table = self.table.lock();
+        let mut linked = Vec::new();
+        for name in dependencies(module) {
+            link_library(&table, &name, linker, store, &mut linked)?;
+        }
+        Ok(())
+    }
+}
+
+/// Instantiate library `name` (after its dependencies) unless it is
+/// already in `linked`.
+fn link_library(
+    table: &BTreeMap<String, Library>,
+    name: &str,
+    linker: &mut Linker<HostState>,
+    store: &mut Store<HostState>,
+    linked: &mut Vec<String>,
+) -> Result<(), wasmi::Error> {
+    if linked.iter().any(|done| done == name) {
+        return Ok(());
+    }
+    let Some(lib) = table.get(name) else {
+        return Ok(());
+    };
+    for dep in &lib.dependencies {
+        link_library(table, dep, linker, store, linked)?;
+    }
+
+    let instance = linker
+        .instantiate(&mut *store, &lib.module)?
+        .start(&mut *store)?;
+    let exports: Vec<(String, Extern)> = instance
+        .exports(&*store)
+        .map(|export| (export.name().to_string(), export.into_extern()))
+        .collect();
+    for (export, item) in exports {
+        linker.define(name, &export, item)?;
+    }
+    linked.push(name.to_string());
+    Ok(())
+}
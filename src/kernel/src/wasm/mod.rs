@@ -9,6 +9,16 @@
 //! - **WasmEngine**: Shared engine configuration for all WASM modules.
 //! - **WasmProcess**: A running WASM instance with its own store and capabilities.
 //! - **WasmTask**: A Future adapter for running WASM functions as kernel tasks.
+//! - **ProcessManager**: Runs background processes and delivers signals to them.
+//!
+//! The scheduler side only relies on the `runtime::Engine`/`runtime::Process`
+//! traits, which `WasmEngine` and `WasmProcess` implement for wasmi.
+//!
+//! # Libraries
+//!
+//! Modules loaded with `WasmEngine::load_library` form a namespace that
+//! processes import from by library name (see `library`). They are linked
+//! into each process when it is spawned.
 //!
 //! # Security
 //!
@@ -24,34 +34,109 @@
 //!
 //! The host fuel mechanism ensures tasks yield cleanly (preserving the `ResumableInvocation`)
 //! before wasmi's fuel runs out (which would terminate the task).
-
+//!
+//! # Timers
+//!
+//! A process holding a Timer capability can sleep (`sp_sleep_ms`) and own
+//! timers (`sp_timer_*`). Both are driven from the task's poll: expired
+//! timers are queued as events, and a sleeping invocation is only resumed
+//! once its deadline has passed.
+//!
+//! # Events
+//!
+//! Each process has an event queue (see `event`) that kernel objects post
+//! to. `sp_poll` drains it, blocking via `HostTrap::Poll` while it is
+//! empty.
+//!
+//! # Pipes
+//!
+//! `sp_stdout_write` and `sp_stdin_read` are the process's standard
+//! streams. `ProcessManager::spawn_pipeline` connects them with pipes
+//! (see `pipe`); otherwise stdout goes to the console.
+//!
+//! # Memory
+//!
+//! Each process gets an arena of its own (see `allocator::arena`) that its
+//! linear memory is allocated from, so guest memory can grow well beyond
+//! the kernel heap. wasmi reports every growth to `HostState` as the
+//! store's resource limiter, which points the arena at that allocation
+//! alone; what host calls allocate stays on the kernel heap. The arena is
+//! unmapped when the process is dropped.
+
+use crate::allocator::arena::{self, ArenaGuard, ProcessMemory};
+use crate::fs::FsError;
 use alloc::boxed::Box;
 use core::{
     future::Future,
     pin::Pin,
     task::{Context, Poll},
 };
+use sovelma_common::error::{Context as _, Error, ErrorKind};
 use wasmi::{core::TrapCode, Engine, Linker, Module, Store};
 
-/// Fuel units granted per scheduler time slice.
-///
-/// This value controls how much computation a task can perform before
-/// yielding to the scheduler. Higher values = longer time slices.
-const FUEL_PER_SLICE: u64 = 10_000;
+/// Size of a WASM linear memory page.
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+/// Why a module could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file could not be read.
+    Fs(FsError),
+    /// wasmi rejected the module or could not instantiate it. Boxed, as
+    /// wasmi's errors are large.
+    Module(Box<wasmi::Error>),
+}
 
-mod host;
-pub use host::HostState;
+impl From<wasmi::Error> for LoadError {
+    fn from(e: wasmi::Error) -> Self {
+        LoadError::Module(Box::new(e))
+    }
+}
 
+impl core::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LoadError::Fs(e) => write!(f, "{}", e),
+            LoadError::Module(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl ErrorKind for LoadError {}
+
+pub mod batch;
+#[cfg(feature = "terminal")]
+pub mod commands;
+pub mod cpu;
+pub mod event;
+mod host;
+pub mod library;
+pub mod pipe;
+pub mod process;
+pub mod snapshot;
+pub mod timer;
+#[cfg(feature = "net")]
+pub use host::release_sockets;
+pub use host::{HostState, Teardown};
+pub use sovelma_core::wasm::{calls, capabilities, handles, manifest, runtime};
+use host::HostTrap;
+
+use alloc::string::String;
 use alloc::vec::Vec;
+use library::{Libraries, LibraryError, LibraryInfo};
+use runtime::{Process as _, Slice, FUEL_PER_SLICE};
+use snapshot::{GlobalValue, Snapshot, SnapshotError};
 use sovelma_common::capability::Capability;
 
 /// The shared WASM engine.
 ///
 /// The engine holds the compilation cache and configuration shared by all
-/// WASM instances. It is safe to clone (cheap Arc reference).
+/// WASM instances, and the libraries loaded into it. It is safe to clone
+/// (cheap Arc reference); clones share the library namespace.
 #[derive(Clone)]
 pub struct WasmEngine {
     engine: Engine,
+    libraries: Libraries,
 }
 
 impl WasmEngine {
@@ -62,14 +147,34 @@ impl WasmEngine {
 
         Self {
             engine: Engine::new(&config),
+            libraries: Libraries::default(),
         }
     }
 
+    /// Compile `wasm_bytes` and register it as library `name`.
+    ///
+    /// Every library the module imports from must already be loaded.
+    pub fn load_library(&self, name: &str, wasm_bytes: &[u8]) -> Result<(), LibraryError> {
+        let module = Module::new(&self.engine, wasm_bytes)?;
+        self.libraries.load(name, module)
+    }
+
+    /// Remove library `name` from the namespace.
+    pub fn unload_library(&self, name: &str) -> Result<(), LibraryError> {
+        self.libraries.unload(name)
+    }
+
+    /// Loaded libraries, by name.
+    pub fn libraries(&self) -> Vec<LibraryInfo> {
+        self.libraries.list()
+    }
+
     /// Create a new process from WASM bytes with initial capabilities.
     ///
     /// This is the **preferred** method for spawning WASM processes as it enforces
     /// the object-capability security model. The process will only have access
-    /// to resources explicitly granted via `initial_caps`.
+    /// to resources explicitly granted via `initial_caps`. Imports from
+    /// loaded libraries are linked in.
     ///
     /// # Arguments
     ///
@@ -91,14 +196,40 @@ impl WasmEngine {
         &self,
         wasm_bytes: &[u8],
         initial_caps: Vec<Capability>,
+    ) -> Result<WasmProcess, wasmi::Error> {
+        self.instantiate_with(wasm_bytes, HostState::with_capabilities(initial_caps))
+    }
+
+    /// Spawn the module at `path` in the root filesystem with
+    /// `initial_caps`. The error names the file and what failed.
+    pub fn spawn_file(
+        &self,
+        path: &str,
+        initial_caps: Vec<Capability>,
+    ) -> Result<WasmProcess, Error<LoadError>> {
+        let bytes = crate::fs::read_file(path).map_err(|e| e.map_kind(LoadError::Fs))?;
+        self.spawn_process_with_caps(&bytes, initial_caps)
+            .map_err(LoadError::from)
+            .context("loading", path)
+    }
+
+    /// Create a new process from WASM bytes with `host_state`.
+    fn instantiate_with(
+        &self,
+        wasm_bytes: &[u8],
+        host_state: HostState,
     ) -> Result<WasmProcess, wasmi::Error> {
         let module = Module::new(&self.engine, wasm_bytes)?;
-        let host_state = HostState::with_capabilities(initial_caps);
+        // Without a free arena the process still runs, on the kernel heap
+        let memory = ProcessMemory::new();
+        let _arena = memory.as_ref().map(ProcessMemory::enter);
         let mut store = Store::new(&self.engine, host_state);
+        store.limiter(|state| state);
         let mut linker = <Linker<HostState>>::new(&self.engine);
 
         // Define host functions
         host::register_functions(&mut linker)?;
+        self.libraries.link(&mut linker, &mut store, &module)?;
 
         let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
 
@@ -108,7 +239,37 @@ impl WasmEngine {
             crate::println!("[WASM] Failed to add fuel: {:?}", e);
         }
 
-        Ok(WasmProcess { store, instance })
+        Ok(WasmProcess {
+            store,
+            instance,
+            memory,
+        })
+    }
+
+    /// Instantiate a module again and load a snapshot's state into it.
+    ///
+    /// The snapshot's capabilities are derived anew from `parents` (see
+    /// `Snapshot::derive_capabilities`) and granted at their original
+    /// handles, so handles stored in the restored memory stay valid.
+    pub fn restore(
+        &self,
+        wasm_bytes: &[u8],
+        snapshot: &Snapshot,
+        parents: &[Capability],
+    ) -> Result<WasmProcess, SnapshotError> {
+        let capabilities = snapshot.derive_capabilities(parents)?;
+        let host_state = HostState::with_handles(capabilities.iter().cloned());
+        let restored = self
+            .instantiate_with(wasm_bytes, host_state)
+            .map_err(SnapshotError::from)
+            .and_then(|mut process| {
+                process.apply_state(snapshot)?;
+                Ok(process)
+            });
+        if restored.is_err() {
+            snapshot::release(&capabilities);
+        }
+        restored
     }
 
     /// Create a new process from WASM bytes without initial capabilities.
@@ -133,12 +294,59 @@ impl Default for WasmEngine {
     }
 }
 
+impl runtime::Engine for WasmEngine {
+    type Process = WasmProcess;
+    type Error = wasmi::Error;
+
+    fn instantiate(
+        &self,
+        wasm_bytes: &[u8],
+        capabilities: Vec<Capability>,
+    ) -> Result<WasmProcess, wasmi::Error> {
+        self.spawn_process_with_caps(wasm_bytes, capabilities)
+    }
+}
+
+/// Sends each growth of the linear memory to the process's arena.
+impl wasmi::ResourceLimiter for HostState {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool, wasmi::errors::MemoryError> {
+        arena::expect_growth(desired);
+        Ok(true)
+    }
+
+    fn memory_grow_failed(&mut self, _error: &wasmi::errors::MemoryError) {
+        arena::growth_failed();
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: u32,
+        _desired: u32,
+        _maximum: Option<u32>,
+    ) -> Result<bool, wasmi::errors::TableError> {
+        Ok(true)
+    }
+}
+
+/// Capabilities a process holds, with its handles for them.
+type HeldCapabilities = Vec<(handles::Handle, Capability)>;
+
 /// A running WASM process.
 ///
 /// Contains the wasmi store (with host state) and the instantiated module.
+/// Its linear memory lives in the process's own arena, released when the
+/// process is dropped.
 pub struct WasmProcess {
     store: Store<HostState>,
     instance: wasmi::Instance,
+    /// Declared last so the store, which holds the linear memory, is
+    /// dropped before the arena.
+    memory: Option<ProcessMemory>,
 }
 
 impl WasmProcess {
@@ -160,6 +368,7 @@ impl WasmProcess {
         name: &str,
         params: &[wasmi::Value],
     ) -> Result<Box<[wasmi::Value]>, wasmi::Error> {
+        let _arena = self.enter_memory();
         let func = self.instance.get_func(&self.store, name).ok_or_else(|| {
             wasmi::Error::from(wasmi::core::Trap::from(
                 wasmi::core::TrapCode::UnreachableCodeReached,
@@ -183,28 +392,348 @@ impl WasmProcess {
         }
     }
 
-    /// Spawn this process as a kernel task.
+    /// Route large allocations to this process's arena while the guard lives.
+    fn enter_memory(&self) -> Option<ArenaGuard> {
+        self.memory.as_ref().map(ProcessMemory::enter)
+    }
+
+    /// Connect the process's stdin and stdout to pipes (see `pipe`).
+    ///
+    /// `None` keeps the default: empty stdin, stdout on the console.
+    pub fn connect_stdio(
+        &mut self,
+        stdin: Option<pipe::PipeReader>,
+        stdout: Option<pipe::PipeWriter>,
+    ) {
+        let state = self.store.data_mut();
+        state.stdin = stdin;
+        state.stdout = stdout;
+    }
+
+    /// Give the process `cap` while it runs and post an `Event::Granted`
+    /// for it (dropped if the event queue is full). Returns the process's
+    /// handle for it.
+    pub fn grant(&mut self, cap: Capability) -> handles::Handle {
+        let state = self.store.data_mut();
+        let handle = state.grant(cap);
+        state.events.lock().push(event::Event::Granted {
+            handle: handle.as_raw() as u64,
+        });
+        handle
+    }
+
+    /// Whether the process is stopped, in `sp_dbg_break` or after a step.
+    pub fn is_stopped(&self) -> bool {
+        self.store.data().stopped
+    }
+
+    /// Let a stopped process continue. Returns `false` if it was not
+    /// stopped.
+    pub fn resume(&mut self) -> bool {
+        let state = self.store.data_mut();
+        state.step_until = None;
+        core::mem::replace(&mut state.stopped, false)
+    }
+
+    /// Let a stopped process run for `fuel` more units of fuel, after
+    /// which `end_step` stops it again. Returns `false` if it was not
+    /// stopped.
+    ///
+    /// wasmi cannot suspend a call that runs out of fuel, only one in a
+    /// host function, so the step is not cut off at exactly `fuel`: the
+    /// process runs on to the end of the slice in which it used up the
+    /// step, and the slice ends at its next host call that suspends it
+    /// (`sp_sched_yield`, a blocking call, `sp_dbg_break`, ...).
+    pub fn step(&mut self, fuel: u64) -> bool {
+        let until = self.fuel_consumed().saturating_add(fuel);
+        let state = self.store.data_mut();
+        if !state.stopped {
+            return false;
+        }
+        state.stopped = false;
+        state.step_until = Some(until);
+        true
+    }
+
+    /// Stop the process if it has used up the step `step` gave it, after
+    /// a slice. Returns whether it stopped.
+    pub fn end_step(&mut self) -> bool {
+        let consumed = self.fuel_consumed();
+        let state = self.store.data_mut();
+        if !state.step_until.is_some_and(|until| consumed >= until) {
+            return false;
+        }
+        state.step_until = None;
+        state.stopped = true;
+        true
+    }
+
+    /// Copy `len` bytes of linear memory from `addr`, or `None` if the
+    /// range is out of bounds or the process exports no memory.
+    pub fn peek(&self, addr: u32, len: u32) -> Option<Vec<u8>> {
+        let memory = self.instance.get_memory(&self.store, "memory")?;
+        let start = addr as usize;
+        let end = start.checked_add(len as usize)?;
+        memory.data(&self.store).get(start..end).map(<[u8]>::to_vec)
+    }
+
+    /// Exported globals with their current values, mutable or not.
+    pub fn globals(&self) -> Vec<(String, GlobalValue)> {
+        self.instance
+            .exports(&self.store)
+            .filter_map(|export| {
+                let name = String::from(export.name());
+                let global = export.into_global()?;
+                Some((name, GlobalValue::from_value(&global.get(&self.store))?))
+            })
+            .collect()
+    }
+
+    /// Handles the process holds and the capabilities behind them.
+    pub fn capabilities(&self) -> HeldCapabilities {
+        self.store.data().held()
+    }
+
+    /// Record the process's PID, as the owner of the sync objects it
+    /// creates.
+    pub fn set_pid(&mut self, pid: u64) {
+        self.store.data_mut().pid = Some(pid);
+    }
+
+    /// Bytes of memory mapped for this process's arena.
+    pub fn arena_bytes(&self) -> u64 {
+        self.memory.as_ref().map_or(0, ProcessMemory::mapped_bytes)
+    }
+
+    /// Capture linear memory, exported mutable globals and capabilities.
+    fn capture_state(&self) -> (Vec<u8>, Vec<(String, GlobalValue)>, HeldCapabilities) {
+        let memory = self
+            .instance
+            .get_memory(&self.store, "memory")
+            .map(|memory| memory.data(&self.store).to_vec())
+            .unwrap_or_default();
+        let globals = self
+            .instance
+            .exports(&self.store)
+            .filter_map(|export| {
+                let name = String::from(export.name());
+                let global = export.into_global()?;
+                if !global.ty(&self.store).mutability().is_mut() {
+                    return None;
+                }
+                Some((name, GlobalValue::from_value(&global.get(&self.store))?))
+            })
+            .collect();
+        let capabilities = self.store.data().held();
+        (memory, globals, capabilities)
+    }
+
+    /// Load saved memory and globals, growing memory as needed.
+    fn apply_state(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        let _arena = self.enter_memory();
+        if let Some(memory) = self.instance.get_memory(&self.store, "memory") {
+            let current = memory.data(&self.store).len();
+            if snapshot.memory.len() > current {
+                let missing = (snapshot.memory.len() - current).div_ceil(WASM_PAGE_SIZE);
+                let pages = wasmi::core::Pages::new(missing as u32).ok_or(wasmi::Error::from(
+                    wasmi::errors::MemoryError::OutOfBoundsGrowth,
+                ))?;
+                memory
+                    .grow(&mut self.store, pages)
+                    .map_err(wasmi::Error::from)?;
+            }
+            memory
+                .write(&mut self.store, 0, &snapshot.memory)
+                .map_err(wasmi::Error::from)?;
+        }
+        for (name, value) in &snapshot.globals {
+            if let Some(global) = self.instance.get_global(&self.store, name) {
+                global
+                    .set(&mut self.store, value.to_value())
+                    .map_err(wasmi::Error::from)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Entry point to run after `WasmEngine::restore`.
+    fn restore_entry<'a>(&self, snapshot: &'a Snapshot) -> &'a str {
+        if self
+            .instance
+            .get_func(&self.store, snapshot::RESTORE_ENTRY)
+            .is_some()
+        {
+            snapshot::RESTORE_ENTRY
+        } else {
+            &snapshot.entry
+        }
+    }
+
+    /// Check whether a suspended invocation must keep waiting at `now`.
+    fn is_blocked(&self, invocation: &wasmi::ResumableInvocation, now: u64) -> bool {
+        match invocation.host_error().downcast_ref::<HostTrap>() {
+            Some(HostTrap::Sleep(deadline)) => now < *deadline,
+            Some(HostTrap::Poll { deadline, .. }) => {
+                self.store.data().events.lock().is_empty()
+                    && !deadline.is_some_and(|deadline| now >= deadline)
+            }
+            Some(HostTrap::FutexWait { waiter, deadline }) => {
+                !waiter.is_woken() && !deadline.is_some_and(|deadline| now >= deadline)
+            }
+            Some(HostTrap::FileLock { handle, exclusive }) => {
+                !crate::fs::ROOT_FS.can_lock(*handle, *exclusive)
+            }
+            Some(HostTrap::Throttled { until, .. }) => now < *until,
+            _ => false,
+        }
+    }
+
+    /// Return value of the host function an invocation is suspended in.
+    ///
+    /// `sp_sleep_ms` reports success; `sp_poll` delivers the pending events
+    /// (none if it timed out); `sp_futex_wait` reports whether it was woken;
+    /// `sp_fs_lock` takes the lock it waited for; a throttled call returns
+    /// the result it was held back with; `sp_dbg_break` reports success.
+    /// Other suspensions return nothing.
+    fn resume_value(&mut self, invocation: &wasmi::ResumableInvocation) -> Option<wasmi::Value> {
+        match *invocation.host_error().downcast_ref::<HostTrap>()? {
+            HostTrap::Sleep(_) | HostTrap::Break => Some(wasmi::Value::I32(0)),
+            HostTrap::Poll { ptr, max, .. } => {
+                let delivered = match self.instance.get_memory(&self.store, "memory") {
+                    Some(memory) => host::deliver_events(&mut self.store, memory, ptr, max),
+                    None => host::error::NO_MEMORY_EXPORT as i32,
+                };
+                Some(wasmi::Value::I32(delivered))
+            }
+            HostTrap::FutexWait { ref waiter, .. } => {
+                let result = if waiter.is_woken() {
+                    0
+                } else {
+                    host::error::TIMED_OUT as i32
+                };
+                Some(wasmi::Value::I32(result))
+            }
+            HostTrap::FileLock { handle, exclusive } => {
+                let result = match crate::fs::ROOT_FS.try_lock(handle, exclusive) {
+                    Ok(true) => 0,
+                    // Taken again since `is_blocked`; cannot happen while
+                    // tasks do not run in parallel
+                    Ok(false) => host::error::WOULD_BLOCK as i32,
+                    Err(_) => host::error::FS_ERROR as i32,
+                };
+                Some(wasmi::Value::I32(result))
+            }
+            HostTrap::Throttled { ref result, .. } => Some(result.clone()),
+            _ => None,
+        }
+    }
+
+    /// Release what the process holds after it has finished or trapped
+    /// (see `HostState::teardown`).
+    pub fn teardown(&mut self) -> Teardown {
+        self.store.data_mut().teardown()
+    }
+
+    /// Event queue of this process, for kernel objects that post events.
+    pub fn events(&self) -> event::SharedEventQueue {
+        self.store.data().events.clone()
+    }
+
+    /// Spawn this process as a kernel task running `entry`.
     ///
     /// The process will be driven by the executor, yielding cooperatively
-    /// based on fuel consumption.
-    pub fn spawn_task(self, name: &str, executor: &mut crate::task::executor::Executor) {
-        use crate::task::{Priority, Task};
+    /// based on fuel consumption. May be called from inside a task; the
+    /// process starts at the executor's next round.
+    pub fn spawn_task(self, entry: &str) {
+        use crate::task::{executor, Priority};
 
-        let task = WasmTask {
-            process: self,
-            func_name: alloc::string::String::from(name),
-            invocation: None,
-        };
+        let mut task = WasmTask::new(self, entry);
 
-        executor.spawn(Task::with_priority(
+        executor::spawn(
             async move {
-                match task.await {
+                match (&mut task).await {
                     Ok(()) => crate::println!("[WASM] Completed."),
                     Err(e) => crate::println!("[WASM] Error: {:?}", e),
                 }
+                #[cfg(feature = "net")]
+                {
+                    let mut sockets = task.process.teardown().sockets;
+                    while !release_sockets(&mut sockets) {
+                        crate::task::yield_now().await;
+                    }
+                }
+                #[cfg(not(feature = "net"))]
+                task.process.teardown();
             },
             Priority::Normal,
-        ));
+        );
+    }
+}
+
+/// Convert a wasmi call outcome into a slice outcome.
+fn slice(call: wasmi::ResumableCall) -> Slice<wasmi::ResumableInvocation> {
+    match call {
+        wasmi::ResumableCall::Finished => Slice::Finished,
+        wasmi::ResumableCall::Resumable(invocation) => Slice::Suspended(invocation),
+    }
+}
+
+impl runtime::Process for WasmProcess {
+    type Suspended = wasmi::ResumableInvocation;
+    type Error = wasmi::Error;
+
+    /// Deliver expired timers and refill both wasmi and host fuel. A
+    /// stopped process gets no slice.
+    fn begin_slice(&mut self, suspended: Option<&wasmi::ResumableInvocation>) -> bool {
+        let now = crate::time::now_ms();
+        self.store.data_mut().fire_timers(now);
+
+        if self.store.data().stopped {
+            return false;
+        }
+        if suspended.is_some_and(|invocation| self.is_blocked(invocation, now)) {
+            return false;
+        }
+
+        // Refill wasmi fuel for this time slice
+        if let Err(e) = self.store.add_fuel(FUEL_PER_SLICE) {
+            crate::println!("[WASM] Failed to add fuel: {:?}", e);
+        }
+
+        // Reset host fuel for proactive yielding
+        self.store.data_mut().fuel_remaining = FUEL_PER_SLICE;
+        true
+    }
+
+    fn start(&mut self, entry: &str) -> Result<Slice<wasmi::ResumableInvocation>, wasmi::Error> {
+        let _arena = self.enter_memory();
+        let func = self.instance.get_func(&self.store, entry).ok_or_else(|| {
+            wasmi::Error::from(wasmi::core::Trap::from(TrapCode::UnreachableCodeReached))
+        })?;
+
+        let mut results = [wasmi::Value::I32(0); 1];
+        func.call_resumable(&mut self.store, &[], &mut results)
+            .map(slice)
+    }
+
+    fn resume(
+        &mut self,
+        invocation: wasmi::ResumableInvocation,
+    ) -> Result<Slice<wasmi::ResumableInvocation>, wasmi::Error> {
+        let _arena = self.enter_memory();
+        let mut results = [wasmi::Value::I32(0); 1];
+        let value = self.resume_value(&invocation);
+        let inputs = match &value {
+            Some(value) => core::slice::from_ref(value),
+            None => &[],
+        };
+        invocation
+            .resume(&mut self.store, inputs, &mut results)
+            .map(slice)
+    }
+
+    fn fuel_consumed(&self) -> u64 {
+        self.store.fuel_consumed().unwrap_or(0)
     }
 }
 
@@ -220,6 +749,9 @@ impl WasmProcess {
 /// The task yields control when:
 /// - The WASM code calls `sp_sched_yield`
 /// - A host function's fuel check triggers `HostTrap::Yield`
+/// - The WASM code calls `sp_sleep_ms` (not resumed before the deadline)
+/// - The WASM code calls `sp_poll` with no events pending
+/// - The WASM code calls `sp_futex_wait` (not resumed before it is woken)
 ///
 /// # Termination
 ///
@@ -232,53 +764,25 @@ pub struct WasmTask {
     invocation: Option<wasmi::ResumableInvocation>,
 }
 
+impl WasmTask {
+    /// Create a task that runs `func_name` of `process`.
+    fn new(process: WasmProcess, func_name: &str) -> Self {
+        Self {
+            process,
+            func_name: alloc::string::String::from(func_name),
+            invocation: None,
+        }
+    }
+}
+
 impl Future for WasmTask {
     type Output = Result<(), wasmi::Error>;
 
-    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
-
-        // Refill wasmi fuel for this time slice
-        if let Err(e) = this.process.store.add_fuel(FUEL_PER_SLICE) {
-            crate::println!("[WASM] Failed to add fuel: {:?}", e);
-        }
-
-        // Reset host fuel for proactive yielding
-        this.process.store.data_mut().fuel_remaining = FUEL_PER_SLICE;
-
-        let result = match this.invocation.take() {
-            None => {
-                let func = this
-                    .process
-                    .instance
-                    .get_func(&this.process.store, &this.func_name)
-                    .ok_or_else(|| {
-                        wasmi::Error::from(wasmi::core::Trap::from(
-                            TrapCode::UnreachableCodeReached,
-                        ))
-                    })?;
-
-                let mut results = [wasmi::Value::I32(0); 1];
-                func.call_resumable(&mut this.process.store, &[], &mut results)
-            }
-            Some(invocation) => {
-                let mut results = [wasmi::Value::I32(0); 1];
-                invocation.resume(&mut this.process.store, &[], &mut results)
-            }
-        };
-
-        match result {
-            Ok(wasmi::ResumableCall::Finished) => Poll::Ready(Ok(())),
-            Ok(wasmi::ResumableCall::Resumable(invocation)) => {
-                this.invocation = Some(invocation);
-                Poll::Pending
-            }
-            Err(e) => {
-                // All errors terminate the task.
-                // Proactive yielding via HostTrap::Yield returns Resumable, not Err.
-                Poll::Ready(Err(e))
-            }
-        }
+        // All errors terminate the task. Proactive yielding via
+        // HostTrap::Yield suspends the invocation rather than failing it.
+        runtime::poll_slice(&mut this.process, &this.func_name, &mut this.invocation, cx)
     }
 }
 
@@ -295,45 +799,8 @@ pub struct WasmCallFuture<'a> {
 impl Future for WasmCallFuture<'_> {
     type Output = Result<(), wasmi::Error>;
 
-    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
-
-        // Refill wasmi fuel for this time slice
-        if let Err(e) = this.process.store.add_fuel(FUEL_PER_SLICE) {
-            crate::println!("[WASM] Failed to add fuel: {:?}", e);
-        }
-
-        // Reset host fuel for proactive yielding
-        this.process.store.data_mut().fuel_remaining = FUEL_PER_SLICE;
-
-        let result = match this.invocation.take() {
-            None => {
-                let func = this
-                    .process
-                    .instance
-                    .get_func(&this.process.store, this.func_name)
-                    .ok_or_else(|| {
-                        wasmi::Error::from(wasmi::core::Trap::from(
-                            TrapCode::UnreachableCodeReached,
-                        ))
-                    })?;
-
-                let mut results = [wasmi::Value::I32(0); 1];
-                func.call_resumable(&mut this.process.store, &[], &mut results)
-            }
-            Some(invocation) => {
-                let mut results = [wasmi::Value::I32(0); 1];
-                invocation.resume(&mut this.process.store, &[], &mut results)
-            }
-        };
-
-        match result {
-            Ok(wasmi::ResumableCall::Finished) => Poll::Ready(Ok(())),
-            Ok(wasmi::ResumableCall::Resumable(invocation)) => {
-                this.invocation = Some(invocation);
-                Poll::Pending
-            }
-            Err(e) => Poll::Ready(Err(e)),
-        }
+        runtime::poll_slice(this.process, this.func_name, &mut this.invocation, cx)
     }
 }
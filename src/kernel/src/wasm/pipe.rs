@@ -0,0 +1,105 @@
+//! Pipes between processes.
+//!
+//! A pipe is a bounded byte buffer with one writing and one reading end.
+//! `ProcessManager::spawn_pipeline` connects the stdout of each process to
+//! the stdin of the next (`wasm run a.wasm | wasm run b.wasm`); processes
+//! use them through `sp_stdout_write` and `sp_stdin_read`.
+//!
+//! Neither end blocks: a full pipe accepts no bytes and an empty one reports
+//! `PipeError::WouldBlock`, and the SDK yields and retries. Dropping the
+//! writing end (the process exited) lets the reader drain the rest and then
+//! see end of input; dropping the reading end makes writes fail with
+//! `PipeError::Broken`.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::fmt;
+use spin::Mutex;
+
+/// Bytes a pipe holds before writes are refused.
+pub const PIPE_CAPACITY: usize = 4096;
+
+/// Why a pipe operation did not transfer data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeError {
+    /// The pipe is empty but the writer may still send more.
+    WouldBlock,
+    /// The reading end is gone.
+    Broken,
+}
+
+impl fmt::Display for PipeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::WouldBlock => write!(f, "pipe is empty"),
+            Self::Broken => write!(f, "broken pipe"),
+        }
+    }
+}
+
+struct Buffer {
+    data: VecDeque<u8>,
+    writer_open: bool,
+    reader_open: bool,
+}
+
+/// Create a pipe, returning its writing and reading ends.
+pub fn pipe() -> (PipeWriter, PipeReader) {
+    let buffer = Arc::new(Mutex::new(Buffer {
+        data: VecDeque::with_capacity(PIPE_CAPACITY),
+        writer_open: true,
+        reader_open: true,
+    }));
+    (PipeWriter(buffer.clone()), PipeReader(buffer))
+}
+
+/// The writing end of a pipe.
+pub struct PipeWriter(Arc<Mutex<Buffer>>);
+
+impl PipeWriter {
+    /// Append as much of `data` as fits; returns the number of bytes taken
+    /// (0 if the pipe is full).
+    pub fn write(&self, data: &[u8]) -> Result<usize, PipeError> {
+        let mut buffer = self.0.lock();
+        if !buffer.reader_open {
+            return Err(PipeError::Broken);
+        }
+        let count = data.len().min(PIPE_CAPACITY - buffer.data.len());
+        buffer.data.extend(&data[..count]);
+        Ok(count)
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.lock().writer_open = false;
+    }
+}
+
+/// The reading end of a pipe.
+pub struct PipeReader(Arc<Mutex<Buffer>>);
+
+impl PipeReader {
+    /// Take up to `out.len()` bytes; returns 0 at end of input.
+    pub fn read(&self, out: &mut [u8]) -> Result<usize, PipeError> {
+        let mut buffer = self.0.lock();
+        if buffer.data.is_empty() {
+            return if buffer.writer_open && !out.is_empty() {
+                Err(PipeError::WouldBlock)
+            } else {
+                Ok(0)
+            };
+        }
+        let count = out.len().min(buffer.data.len());
+        for (byte, value) in out.iter_mut().zip(buffer.data.drain(..count)) {
+            *byte = value;
+        }
+        Ok(count)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.lock().reader_open = false;
+    }
+}
@@ -0,0 +1,543 @@
+//! Background WASM processes and signals.
+//!
+//! `ProcessManager` owns the processes started with `wasm run` and drives
+//! them from its own kernel task. Each process gets a pid and an entry in a
+//! global table, through which the shell (`kill`) and other processes
+//! (`sp_kill`, with a `Process` capability) post signals.
+//!
+//! HUP, TERM and USR1 are delivered as `Event::Signal` on the target's
+//! event queue; what to do about them is up to the process. A process that
+//! has not exited `TERM_GRACE_MS` after a TERM is terminated, as is one
+//! sent KILL.
+//!
+//! The manager also accounts CPU time per process (see `cpu`). A process
+//! started with a CPU limit is sent TERM once it has used it up.
+//!
+//! `spawn_pipeline` starts several processes with the stdout of each piped
+//! to the stdin of the next.
+//!
+//! A process that calls `sp_dbg_break` stops until the shell resumes it.
+//! Meanwhile `peek`, `globals` and `capabilities` let the shell look at
+//! its memory, exported globals and capability table.
+//!
+//! When a process exits, traps or is terminated, the manager tears it down
+//! (see `HostState::teardown`): its files are closed, the sync objects it
+//! created destroyed, its capabilities revoked and its sockets closed.
+
+use super::cpu::FuelCalibration;
+use super::event::{Event, SharedEventQueue};
+use super::handles::Handle;
+use super::pipe;
+#[cfg(feature = "net")]
+use super::release_sockets;
+use super::runtime::Process;
+use super::snapshot::{GlobalValue, SavedCapability, Snapshot, SnapshotError};
+use super::{WasmEngine, WasmProcess, WasmTask};
+#[cfg(feature = "net")]
+use crate::net::TcpSocket;
+use crate::sync::TrackedMutex;
+use crate::time;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use sovelma_common::capability::Capability;
+
+/// Process identifier.
+pub type Pid = u64;
+
+/// Time a process has to exit after TERM before it is terminated.
+pub const TERM_GRACE_MS: u64 = 2000;
+
+/// Processes that can receive signals.
+static TABLE: TrackedMutex<BTreeMap<Pid, Arc<Control>>> =
+    TrackedMutex::new("process_table", BTreeMap::new());
+
+/// Next pid to hand out.
+static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+
+/// A signal that can be posted to a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Hang-up; conventionally "reload configuration".
+    Hup,
+    /// Forced termination; never seen by the process.
+    Kill,
+    /// User-defined.
+    Usr1,
+    /// Polite termination request.
+    Term,
+}
+
+impl Signal {
+    /// Conventional signal number.
+    pub fn number(self) -> u32 {
+        match self {
+            Signal::Hup => 1,
+            Signal::Kill => 9,
+            Signal::Usr1 => 10,
+            Signal::Term => 15,
+        }
+    }
+
+    /// Signal name without the `SIG` prefix.
+    pub fn name(self) -> &'static str {
+        match self {
+            Signal::Hup => "HUP",
+            Signal::Kill => "KILL",
+            Signal::Usr1 => "USR1",
+            Signal::Term => "TERM",
+        }
+    }
+
+    /// Look up a signal by number.
+    pub fn from_number(number: u32) -> Option<Self> {
+        [Signal::Hup, Signal::Kill, Signal::Usr1, Signal::Term]
+            .into_iter()
+            .find(|signal| signal.number() == number)
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SIG{}", self.name())
+    }
+}
+
+impl FromStr for Signal {
+    type Err = SignalError;
+
+    /// Parse `TERM`, `SIGTERM`, `term` or `15`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(number) = s.parse::<u32>() {
+            return Signal::from_number(number).ok_or(SignalError::UnknownSignal);
+        }
+        let upper = s.to_ascii_uppercase();
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+        [Signal::Hup, Signal::Kill, Signal::Usr1, Signal::Term]
+            .into_iter()
+            .find(|signal| signal.name() == name)
+            .ok_or(SignalError::UnknownSignal)
+    }
+}
+
+/// Errors from posting a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalError {
+    /// No process with that pid.
+    NoSuchProcess,
+    /// Not a supported signal name or number.
+    UnknownSignal,
+    /// The target's event queue is full.
+    QueueFull,
+}
+
+impl fmt::Display for SignalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignalError::NoSuchProcess => write!(f, "no such process"),
+            SignalError::UnknownSignal => write!(f, "unknown signal"),
+            SignalError::QueueFull => write!(f, "event queue full"),
+        }
+    }
+}
+
+/// Errors from the debugging calls of `ProcessManager`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugError {
+    /// No process with that pid.
+    NoSuchProcess,
+    /// The process is not stopped.
+    NotStopped,
+    /// The range is outside the process's linear memory.
+    OutOfBounds,
+}
+
+impl fmt::Display for DebugError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebugError::NoSuchProcess => write!(f, "no such process"),
+            DebugError::NotStopped => write!(f, "process is not stopped"),
+            DebugError::OutOfBounds => write!(f, "address out of bounds"),
+        }
+    }
+}
+
+/// Per-process state shared with signal senders.
+struct Control {
+    events: SharedEventQueue,
+    /// Monotonic time (ms) by which a TERM must be honoured; 0 if none.
+    term_deadline: AtomicU64,
+    killed: AtomicBool,
+}
+
+impl Control {
+    /// Why the process must be terminated now, if it must.
+    fn forced_exit(&self, now: u64) -> Option<&'static str> {
+        if self.killed.load(Ordering::Relaxed) {
+            return Some("killed");
+        }
+        let deadline = self.term_deadline.load(Ordering::Relaxed);
+        if deadline != 0 && now >= deadline {
+            return Some("did not exit after SIGTERM, killed");
+        }
+        None
+    }
+}
+
+/// Post `signal` to process `pid`.
+pub fn signal(pid: Pid, signal: Signal) -> Result<(), SignalError> {
+    let control = TABLE
+        .lock()
+        .get(&pid)
+        .cloned()
+        .ok_or(SignalError::NoSuchProcess)?;
+
+    if signal == Signal::Kill {
+        control.killed.store(true, Ordering::Relaxed);
+        return Ok(());
+    }
+    let event = Event::Signal {
+        signal: signal.number(),
+    };
+    if !control.events.lock().push(event) {
+        return Err(SignalError::QueueFull);
+    }
+    if signal == Signal::Term {
+        let deadline = time::now_ms().saturating_add(TERM_GRACE_MS).max(1);
+        // Repeated TERMs do not extend the grace period.
+        let _ = control.term_deadline.compare_exchange(
+            0,
+            deadline,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+    Ok(())
+}
+
+/// A process run by the manager.
+struct Running {
+    name: String,
+    task: WasmTask,
+    control: Arc<Control>,
+    /// CPU time after which the process is sent TERM.
+    cpu_limit_ms: Option<u64>,
+    /// Whether the CPU limit was already enforced.
+    over_limit: bool,
+}
+
+/// State of a process, as reported by `ProcessManager::list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    /// Process ID.
+    pub pid: Pid,
+    /// Module file the process was started from.
+    pub name: String,
+    /// CPU time used so far.
+    pub cpu_ms: u64,
+    /// CPU limit, if any.
+    pub cpu_limit_ms: Option<u64>,
+    /// Whether the process was asked to terminate.
+    pub terminating: bool,
+    /// Whether the process is stopped, in `sp_dbg_break` or after a step.
+    pub stopped: bool,
+}
+
+/// Runs background WASM processes.
+///
+/// The manager also owns the engine processes are created with, so that
+/// libraries loaded into it stay available to later processes.
+pub struct ProcessManager {
+    engine: WasmEngine,
+    processes: BTreeMap<Pid, Running>,
+    calibration: FuelCalibration,
+    /// Sockets of exited processes, closed once the stack is free.
+    #[cfg(feature = "net")]
+    orphaned_sockets: Vec<TcpSocket>,
+}
+
+impl ProcessManager {
+    /// Create a manager with no processes.
+    pub fn new() -> Self {
+        Self {
+            engine: WasmEngine::new(),
+            processes: BTreeMap::new(),
+            calibration: FuelCalibration::new(),
+            #[cfg(feature = "net")]
+            orphaned_sockets: Vec::new(),
+        }
+    }
+
+    /// Engine to create processes and load libraries with.
+    pub fn engine(&self) -> &WasmEngine {
+        &self.engine
+    }
+
+    /// Start running `entry` of `process` in the background.
+    pub fn spawn(&mut self, name: &str, mut process: WasmProcess, entry: &str) -> Pid {
+        let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+        process.set_pid(pid);
+        let control = Arc::new(Control {
+            events: process.events(),
+            term_deadline: AtomicU64::new(0),
+            killed: AtomicBool::new(false),
+        });
+        TABLE.lock().insert(pid, control.clone());
+        self.processes.insert(
+            pid,
+            Running {
+                name: name.into(),
+                task: WasmTask::new(process, entry),
+                control,
+                cpu_limit_ms: None,
+                over_limit: false,
+            },
+        );
+        pid
+    }
+
+    /// Start the stages of a pipeline, connecting the stdout of each to
+    /// the stdin of the next (see `pipe`). Returns their pids in order.
+    ///
+    /// The first stage's stdin stays empty and the last stage's stdout
+    /// goes to the console.
+    pub fn spawn_pipeline(&mut self, stages: Vec<(String, WasmProcess, String)>) -> Vec<Pid> {
+        let last = stages.len().saturating_sub(1);
+        let mut stdin = None;
+        let mut pids = Vec::with_capacity(stages.len());
+        for (i, (name, mut process, entry)) in stages.into_iter().enumerate() {
+            let (stdout, next_stdin) = if i < last {
+                let (writer, reader) = pipe::pipe();
+                (Some(writer), Some(reader))
+            } else {
+                (None, None)
+            };
+            process.connect_stdio(core::mem::replace(&mut stdin, next_stdin), stdout);
+            pids.push(self.spawn(&name, process, &entry));
+        }
+        pids
+    }
+
+    /// Set or clear the CPU limit of a process. Returns `false` if there is
+    /// no such process.
+    pub fn set_cpu_limit(&mut self, pid: Pid, limit_ms: Option<u64>) -> bool {
+        match self.processes.get_mut(&pid) {
+            Some(running) => {
+                running.cpu_limit_ms = limit_ms;
+                running.over_limit = false;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Give running process `pid` the capability `cap` (see
+    /// `WasmProcess::grant`). Returns its handle for it, or `None` if
+    /// there is no such process.
+    pub fn grant(&mut self, pid: Pid, cap: Capability) -> Option<Handle> {
+        let running = self.processes.get_mut(&pid)?;
+        Some(running.task.process.grant(cap))
+    }
+
+    /// Let process `pid`, stopped in `sp_dbg_break` or after a step,
+    /// continue.
+    pub fn resume(&mut self, pid: Pid) -> Result<(), DebugError> {
+        let running = self
+            .processes
+            .get_mut(&pid)
+            .ok_or(DebugError::NoSuchProcess)?;
+        if !running.task.process.resume() {
+            return Err(DebugError::NotStopped);
+        }
+        log::info!(target: "wasm", "[{}] {} resumed", pid, running.name);
+        Ok(())
+    }
+
+    /// Let stopped process `pid` run for about `fuel` units of fuel, then
+    /// stop it again; see `WasmProcess::step` for how far past `fuel` it
+    /// gets.
+    pub fn step(&mut self, pid: Pid, fuel: u64) -> Result<(), DebugError> {
+        let running = self
+            .processes
+            .get_mut(&pid)
+            .ok_or(DebugError::NoSuchProcess)?;
+        if !running.task.process.step(fuel) {
+            return Err(DebugError::NotStopped);
+        }
+        Ok(())
+    }
+
+    /// Copy `len` bytes of the linear memory of process `pid` from `addr`.
+    pub fn peek(&self, pid: Pid, addr: u32, len: u32) -> Result<Vec<u8>, DebugError> {
+        let running = self.processes.get(&pid).ok_or(DebugError::NoSuchProcess)?;
+        running
+            .task
+            .process
+            .peek(addr, len)
+            .ok_or(DebugError::OutOfBounds)
+    }
+
+    /// Exported globals of process `pid` with their current values.
+    pub fn globals(&self, pid: Pid) -> Result<Vec<(String, GlobalValue)>, DebugError> {
+        let running = self.processes.get(&pid).ok_or(DebugError::NoSuchProcess)?;
+        Ok(running.task.process.globals())
+    }
+
+    /// Handles process `pid` holds and the capabilities behind them.
+    pub fn capabilities(&self, pid: Pid) -> Result<Vec<(Handle, Capability)>, DebugError> {
+        let running = self.processes.get(&pid).ok_or(DebugError::NoSuchProcess)?;
+        Ok(running.task.process.capabilities())
+    }
+
+    /// Running processes, by pid.
+    pub fn list(&self) -> Vec<ProcessInfo> {
+        self.processes
+            .iter()
+            .map(|(&pid, running)| ProcessInfo {
+                pid,
+                name: running.name.clone(),
+                cpu_ms: self.calibration.to_ms(running.task.process.fuel_consumed()),
+                cpu_limit_ms: running.cpu_limit_ms,
+                terminating: running.over_limit
+                    || running.control.term_deadline.load(Ordering::Relaxed) != 0,
+                stopped: running.task.process.is_stopped(),
+            })
+            .collect()
+    }
+
+    /// Current fuel-to-time calibration.
+    pub fn calibration(&self) -> FuelCalibration {
+        self.calibration
+    }
+
+    /// Capture the state of a running process.
+    ///
+    /// The process keeps running; see `snapshot` for what is captured.
+    pub fn snapshot(&self, pid: Pid) -> Result<Snapshot, SnapshotError> {
+        let running = self
+            .processes
+            .get(&pid)
+            .ok_or(SnapshotError::NoSuchProcess)?;
+        let (memory, globals, capabilities) = running.task.process.capture_state();
+        Ok(Snapshot {
+            module: running.name.clone(),
+            entry: running.task.func_name.clone(),
+            memory,
+            globals,
+            capabilities: capabilities
+                .iter()
+                .filter_map(|(handle, cap)| SavedCapability::save(*handle, cap))
+                .collect(),
+        })
+    }
+
+    /// Start a process from a snapshot of `wasm_bytes`, deriving its
+    /// capabilities from `parents`.
+    pub fn restore(
+        &mut self,
+        wasm_bytes: &[u8],
+        snapshot: &Snapshot,
+        parents: &[Capability],
+    ) -> Result<Pid, SnapshotError> {
+        let process = self.engine.restore(wasm_bytes, snapshot, parents)?;
+        let entry = String::from(process.restore_entry(snapshot));
+        Ok(self.spawn(&snapshot.module, process, &entry))
+    }
+
+    /// Number of running processes.
+    pub fn len(&self) -> usize {
+        self.processes.len()
+    }
+
+    /// Check whether no processes are running.
+    pub fn is_empty(&self) -> bool {
+        self.processes.is_empty()
+    }
+
+    /// Give every process a time slice and reap the ones that finished or
+    /// must be terminated.
+    pub fn poll(&mut self) {
+        let waker = futures_util::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let now = time::now_ms();
+        let mut exited = Vec::new();
+        let mut over_limit = Vec::new();
+        let mut fuel_used = 0;
+
+        for (&pid, running) in self.processes.iter_mut() {
+            if let Some(reason) = running.control.forced_exit(now) {
+                log::warn!(target: "wasm", "[{}] {} {}", pid, running.name, reason);
+                exited.push(pid);
+                continue;
+            }
+            let fuel_before = running.task.process.fuel_consumed();
+            let poll = Pin::new(&mut running.task).poll(&mut cx);
+            let fuel = running.task.process.fuel_consumed();
+            fuel_used += fuel.saturating_sub(fuel_before);
+
+            let cpu_ms = self.calibration.to_ms(fuel);
+            if !running.over_limit && running.cpu_limit_ms.is_some_and(|limit| cpu_ms >= limit) {
+                running.over_limit = true;
+                log::warn!(target: "wasm", "[{}] {} exceeded its CPU limit", pid, running.name);
+                over_limit.push(pid);
+            }
+            match poll {
+                Poll::Ready(Ok(())) => {
+                    log::info!(target: "wasm", "[{}] {} exited", pid, running.name);
+                    exited.push(pid);
+                }
+                Poll::Ready(Err(e)) => {
+                    log::warn!(target: "wasm", "[{}] {} failed: {}", pid, running.name, e);
+                    exited.push(pid);
+                }
+                Poll::Pending => {
+                    if running.task.process.end_step() {
+                        log::info!(target: "wasm", "[{}] {} stopped after step", pid, running.name);
+                    }
+                }
+            }
+        }
+
+        self.calibration
+            .sample(fuel_used, time::now_ms().saturating_sub(now));
+
+        for pid in exited {
+            if let Some(mut running) = self.processes.remove(&pid) {
+                let released = running.task.process.teardown();
+                if !released.is_empty() {
+                    log::info!(
+                        target: "wasm",
+                        "[{}] released {} capabilities ({} files, {} sync objects, {} sockets)",
+                        pid,
+                        released.capabilities,
+                        released.files,
+                        released.sync_objects,
+                        released.open_sockets()
+                    );
+                }
+                #[cfg(feature = "net")]
+                self.orphaned_sockets.extend(released.sockets);
+            }
+            TABLE.lock().remove(&pid);
+        }
+        #[cfg(feature = "net")]
+        release_sockets(&mut self.orphaned_sockets);
+        for pid in over_limit {
+            // The process may have exited in the same slice
+            let _ = signal(pid, Signal::Term);
+        }
+    }
+}
+
+impl Default for ProcessManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,576 @@
+//! Process snapshots.
+//!
+//! A snapshot captures what is needed to bring a background process back
+//! later: its linear memory, exported mutable globals and what its
+//! capabilities refer to, plus the module file and entry point it was
+//! started from. `snapshot <pid>` writes one to the RamFs; `restore <file>`
+//! instantiates the module again and loads the saved state into it.
+//!
+//! Checkpoints are cooperative. The interpreter's call stack cannot be
+//! saved, so a restored process starts over in its `RESTORE_ENTRY` export
+//! (or the original entry point if it has none) and finds its memory as it
+//! was. Timers and pending events are not saved.
+//!
+//! A snapshot file is not trusted. Capabilities are saved as what they
+//! refer to (a directory or file by its path from the root, a port range,
+//! a serial port), never as kernel handles or capability IDs, and restoring
+//! derives each one anew from the parent capabilities the caller gives (see
+//! `SavedCapability::derive`): a directory or file is reopened below a
+//! parent directory, and nothing gets rights its parent lacks. Memory and
+//! Interrupt capabilities and kernel objects (mutexes, semaphores,
+//! processes, sockets) are not saved, nor is a directory or file the root
+//! does not reach, such as a `--mount` namespace.
+//!
+//! # Format
+//!
+//! Little endian throughout: `SNAPSHOT_MAGIC`, then the module path and
+//! entry name (`u32` length + UTF-8), memory (`u32` length + bytes),
+//! globals (`u32` count; each name, `u8` type, `u64` bits) and
+//! capabilities (`u32` count; each `handle: u32`, `rights: u32`, `type:
+//! u32`, two `u64` payload words, the path, empty for none, the label,
+//! empty for none, and the rate limit as `per_second: u32` and `burst:
+//! u32`, both 0 for none).
+
+use super::handles::Handle;
+use crate::fs::{FileHandle, FileSystem, FsError, ROOT_FS};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType, Label};
+use sovelma_common::rate::RateLimit;
+
+/// First bytes of every snapshot file.
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"SVSNAP05";
+
+/// Export called instead of the entry point when a process is restored.
+pub const RESTORE_ENTRY: &str = "sovelma_restore";
+
+/// Directory `snapshot` writes to unless given a file name.
+pub const SNAPSHOT_DIR: &str = "snapshots";
+
+/// Value of a saved global.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlobalValue {
+    /// `i32` global.
+    I32(i32),
+    /// `i64` global.
+    I64(i64),
+    /// `f32` global, as raw bits.
+    F32(u32),
+    /// `f64` global, as raw bits.
+    F64(u64),
+}
+
+impl GlobalValue {
+    /// Convert a wasmi value. Reference types are not saved.
+    pub fn from_value(value: &wasmi::Value) -> Option<Self> {
+        match value {
+            wasmi::Value::I32(v) => Some(GlobalValue::I32(*v)),
+            wasmi::Value::I64(v) => Some(GlobalValue::I64(*v)),
+            wasmi::Value::F32(v) => Some(GlobalValue::F32(v.to_bits())),
+            wasmi::Value::F64(v) => Some(GlobalValue::F64(v.to_bits())),
+            _ => None,
+        }
+    }
+
+    /// Convert back to a wasmi value.
+    pub fn to_value(self) -> wasmi::Value {
+        match self {
+            GlobalValue::I32(v) => wasmi::Value::I32(v),
+            GlobalValue::I64(v) => wasmi::Value::I64(v),
+            GlobalValue::F32(bits) => wasmi::Value::F32(wasmi::core::F32::from_bits(bits)),
+            GlobalValue::F64(bits) => wasmi::Value::F64(wasmi::core::F64::from_bits(bits)),
+        }
+    }
+
+    fn tag_and_bits(self) -> (u8, u64) {
+        match self {
+            GlobalValue::I32(v) => (0, v as u32 as u64),
+            GlobalValue::I64(v) => (1, v as u64),
+            GlobalValue::F32(bits) => (2, u64::from(bits)),
+            GlobalValue::F64(bits) => (3, bits),
+        }
+    }
+
+    fn from_tag_and_bits(tag: u8, bits: u64) -> Option<Self> {
+        match tag {
+            0 => Some(GlobalValue::I32(bits as u32 as i32)),
+            1 => Some(GlobalValue::I64(bits as i64)),
+            2 => Some(GlobalValue::F32(bits as u32)),
+            3 => Some(GlobalValue::F64(bits)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GlobalValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            GlobalValue::I32(v) => write!(f, "i32 {}", v),
+            GlobalValue::I64(v) => write!(f, "i64 {}", v),
+            GlobalValue::F32(bits) => write!(f, "f32 {}", f32::from_bits(bits)),
+            GlobalValue::F64(bits) => write!(f, "f64 {}", f64::from_bits(bits)),
+        }
+    }
+}
+
+/// Errors from taking, decoding or restoring a snapshot.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// No process with that pid.
+    NoSuchProcess,
+    /// The file does not start with `SNAPSHOT_MAGIC`.
+    BadMagic,
+    /// The file ended early.
+    Truncated,
+    /// A string field was not valid UTF-8.
+    InvalidUtf8,
+    /// A global or capability type this kernel does not know.
+    UnknownType(u32),
+    /// A capability handle of 0 or beyond `Handle::MAX`.
+    BadHandle,
+    /// A capability label that is not a valid `Label`.
+    BadLabel,
+    /// A rate limit of zero operations or a zero burst.
+    BadRateLimit,
+    /// No parent capability given covers a saved one.
+    NotCovered(SavedObject),
+    /// A saved directory or file could not be opened again.
+    Reopen(SavedObject, FsError),
+    /// The module could not be instantiated or the state not applied.
+    Wasm(wasmi::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::NoSuchProcess => write!(f, "no such process"),
+            SnapshotError::BadMagic => write!(f, "not a snapshot file"),
+            SnapshotError::Truncated => write!(f, "snapshot truncated"),
+            SnapshotError::InvalidUtf8 => write!(f, "invalid UTF-8 in snapshot"),
+            SnapshotError::UnknownType(tag) => write!(f, "unknown type {} in snapshot", tag),
+            SnapshotError::BadHandle => write!(f, "invalid capability handle in snapshot"),
+            SnapshotError::BadLabel => write!(f, "invalid capability label in snapshot"),
+            SnapshotError::BadRateLimit => write!(f, "invalid rate limit in snapshot"),
+            SnapshotError::NotCovered(object) => {
+                write!(f, "no capability given covers {}", object)
+            }
+            SnapshotError::Reopen(object, e) => write!(f, "cannot reopen {}: {}", object, e),
+            SnapshotError::Wasm(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<wasmi::Error> for SnapshotError {
+    fn from(e: wasmi::Error) -> Self {
+        SnapshotError::Wasm(e)
+    }
+}
+
+/// Saved state of a process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Module file the process was started from.
+    pub module: String,
+    /// Entry point it was started with.
+    pub entry: String,
+    /// Contents of the exported linear memory (empty if none).
+    pub memory: Vec<u8>,
+    /// Exported mutable globals by name.
+    pub globals: Vec<(String, GlobalValue)>,
+    /// Capabilities held by the process, with its handles for them.
+    pub capabilities: Vec<SavedCapability>,
+}
+
+/// What a saved capability refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SavedObject {
+    /// The clock and timers.
+    Timer,
+    /// The system configuration.
+    Config,
+    /// A serial port, by I/O port.
+    Serial {
+        /// I/O port base.
+        port: u16,
+    },
+    /// Network access; the ports only matter with `LISTEN`.
+    Network {
+        /// First port that may be listened on.
+        first_port: u16,
+        /// Last port that may be listened on.
+        last_port: u16,
+    },
+    /// A directory, by its path from the root.
+    Directory(String),
+    /// A file, by its path from the root.
+    File(String),
+}
+
+impl fmt::Display for SavedObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SavedObject::Timer => write!(f, "timer"),
+            SavedObject::Config => write!(f, "config"),
+            SavedObject::Serial { port } => write!(f, "serial port {:#x}", port),
+            SavedObject::Network {
+                first_port,
+                last_port,
+            } => write!(f, "network ports {}-{}", first_port, last_port),
+            SavedObject::Directory(path) => write!(f, "directory {}", path),
+            SavedObject::File(path) => write!(f, "file {}", path),
+        }
+    }
+}
+
+/// A capability as a snapshot holds it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedCapability {
+    /// The process's handle for it, kept so handles in its memory stay valid.
+    pub handle: Handle,
+    /// What it refers to.
+    pub object: SavedObject,
+    /// Rights it granted.
+    pub rights: CapabilityRights,
+    /// Label it was granted with.
+    pub label: Option<Label>,
+    /// Rate limit it was granted with.
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl SavedCapability {
+    /// Describe `cap`, held as `handle`, for a snapshot; `None` for what is
+    /// not saved.
+    pub fn save(handle: Handle, cap: &Capability) -> Option<Self> {
+        let path = |fs_handle: u64| ROOT_FS.path(FileHandle(fs_handle as u32));
+        let object = match cap.object {
+            CapabilityType::Timer => SavedObject::Timer,
+            CapabilityType::Config => SavedObject::Config,
+            CapabilityType::Serial { port } => SavedObject::Serial { port },
+            CapabilityType::Network {
+                first_port,
+                last_port,
+            } => SavedObject::Network {
+                first_port,
+                last_port,
+            },
+            CapabilityType::Directory(fs_handle) => SavedObject::Directory(path(fs_handle)?),
+            CapabilityType::File(fs_handle) => SavedObject::File(path(fs_handle)?),
+            CapabilityType::Memory { .. }
+            | CapabilityType::Interrupt { .. }
+            | CapabilityType::Mutex(_)
+            | CapabilityType::Semaphore(_)
+            | CapabilityType::Process(_)
+            | CapabilityType::Socket(_) => return None,
+        };
+        Some(Self {
+            handle,
+            object,
+            rights: cap.rights,
+            label: cap.label,
+            rate_limit: cap.rate_limit,
+        })
+    }
+
+    /// Make the capability anew from one of `parents` that covers it: one
+    /// of the same kind with all its rights, a Network capability whose
+    /// ports include its own if it may listen, the same serial port, or a
+    /// directory it is reopened below. The capability gets a new ID, and a
+    /// directory or file a new kernel handle.
+    pub fn derive(&self, parents: &[Capability]) -> Result<Capability, SnapshotError> {
+        let covers = |parent: &&Capability| {
+            parent.rights.contains(self.rights)
+                && match (&self.object, parent.object) {
+                    (SavedObject::Timer, CapabilityType::Timer)
+                    | (SavedObject::Config, CapabilityType::Config) => true,
+                    (
+                        SavedObject::Serial { port },
+                        CapabilityType::Serial { port: parent_port },
+                    ) => *port == parent_port,
+                    (
+                        SavedObject::Network {
+                            first_port,
+                            last_port,
+                        },
+                        CapabilityType::Network {
+                            first_port: parent_first,
+                            last_port: parent_last,
+                        },
+                    ) => {
+                        !self.rights.contains(CapabilityRights::LISTEN)
+                            || (parent_first <= *first_port && *last_port <= parent_last)
+                    }
+                    _ => false,
+                }
+        };
+        let object = match &self.object {
+            SavedObject::Directory(path) | SavedObject::File(path) => self.reopen(path, parents)?,
+            _ => {
+                parents
+                    .iter()
+                    .find(covers)
+                    .ok_or_else(|| SnapshotError::NotCovered(self.object.clone()))?
+                    .object
+            }
+        };
+        let cap = Capability::new(object, self.rights);
+        Ok(Capability {
+            label: self.label,
+            rate_limit: self.rate_limit,
+            ..cap
+        })
+    }
+
+    /// Open `path` again below the first of `parents` that is a directory
+    /// holding it with all the rights saved.
+    fn reopen(&self, path: &str, parents: &[Capability]) -> Result<CapabilityType, SnapshotError> {
+        let is_dir = matches!(self.object, SavedObject::Directory(_));
+        for parent in parents {
+            let CapabilityType::Directory(dir) = parent.object else {
+                continue;
+            };
+            if !parent.rights.contains(self.rights) {
+                continue;
+            }
+            let dir = FileHandle(dir as u32);
+            let Some(relative) = ROOT_FS.path(dir).and_then(|root| below(&root, path)) else {
+                continue;
+            };
+            let handle = ROOT_FS
+                .open_at(dir, relative)
+                .map_err(|e| SnapshotError::Reopen(self.object.clone(), e))?;
+            if ROOT_FS.is_dir(handle) != is_dir
+                || (ROOT_FS.is_read_only(handle) && self.rights.contains(CapabilityRights::WRITE))
+            {
+                ROOT_FS.close(handle);
+                return Err(SnapshotError::Reopen(
+                    self.object.clone(),
+                    FsError::PermissionDenied,
+                ));
+            }
+            return Ok(if is_dir {
+                CapabilityType::Directory(u64::from(handle.0))
+            } else {
+                CapabilityType::File(u64::from(handle.0))
+            });
+        }
+        Err(SnapshotError::NotCovered(self.object.clone()))
+    }
+}
+
+/// `path` relative to the directory `dir`, both from the root, if it is
+/// `dir` or below it; `.` and `..` are not followed.
+fn below<'a>(dir: &str, path: &'a str) -> Option<&'a str> {
+    let relative = path.strip_prefix(dir.trim_end_matches('/'))?;
+    if !relative.is_empty() && !relative.starts_with('/') {
+        return None; // `/data` does not hold `/database`
+    }
+    let escapes = relative.split('/').any(|part| part == "." || part == "..");
+    (!escapes).then_some(relative)
+}
+
+/// Close the directories and files among restored `capabilities`.
+pub fn release(capabilities: &[(Handle, Capability)]) {
+    for (_, cap) in capabilities {
+        if let CapabilityType::Directory(handle) | CapabilityType::File(handle) = cap.object {
+            ROOT_FS.close(FileHandle(handle as u32));
+        }
+    }
+}
+
+impl Snapshot {
+    /// Derive the saved capabilities from `parents` (see
+    /// `SavedCapability::derive`), closing what was reopened if one fails.
+    pub fn derive_capabilities(
+        &self,
+        parents: &[Capability],
+    ) -> Result<Vec<(Handle, Capability)>, SnapshotError> {
+        let mut derived = Vec::with_capacity(self.capabilities.len());
+        for saved in &self.capabilities {
+            match saved.derive(parents) {
+                Ok(cap) => derived.push((saved.handle, cap)),
+                Err(e) => {
+                    release(&derived);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(derived)
+    }
+
+    /// Serialize to the snapshot file format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.memory.len() + 256);
+        out.extend_from_slice(SNAPSHOT_MAGIC);
+        put_bytes(&mut out, self.module.as_bytes());
+        put_bytes(&mut out, self.entry.as_bytes());
+        put_bytes(&mut out, &self.memory);
+
+        out.extend_from_slice(&(self.globals.len() as u32).to_le_bytes());
+        for (name, value) in &self.globals {
+            let (tag, bits) = value.tag_and_bits();
+            put_bytes(&mut out, name.as_bytes());
+            out.push(tag);
+            out.extend_from_slice(&bits.to_le_bytes());
+        }
+
+        out.extend_from_slice(&(self.capabilities.len() as u32).to_le_bytes());
+        for cap in &self.capabilities {
+            let (tag, a, b, path) = encode_object(&cap.object);
+            out.extend_from_slice(&cap.handle.value().to_le_bytes());
+            out.extend_from_slice(&cap.rights.bits().to_le_bytes());
+            out.extend_from_slice(&tag.to_le_bytes());
+            out.extend_from_slice(&a.to_le_bytes());
+            out.extend_from_slice(&b.to_le_bytes());
+            put_bytes(&mut out, path.as_bytes());
+            let label = cap.label.as_ref().map_or("", Label::as_str);
+            put_bytes(&mut out, label.as_bytes());
+            let (per_second, burst) = cap
+                .rate_limit
+                .map_or((0, 0), |limit| (limit.per_second, limit.burst));
+            out.extend_from_slice(&per_second.to_le_bytes());
+            out.extend_from_slice(&burst.to_le_bytes());
+        }
+        out
+    }
+
+    /// Parse a snapshot file.
+    pub fn decode(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let module = reader.string()?;
+        let entry = reader.string()?;
+        let memory = reader.bytes()?.to_vec();
+
+        let count = reader.u32()?;
+        let mut globals = Vec::new();
+        for _ in 0..count {
+            let name = reader.string()?;
+            let tag = reader.take(1)?[0];
+            let bits = reader.u64()?;
+            let value = GlobalValue::from_tag_and_bits(tag, bits)
+                .ok_or(SnapshotError::UnknownType(u32::from(tag)))?;
+            globals.push((name, value));
+        }
+
+        let count = reader.u32()?;
+        let mut capabilities = Vec::new();
+        for _ in 0..count {
+            let handle = Handle::new(reader.u32()?).ok_or(SnapshotError::BadHandle)?;
+            let rights = CapabilityRights::from_bits_truncate(reader.u32()?);
+            let tag = reader.u32()?;
+            let (a, b) = (reader.u64()?, reader.u64()?);
+            let path = reader.string()?;
+            let object = decode_object(tag, a, b, path).ok_or(SnapshotError::UnknownType(tag))?;
+            let label = match reader.string()?.as_str() {
+                "" => None,
+                label => Some(Label::new(label).ok_or(SnapshotError::BadLabel)?),
+            };
+            let rate_limit = match (reader.u32()?, reader.u32()?) {
+                (0, 0) => None,
+                (per_second, burst) => {
+                    Some(RateLimit::new(per_second, burst).ok_or(SnapshotError::BadRateLimit)?)
+                }
+            };
+            capabilities.push(SavedCapability {
+                handle,
+                object,
+                rights,
+                label,
+                rate_limit,
+            });
+        }
+
+        Ok(Self {
+            module,
+            entry,
+            memory,
+            globals,
+            capabilities,
+        })
+    }
+}
+
+/// Append a length-prefixed byte string.
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Saved object as a type tag, two payload words and a path.
+fn encode_object(object: &SavedObject) -> (u32, u64, u64, &str) {
+    match object {
+        SavedObject::Serial { port } => (1, u64::from(*port), 0, ""),
+        SavedObject::Timer => (2, 0, 0, ""),
+        SavedObject::Network {
+            first_port,
+            last_port,
+        } => (4, u64::from(*first_port), u64::from(*last_port), ""),
+        SavedObject::Directory(path) => (5, 0, 0, path),
+        SavedObject::File(path) => (6, 0, 0, path),
+        SavedObject::Config => (10, 0, 0, ""),
+    }
+}
+
+/// Inverse of `encode_object`. Tags of kinds that are never saved are
+/// unknown.
+fn decode_object(tag: u32, a: u64, b: u64, path: String) -> Option<SavedObject> {
+    Some(match tag {
+        1 => SavedObject::Serial {
+            port: u16::try_from(a).ok()?,
+        },
+        2 => SavedObject::Timer,
+        4 => SavedObject::Network {
+            first_port: u16::try_from(a).ok()?,
+            last_port: u16::try_from(b).ok()?,
+        },
+        5 => SavedObject::Directory(path),
+        6 => SavedObject::File(path),
+        10 => SavedObject::Config,
+        _ => return None,
+    })
+}
+
+/// Cursor over a snapshot file.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        let end = self.pos.checked_add(len).ok_or(SnapshotError::Truncated)?;
+        let slice = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or(SnapshotError::Truncated)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, SnapshotError> {
+        let bytes = self.bytes()?;
+        core::str::from_utf8(bytes)
+            .map(String::from)
+            .map_err(|_| SnapshotError::InvalidUtf8)
+    }
+}
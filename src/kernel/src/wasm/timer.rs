@@ -0,0 +1,122 @@
+//! Per-process kernel timers.
+//!
+//! Timers are created with `sp_timer_create` and armed with an initial
+//! delay and an optional period. They are checked whenever the owning
+//! process is polled; each expiry is delivered as an `Event::Timer` on the
+//! process's event queue. A periodic timer that fell behind (because the
+//! process was not scheduled) reports the missed expirations in one event
+//! rather than firing repeatedly.
+
+use super::event::{Event, EventQueue};
+use alloc::collections::BTreeMap;
+
+/// Timers a single process may own.
+pub const MAX_TIMERS: usize = 32;
+
+/// State of one timer.
+#[derive(Debug, Clone, Copy, Default)]
+struct Timer {
+    /// Next expiry (monotonic ms), or `None` while disarmed.
+    deadline: Option<u64>,
+    /// Re-arm interval in ms; zero for one-shot timers.
+    period_ms: u64,
+}
+
+/// The timers owned by one process.
+#[derive(Debug)]
+pub struct ProcessTimers {
+    timers: BTreeMap<u64, Timer>,
+    next_id: u64,
+}
+
+impl ProcessTimers {
+    /// Create an empty timer set.
+    pub fn new() -> Self {
+        Self {
+            timers: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Create a disarmed timer. Returns `None` at `MAX_TIMERS`.
+    pub fn create(&mut self) -> Option<u64> {
+        if self.timers.len() >= MAX_TIMERS {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.timers.insert(id, Timer::default());
+        Some(id)
+    }
+
+    /// Arm a timer to expire `initial_ms` after `now`, then every
+    /// `period_ms` (zero for one-shot). Re-arming replaces the previous
+    /// schedule. Returns `false` for an unknown timer.
+    pub fn arm(&mut self, id: u64, now: u64, initial_ms: u64, period_ms: u64) -> bool {
+        match self.timers.get_mut(&id) {
+            Some(timer) => {
+                timer.deadline = Some(now.saturating_add(initial_ms));
+                timer.period_ms = period_ms;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Disarm and delete a timer. Returns `false` for an unknown timer.
+    pub fn cancel(&mut self, id: u64) -> bool {
+        self.timers.remove(&id).is_some()
+    }
+
+    /// Number of timers.
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Check whether the process owns no timers.
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Earliest armed deadline.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.timers
+            .values()
+            .filter_map(|timer| timer.deadline)
+            .min()
+    }
+
+    /// Queue an event for every timer whose deadline has passed.
+    pub fn fire(&mut self, now: u64, events: &mut EventQueue) {
+        for (&id, timer) in self.timers.iter_mut() {
+            let Some(deadline) = timer.deadline else {
+                continue;
+            };
+            if now < deadline {
+                continue;
+            }
+
+            // A zero period (one-shot timer) has no next deadline.
+            let expirations = match (now - deadline).checked_div(timer.period_ms) {
+                Some(missed) => {
+                    timer.deadline = Some(deadline + (missed + 1) * timer.period_ms);
+                    missed + 1
+                }
+                None => {
+                    timer.deadline = None;
+                    1
+                }
+            };
+            events.push(Event::Timer {
+                timer: id,
+                expirations,
+            });
+        }
+    }
+}
+
+impl Default for ProcessTimers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,1069 @@
+//! Host functions of the simulator.
+//!
+//! A subset of the kernel's (`src/kernel/src/wasm/host.rs`), with the same
+//! signatures:
+//!
+//! - `sp_get_capabilities`, `sp_describe_capabilities`
+//! - `sp_fs_open`, `sp_fs_read`, `sp_fs_write`, `sp_fs_size`,
+//!   `sp_fs_close`, `sp_fs_mkdir`, `sp_fs_create`, `sp_fs_readdir`,
+//!   `sp_fs_unlink`, `sp_fs_rename`
+//! - `sp_sched_yield`, `sp_clock_monotonic_ms`, `sp_sleep_ms`
+//! - `sp_stdout_write`
+//! - `sp_net_connect`, `sp_net_listen`, `sp_net_send`, `sp_net_recv`,
+//!   `sp_net_close`
+//!
+//! Anything else a module imports traps with `SimTrap::Unsupported` when
+//! called. Fuel is not metered (see `process`), so only `sp_sched_yield`
+//! and `sp_sleep_ms` end a slice early.
+//!
+//! Each function is a method of `SimState` on plain values, which the
+//! linker glue calls after copying arguments out of linear memory. The
+//! capability checks and filesystem work are the kernel's own, from
+//! `sovelma_core::wasm::calls` on the process's `ProcessCapabilities`; only
+//! the clock, stdout and the sockets on the simulated network are the
+//! simulator's.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use smoltcp::iface::SocketHandle;
+use smoltcp::wire::Ipv4Address;
+use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType};
+use sovelma_common::host::{DEFAULT_FS_QUOTA, FS_PATH_MAX, FS_WRITE_MAX, MAX_PROCESS_SOCKETS};
+use wasmi::core::Trap;
+use wasmi::{Caller, Extern, ExternType, Linker, Memory, Module};
+
+use crate::calls;
+use crate::capabilities::ProcessCapabilities;
+use crate::fs::{FileHandle, FileSystem, ROOT_FS};
+use crate::handles::Handle;
+use crate::net::SimNet;
+
+pub use sovelma_common::host::error;
+
+/// Why a host function stopped the process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimTrap {
+    /// `sp_sched_yield`: end the slice.
+    Yield,
+    /// `sp_sleep_ms`: not resumed before this time (ms since start).
+    Sleep(u64),
+    /// The module called a host function the simulator does not have.
+    Unsupported(String),
+}
+
+impl fmt::Display for SimTrap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimTrap::Yield => write!(f, "Yield"),
+            SimTrap::Sleep(deadline) => write!(f, "Sleep(until {}ms)", deadline),
+            SimTrap::Unsupported(name) => write!(f, "{} is not simulated", name),
+        }
+    }
+}
+
+impl wasmi::core::HostError for SimTrap {}
+
+/// State of one simulated process.
+pub struct SimState {
+    /// Capabilities the process holds, with its handles for them.
+    pub capabilities: ProcessCapabilities,
+    /// Bytes the process may still add to files with `sp_fs_write`.
+    pub fs_quota: u64,
+    /// What the process wrote to stdout and nobody has taken yet.
+    pub output: Vec<u8>,
+    /// The network; without one the net functions fail with
+    /// DEVICE_UNAVAILABLE.
+    pub net: Option<SimNet>,
+    /// Sockets by the handle in their Socket capability.
+    sockets: BTreeMap<u64, SocketHandle>,
+    next_socket: u64,
+    started: std::time::Instant,
+}
+
+impl Default for SimState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimState {
+    /// A process holding nothing.
+    pub fn new() -> Self {
+        Self {
+            capabilities: ProcessCapabilities::new(),
+            fs_quota: DEFAULT_FS_QUOTA,
+            output: Vec::new(),
+            net: None,
+            sockets: BTreeMap::new(),
+            next_socket: 1,
+            started: std::time::Instant::now(),
+        }
+    }
+
+    /// A process holding `caps`, at handles 1, 2, ... in order.
+    pub fn with_capabilities(caps: impl IntoIterator<Item = Capability>) -> Self {
+        let mut state = Self::new();
+        for cap in caps {
+            state.grant(cap);
+        }
+        state
+    }
+
+    /// Add a capability and return the process's handle for it.
+    pub fn grant(&mut self, cap: Capability) -> Handle {
+        self.capabilities.grant(cap)
+    }
+
+    /// The capability `handle` names, if the process holds it and its
+    /// generation is current.
+    pub fn capability(&self, handle: i64) -> Option<&Capability> {
+        self.capabilities.capability(handle)
+    }
+
+    /// Give up the capability named by `handle`, returning it.
+    pub fn close(&mut self, handle: i64) -> Option<Capability> {
+        self.capabilities.close(handle)
+    }
+
+    /// Capabilities the process holds with their handles, lowest first.
+    pub fn held(&self) -> Vec<(Handle, Capability)> {
+        self.capabilities.held()
+    }
+
+    /// Release what the process holds: files are closed, sockets closed
+    /// and capabilities dropped.
+    pub fn teardown(&mut self) {
+        for cap in self.capabilities.take_all() {
+            if let CapabilityType::File(handle) | CapabilityType::Directory(handle) = cap.object {
+                ROOT_FS.close(FileHandle(handle as u32));
+            }
+        }
+        let sockets = core::mem::take(&mut self.sockets);
+        if let Some(net) = &mut self.net {
+            for socket in sockets.into_values() {
+                net.close(socket);
+            }
+        }
+    }
+
+    /// Milliseconds since the process was created.
+    pub fn now_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    /// `sp_describe_capabilities` (`sp_get_capabilities` is version 1):
+    /// the held capabilities in capability list format `version`, with how
+    /// many there are, or BUFFER_TOO_SMALL if they need more than `len`
+    /// bytes.
+    pub fn capability_list(&self, version: u32, len: usize) -> Result<(Vec<u8>, usize), i32> {
+        calls::capability_list(&self.capabilities, version, len)
+    }
+
+    /// `sp_fs_open`: see `calls::fs_open`.
+    pub fn fs_open(&mut self, dir_cap: i64, path: &str) -> i64 {
+        calls::fs_open(&mut self.capabilities, dir_cap, path)
+    }
+
+    /// `sp_fs_read`: see `calls::fs_read`.
+    pub fn fs_read(&self, file_cap: i64, buf: &mut [u8], offset: usize) -> i32 {
+        calls::fs_read(&self.capabilities, file_cap, buf, offset)
+    }
+
+    /// `sp_fs_write`: see `calls::fs_write`; growth is taken from
+    /// `fs_quota`.
+    pub fn fs_write(&mut self, file_cap: i64, data: &[u8], offset: usize) -> i32 {
+        let quota = &mut self.fs_quota;
+        calls::fs_write(&self.capabilities, quota, file_cap, data, offset)
+    }
+
+    /// `sp_fs_size`: see `calls::fs_size`.
+    pub fn fs_size(&self, file_cap: i64) -> i32 {
+        calls::fs_size(&self.capabilities, file_cap)
+    }
+
+    /// `sp_fs_close`: see `calls::fs_close`.
+    pub fn fs_close(&mut self, file_cap: i64) {
+        calls::fs_close(&mut self.capabilities, file_cap)
+    }
+
+    /// `sp_fs_mkdir`: see `calls::fs_mkdir`.
+    pub fn fs_mkdir(&mut self, dir_cap: i64, path: &str) -> i32 {
+        calls::fs_mkdir(&self.capabilities, dir_cap, path)
+    }
+
+    /// `sp_fs_create`: see `calls::fs_create`.
+    pub fn fs_create(&mut self, dir_cap: i64, path: &str) -> i64 {
+        calls::fs_create(&mut self.capabilities, dir_cap, path)
+    }
+
+    /// `sp_fs_unlink`: see `calls::fs_unlink`.
+    pub fn fs_unlink(&mut self, dir_cap: i64, path: &str) -> i32 {
+        calls::fs_unlink(&self.capabilities, dir_cap, path)
+    }
+
+    /// `sp_fs_rename`: see `calls::fs_rename`.
+    pub fn fs_rename(&mut self, dir_cap: i64, from: &str, to: &str) -> i32 {
+        calls::fs_rename(&self.capabilities, dir_cap, from, to)
+    }
+
+    /// `sp_fs_readdir`: see `calls::fs_readdir`.
+    pub fn fs_readdir(
+        &self,
+        dir_cap: i64,
+        start: usize,
+        len: usize,
+    ) -> Result<(Vec<u8>, usize), i32> {
+        calls::fs_readdir(&self.capabilities, dir_cap, start, len)
+    }
+
+    /// `sp_clock_monotonic_ms`: milliseconds since start, with a Timer
+    /// capability with READ.
+    pub fn clock_ms(&self) -> i64 {
+        if !self.capabilities.has_timer_rights(CapabilityRights::READ) {
+            return error::PERMISSION_DENIED;
+        }
+        self.now_ms() as i64
+    }
+
+    /// `sp_sleep_ms`: the time to sleep until, with a Timer capability
+    /// with CALL, or an error code.
+    pub fn sleep_until(&self, ms: i64) -> Result<u64, i32> {
+        if !self.capabilities.has_timer_rights(CapabilityRights::CALL) {
+            return Err(error::PERMISSION_DENIED as i32);
+        }
+        if ms < 0 {
+            return Err(error::INVALID_ARGUMENT as i32);
+        }
+        Ok(self.now_ms().saturating_add(ms as u64))
+    }
+
+    /// `sp_stdout_write`: queue `data` for the console. Returns its
+    /// length.
+    pub fn stdout_write(&mut self, data: &[u8]) -> i32 {
+        self.output.extend_from_slice(data);
+        data.len() as i32
+    }
+
+    /// Check that `net_cap` is a Network capability with `rights` and the
+    /// process may open another socket.
+    fn check_network(&self, net_cap: i64, rights: CapabilityRights) -> Result<Capability, i64> {
+        let cap = calls::network(&self.capabilities, net_cap, rights).map_err(i64::from)?;
+        if self.sockets.len() >= MAX_PROCESS_SOCKETS {
+            return Err(error::TOO_MANY_SOCKETS);
+        }
+        if self.net.is_none() {
+            return Err(error::DEVICE_UNAVAILABLE);
+        }
+        Ok(cap)
+    }
+
+    /// Keep `socket` and grant a Socket capability for it, derived from
+    /// `net_cap`.
+    fn add_socket(&mut self, net_cap: i64, socket: SocketHandle) -> i64 {
+        let handle = self.next_socket;
+        self.next_socket += 1;
+        self.sockets.insert(handle, socket);
+        let cap = Capability::new(
+            CapabilityType::Socket(handle),
+            CapabilityRights::READ | CapabilityRights::WRITE,
+        );
+        self.capabilities.derive(net_cap, cap).as_raw()
+    }
+
+    /// `sp_net_connect`: connect to `addr` (network byte order) on
+    /// `port`, with a Network capability with CONNECT. Returns the
+    /// Socket capability's handle, or an error code.
+    pub fn net_connect(&mut self, net_cap: i64, addr: u32, port: i32) -> i64 {
+        if let Err(code) = self.check_network(net_cap, CapabilityRights::CONNECT) {
+            return code;
+        }
+        let Ok(port) = u16::try_from(port) else {
+            return error::INVALID_ARGUMENT;
+        };
+        let addr = Ipv4Address::from_bytes(&addr.to_be_bytes());
+        match self.net.as_mut().map(|net| net.connect(addr, port)) {
+            Some(Ok(socket)) => self.add_socket(net_cap, socket),
+            Some(Err(sovelma_common::net::NetError::OutOfMemory)) => error::OUT_OF_MEMORY,
+            _ => error::NET_ERROR,
+        }
+    }
+
+    /// `sp_net_listen`: listen on `port`, which the Network capability
+    /// must cover. Returns the Socket capability's handle, or an error
+    /// code.
+    pub fn net_listen(&mut self, net_cap: i64, port: i32) -> i64 {
+        let cap = match self.check_network(net_cap, CapabilityRights::empty()) {
+            Ok(cap) => cap,
+            Err(code) => return code,
+        };
+        let Ok(port) = u16::try_from(port) else {
+            return error::INVALID_ARGUMENT;
+        };
+        if !cap.permits_listen(port) {
+            return error::PERMISSION_DENIED;
+        }
+        match self.net.as_mut().map(|net| net.listen(port)) {
+            Some(Ok(socket)) => self.add_socket(net_cap, socket),
+            Some(Err(sovelma_common::net::NetError::OutOfMemory)) => error::OUT_OF_MEMORY,
+            _ => error::NET_ERROR,
+        }
+    }
+
+    /// The socket `sock_cap` grants `rights` on, and the network.
+    fn socket(
+        &mut self,
+        sock_cap: i64,
+        rights: CapabilityRights,
+    ) -> Result<(u64, SocketHandle, &mut SimNet), i32> {
+        let handle = calls::socket(&self.capabilities, sock_cap, rights)?;
+        let socket = *self
+            .sockets
+            .get(&handle)
+            .ok_or(error::INVALID_HANDLE as i32)?;
+        let net = self.net.as_mut().ok_or(error::DEVICE_UNAVAILABLE as i32)?;
+        Ok((handle, socket, net))
+    }
+
+    /// `sp_net_send`: queue `data` on the socket `sock_cap` grants WRITE
+    /// on. Returns bytes queued, WOULD_BLOCK while it cannot send yet, or
+    /// NET_ERROR once the connection is closed.
+    pub fn net_send(&mut self, sock_cap: i64, data: &[u8]) -> i32 {
+        let (_, handle, net) = match self.socket(sock_cap, CapabilityRights::WRITE) {
+            Ok(socket) => socket,
+            Err(code) => return code,
+        };
+        let socket = net.socket(handle);
+        if socket.can_send() {
+            socket
+                .send_slice(data)
+                .map_or(error::NET_ERROR as i32, |sent| sent as i32)
+        } else if socket.is_active() {
+            error::WOULD_BLOCK as i32
+        } else {
+            error::NET_ERROR as i32
+        }
+    }
+
+    /// `sp_net_recv`: receive into `buf` from the socket `sock_cap` grants
+    /// READ on. Returns bytes received, 0 once the peer has closed,
+    /// WOULD_BLOCK while nothing is waiting, or an error code.
+    pub fn net_recv(&mut self, sock_cap: i64, buf: &mut [u8]) -> i32 {
+        let (_, handle, net) = match self.socket(sock_cap, CapabilityRights::READ) {
+            Ok(socket) => socket,
+            Err(code) => return code,
+        };
+        let socket = net.socket(handle);
+        if socket.can_recv() {
+            socket
+                .recv_slice(buf)
+                .map_or(error::NET_ERROR as i32, |count| count as i32)
+        } else if socket.may_recv() || socket.is_listening() || socket.is_active() {
+            error::WOULD_BLOCK as i32
+        } else {
+            0
+        }
+    }
+
+    /// `sp_net_close`: close the socket `sock_cap` names and give up the
+    /// capability. Returns 0, or an error code.
+    pub fn net_close(&mut self, sock_cap: i64) -> i32 {
+        let (handle, socket, net) = match self.socket(sock_cap, CapabilityRights::empty()) {
+            Ok(socket) => socket,
+            Err(code) => return code,
+        };
+        net.close(socket);
+        self.sockets.remove(&handle);
+        self.close(sock_cap);
+        0
+    }
+}
+
+/// Host functions the simulator has.
+pub const SIMULATED: &[&str] = &[
+    "sp_get_capabilities",
+    "sp_describe_capabilities",
+    "sp_fs_open",
+    "sp_fs_read",
+    "sp_fs_write",
+    "sp_fs_size",
+    "sp_fs_close",
+    "sp_fs_mkdir",
+    "sp_fs_create",
+    "sp_fs_readdir",
+    "sp_fs_unlink",
+    "sp_fs_rename",
+    "sp_sched_yield",
+    "sp_clock_monotonic_ms",
+    "sp_sleep_ms",
+    "sp_stdout_write",
+    "sp_net_connect",
+    "sp_net_listen",
+    "sp_net_send",
+    "sp_net_recv",
+    "sp_net_close",
+];
+
+/// The caller's exported linear memory.
+fn memory(caller: &Caller<'_, SimState>) -> Result<Memory, i64> {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => Ok(memory),
+        _ => Err(error::NO_MEMORY_EXPORT),
+    }
+}
+
+/// Copy `len` bytes at `ptr` out of the caller's memory.
+fn read_bytes(caller: &Caller<'_, SimState>, ptr: i32, len: i32) -> Result<Vec<u8>, i64> {
+    let memory = memory(caller)?;
+    let mut buffer = alloc::vec![0u8; len.max(0) as usize];
+    memory
+        .read(caller, ptr as u32 as usize, &mut buffer)
+        .map_err(|_| error::MEMORY_READ_FAILED)?;
+    Ok(buffer)
+}
+
+/// Copy `len` bytes at `ptr` out of the caller's memory as a string.
+fn read_str(caller: &Caller<'_, SimState>, ptr: i32, len: i32) -> Result<String, i64> {
+    String::from_utf8(read_bytes(caller, ptr, len)?).map_err(|_| error::INVALID_UTF8)
+}
+
+/// Copy a path of `len` bytes at `ptr` out of the caller's memory; it
+/// may be at most `FS_PATH_MAX` bytes long.
+fn read_path(caller: &Caller<'_, SimState>, ptr: i32, len: i32) -> Result<String, i64> {
+    if usize::try_from(len).map_or(true, |len| len > FS_PATH_MAX) {
+        return Err(error::INVALID_ARGUMENT);
+    }
+    read_str(caller, ptr, len)
+}
+
+/// Copy `data` to `ptr` in the caller's memory.
+fn write_bytes(caller: &mut Caller<'_, SimState>, ptr: i32, data: &[u8]) -> Result<(), i64> {
+    let memory = memory(caller)?;
+    memory
+        .write(caller, ptr as u32 as usize, data)
+        .map_err(|_| error::MEMORY_WRITE_FAILED)
+}
+
+/// Register the simulated host functions with `linker`, and for every
+/// other function `module` imports one that traps.
+pub fn register_functions(
+    linker: &mut Linker<SimState>,
+    module: &Module,
+) -> Result<(), wasmi::Error> {
+    register_fs_functions(linker)?;
+    register_process_functions(linker)?;
+    register_net_functions(linker)?;
+
+    for import in module.imports() {
+        let ExternType::Func(ty) = import.ty() else {
+            continue;
+        };
+        if import.module() == "env" && SIMULATED.contains(&import.name()) {
+            continue;
+        }
+        let name = String::from(import.name());
+        linker.func_new(
+            import.module(),
+            import.name(),
+            ty.clone(),
+            move |_, _, _| Err(Trap::from(SimTrap::Unsupported(name.clone()))),
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the capability list for `sp_get_capabilities` and
+/// `sp_describe_capabilities` to `ptr`.
+fn write_capability_list(
+    caller: &mut Caller<'_, SimState>,
+    version: u32,
+    ptr: i32,
+    len: i32,
+) -> i32 {
+    let (list, count) = match caller.data().capability_list(version, len.max(0) as usize) {
+        Ok(list) => list,
+        Err(code) => return code,
+    };
+    match write_bytes(caller, ptr, &list) {
+        Ok(()) => count as i32,
+        Err(code) => code as i32,
+    }
+}
+
+/// Register the capability and filesystem functions.
+fn register_fs_functions(linker: &mut Linker<SimState>) -> Result<(), wasmi::Error> {
+    linker.func_wrap(
+        "env",
+        "sp_get_capabilities",
+        |mut caller: Caller<'_, SimState>, ptr: i32, len: i32| -> i32 {
+            write_capability_list(&mut caller, 1, ptr, len)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_describe_capabilities",
+        |mut caller: Caller<'_, SimState>, version: i32, ptr: i32, len: i32| -> i32 {
+            write_capability_list(&mut caller, version as u32, ptr, len)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_fs_open",
+        |mut caller: Caller<'_, SimState>, dir_cap: i64, path_ptr: i32, path_len: i32| -> i64 {
+            match read_str(&caller, path_ptr, path_len) {
+                Ok(path) => caller.data_mut().fs_open(dir_cap, &path),
+                Err(code) => code,
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_fs_read",
+        |mut caller: Caller<'_, SimState>,
+         file_cap: i64,
+         buf_ptr: i32,
+         buf_len: i32,
+         offset: i32|
+         -> i32 {
+            let mut buffer = alloc::vec![0u8; buf_len.max(0) as usize];
+            let count = caller
+                .data()
+                .fs_read(file_cap, &mut buffer, offset.max(0) as usize);
+            if count <= 0 {
+                return count;
+            }
+            match write_bytes(&mut caller, buf_ptr, &buffer[..count as usize]) {
+                Ok(()) => count,
+                Err(code) => code as i32,
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_fs_write",
+        |mut caller: Caller<'_, SimState>,
+         file_cap: i64,
+         buf_ptr: i32,
+         buf_len: i32,
+         offset: i32|
+         -> i32 {
+            if buf_len < 0 || offset < 0 {
+                return error::INVALID_ARGUMENT as i32;
+            }
+            let len = (buf_len as usize).min(FS_WRITE_MAX) as i32;
+            match read_bytes(&caller, buf_ptr, len) {
+                Ok(data) => caller.data_mut().fs_write(file_cap, &data, offset as usize),
+                Err(code) => code as i32,
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_fs_size",
+        |caller: Caller<'_, SimState>, file_cap: i64| -> i32 { caller.data().fs_size(file_cap) },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_fs_close",
+        |mut caller: Caller<'_, SimState>, file_cap: i64| caller.data_mut().fs_close(file_cap),
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_fs_mkdir",
+        |mut caller: Caller<'_, SimState>, dir_cap: i64, path_ptr: i32, path_len: i32| -> i32 {
+            match read_str(&caller, path_ptr, path_len) {
+                Ok(path) => caller.data_mut().fs_mkdir(dir_cap, &path),
+                Err(code) => code as i32,
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_fs_create",
+        |mut caller: Caller<'_, SimState>, dir_cap: i64, path_ptr: i32, path_len: i32| -> i64 {
+            match read_str(&caller, path_ptr, path_len) {
+                Ok(path) => caller.data_mut().fs_create(dir_cap, &path),
+                Err(code) => code,
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_fs_readdir",
+        |mut caller: Caller<'_, SimState>,
+         dir_cap: i64,
+         start: i32,
+         buf_ptr: i32,
+         buf_len: i32|
+         -> i32 {
+            if start < 0 || buf_len < 0 {
+                return error::INVALID_ARGUMENT as i32;
+            }
+            let listing = caller
+                .data()
+                .fs_readdir(dir_cap, start as usize, buf_len as usize);
+            let (bytes, count) = match listing {
+                Ok(listing) => listing,
+                Err(code) => return code,
+            };
+            match write_bytes(&mut caller, buf_ptr, &bytes) {
+                Ok(()) => count as i32,
+                Err(code) => code as i32,
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_fs_unlink",
+        |mut caller: Caller<'_, SimState>, dir_cap: i64, path_ptr: i32, path_len: i32| -> i32 {
+            match read_path(&caller, path_ptr, path_len) {
+                Ok(path) => caller.data_mut().fs_unlink(dir_cap, &path),
+                Err(code) => code as i32,
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_fs_rename",
+        |mut caller: Caller<'_, SimState>,
+         dir_cap: i64,
+         from_ptr: i32,
+         from_len: i32,
+         to_ptr: i32,
+         to_len: i32|
+         -> i32 {
+            let paths = read_path(&caller, from_ptr, from_len)
+                .and_then(|from| Ok((from, read_path(&caller, to_ptr, to_len)?)));
+            match paths {
+                Ok((from, to)) => caller.data_mut().fs_rename(dir_cap, &from, &to),
+                Err(code) => code as i32,
+            }
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Register the scheduling, clock and stdout functions.
+fn register_process_functions(linker: &mut Linker<SimState>) -> Result<(), wasmi::Error> {
+    linker.func_wrap(
+        "env",
+        "sp_sched_yield",
+        |_caller: Caller<'_, SimState>| -> Result<(), Trap> { Err(Trap::from(SimTrap::Yield)) },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_clock_monotonic_ms",
+        |caller: Caller<'_, SimState>| -> i64 { caller.data().clock_ms() },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_sleep_ms",
+        |caller: Caller<'_, SimState>, ms: i64| -> Result<i32, Trap> {
+            match caller.data().sleep_until(ms) {
+                Ok(deadline) => Err(Trap::from(SimTrap::Sleep(deadline))),
+                Err(code) => Ok(code),
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_stdout_write",
+        |mut caller: Caller<'_, SimState>, buf_ptr: i32, buf_len: i32| -> i32 {
+            match read_bytes(&caller, buf_ptr, buf_len) {
+                Ok(data) => caller.data_mut().stdout_write(&data),
+                Err(code) => code as i32,
+            }
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Register the TCP socket functions.
+fn register_net_functions(linker: &mut Linker<SimState>) -> Result<(), wasmi::Error> {
+    linker.func_wrap(
+        "env",
+        "sp_net_connect",
+        |mut caller: Caller<'_, SimState>, net_cap: i64, addr: i32, port: i32| -> i64 {
+            caller.data_mut().net_connect(net_cap, addr as u32, port)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_net_listen",
+        |mut caller: Caller<'_, SimState>, net_cap: i64, port: i32| -> i64 {
+            caller.data_mut().net_listen(net_cap, port)
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_net_send",
+        |mut caller: Caller<'_, SimState>, sock_cap: i64, buf_ptr: i32, buf_len: i32| -> i32 {
+            match read_bytes(&caller, buf_ptr, buf_len) {
+                Ok(data) => caller.data_mut().net_send(sock_cap, &data),
+                Err(code) => code as i32,
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_net_recv",
+        |mut caller: Caller<'_, SimState>, sock_cap: i64, buf_ptr: i32, buf_len: i32| -> i32 {
+            let mut buffer = alloc::vec![0u8; buf_len.max(0) as usize];
+            let count = caller.data_mut().net_recv(sock_cap, &mut buffer);
+            if count <= 0 {
+                return count;
+            }
+            match write_bytes(&mut caller, buf_ptr, &buffer[..count as usize]) {
+                Ok(()) => count,
+                Err(code) => code as i32,
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        "env",
+        "sp_net_close",
+        |mut caller: Caller<'_, SimState>, sock_cap: i64| -> i32 {
+            caller.data_mut().net_close(sock_cap)
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sovelma_common::capability::{
+        CapabilityDescriptor, CapabilityDetail, CapabilityRecord, Label,
+    };
+    use sovelma_common::codec::Reader;
+    use sovelma_common::fs::{DirEntryRecord, EntryKind};
+
+    /// A process holding `rights` on the directory `path`, created with a
+    /// file `a.txt` of "abc".
+    fn with_dir(path: &str, rights: CapabilityRights) -> (SimState, i64) {
+        ROOT_FS.add_file(&alloc::format!("{}/a.txt", path), b"abc");
+        let Ok(dir) = ROOT_FS.open(path) else {
+            panic!("{} was not created", path);
+        };
+        let mut state = SimState::new();
+        let cap = Capability::new(CapabilityType::Directory(u64::from(dir.0)), rights);
+        let handle = state.grant(cap).as_raw();
+        (state, handle)
+    }
+
+    #[test]
+    fn fs_open_narrows_rights_to_the_directory() {
+        let (mut state, dir) = with_dir("sim-test/open", CapabilityRights::READ);
+        let file = state.fs_open(dir, "a.txt");
+        assert!(file > 0);
+        let Some(cap) = state.capability(file) else {
+            panic!("no capability for the opened file");
+        };
+        assert_eq!(cap.rights, CapabilityRights::READ);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(state.fs_read(file, &mut buf, 1), 2);
+        assert_eq!(&buf[..2], b"bc");
+        assert_eq!(
+            state.fs_write(file, b"x", 0),
+            error::PERMISSION_DENIED as i32
+        );
+        assert_eq!(state.fs_mkdir(dir, "sub"), error::PERMISSION_DENIED as i32);
+        assert_eq!(state.fs_open(dir, "missing"), error::FS_ERROR);
+    }
+
+    #[test]
+    fn fs_readdir_lists_in_batches() {
+        ROOT_FS.add_file("sim-test/readdir/sub/b.txt", b"");
+        let (state, dir) = with_dir("sim-test/readdir", CapabilityRights::READ);
+        let entries = |start, len| {
+            let Ok((bytes, count)) = state.fs_readdir(dir, start, len) else {
+                panic!("listing failed");
+            };
+            let mut reader = Reader::new(&bytes);
+            let names: Vec<(String, EntryKind, u64)> = match reader.seq::<DirEntryRecord>() {
+                Ok(items) => items
+                    .map_while(Result::ok)
+                    .map(|entry| (String::from(entry.name), entry.kind, entry.size))
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            assert_eq!(names.len(), count);
+            names
+        };
+        assert_eq!(
+            entries(0, 64),
+            [
+                (String::from("a.txt"), EntryKind::File, 3),
+                (String::from("sub"), EntryKind::Directory, 0),
+            ]
+        );
+        assert_eq!(entries(0, 10).len(), 1);
+        assert_eq!(entries(1, 10).len(), 1);
+        assert!(entries(2, 10).is_empty());
+        assert_eq!(
+            state.fs_readdir(dir, 0, 4),
+            Err(error::BUFFER_TOO_SMALL as i32)
+        );
+    }
+
+    #[test]
+    fn fs_unlink_and_rename_need_write() {
+        let (mut state, dir) = with_dir("sim-test/unlink", CapabilityRights::READ);
+        assert_eq!(
+            state.fs_unlink(dir, "a.txt"),
+            error::PERMISSION_DENIED as i32
+        );
+        let (mut state, dir) = with_dir(
+            "sim-test/unlink",
+            CapabilityRights::READ | CapabilityRights::WRITE,
+        );
+        let file = state.fs_open(dir, "a.txt");
+        assert_eq!(state.fs_rename(dir, "a.txt", "b.txt"), 0);
+        assert_eq!(state.fs_open(dir, "a.txt"), error::FS_ERROR);
+        assert_eq!(state.fs_unlink(dir, "b.txt"), 0);
+        assert_eq!(state.fs_unlink(dir, "b.txt"), error::FS_ERROR as i32);
+        // The open file outlives its name
+        let mut buf = [0u8; 4];
+        assert_eq!(state.fs_read(file, &mut buf, 0), 3);
+    }
+
+    #[test]
+    fn closed_handles_are_not_found() {
+        let (mut state, dir) = with_dir("sim-test/close", CapabilityRights::READ);
+        let file = state.fs_open(dir, "a.txt");
+        state.fs_close(file);
+        assert_eq!(state.fs_size(file), error::CAP_NOT_FOUND as i32);
+        assert_eq!(state.fs_open(file, "a.txt"), error::CAP_NOT_FOUND);
+        assert_eq!(state.fs_open(0, "a.txt"), error::CAP_NOT_FOUND);
+    }
+
+    #[test]
+    fn fs_write_takes_growth_from_the_quota() {
+        let (mut state, dir) = with_dir(
+            "sim-test/quota",
+            CapabilityRights::READ | CapabilityRights::WRITE,
+        );
+        let file = state.fs_open(dir, "a.txt");
+        state.fs_quota = 4;
+        assert_eq!(state.fs_write(file, b"xyz", 0), 3);
+        assert_eq!(state.fs_quota, 4);
+        assert_eq!(state.fs_write(file, b"1234", 2), 4);
+        assert_eq!(state.fs_quota, 1);
+        assert_eq!(state.fs_write(file, b"12", 6), error::QUOTA_EXCEEDED as i32);
+        assert_eq!(state.fs_size(file), 6);
+    }
+
+    #[test]
+    fn namespaces_see_only_their_mounts() {
+        ROOT_FS.add_file("sim-test/ns/apps/app.wasm", b"\0asm");
+        ROOT_FS.add_file("sim-test/ns/data/log", b"");
+        let (Ok(apps), Ok(data)) = (
+            ROOT_FS.open("sim-test/ns/apps"),
+            ROOT_FS.open("sim-test/ns/data"),
+        ) else {
+            panic!("mount sources were not created");
+        };
+        let namespace = ROOT_FS.namespace();
+        assert_eq!(ROOT_FS.mount(namespace, "/apps", apps, true), Ok(()));
+        assert_eq!(ROOT_FS.mount(namespace, "/srv/data", data, false), Ok(()));
+        assert!(ROOT_FS.mount(namespace, "/apps/lib", data, false).is_err());
+        let mut state = SimState::new();
+        let rights = CapabilityRights::READ | CapabilityRights::WRITE;
+        let cap = Capability::new(CapabilityType::Directory(u64::from(namespace.0)), rights);
+        let root = state.grant(cap).as_raw();
+
+        let app = state.fs_open(root, "apps/app.wasm");
+        assert_eq!(state.fs_size(app), 4);
+        assert_eq!(
+            state.fs_write(app, b"x", 0),
+            error::PERMISSION_DENIED as i32
+        );
+        assert_eq!(state.fs_mkdir(root, "apps/new"), error::FS_ERROR as i32);
+        let log = state.fs_open(root, "srv/data/log");
+        assert_eq!(state.fs_write(log, b"ok", 0), 2);
+        assert_eq!(state.fs_open(root, "sim-test"), error::FS_ERROR);
+
+        assert_eq!(state.fs_mkdir(root, "tmp"), 0);
+        assert!(ROOT_FS.open("tmp").is_err());
+    }
+
+    #[test]
+    fn tmpfs_files_take_from_its_quota() {
+        let namespace = ROOT_FS.namespace();
+        assert_eq!(ROOT_FS.mount_tmpfs(namespace, "tmp", 4), Ok(()));
+        let mut state = SimState::new();
+        let rights = CapabilityRights::READ | CapabilityRights::WRITE;
+        let cap = Capability::new(CapabilityType::Directory(u64::from(namespace.0)), rights);
+        let root = state.grant(cap).as_raw();
+
+        let file = state.fs_create(root, "tmp/a");
+        assert!(file > 0);
+        assert_eq!(state.fs_create(root, "tmp/a"), error::FS_ERROR);
+        assert_eq!(state.fs_write(file, b"abc", 0), 3);
+        assert_eq!(state.fs_write(file, b"de", 3), error::QUOTA_EXCEEDED as i32);
+        assert_eq!(state.fs_size(file), 3);
+    }
+
+    #[test]
+    fn capability_records_list_held_capabilities() {
+        let state = SimState::with_capabilities([
+            Capability::new(CapabilityType::Timer, CapabilityRights::READ),
+            Capability::new(CapabilityType::Config, CapabilityRights::WRITE),
+        ]);
+        assert_eq!(
+            state.capability_list(1, 8),
+            Err(error::BUFFER_TOO_SMALL as i32)
+        );
+        let Ok((records, 2)) = state.capability_list(1, 64) else {
+            panic!("records did not fit");
+        };
+        let mut reader = Reader::new(&records);
+        let listed: Vec<_> = reader.seq::<CapabilityRecord>().unwrap().collect();
+        assert_eq!(
+            listed,
+            [
+                Ok(CapabilityRecord {
+                    handle: 1,
+                    kind: "timer",
+                    rights: CapabilityRights::READ,
+                }),
+                Ok(CapabilityRecord {
+                    handle: 2,
+                    kind: "config",
+                    rights: CapabilityRights::WRITE,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn descriptors_carry_labels_and_details() {
+        ROOT_FS.add_file("described/data/notes.txt", b"");
+        let Ok(dir) = ROOT_FS.open("described/data") else {
+            panic!("described/data was not created");
+        };
+        let label = |name| Label::new(name).unwrap();
+        let state = SimState::with_capabilities([
+            Capability::new(
+                CapabilityType::Network {
+                    first_port: 8000,
+                    last_port: 8099,
+                },
+                CapabilityRights::LISTEN,
+            )
+            .with_label(label("web")),
+            Capability::new(
+                CapabilityType::Directory(u64::from(dir.0)),
+                CapabilityRights::READ,
+            )
+            .with_label(label("data")),
+            Capability::new(
+                CapabilityType::Directory(u64::from(ROOT_FS.namespace().0)),
+                CapabilityRights::READ,
+            ),
+        ]);
+        assert_eq!(
+            state.capability_list(3, 256),
+            Err(error::INVALID_ARGUMENT as i32)
+        );
+        let Ok((list, 3)) = state.capability_list(2, 256) else {
+            panic!("descriptors did not fit");
+        };
+        let mut reader = Reader::new(&list);
+        let described: Vec<_> = reader
+            .seq::<CapabilityDescriptor>()
+            .unwrap()
+            .map(|descriptor| {
+                let descriptor = descriptor.unwrap();
+                (descriptor.label, descriptor.detail)
+            })
+            .collect();
+        assert_eq!(
+            described,
+            [
+                (
+                    Some("web"),
+                    CapabilityDetail::Ports {
+                        first: 8000,
+                        last: 8099
+                    }
+                ),
+                (Some("data"), CapabilityDetail::Path("/described/data")),
+                (None, CapabilityDetail::None),
+            ]
+        );
+    }
+
+    #[test]
+    fn sockets_talk_over_loopback() {
+        let mut state = SimState::with_capabilities([Capability::new(
+            CapabilityType::Network {
+                first_port: 7000,
+                last_port: 7000,
+            },
+            CapabilityRights::CONNECT | CapabilityRights::LISTEN,
+        )]);
+        state.net = Some(SimNet::loopback());
+        assert_eq!(state.net_listen(1, 7001), error::PERMISSION_DENIED);
+
+        let server = state.net_listen(1, 7000);
+        let client = state.net_connect(1, u32::from_be_bytes([127, 0, 0, 1]), 7000);
+        assert!(server > 0 && client > 0);
+
+        let mut buf = [0u8; 8];
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            if let Some(net) = &mut state.net {
+                net.poll();
+            }
+            if received.is_empty() {
+                state.net_send(client, b"ping");
+            }
+            let count = state.net_recv(server, &mut buf);
+            if count > 0 {
+                received.extend_from_slice(&buf[..count as usize]);
+                break;
+            }
+            assert_eq!(count, error::WOULD_BLOCK as i32);
+        }
+        assert_eq!(received, b"ping");
+        assert_eq!(state.net_close(client), 0);
+        assert_eq!(state.net_close(client), error::CAP_NOT_FOUND as i32);
+    }
+
+    #[test]
+    fn net_functions_need_a_network() {
+        let mut state = SimState::with_capabilities([Capability::new(
+            CapabilityType::Network {
+                first_port: 0,
+                last_port: 0,
+            },
+            CapabilityRights::CONNECT,
+        )]);
+        assert_eq!(state.net_connect(1, 0, 80), error::DEVICE_UNAVAILABLE);
+        assert_eq!(state.net_connect(2, 0, 80), error::CAP_NOT_FOUND);
+    }
+}
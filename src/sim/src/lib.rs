@@ -0,0 +1,31 @@
+//! Host-side simulator for the kernel's services layer.
+//!
+//! Runs WASM processes as an ordinary program on the host, without QEMU:
+//! the RAM filesystem, the process capability table, what host functions
+//! do with capabilities and the WASM runtime contract are the kernel's own
+//! code, from the `sovelma-core` crate (`fs`, `capabilities`, `calls`,
+//! `handles`, `manifest`, `runtime`). The network is smoltcp on an
+//! in-memory loopback device, or a TAP interface with the `tap` feature.
+//!
+//! `host` links the subset of host functions it lists to that code; a
+//! module importing any other traps when it calls it. Each host function
+//! is a method of `host::SimState` taking plain values, so tests can drive
+//! the syscall surface directly.
+//!
+//! ```text
+//! cd src/sim && cargo run -- --net listen=7 ../../target/apps/echo.wasm
+//! ```
+
+#![warn(missing_docs)]
+
+extern crate alloc;
+
+pub mod host;
+pub mod net;
+pub mod process;
+
+pub use host::SimState;
+pub use net::SimNet;
+pub use process::{SimEngine, SimProcess};
+pub use sovelma_core::fs;
+pub use sovelma_core::wasm::{calls, capabilities, handles, manifest, runtime};
@@ -0,0 +1,260 @@
+//! `sovelma-sim`: run a WASM module on the host.
+//!
+//! ```text
+//! sovelma-sim [--dir <[label@]path>] [--mount <path>=<dir>[:ro]]...
+//!             [--tmp <bytes>] [--net <[label@]rights>] [--tap <ifname>]
+//!             [--file <host path>[=<path>]]... <module.wasm>
+//! ```
+//!
+//! The options follow `wasm run`: the process holds a Timer capability,
+//! `--net` grants a Network capability (`connect`, `raw`,
+//! `listen=<first>-<last>`) and `--dir` read access to a directory of the
+//! RAM filesystem, or `--mount` read/write access to a namespace holding
+//! the directories mounted, granted last; `--tmp` adds a `/tmp` to the
+//! namespace whose files may take that many bytes. `--net` and `--dir`
+//! take a `label@` prefix as in `wasm run`. `--file` copies a host file
+//! into the RAM filesystem first, at the path after `=` or under its own
+//! name. The network is loopback unless `--tap` names a TAP interface
+//! (with the `tap` feature).
+
+use std::io::Write;
+use std::process::ExitCode;
+
+use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType, Label};
+use sovelma_sim::fs::{FileHandle, FileSystem, ROOT_FS};
+use sovelma_sim::manifest::Manifest;
+use sovelma_sim::{SimEngine, SimNet, SimState};
+
+/// Export run when the module has no manifest, as in the kernel.
+const WASM_ENTRY: &str = "_start";
+
+const USAGE: &str = "sovelma-sim [--dir <[label@]path>] [--mount <path>=<dir>[:ro]]... \
+                     [--tmp <bytes>] [--net <[label@]rights>] [--tap <ifname>] \
+                     [--file <host path>[=<path>]]... <module.wasm>";
+
+/// What the command line asks for.
+struct Options {
+    module: String,
+    dir: Option<String>,
+    dir_label: Option<Label>,
+    /// `--mount`s: path in the namespace, directory and whether read-only.
+    mounts: Vec<(String, String, bool)>,
+    /// `--tmp`: quota of the tmpfs at `/tmp`.
+    tmp: Option<usize>,
+    net: Option<Capability>,
+    tap: Option<String>,
+    files: Vec<(String, String)>,
+}
+
+/// Parse a `--net` grant, in `wasm run` syntax.
+fn parse_net_grant(spec: &str) -> Option<Capability> {
+    let mut rights = CapabilityRights::empty();
+    let (mut first_port, mut last_port) = (0, 0);
+    for item in spec.split(',') {
+        match item.split_once('=') {
+            None if item == "connect" => rights |= CapabilityRights::CONNECT,
+            None if item == "raw" => rights |= CapabilityRights::RAW,
+            Some(("listen", ports)) => {
+                let (first, last) = ports.split_once('-').unwrap_or((ports, ports));
+                first_port = first.parse().ok()?;
+                last_port = last.parse().ok()?;
+                if first_port > last_port {
+                    return None;
+                }
+                rights |= CapabilityRights::LISTEN;
+            }
+            _ => return None,
+        }
+    }
+    Some(Capability::new(
+        CapabilityType::Network {
+            first_port,
+            last_port,
+        },
+        rights,
+    ))
+}
+
+/// Split the label off a grant such as `web@listen=80`, as `wasm run`
+/// does.
+fn split_label(value: &str) -> Option<(Option<Label>, &str)> {
+    match value.split_once('@') {
+        Some((label, rest)) => Some((Some(Label::new(label)?), rest)),
+        None => Some((None, value)),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Option<Options> {
+    let mut options = Options {
+        module: String::new(),
+        dir: None,
+        dir_label: None,
+        mounts: Vec::new(),
+        tmp: None,
+        net: None,
+        tap: None,
+        files: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dir" => {
+                let spec = args.next()?;
+                let (label, path) = split_label(&spec)?;
+                options.dir = Some(path.to_string());
+                options.dir_label = label;
+            }
+            "--mount" => {
+                let spec = args.next()?;
+                let (path, dir) = spec.split_once('=')?;
+                let (dir, read_only) = match dir.strip_suffix(":ro") {
+                    Some(dir) => (dir, true),
+                    None => (dir, false),
+                };
+                options
+                    .mounts
+                    .push((path.to_string(), dir.to_string(), read_only));
+            }
+            "--tmp" => options.tmp = Some(args.next()?.parse().ok()?),
+            "--net" => {
+                let spec = args.next()?;
+                let (label, rights) = split_label(&spec)?;
+                options.net = Some(Capability {
+                    label,
+                    ..parse_net_grant(rights)?
+                });
+            }
+            "--tap" => options.tap = Some(args.next()?),
+            "--file" => {
+                let spec = args.next()?;
+                let (host, path) = match spec.split_once('=') {
+                    Some((host, path)) => (host.to_string(), path.to_string()),
+                    None => {
+                        let name = std::path::Path::new(&spec).file_name()?;
+                        (spec.clone(), name.to_string_lossy().into_owned())
+                    }
+                };
+                options.files.push((host, path));
+            }
+            _ if arg.starts_with("--") => return None,
+            _ => options.module = arg,
+        }
+    }
+    let namespace = !options.mounts.is_empty() || options.tmp.is_some();
+    let dir_and_mounts = options.dir.is_some() && namespace;
+    (!options.module.is_empty() && !dir_and_mounts).then_some(options)
+}
+
+/// Open `path` as a directory to grant.
+fn open_dir(path: &str) -> Result<FileHandle, String> {
+    let handle = ROOT_FS.open(path).map_err(|e| format!("{}: {}", path, e))?;
+    if !ROOT_FS.is_dir(handle) {
+        ROOT_FS.close(handle);
+        return Err(format!("{}: not a directory", path));
+    }
+    Ok(handle)
+}
+
+/// A namespace holding the `--mount`s and the `--tmp` tmpfs.
+fn open_namespace(
+    mounts: &[(String, String, bool)],
+    tmp: Option<usize>,
+) -> Result<FileHandle, String> {
+    let namespace = ROOT_FS.namespace();
+    if let Some(limit) = tmp {
+        // The namespace is empty, so this cannot fail
+        let _ = ROOT_FS.mount_tmpfs(namespace, "/tmp", limit);
+    }
+    for (path, dir, read_only) in mounts {
+        let mounted = open_dir(dir).and_then(|handle| {
+            let result = ROOT_FS.mount(namespace, path, handle, *read_only);
+            ROOT_FS.close(handle);
+            result.map_err(|e| format!("cannot mount {} at {}: {}", dir, path, e))
+        });
+        if let Err(e) = mounted {
+            ROOT_FS.close(namespace);
+            return Err(e);
+        }
+    }
+    Ok(namespace)
+}
+
+/// The network `--tap` asks for, or loopback.
+fn network(tap: Option<&str>) -> Result<SimNet, String> {
+    match tap {
+        None => Ok(SimNet::loopback()),
+        #[cfg(feature = "tap")]
+        Some(name) => SimNet::tap(name).map_err(|e| format!("{}: {}", name, e)),
+        #[cfg(not(feature = "tap"))]
+        Some(_) => Err(String::from("--tap needs the `tap` feature")),
+    }
+}
+
+fn run(options: Options) -> Result<(), String> {
+    for (host, path) in &options.files {
+        let content = std::fs::read(host).map_err(|e| format!("{}: {}", host, e))?;
+        ROOT_FS.add_file(path, &content);
+    }
+
+    let wasm = std::fs::read(&options.module).map_err(|e| format!("{}: {}", options.module, e))?;
+
+    let mut granted = vec![Capability::new(
+        CapabilityType::Timer,
+        CapabilityRights::READ | CapabilityRights::CALL,
+    )];
+    granted.extend(options.net);
+    if let Some(path) = &options.dir {
+        let handle = open_dir(path)?;
+        granted.push(Capability {
+            label: options.dir_label,
+            ..Capability::new(
+                CapabilityType::Directory(u64::from(handle.0)),
+                CapabilityRights::READ,
+            )
+        });
+    } else if !options.mounts.is_empty() || options.tmp.is_some() {
+        let handle = open_namespace(&options.mounts, options.tmp)?;
+        granted.push(Capability::new(
+            CapabilityType::Directory(u64::from(handle.0)),
+            CapabilityRights::READ | CapabilityRights::WRITE,
+        ));
+    }
+
+    let entry = match Manifest::from_module(&wasm).map_err(|e| format!("manifest: {}", e))? {
+        Some(manifest) => {
+            let missing = manifest.missing_capabilities(&granted);
+            if !missing.is_empty() {
+                return Err(format!(
+                    "{} requires capabilities not granted: {}",
+                    manifest.name,
+                    missing.join(", ")
+                ));
+            }
+            manifest.entry
+        }
+        None => String::from(WASM_ENTRY),
+    };
+
+    let mut state = SimState::with_capabilities(granted);
+    state.net = Some(network(options.tap.as_deref())?);
+    let mut process = SimEngine::new()
+        .instantiate_with(&wasm, state)
+        .map_err(|e| format!("{}: {}", options.module, e))?;
+    process
+        .run(&entry, &mut std::io::stdout())
+        .map_err(|e| format!("{}: {}", entry, e))
+}
+
+fn main() -> ExitCode {
+    let Some(options) = parse_args(std::env::args().skip(1)) else {
+        eprintln!("Usage: {}", USAGE);
+        return ExitCode::from(2);
+    };
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let _ = std::io::stdout().flush();
+            eprintln!("sovelma-sim: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! The simulated network.
+//!
+//! smoltcp, as in the kernel, on a device the host provides: an in-memory
+//! loopback, where the simulator is 127.0.0.1 and only talks to itself,
+//! or with the `tap` feature a TAP interface, where it has the address
+//! QEMU's user network gives the kernel. The simulator polls the stack
+//! between process slices.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
+use smoltcp::phy::{self, DeviceCapabilities, Loopback, Medium};
+use smoltcp::socket::tcp;
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetAddress, HardwareAddress, IpCidr, Ipv4Address};
+use sovelma_common::net::NetError;
+
+/// Bytes buffered per socket and direction.
+const TCP_BUFFER_SIZE: usize = 4096;
+
+/// First local port of outgoing connections.
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+/// MAC address of the interface, the one the QEMU run configuration gives
+/// the kernel's e1000.
+const MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+/// Most sockets open at once.
+pub const MAX_SOCKETS: usize = 32;
+
+/// Frames go to a loopback or TAP device.
+enum Phy {
+    Loopback(Loopback),
+    #[cfg(feature = "tap")]
+    Tap(phy::TunTapInterface),
+}
+
+/// A received frame, from either device.
+enum Rx<'a> {
+    Loopback(<Loopback as phy::Device>::RxToken<'a>),
+    #[cfg(feature = "tap")]
+    Tap(<phy::TunTapInterface as phy::Device>::RxToken<'a>),
+}
+
+/// Room for a frame to send, on either device.
+enum Tx<'a> {
+    Loopback(<Loopback as phy::Device>::TxToken<'a>),
+    #[cfg(feature = "tap")]
+    Tap(<phy::TunTapInterface as phy::Device>::TxToken<'a>),
+}
+
+impl phy::RxToken for Rx<'_> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        match self {
+            Rx::Loopback(token) => token.consume(f),
+            #[cfg(feature = "tap")]
+            Rx::Tap(token) => token.consume(f),
+        }
+    }
+}
+
+impl phy::TxToken for Tx<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        match self {
+            Tx::Loopback(token) => token.consume(len, f),
+            #[cfg(feature = "tap")]
+            Tx::Tap(token) => token.consume(len, f),
+        }
+    }
+}
+
+impl phy::Device for Phy {
+    type RxToken<'a> = Rx<'a>;
+    type TxToken<'a> = Tx<'a>;
+
+    fn receive(&mut self, timestamp: Instant) -> Option<(Rx<'_>, Tx<'_>)> {
+        match self {
+            Phy::Loopback(device) => device
+                .receive(timestamp)
+                .map(|(rx, tx)| (Rx::Loopback(rx), Tx::Loopback(tx))),
+            #[cfg(feature = "tap")]
+            Phy::Tap(device) => device
+                .receive(timestamp)
+                .map(|(rx, tx)| (Rx::Tap(rx), Tx::Tap(tx))),
+        }
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Tx<'_>> {
+        match self {
+            Phy::Loopback(device) => device.transmit(timestamp).map(Tx::Loopback),
+            #[cfg(feature = "tap")]
+            Phy::Tap(device) => device.transmit(timestamp).map(Tx::Tap),
+        }
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        match self {
+            Phy::Loopback(device) => device.capabilities(),
+            #[cfg(feature = "tap")]
+            Phy::Tap(device) => device.capabilities(),
+        }
+    }
+}
+
+/// A network interface and its TCP sockets.
+pub struct SimNet {
+    iface: Interface,
+    device: Phy,
+    sockets: SocketSet<'static>,
+    address: Ipv4Address,
+    started: std::time::Instant,
+    next_port: u16,
+    /// Closed by their owner, removed once the connection is over.
+    closing: Vec<SocketHandle>,
+}
+
+impl Default for SimNet {
+    fn default() -> Self {
+        Self::loopback()
+    }
+}
+
+impl SimNet {
+    /// A network on loopback, as 127.0.0.1.
+    pub fn loopback() -> Self {
+        Self::with_device(
+            Phy::Loopback(Loopback::new(Medium::Ethernet)),
+            Ipv4Address::new(127, 0, 0, 1),
+            8,
+            None,
+        )
+    }
+
+    /// A network on the TAP interface `name`, as 10.0.2.15/24 with the
+    /// gateway at 10.0.2.2.
+    #[cfg(feature = "tap")]
+    pub fn tap(name: &str) -> std::io::Result<Self> {
+        let device = phy::TunTapInterface::new(name, Medium::Ethernet)?;
+        Ok(Self::with_device(
+            Phy::Tap(device),
+            Ipv4Address::new(10, 0, 2, 15),
+            24,
+            Some(Ipv4Address::new(10, 0, 2, 2)),
+        ))
+    }
+
+    fn with_device(
+        mut device: Phy,
+        address: Ipv4Address,
+        prefix_len: u8,
+        gateway: Option<Ipv4Address>,
+    ) -> Self {
+        let config = Config::new(HardwareAddress::Ethernet(EthernetAddress(MAC)));
+        let mut iface = Interface::new(config, &mut device, Instant::from_millis(0));
+        iface.update_ip_addrs(|addrs| {
+            let _ = addrs.push(IpCidr::new(address.into(), prefix_len));
+        });
+        if let Some(gateway) = gateway {
+            let _ = iface.routes_mut().add_default_ipv4_route(gateway);
+        }
+        Self {
+            iface,
+            device,
+            sockets: SocketSet::new(vec![]),
+            address,
+            started: std::time::Instant::now(),
+            next_port: FIRST_EPHEMERAL_PORT,
+            closing: Vec::new(),
+        }
+    }
+
+    /// The interface's address.
+    pub fn address(&self) -> Ipv4Address {
+        self.address
+    }
+
+    fn now(&self) -> Instant {
+        Instant::from_millis(self.started.elapsed().as_millis() as i64)
+    }
+
+    /// Move frames between the device and the sockets, and drop closed
+    /// sockets whose connection has ended.
+    pub fn poll(&mut self) {
+        let now = self.now();
+        self.iface.poll(now, &mut self.device, &mut self.sockets);
+        let sockets = &mut self.sockets;
+        self.closing.retain(|&handle| {
+            let open = sockets.get::<tcp::Socket>(handle).is_open();
+            if !open {
+                sockets.remove(handle);
+            }
+            open
+        });
+    }
+
+    fn open(&mut self) -> Result<SocketHandle, NetError> {
+        if self.sockets.iter().count() >= MAX_SOCKETS {
+            return Err(NetError::OutOfMemory);
+        }
+        let socket = tcp::Socket::new(
+            tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+            tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+        );
+        Ok(self.sockets.add(socket))
+    }
+
+    /// Start connecting to `addr:port`; the connection completes as the
+    /// stack is polled.
+    pub fn connect(&mut self, addr: Ipv4Address, port: u16) -> Result<SocketHandle, NetError> {
+        let handle = self.open()?;
+        let local_port = self.next_port;
+        self.next_port = self
+            .next_port
+            .checked_add(1)
+            .unwrap_or(FIRST_EPHEMERAL_PORT);
+        let socket = self.sockets.get_mut::<tcp::Socket>(handle);
+        if socket
+            .connect(self.iface.context(), (addr, port), local_port)
+            .is_err()
+        {
+            self.sockets.remove(handle);
+            return Err(NetError::ConnectionRefused);
+        }
+        Ok(handle)
+    }
+
+    /// Listen on `port` for one connection.
+    pub fn listen(&mut self, port: u16) -> Result<SocketHandle, NetError> {
+        let handle = self.open()?;
+        if self
+            .sockets
+            .get_mut::<tcp::Socket>(handle)
+            .listen(port)
+            .is_err()
+        {
+            self.sockets.remove(handle);
+            return Err(NetError::InvalidAddress);
+        }
+        Ok(handle)
+    }
+
+    /// The socket `handle` names.
+    pub fn socket(&mut self, handle: SocketHandle) -> &mut tcp::Socket<'static> {
+        self.sockets.get_mut(handle)
+    }
+
+    /// Close the connection; queued data is still sent, and the socket is
+    /// dropped once the peer has closed too.
+    pub fn close(&mut self, handle: SocketHandle) {
+        self.sockets.get_mut::<tcp::Socket>(handle).close();
+        self.closing.push(handle);
+    }
+
+    /// Number of sockets, including closing ones.
+    pub fn socket_count(&self) -> usize {
+        self.sockets.iter().count()
+    }
+}
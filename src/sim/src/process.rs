@@ -0,0 +1,274 @@
+//! Simulated processes on the kernel's runtime contract.
+//!
+//! `SimEngine` and `SimProcess` implement `runtime::Engine` and
+//! `runtime::Process` with wasmi, as the kernel's `WasmEngine` does, and
+//! `SimProcess::run` drives an invocation with `runtime::poll_slice` the
+//! way a kernel task does. Between slices the network is polled and
+//! sleeping processes wait for their deadline.
+//!
+//! Fuel is not metered: without the kernel's host fuel checks a process
+//! that only calls host functions would run out mid-slice, so a slice
+//! lasts until the process yields, sleeps or returns.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::task::{Context, Poll, Waker};
+use sovelma_common::capability::Capability;
+use std::io::Write;
+use std::task::Wake;
+use wasmi::core::{Trap, TrapCode};
+use wasmi::{Linker, Module, ResumableCall, ResumableInvocation, Store, Value};
+
+use crate::host::{self, SimState, SimTrap};
+use crate::runtime::{self, Slice};
+
+/// How long `SimProcess::run` waits while the process sleeps.
+const IDLE_WAIT: std::time::Duration = std::time::Duration::from_millis(1);
+
+/// Compiles modules and links them against the simulated host functions.
+pub struct SimEngine {
+    engine: wasmi::Engine,
+}
+
+impl Default for SimEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimEngine {
+    /// An engine with wasmi's default configuration.
+    pub fn new() -> Self {
+        Self {
+            engine: wasmi::Engine::default(),
+        }
+    }
+
+    /// Instantiate `wasm_bytes` as a process with `state`, which holds its
+    /// capabilities and network.
+    pub fn instantiate_with(
+        &self,
+        wasm_bytes: &[u8],
+        state: SimState,
+    ) -> Result<SimProcess, wasmi::Error> {
+        let module = Module::new(&self.engine, wasm_bytes)?;
+        let mut store = Store::new(&self.engine, state);
+        let mut linker = <Linker<SimState>>::new(&self.engine);
+        host::register_functions(&mut linker, &module)?;
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+        Ok(SimProcess {
+            store,
+            instance,
+            results: Vec::new(),
+        })
+    }
+}
+
+impl runtime::Engine for SimEngine {
+    type Process = SimProcess;
+    type Error = wasmi::Error;
+
+    fn instantiate(
+        &self,
+        wasm_bytes: &[u8],
+        capabilities: Vec<Capability>,
+    ) -> Result<SimProcess, wasmi::Error> {
+        self.instantiate_with(wasm_bytes, SimState::with_capabilities(capabilities))
+    }
+}
+
+/// A module instance and its state.
+pub struct SimProcess {
+    store: Store<SimState>,
+    instance: wasmi::Instance,
+    /// Results of the running invocation's function.
+    results: Vec<Value>,
+}
+
+/// Convert a wasmi call outcome into a slice outcome. A call to a host
+/// function the simulator does not have ends the invocation.
+fn slice(call: ResumableCall) -> Result<Slice<ResumableInvocation>, wasmi::Error> {
+    match call {
+        ResumableCall::Finished => Ok(Slice::Finished),
+        ResumableCall::Resumable(invocation) => {
+            match invocation.host_error().downcast_ref::<SimTrap>() {
+                Some(trap @ SimTrap::Unsupported(_)) => Err(Trap::from(trap.clone()).into()),
+                _ => Ok(Slice::Suspended(invocation)),
+            }
+        }
+    }
+}
+
+/// Wakes nobody; `SimProcess::run` polls until the invocation is done.
+struct NoopWake;
+
+impl Wake for NoopWake {
+    fn wake(self: Arc<Self>) {}
+}
+
+impl SimProcess {
+    /// The process's state.
+    pub fn state(&self) -> &SimState {
+        self.store.data()
+    }
+
+    /// The process's state, to grant capabilities or inspect the network.
+    pub fn state_mut(&mut self) -> &mut SimState {
+        self.store.data_mut()
+    }
+
+    /// Run `entry` to the end, writing what the process prints to `out`
+    /// after every slice, then release what it holds.
+    pub fn run(&mut self, entry: &str, out: &mut dyn Write) -> Result<(), wasmi::Error> {
+        let waker = Waker::from(Arc::new(NoopWake));
+        let mut cx = Context::from_waker(&waker);
+        let mut suspended = None;
+        let result = loop {
+            let poll = runtime::poll_slice(self, entry, &mut suspended, &mut cx);
+            let output = core::mem::take(&mut self.state_mut().output);
+            // The console going away does not stop the process.
+            let _ = out.write_all(&output).and_then(|()| out.flush());
+            match poll {
+                Poll::Ready(result) => break result,
+                Poll::Pending => {
+                    let now = self.state().now_ms();
+                    if suspended
+                        .as_ref()
+                        .is_some_and(|invocation| self.is_blocked(invocation, now))
+                    {
+                        std::thread::sleep(IDLE_WAIT);
+                    }
+                }
+            }
+        };
+        self.state_mut().teardown();
+        result
+    }
+
+    /// Whether `invocation` is asleep at `now`.
+    fn is_blocked(&self, invocation: &ResumableInvocation, now: u64) -> bool {
+        match invocation.host_error().downcast_ref::<SimTrap>() {
+            Some(SimTrap::Sleep(deadline)) => now < *deadline,
+            _ => false,
+        }
+    }
+
+    /// The value the host function that suspended `invocation` returns.
+    fn resume_value(invocation: &ResumableInvocation) -> Option<Value> {
+        match invocation.host_error().downcast_ref::<SimTrap>()? {
+            SimTrap::Sleep(_) => Some(Value::I32(0)),
+            _ => None,
+        }
+    }
+}
+
+impl runtime::Process for SimProcess {
+    type Suspended = ResumableInvocation;
+    type Error = wasmi::Error;
+
+    /// Poll the network; a sleeping invocation waits for its deadline.
+    fn begin_slice(&mut self, suspended: Option<&ResumableInvocation>) -> bool {
+        if let Some(net) = &mut self.state_mut().net {
+            net.poll();
+        }
+        let now = self.state().now_ms();
+        !suspended.is_some_and(|invocation| self.is_blocked(invocation, now))
+    }
+
+    fn start(&mut self, entry: &str) -> Result<Slice<ResumableInvocation>, wasmi::Error> {
+        let func = self
+            .instance
+            .get_func(&self.store, entry)
+            .ok_or_else(|| wasmi::Error::from(Trap::from(TrapCode::UnreachableCodeReached)))?;
+        self.results = func
+            .ty(&self.store)
+            .results()
+            .iter()
+            .map(|&ty| Value::default(ty))
+            .collect();
+        func.call_resumable(&mut self.store, &[], &mut self.results)
+            .and_then(slice)
+    }
+
+    fn resume(
+        &mut self,
+        invocation: ResumableInvocation,
+    ) -> Result<Slice<ResumableInvocation>, wasmi::Error> {
+        let value = Self::resume_value(&invocation);
+        let inputs = match &value {
+            Some(value) => core::slice::from_ref(value),
+            None => &[],
+        };
+        invocation
+            .resume(&mut self.store, inputs, &mut self.results)
+            .and_then(slice)
+    }
+
+    fn fuel_consumed(&self) -> u64 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A module whose `_start` calls the `(i32, i32) -> i32` host function
+    /// `import` with the 3 bytes "hi\n" at address 0.
+    fn module(import: &str) -> Vec<u8> {
+        fn section(id: u8, content: &[u8]) -> Vec<u8> {
+            let mut section = alloc::vec![id, content.len() as u8];
+            section.extend_from_slice(content);
+            section
+        }
+        let mut imports = alloc::vec![1, 3];
+        imports.extend_from_slice(b"env");
+        imports.push(import.len() as u8);
+        imports.extend_from_slice(import.as_bytes());
+        imports.extend_from_slice(&[0, 0]);
+
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        // Types (i32, i32) -> i32 and () -> ()
+        wasm.extend(section(1, &[2, 0x60, 2, 0x7f, 0x7f, 1, 0x7f, 0x60, 0, 0]));
+        wasm.extend(section(2, &imports));
+        wasm.extend(section(3, &[1, 1]));
+        wasm.extend(section(5, &[1, 0, 1]));
+        let mut exports = alloc::vec![2, 6];
+        exports.extend_from_slice(b"memory");
+        exports.extend_from_slice(&[2, 0, 6]);
+        exports.extend_from_slice(b"_start");
+        exports.extend_from_slice(&[0, 1]);
+        wasm.extend(section(7, &exports));
+        // i32.const 0, i32.const 3, call 0, drop
+        let body = [0, 0x41, 0, 0x41, 3, 0x10, 0, 0x1a, 0x0b];
+        let mut code = alloc::vec![1, body.len() as u8];
+        code.extend_from_slice(&body);
+        wasm.extend(section(10, &code));
+        wasm.extend(section(11, &[1, 0, 0x41, 0, 0x0b, 3, b'h', b'i', b'\n']));
+        wasm
+    }
+
+    #[test]
+    fn runs_a_module_to_the_end() {
+        let engine = SimEngine::new();
+        let Ok(mut process) = engine.instantiate_with(&module("sp_stdout_write"), SimState::new())
+        else {
+            panic!("module did not instantiate");
+        };
+        let mut out = Vec::new();
+        assert!(process.run("_start", &mut out).is_ok());
+        assert_eq!(out, b"hi\n");
+    }
+
+    #[test]
+    fn unsimulated_imports_trap_when_called() {
+        let engine = SimEngine::new();
+        let Ok(mut process) = engine.instantiate_with(&module("sp_spawn"), SimState::new()) else {
+            panic!("module did not instantiate");
+        };
+        let Err(e) = process.run("_start", &mut Vec::new()) else {
+            panic!("sp_spawn did not trap");
+        };
+        assert!(e.to_string().contains("sp_spawn is not simulated"));
+    }
+}
@@ -0,0 +1,209 @@
+//! Properties of capability derivation and lookup, on random inputs.
+//!
+//! They run against the kernel's own code from `sovelma-core`: the
+//! per-process `ProcessCapabilities` and the host calls in `calls`.
+//!
+//! - Opening or creating below a directory never yields rights the
+//!   directory lacks, nor more than a restricted open asks for.
+//! - A capability whose generation moved past its `CapId` never resolves.
+//! - A revoked capability never resolves, and its handle is not reused
+//!   for anything but a new grant.
+//! - A handle names a capability of the process that holds it only.
+
+use proptest::prelude::*;
+use sovelma_common::capability::{Capability, CapabilityRights, CapabilityType};
+use sovelma_common::host::error;
+use sovelma_core::fs::{FileSystem, ROOT_FS};
+use sovelma_core::wasm::calls;
+use sovelma_core::wasm::capabilities::ProcessCapabilities;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Depth of the directory chain in `fresh_dir`.
+const CHAIN_DEPTH: usize = 5;
+
+/// A new directory in the RAM filesystem holding `file` and a chain of
+/// `CHAIN_DEPTH` directories `a/a/...`, each also holding `file`.
+fn fresh_dir() -> String {
+    static NEXT: AtomicU32 = AtomicU32::new(0);
+    let path = format!("proptest/{}", NEXT.fetch_add(1, Ordering::Relaxed));
+    let mut dir = path.clone();
+    for _ in 0..=CHAIN_DEPTH {
+        ROOT_FS.add_file(&format!("{}/file", dir), b"data");
+        dir.push_str("/a");
+    }
+    path
+}
+
+/// Capabilities of a process holding `rights` on a fresh directory, and
+/// its handle.
+fn process_with_dir(rights: CapabilityRights) -> (ProcessCapabilities, i64) {
+    let path = fresh_dir();
+    let Ok(dir) = ROOT_FS.open(&path) else {
+        panic!("{} was not created", path);
+    };
+    let mut caps = ProcessCapabilities::new();
+    let cap = Capability::new(CapabilityType::Directory(u64::from(dir.0)), rights);
+    let handle = caps.grant(cap).as_raw();
+    (caps, handle)
+}
+
+/// Capabilities of a process holding `caps`, at handles 1, 2, ...
+fn process_with(caps: Vec<Capability>) -> ProcessCapabilities {
+    let mut held = ProcessCapabilities::new();
+    for cap in caps {
+        held.grant(cap);
+    }
+    held
+}
+
+fn rights() -> impl Strategy<Value = CapabilityRights> {
+    any::<u32>().prop_map(CapabilityRights::from_bits_truncate)
+}
+
+fn capability() -> impl Strategy<Value = Capability> {
+    let object = prop_oneof![
+        Just(CapabilityType::Timer),
+        Just(CapabilityType::Config),
+        any::<u64>().prop_map(CapabilityType::Mutex),
+        any::<u64>().prop_map(CapabilityType::Semaphore),
+        any::<u64>().prop_map(CapabilityType::Process),
+        any::<u16>().prop_map(|port| CapabilityType::Serial { port }),
+        (any::<u16>(), any::<u16>()).prop_map(|(first_port, last_port)| {
+            CapabilityType::Network {
+                first_port,
+                last_port,
+            }
+        }),
+    ];
+    (object, rights()).prop_map(|(object, rights)| Capability::new(object, rights))
+}
+
+proptest! {
+    #[test]
+    fn opened_rights_stay_within_the_directory(
+        parent in rights(),
+        path in prop_oneof![Just("file"), Just("a"), Just("a/a/file")],
+    ) {
+        let (mut caps, dir) = process_with_dir(parent);
+        let opened = calls::fs_open(&mut caps, dir, path);
+        if !parent.contains(CapabilityRights::READ) {
+            prop_assert_eq!(opened, error::PERMISSION_DENIED);
+            prop_assert_eq!(caps.held().len(), 1);
+            return Ok(());
+        }
+        let Some(cap) = caps.capability(opened).cloned() else {
+            return Err(TestCaseError::fail(format!("{} opened as {}", path, opened)));
+        };
+        prop_assert!(parent.contains(cap.rights));
+        if matches!(cap.object, CapabilityType::File(_)) {
+            prop_assert!((CapabilityRights::READ | CapabilityRights::WRITE).contains(cap.rights));
+        }
+    }
+
+    #[test]
+    fn rights_never_grow_down_a_chain_of_opens(parent in rights()) {
+        let parent = parent | CapabilityRights::READ;
+        let (mut caps, mut dir) = process_with_dir(parent);
+        let mut rights = parent;
+        for _ in 0..CHAIN_DEPTH {
+            let opened = calls::fs_open(&mut caps, dir, "a");
+            let Some(cap) = caps.capability(opened) else {
+                return Err(TestCaseError::fail(format!("a opened as {}", opened)));
+            };
+            prop_assert!(rights.contains(cap.rights));
+            rights = cap.rights;
+            dir = opened;
+        }
+    }
+
+    #[test]
+    fn restricted_and_created_rights_stay_within_both(
+        parent in rights(),
+        requested in rights(),
+    ) {
+        let parent = parent | CapabilityRights::READ;
+        let (mut caps, dir) = process_with_dir(parent);
+        match calls::fs_opendir_restricted(&mut caps, dir, "a", requested.bits()) {
+            Ok(handle) => {
+                let Some(cap) = caps.capability(handle.as_raw()) else {
+                    return Err(TestCaseError::fail("restricted open did not resolve"));
+                };
+                prop_assert!(parent.contains(cap.rights));
+                prop_assert!(requested.contains(cap.rights));
+            }
+            Err(code) => prop_assert_eq!(code, error::INVALID_ARGUMENT),
+        }
+
+        let created = calls::fs_create(&mut caps, dir, "new");
+        if !parent.contains(CapabilityRights::WRITE) {
+            prop_assert_eq!(created, error::PERMISSION_DENIED);
+            return Ok(());
+        }
+        let Some(cap) = caps.capability(created) else {
+            return Err(TestCaseError::fail(format!("new created as {}", created)));
+        };
+        prop_assert!(parent.contains(cap.rights));
+        prop_assert!((CapabilityRights::READ | CapabilityRights::WRITE).contains(cap.rights));
+    }
+
+    #[test]
+    fn stale_generations_never_resolve(cap in capability(), generation in 1u32..) {
+        let mut cap = cap;
+        cap.generation = u64::from(generation);
+        let mut caps = ProcessCapabilities::new();
+        let handle = caps.grant(cap).as_raw();
+        prop_assert!(caps.capability(handle).is_none());
+        prop_assert_eq!(calls::fs_size(&caps, handle), error::CAP_NOT_FOUND as i32);
+    }
+
+    #[test]
+    fn revoked_capabilities_never_resolve(
+        caps in prop::collection::vec(capability(), 1..16),
+        revoked in prop::collection::vec(any::<bool>(), 16),
+        later in prop::collection::vec(capability(), 0..8),
+    ) {
+        let mut held = ProcessCapabilities::new();
+        let granted: Vec<_> = caps
+            .into_iter()
+            .map(|cap| (held.grant(cap.clone()).as_raw(), cap))
+            .collect();
+        for ((_, cap), &revoke) in granted.iter().zip(&revoked) {
+            if revoke {
+                held.revoke(cap.id);
+            }
+        }
+        let later_ids: Vec<_> = later
+            .into_iter()
+            .map(|cap| {
+                held.grant(cap.clone());
+                cap.id
+            })
+            .collect();
+
+        for ((handle, cap), &revoke) in granted.iter().zip(&revoked) {
+            match held.capability(*handle) {
+                Some(found) if revoke => prop_assert!(later_ids.contains(&found.id)),
+                Some(found) => prop_assert_eq!(found, cap),
+                None => prop_assert!(revoke),
+            }
+        }
+    }
+
+    #[test]
+    fn handles_never_alias_across_processes(
+        a in prop::collection::vec(capability(), 0..12),
+        b in prop::collection::vec(capability(), 0..12),
+    ) {
+        let a = process_with(a);
+        let b = process_with(b);
+        let a_ids: Vec<_> = a.held().into_iter().map(|(_, cap)| cap.id).collect();
+        for (handle, _) in a.held() {
+            if let Some(cap) = b.capability(handle.as_raw()) {
+                prop_assert!(!a_ids.contains(&cap.id));
+            }
+        }
+        for (handle, cap) in b.held() {
+            prop_assert_eq!(b.capability(handle.as_raw()), Some(&cap));
+        }
+    }
+}
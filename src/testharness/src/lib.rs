@@ -0,0 +1,413 @@
+//! Host-side harness for the kernel's QEMU tests.
+//!
+//! The kernel frames each test on serial with a `TEST_BEGIN <name>` line and
+//! a `TEST_END <name> ok|FAILED` line (see the kernel's `testutil`). This
+//! crate cuts a captured serial log into those sections and compares each
+//! with a golden file, so the shell's output (`help`, `ifconfig`, ...) is
+//! checked end to end.
+//!
+//! A golden file holds the expected output line for line. `{...}` in a
+//! golden line matches any text within that line, for values that change
+//! between runs such as addresses and timings.
+//!
+//! QEMU's exit status says what failed when a run panicked: a test by its
+//! number in the run, or the kernel module a panic outside any test
+//! happened in. `decode_status` turns it back into an `ExitReason`.
+
+use std::fmt;
+
+/// First word of the line that starts a test.
+pub const TEST_BEGIN: &str = "TEST_BEGIN";
+
+/// First word of the line that ends a test.
+pub const TEST_END: &str = "TEST_END";
+
+/// Matches any text within a golden line.
+pub const WILDCARD: &str = "{...}";
+
+/// Exit code base for a panic outside any test (kernel
+/// `testutil::SUBSYSTEM_CODE_BASE`).
+pub const SUBSYSTEM_CODE_BASE: u32 = 0x20;
+
+/// Exit code base for a failed test (kernel `testutil::TEST_CODE_BASE`).
+pub const TEST_CODE_BASE: u32 = 0x40;
+
+/// Number of test exit codes; the last stands for every later test too.
+pub const TEST_CODES: u32 = 0x40;
+
+/// Kernel modules by subsystem exit code; must match the kernel's
+/// `testutil::SUBSYSTEMS`.
+pub const SUBSYSTEMS: [&str; 22] = [
+    "allocator",
+    "arch",
+    "bench",
+    "boot",
+    "capability",
+    "config",
+    "ctl",
+    "fs",
+    "klog",
+    "ksym",
+    "memory",
+    "net",
+    "replay",
+    "services",
+    "sync",
+    "task",
+    "terminal",
+    "tests",
+    "testutil",
+    "time",
+    "trace",
+    "wasm",
+];
+
+/// What QEMU's exit status says about a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// All tests passed.
+    Success,
+    /// A test failed, without saying which.
+    Failed,
+    /// The `n`th test of the run (from 0) failed; the last code also
+    /// stands for every later test.
+    Test(usize),
+    /// The kernel panicked outside any test, in this module.
+    Subsystem(&'static str),
+    /// The status was not set through the exit device (QEMU was killed,
+    /// or the kernel never got to exit).
+    Unknown(i32),
+}
+
+/// Decode QEMU's exit status. The exit device makes it
+/// `(code << 1) | 1`.
+pub fn decode_status(status: i32) -> ExitReason {
+    if status & 1 == 0 {
+        return ExitReason::Unknown(status);
+    }
+    let Ok(code) = u32::try_from(status >> 1) else {
+        return ExitReason::Unknown(status);
+    };
+    match code {
+        0x10 => ExitReason::Success,
+        0x11 => ExitReason::Failed,
+        _ if (SUBSYSTEM_CODE_BASE..TEST_CODE_BASE).contains(&code) => {
+            match SUBSYSTEMS.get((code - SUBSYSTEM_CODE_BASE) as usize) {
+                Some(name) => ExitReason::Subsystem(name),
+                None => ExitReason::Unknown(status),
+            }
+        }
+        _ if (TEST_CODE_BASE..TEST_CODE_BASE + TEST_CODES).contains(&code) => {
+            ExitReason::Test((code - TEST_CODE_BASE) as usize)
+        }
+        _ => ExitReason::Unknown(status),
+    }
+}
+
+/// How a test ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The kernel reported it passed.
+    Passed,
+    /// The kernel reported it failed.
+    Failed,
+    /// The log ended (or the next test began) before it did.
+    Unfinished,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "ok"),
+            Outcome::Failed => write!(f, "FAILED"),
+            Outcome::Unfinished => write!(f, "did not finish"),
+        }
+    }
+}
+
+/// One test's part of the serial log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// Test name from the markers.
+    pub name: String,
+    /// How it ended.
+    pub outcome: Outcome,
+    /// Output between the markers, normalized (see `normalize`).
+    pub output: String,
+}
+
+/// Cut a serial log into its tests' sections, in order.
+///
+/// Text outside the markers (boot messages, the kernel's own checks) is
+/// ignored.
+pub fn parse_sections(log: &str) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut current: Option<(String, Vec<&str>)> = None;
+
+    for line in log.lines() {
+        let line = line.trim_end_matches('\r');
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some(TEST_BEGIN) => {
+                if let Some((name, lines)) = current.take() {
+                    sections.push(section(name, Outcome::Unfinished, &lines));
+                }
+                let name = words.next().unwrap_or_default().to_string();
+                current = Some((name, Vec::new()));
+            }
+            Some(TEST_END) => {
+                let Some((name, lines)) = current.take() else {
+                    continue;
+                };
+                let outcome = match words.nth(1) {
+                    Some("ok") => Outcome::Passed,
+                    _ => Outcome::Failed,
+                };
+                sections.push(section(name, outcome, &lines));
+            }
+            _ => {
+                if let Some((_, lines)) = current.as_mut() {
+                    lines.push(line);
+                }
+            }
+        }
+    }
+    if let Some((name, lines)) = current {
+        sections.push(section(name, Outcome::Unfinished, &lines));
+    }
+    sections
+}
+
+/// A section from its raw lines.
+fn section(name: String, outcome: Outcome, lines: &[&str]) -> Section {
+    let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    Section {
+        name,
+        outcome,
+        output: normalize(&text),
+    }
+}
+
+/// Normalize captured output for comparison: strip carriage returns, ANSI
+/// escape sequences and trailing whitespace, and drop kernel log records
+/// (`[    1.234567] [INFO  net] ...`), which other tasks may print at any
+/// time. Every line, including the last, ends with a newline.
+pub fn normalize(text: &str) -> String {
+    let text = strip_ansi(text);
+    let mut out = String::new();
+    for line in text.lines() {
+        if is_log_record(line) {
+            continue;
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// `text` without ANSI escape sequences (`ESC [ ... final`).
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        if chars.next() == Some('[') {
+            // Parameters and intermediates, up to the final byte
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Whether `line` is a kernel log record: an uptime in brackets, then the
+/// level and target in brackets.
+fn is_log_record(line: &str) -> bool {
+    let Some(rest) = line.strip_prefix('[') else {
+        return false;
+    };
+    let Some((uptime, rest)) = rest.split_once(']') else {
+        return false;
+    };
+    let Some((seconds, micros)) = uptime.trim_start().split_once('.') else {
+        return false;
+    };
+    !seconds.is_empty()
+        && seconds.bytes().all(|b| b.is_ascii_digit())
+        && !micros.is_empty()
+        && micros.bytes().all(|b| b.is_ascii_digit())
+        && rest.starts_with(" [")
+}
+
+/// Where output first differs from a golden file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Line number, from 1.
+    pub line: usize,
+    /// The golden line, or `None` past the golden file's end.
+    pub expected: Option<String>,
+    /// The output line, or `None` past the output's end.
+    pub actual: Option<String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |line: &Option<String>| match line {
+            Some(line) => format!("{:?}", line),
+            None => String::from("end of output"),
+        };
+        write!(
+            f,
+            "line {}: expected {}, got {}",
+            self.line,
+            show(&self.expected),
+            show(&self.actual)
+        )
+    }
+}
+
+/// Compare normalized `output` with `golden`, line for line.
+pub fn compare(golden: &str, output: &str) -> Result<(), Mismatch> {
+    let golden = normalize(golden);
+    let mut expected = golden.lines();
+    let mut actual = output.lines();
+    let mut line = 0;
+    loop {
+        line += 1;
+        match (expected.next(), actual.next()) {
+            (None, None) => return Ok(()),
+            (Some(pattern), Some(text)) if line_matches(pattern, text) => {}
+            (pattern, text) => {
+                return Err(Mismatch {
+                    line,
+                    expected: pattern.map(String::from),
+                    actual: text.map(String::from),
+                })
+            }
+        }
+    }
+}
+
+/// Whether `text` matches the golden line `pattern`, where each `{...}`
+/// matches any text.
+pub fn line_matches(pattern: &str, text: &str) -> bool {
+    let Some((literal, rest)) = pattern.split_once(WILDCARD) else {
+        return pattern == text;
+    };
+    let Some(text) = text.strip_prefix(literal) else {
+        return false;
+    };
+    // Try every length for the wildcard, shortest first
+    text.char_indices()
+        .map(|(i, _)| i)
+        .chain([text.len()])
+        .any(|i| line_matches(rest, &text[i..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sections() {
+        let log = "booting\r\n\
+                   TEST_BEGIN shell.echo\r\n\
+                   hello world\r\n\
+                   TEST_END shell.echo ok\r\n\
+                   noise\n\
+                   TEST_BEGIN shell.help\n\
+                   \x1b[36mSovelmaOS\x1b[0m   \n\
+                   [    2.000100] [INFO  dhcp] Bound\n\
+                   TEST_END shell.help FAILED\n\
+                   TEST_BEGIN shell.ifconfig\n\
+                   MAC\n";
+        let sections = parse_sections(log);
+        assert_eq!(
+            sections,
+            vec![
+                Section {
+                    name: "shell.echo".into(),
+                    outcome: Outcome::Passed,
+                    output: "hello world\n".into(),
+                },
+                Section {
+                    name: "shell.help".into(),
+                    outcome: Outcome::Failed,
+                    output: "SovelmaOS\n".into(),
+                },
+                Section {
+                    name: "shell.ifconfig".into(),
+                    outcome: Outcome::Unfinished,
+                    output: "MAC\n".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_log_records() {
+        assert!(is_log_record("[    0.002000] [INFO  dhcp] Bound"));
+        assert!(is_log_record("[12345.000001] [WARN  net] Lost"));
+        assert!(!is_log_record("[ok] done"));
+        assert!(!is_log_record("[1.5]"));
+        assert!(!is_log_record("  IP:      10.0.2.15"));
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(line_matches("  IP:      {...}", "  IP:      10.0.2.15"));
+        assert!(line_matches("  IP:      {...}", "  IP:      "));
+        assert!(!line_matches("  IP:      {...}", "  MAC:     10.0.2.15"));
+        assert!(line_matches(
+            r#"{"ip":"{...}","dns":[{...}]}"#,
+            r#"{"ip":"10.0.2.15","dns":["10.0.2.3"]}"#
+        ));
+        assert!(line_matches(r#"{"dns":[{...}]}"#, r#"{"dns":[]}"#));
+        // A wildcard stops at the end of its line
+        assert!(!line_matches("a{...}b", "a"));
+        assert!(line_matches("a{...}b{...}b", "axbyb"));
+        assert!(line_matches("plain", "plain"));
+    }
+
+    #[test]
+    fn test_decode_status() {
+        assert_eq!(decode_status(33), ExitReason::Success);
+        assert_eq!(decode_status(35), ExitReason::Failed);
+        // 0x20 + 11, and 0x40 + 3
+        assert_eq!(decode_status(0x2b << 1 | 1), ExitReason::Subsystem("net"));
+        assert_eq!(decode_status(0x43 << 1 | 1), ExitReason::Test(3));
+        assert_eq!(decode_status(0x7f << 1 | 1), ExitReason::Test(63));
+        // Past the last subsystem, and not from the device at all
+        assert_eq!(decode_status(0x3f << 1 | 1), ExitReason::Unknown(0x7f));
+        assert_eq!(decode_status(0), ExitReason::Unknown(0));
+        assert_eq!(decode_status(-1), ExitReason::Unknown(-1));
+    }
+
+    #[test]
+    fn test_compare() {
+        let output = normalize("Network\n  Link:    up\n\n");
+        assert_eq!(compare("Network\n  Link:    {...}\n\n", &output), Ok(()));
+        assert_eq!(
+            compare("Network\n  Link:    {...}\n", &output),
+            Err(Mismatch {
+                line: 3,
+                expected: None,
+                actual: Some(String::new()),
+            })
+        );
+        assert_eq!(
+            compare("Network\n  Link:    down\n\n", &output),
+            Err(Mismatch {
+                line: 2,
+                expected: Some("  Link:    down".into()),
+                actual: Some("  Link:    up".into()),
+            })
+        );
+    }
+}
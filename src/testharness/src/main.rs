@@ -0,0 +1,145 @@
+//! Check a serial log from a QEMU test run against the golden files.
+//!
+//! ```text
+//! sovelma-testharness <serial.log> [--golden <dir>] [--update] [--status <n>]
+//! ```
+//!
+//! Every test in the log must have passed, and the output of every test
+//! with a golden file (`<dir>/<name>.txt`) must match it. `--status` gives
+//! QEMU's exit status, which must say the run succeeded; if it does not,
+//! the harness reports the failed test or kernel module it names. `--update`
+//! rewrites the golden files from the log instead; wildcards are lost, so
+//! put them back before committing. Exits with status 1 if anything
+//! failed.
+
+use sovelma_testharness::{compare, decode_status, parse_sections, ExitReason, Outcome, Section};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Golden files shipped with the harness.
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+
+/// Command-line usage.
+const USAGE: &str =
+    "usage: sovelma-testharness <serial.log> [--golden <dir>] [--update] [--status <n>]";
+
+/// Parsed command line.
+struct Args {
+    log: PathBuf,
+    golden: PathBuf,
+    update: bool,
+    status: Option<i32>,
+}
+
+impl Args {
+    /// Parse the arguments after the program name.
+    fn parse(mut args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut log = None;
+        let mut golden = PathBuf::from(GOLDEN_DIR);
+        let mut update = false;
+        let mut status = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--golden" => golden = PathBuf::from(args.next()?),
+                "--update" => update = true,
+                "--status" => status = Some(args.next()?.parse().ok()?),
+                _ if arg.starts_with("--") || log.is_some() => return None,
+                _ => log = Some(PathBuf::from(arg)),
+            }
+        }
+        Some(Self {
+            log: log?,
+            golden,
+            update,
+            status,
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    let Some(args) = Args::parse(std::env::args().skip(1)) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+    let log = match std::fs::read(&args.log) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            eprintln!("{}: {}", args.log.display(), e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let sections = parse_sections(&log);
+    if sections.is_empty() {
+        eprintln!("{}: no tests found", args.log.display());
+        if let Some(status) = args.status {
+            eprintln!("qemu: {}", describe(decode_status(status), &sections));
+        }
+        return ExitCode::FAILURE;
+    }
+
+    let (mut passed, mut failed) = (0, 0);
+    for section in &sections {
+        let golden = args.golden.join(format!("{}.txt", section.name));
+        let result = if section.outcome != Outcome::Passed {
+            Err(section.outcome.to_string())
+        } else if args.update {
+            std::fs::write(&golden, &section.output)
+                .map_err(|e| format!("{}: {}", golden.display(), e))
+        } else {
+            match std::fs::read_to_string(&golden) {
+                Ok(expected) => compare(&expected, &section.output)
+                    .map_err(|mismatch| format!("{}: {}", golden.display(), mismatch)),
+                // Nothing to compare; the kernel's own check passed
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(format!("{}: {}", golden.display(), e)),
+            }
+        };
+        match result {
+            Ok(()) => {
+                println!("test {} ... ok", section.name);
+                passed += 1;
+            }
+            Err(reason) => {
+                println!("test {} ... FAILED ({})", section.name, reason);
+                failed += 1;
+            }
+        }
+    }
+
+    if let Some(status) = args.status {
+        let reason = decode_status(status);
+        if reason != ExitReason::Success {
+            println!("qemu ... FAILED ({})", describe(reason, &sections));
+            failed += 1;
+        }
+    }
+
+    println!();
+    println!(
+        "test result: {}. {} passed; {} failed",
+        if failed == 0 { "ok" } else { "FAILED" },
+        passed,
+        failed
+    );
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// What a non-success exit status says failed, naming the test from the
+/// log where it can.
+fn describe(reason: ExitReason, sections: &[Section]) -> String {
+    match reason {
+        ExitReason::Success => String::from("success"),
+        ExitReason::Failed => String::from("a test failed"),
+        ExitReason::Test(n) => match sections.get(n) {
+            Some(section) => format!("test {} failed", section.name),
+            None => format!("test #{} failed", n),
+        },
+        ExitReason::Subsystem(module) => format!("kernel panic in {}", module),
+        ExitReason::Unknown(status) => format!("exit status {}", status),
+    }
+}
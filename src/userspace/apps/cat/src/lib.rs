@@ -0,0 +1,93 @@
+//! `cat` for SovelmaOS: prints files, or copies its input.
+//!
+//! Reads lines from stdin. Started with a directory capability, each line
+//! names a file in that directory whose contents are written to stdout;
+//! without one, the lines are copied to stdout as they are:
+//!
+//! ```text
+//! wasm run apps/ping.wasm | wasm run --dir /etc apps/cat.wasm
+//! wasm run apps/ping.wasm | wasm run apps/cat.wasm
+//! ```
+
+#![no_std]
+
+use core::fmt::Write;
+use sovelma_sdk::{capabilities, close, open, read, stdin_read, stdout_write, Stdout};
+
+sovelma_sdk::manifest!(b"name = cat\nversion = 0.1.0\ncapabilities = timer\n");
+
+/// Longest input line; longer lines are cut here.
+const LINE_MAX: usize = 128;
+
+/// Bytes read from a file per call.
+const CHUNK: usize = 512;
+
+/// Entry point: handle every line of stdin.
+#[no_mangle]
+pub extern "C" fn _start() {
+    let dir = dir_handle();
+    let mut input = [0u8; CHUNK];
+    let mut line = [0u8; LINE_MAX];
+    let mut len = 0;
+    while let Ok(count) = stdin_read(&mut input) {
+        if count == 0 {
+            break;
+        }
+        for &byte in &input[..count] {
+            if byte == b'\n' {
+                handle_line(dir, &line[..len]);
+                len = 0;
+            } else if len < LINE_MAX {
+                line[len] = byte;
+                len += 1;
+            }
+        }
+    }
+    if len > 0 {
+        handle_line(dir, &line[..len]);
+    }
+}
+
+/// Handle of the directory granted with `wasm run --dir`, if any.
+fn dir_handle() -> Option<i64> {
+    let mut buf = [0u8; 256];
+    capabilities(&mut buf)
+        .ok()?
+        .find(|cap| cap.kind == "directory")
+        .map(|cap| cap.handle)
+}
+
+/// Print the file `line` names in `dir`, or the line itself without one.
+fn handle_line(dir: Option<i64>, line: &[u8]) {
+    let Some(dir) = dir else {
+        let _ = stdout_write(line);
+        let _ = stdout_write(b"\n");
+        return;
+    };
+    let Ok(name) = core::str::from_utf8(line) else {
+        let _ = writeln!(Stdout, "cat: name is not UTF-8");
+        return;
+    };
+    let file = open(dir, name.trim());
+    if file < 0 {
+        let _ = writeln!(Stdout, "cat: {}: error {}", name.trim(), file);
+        return;
+    }
+
+    let mut buf = [0u8; CHUNK];
+    let mut offset = 0;
+    loop {
+        let count = read(file, &mut buf, offset);
+        if count <= 0 {
+            break;
+        }
+        let _ = stdout_write(&buf[..count as usize]);
+        offset += count as usize;
+    }
+    close(file);
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
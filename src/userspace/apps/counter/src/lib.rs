@@ -0,0 +1,77 @@
+//! Counter: counts timer ticks under a kernel mutex.
+//!
+//! Arms a periodic timer and, on every tick, takes a mutex, adds the
+//! tick's expirations to the count and releases it. While holding the
+//! mutex it also probes it with `mutex_try_lock`, which must fail: the
+//! path another process contending for the lock would take. Prints the
+//! count as it goes and a summary at the end.
+
+#![no_std]
+
+use core::fmt::Write;
+use sovelma_sdk::{
+    event_kind, mutex_create, mutex_lock, mutex_try_lock, mutex_unlock, poll, timer_arm,
+    timer_create, Event, MutexHandle, Stdout,
+};
+
+sovelma_sdk::manifest!(b"name = counter\nversion = 0.1.0\ncapabilities = timer\n");
+
+/// Timer period.
+const PERIOD_MS: u64 = 100;
+
+/// Ticks counted before exiting.
+const TICKS: u64 = 10;
+
+/// Entry point: count, then report.
+#[no_mangle]
+pub extern "C" fn _start() {
+    if let Err(code) = run() {
+        let _ = writeln!(Stdout, "counter: error {}", code);
+    }
+}
+
+/// Count `TICKS` ticks.
+fn run() -> Result<(), i32> {
+    let mutex = mutex_create()?;
+    let timer = timer_create()?;
+    timer_arm(timer, PERIOD_MS, PERIOD_MS)?;
+
+    let mut count = 0;
+    let mut probes = 0;
+    let mut refused = 0;
+    let mut events = [Event::default(); 4];
+    while count < TICKS {
+        let ready = poll(&mut events, None)?;
+        for event in &events[..ready] {
+            if event.kind != event_kind::TIMER || event.object != timer.0 as u64 {
+                continue;
+            }
+            probes += 1;
+            if tick(mutex, &mut count, event.data)? {
+                refused += 1;
+            }
+            let _ = writeln!(Stdout, "counter: {}", count);
+        }
+    }
+    let _ = writeln!(
+        Stdout,
+        "counter: done, {} ticks, {} of {} try_locks refused",
+        count, refused, probes
+    );
+    Ok(())
+}
+
+/// Add `expirations` to `count` under `mutex`. Returns whether a
+/// `try_lock` while holding it was refused, as it must be.
+fn tick(mutex: MutexHandle, count: &mut u64, expirations: u64) -> Result<bool, i32> {
+    mutex_lock(mutex)?;
+    let refused = !mutex_try_lock(mutex)?;
+    *count += expirations;
+    mutex_unlock(mutex)?;
+    Ok(refused)
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
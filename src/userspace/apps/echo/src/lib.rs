@@ -0,0 +1,80 @@
+//! Echo server: sends back whatever a TCP client sends.
+//!
+//! Listens on `PORT` (the echo service), echoes one connection until the
+//! peer closes it, then listens again. Needs a Network capability that
+//! may listen there:
+//!
+//! ```text
+//! wasm run --net listen=7 apps/echo.wasm
+//! ```
+//!
+//! The SDK has no UDP sockets yet, so this is the TCP flavor of echo.
+
+#![no_std]
+
+use core::fmt::Write;
+use sovelma_sdk::{capabilities, net_close, net_listen, net_recv, net_send, Stdout};
+
+sovelma_sdk::manifest!(b"name = echo\nversion = 0.1.0\ncapabilities = timer, network\n");
+
+/// Port listened on.
+const PORT: u16 = 7;
+
+/// Bytes received per call.
+const CHUNK: usize = 512;
+
+/// Entry point: serve connections one after another.
+#[no_mangle]
+pub extern "C" fn _start() {
+    let Some(net) = net_handle() else {
+        let _ = writeln!(Stdout, "echo: no network capability");
+        return;
+    };
+    let _ = writeln!(Stdout, "echo: listening on port {}", PORT);
+    loop {
+        let socket = match net_listen(net, PORT) {
+            Ok(socket) => socket,
+            Err(code) => {
+                let _ = writeln!(Stdout, "echo: cannot listen: error {}", code);
+                return;
+            }
+        };
+        let echoed = serve(socket);
+        let _ = net_close(socket);
+        let _ = writeln!(Stdout, "echo: connection closed, {} bytes echoed", echoed);
+    }
+}
+
+/// Handle of the Network capability granted with `wasm run --net`.
+fn net_handle() -> Option<i64> {
+    let mut buf = [0u8; 256];
+    capabilities(&mut buf)
+        .ok()?
+        .find(|cap| cap.kind == "network")
+        .map(|cap| cap.handle)
+}
+
+/// Echo on `socket` until the peer closes; returns the bytes echoed.
+fn serve(socket: i64) -> usize {
+    let mut buf = [0u8; CHUNK];
+    let mut echoed = 0;
+    while let Ok(count) = net_recv(socket, &mut buf) {
+        if count == 0 {
+            break;
+        }
+        let mut sent = 0;
+        while sent < count {
+            match net_send(socket, &buf[sent..count]) {
+                Ok(n) => sent += n,
+                Err(_) => return echoed + sent,
+            }
+        }
+        echoed += count;
+    }
+    echoed
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
@@ -7,6 +7,8 @@
 
 use sovelma_sdk::{print_str, yield_now};
 
+sovelma_sdk::manifest!(b"name = hello\nversion = 0.1.0\n");
+
 /// Entry point for the WASM module.
 ///
 /// Note: Initial capabilities are granted at spawn time by the kernel.
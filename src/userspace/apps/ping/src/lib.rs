@@ -0,0 +1,38 @@
+//! Ping: one half of a pipe ping-pong pair.
+//!
+//! Writes `ping 1` to `ping 5` to stdout, one every `INTERVAL_MS`; run it
+//! ahead of `pong` in a pipeline so they talk over the pipe:
+//!
+//! ```text
+//! wasm run apps/ping.wasm | wasm run apps/pong.wasm
+//! ```
+
+#![no_std]
+
+use core::fmt::Write;
+use sovelma_sdk::{sleep_ms, Stdout};
+
+sovelma_sdk::manifest!(b"name = ping\nversion = 0.1.0\ncapabilities = timer\n");
+
+/// Messages sent.
+const ROUNDS: u32 = 5;
+
+/// Pause between messages.
+const INTERVAL_MS: u64 = 100;
+
+/// Entry point: send the pings; stdout closes when the process exits.
+#[no_mangle]
+pub extern "C" fn _start() {
+    for round in 1..=ROUNDS {
+        if writeln!(Stdout, "ping {}", round).is_err() {
+            // pong has gone
+            return;
+        }
+        let _ = sleep_ms(INTERVAL_MS);
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
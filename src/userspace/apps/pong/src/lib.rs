@@ -0,0 +1,50 @@
+//! Pong: the other half of the pipe ping-pong pair.
+//!
+//! Answers every `ping <n>` line on stdin with `pong <n>` on stdout (the
+//! console, at the end of a pipeline), and reports how many it answered
+//! once `ping` has exited.
+
+#![no_std]
+
+use core::fmt::Write;
+use sovelma_sdk::{stdin_read, Stdout};
+
+sovelma_sdk::manifest!(b"name = pong\nversion = 0.1.0\ncapabilities = timer\n");
+
+/// Longest line handled; longer lines are cut here.
+const LINE_MAX: usize = 64;
+
+/// Entry point: answer pings until end of input.
+#[no_mangle]
+pub extern "C" fn _start() {
+    let mut input = [0u8; LINE_MAX];
+    let mut line = [0u8; LINE_MAX];
+    let mut len = 0;
+    let mut answered = 0;
+    while let Ok(count) = stdin_read(&mut input) {
+        if count == 0 {
+            break;
+        }
+        for &byte in &input[..count] {
+            if byte != b'\n' {
+                if len < LINE_MAX {
+                    line[len] = byte;
+                    len += 1;
+                }
+                continue;
+            }
+            if let Some(round) = line[..len].strip_prefix(b"ping ") {
+                let round = core::str::from_utf8(round).unwrap_or("?");
+                let _ = writeln!(Stdout, "pong {}", round);
+                answered += 1;
+            }
+            len = 0;
+        }
+    }
+    let _ = writeln!(Stdout, "pong: done, {} answered", answered);
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
@@ -10,14 +10,50 @@
 //!
 //! Initial capabilities are passed to the process and can be accessed via
 //! a well-known memory location or passed as arguments to the entry point.
+//!
+//! Capabilities are named by handles private to the process, like file
+//! descriptors: small positive integers, lowest free first, with the
+//! initial capabilities at 1, 2, ... in the order they were granted. A
+//! closed handle is reused by the next capability the process receives.
 
 #![no_std]
 
+use core::marker::PhantomData;
+use core::sync::atomic::AtomicU32;
+use sovelma_common::capability::CapabilityRights;
+use sovelma_common::codec::{Decode, Reader};
+
+pub use sovelma_common::capability::{CapabilityDescriptor, CapabilityDetail, CapabilityRecord};
+pub use sovelma_common::codec;
+pub use sovelma_common::fs::{DirEntryRecord, EntryKind};
+
 extern "C" {
     fn print(ptr: *const u8, len: usize);
+    fn sp_dbg_break() -> i32;
+    fn sp_get_capabilities(buf_ptr: *mut u8, buf_len: usize) -> i32;
+    fn sp_describe_capabilities(version: i32, buf_ptr: *mut u8, buf_len: usize) -> i32;
     fn sp_fs_open(dir_cap: i64, path_ptr: *const u8, path_len: usize) -> i64;
+    fn sp_fs_opendir_restricted(
+        dir_cap: i64,
+        path_ptr: *const u8,
+        path_len: usize,
+        rights: u32,
+    ) -> i64;
     fn sp_fs_read(file_cap: i64, buf_ptr: *mut u8, buf_len: usize, offset: i32) -> i32;
+    fn sp_fs_write(file_cap: i64, buf_ptr: *const u8, buf_len: usize, offset: i32) -> i32;
     fn sp_fs_mkdir(dir_cap: i64, path_ptr: *const u8, path_len: usize) -> i32;
+    fn sp_fs_create(dir_cap: i64, path_ptr: *const u8, path_len: usize) -> i64;
+    fn sp_fs_unlink(dir_cap: i64, path_ptr: *const u8, path_len: usize) -> i32;
+    fn sp_fs_rename(
+        dir_cap: i64,
+        from_ptr: *const u8,
+        from_len: usize,
+        to_ptr: *const u8,
+        to_len: usize,
+    ) -> i32;
+    fn sp_fs_readdir(dir_cap: i64, start: i32, buf_ptr: *mut u8, buf_len: usize) -> i32;
+    fn sp_fs_lock(file_cap: i64, exclusive: i32) -> i32;
+    fn sp_fs_unlock(file_cap: i64) -> i32;
     fn sp_fs_close(file_cap: i64);
     fn sp_sched_yield();
 
@@ -30,6 +66,57 @@ extern "C" {
     fn sp_sem_acquire(cap: i64) -> i32;
     fn sp_sem_try_acquire(cap: i64) -> i32;
     fn sp_sem_release(cap: i64) -> i32;
+    fn sp_futex_wait(addr: *const AtomicU32, expected: u32, timeout_ms: i64) -> i32;
+    fn sp_futex_wake(addr: *const AtomicU32, count: i32) -> i32;
+
+    // Clock and timers (Timer capability)
+    fn sp_clock_monotonic_ms() -> i64;
+    fn sp_clock_resolution_ms() -> i64;
+    fn sp_sleep_ms(ms: i64) -> i32;
+    fn sp_timer_create() -> i64;
+    fn sp_timer_arm(timer: i64, initial_ms: i64, period_ms: i64) -> i32;
+    fn sp_timer_cancel(timer: i64) -> i32;
+
+    // Event queue
+    fn sp_poll(events_ptr: *mut Event, max: i32, timeout_ms: i64) -> i32;
+
+    // Process control
+    fn sp_kill(process_cap: i64, signal: i32) -> i32;
+
+    // Serial ports (Serial capability)
+    fn sp_serial_write(serial_cap: i64, buf_ptr: *const u8, buf_len: usize) -> i32;
+    fn sp_serial_read(serial_cap: i64, buf_ptr: *mut u8, buf_len: usize) -> i32;
+
+    // Standard streams
+    fn sp_stdout_write(buf_ptr: *const u8, buf_len: usize) -> i32;
+    fn sp_stdin_read(buf_ptr: *mut u8, buf_len: usize) -> i32;
+
+    // System configuration (Config capability)
+    fn sp_cfg_get(
+        cfg_cap: i64,
+        key_ptr: *const u8,
+        key_len: usize,
+        buf_ptr: *mut u8,
+        buf_len: usize,
+    ) -> i32;
+    fn sp_cfg_set(
+        cfg_cap: i64,
+        key_ptr: *const u8,
+        key_len: usize,
+        val_ptr: *const u8,
+        val_len: usize,
+    ) -> i32;
+
+    // Networking (Network and Socket capabilities)
+    fn sp_net_connect(net_cap: i64, addr: i32, port: i32) -> i64;
+    fn sp_net_listen(net_cap: i64, port: i32) -> i64;
+    fn sp_net_send(sock_cap: i64, buf_ptr: *const u8, buf_len: usize) -> i32;
+    fn sp_net_recv(sock_cap: i64, buf_ptr: *mut u8, buf_len: usize) -> i32;
+    fn sp_net_close(sock_cap: i64) -> i32;
+    fn sp_net_raw_send(net_cap: i64, buf_ptr: *const u8, buf_len: usize) -> i32;
+
+    // Batched operations
+    fn sp_batch(ops_ptr: *mut u8, count: usize) -> i32;
 }
 
 /// Print a message via the kernel console.
@@ -37,27 +124,140 @@ pub fn print_str(s: &str) {
     unsafe { print(s.as_ptr(), s.len()) };
 }
 
+/// Stop here until resumed from the shell.
+///
+/// While the process is stopped, the shell's `peek` and `inspect` show its
+/// memory, globals and capabilities; `resume <pid>` lets it continue.
+pub fn dbg_break() {
+    unsafe { sp_dbg_break() };
+}
+
 // Note: get_root() has been removed. Capabilities are now granted at spawn time.
 // Access your initial capabilities through the mechanism provided by the kernel
 // (e.g., passed as arguments to your entry point or via a well-known memory location).
 
+/// `capabilities` or `describe_capabilities` was given a buffer too
+/// small for the list.
+pub const BUFFER_TOO_SMALL: i32 = -8;
+
+/// List the capabilities the process holds.
+///
+/// The kernel writes the list to `buf`, encoded with `codec`, and the
+/// records returned borrow their kind names from it. Each takes a few
+/// bytes plus its kind name, so 32 bytes per capability is plenty.
+///
+/// ```ignore
+/// let mut buf = [0u8; 256];
+/// for cap in sovelma_sdk::capabilities(&mut buf)? {
+///     // cap.handle, cap.kind, cap.rights
+/// }
+/// ```
+///
+/// # Returns
+/// * `Ok(records)` - The held capabilities, lowest handle first
+/// * `Err(i32)` - Error code (`BUFFER_TOO_SMALL` if the list does not fit)
+pub fn capabilities(buf: &mut [u8]) -> Result<Capabilities<'_, CapabilityRecord<'_>>, i32> {
+    let result = unsafe { sp_get_capabilities(buf.as_mut_ptr(), buf.len()) };
+    Capabilities::read(result, buf)
+}
+
+/// List the capabilities the process holds with their labels and what
+/// they refer to, so one of several of the same kind can be picked:
+///
+/// ```ignore
+/// let mut buf = [0u8; 512];
+/// let web = sovelma_sdk::describe_capabilities(&mut buf)?
+///     .find(|cap| cap.label == Some("web"))
+///     .map(|cap| cap.record.handle);
+/// ```
+///
+/// Labels are given at grant time (`wasm run --net web@listen=80`). The
+/// detail is the path of a directory or file, the ports of a Network
+/// capability or the I/O port of a Serial one. Allow 64 bytes per
+/// capability, plus the length of any path.
+///
+/// # Returns
+/// * `Ok(descriptors)` - The held capabilities, lowest handle first
+/// * `Err(i32)` - Error code (`BUFFER_TOO_SMALL` if the list does not fit)
+pub fn describe_capabilities(
+    buf: &mut [u8],
+) -> Result<Capabilities<'_, CapabilityDescriptor<'_>>, i32> {
+    let result = unsafe { sp_describe_capabilities(2, buf.as_mut_ptr(), buf.len()) };
+    Capabilities::read(result, buf)
+}
+
+/// The records or descriptors listed by `capabilities` or
+/// `describe_capabilities`.
+pub struct Capabilities<'a, T> {
+    reader: Reader<'a>,
+    left: usize,
+    item: PhantomData<T>,
+}
+
+impl<'a, T> Capabilities<'a, T> {
+    /// The list the kernel wrote to `buf`, given what it returned.
+    fn read(result: i32, buf: &'a [u8]) -> Result<Self, i32> {
+        if result < 0 {
+            return Err(result);
+        }
+        let mut reader = Reader::new(buf);
+        // The count the kernel returned is also the sequence's length
+        let _ = reader.varint();
+        Ok(Self {
+            reader,
+            left: result as usize,
+            item: PhantomData,
+        })
+    }
+}
+
+impl<'a, T: Decode<'a>> Iterator for Capabilities<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        self.reader.decode().ok()
+    }
+}
+
 /// Open a file or directory relative to a directory capability.
 ///
 /// # Arguments
-/// * `dir_cap` - A directory capability ID (must have READ permission)
+/// * `dir_cap` - A directory capability handle (must have READ permission)
 /// * `path` - Relative path to open
 ///
 /// # Returns
-/// * Positive value: New capability ID for the opened file/directory
+/// * Positive value: New capability handle for the opened file/directory
 /// * Negative value: Error code
 pub fn open(dir_cap: i64, path: &str) -> i64 {
     unsafe { sp_fs_open(dir_cap, path.as_ptr(), path.len()) }
 }
 
+/// Open a subdirectory as a new capability with narrowed rights.
+///
+/// The usual way to hand a child process one data directory: the new
+/// capability reaches only `path` and below, and has just the rights in
+/// `rights` that `dir_cap` also has.
+///
+/// # Arguments
+/// * `dir_cap` - A directory capability handle (must have READ permission)
+/// * `path` - Relative path of the directory
+/// * `rights` - Rights to keep
+///
+/// # Returns
+/// * Positive value: New directory capability handle
+/// * Negative value: Error code
+pub fn opendir_restricted(dir_cap: i64, path: &str, rights: CapabilityRights) -> i64 {
+    unsafe { sp_fs_opendir_restricted(dir_cap, path.as_ptr(), path.len(), rights.bits()) }
+}
+
 /// Read data from a file capability.
 ///
 /// # Arguments
-/// * `file_cap` - A file capability ID (must have READ permission)
+/// * `file_cap` - A file capability handle (must have READ permission)
 /// * `buf` - Buffer to read into
 /// * `offset` - Byte offset to start reading from
 ///
@@ -68,10 +268,27 @@ pub fn read(file_cap: i64, buf: &mut [u8], offset: usize) -> i32 {
     unsafe { sp_fs_read(file_cap, buf.as_mut_ptr(), buf.len(), offset as i32) }
 }
 
+/// Write data to a file capability, growing the file as needed.
+///
+/// At most 16 KiB are written per call; write the rest with another call.
+/// Bytes that grow the file count against the process's filesystem quota.
+///
+/// # Arguments
+/// * `file_cap` - A file capability handle (must have WRITE permission)
+/// * `buf` - Data to write
+/// * `offset` - Byte offset to start writing at
+///
+/// # Returns
+/// * Positive value: Number of bytes written
+/// * Negative value: Error code (-20 if the quota would be exceeded)
+pub fn write(file_cap: i64, buf: &[u8], offset: usize) -> i32 {
+    unsafe { sp_fs_write(file_cap, buf.as_ptr(), buf.len(), offset as i32) }
+}
+
 /// Create a directory relative to a directory capability.
 ///
 /// # Arguments
-/// * `dir_cap` - A directory capability ID (must have WRITE permission)
+/// * `dir_cap` - A directory capability handle (must have WRITE permission)
 /// * `path` - Relative path of directory to create
 ///
 /// # Returns
@@ -81,10 +298,153 @@ pub fn mkdir(dir_cap: i64, path: &str) -> i32 {
     unsafe { sp_fs_mkdir(dir_cap, path.as_ptr(), path.len()) }
 }
 
+/// Create an empty file relative to a directory capability and open it.
+///
+/// # Arguments
+/// * `dir_cap` - A directory capability handle (must have WRITE permission)
+/// * `path` - Relative path of the file to create; nothing may be there yet
+///
+/// # Returns
+/// * Positive value: File capability handle, with the directory's READ
+///   and WRITE
+/// * Negative value: Error code
+pub fn create(dir_cap: i64, path: &str) -> i64 {
+    unsafe { sp_fs_create(dir_cap, path.as_ptr(), path.len()) }
+}
+
+/// Remove a file, device node or empty directory relative to a directory
+/// capability.
+///
+/// File capabilities already open on a removed file keep working until
+/// they are closed.
+///
+/// # Arguments
+/// * `dir_cap` - A directory capability handle (must have WRITE permission)
+/// * `path` - Relative path of what to remove
+///
+/// # Returns
+/// * 0: Success
+/// * Negative value: Error code
+pub fn unlink(dir_cap: i64, path: &str) -> i32 {
+    unsafe { sp_fs_unlink(dir_cap, path.as_ptr(), path.len()) }
+}
+
+/// Move a file or directory to a new path, both relative to a directory
+/// capability.
+///
+/// # Arguments
+/// * `dir_cap` - A directory capability handle (must have WRITE permission)
+/// * `from` - Relative path of what to move
+/// * `to` - Relative path to move it to; nothing may be there yet
+///
+/// # Returns
+/// * 0: Success
+/// * Negative value: Error code
+pub fn rename(dir_cap: i64, from: &str, to: &str) -> i32 {
+    unsafe { sp_fs_rename(dir_cap, from.as_ptr(), from.len(), to.as_ptr(), to.len()) }
+}
+
+/// List a directory, as many entries as fit in `buf` at a time.
+///
+/// Entries come sorted by name, from the `start`th on; the records
+/// returned borrow their names from `buf`. Ask again from where the last
+/// batch ended until one comes back empty:
+///
+/// ```ignore
+/// let mut buf = [0u8; 256];
+/// let mut start = 0;
+/// loop {
+///     let entries = sovelma_sdk::read_dir(dir_cap, start, &mut buf)?;
+///     if entries.len() == 0 {
+///         break;
+///     }
+///     start += entries.len();
+///     for entry in entries {
+///         // entry.name, entry.kind, entry.size
+///     }
+/// }
+/// ```
+///
+/// # Arguments
+/// * `dir_cap` - A directory capability handle (must have READ permission)
+/// * `start` - Index of the first entry to list
+/// * `buf` - Where the kernel writes the entries; each takes a few bytes
+///   plus its name
+///
+/// # Returns
+/// * `Ok(entries)` - The next entries, none once all were listed
+/// * `Err(i32)` - Error code (`BUFFER_TOO_SMALL` if not even one entry fits)
+pub fn read_dir(dir_cap: i64, start: usize, buf: &mut [u8]) -> Result<DirEntries<'_>, i32> {
+    let result = unsafe { sp_fs_readdir(dir_cap, start as i32, buf.as_mut_ptr(), buf.len()) };
+    if result < 0 {
+        return Err(result);
+    }
+    let mut reader = Reader::new(buf);
+    if result > 0 {
+        // The count the kernel returned is also the sequence's length
+        let _ = reader.varint();
+    }
+    Ok(DirEntries {
+        reader,
+        left: result as usize,
+    })
+}
+
+/// A batch of directory entries listed by `read_dir`.
+pub struct DirEntries<'a> {
+    reader: Reader<'a>,
+    left: usize,
+}
+
+impl<'a> Iterator for DirEntries<'a> {
+    type Item = DirEntryRecord<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        self.reader.decode().ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.left, Some(self.left))
+    }
+}
+
+impl ExactSizeIterator for DirEntries<'_> {}
+
+/// Take an advisory lock on a file, blocking until no other holder's lock
+/// conflicts: any number of shared locks, or one exclusive lock.
+///
+/// Locks only keep out other `lock` calls, so every process sharing the
+/// file must use them. Closing the capability releases its lock.
+///
+/// # Arguments
+/// * `file_cap` - A file capability handle (READ for a shared lock, WRITE
+///   for an exclusive one)
+/// * `exclusive` - Whether to take an exclusive lock
+///
+/// # Returns
+/// * 0: Lock held
+/// * Negative value: Error code
+pub fn lock(file_cap: i64, exclusive: bool) -> i32 {
+    unsafe { sp_fs_lock(file_cap, i32::from(exclusive)) }
+}
+
+/// Release the advisory lock held through a file capability.
+///
+/// # Returns
+/// * 0: Success
+/// * Negative value: Error code (-29 if the capability holds no lock)
+pub fn unlock(file_cap: i64) -> i32 {
+    unsafe { sp_fs_unlock(file_cap) }
+}
+
 /// Close a file or directory capability.
 ///
 /// # Arguments
-/// * `file_cap` - The capability ID to close
+/// * `file_cap` - The capability handle to close
 pub fn close(file_cap: i64) {
     unsafe { sp_fs_close(file_cap) }
 }
@@ -253,3 +613,807 @@ pub fn sem_release(handle: SemHandle) -> Result<(), i32> {
         Err(result)
     }
 }
+
+/// Error codes for futex operations.
+pub mod futex_error {
+    /// The word does not hold the expected value.
+    pub const VALUE_CHANGED: i32 = -27;
+    /// Not woken before the timeout.
+    pub const TIMED_OUT: i32 = -28;
+    /// Negative count or address.
+    pub const INVALID_ARGUMENT: i32 = -15;
+}
+
+/// Wait until `futex_wake` is called on `word`, if it still holds
+/// `expected`.
+///
+/// The building block for locks and channels of your own: keep the state
+/// in an atomic and only call into the kernel to wait for it to change.
+/// Checking the value and starting to wait is atomic with respect to
+/// `futex_wake`, so a wakeup is never missed.
+///
+/// # Arguments
+/// * `word` - The word to wait on
+/// * `expected` - Value the word must hold for the wait to start
+/// * `timeout_ms` - Give up after this long; `None` waits forever
+///
+/// # Returns
+/// * `Ok(())` - Woken by `futex_wake`
+/// * `Err(futex_error::VALUE_CHANGED)` - `word` did not hold `expected`
+/// * `Err(futex_error::TIMED_OUT)` - Not woken in time
+/// * `Err(i32)` - Other error code
+pub fn futex_wait(word: &AtomicU32, expected: u32, timeout_ms: Option<u64>) -> Result<(), i32> {
+    let timeout = timeout_ms.map_or(-1, |ms| ms.min(i64::MAX as u64) as i64);
+    let result = unsafe { sp_futex_wait(word, expected, timeout) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(result)
+    }
+}
+
+/// Wake up to `count` tasks waiting on `word` in `futex_wait`.
+///
+/// # Returns
+/// * `Ok(u32)` - Number of waiters woken
+/// * `Err(i32)` - Error code
+pub fn futex_wake(word: &AtomicU32, count: u32) -> Result<u32, i32> {
+    let count = count.min(i32::MAX as u32) as i32;
+    let result = unsafe { sp_futex_wake(word, count) };
+    if result < 0 {
+        Err(result)
+    } else {
+        Ok(result as u32)
+    }
+}
+
+// ============================================================================
+// Clock and Timers
+// ============================================================================
+//
+// These require a Timer capability granted at spawn time: READ for the
+// clock, CALL for sleeping and timers.
+
+/// A handle to a kernel timer owned by this process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerHandle(pub i64);
+
+/// Error codes for clock and timer operations.
+pub mod timer_error {
+    /// No Timer capability with the required rights.
+    pub const PERMISSION_DENIED: i32 = -5;
+    /// Unknown timer.
+    pub const INVALID_HANDLE: i32 = -13;
+    /// The process owns the maximum number of timers.
+    pub const TOO_MANY_TIMERS: i32 = -14;
+    /// Negative duration or period.
+    pub const INVALID_ARGUMENT: i32 = -15;
+}
+
+/// Milliseconds since boot.
+///
+/// # Returns
+/// * `Ok(u64)` - Monotonic time
+/// * `Err(i32)` - Error code
+pub fn clock_monotonic_ms() -> Result<u64, i32> {
+    let result = unsafe { sp_clock_monotonic_ms() };
+    if result < 0 {
+        Err(result as i32)
+    } else {
+        Ok(result as u64)
+    }
+}
+
+/// Resolution of the monotonic clock in milliseconds.
+///
+/// # Returns
+/// * `Ok(u64)` - Clock resolution
+/// * `Err(i32)` - Error code
+pub fn clock_resolution_ms() -> Result<u64, i32> {
+    let result = unsafe { sp_clock_resolution_ms() };
+    if result < 0 {
+        Err(result as i32)
+    } else {
+        Ok(result as u64)
+    }
+}
+
+/// Sleep for at least `ms` milliseconds.
+///
+/// Other tasks run meanwhile.
+///
+/// # Returns
+/// * `Ok(())` - The time has passed
+/// * `Err(i32)` - Error code
+pub fn sleep_ms(ms: u64) -> Result<(), i32> {
+    let result = unsafe { sp_sleep_ms(ms as i64) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(result)
+    }
+}
+
+/// Create a disarmed timer.
+///
+/// # Returns
+/// * `Ok(TimerHandle)` - Handle to the created timer
+/// * `Err(i32)` - Error code
+pub fn timer_create() -> Result<TimerHandle, i32> {
+    let result = unsafe { sp_timer_create() };
+    if result < 0 {
+        Err(result as i32)
+    } else {
+        Ok(TimerHandle(result))
+    }
+}
+
+/// Arm a timer.
+///
+/// The timer expires `initial_ms` from now and then every `period_ms`;
+/// a period of 0 makes it one-shot. Each expiry is delivered as an event
+/// on the process's event queue.
+///
+/// # Returns
+/// * `Ok(())` - Timer armed
+/// * `Err(i32)` - Error code
+pub fn timer_arm(handle: TimerHandle, initial_ms: u64, period_ms: u64) -> Result<(), i32> {
+    let result = unsafe { sp_timer_arm(handle.0, initial_ms as i64, period_ms as i64) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(result)
+    }
+}
+
+/// Cancel and delete a timer, discarding undelivered expirations.
+///
+/// # Returns
+/// * `Ok(())` - Timer deleted
+/// * `Err(i32)` - Error code
+pub fn timer_cancel(handle: TimerHandle) -> Result<(), i32> {
+    let result = unsafe { sp_timer_cancel(handle.0) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(result)
+    }
+}
+
+// ============================================================================
+// Events
+// ============================================================================
+
+/// Event kinds reported by `poll`.
+pub mod event_kind {
+    /// A timer expired. Object: timer handle; data: expirations.
+    pub const TIMER: u32 = 1;
+    /// An IPC message arrived. Object: sender; data: message length.
+    pub const MESSAGE: u32 = 2;
+    /// A socket became ready. Object: socket; data: readiness bits.
+    pub const SOCKET: u32 = 3;
+    /// A watched path changed. Object: watch; data: change bits.
+    pub const FS_WATCH: u32 = 4;
+    /// A child process exited. Object: process ID; data: exit status.
+    pub const CHILD_EXIT: u32 = 5;
+    /// A signal was posted. Object: signal number (see `signal`).
+    pub const SIGNAL: u32 = 6;
+    /// A TCP connection changed state. Object: socket; data: the change
+    /// (see `connection`).
+    pub const CONNECTION: u32 = 7;
+    /// The shell granted a capability with `grant`. Object: its handle.
+    pub const GRANTED: u32 = 8;
+}
+
+/// Connection state changes, the data of `event_kind::CONNECTION` events.
+///
+/// `CLOSED`, `REFUSED` and `TIMED_OUT` are the last event of a socket.
+pub mod connection {
+    /// The handshake completed.
+    pub const CONNECTED: u64 = 1;
+    /// The established connection was closed.
+    pub const CLOSED: u64 = 2;
+    /// The connection was reset before it was established.
+    pub const REFUSED: u64 = 3;
+    /// No answer within 10 seconds; the socket is aborted.
+    pub const TIMED_OUT: u64 = 4;
+}
+
+/// An event from the process's event queue, as written by the kernel.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Event {
+    /// Event kind (see `event_kind`).
+    pub kind: u32,
+    _reserved: u32,
+    /// Object the event is about (timer, socket, process, ...).
+    pub object: u64,
+    /// Kind-specific payload.
+    pub data: u64,
+}
+
+/// Wait for events.
+///
+/// Fills `events` with pending events, blocking until at least one is
+/// available. `timeout_ms` of `None` waits forever; `Some(0)` only checks.
+///
+/// # Returns
+/// * `Ok(n)` - Number of events written (0 on timeout)
+/// * `Err(i32)` - Error code
+pub fn poll(events: &mut [Event], timeout_ms: Option<u64>) -> Result<usize, i32> {
+    let timeout = timeout_ms.map_or(-1, |ms| ms as i64);
+    let result = unsafe { sp_poll(events.as_mut_ptr(), events.len() as i32, timeout) };
+    if result < 0 {
+        Err(result)
+    } else {
+        Ok(result as usize)
+    }
+}
+
+// ============================================================================
+// Signals
+// ============================================================================
+
+/// Signal numbers, delivered as `event_kind::SIGNAL` events.
+///
+/// A process that does not exit soon after `TERM` is terminated by the
+/// kernel; `KILL` terminates it immediately and is never delivered.
+pub mod signal {
+    /// Hang-up; conventionally "reload configuration".
+    pub const HUP: i32 = 1;
+    /// Forced termination.
+    pub const KILL: i32 = 9;
+    /// User-defined.
+    pub const USR1: i32 = 10;
+    /// Polite termination request.
+    pub const TERM: i32 = 15;
+}
+
+/// Post a signal to another process.
+///
+/// # Arguments
+/// * `process_cap` - A process capability (must have CALL permission)
+/// * `sig` - Signal number (see `signal`)
+///
+/// # Returns
+/// * `Ok(())` - Signal posted
+/// * `Err(i32)` - Error code
+pub fn kill(process_cap: i64, sig: i32) -> Result<(), i32> {
+    let result = unsafe { sp_kill(process_cap, sig) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(result)
+    }
+}
+
+// ============================================================================
+// Serial Ports
+// ============================================================================
+//
+// A Serial capability is granted with `wasm run --serial <n>` or obtained
+// by opening `serial<n>` through a capability for the `/dev` directory.
+
+/// Write bytes to a serial port, waiting until they are sent.
+///
+/// # Arguments
+/// * `serial_cap` - A serial capability (must have WRITE permission)
+/// * `data` - Bytes to send
+///
+/// # Returns
+/// * `Ok(n)` - Number of bytes written
+/// * `Err(i32)` - Error code
+pub fn serial_write(serial_cap: i64, data: &[u8]) -> Result<usize, i32> {
+    let result = unsafe { sp_serial_write(serial_cap, data.as_ptr(), data.len()) };
+    if result < 0 {
+        Err(result)
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// Read the bytes a serial port has received, without waiting.
+///
+/// # Arguments
+/// * `serial_cap` - A serial capability (must have READ permission)
+/// * `buf` - Buffer to read into
+///
+/// # Returns
+/// * `Ok(n)` - Number of bytes read (0 if none were waiting)
+/// * `Err(i32)` - Error code
+pub fn serial_read(serial_cap: i64, buf: &mut [u8]) -> Result<usize, i32> {
+    let result = unsafe { sp_serial_read(serial_cap, buf.as_mut_ptr(), buf.len()) };
+    if result < 0 {
+        Err(result)
+    } else {
+        Ok(result as usize)
+    }
+}
+
+// ============================================================================
+// Standard Streams
+// ============================================================================
+//
+// Started as `wasm run a.wasm | wasm run b.wasm`, a's stdout is piped to
+// b's stdin. Otherwise stdin is empty and stdout goes to the console.
+
+/// Error codes for the standard streams.
+pub mod stdio_error {
+    /// The process reading stdout has exited.
+    pub const BROKEN_PIPE: i32 = -22;
+}
+
+/// Stdin is empty but more may come (internal; `stdin_read` waits).
+const WOULD_BLOCK: i32 = -21;
+
+/// Write all of `data` to stdout, yielding while the pipe is full.
+///
+/// # Returns
+/// * `Ok(n)` - Number of bytes written (`data.len()`)
+/// * `Err(i32)` - Error code (`stdio_error::BROKEN_PIPE` if the reader exited)
+pub fn stdout_write(data: &[u8]) -> Result<usize, i32> {
+    let mut written = 0;
+    while written < data.len() {
+        let rest = &data[written..];
+        let result = unsafe { sp_stdout_write(rest.as_ptr(), rest.len()) };
+        match result {
+            0 => yield_now(),
+            n if n < 0 => return Err(n),
+            n => written += n as usize,
+        }
+    }
+    Ok(written)
+}
+
+/// Read from stdin, yielding until some input is available.
+///
+/// # Arguments
+/// * `buf` - Buffer to read into
+///
+/// # Returns
+/// * `Ok(n)` - Number of bytes read (0 at end of input)
+/// * `Err(i32)` - Error code
+pub fn stdin_read(buf: &mut [u8]) -> Result<usize, i32> {
+    loop {
+        let result = unsafe { sp_stdin_read(buf.as_mut_ptr(), buf.len()) };
+        match result {
+            WOULD_BLOCK => yield_now(),
+            n if n < 0 => return Err(n),
+            n => return Ok(n as usize),
+        }
+    }
+}
+
+/// Stdout as a `core::fmt::Write`, so output can be formatted without an
+/// allocator:
+///
+/// ```ignore
+/// use core::fmt::Write;
+/// let _ = writeln!(sovelma_sdk::Stdout, "count: {}", count);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stdout;
+
+impl core::fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        stdout_write(s.as_bytes())
+            .map(|_| ())
+            .map_err(|_| core::fmt::Error)
+    }
+}
+
+// ============================================================================
+// System Configuration
+// ============================================================================
+//
+// A Config capability is granted with `wasm run --config`. Keys are ASCII
+// letters, digits, `.`, `_` and `-` (at most 64 bytes), conventionally
+// dotted by service (`myapp.interval`).
+
+/// Longest configuration value, so a buffer this size always suffices.
+pub const CONFIG_VALUE_MAX: usize = 256;
+
+/// Error codes for the configuration functions.
+pub mod config_error {
+    /// The key is not set.
+    pub const NO_SUCH_KEY: i32 = -23;
+    /// The key or value is invalid.
+    pub const INVALID_ARGUMENT: i32 = -15;
+    /// The configuration is full.
+    pub const FULL: i32 = -20;
+}
+
+/// Read a configuration value.
+///
+/// # Arguments
+/// * `cfg_cap` - A config capability (must have READ permission)
+/// * `key` - Key to look up
+/// * `buf` - Buffer for the value (`CONFIG_VALUE_MAX` bytes always suffice)
+///
+/// # Returns
+/// * `Ok(n)` - Length of the value written to `buf`
+/// * `Err(i32)` - Error code (`config_error::NO_SUCH_KEY` if not set)
+pub fn cfg_get(cfg_cap: i64, key: &str, buf: &mut [u8]) -> Result<usize, i32> {
+    let result = unsafe {
+        sp_cfg_get(
+            cfg_cap,
+            key.as_ptr(),
+            key.len(),
+            buf.as_mut_ptr(),
+            buf.len(),
+        )
+    };
+    if result < 0 {
+        Err(result)
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// Set a configuration value; the kernel saves it to `/etc/config`.
+///
+/// # Arguments
+/// * `cfg_cap` - A config capability (must have WRITE permission)
+/// * `key` - Key to set
+/// * `value` - One line of at most `CONFIG_VALUE_MAX` bytes, without `#`
+///
+/// # Returns
+/// * `Ok(())` - Value set
+/// * `Err(i32)` - Error code
+pub fn cfg_set(cfg_cap: i64, key: &str, value: &str) -> Result<(), i32> {
+    let result = unsafe {
+        sp_cfg_set(
+            cfg_cap,
+            key.as_ptr(),
+            key.len(),
+            value.as_ptr(),
+            value.len(),
+        )
+    };
+    if result < 0 {
+        Err(result)
+    } else {
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Networking
+// ============================================================================
+//
+// A Network capability is granted with `wasm run --net <rights>`, e.g.
+// `--net connect` for a client or `--net listen=8080` for a server. Each
+// right is separate: a client cannot listen, a server cannot connect out,
+// and only `raw` allows sending Ethernet frames. The sockets opened are
+// Socket capabilities.
+
+/// Error codes for the network functions.
+pub mod net_error {
+    /// The Network capability lacks the right (or port) needed.
+    pub const PERMISSION_DENIED: i32 = -5;
+    /// The network stack refused: no address, connection refused or reset,
+    /// or the port is in use.
+    pub const NET_ERROR: i32 = -24;
+    /// The kernel has no memory left for another socket.
+    pub const OUT_OF_MEMORY: i32 = -25;
+    /// The process already holds its limit of open sockets (8).
+    pub const TOO_MANY_SOCKETS: i32 = -26;
+}
+
+/// Call `f` until it stops returning `WOULD_BLOCK`, yielding in between.
+fn retry(mut f: impl FnMut() -> i64) -> i64 {
+    loop {
+        match f() {
+            r if r == i64::from(WOULD_BLOCK) => yield_now(),
+            r => return r,
+        }
+    }
+}
+
+/// Open a TCP connection to `addr:port`.
+///
+/// Returns once the connection is under way; `net_send` waits for it to
+/// complete. An `event_kind::CONNECTION` event reports when it does, or
+/// when it is refused or times out.
+///
+/// # Arguments
+/// * `net_cap` - A network capability (must have CONNECT permission)
+/// * `addr` - IPv4 address, e.g. `[10, 0, 2, 2]`
+/// * `port` - Remote port
+///
+/// # Returns
+/// * `Ok(sock_cap)` - Socket capability for the connection
+/// * `Err(i32)` - Error code
+pub fn net_connect(net_cap: i64, addr: [u8; 4], port: u16) -> Result<i64, i32> {
+    let addr = i32::from_be_bytes(addr);
+    let result = retry(|| unsafe { sp_net_connect(net_cap, addr, i32::from(port)) });
+    if result < 0 {
+        Err(result as i32)
+    } else {
+        Ok(result)
+    }
+}
+
+/// Listen for one TCP connection on `port`.
+///
+/// # Arguments
+/// * `net_cap` - A network capability (must have LISTEN permission for `port`)
+/// * `port` - Local port
+///
+/// # Returns
+/// * `Ok(sock_cap)` - Socket capability; `net_recv` waits for a peer
+/// * `Err(i32)` - Error code
+pub fn net_listen(net_cap: i64, port: u16) -> Result<i64, i32> {
+    let result = retry(|| unsafe { sp_net_listen(net_cap, i32::from(port)) });
+    if result < 0 {
+        Err(result as i32)
+    } else {
+        Ok(result)
+    }
+}
+
+/// Send bytes on a socket, yielding until the connection can take some.
+///
+/// # Arguments
+/// * `sock_cap` - A socket capability (must have WRITE permission)
+/// * `data` - Bytes to send
+///
+/// # Returns
+/// * `Ok(n)` - Number of bytes queued (may be fewer than `data.len()`)
+/// * `Err(i32)` - Error code (`net_error::NET_ERROR` once the connection is closed)
+pub fn net_send(sock_cap: i64, data: &[u8]) -> Result<usize, i32> {
+    let result = retry(|| i64::from(unsafe { sp_net_send(sock_cap, data.as_ptr(), data.len()) }));
+    if result < 0 {
+        Err(result as i32)
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// Receive bytes from a socket, yielding until some arrive.
+///
+/// # Arguments
+/// * `sock_cap` - A socket capability (must have READ permission)
+/// * `buf` - Buffer to read into
+///
+/// # Returns
+/// * `Ok(n)` - Number of bytes read (0 once the peer has closed)
+/// * `Err(i32)` - Error code
+pub fn net_recv(sock_cap: i64, buf: &mut [u8]) -> Result<usize, i32> {
+    let result = retry(|| i64::from(unsafe { sp_net_recv(sock_cap, buf.as_mut_ptr(), buf.len()) }));
+    if result < 0 {
+        Err(result as i32)
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// Close a socket. Data already queued is still sent.
+///
+/// # Arguments
+/// * `sock_cap` - The socket capability, which is revoked
+///
+/// # Returns
+/// * `Ok(())` - Socket closed
+/// * `Err(i32)` - Error code
+pub fn net_close(sock_cap: i64) -> Result<(), i32> {
+    let result = retry(|| i64::from(unsafe { sp_net_close(sock_cap) }));
+    if result < 0 {
+        Err(result as i32)
+    } else {
+        Ok(())
+    }
+}
+
+/// Send a complete Ethernet frame, as is.
+///
+/// # Arguments
+/// * `net_cap` - A network capability (must have RAW permission)
+/// * `frame` - Frame from the destination MAC on, at most 1514 bytes
+///
+/// # Returns
+/// * `Ok(n)` - Number of bytes sent
+/// * `Err(i32)` - Error code
+pub fn net_raw_send(net_cap: i64, frame: &[u8]) -> Result<usize, i32> {
+    let result =
+        retry(|| i64::from(unsafe { sp_net_raw_send(net_cap, frame.as_ptr(), frame.len()) }));
+    if result < 0 {
+        Err(result as i32)
+    } else {
+        Ok(result as usize)
+    }
+}
+
+// ============================================================================
+// Batched Operations
+// ============================================================================
+//
+// Each host call has a fixed cost, which dominates small reads and writes.
+// A `Batch` collects file and socket operations and runs them with one
+// call.
+
+/// Size of one operation record passed to `sp_batch`.
+const BATCH_RECORD_SIZE: usize = 32;
+
+/// Most operations in one `Batch`.
+pub const BATCH_MAX: usize = 64;
+
+/// Operation codes of `sp_batch` records.
+const BATCH_FS_READ: u32 = 1;
+const BATCH_FS_WRITE: u32 = 2;
+const BATCH_NET_SEND: u32 = 3;
+const BATCH_NET_RECV: u32 = 4;
+
+/// Offset of the result in an `sp_batch` record.
+const BATCH_RESULT_OFFSET: usize = 24;
+
+/// Error code when a batch already holds `BATCH_MAX` operations.
+pub const BATCH_FULL: i32 = -15;
+
+/// File and socket operations to run with one host call.
+///
+/// Each operation gets the result its single call (`read`, `write`,
+/// `net_send`, `net_recv`) would return, except that `net_send` and
+/// `net_recv` do not wait: a socket that is not ready reports -21.
+///
+/// ```ignore
+/// let mut batch = Batch::new();
+/// let header = batch.read(file, &mut header_buf, 0)?;
+/// let body = batch.read(file, &mut body_buf, 512)?;
+/// let results = batch.submit()?;
+/// let header_len = results.get(header);
+/// ```
+pub struct Batch<'a> {
+    records: [[u8; BATCH_RECORD_SIZE]; BATCH_MAX],
+    len: usize,
+    buffers: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> Default for Batch<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Batch<'a> {
+    /// Create an empty batch.
+    pub fn new() -> Self {
+        Self {
+            records: [[0; BATCH_RECORD_SIZE]; BATCH_MAX],
+            len: 0,
+            buffers: PhantomData,
+        }
+    }
+
+    /// Add a read of `buf.len()` bytes at `offset` from a file capability.
+    ///
+    /// # Returns
+    /// * `Ok(index)` - Index of the operation's result
+    /// * `Err(BATCH_FULL)` - The batch is full
+    pub fn read(&mut self, file_cap: i64, buf: &'a mut [u8], offset: u32) -> Result<usize, i32> {
+        self.push(BATCH_FS_READ, file_cap, buf.as_mut_ptr(), buf.len(), offset)
+    }
+
+    /// Add a write of `data` at `offset` to a file capability.
+    ///
+    /// # Returns
+    /// * `Ok(index)` - Index of the operation's result
+    /// * `Err(BATCH_FULL)` - The batch is full
+    pub fn write(&mut self, file_cap: i64, data: &'a [u8], offset: u32) -> Result<usize, i32> {
+        self.push(BATCH_FS_WRITE, file_cap, data.as_ptr(), data.len(), offset)
+    }
+
+    /// Add a send of `data` on a socket capability.
+    ///
+    /// # Returns
+    /// * `Ok(index)` - Index of the operation's result
+    /// * `Err(BATCH_FULL)` - The batch is full
+    pub fn send(&mut self, sock_cap: i64, data: &'a [u8]) -> Result<usize, i32> {
+        self.push(BATCH_NET_SEND, sock_cap, data.as_ptr(), data.len(), 0)
+    }
+
+    /// Add a receive into `buf` from a socket capability.
+    ///
+    /// # Returns
+    /// * `Ok(index)` - Index of the operation's result
+    /// * `Err(BATCH_FULL)` - The batch is full
+    pub fn recv(&mut self, sock_cap: i64, buf: &'a mut [u8]) -> Result<usize, i32> {
+        self.push(BATCH_NET_RECV, sock_cap, buf.as_mut_ptr(), buf.len(), 0)
+    }
+
+    /// Number of operations in the batch.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the batch has no operations.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Run the operations in order, yielding if the kernel runs them in
+    /// parts.
+    ///
+    /// # Returns
+    /// * `Ok(BatchResults)` - Each operation's result
+    /// * `Err(i32)` - Error code; operations already run are not undone
+    pub fn submit(mut self) -> Result<BatchResults, i32> {
+        let mut done = 0;
+        while done < self.len {
+            let rest = &mut self.records[done..self.len];
+            let result = unsafe { sp_batch(rest.as_mut_ptr() as *mut u8, rest.len()) };
+            if result < 0 {
+                return Err(result);
+            }
+            done += result as usize;
+            if done < self.len {
+                yield_now();
+            }
+        }
+        let mut results = [0; BATCH_MAX];
+        for (result, record) in results.iter_mut().zip(&self.records[..self.len]) {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&record[BATCH_RESULT_OFFSET..BATCH_RESULT_OFFSET + 4]);
+            *result = i32::from_le_bytes(bytes);
+        }
+        Ok(BatchResults {
+            results,
+            len: self.len,
+        })
+    }
+
+    fn push(
+        &mut self,
+        op: u32,
+        cap: i64,
+        ptr: *const u8,
+        len: usize,
+        offset: u32,
+    ) -> Result<usize, i32> {
+        let Some(record) = self.records.get_mut(self.len) else {
+            return Err(BATCH_FULL);
+        };
+        record[0..4].copy_from_slice(&op.to_le_bytes());
+        record[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+        record[8..16].copy_from_slice(&cap.to_le_bytes());
+        record[16..20].copy_from_slice(&(ptr as u32).to_le_bytes());
+        record[20..24].copy_from_slice(&offset.to_le_bytes());
+        self.len += 1;
+        Ok(self.len - 1)
+    }
+}
+
+/// Results of a submitted `Batch`.
+pub struct BatchResults {
+    results: [i32; BATCH_MAX],
+    len: usize,
+}
+
+impl BatchResults {
+    /// Result of the operation at `index`: bytes moved, or an error code.
+    pub fn get(&self, index: usize) -> Option<i32> {
+        self.results[..self.len].get(index).copied()
+    }
+
+    /// All results, in the order the operations were added.
+    pub fn as_slice(&self) -> &[i32] {
+        &self.results[..self.len]
+    }
+}
+
+/// Embed a module manifest in the `sovelma.manifest` custom section.
+///
+/// The kernel reads it for `apps` and checks the listed capability kinds
+/// before `wasm run` starts the module.
+///
+/// ```ignore
+/// sovelma_sdk::manifest!(b"name = hello\nversion = 0.1.0\ncapabilities = timer\n");
+/// ```
+#[macro_export]
+macro_rules! manifest {
+    ($text:literal) => {
+        #[used]
+        #[link_section = "sovelma.manifest"]
+        static SOVELMA_MANIFEST: [u8; $text.len()] = *$text;
+    };
+}